@@ -0,0 +1,71 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Blending of images with many extra channels: all channels blended row by row, against each
+//! channel blended on its own through a temporary copy, as frames used to be blended.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use jxl::api::{BlendMode, blit, blit_channels};
+use jxl::image::{Image, Rect};
+
+const SIZE: (usize, usize) = (1024, 256);
+const ALPHA: usize = 3;
+
+fn planes(count: usize, scale: f32) -> Vec<Image<f32>> {
+    (0..count)
+        .map(|c| {
+            let mut image = Image::new(SIZE).unwrap();
+            for y in 0..SIZE.1 {
+                for (x, v) in image.row_mut(y).iter_mut().enumerate() {
+                    *v = scale * ((x + y + c) % 7) as f32 / 7.0;
+                }
+            }
+            image
+        })
+        .collect()
+}
+
+fn bench_blit_channels(c: &mut Criterion) {
+    let rect = Rect {
+        origin: (0, 0),
+        size: SIZE,
+    };
+    let mut group = c.benchmark_group("blit_channels");
+    // RGB with alpha and 0, 4 and 15 more extra channels.
+    for channels in [4, 8, 19] {
+        let src = planes(channels, 0.5);
+        let mut dst = planes(channels, 1.0);
+
+        group.bench_function(BenchmarkId::new("fused", channels), |b| {
+            b.iter(|| {
+                let mut dst: Vec<_> = dst.iter_mut().map(|d| d.get_rect_mut(rect)).collect();
+                let src: Vec<_> = src.iter().map(|s| s.get_rect(rect)).collect();
+                blit_channels(&mut dst, &src, BlendMode::Blend, Some(ALPHA), false);
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("per_channel", channels), |b| {
+            b.iter(|| {
+                let alpha = src[ALPHA].get_rect(rect);
+                for (dst, src) in dst.iter_mut().zip(&src) {
+                    let mut tmp = dst.try_clone().unwrap();
+                    let mode = BlendMode::Blend;
+                    blit(
+                        &mut tmp.get_rect_mut(rect),
+                        &src.get_rect(rect),
+                        mode,
+                        Some(&alpha),
+                        false,
+                    );
+                    *dst = tmp;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_blit_channels);
+criterion_main!(benches);
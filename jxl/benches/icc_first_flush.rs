@@ -0,0 +1,251 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Time until the image header is read and until the first pixels can be flushed, for an image
+//! with a 2 MiB ICC profile against the same image without it. Decoding the profile delays both.
+//!
+//! The image with the profile is built by re-coding the header of a lossless RGB test image with
+//! an ICC profile of pseudo-random bytes, each coded with an 8-bit prefix code, followed by the
+//! unchanged frame of the test image.
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use jxl::prelude::*;
+use std::path::Path;
+
+const ICC_SIZE: usize = 2 << 20;
+/// Size of the ICC profile header, which is coded as residuals of its prediction.
+const ICC_HEADER_SIZE: usize = 128;
+
+/// Writes bits in the order they are read from a codestream, least significant first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    num_bits: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, num_bits: usize, value: u64) -> &mut Self {
+        assert!(num_bits <= 32 && value >> num_bits == 0);
+        self.buffer |= value << self.num_bits;
+        self.num_bits += num_bits;
+        while self.num_bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.num_bits -= 8;
+        }
+        self
+    }
+
+    fn write_bool(&mut self, value: bool) -> &mut Self {
+        self.write(1, value as u64)
+    }
+
+    /// Writes a `U64` field with a value of at least 4096.
+    fn write_large_u64(&mut self, value: u64) -> &mut Self {
+        assert!(value >= 1 << 12);
+        self.write(2, 3).write(12, value & 0xfff);
+        let mut rest = value >> 12;
+        while rest != 0 {
+            self.write_bool(true).write(8, rest & 0xff);
+            rest >>= 8;
+        }
+        self.write_bool(false)
+    }
+
+    fn zero_pad_to_byte(&mut self) -> &mut Self {
+        self.write((8 - self.num_bits) % 8, 0)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.zero_pad_to_byte();
+        self.bytes
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Writes the image header of an 8-bit RGB image of `size` that is not XYB encoded. If `icc`
+/// is given, the image has that ICC profile, otherwise it is sRGB.
+fn write_image_header(writer: &mut BitWriter, (width, height): (usize, usize), icc: Option<&[u8]>) {
+    let write_size = |writer: &mut BitWriter, size: usize| {
+        let selector = [9, 13, 18, 30]
+            .iter()
+            .position(|&n| size <= 1 << n)
+            .unwrap();
+        writer
+            .write(2, selector as u64)
+            .write([9, 13, 18, 30][selector], size as u64 - 1);
+    };
+    writer.write(8, 0xff).write(8, 0x0a);
+    // Size, without the small coding or a fixed aspect ratio.
+    writer.write_bool(false);
+    write_size(writer, height);
+    writer.write(3, 0);
+    write_size(writer, width);
+    // Image metadata without extra fields: 8-bit integer samples, modular_16bit_sufficient, no
+    // extra channels and no XYB encoding.
+    writer
+        .write_bool(false)
+        .write_bool(false)
+        .write_bool(false)
+        .write(2, 0)
+        .write_bool(true)
+        .write(2, 0)
+        .write_bool(false);
+    match icc {
+        // A color encoding that only states the RGB color space, as the profile describes it.
+        Some(_) => writer.write_bool(false).write_bool(true).write(2, 0),
+        None => writer.write_bool(true),
+    };
+    // No extensions, and default transform data.
+    writer.write(2, 0).write_bool(true);
+    if let Some(icc) = icc {
+        write_icc(writer, icc);
+    }
+}
+
+/// Writes `icc`, a profile made by `generate_icc`, as an ICC stream that inserts all the bytes
+/// after the header at once, coded with a single prefix code in which every byte has 8 bits.
+fn write_icc(writer: &mut BitWriter, icc: &[u8]) {
+    let mut stream = vec![];
+    write_varint(&mut stream, icc.len());
+    // No tag list, then a command inserting the rest of the profile.
+    let mut commands = vec![0, 1];
+    write_varint(&mut commands, icc.len() - ICC_HEADER_SIZE);
+    write_varint(&mut stream, commands.len());
+    stream.extend_from_slice(&commands);
+    // Header bytes are coded as the difference to their prediction, which is the header of
+    // `generate_icc`.
+    stream.extend([0; ICC_HEADER_SIZE]);
+    stream.extend_from_slice(&icc[ICC_HEADER_SIZE..]);
+
+    writer.write_large_u64(stream.len() as u64);
+    // No LZ77, all 41 contexts in one cluster, prefix codes, and tokens that are the symbols.
+    writer
+        .write_bool(false)
+        .write_bool(true)
+        .write(2, 0)
+        .write_bool(true)
+        .write(4, 15);
+    // An alphabet of 256 symbols, with a complex prefix code whose code length code only has the
+    // length 8, so that all lengths are 8 and coded with 0 bits.
+    writer
+        .write_bool(true)
+        .write(4, 7)
+        .write(7, 127)
+        .write(2, 0);
+    const CODE_LENGTH_CODE_ORDER: [u8; 18] =
+        [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    for symbol in CODE_LENGTH_CODE_ORDER {
+        // Code length code lengths are coded with a static code, in which 0 is read as 00 and 1
+        // as 1110.
+        match symbol {
+            8 => writer.write(4, 0b0111),
+            _ => writer.write(2, 0),
+        };
+    }
+    for byte in stream {
+        // Canonical codes of equal lengths are in symbol order, and read most significant bit
+        // first.
+        writer.write(8, byte.reverse_bits() as u64);
+    }
+}
+
+/// Generates a profile of pseudo-random bytes after a header of an RGB display profile, which
+/// only holds the fields that the ICC stream predicts.
+fn generate_icc() -> Vec<u8> {
+    let mut header = [0; ICC_HEADER_SIZE];
+    header[..4].copy_from_slice(&(ICC_SIZE as u32).to_be_bytes());
+    header[8] = 4;
+    header[12..24].copy_from_slice(b"mntrRGB XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    // The D50 illuminant.
+    header[68..80].copy_from_slice(&[0, 0, 246, 214, 0, 1, 0, 0, 0, 0, 211, 45]);
+    let mut state = 0x2545f4914f6cdd1du64;
+    let data = (ICC_HEADER_SIZE..ICC_SIZE).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    });
+    header.into_iter().chain(data).collect()
+}
+
+/// Decodes the image header of `data`, then the header of the first frame, and flushes the
+/// pixels decoded so far if `flush` is set.
+fn decode_until_first_flush(mut data: &[u8], flush: bool) -> JxlColorProfile {
+    let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+    let ProcessingResult::Complete { result: decoder } = decoder.process(&mut data).unwrap() else {
+        panic!("truncated image header");
+    };
+    let profile = decoder.embedded_color_profile().clone();
+    if flush {
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut data).unwrap()
+        else {
+            panic!("truncated frame header");
+        };
+        let requirements = decoder.output_buffer_requirements();
+        let mut buffers: Vec<_> = requirements
+            .iter()
+            .map(|req| vec![0u8; req.bytes_per_row() * req.height])
+            .collect();
+        let mut outputs: Vec<_> = (buffers.iter_mut().zip(&requirements))
+            .map(|(buf, req)| JxlOutputBuffer::new(buf, req.height, req.bytes_per_row()))
+            .collect();
+        decoder.flush_pixels(&mut outputs).unwrap();
+    }
+    profile
+}
+
+fn bench_icc_first_flush(c: &mut Criterion) {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/test/green_queen_modular_e3.jxl");
+    let original = std::fs::read(path).unwrap();
+    const SIZE: (usize, usize) = (438, 589);
+
+    // The frame starts at the first byte after the image header.
+    let mut writer = BitWriter::default();
+    write_image_header(&mut writer, SIZE, None);
+    let header = writer.finish();
+    assert_eq!(original[..header.len()], header, "unexpected image header");
+    let frame = &original[header.len()..];
+
+    let icc = generate_icc();
+    let mut writer = BitWriter::default();
+    write_image_header(&mut writer, SIZE, Some(&icc));
+    let mut with_icc = writer.finish();
+    with_icc.extend_from_slice(frame);
+    let JxlColorProfile::Icc(decoded_icc) = decode_until_first_flush(&with_icc, false) else {
+        panic!("no ICC profile");
+    };
+    assert!(decoded_icc == icc);
+
+    let mut group = c.benchmark_group("icc_first_flush");
+    for (name, data) in [("no_icc", &original), ("2MiB_icc", &with_icc)] {
+        group.bench_with_input(BenchmarkId::new("header", name), data, |b, data| {
+            b.iter(|| decode_until_first_flush(data, false))
+        });
+        group.bench_with_input(BenchmarkId::new("first_flush", name), data, |b, data| {
+            b.iter(|| decode_until_first_flush(data, true))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    name = icc_first_flush;
+    config = Criterion::default().sample_size(20);
+    targets = bench_icc_first_flush
+);
+criterion_main!(icc_first_flush);
@@ -0,0 +1,227 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::{
+        JxlColorType, JxlDataFormat, JxlDecoder, JxlDecoderOptions, JxlExtraChannelType,
+        JxlOutputBuffer, JxlPixelFormat, ProcessingResult, states, thumbnail::thumbnail_size,
+    },
+    error::{Error, Result},
+    image::{Image, Rect},
+};
+
+/// Largest downsampling factor used by [`decode_alpha`]. At a factor of 8, VarDCT frames are
+/// rendered from their LF image alone, which does not include the extra channels.
+const MAX_ALPHA_DOWNSAMPLE: usize = 4;
+
+/// Decodes the alpha channel of the first frame of a JPEG XL file, for example for pointer
+/// hit-testing.
+///
+/// Returns `None` for images without alpha. Otherwise, returns the alpha channel as 8-bit
+/// samples, upsampled from the resolution given by its `dim_shift`, oriented for display, and
+/// resampled to fit within `max_dim` x `max_dim` pixels while preserving the aspect ratio, like
+/// [`decode_thumbnail`](crate::api::decode_thumbnail).
+///
+/// Only alpha is rendered. The color of VarDCT frames is still read, since the alpha of a group
+/// follows it in the same section, but it is neither dequantized nor transformed, and the color
+/// stages of the rendering are skipped. Progressive passes that only add detail beyond the
+/// output size are not decoded, up to a factor of 4.
+pub fn decode_alpha(bytes: &[u8], max_dim: usize) -> Result<Option<Image<u8>>> {
+    if max_dim == 0 {
+        return Err(Error::InvalidThumbnailSize(max_dim));
+    }
+    let mut input = bytes;
+    let initialized = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+    let mut decoder = match initialized.process(&mut input)? {
+        ProcessingResult::Complete { result } => result,
+        ProcessingResult::NeedsMoreInput { size_hint, .. } => {
+            return Err(Error::OutOfBounds(size_hint));
+        }
+    };
+    let basic_info = decoder.basic_info().clone();
+    let Some(alpha_channel) = basic_info
+        .extra_channels
+        .iter()
+        .position(|ec| ec.ec_type == JxlExtraChannelType::Alpha)
+    else {
+        return Ok(None);
+    };
+    let mut extra_channel_format = vec![None; basic_info.extra_channels.len()];
+    extra_channel_format[alpha_channel] = Some(JxlDataFormat::U8 { bit_depth: 8 });
+    // The color type must still suit the image, even though color is not output.
+    let color_type = if decoder.current_pixel_format().color_type.is_grayscale() {
+        JxlColorType::Grayscale
+    } else {
+        JxlColorType::Rgb
+    };
+    decoder.set_pixel_format(JxlPixelFormat {
+        color_type,
+        color_data_format: None,
+        extra_channel_format,
+    });
+    let size = thumbnail_size(basic_info.size, max_dim);
+    if size != basic_info.size {
+        decoder.set_resize_to(Some(size));
+        let factor = basic_info.size.0.max(basic_info.size.1) / size.0.max(size.1);
+        decoder.set_downsample(factor.min(MAX_ALPHA_DOWNSAMPLE) as u32);
+    }
+
+    let mut alpha = Image::<u8>::new(size)?;
+    let mut output = [JxlOutputBuffer::from_image_rect_mut(
+        alpha
+            .get_rect_mut(Rect {
+                origin: (0, 0),
+                size,
+            })
+            .into_raw(),
+    )];
+    let decoder = match decoder.process(&mut input)? {
+        ProcessingResult::Complete { result } => result,
+        ProcessingResult::NeedsMoreInput { size_hint, .. } => {
+            return Err(Error::OutOfBounds(size_hint));
+        }
+    };
+    if let ProcessingResult::NeedsMoreInput { size_hint, .. } =
+        decoder.process(&mut input, &mut output)?
+    {
+        return Err(Error::OutOfBounds(size_hint));
+    }
+    Ok(Some(alpha))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes the color and the alpha of the first frame of `bytes` at `size`, and returns the
+    /// alpha channel.
+    fn full_decode_alpha(bytes: &[u8], size: Option<(usize, usize)>) -> Image<u8> {
+        let options = JxlDecoderOptions {
+            resize_to: size,
+            ..Default::default()
+        };
+        let mut input = bytes;
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = JxlDecoder::<states::Initialized>::new(options)
+            .process(&mut input)
+            .unwrap()
+        else {
+            panic!("truncated image header");
+        };
+        let basic_info = decoder.basic_info().clone();
+        let alpha_channel = basic_info
+            .extra_channels
+            .iter()
+            .position(|ec| ec.ec_type == JxlExtraChannelType::Alpha)
+            .unwrap();
+        let mut extra_channel_format = vec![None; basic_info.extra_channels.len()];
+        extra_channel_format[alpha_channel] = Some(JxlDataFormat::U8 { bit_depth: 8 });
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format,
+        });
+        let size = size.unwrap_or(basic_info.size);
+        let mut color = Image::<u8>::new((size.0 * 3, size.1)).unwrap();
+        let mut alpha = Image::<u8>::new(size).unwrap();
+        let mut output = [&mut color, &mut alpha].map(|image| {
+            let size = image.size();
+            JxlOutputBuffer::from_image_rect_mut(
+                image
+                    .get_rect_mut(Rect {
+                        origin: (0, 0),
+                        size,
+                    })
+                    .into_raw(),
+            )
+        });
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input).unwrap()
+        else {
+            panic!("truncated frame header");
+        };
+        let result = decoder.process(&mut input, &mut output).unwrap();
+        assert!(matches!(result, ProcessingResult::Complete { .. }));
+        alpha
+    }
+
+    fn max_difference(a: &Image<u8>, b: &Image<u8>) -> u8 {
+        assert_eq!(a.size(), b.size());
+        (0..a.size().1)
+            .flat_map(|y| a.row(y).iter().zip(b.row(y)).map(|(a, b)| a.abs_diff(*b)))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_full_decode() {
+        for path in [
+            "resources/test/dice.jxl",
+            "resources/test/cropped_traffic_light.jxl",
+            // The alpha channel has a `dim_shift` of 2.
+            "resources/test/conformance_test_images/upsampling.jxl",
+        ] {
+            let bytes = std::fs::read(path).unwrap();
+            let alpha = decode_alpha(&bytes, usize::MAX).unwrap().unwrap();
+            assert_eq!(alpha.size(), full_decode_alpha(&bytes, None).size());
+            assert_eq!(
+                max_difference(&alpha, &full_decode_alpha(&bytes, None)),
+                0,
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn downsampled_matches_resized_full_decode() {
+        let bytes = std::fs::read("resources/test/dice.jxl").unwrap();
+        let alpha = decode_alpha(&bytes, 200).unwrap().unwrap();
+        assert_eq!(alpha.size(), (200, 150));
+        let full = full_decode_alpha(&bytes, Some((200, 150)));
+        assert!(max_difference(&alpha, &full) <= 2);
+    }
+
+    /// Run with `cargo test --release -- --ignored faster_than_full_decode`.
+    #[test]
+    #[ignore = "benchmark, only meaningful in release builds on an idle machine"]
+    fn faster_than_full_decode() {
+        // Hit-testing decodes the alpha at a reduced resolution. Skipping the color, which takes
+        // most of the time of a full decode of this image, and downsampling while decoding make
+        // that more than twice as fast as a full decode resized to the same size. Images whose
+        // alpha is expensive to decode, such as dice.jxl, gain less.
+        let bytes = std::fs::read("resources/test/conformance_test_images/upsampling.jxl").unwrap();
+        let size = decode_alpha(&bytes, 256).unwrap().unwrap().size();
+        // The fastest of several runs is the least affected by other processes.
+        let fastest = |decode: &dyn Fn()| {
+            (0..5)
+                .map(|_| {
+                    let start = crate::time::clock().now();
+                    decode();
+                    crate::time::clock().now() - start
+                })
+                .min()
+                .unwrap()
+        };
+        let alpha_time = fastest(&|| {
+            decode_alpha(&bytes, 256).unwrap().unwrap();
+        });
+        let full_time = fastest(&|| {
+            full_decode_alpha(&bytes, Some(size));
+        });
+        let speedup = full_time.as_secs_f64() / alpha_time.as_secs_f64();
+        println!("alpha: {alpha_time:?}, full: {full_time:?}, speedup: {speedup:.2}x");
+        assert!(speedup > 2.0, "{speedup:.2}x");
+    }
+
+    #[test]
+    fn no_alpha() {
+        let bytes = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        assert!(decode_alpha(&bytes, 64).unwrap().is_none());
+        assert!(matches!(
+            decode_alpha(&bytes, 0),
+            Err(Error::InvalidThumbnailSize(0))
+        ));
+    }
+}
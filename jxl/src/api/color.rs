@@ -6,7 +6,7 @@
 use std::{borrow::Cow, fmt};
 
 use crate::{
-    color::tf::{hlg_to_scene, linear_to_pq_precise, pq_to_linear_precise},
+    color::{icc_profile::MatrixTrcProfile, tf::hlg_to_scene, transfer},
     error::{Error, Result},
     headers::color_encoding::{
         ColorEncoding, ColorSpace, Primaries, RenderingIntent, TransferFunction, WhitePoint,
@@ -448,7 +448,7 @@ impl fmt::Display for JxlColorEncoding {
 }
 
 impl JxlColorEncoding {
-    pub fn from_internal(internal: &ColorEncoding) -> Result<Self> {
+    pub(crate) fn from_internal(internal: &ColorEncoding) -> Result<Self> {
         let rendering_intent = internal.rendering_intent;
         if internal.color_space == ColorSpace::XYB {
             if rendering_intent != RenderingIntent::Perceptual {
@@ -766,8 +766,8 @@ impl JxlColorEncoding {
     }
 
     pub fn maybe_create_profile(&self) -> Result<Option<Vec<u8>>, Error> {
-        if let JxlColorEncoding::XYB { rendering_intent } = self
-            && *rendering_intent != RenderingIntent::Perceptual
+        if matches!(self, JxlColorEncoding::XYB { rendering_intent }
+            if *rendering_intent != RenderingIntent::Perceptual)
         {
             return Err(Error::InvalidRenderingIntent);
         }
@@ -800,34 +800,23 @@ impl JxlColorEncoding {
             size_unpadded: cprt_tag_unpadded_size,
         });
 
-        match self {
-            JxlColorEncoding::GrayscaleColorSpace { white_point, .. } => {
-                let (wx, wy) = white_point.to_xy_coords();
-                collected_tags.push(create_icc_xyz_tag(
-                    &mut tags_data,
-                    &cie_xyz_from_white_cie_xy(wx, wy)?,
-                )?);
-            }
-            _ => {
-                // Ok, in this case we will add the chad tag below
-                const D50: [f32; 3] = [0.964203f32, 1.0, 0.824905];
-                collected_tags.push(create_icc_xyz_tag(&mut tags_data, &D50)?);
-            }
-        }
+        // ICC v4 requires the media white point of display profiles to be the PCS illuminant,
+        // with the actual white point given by the chromatic adaptation to D50, also for
+        // grayscale profiles (libjxl writes the white point of those as is instead).
+        const D50: [f32; 3] = [0.964203f32, 1.0, 0.824905];
+        collected_tags.push(create_icc_xyz_tag(&mut tags_data, &D50)?);
+        pad_to_4_byte_boundary(&mut tags_data);
+        let (wx, wy) = match self {
+            JxlColorEncoding::GrayscaleColorSpace { white_point, .. }
+            | JxlColorEncoding::RgbColorSpace { white_point, .. } => white_point.to_xy_coords(),
+            JxlColorEncoding::XYB { .. } => JxlWhitePoint::D65.to_xy_coords(),
+        };
+        let chad_matrix_f64 = adapt_to_xyz_d50(wx, wy)?;
+        let chad_matrix = std::array::from_fn(|r_idx| {
+            std::array::from_fn(|c_idx| chad_matrix_f64[r_idx][c_idx] as f32)
+        });
+        collected_tags.push(create_icc_chad_tag(&mut tags_data, &chad_matrix)?);
         pad_to_4_byte_boundary(&mut tags_data);
-        if !matches!(self, JxlColorEncoding::GrayscaleColorSpace { .. }) {
-            let (wx, wy) = match self {
-                JxlColorEncoding::GrayscaleColorSpace { .. } => unreachable!(),
-                JxlColorEncoding::RgbColorSpace { white_point, .. } => white_point.to_xy_coords(),
-                JxlColorEncoding::XYB { .. } => JxlWhitePoint::D65.to_xy_coords(),
-            };
-            let chad_matrix_f64 = adapt_to_xyz_d50(wx, wy)?;
-            let chad_matrix = std::array::from_fn(|r_idx| {
-                std::array::from_fn(|c_idx| chad_matrix_f64[r_idx][c_idx] as f32)
-            });
-            collected_tags.push(create_icc_chad_tag(&mut tags_data, &chad_matrix)?);
-            pad_to_4_byte_boundary(&mut tags_data);
-        }
 
         if let JxlColorEncoding::RgbColorSpace {
             white_point,
@@ -996,8 +985,8 @@ impl JxlColorEncoding {
                             create_icc_curv_para_tag(&mut tags_data, &PARAMS, 3)?
                         }
                         JxlTransferFunction::HLG | JxlTransferFunction::PQ => {
-                            let params = create_table_curve(64, transfer_function, false)?;
-                            create_icc_curv_para_tag(&mut tags_data, params.as_slice(), 3)?
+                            let table = create_table_curve(64, transfer_function, false)?;
+                            create_icc_curv_table_tag(&mut tags_data, &table)
                         }
                     };
                     pad_to_4_byte_boundary(&mut tags_data);
@@ -1150,6 +1139,12 @@ impl JxlColorEncoding {
     /// Returns a copy of this encoding with linear transfer function.
     /// For XYB encoding, returns linear sRGB as fallback.
     pub fn with_linear_tf(&self) -> Self {
+        self.with_transfer_function(JxlTransferFunction::Linear)
+    }
+
+    /// Returns a copy of this encoding with the given transfer function.
+    /// For XYB encoding, returns sRGB primaries with that transfer function as fallback.
+    pub fn with_transfer_function(&self, transfer_function: JxlTransferFunction) -> Self {
         match self {
             JxlColorEncoding::RgbColorSpace {
                 white_point,
@@ -1159,7 +1154,7 @@ impl JxlColorEncoding {
             } => JxlColorEncoding::RgbColorSpace {
                 white_point: white_point.clone(),
                 primaries: primaries.clone(),
-                transfer_function: JxlTransferFunction::Linear,
+                transfer_function,
                 rendering_intent: *rendering_intent,
             },
             JxlColorEncoding::GrayscaleColorSpace {
@@ -1168,10 +1163,15 @@ impl JxlColorEncoding {
                 ..
             } => JxlColorEncoding::GrayscaleColorSpace {
                 white_point: white_point.clone(),
-                transfer_function: JxlTransferFunction::Linear,
+                transfer_function,
                 rendering_intent: *rendering_intent,
             },
-            JxlColorEncoding::XYB { .. } => Self::linear_srgb(false),
+            JxlColorEncoding::XYB { .. } => JxlColorEncoding::RgbColorSpace {
+                white_point: JxlWhitePoint::D65,
+                primaries: JxlPrimaries::SRGB,
+                transfer_function,
+                rendering_intent: RenderingIntent::Relative,
+            },
         }
     }
 
@@ -1264,6 +1264,88 @@ impl JxlColorProfile {
         }
     }
 
+    /// Returns true if both profiles describe the same color space up to `tolerance`, even if
+    /// they are represented differently.
+    ///
+    /// Simple color encodings are compared by their white point and primaries chromaticities and
+    /// their transfer functions. Otherwise, both profiles are compared as ICC profiles: identical
+    /// profiles always match, and matrix/TRC profiles match if their colorants and their tone
+    /// curves, sampled at evenly spaced points, differ by at most `tolerance`. Rendering intents
+    /// are ignored.
+    pub fn is_approx_equivalent(&self, other: &Self, tolerance: f32) -> bool {
+        match (self, other) {
+            (Self::Simple(a), Self::Simple(b)) => encodings_approx_equivalent(a, b, tolerance),
+            (Self::Icc(a), Self::Icc(b)) if a == b => true,
+            _ => {
+                let (Some(a), Some(b)) = (self.try_as_icc(), other.try_as_icc()) else {
+                    return false;
+                };
+                match (MatrixTrcProfile::parse(&a), MatrixTrcProfile::parse(&b)) {
+                    (Some(a), Some(b)) => a.is_approx_equivalent(&b, tolerance),
+                    _ => false,
+                }
+            }
+        }
+    }
+
+    /// Returns a short human-readable summary of the profile, such as
+    /// "RGB, D65, P3 primaries, sRGB transfer".
+    pub fn describe(&self) -> String {
+        let encoding = match self {
+            Self::Icc(icc) => {
+                let color_space = match icc.get(16..20) {
+                    Some(b"RGB ") => "RGB",
+                    Some(b"GRAY") => "Grayscale",
+                    Some(b"CMYK") => "CMYK",
+                    _ => "Unknown color space",
+                };
+                return format!("{color_space}, ICC profile of {} bytes", icc.len());
+            }
+            Self::Simple(encoding) => encoding,
+        };
+        let white_point = |white_point: &JxlWhitePoint| match white_point {
+            JxlWhitePoint::D65 => "D65".to_string(),
+            JxlWhitePoint::E => "equal energy white point".to_string(),
+            JxlWhitePoint::DCI => "DCI white point".to_string(),
+            JxlWhitePoint::Chromaticity { wx, wy } => format!("white point {wx:.4},{wy:.4}"),
+        };
+        let transfer_function = |transfer_function: &JxlTransferFunction| match transfer_function {
+            JxlTransferFunction::BT709 => "BT.709 transfer".to_string(),
+            JxlTransferFunction::Linear => "linear transfer".to_string(),
+            JxlTransferFunction::SRGB => "sRGB transfer".to_string(),
+            JxlTransferFunction::PQ => "PQ transfer".to_string(),
+            JxlTransferFunction::DCI => "DCI transfer".to_string(),
+            JxlTransferFunction::HLG => "HLG transfer".to_string(),
+            JxlTransferFunction::Gamma(g) => format!("gamma {:.2} transfer", 1.0 / g),
+        };
+        match encoding {
+            JxlColorEncoding::RgbColorSpace {
+                white_point: wp,
+                primaries,
+                transfer_function: tf,
+                ..
+            } => {
+                let primaries = match primaries {
+                    JxlPrimaries::SRGB => "sRGB primaries",
+                    JxlPrimaries::BT2100 => "BT.2100 primaries",
+                    JxlPrimaries::P3 => "P3 primaries",
+                    JxlPrimaries::Chromaticities { .. } => "custom primaries",
+                };
+                format!(
+                    "RGB, {}, {primaries}, {}",
+                    white_point(wp),
+                    transfer_function(tf)
+                )
+            }
+            JxlColorEncoding::GrayscaleColorSpace {
+                white_point: wp,
+                transfer_function: tf,
+                ..
+            } => format!("Grayscale, {}, {}", white_point(wp), transfer_function(tf)),
+            JxlColorEncoding::XYB { .. } => "XYB".to_string(),
+        }
+    }
+
     /// Returns the transfer function if this is a simple color profile.
     /// Returns None for ICC profiles or XYB.
     pub fn transfer_function(&self) -> Option<&JxlTransferFunction> {
@@ -1351,6 +1433,134 @@ impl JxlColorProfile {
     }
 }
 
+/// Where the embedded color profile of an image comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JxlColorProfileSource {
+    /// The enumerated color encoding of the image header.
+    Enumerated,
+    /// The ICC profile stored in the codestream.
+    Icc,
+}
+
+/// An ICC profile that disagrees with the enumerated color space of the image header.
+///
+/// Images with an ICC profile still signal in their header whether they are grayscale, which
+/// determines the number of decoded color channels. An ICC profile of the other kind cannot
+/// describe the decoded pixels, so the header takes precedence: the embedded profile is then
+/// sRGB, or its grayscale variant, unless
+/// [`prefer_icc_profile`](crate::api::JxlDecoderOptions::prefer_icc_profile) is set.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct JxlColorProfileMismatch {
+    /// The profile derived from the color space of the image header.
+    pub enumerated: JxlColorEncoding,
+    /// The ICC profile stored in the codestream.
+    pub icc: Vec<u8>,
+    /// The profile used as the embedded color profile.
+    pub used: JxlColorProfileSource,
+}
+
+impl JxlColorProfileMismatch {
+    /// Returns the mismatch between `icc` and the header color space `color_space`, or `None` if
+    /// the ICC profile can describe images of that color space. CMYK profiles and profiles of
+    /// other color spaces are accepted for color images.
+    pub(crate) fn check(color_space: ColorSpace, icc: &[u8], prefer_icc: bool) -> Option<Self> {
+        let icc_is_gray = icc.get(16..20) == Some(b"GRAY");
+        let is_gray = color_space == ColorSpace::Gray;
+        if color_space == ColorSpace::Unknown || icc_is_gray == is_gray {
+            return None;
+        }
+        Some(Self {
+            enumerated: JxlColorEncoding::srgb(is_gray),
+            icc: icc.to_vec(),
+            used: if prefer_icc {
+                JxlColorProfileSource::Icc
+            } else {
+                JxlColorProfileSource::Enumerated
+            },
+        })
+    }
+
+    /// Returns the profile used as the embedded color profile.
+    pub fn used_profile(&self) -> JxlColorProfile {
+        match self.used {
+            JxlColorProfileSource::Enumerated => JxlColorProfile::Simple(self.enumerated.clone()),
+            JxlColorProfileSource::Icc => JxlColorProfile::Icc(self.icc.clone()),
+        }
+    }
+}
+
+impl fmt::Display for JxlColorProfileMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enumerated = JxlColorProfile::Simple(self.enumerated.clone());
+        let icc = JxlColorProfile::Icc(self.icc.clone());
+        let (used, ignored) = match self.used {
+            JxlColorProfileSource::Enumerated => (enumerated, icc),
+            JxlColorProfileSource::Icc => (icc, enumerated),
+        };
+        write!(
+            f,
+            "ICC profile disagrees with the enumerated color space; using {}, ignoring {}",
+            used.describe(),
+            ignored.describe()
+        )
+    }
+}
+
+/// Compares simple color encodings for [`JxlColorProfile::is_approx_equivalent`].
+fn encodings_approx_equivalent(a: &JxlColorEncoding, b: &JxlColorEncoding, tolerance: f32) -> bool {
+    let close = |a: (f32, f32), b: (f32, f32)| {
+        (a.0 - b.0).abs() <= tolerance && (a.1 - b.1).abs() <= tolerance
+    };
+    let same_tf = |a: &JxlTransferFunction, b: &JxlTransferFunction| {
+        use JxlTransferFunction::*;
+        match (a, b) {
+            (Gamma(a), Gamma(b)) => (a - b).abs() <= tolerance,
+            (Gamma(g), Linear) | (Linear, Gamma(g)) => (g - 1.0).abs() <= tolerance,
+            _ => a == b,
+        }
+    };
+    use JxlColorEncoding::*;
+    match (a, b) {
+        (
+            RgbColorSpace {
+                white_point: wp_a,
+                primaries: prim_a,
+                transfer_function: tf_a,
+                ..
+            },
+            RgbColorSpace {
+                white_point: wp_b,
+                primaries: prim_b,
+                transfer_function: tf_b,
+                ..
+            },
+        ) => {
+            close(wp_a.to_xy_coords(), wp_b.to_xy_coords())
+                && prim_a
+                    .to_xy_coords()
+                    .into_iter()
+                    .zip(prim_b.to_xy_coords())
+                    .all(|(a, b)| close(a, b))
+                && same_tf(tf_a, tf_b)
+        }
+        (
+            GrayscaleColorSpace {
+                white_point: wp_a,
+                transfer_function: tf_a,
+                ..
+            },
+            GrayscaleColorSpace {
+                white_point: wp_b,
+                transfer_function: tf_b,
+                ..
+            },
+        ) => close(wp_a.to_xy_coords(), wp_b.to_xy_coords()) && same_tf(tf_a, tf_b),
+        (XYB { .. }, XYB { .. }) => true,
+        _ => false,
+    }
+}
+
 impl fmt::Display for JxlColorProfile {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -1540,19 +1750,6 @@ fn create_icc_chad_tag(
     })
 }
 
-/// Converts CIE xy white point coordinates to CIE XYZ values (Y is normalized to 1.0).
-fn cie_xyz_from_white_cie_xy(wx: f32, wy: f32) -> Result<[f32; 3], Error> {
-    // Check for wy being too close to zero to prevent division by zero or extreme values.
-    if wy.abs() < 1e-12 {
-        return Err(Error::IccInvalidWhitePointY(wy));
-    }
-    let factor = 1.0 / wy;
-    let x_val = wx * factor;
-    let y_val = 1.0f32;
-    let z_val = (1.0 - wx - wy) * factor;
-    Ok([x_val, y_val, z_val])
-}
-
 /// Creates the data for an ICC `para` (parametricCurveType) tag.
 /// It writes `12 + 4 * params.len()` bytes.
 fn create_icc_curv_para_tag(
@@ -1576,131 +1773,18 @@ fn create_icc_curv_para_tag(
     Ok((tags_data.len() - start_offset) as u32)
 }
 
-fn display_from_encoded_pq(display_intensity_target: f32, mut e: f64) -> f64 {
-    const M1: f64 = 2610.0 / 16384.0;
-    const M2: f64 = (2523.0 / 4096.0) * 128.0;
-    const C1: f64 = 3424.0 / 4096.0;
-    const C2: f64 = (2413.0 / 4096.0) * 32.0;
-    const C3: f64 = (2392.0 / 4096.0) * 32.0;
-    // Handle the zero case directly.
-    if e == 0.0 {
-        return 0.0;
-    }
-
-    // Handle negative inputs by using their absolute
-    // value for the calculation and reapplying the sign at the end.
-    let original_sign = e.signum();
-    e = e.abs();
-
-    // Core PQ EOTF formula from ST 2084.
-    let xp = e.powf(1.0 / M2);
-    let num = (xp - C1).max(0.0);
-    let den = C2 - C3 * xp;
-
-    // In release builds, a zero denominator would lead to `inf` or `NaN`,
-    // which is handled by the assertion below. For valid inputs (e in [0,1]),
-    // the denominator is always positive.
-    debug_assert!(den != 0.0, "PQ transfer function denominator is zero.");
-
-    let d = (num / den).powf(1.0 / M1);
-
-    // The result `d` should always be non-negative for non-negative inputs.
-    debug_assert!(
-        d >= 0.0,
-        "PQ intermediate value `d` should not be negative."
-    );
-
-    // The libjxl implementation includes a scaling factor. Note that `d` represents
-    // a value normalized to a 10,000 nit peak.
-    let scaled_d = d * (10000.0 / display_intensity_target as f64);
-
-    // Re-apply the original sign.
-    scaled_d.copysign(original_sign)
-}
-
-/// TF_HLG_Base class for BT.2100 HLG.
-///
-/// This struct provides methods to convert between non-linear encoded HLG signals
-/// and linear display-referred light, following the definitions in BT.2100-2.
-///
-/// - **"display"**: linear light, normalized to [0, 1].
-/// - **"encoded"**: a non-linear HLG signal, nominally in [0, 1].
-/// - **"scene"**: scene-referred linear light, normalized to [0, 1].
-///
-/// The functions are designed to be unbounded to handle inputs outside the
-/// nominal [0, 1] range, which can occur during color space conversions. Negative
-/// inputs are handled by mirroring the function (`f(-x) = -f(x)`).
-#[allow(non_camel_case_types)]
-struct TF_HLG;
-
-impl TF_HLG {
-    // Constants for the HLG formula, as defined in BT.2100.
-    const A: f64 = 0.17883277;
-    const RA: f64 = 1.0 / Self::A;
-    const B: f64 = 1.0 - 4.0 * Self::A;
-    const C: f64 = 0.5599107295;
-    const INV_12: f64 = 1.0 / 12.0;
-
-    /// Converts a non-linear encoded signal to a linear display value (EOTF).
-    ///
-    /// This corresponds to `DisplayFromEncoded(e) = OOTF(InvOETF(e))`.
-    /// Since the OOTF is simplified to an identity function, this is equivalent
-    /// to calling `inv_oetf(e)`.
-    #[inline]
-    fn display_from_encoded(e: f64) -> f64 {
-        Self::inv_oetf(e)
-    }
-
-    /// Converts a linear display value to a non-linear encoded signal (inverse EOTF).
-    ///
-    /// This corresponds to `EncodedFromDisplay(d) = OETF(InvOOTF(d))`.
-    /// Since the InvOOTF is an identity function, this is equivalent to `oetf(d)`.
-    #[inline]
-    #[allow(dead_code)]
-    fn encoded_from_display(d: f64) -> f64 {
-        Self::oetf(d)
-    }
-
-    /// The private HLG OETF, converting scene-referred light to a non-linear signal.
-    fn oetf(mut s: f64) -> f64 {
-        if s == 0.0 {
-            return 0.0;
-        }
-        let original_sign = s.signum();
-        s = s.abs();
-
-        let e = if s <= Self::INV_12 {
-            (3.0 * s).sqrt()
-        } else {
-            Self::A * (12.0 * s - Self::B).ln() + Self::C
-        };
-
-        // The result should be positive for positive inputs.
-        debug_assert!(e > 0.0);
-
-        e.copysign(original_sign)
-    }
-
-    /// The private HLG inverse OETF, converting a non-linear signal back to scene-referred light.
-    fn inv_oetf(mut e: f64) -> f64 {
-        if e == 0.0 {
-            return 0.0;
-        }
-        let original_sign = e.signum();
-        e = e.abs();
-
-        let s = if e <= 0.5 {
-            // The `* (1.0 / 3.0)` is slightly more efficient than `/ 3.0`.
-            e * e * (1.0 / 3.0)
-        } else {
-            (((e - Self::C) * Self::RA).exp() + Self::B) * Self::INV_12
-        };
-
-        // The result should be non-negative for non-negative inputs.
-        debug_assert!(s >= 0.0);
-
-        s.copysign(original_sign)
+/// Creates the data for an ICC `curv` (curveType) tag with a table of `table.len()` entries,
+/// which must be in [0, 1]. It writes `12 + 2 * table.len()` bytes.
+fn create_icc_curv_table_tag(tags_data: &mut Vec<u8>, table: &[f32]) -> u32 {
+    let start_offset = tags_data.len();
+    tags_data.extend_from_slice(b"curv");
+    // Reserved, must be 0 (4 bytes)
+    tags_data.extend_from_slice(&0u32.to_be_bytes());
+    tags_data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+    for &value in table {
+        tags_data.extend_from_slice(&((value * 65535.0).round() as u16).to_be_bytes());
     }
+    (tags_data.len() - start_offset) as u32
 }
 
 /// Creates a lookup table for an ICC `curv` tag from a transfer function.
@@ -1743,12 +1827,9 @@ fn create_table_curve(
         // Apply the specified EOTF to get the linear light value `y`.
         // The output `y` is normalized to the range [0.0, 1.0].
         let y = match tf {
-            JxlTransferFunction::HLG => TF_HLG::display_from_encoded(x),
-            JxlTransferFunction::PQ => {
-                // For PQ, the output of the EOTF is absolute luminance, so we
-                // normalize it back to [0, 1] relative to the peak luminance.
-                display_from_encoded_pq(PQ_INTENSITY_TARGET as f32, x) / PQ_INTENSITY_TARGET
-            }
+            // The OOTF of HLG is simplified to the identity.
+            JxlTransferFunction::HLG => transfer::hlg_to_scene(x),
+            JxlTransferFunction::PQ => transfer::pq_to_linear(x) / PQ_INTENSITY_TARGET,
             _ => unreachable!(), // Already checked above.
         };
 
@@ -1780,7 +1861,8 @@ fn create_table_curve(
 
 /// BT.2408 HDR to SDR tone mapper.
 /// Maps PQ content from source range (e.g., 0-10000 nits) to target range (e.g., 0-250 nits).
-struct Rec2408ToneMapper {
+#[derive(Clone)]
+pub(crate) struct Rec2408ToneMapper {
     source_range: (f32, f32), // (min, max) in nits
     target_range: (f32, f32),
     luminances: [f32; 3], // RGB luminance coefficients (Y values)
@@ -1800,7 +1882,11 @@ struct Rec2408ToneMapper {
 }
 
 impl Rec2408ToneMapper {
-    fn new(source_range: (f32, f32), target_range: (f32, f32), luminances: [f32; 3]) -> Self {
+    pub(crate) fn new(
+        source_range: (f32, f32),
+        target_range: (f32, f32),
+        luminances: [f32; 3],
+    ) -> Self {
         let pq_mastering_min = Self::linear_to_pq(source_range.0);
         let pq_mastering_max = Self::linear_to_pq(source_range.1);
         let pq_mastering_range = pq_mastering_max - pq_mastering_min;
@@ -1830,19 +1916,13 @@ impl Rec2408ToneMapper {
     }
 
     /// PQ inverse EOTF - converts luminance (nits) to PQ encoded value.
-    /// Uses the existing `linear_to_pq_precise` from color::tf.
     fn linear_to_pq(luminance: f32) -> f32 {
-        let mut val = [luminance / 10000.0]; // Normalize to 0-1 for 10000 nits
-        linear_to_pq_precise(10000.0, &mut val);
-        val[0]
+        transfer::linear_to_pq(luminance as f64 / 10000.0) as f32
     }
 
     /// PQ EOTF - converts PQ encoded value to luminance (nits).
-    /// Uses the existing `pq_to_linear_precise` from color::tf.
     fn pq_to_linear(encoded: f32) -> f32 {
-        let mut val = [encoded];
-        pq_to_linear_precise(10000.0, &mut val);
-        val[0] * 10000.0
+        (transfer::pq_to_linear(encoded as f64) * 10000.0) as f32
     }
 
     fn t(&self, a: f32) -> f32 {
@@ -1859,7 +1939,7 @@ impl Rec2408ToneMapper {
     }
 
     /// Apply tone mapping to RGB values (in-place)
-    fn tone_map(&self, rgb: &mut [f32; 3]) {
+    pub(crate) fn tone_map(&self, rgb: &mut [f32; 3]) {
         let luminance = self.source_range.1
             * (self.luminances[0] * rgb[0]
                 + self.luminances[1] * rgb[1]
@@ -1917,7 +1997,7 @@ fn apply_hlg_ootf(rgb: &mut [f32; 3], target_luminance: f32, luminances: [f32; 3
 }
 
 /// Desaturate out-of-gamut pixels while preserving luminance.
-fn gamut_map(rgb: &mut [f32; 3], luminances: &[f32; 3], preserve_saturation: f32) {
+pub(crate) fn gamut_map(rgb: &mut [f32; 3], luminances: &[f32; 3], preserve_saturation: f32) {
     let luminance = luminances[0] * rgb[0] + luminances[1] * rgb[1] + luminances[2] * rgb[2];
 
     let mut gray_mix_saturation = 0.0_f32;
@@ -2734,6 +2814,105 @@ mod test {
         assert!(gray_srgb.same_color_encoding(&gray_srgb2));
     }
 
+    /// Builds an sRGB ICC profile the way other tools do: version 2, with the usual rounded
+    /// colorants and a sampled tone curve instead of a parametric one.
+    fn sampled_srgb_icc() -> Vec<u8> {
+        fn xyz(v: [f32; 3]) -> Vec<u8> {
+            let mut data = b"XYZ \0\0\0\0".to_vec();
+            for c in v {
+                data.extend_from_slice(&((c * 65536.0).round() as i32).to_be_bytes());
+            }
+            data
+        }
+        let mut curve = b"curv\0\0\0\0".to_vec();
+        curve.extend_from_slice(&1024u32.to_be_bytes());
+        for i in 0..1024 {
+            let y = transfer::srgb_to_linear(i as f64 / 1023.0);
+            curve.extend_from_slice(&((y * 65535.0).round() as u16).to_be_bytes());
+        }
+        let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"wtpt", xyz([0.9505, 1.0, 1.089])),
+            (b"rXYZ", xyz([0.436_074_7, 0.222_504_5, 0.013_932_2])),
+            (b"gXYZ", xyz([0.385_064_9, 0.716_878_6, 0.097_104_5])),
+            (b"bXYZ", xyz([0.143_080_4, 0.060_616_9, 0.714_173_3])),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+        let mut header = vec![0u8; 128];
+        header[8..12].copy_from_slice(&0x0210_0000u32.to_be_bytes());
+        header[12..16].copy_from_slice(b"mntr");
+        header[16..20].copy_from_slice(b"RGB ");
+        header[20..24].copy_from_slice(b"XYZ ");
+        header[36..40].copy_from_slice(b"acsp");
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut data = vec![];
+        let data_start = 128 + 4 + 12 * tags.len();
+        for (signature, tag) in &tags {
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+            table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            data.extend_from_slice(tag);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        let mut icc = [header, table, data].concat();
+        let size = icc.len() as u32;
+        icc[0..4].copy_from_slice(&size.to_be_bytes());
+        icc
+    }
+
+    #[test]
+    fn test_approx_equivalent_srgb_icc() {
+        let srgb = JxlColorProfile::Simple(JxlColorEncoding::srgb(false));
+        let sampled = JxlColorProfile::Icc(sampled_srgb_icc());
+        assert_ne!(*srgb.as_icc(), *sampled.as_icc());
+        assert!(srgb.is_approx_equivalent(&sampled, 1e-3));
+        assert!(sampled.is_approx_equivalent(&srgb, 1e-3));
+        // The generated profile is byte-different from the encoding, but parsed the same way.
+        let generated = JxlColorProfile::Icc(srgb.as_icc().into_owned());
+        assert!(generated.is_approx_equivalent(&sampled, 1e-3));
+        assert!(!srgb.is_approx_equivalent(&sampled, 1e-6));
+    }
+
+    #[test]
+    fn test_approx_equivalent_p3_srgb() {
+        let srgb = JxlColorProfile::Simple(JxlColorEncoding::srgb(false));
+        let p3 = JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+            white_point: JxlWhitePoint::D65,
+            primaries: JxlPrimaries::P3,
+            transfer_function: JxlTransferFunction::SRGB,
+            rendering_intent: RenderingIntent::Relative,
+        });
+        assert!(!p3.is_approx_equivalent(&srgb, 1e-3));
+        assert!(!srgb.is_approx_equivalent(&p3, 1e-3));
+        let sampled = JxlColorProfile::Icc(sampled_srgb_icc());
+        assert!(!p3.is_approx_equivalent(&sampled, 1e-3));
+        let linear = JxlColorProfile::Simple(JxlColorEncoding::linear_srgb(false));
+        assert!(!linear.is_approx_equivalent(&sampled, 1e-3));
+    }
+
+    #[test]
+    fn test_describe() {
+        let p3 = JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+            white_point: JxlWhitePoint::D65,
+            primaries: JxlPrimaries::P3,
+            transfer_function: JxlTransferFunction::SRGB,
+            rendering_intent: RenderingIntent::Relative,
+        });
+        assert_eq!(p3.describe(), "RGB, D65, P3 primaries, sRGB transfer");
+        let gray = JxlColorProfile::Simple(JxlColorEncoding::GrayscaleColorSpace {
+            white_point: JxlWhitePoint::D65,
+            transfer_function: JxlTransferFunction::Gamma(1.0 / 2.2),
+            rendering_intent: RenderingIntent::Relative,
+        });
+        assert_eq!(gray.describe(), "Grayscale, D65, gamma 2.20 transfer");
+        let icc = JxlColorProfile::Icc(sampled_srgb_icc());
+        assert_eq!(
+            icc.describe(),
+            format!("RGB, ICC profile of {} bytes", icc.as_icc().len())
+        );
+    }
+
     #[test]
     fn test_same_color_encoding_icc_profile() {
         // ICC profiles are never considered same (even with themselves)
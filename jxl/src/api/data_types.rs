@@ -3,7 +3,19 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{headers::extra_channels::ExtraChannel, image::DataTypeTag};
+use std::time::Duration;
+
+use crate::{
+    api::Simplifications,
+    frame::{GroupId, LfGroupId, PassId, Section, SectionId, modular::Predictor},
+    headers::{
+        bit_depth::BitDepth,
+        extra_channels::ExtraChannel,
+        frame_header::{FrameFlags, FrameHeader, Passes},
+        modular::{Transform, TransformId},
+    },
+    image::{DataTypeTag, Rect},
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum JxlColorType {
@@ -109,6 +121,20 @@ impl JxlDataFormat {
         }
     }
 
+    /// Returns the same format with samples in native byte order.
+    pub(crate) fn with_native_endianness(&self) -> Self {
+        let native = Endianness::native();
+        match *self {
+            JxlDataFormat::U8 { bit_depth } => JxlDataFormat::U8 { bit_depth },
+            JxlDataFormat::U16 { bit_depth, .. } => JxlDataFormat::U16 {
+                endianness: native,
+                bit_depth,
+            },
+            JxlDataFormat::F16 { .. } => JxlDataFormat::F16 { endianness: native },
+            JxlDataFormat::F32 { .. } => JxlDataFormat::F32 { endianness: native },
+        }
+    }
+
     /// Returns the byte representation of opaque alpha (1.0) for this format.
     pub(crate) fn opaque_alpha_bytes(&self) -> Vec<u8> {
         match self {
@@ -233,6 +259,19 @@ pub enum JxlBitDepth {
 }
 
 impl JxlBitDepth {
+    pub(crate) fn new(bit_depth: &BitDepth) -> Self {
+        if bit_depth.floating_point_sample() {
+            JxlBitDepth::Float {
+                bits_per_sample: bit_depth.bits_per_sample(),
+                exponent_bits_per_sample: bit_depth.exponent_bits_per_sample(),
+            }
+        } else {
+            JxlBitDepth::Int {
+                bits_per_sample: bit_depth.bits_per_sample(),
+            }
+        }
+    }
+
     pub fn bits_per_sample(&self) -> u32 {
         match self {
             JxlBitDepth::Int { bits_per_sample: b } => *b,
@@ -241,14 +280,116 @@ impl JxlBitDepth {
             } => *b,
         }
     }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, JxlBitDepth::Float { .. })
+    }
+
+    /// Returns the number of exponent bits of float samples, or 0 for integer samples.
+    pub fn exponent_bits(&self) -> u32 {
+        match self {
+            JxlBitDepth::Int { .. } => 0,
+            JxlBitDepth::Float {
+                exponent_bits_per_sample: e,
+                ..
+            } => *e,
+        }
+    }
+
+    /// Returns the smallest output sample type that represents all the values of samples with
+    /// this bit depth, falling back to [`PreferredOutput::F32`] if none of them does.
+    pub fn preferred_output(&self) -> PreferredOutput {
+        let bits = self.bits_per_sample();
+        match self {
+            JxlBitDepth::Int { .. } if bits <= 8 => PreferredOutput::U8,
+            JxlBitDepth::Int { .. } if bits <= 16 => PreferredOutput::U16,
+            // Half floats have 5 exponent bits and 10 mantissa bits.
+            JxlBitDepth::Float { .. }
+                if self.exponent_bits() <= 5 && bits <= self.exponent_bits() + 11 =>
+            {
+                PreferredOutput::F16
+            }
+            _ => PreferredOutput::F32,
+        }
+    }
 }
 
+/// Output sample type suggested by [`JxlBitDepth::preferred_output`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PreferredOutput {
+    U8,
+    U16,
+    F16,
+    F32,
+}
+
+impl PreferredOutput {
+    /// Returns the data format for this sample type, with native endianness.
+    pub fn data_format(&self) -> JxlDataFormat {
+        match self {
+            PreferredOutput::U8 => JxlDataFormat::U8 { bit_depth: 8 },
+            PreferredOutput::U16 => JxlDataFormat::U16 {
+                endianness: Endianness::native(),
+                bit_depth: 16,
+            },
+            PreferredOutput::F16 => JxlDataFormat::F16 {
+                endianness: Endianness::native(),
+            },
+            PreferredOutput::F32 => JxlDataFormat::f32(),
+        }
+    }
+}
+
+/// Type of an extra channel.
+#[allow(clippy::upper_case_acronyms)]
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JxlExtraChannelType {
+    Alpha,
+    Depth,
+    SpotColor,
+    SelectionMask,
+    Black,
+    CFA,
+    Thermal,
+    Optional,
+    /// A channel without known semantics, holding the raw type value. This covers the
+    /// "unknown" and reserved types of the specification and, with
+    /// [`JxlDecoderOptions::permissive`], types that are not defined by it at all.
+    ///
+    /// [`JxlDecoderOptions::permissive`]: crate::api::JxlDecoderOptions::permissive
+    Unknown(u32),
+}
+
+impl JxlExtraChannelType {
+    pub(crate) fn new(ec_type: ExtraChannel) -> Self {
+        match ec_type {
+            ExtraChannel::Alpha => JxlExtraChannelType::Alpha,
+            ExtraChannel::Depth => JxlExtraChannelType::Depth,
+            ExtraChannel::SpotColor => JxlExtraChannelType::SpotColor,
+            ExtraChannel::SelectionMask => JxlExtraChannelType::SelectionMask,
+            ExtraChannel::Black => JxlExtraChannelType::Black,
+            ExtraChannel::CFA => JxlExtraChannelType::CFA,
+            ExtraChannel::Thermal => JxlExtraChannelType::Thermal,
+            ExtraChannel::Optional => JxlExtraChannelType::Optional,
+            other => JxlExtraChannelType::Unknown(other.raw()),
+        }
+    }
+}
+
+#[non_exhaustive]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct JxlExtraChannel {
-    pub ec_type: ExtraChannel,
+    pub ec_type: JxlExtraChannelType,
     pub alpha_associated: bool,
+    /// Name of the channel, empty for unnamed channels.
+    pub name: String,
+    pub bit_depth: JxlBitDepth,
+    /// The channel is stored downsampled by `1 << dim_shift` in each direction.
+    pub dim_shift: u32,
 }
 
+#[non_exhaustive]
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct JxlAnimation {
     pub tps_numerator: u32,
@@ -257,10 +398,954 @@ pub struct JxlAnimation {
     pub have_timecodes: bool,
 }
 
+impl JxlAnimation {
+    /// Describes an animation with the given tick rate, in ticks per second, that repeats
+    /// `num_loops` times, or forever if it is 0.
+    pub fn new(tps_numerator: u32, tps_denominator: u32, num_loops: u32) -> Self {
+        Self {
+            tps_numerator,
+            tps_denominator,
+            num_loops,
+            have_timecodes: false,
+        }
+    }
+}
+
+/// An image size declared by the container that disagrees with the size in the codestream.
+///
+/// Exif metadata can declare the dimensions of the image, which some muxers copy from the
+/// source of a converted image without updating them. Only the codestream determines the decoded
+/// pixels, so its size is always the one used, unless
+/// [`reject_size_mismatch`](crate::api::JxlDecoderOptions::reject_size_mismatch) makes the
+/// disagreement an error. Only Exif boxes before the codestream are compared with it. Both sizes
+/// are the ones the image is stored with, before its orientation is applied.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JxlSizeMismatch {
+    /// The width and height declared by the Exif box of the container.
+    pub container: (usize, usize),
+    /// The width and height in the codestream.
+    pub codestream: (usize, usize),
+}
+
+impl std::fmt::Display for JxlSizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (container, codestream) = (self.container, self.codestream);
+        write!(
+            f,
+            "Exif metadata declares an image size of {}x{}, but the codestream has a size of \
+             {}x{}; using the codestream size",
+            container.0, container.1, codestream.0, codestream.1
+        )
+    }
+}
+
+/// Display duration of a frame, as an exact number of ticks at a rational tick rate.
+///
+/// Converting each frame duration to a floating point or integer time base accumulates rounding
+/// errors over long animations: durations should be summed with [`Self::cumulative_ticks`], and
+/// only converted at the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// Duration in ticks.
+    pub ticks: u32,
+    /// Numerator of the number of ticks per second.
+    pub tps_num: u32,
+    /// Denominator of the number of ticks per second.
+    pub tps_den: u32,
+}
+
+impl FrameTiming {
+    /// Returns the timing of a frame that lasts `ticks` ticks of `animation`.
+    pub fn new(ticks: u32, animation: &JxlAnimation) -> Self {
+        Self {
+            ticks,
+            tps_num: animation.tps_numerator,
+            tps_den: animation.tps_denominator,
+        }
+    }
+
+    /// Returns the duration of the frame, rounded to the nearest nanosecond. Frames with a tick
+    /// rate of zero have no duration.
+    pub fn as_duration(&self) -> Duration {
+        self.ticks_as_duration(self.ticks as u64)
+    }
+
+    /// Returns the duration of `ticks` ticks at the tick rate of this frame, rounded to the
+    /// nearest nanosecond.
+    pub fn ticks_as_duration(&self, ticks: u64) -> Duration {
+        if self.tps_num == 0 {
+            return Duration::ZERO;
+        }
+        let num = ticks as u128 * self.tps_den as u128 * 1_000_000_000;
+        let den = self.tps_num as u128;
+        let nanos = (num + den / 2) / den;
+        Duration::new(
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        )
+    }
+
+    /// Returns the total number of ticks of `timings`, which are expected to share a tick rate.
+    pub fn cumulative_ticks<'a>(timings: impl IntoIterator<Item = &'a FrameTiming>) -> u64 {
+        timings.into_iter().map(|timing| timing.ticks as u64).sum()
+    }
+}
+
+#[non_exhaustive]
 #[derive(Clone, Debug)]
 pub struct JxlFrameHeader {
+    /// Frame name, empty if the frame has none. With [`JxlDecoderOptions::permissive`], names
+    /// that are not valid UTF-8 are converted lossily.
+    ///
+    /// [`JxlDecoderOptions::permissive`]: crate::api::JxlDecoderOptions::permissive
     pub name: String,
-    pub duration: Option<f64>,
+    /// Duration of the frame, for animations.
+    pub timing: Option<FrameTiming>,
     /// Frame size (width, height)
     pub size: (usize, usize),
+    /// Progressive passes of the frame.
+    pub passes: PassesInfo,
+    /// Downsampling factor at which the frame is decoded, following
+    /// [`JxlDecoderOptions::downsample`]. Frames whose coding tools cannot be rendered from some
+    /// of their passes are still decoded at full resolution, which is only known later.
+    ///
+    /// [`JxlDecoderOptions::downsample`]: crate::api::JxlDecoderOptions::downsample
+    pub downsample: u32,
+    /// Groups in which the frame is coded.
+    pub groups: GroupLayout,
+    /// Simplifications of [`JxlDecoderOptions::speed_profile`] that change the rendering of the
+    /// frame, because it uses the features that they simplify.
+    ///
+    /// [`JxlDecoderOptions::speed_profile`]: crate::api::JxlDecoderOptions::speed_profile
+    pub simplifications: Simplifications,
+    /// Whether the frame header was coded with `all_default`, which gives all its fields their
+    /// default values. Such frames decode exactly like frames whose header codes the default
+    /// values explicitly.
+    pub all_default: bool,
+    /// Coding features that the frame uses.
+    pub flags: FrameFlags,
+    /// The flags as they were coded. Bits that are reserved by the specification, which are only
+    /// accepted with [`JxlDecoderOptions::permissive`], have no effect on decoding.
+    ///
+    /// [`JxlDecoderOptions::permissive`]: crate::api::JxlDecoderOptions::permissive
+    pub raw_flags: u64,
+}
+
+impl JxlFrameHeader {
+    /// Side of the groups of the frame, in pixels.
+    pub fn group_dim(&self) -> usize {
+        self.groups.group_dim
+    }
+}
+
+/// Geometry of the groups of a frame. Groups are the units in which pixels are coded and decoded
+/// in parallel, and determine the number of sections in the table of contents.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GroupLayout {
+    /// Side of a group in pixels, before upsampling. This is 128, 256, 512 or 1024 for modular
+    /// frames, and always 256 for VarDCT frames.
+    pub group_dim: usize,
+    /// Side of an LF group in pixels, which is 8 times `group_dim`.
+    pub lf_group_dim: usize,
+    /// Number of groups horizontally and vertically.
+    pub num_groups: (usize, usize),
+    /// Number of LF groups horizontally and vertically.
+    pub num_lf_groups: (usize, usize),
+    /// Number of progressive passes, each of which has a section for every group.
+    pub num_passes: usize,
+}
+
+impl GroupLayout {
+    pub(crate) fn new(header: &FrameHeader) -> Self {
+        Self {
+            group_dim: header.group_dim(),
+            lf_group_dim: header.lf_group_dim(),
+            num_groups: header.size_groups(),
+            num_lf_groups: header.size_lf_groups(),
+            num_passes: header.passes.num_passes as usize,
+        }
+    }
+
+    /// Returns the groups, in raster order.
+    pub fn groups(&self) -> impl Iterator<Item = GroupId> + use<> {
+        (0..self.num_groups.0 * self.num_groups.1).map(GroupId::new)
+    }
+
+    /// Returns the LF groups, in raster order.
+    pub fn lf_groups(&self) -> impl Iterator<Item = LfGroupId> + use<> {
+        (0..self.num_lf_groups.0 * self.num_lf_groups.1).map(LfGroupId::new)
+    }
+
+    /// Returns the passes, in decoding order.
+    pub fn passes(&self) -> impl Iterator<Item = PassId> + use<> {
+        (0..self.num_passes).map(PassId::new)
+    }
+
+    /// Returns the horizontal and vertical position of `group`, in groups.
+    pub fn group_coords(&self, group: GroupId) -> (usize, usize) {
+        (
+            group.index() % self.num_groups.0,
+            group.index() / self.num_groups.0,
+        )
+    }
+
+    /// Returns the horizontal and vertical position of `group`, in LF groups.
+    pub fn lf_group_coords(&self, group: LfGroupId) -> (usize, usize) {
+        (
+            group.index() % self.num_lf_groups.0,
+            group.index() / self.num_lf_groups.0,
+        )
+    }
+
+    /// Number of sections in the table of contents. Frames with a single group and a single pass
+    /// store all of their sections in one entry.
+    pub fn num_sections(&self) -> usize {
+        let num_groups = self.num_groups.0 * self.num_groups.1;
+        if num_groups == 1 && self.num_passes == 1 {
+            1
+        } else {
+            2 + self.num_lf_groups.0 * self.num_lf_groups.1 + num_groups * self.num_passes
+        }
+    }
+
+    /// Returns the sections of the frame in the order of their [`SectionId`]s. Frames with a
+    /// single TOC entry store all of them in that entry.
+    pub fn sections(&self) -> impl Iterator<Item = Section> + use<> {
+        let layout = *self;
+        let hf_sections = self.passes().flat_map(move |pass| {
+            layout
+                .groups()
+                .map(move |group| Section::Hf { group, pass })
+        });
+        std::iter::once(Section::LfGlobal)
+            .chain(self.lf_groups().map(|group| Section::Lf { group }))
+            .chain(std::iter::once(Section::HfGlobal))
+            .chain(hf_sections)
+    }
+
+    /// Returns the id of the TOC entry that stores `section`.
+    pub fn section_id(&self, section: Section) -> SectionId {
+        if self.num_sections() == 1 {
+            return SectionId::new(0);
+        }
+        let num_lf_groups = self.num_lf_groups.0 * self.num_lf_groups.1;
+        SectionId::new(match section {
+            Section::LfGlobal => 0,
+            Section::Lf { group } => 1 + group.index(),
+            Section::HfGlobal => 1 + num_lf_groups,
+            // The sections of all groups of a pass precede those of the next pass.
+            Section::Hf { group, pass } => {
+                2 + num_lf_groups
+                    + self.num_groups.0 * self.num_groups.1 * pass.index()
+                    + group.index()
+            }
+        })
+    }
+
+    /// Returns the id of the TOC entry that stores `pass` of `group`.
+    pub fn section_for(&self, pass: PassId, group: GroupId) -> SectionId {
+        self.section_id(Section::Hf { group, pass })
+    }
+}
+
+/// How a single pass of a frame refines the image.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassInfo {
+    /// Smallest downsampling factor at which the image is complete once this pass is decoded.
+    /// This is 1 for the last pass, and 8 for passes that only refine the LF image.
+    pub downsample: u32,
+    /// Whether this pass is the one that completes the image at `downsample`. Passes for which
+    /// this is false add detail towards the next downsampling factor.
+    pub last_pass: bool,
+    /// Number of bits by which the VarDCT coefficients decoded in this pass are shifted left,
+    /// so that earlier passes can carry the most significant bits of the coefficients.
+    pub coefficient_shift: u32,
+}
+
+/// Pass structure of a frame, as signaled in its header. Frames that are not progressive have a
+/// single pass.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassesInfo {
+    pub passes: Vec<PassInfo>,
+}
+
+impl PassesInfo {
+    pub(crate) fn new(passes: &Passes) -> Self {
+        let passes = (0..passes.num_passes as usize)
+            .map(|pass| {
+                let (downsample, last_pass) = passes.completed_downsample(pass);
+                PassInfo {
+                    downsample,
+                    last_pass,
+                    coefficient_shift: passes.coefficient_shift(pass),
+                }
+            })
+            .collect();
+        Self { passes }
+    }
+
+    pub fn num_passes(&self) -> usize {
+        self.passes.len()
+    }
+}
+
+impl Default for PassesInfo {
+    fn default() -> Self {
+        Self {
+            passes: vec![PassInfo {
+                downsample: 1,
+                last_pass: true,
+                coefficient_shift: 0,
+            }],
+        }
+    }
+}
+
+impl std::fmt::Display for PassesInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, pass) in self.passes.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "1/{}", pass.downsample)?;
+            if !pass.last_pass {
+                write!(f, " (partial)")?;
+            }
+            if pass.coefficient_shift > 0 {
+                write!(f, " <<{}", pass.coefficient_shift)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Difference between a decoded frame and the previously decoded one, as computed with
+/// [`JxlDecoderOptions::compute_frame_diffs`].
+///
+/// The first frame after creating, rewinding or seeking the decoder is reported as entirely
+/// changed.
+///
+/// [`JxlDecoderOptions::compute_frame_diffs`]: crate::api::JxlDecoderOptions::compute_frame_diffs
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JxlFrameDiff {
+    /// Smallest rectangle, in output pixels, containing every changed pixel, or `None` if the
+    /// frame is identical to the previous one.
+    pub changed_rect: Option<Rect>,
+    /// Number of pixels that changed in at least one of the output buffers.
+    pub changed_pixels: usize,
+}
+
+/// A group of a frame that failed to decode and was concealed, with
+/// [`JxlDecoderOptions::skip_corrupt_sections`].
+///
+/// [`JxlDecoderOptions::skip_corrupt_sections`]: crate::api::JxlDecoderOptions::skip_corrupt_sections
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConcealedGroup {
+    /// The section that failed: the HF section of a group that is rendered without its AC
+    /// coefficients from this pass on, or an LF group whose DC is interpolated. The groups of a
+    /// concealed VarDCT LF group are reported as well, with their first pass.
+    pub section: Section,
+    /// Horizontal and vertical position of the group, in groups for HF sections and in LF groups
+    /// for LF sections, as returned by [`GroupLayout::group_coords`] and
+    /// [`GroupLayout::lf_group_coords`].
+    pub coords: (usize, usize),
+}
+
+/// Geometry of a single output buffer expected by the decoder for the current frame.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferRequirement {
+    /// Width of the buffer, in pixels.
+    pub width: usize,
+    /// Height of the buffer, in pixels.
+    pub height: usize,
+    /// Number of interleaved samples for each pixel.
+    pub samples_per_pixel: usize,
+    /// Data type of the samples, which sets the number of bytes of each.
+    pub data_type: JxlDataFormat,
+}
+
+impl BufferRequirement {
+    /// Minimum number of bytes in each row of the buffer.
+    pub fn bytes_per_row(&self) -> usize {
+        self.width * self.samples_per_pixel * self.data_type.bytes_per_sample()
+    }
+
+    /// Buffer size in bytes, as (bytes per row, number of rows).
+    pub fn byte_size(&self) -> (usize, usize) {
+        (self.bytes_per_row(), self.height)
+    }
+}
+
+/// Compression properties of a single (non-preview) frame, as seen from its headers.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameCompressionInfo {
+    /// Whether the frame is displayed, as opposed to e.g. reference-only or LF frames.
+    pub is_visible: bool,
+    /// Whether the frame uses modular (as opposed to VarDCT) encoding.
+    pub modular: bool,
+    /// Whether nothing in the headers makes the frame lossy: it is modular, not XYB encoded, and
+    /// uses no upsampling, YCbCr, restoration filters or noise.
+    ///
+    /// Quantization in modular transforms and trees is not part of the headers, so this is an
+    /// upper bound on losslessness.
+    pub lossless: bool,
+    /// Total size of the frame sections, as listed in the TOC.
+    pub section_bytes: u64,
+}
+
+/// Summary of how an image was compressed, available once the headers of all frames have been
+/// parsed.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressionSummary {
+    /// All frames are modular and [`lossless`](FrameCompressionInfo::lossless).
+    pub all_modular_lossless: bool,
+    /// At least one frame uses VarDCT.
+    pub any_vardct: bool,
+    /// The image is stored in the XYB color space.
+    pub xyb_encoded: bool,
+    /// Size of the codestream in bits, divided by the number of pixels of all visible frames.
+    pub bits_per_pixel: f32,
+    /// Compression of each frame whose header was decoded, in codestream order, including frames
+    /// that are not displayed.
+    pub per_frame: Vec<FrameCompressionInfo>,
+}
+
+impl std::fmt::Display for CompressionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.all_modular_lossless {
+            write!(f, "lossless (modular)")?;
+        } else {
+            let any_modular = self.per_frame.iter().any(|frame| frame.modular);
+            let encoding = match (self.any_vardct, any_modular) {
+                (true, true) => "VarDCT + modular",
+                (true, false) => "VarDCT",
+                _ => "modular",
+            };
+            write!(f, "lossy ({encoding}")?;
+            if self.xyb_encoded {
+                write!(f, ", XYB")?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ", {:.1} bpp", self.bits_per_pixel)
+    }
+}
+
+/// Decoding stages whose time is measured when the `timing-stats` feature is enabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JxlDecodeStage {
+    /// Entropy decoding of VarDCT coefficients.
+    EntropyDecode,
+    /// Dequantization and inverse DCT of VarDCT blocks.
+    DequantIdct,
+    /// Decoding of modular channels. Entropy decoding and prediction are interleaved for each
+    /// sample, so both are counted here.
+    ModularPrediction,
+    /// Inverse modular transforms (RCT, palette and squeeze).
+    InverseTransforms,
+    /// Edge-preserving filter.
+    Epf,
+    Gaborish,
+    /// Upsampling of frames, extra channels and chroma.
+    Upsample,
+    /// Color space conversions, including XYB, YCbCr and CMS transforms.
+    ColorConvert,
+    /// Conversion to the output format and writing to the output buffers.
+    OutputWrite,
+}
+
+impl JxlDecodeStage {
+    pub const ALL: [JxlDecodeStage; 9] = [
+        JxlDecodeStage::EntropyDecode,
+        JxlDecodeStage::DequantIdct,
+        JxlDecodeStage::ModularPrediction,
+        JxlDecodeStage::InverseTransforms,
+        JxlDecodeStage::Epf,
+        JxlDecodeStage::Gaborish,
+        JxlDecodeStage::Upsample,
+        JxlDecodeStage::ColorConvert,
+        JxlDecodeStage::OutputWrite,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            JxlDecodeStage::EntropyDecode => "entropy decode",
+            JxlDecodeStage::DequantIdct => "dequant + IDCT",
+            JxlDecodeStage::ModularPrediction => "modular prediction",
+            JxlDecodeStage::InverseTransforms => "inverse transforms",
+            JxlDecodeStage::Epf => "EPF",
+            JxlDecodeStage::Gaborish => "gaborish",
+            JxlDecodeStage::Upsample => "upsample",
+            JxlDecodeStage::ColorConvert => "color convert",
+            JxlDecodeStage::OutputWrite => "output write",
+        }
+    }
+}
+
+/// Cumulative time spent in each [`JxlDecodeStage`], summed over all threads.
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JxlDecodeTimings {
+    nanos: [u64; JxlDecodeStage::ALL.len()],
+}
+
+impl JxlDecodeTimings {
+    pub(crate) fn add(&mut self, stage: JxlDecodeStage, nanos: u64) {
+        self.nanos[stage as usize] += nanos;
+    }
+
+    /// Adds the timings of `other` (e.g. collected on a different thread) to these.
+    pub fn merge(&mut self, other: &JxlDecodeTimings) {
+        for (total, nanos) in self.nanos.iter_mut().zip(other.nanos) {
+            *total += nanos;
+        }
+    }
+
+    /// Time spent in `stage`.
+    pub fn stage(&self, stage: JxlDecodeStage) -> Duration {
+        Duration::from_nanos(self.nanos[stage as usize])
+    }
+
+    /// Time spent in all stages.
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.nanos.iter().sum())
+    }
+
+    /// Iterates over all stages and the time spent in each of them.
+    pub fn iter(&self) -> impl Iterator<Item = (JxlDecodeStage, Duration)> + '_ {
+        JxlDecodeStage::ALL
+            .into_iter()
+            .map(|stage| (stage, self.stage(stage)))
+    }
+}
+
+/// Number of modular predictors, i.e. the length of [`ModularStats::predictor_pixel_counts`].
+pub const NUM_MODULAR_PREDICTORS: usize = Predictor::NUM_PREDICTORS as usize;
+
+/// Statistics about the modular streams of a frame, collected when the `timing-stats` feature is
+/// enabled. VarDCT frames use modular streams too, for their LF coefficients and block metadata.
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModularStats {
+    /// Number of samples predicted with each [`Predictor`], indexed by predictor id.
+    pub predictor_pixel_counts: [u64; NUM_MODULAR_PREDICTORS],
+    /// Transforms of every modular stream, in stream order and, within a stream, in the order in
+    /// which they appear in the bitstream (i.e. the reverse of the order they are undone in).
+    pub transforms: Vec<TransformDesc>,
+    /// Total number of nodes of the global and local MA trees.
+    pub tree_nodes: usize,
+    /// Number of channels coded in the global modular image, including meta channels.
+    pub channels: usize,
+}
+
+impl ModularStats {
+    /// Adds the statistics of `other` (e.g. collected on a different thread) to these.
+    pub fn merge(&mut self, other: &ModularStats) {
+        for (total, count) in self
+            .predictor_pixel_counts
+            .iter_mut()
+            .zip(other.predictor_pixel_counts)
+        {
+            *total += count;
+        }
+        self.transforms.extend_from_slice(&other.transforms);
+        self.transforms.sort_by_key(|desc| desc.stream);
+        self.tree_nodes += other.tree_nodes;
+        self.channels += other.channels;
+    }
+
+    /// Iterates over all predictors and the number of samples predicted with each of them.
+    pub fn predictor_counts(&self) -> impl Iterator<Item = (Predictor, u64)> + '_ {
+        self.predictor_pixel_counts
+            .iter()
+            .enumerate()
+            .map(|(id, count)| (Predictor::try_from(id as u32).unwrap(), *count))
+    }
+
+    /// The predictor used for the most samples, if any sample was predicted.
+    pub fn dominant_predictor(&self) -> Option<Predictor> {
+        self.predictor_counts()
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(_, count)| *count)
+            .map(|(predictor, _)| predictor)
+    }
+}
+
+/// A transform of a modular stream, as listed in [`ModularStats::transforms`].
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformDesc {
+    /// Id of the stream the transform belongs to, as used by the `stream` property of MA trees:
+    /// 0 for the global stream, followed by the LF, LF metadata and HF group streams.
+    pub stream: usize,
+    pub(crate) transform: Transform,
+}
+
+impl std::fmt::Display for TransformDesc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let t = &self.transform;
+        match t.id {
+            TransformId::Rct => write!(
+                f,
+                "RCT {} (channels {}-{})",
+                t.rct_type,
+                t.begin_channel,
+                t.begin_channel + 2
+            ),
+            TransformId::Palette => {
+                write!(
+                    f,
+                    "palette (channels {}-{}, {} colors, {} deltas",
+                    t.begin_channel,
+                    t.begin_channel + t.num_channels - 1,
+                    t.num_colors,
+                    t.num_deltas
+                )?;
+                if t.num_deltas > 0 {
+                    let predictor =
+                        Predictor::try_from(t.predictor_id).map_err(|_| std::fmt::Error)?;
+                    write!(f, ", {predictor:?} predictor")?;
+                }
+                write!(f, ")")
+            }
+            TransformId::Squeeze if t.squeezes.is_empty() => write!(f, "squeeze (default)"),
+            TransformId::Squeeze => {
+                write!(f, "squeeze (")?;
+                for (i, step) in t.squeezes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(
+                        f,
+                        "{}{} channels {}-{}",
+                        if step.horizontal { "H" } else { "V" },
+                        if step.in_place { "" } else { " moved" },
+                        step.begin_channel,
+                        step.begin_channel + step.num_channels - 1
+                    )?;
+                }
+                write!(f, ")")
+            }
+            TransformId::Invalid => write!(f, "invalid"),
+        }
+    }
+}
+
+/// Result of re-checking a coded channel of a modular stream, collected when the `verify` feature
+/// and [`JxlDecoderOptions::verify_modular`](crate::api::JxlDecoderOptions::verify_modular) are
+/// enabled.
+///
+/// The check re-applies the transforms of the stream to the decoded channels, which turns them
+/// back into the coded channels, re-predicts every sample of those and compares the residuals with
+/// the ones decoded from the bitstream. Transforms of the global stream are undone when rendering,
+/// so they are not re-applied: for its channels, only the prediction is checked.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModularChannelCheck {
+    /// Id of the stream, as in [`TransformDesc::stream`].
+    pub stream: usize,
+    /// Index of the channel among the coded channels of the stream, including meta channels.
+    pub channel: usize,
+    pub size: (usize, usize),
+    pub outcome: ModularCheckOutcome,
+}
+
+/// See [`ModularChannelCheck`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModularCheckOutcome {
+    Passed,
+    /// Adding the decoded residual to the prediction of the sample at `(x, y)` of the coded
+    /// channel does not give its decoded value. Samples are checked in decoding order, so this is
+    /// the first such sample; `y` is the height of the channel if there were more residuals than
+    /// samples.
+    Mismatch {
+        x: usize,
+        y: usize,
+    },
+    /// The transforms of the stream cannot be re-applied unambiguously, as for palettes with
+    /// delta entries or repeated colors.
+    Skipped,
+}
+
+impl std::fmt::Display for ModularCheckOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModularCheckOutcome::Passed => f.write_str("passed"),
+            ModularCheckOutcome::Mismatch { x, y } => write!(f, "mismatch at ({x}, {y})"),
+            ModularCheckOutcome::Skipped => f.write_str("skipped"),
+        }
+    }
+}
+
+/// Entropy code read from the codestream, with the symbol distributions that are otherwise
+/// discarded once decoding tables are built. Collected when the `debug-tools` feature and
+/// [`JxlDecoderOptions::dump_entropy_codes`](crate::api::JxlDecoderOptions::dump_entropy_codes)
+/// are enabled.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntropyCodeInfo {
+    /// Index of the frame in the codestream, or `None` for the ICC profile and the preview frame.
+    pub frame: Option<usize>,
+    /// Section the code was read from, or `None` outside of sections, as for the ICC profile and
+    /// TOC permutations.
+    pub section: Option<Section>,
+    pub purpose: EntropyCodePurpose,
+    /// Cluster of each context. With LZ77, the last context is the one of LZ77 distances.
+    pub context_map: Vec<u8>,
+    pub lz77: Option<Lz77Info>,
+    /// Distribution and integer configuration of each cluster.
+    pub clusters: Vec<EntropyCluster>,
+}
+
+/// What an [`EntropyCodeInfo`] is used to decode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntropyCodePurpose {
+    IccProfile,
+    TocPermutation,
+    Patches,
+    Splines,
+    ModularTree,
+    ModularStream,
+    CoeffOrder,
+    HfCoefficients,
+}
+
+impl EntropyCodePurpose {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::IccProfile => "icc_profile",
+            Self::TocPermutation => "toc_permutation",
+            Self::Patches => "patches",
+            Self::Splines => "splines",
+            Self::ModularTree => "modular_tree",
+            Self::ModularStream => "modular_stream",
+            Self::CoeffOrder => "coeff_order",
+            Self::HfCoefficients => "hf_coefficients",
+        }
+    }
+}
+
+/// Configuration of how integers are split into a token and raw bits.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HybridUintInfo {
+    pub split_exponent: u32,
+    pub msb_in_token: u32,
+    pub lsb_in_token: u32,
+}
+
+/// LZ77 parameters of an [`EntropyCodeInfo`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lz77Info {
+    pub min_symbol: u32,
+    pub min_length: u32,
+    /// Configuration of the integers that encode copy lengths.
+    pub length_uint: HybridUintInfo,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EntropyCluster {
+    pub uint: HybridUintInfo,
+    pub distribution: SymbolDistribution,
+}
+
+/// Distribution of the tokens of a cluster, indexed by token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolDistribution {
+    /// ANS frequencies, which add up to [`SymbolDistribution::ANS_TOTAL`].
+    Ans { counts: Vec<u16> },
+    /// Lengths of the prefix codes, 0 for symbols that cannot be decoded.
+    Prefix { code_lengths: Vec<u8> },
+    /// Prefix code with a single symbol, which is decoded without reading any bits.
+    PrefixSingle { symbol: u32 },
+}
+
+impl SymbolDistribution {
+    pub const ANS_TOTAL: u32 = 1 << 12;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameTiming, GroupLayout, JxlBitDepth, PreferredOutput, Section};
+    use std::time::Duration;
+
+    #[test]
+    fn preferred_output() {
+        let int = |bits_per_sample| JxlBitDepth::Int { bits_per_sample };
+        let float = |bits_per_sample, exponent_bits_per_sample| JxlBitDepth::Float {
+            bits_per_sample,
+            exponent_bits_per_sample,
+        };
+        for (bit_depth, preferred) in [
+            (int(1), PreferredOutput::U8),
+            (int(8), PreferredOutput::U8),
+            (int(9), PreferredOutput::U16),
+            (int(12), PreferredOutput::U16),
+            (int(16), PreferredOutput::U16),
+            (int(17), PreferredOutput::F32),
+            (int(31), PreferredOutput::F32),
+            (float(16, 5), PreferredOutput::F16),
+            (float(11, 4), PreferredOutput::F16),
+            (float(14, 3), PreferredOutput::F16),
+            // Fewer exponent bits do not make up for more mantissa bits.
+            (float(16, 4), PreferredOutput::F32),
+            // bfloat16 has a wider range than half floats.
+            (float(16, 8), PreferredOutput::F32),
+            (float(24, 7), PreferredOutput::F32),
+            (float(32, 8), PreferredOutput::F32),
+        ] {
+            assert_eq!(bit_depth.preferred_output(), preferred, "{bit_depth:?}");
+        }
+    }
+
+    #[test]
+    fn float_semantics() {
+        let int = JxlBitDepth::Int { bits_per_sample: 1 };
+        assert!(!int.is_float());
+        assert_eq!(int.exponent_bits(), 0);
+        let half = JxlBitDepth::Float {
+            bits_per_sample: 16,
+            exponent_bits_per_sample: 5,
+        };
+        assert!(half.is_float());
+        assert_eq!(half.exponent_bits(), 5);
+        assert_eq!(half.bits_per_sample(), 16);
+    }
+
+    #[test]
+    fn frame_timing_ntsc() {
+        let timings: Vec<_> = (0..10000)
+            .map(|i| FrameTiming {
+                ticks: 1 + i % 3,
+                tps_num: 30000,
+                tps_den: 1001,
+            })
+            .collect();
+        let ticks = FrameTiming::cumulative_ticks(&timings);
+        assert_eq!(ticks, 19999);
+        let total = timings[0].ticks_as_duration(ticks);
+        // 19999 * 1001 / 30000 seconds, rounded to the nearest nanosecond.
+        assert_eq!(total, Duration::new(667, 299_966_667));
+        // Summing rounded durations drifts by at most half a nanosecond per frame, while
+        // summing milliseconds drifts by seconds.
+        let sum: Duration = timings.iter().map(FrameTiming::as_duration).sum();
+        let tick = timings[0].ticks_as_duration(1);
+        assert!(sum.abs_diff(total) <= Duration::from_nanos(5000));
+        assert!(sum.abs_diff(total) < tick);
+        let sum_ms: u64 = timings
+            .iter()
+            .map(|timing| timing.as_duration().as_millis() as u64)
+            .sum();
+        assert!(total.abs_diff(Duration::from_millis(sum_ms)) > tick);
+    }
+
+    #[test]
+    fn frame_timing_duration() {
+        let timing = |ticks, tps_num, tps_den| FrameTiming {
+            ticks,
+            tps_num,
+            tps_den,
+        };
+        assert_eq!(
+            timing(1, 3, 1).as_duration(),
+            Duration::from_nanos(333_333_333)
+        );
+        assert_eq!(
+            timing(2, 3, 1).as_duration(),
+            Duration::from_nanos(666_666_667)
+        );
+        assert_eq!(timing(5, 0, 1).as_duration(), Duration::ZERO);
+        assert_eq!(
+            timing(u32::MAX, 1, u32::MAX).as_duration(),
+            Duration::from_secs(u32::MAX as u64 * u32::MAX as u64)
+        );
+    }
+
+    #[test]
+    fn group_layout_ids() {
+        for (num_groups, num_lf_groups, num_passes) in [
+            ((1, 1), (1, 1), 1),
+            ((1, 1), (1, 1), 3),
+            ((3, 2), (1, 1), 1),
+            ((3, 2), (1, 1), 2),
+            ((17, 1), (3, 1), 2),
+            ((9, 9), (2, 2), 4),
+        ] {
+            let layout = GroupLayout {
+                group_dim: 256,
+                lf_group_dim: 2048,
+                num_groups,
+                num_lf_groups,
+                num_passes,
+            };
+            let groups = num_groups.0 * num_groups.1;
+            let lf_groups = num_lf_groups.0 * num_lf_groups.1;
+            let ids: Vec<_> = layout
+                .sections()
+                .map(|section| layout.section_id(section).index())
+                .collect();
+            assert_eq!(ids.len(), 2 + lf_groups + groups * num_passes);
+            if groups == 1 && num_passes == 1 {
+                assert_eq!(layout.num_sections(), 1);
+                assert!(ids.iter().all(|&id| id == 0));
+            } else {
+                assert_eq!(layout.num_sections(), ids.len());
+                assert_eq!(ids, (0..ids.len()).collect::<Vec<_>>());
+                // The sections of a pass are consecutive, so the pass is the major index.
+                for pass in layout.passes() {
+                    for group in layout.groups() {
+                        assert_eq!(
+                            layout.section_for(pass, group).index(),
+                            2 + lf_groups + pass.index() * groups + group.index()
+                        );
+                    }
+                }
+                for group in layout.lf_groups() {
+                    let id = layout.section_id(Section::Lf { group });
+                    assert_eq!(id.index(), 1 + group.index());
+                }
+            }
+
+            let raster = |(xsize, ysize): (usize, usize)| {
+                (0..ysize)
+                    .flat_map(|y| (0..xsize).map(move |x| (x, y)))
+                    .collect::<Vec<_>>()
+            };
+            let coords: Vec<_> = layout.groups().map(|g| layout.group_coords(g)).collect();
+            assert_eq!(coords, raster(num_groups));
+            let coords: Vec<_> = layout
+                .lf_groups()
+                .map(|g| layout.lf_group_coords(g))
+                .collect();
+            assert_eq!(coords, raster(num_lf_groups));
+        }
+
+        // Two passes of 3x2 groups: the second pass of the first group comes after all groups of
+        // the first pass.
+        let layout = GroupLayout {
+            group_dim: 256,
+            lf_group_dim: 2048,
+            num_groups: (3, 2),
+            num_lf_groups: (1, 1),
+            num_passes: 2,
+        };
+        let mut passes = layout.passes();
+        let (first, second) = (passes.next().unwrap(), passes.next().unwrap());
+        let mut groups = layout.groups();
+        let (group0, group1) = (groups.next().unwrap(), groups.next().unwrap());
+        assert_eq!(layout.section_for(first, group1).index(), 4);
+        assert_eq!(layout.section_for(second, group0).index(), 9);
+        assert_eq!(layout.group_coords(group1), (1, 0));
+    }
 }
@@ -4,20 +4,38 @@
 // license that can be found in the LICENSE file.
 
 use super::{
-    JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlDecoderInner, JxlDecoderOptions,
-    JxlOutputBuffer, JxlPixelFormat, ProcessingResult,
+    BufferRequirement, CompressionSummary, JxlBasicInfo, JxlBitstreamInput, JxlColorProfile,
+    JxlColorProfileMismatch, JxlColorProfileSource, JxlDecoderInner, JxlDecoderOptions,
+    JxlFrameDiff, JxlMemoryUsage, JxlOutputBuffer, JxlPixelFormat, JxlSizeMismatch,
+    ProcessingResult,
 };
 #[cfg(test)]
 use crate::frame::Frame;
-use crate::{api::JxlFrameHeader, container::frame_index::FrameIndexBox, error::Result};
+use crate::frame::FrameIndices;
+use crate::{
+    api::JxlFrameHeader,
+    container::frame_index::FrameIndexBox,
+    error::{Error, Result},
+    image::Rect,
+};
 use states::*;
 use std::marker::PhantomData;
 
 pub mod states {
-    pub trait JxlState {}
+    mod private {
+        #[allow(unnameable_types)]
+        pub trait Sealed {}
+    }
+
+    /// The states of a [`JxlDecoder`](super::JxlDecoder). The decoder only works with the states
+    /// defined here, so this trait cannot be implemented outside of this crate.
+    pub trait JxlState: private::Sealed {}
     pub struct Initialized;
     pub struct WithImageInfo;
     pub struct WithFrameInfo;
+    impl private::Sealed for Initialized {}
+    impl private::Sealed for WithImageInfo {}
+    impl private::Sealed for WithFrameInfo {}
     impl JxlState for Initialized {}
     impl JxlState for WithImageInfo {}
     impl JxlState for WithFrameInfo {}
@@ -27,15 +45,44 @@ pub mod states {
 // If we do, one way is to take a callback &[u8; 4] -> Box<dyn Write>.
 
 /// High level API using the typestate pattern to forbid invalid usage.
+///
+/// Every state transition consumes the decoder, including the ones that run out of input: the
+/// decoder is then handed back as the `fallback` of [`ProcessingResult::NeedsMoreInput`], and
+/// only that value can be used to continue decoding. Reusing an outdated decoder is thus
+/// rejected at compile time:
+///
+/// ```compile_fail,E0382
+/// # use jxl::api::{JxlDecoder, JxlDecoderOptions, ProcessingResult, states};
+/// let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+/// let mut input: &[u8] = &[];
+/// if let ProcessingResult::NeedsMoreInput { .. } = decoder.process(&mut input)? {
+///     // Wrong: `decoder` was moved into `process`, the `fallback` must be used instead.
+///     decoder.process(&mut input)?;
+/// }
+/// # Ok::<(), jxl::error::Error>(())
+/// ```
+///
+/// Dropping the result of a transition drops the decoder with it, which is most likely a bug:
+///
+/// ```compile_fail
+/// # #![deny(unused_must_use)]
+/// # use jxl::api::{JxlDecoder, JxlDecoderOptions, states};
+/// let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+/// let mut input: &[u8] = &[];
+/// decoder.process(&mut input)?;
+/// # Ok::<(), jxl::error::Error>(())
+/// ```
+#[must_use = "dropping the decoder discards all decoding progress"]
 pub struct JxlDecoder<State: JxlState> {
     inner: Box<JxlDecoderInner>,
     _state: PhantomData<State>,
 }
 
 #[cfg(test)]
-pub type FrameCallback = dyn FnMut(&Frame, usize) -> Result<()>;
+pub(crate) type FrameCallback = dyn FnMut(&Frame, usize) -> Result<()>;
 
 /// Information about a single visible frame discovered while decoding.
+#[non_exhaustive]
 #[derive(Debug, Clone, PartialEq)]
 pub struct VisibleFrameInfo {
     /// Zero-based index among visible frames.
@@ -45,7 +92,7 @@ pub struct VisibleFrameInfo {
     /// Duration in raw ticks from the animation header.
     pub duration_ticks: u32,
     /// Byte offset of this frame's header in the input file.
-    pub(crate) file_offset: usize,
+    pub(crate) file_offset: u64,
     /// Whether this is the last frame in the codestream.
     pub is_last: bool,
     /// Whether this frame is a seek-keyframe for visible-frame playback.
@@ -54,21 +101,35 @@ pub struct VisibleFrameInfo {
     pub is_keyframe: bool,
     /// Precomputed seek inputs for this visible frame.
     pub seek_target: VisibleFrameSeekTarget,
-    /// Frame name, if any.
-    pub name: String,
+    /// Length of the frame name in bytes, 0 if the frame has none.
+    pub name_len: usize,
+    /// Frame name, unless it was omitted to stay within
+    /// [`JxlDecoderOptions::max_frame_names_size`].
+    pub(crate) name: Option<Box<str>>,
+}
+
+impl VisibleFrameInfo {
+    /// Frame name, empty if the frame has none, or `None` if the names of the frames before it
+    /// already took [`JxlDecoderOptions::max_frame_names_size`] bytes. See
+    /// [`JxlFrameHeader::name`].
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
 }
 
 /// Computed seek inputs for a target visible frame.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VisibleFrameSeekTarget {
     /// File byte offset to start feeding input from.
-    pub decode_start_file_offset: usize,
+    pub decode_start_file_offset: u64,
     /// Remaining codestream bytes in the current container box at the seek
     /// point. Pass this to [`JxlDecoder::start_new_frame`].
     pub remaining_in_box: u64,
     /// Number of visible frames to skip after seek-start before decoding the
     /// requested target frame.
     pub visible_frames_to_skip: usize,
+    /// Frame counts of the decoder at the seek point, which seed the noise of the frames.
+    pub(crate) frame_indices: FrameIndices,
 }
 
 impl<S: JxlState> JxlDecoder<S> {
@@ -81,7 +142,7 @@ impl<S: JxlState> JxlDecoder<S> {
 
     /// Sets a callback that processes all frames by calling `callback(frame, frame_index)`.
     #[cfg(test)]
-    pub fn set_frame_callback(&mut self, callback: Box<FrameCallback>) {
+    pub(crate) fn set_frame_callback(&mut self, callback: Box<FrameCallback>) {
         self.inner.set_frame_callback(callback);
     }
 
@@ -110,6 +171,35 @@ impl<S: JxlState> JxlDecoder<S> {
         self.inner.scanned_frames()
     }
 
+    /// Returns a summary of how the image was compressed (lossless or not, VarDCT or modular,
+    /// bits per pixel), or `None` if not all frames have been parsed yet.
+    ///
+    /// Enable `JxlDecoderOptions::scan_frames_only` and skip all frames to compute it cheaply.
+    pub fn compression_summary(&self) -> Option<CompressionSummary> {
+        self.inner.compression_summary()
+    }
+
+    /// Returns the cumulative time spent in each decoding stage by this decoder.
+    ///
+    /// Timings are collected at row and block granularity, so stages that take less time than
+    /// the clock resolution per call may be underestimated.
+    #[cfg(feature = "timing-stats")]
+    pub fn decode_timings(&self) -> &super::JxlDecodeTimings {
+        self.inner.decode_timings()
+    }
+
+    /// Returns the number of entropy-coded tokens decoded so far, which is what
+    /// [`JxlDecoderOptions::max_total_tokens`] limits.
+    pub fn total_tokens(&self) -> u64 {
+        self.inner.total_tokens()
+    }
+
+    /// Returns the memory of the image buffers this decoder allocated, which hold all of its
+    /// large allocations, from [`JxlDecoderOptions::allocator`] or the global allocator.
+    pub fn memory_usage(&self) -> JxlMemoryUsage {
+        self.inner.memory_usage()
+    }
+
     /// Rewinds a decoder to the start of the file, allowing past frames to be displayed again.
     pub fn rewind(mut self) -> JxlDecoder<Initialized> {
         self.inner.rewind();
@@ -146,6 +236,12 @@ impl JxlDecoder<Initialized> {
         let inner_result = self.inner.process(input, None)?;
         Ok(self.map_inner_processing_result(inner_result))
     }
+
+    /// Signals that the input ended before the image header was complete, which is always an
+    /// error. See [`JxlDecoder::<WithImageInfo>::end_input`].
+    pub fn end_input(&mut self) -> Result<()> {
+        Err(Error::TruncatedHeader)
+    }
 }
 
 impl JxlDecoder<WithImageInfo> {
@@ -154,11 +250,36 @@ impl JxlDecoder<WithImageInfo> {
         self.inner.basic_info().unwrap()
     }
 
+    /// Returns whether pixels are written in the orientation of the image, as set by
+    /// [`JxlDecoderOptions::adjust_orientation`]. If so, `basic_info().size` and the frame sizes
+    /// are the ones after the orientation is applied, with width and height swapped for
+    /// transposing orientations; otherwise they are the sizes as stored.
+    pub fn orientation_applied(&self) -> bool {
+        self.inner.orientation_applied()
+    }
+
     /// Retrieves the file's color profile.
     pub fn embedded_color_profile(&self) -> &JxlColorProfile {
         self.inner.embedded_color_profile().unwrap()
     }
 
+    /// Returns whether the file's color profile is its ICC profile or its enumerated color
+    /// encoding.
+    pub fn profile_source(&self) -> JxlColorProfileSource {
+        self.inner.profile_source().unwrap()
+    }
+
+    /// Returns how the file's ICC profile disagrees with its enumerated color space, if it does.
+    pub fn color_profile_mismatch(&self) -> Option<&JxlColorProfileMismatch> {
+        self.inner.color_profile_mismatch()
+    }
+
+    /// Returns how the image size declared by the container disagrees with the codestream, if
+    /// it does. The size in the codestream is the one used.
+    pub fn size_mismatch(&self) -> Option<&JxlSizeMismatch> {
+        self.inner.size_mismatch()
+    }
+
     /// Retrieves the current output color profile.
     pub fn output_color_profile(&self) -> &JxlColorProfile {
         self.inner.output_color_profile().unwrap()
@@ -183,6 +304,30 @@ impl JxlDecoder<WithImageInfo> {
         self.inner.set_pixel_format(pixel_format);
     }
 
+    /// Changes [`JxlDecoderOptions::max_total_tokens`], which allows deriving the limit from the
+    /// image size.
+    pub fn set_max_total_tokens(&mut self, limit: Option<u64>) {
+        self.inner.set_max_total_tokens(limit);
+    }
+
+    /// Changes [`JxlDecoderOptions::resize_to`], which allows deriving the output size from the
+    /// image size.
+    pub fn set_resize_to(&mut self, size: Option<(usize, usize)>) {
+        self.inner.set_resize_to(size);
+    }
+
+    /// Changes [`JxlDecoderOptions::downsample`], which allows deriving the factor from the image
+    /// size. It applies to the frames whose header is not decoded yet.
+    pub fn set_downsample(&mut self, downsample: u32) {
+        self.inner.set_downsample(downsample);
+    }
+
+    /// Changes [`JxlDecoderOptions::crop`], which allows checking the region against the image
+    /// size first.
+    pub fn set_crop(&mut self, crop: Option<Rect>) {
+        self.inner.set_crop(crop);
+    }
+
     pub fn process(
         mut self,
         input: &mut impl JxlBitstreamInput,
@@ -198,10 +343,79 @@ impl JxlDecoder<WithImageInfo> {
         self.inner.flush_pixels(buffers)
     }
 
+    /// Returns the geometry of the buffers to pass to [`flush_pixels`](Self::flush_pixels),
+    /// assuming the next frame covers the whole image.
+    ///
+    /// Use [`JxlDecoder::<WithFrameInfo>::output_buffer_requirements`] to obtain the exact
+    /// requirements once the frame header has been parsed.
+    pub fn output_buffer_requirements(&self) -> Vec<BufferRequirement> {
+        self.inner.output_buffer_requirements().unwrap()
+    }
+
     pub fn has_more_frames(&self) -> bool {
         self.inner.has_more_frames()
     }
 
+    /// Signals that the input ended while [`has_more_frames`](Self::has_more_frames) still
+    /// returns true, i.e. before the frame marked as last. This is an error, unless
+    /// [`JxlDecoderOptions::permissive`] is set and a frame was decoded, which is then treated as
+    /// the last one.
+    ///
+    /// An empty input never signals the end of the input: `process` then returns
+    /// [`NeedsMoreInput`](ProcessingResult::NeedsMoreInput), unless the bytes passed before are
+    /// enough to complete its step. So a stream that is complete, wherever its chunks end, never
+    /// needs more input, and the end of the input only has to be signaled when
+    /// [`NeedsMoreInput`](ProcessingResult::NeedsMoreInput) is returned after all of it was
+    /// passed. Once `has_more_frames` returns false, `process` fails with
+    /// [`Error::NoMoreFrames`] and signaling the end of the input has no effect.
+    pub fn end_input(&mut self) -> Result<()> {
+        self.inner.end_input()
+    }
+
+    /// Returns how the last decoded frame differs from the one decoded before it, if
+    /// [`JxlDecoderOptions::compute_frame_diffs`] is enabled and the frame was decoded with
+    /// output buffers.
+    pub fn frame_diff(&self) -> Option<JxlFrameDiff> {
+        self.inner.frame_diff()
+    }
+
+    /// Returns how many groups of the last decoded frame, including the frames that were not
+    /// displayed but that it was composited from, were entirely outside of the image. Their
+    /// sections are still decoded, but they are not rendered.
+    pub fn offcanvas_groups(&self) -> Option<usize> {
+        self.inner.offcanvas_groups()
+    }
+
+    /// Returns the groups of the last decoded frame, including the frames that were not displayed
+    /// but that it was composited from, that failed to decode and were concealed, if
+    /// [`JxlDecoderOptions::skip_corrupt_sections`] is enabled. Empty if none were.
+    pub fn concealed_groups(&self) -> Option<&[super::ConcealedGroup]> {
+        self.inner.concealed_groups()
+    }
+
+    /// Returns statistics about the modular streams of the last decoded frame, including the
+    /// frames that were not displayed but that it was composited from.
+    #[cfg(feature = "timing-stats")]
+    pub fn modular_stats(&self) -> Option<&super::ModularStats> {
+        self.inner.modular_stats()
+    }
+
+    /// Returns the result of re-checking each channel of the modular streams of the last decoded
+    /// frame, if [`JxlDecoderOptions::verify_modular`] is enabled.
+    #[cfg(feature = "verify")]
+    pub fn modular_checks(&self) -> Option<&[super::ModularChannelCheck]> {
+        self.inner.modular_checks()
+    }
+
+    /// Returns the entropy codes read since the previous visible frame was decoded, up to the
+    /// end of the last decoded frame, if [`JxlDecoderOptions::dump_entropy_codes`] is enabled.
+    /// The codes of the image header and of the frames that the last frame was composited from
+    /// are included.
+    #[cfg(feature = "debug-tools")]
+    pub fn entropy_codes(&self) -> Option<&[super::EntropyCodeInfo]> {
+        self.inner.entropy_codes()
+    }
+
     /// Resets frame-level decoder state to prepare for decoding a new frame.
     ///
     /// This clears intermediate buffers (frame header, TOC, section data) while
@@ -223,10 +437,8 @@ impl JxlDecoder<WithImageInfo> {
     ///
     /// ```rust,ignore
     /// // 1. Scan frame info using the regular decoder API.
-    /// let options = JxlDecoderOptions {
-    ///     scan_frames_only: true,
-    ///     ..Default::default()
-    /// };
+    /// let mut options = JxlDecoderOptions::default();
+    /// options.scan_frames_only = true;
     /// let decoder = JxlDecoder::<states::Initialized>::new(options);
     /// // ...advance decoder and call `scanned_frames()`...
     ///
@@ -269,6 +481,20 @@ impl JxlDecoder<WithFrameInfo> {
         self.inner.frame_header().unwrap()
     }
 
+    /// Returns the geometry of the buffers that `process` and `flush_pixels` expect for this
+    /// frame, one entry per non-ignored channel group (color first, then extra channels), in the
+    /// same order as the buffers should be provided.
+    pub fn output_buffer_requirements(&self) -> Vec<BufferRequirement> {
+        self.inner.output_buffer_requirements().unwrap()
+    }
+
+    /// Signals that the input ended before the frame was complete, which is always an error.
+    /// The pixels decoded so far can still be drawn with [`flush_pixels`](Self::flush_pixels).
+    /// See [`JxlDecoder::<WithImageInfo>::end_input`].
+    pub fn end_input(&mut self) -> Result<()> {
+        Err(Error::TruncatedFrame)
+    }
+
     /// Number of passes we have full data for.
     pub fn num_completed_passes(&self) -> usize {
         self.inner.num_completed_passes().unwrap()
@@ -301,11 +527,19 @@ impl JxlDecoder<WithFrameInfo> {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use crate::api::{JxlDataFormat, JxlDecoderOptions};
+    use crate::api::{
+        ConcealedGroup, Endianness, GroupLayout, JxlColorType, JxlDataFormat, JxlDecoderOptions,
+        JxlProgressiveMode, OutputLayout, PassInfo, PassesInfo, Simplifications, SpeedProfile,
+    };
     use crate::error::Error;
+    use crate::frame::{Frame, GroupId, LfGroupId, PassId, Section};
     use crate::image::{Image, Rect};
     use jxl_macros::for_each_test_file;
     use std::path::Path;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
 
     #[test]
     fn decode_small_chunks() {
@@ -324,30 +558,54 @@ pub(crate) mod tests {
 
     #[allow(clippy::type_complexity)]
     pub fn decode(
-        mut input: &[u8],
+        input: &[u8],
         chunk_size: usize,
         use_simple_pipeline: bool,
         do_flush: bool,
         callback: Option<Box<dyn FnMut(&Frame, usize) -> Result<(), Error>>>,
     ) -> Result<(usize, Vec<Vec<Image<f32>>>), Error> {
-        let options = JxlDecoderOptions::default();
+        decode_with_input_ends(
+            input,
+            |end| end.saturating_add(chunk_size),
+            JxlDecoderOptions::default(),
+            use_simple_pipeline,
+            do_flush,
+            callback,
+        )
+    }
+
+    /// Like [`decode`], but with the given `options`, and `next_end` maps the end offset of the
+    /// input that is currently available to the decoder to the end offset of the input to make
+    /// available next.
+    #[allow(clippy::type_complexity)]
+    pub fn decode_with_input_ends(
+        mut input: &[u8],
+        mut next_end: impl FnMut(usize) -> usize,
+        options: JxlDecoderOptions,
+        use_simple_pipeline: bool,
+        do_flush: bool,
+        callback: Option<Box<dyn FnMut(&Frame, usize) -> Result<(), Error>>>,
+    ) -> Result<(usize, Vec<Vec<Image<f32>>>), Error> {
+        let crop = options.crop;
         let mut initialized_decoder = JxlDecoder::<states::Initialized>::new(options);
 
         if let Some(callback) = callback {
             initialized_decoder.set_frame_callback(callback);
         }
 
+        let file_len = input.len();
         let mut chunk_input = &input[0..0];
 
         macro_rules! advance_decoder {
             ($decoder: ident $(, $extra_arg: expr)? $(; $flush_arg: expr)?) => {
                 loop {
-                    chunk_input =
-                        &input[..(chunk_input.len().saturating_add(chunk_size)).min(input.len())];
+                    let consumed = file_len - input.len();
+                    let end = next_end(consumed + chunk_input.len()).max(consumed + 1);
+                    chunk_input = &input[..(end - consumed).min(input.len())];
                     let available_before = chunk_input.len();
                     let process_result = $decoder.process(&mut chunk_input $(, $extra_arg)?);
                     input = &input[(available_before - chunk_input.len())..];
-                    match process_result.unwrap() {
+                    match process_result? {
                         ProcessingResult::Complete { result } => break result,
                         ProcessingResult::NeedsMoreInput { fallback, .. } => {
                             $(
@@ -375,9 +633,10 @@ pub(crate) mod tests {
         assert!(basic_info.bit_depth.bits_per_sample() > 0);
 
         // Get image dimensions (after upsampling, which is the actual output size)
-        let (buffer_width, buffer_height) = basic_info.size;
-        assert!(buffer_width > 0);
-        assert!(buffer_height > 0);
+        let (image_width, image_height) = basic_info.size;
+        assert!(image_width > 0);
+        assert!(image_height > 0);
+        let (buffer_width, buffer_height) = crop.map_or(basic_info.size, |crop| crop.size);
 
         // Explicitly request F32 pixel format (test helper returns Image<f32>)
         let default_format = decoder_with_image_info.current_pixel_format();
@@ -452,6 +711,15 @@ pub(crate) mod tests {
             // Check if there are more frames
             if !decoder_with_image_info.has_more_frames() {
                 let decoded_frames = decoder_with_image_info.decoded_frames();
+                // Stay far below the token budget that jxl_cli allows by default, which counts
+                // pixels in whole 8x8 blocks.
+                let blocks = image_width.div_ceil(8) * image_height.div_ceil(8);
+                let max_tokens = (blocks * 64 * decoded_frames) as u64 * 64;
+                assert!(
+                    decoder_with_image_info.total_tokens() <= max_tokens,
+                    "{} tokens for {image_width}x{image_height} pixels",
+                    decoder_with_image_info.total_tokens()
+                );
 
                 // Ensure we decoded at least one frame
                 assert!(decoded_frames > 0, "No frames were decoded");
@@ -461,6 +729,279 @@ pub(crate) mod tests {
         }
     }
 
+    /// Splits the input in two at every byte of files whose headers make up most of their
+    /// size, so that the split falls in the middle of every header field.
+    #[test]
+    fn decode_split_at_every_header_byte() {
+        use crate::test_utils::scenarios;
+        let files = [
+            std::fs::read("resources/test/lossy_with_icc.jxl").unwrap(),
+            std::fs::read("resources/test/with_preview.jxl").unwrap(),
+            // Header extensions are skipped, rather than parsed, incrementally.
+            scenarios::with_extensions(vec![(3, vec![7; 300])], vec![(1, vec![9; 200])]).build(),
+            scenarios::named_animation(&[("first", 1), ("second", 2)]).build(),
+        ];
+        for data in files.iter() {
+            let (_, expected) = decode(data, usize::MAX, false, false, None).unwrap();
+            for split in 1..data.len() {
+                let next_end = |end| if end < split { split } else { usize::MAX };
+                let options = JxlDecoderOptions::default();
+                let (_, frames) =
+                    decode_with_input_ends(data, next_end, options, false, false, None).unwrap();
+                assert_eq!(frames.len(), expected.len(), "split at {split}");
+                for (frame, expected) in frames.iter().zip(expected.iter()) {
+                    for (image, expected) in frame.iter().zip(expected.iter()) {
+                        crate::util::test::check_equal_images(image, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the file offsets of the boundaries of the boxes, frames and sections of `data`,
+    /// excluding its start and end.
+    fn section_boundaries(data: &[u8]) -> Vec<usize> {
+        let map = crate::api::map_file(data).unwrap();
+        let mut boundaries = vec![];
+        for b in map.boxes.iter() {
+            boundaries.push(b.header_offset);
+            boundaries.push(b.payload_offset);
+            boundaries.extend(b.payload_len.map(|len| b.payload_offset + len));
+        }
+        for frame in map.frames.iter() {
+            let starts = std::iter::once(frame.header_bits_offset / 8);
+            let sections = frame.sections.iter().map(|s| s.offset);
+            for offset in starts.chain(sections).chain([frame.end()]) {
+                boundaries.extend(map.file_offset(offset));
+            }
+        }
+        let mut boundaries: Vec<usize> = boundaries
+            .into_iter()
+            .map(|b| b as usize)
+            .filter(|b| (1..data.len()).contains(b))
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Ends the input chunks exactly at box, frame and section boundaries, both at all of them
+    /// and at each of them alone.
+    #[test]
+    fn decode_chunks_ending_at_section_boundaries() {
+        for file in [
+            "resources/test/green_queen_vardct_e3.jxl",
+            "resources/test/has_permutation_with_container.jxl",
+            "resources/test/multiple_lf_420.jxl",
+            "resources/test/with_preview.jxl",
+            "resources/test/grayscale_patches_var_dct.jxl",
+        ] {
+            let data = std::fs::read(file).unwrap();
+            let (_, expected) = decode(&data, usize::MAX, false, false, None).unwrap();
+            let boundaries = section_boundaries(&data);
+            assert!(!boundaries.is_empty(), "{file}");
+            let check = |next_end: &mut dyn FnMut(usize) -> usize, what: &str| {
+                let options = JxlDecoderOptions::default();
+                let (_, frames) =
+                    decode_with_input_ends(&data, next_end, options, false, false, None).unwrap();
+                assert_eq!(frames.len(), expected.len(), "{file}, {what}");
+                for (frame, expected) in frames.iter().zip(expected.iter()) {
+                    for (image, expected) in frame.iter().zip(expected.iter()) {
+                        crate::util::test::check_equal_images(image, expected);
+                    }
+                }
+            };
+            check(
+                &mut |end| {
+                    let next = boundaries.iter().find(|b| **b > end);
+                    next.copied().unwrap_or(usize::MAX)
+                },
+                "all boundaries",
+            );
+            for &split in boundaries.iter() {
+                check(
+                    &mut |end| if end < split { split } else { usize::MAX },
+                    &format!("split at {split}"),
+                );
+            }
+        }
+    }
+
+    /// Makes the input available up to one box, frame or section boundary at a time, and
+    /// calls `process` again without new bytes whenever it needs more input. That never
+    /// completes a step, and the complete input never needs more bytes.
+    #[test]
+    fn process_without_new_bytes_needs_more_input() {
+        let data = std::fs::read("resources/test/has_permutation_with_container.jxl").unwrap();
+        let mut ends = section_boundaries(&data).into_iter().chain([data.len()]);
+        let mut available = ends.next().unwrap();
+        let mut consumed = 0;
+        macro_rules! advance {
+            ($decoder: expr $(, $buffers: expr)?) => {{
+                let mut decoder = $decoder;
+                loop {
+                    let mut input = &data[consumed..available];
+                    let result = decoder.process(&mut input $(, $buffers)?).unwrap();
+                    consumed = available - input.len();
+                    let ProcessingResult::NeedsMoreInput { fallback, .. } = result else {
+                        let ProcessingResult::Complete { result } = result else {
+                            unreachable!()
+                        };
+                        break result;
+                    };
+                    let mut input = &data[consumed..available];
+                    let result = fallback.process(&mut input $(, $buffers)?).unwrap();
+                    consumed = available - input.len();
+                    let ProcessingResult::NeedsMoreInput { fallback, .. } = result else {
+                        panic!("completed at {available} without new bytes");
+                    };
+                    decoder = fallback;
+                    available = ends.next().expect("complete input needs more bytes");
+                }
+            }};
+        }
+        let mut decoder = advance!(JxlDecoder::<states::Initialized>::new(
+            JxlDecoderOptions::default()
+        ));
+        while decoder.has_more_frames() {
+            let frame = advance!(decoder);
+            let mut images: Vec<_> = (frame.output_buffer_requirements().iter())
+                .map(|req| crate::image::OwnedRawImage::new(req.byte_size()).unwrap())
+                .collect();
+            let mut buffers: Vec<_> = images
+                .iter_mut()
+                .map(|img| {
+                    let rect = Rect {
+                        origin: (0, 0),
+                        size: img.byte_size(),
+                    };
+                    JxlOutputBuffer::from_image_rect_mut(img.get_rect_mut(rect))
+                })
+                .collect();
+            decoder = advance!(frame, &mut buffers);
+        }
+        assert_eq!(consumed, data.len());
+        // Once all frames are decoded, the end of the input is expected.
+        decoder.end_input().unwrap();
+        let result = decoder.process(&mut &data[consumed..]).err();
+        assert!(matches!(result, Some(Error::NoMoreFrames)), "{result:?}");
+    }
+
+    #[test]
+    fn end_input_of_truncated_stream() {
+        let data = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        let mut input = &data[..5];
+        let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let ProcessingResult::NeedsMoreInput { mut fallback, .. } =
+            decoder.process(&mut input).unwrap()
+        else {
+            panic!("image header is complete");
+        };
+        assert!(matches!(fallback.end_input(), Err(Error::TruncatedHeader)));
+
+        let end = data.len() - 1000;
+        let (mut decoder, mut input) =
+            advance_to_frame_info(&data[..end], JxlDecoderOptions::default(), None);
+        let mut images: Vec<_> = (decoder.output_buffer_requirements().iter())
+            .map(|req| crate::image::OwnedRawImage::new(req.byte_size()).unwrap())
+            .collect();
+        let mut buffers: Vec<_> = images
+            .iter_mut()
+            .map(|img| {
+                let rect = Rect {
+                    origin: (0, 0),
+                    size: img.byte_size(),
+                };
+                JxlOutputBuffer::from_image_rect_mut(img.get_rect_mut(rect))
+            })
+            .collect();
+        let ProcessingResult::NeedsMoreInput { mut fallback, .. } =
+            decoder.process(&mut input, &mut buffers).unwrap()
+        else {
+            panic!("frame is complete");
+        };
+        assert!(matches!(fallback.end_input(), Err(Error::TruncatedFrame)));
+        decoder = fallback;
+        decoder.flush_pixels(&mut buffers).unwrap();
+    }
+
+    /// Decodes all frames of `data`, always supplying exactly the bytes hinted by
+    /// `NeedsMoreInput`, and returns how many times more input was needed.
+    fn count_round_trips(data: &[u8], options: JxlDecoderOptions) -> Result<usize, Error> {
+        let mut available = 0;
+        let mut consumed = 0;
+        let mut round_trips = 0;
+        macro_rules! advance {
+            ($decoder: expr $(, $buffers: expr)?) => {{
+                let mut decoder = $decoder;
+                loop {
+                    let mut input = &data[consumed..available];
+                    let result = decoder.process(&mut input $(, $buffers)?)?;
+                    consumed = available - input.len();
+                    match result {
+                        ProcessingResult::Complete { result } => break result,
+                        ProcessingResult::NeedsMoreInput {
+                            size_hint,
+                            fallback,
+                        } => {
+                            assert!(available < data.len(), "complete input needs more bytes");
+                            assert!(size_hint > 0);
+                            round_trips += 1;
+                            available = (consumed + size_hint).clamp(available + 1, data.len());
+                            decoder = fallback;
+                        }
+                    }
+                }
+            }};
+        }
+        let mut decoder = advance!(JxlDecoder::<states::Initialized>::new(options));
+        while decoder.has_more_frames() {
+            let frame = advance!(decoder);
+            let mut images: Vec<_> = (frame.output_buffer_requirements().iter())
+                .map(|req| crate::image::OwnedRawImage::new(req.byte_size()))
+                .collect::<Result<_, _>>()?;
+            let mut buffers: Vec<_> = images
+                .iter_mut()
+                .map(|img| {
+                    let rect = Rect {
+                        origin: (0, 0),
+                        size: img.byte_size(),
+                    };
+                    JxlOutputBuffer::from_image_rect_mut(img.get_rect_mut(rect))
+                })
+                .collect();
+            decoder = advance!(frame, &mut buffers);
+        }
+        Ok(round_trips)
+    }
+
+    /// Supplying the hinted bytes needs a number of round trips that depends on the number of
+    /// boxes, frames and sections, not on the number of header fields.
+    fn round_trips_follow_sections(path: &Path) -> Result<(), Error> {
+        let data = std::fs::read(path)?;
+        let num_parts = section_boundaries(&data).len() + 1;
+        // Rendering passes does not change the input that is needed.
+        let options = || JxlDecoderOptions {
+            progressive_mode: JxlProgressiveMode::FullFrame,
+            ..Default::default()
+        };
+        let round_trips = count_round_trips(&data, options())?;
+        assert!(
+            round_trips <= 2 * num_parts,
+            "{round_trips} round trips for {num_parts} parts"
+        );
+        // Reading ahead never needs more round trips.
+        let options = JxlDecoderOptions {
+            input_readahead: usize::MAX,
+            ..options()
+        };
+        let readahead_round_trips = count_round_trips(&data, options)?;
+        assert!(readahead_round_trips <= round_trips);
+        Ok(())
+    }
+
+    for_each_test_file!(round_trips_follow_sections);
+
     fn decode_test_file(path: &Path) -> Result<(), Error> {
         decode(&std::fs::read(path)?, usize::MAX, false, false, None)?;
         Ok(())
@@ -530,11 +1071,7 @@ pub(crate) mod tests {
         let simple_frames = decode(&file, usize::MAX, true, false, None)?.1;
         let frames = decode(&file, usize::MAX, false, false, None)?.1;
         assert_eq!(frames.len(), simple_frames.len());
-        for (fc, (f, sf)) in frames
-            .into_iter()
-            .zip(simple_frames.into_iter())
-            .enumerate()
-        {
+        for (fc, (f, sf)) in frames.into_iter().zip(simple_frames).enumerate() {
             compare_frames(path, fc, &f, &sf)?;
         }
         Ok(())
@@ -551,11 +1088,7 @@ pub(crate) mod tests {
 
         // Compare one_shot_frames and frames
         assert_eq!(one_shot_frames.len(), frames.len());
-        for (fc, (f, sf)) in frames
-            .into_iter()
-            .zip(one_shot_frames.into_iter())
-            .enumerate()
-        {
+        for (fc, (f, sf)) in frames.into_iter().zip(one_shot_frames).enumerate() {
             compare_frames(path, fc, &f, &sf)?;
         }
 
@@ -564,6 +1097,98 @@ pub(crate) mod tests {
 
     for_each_test_file!(compare_incremental);
 
+    /// Returns the sum of squared differences between the samples of two decoded images, and
+    /// their number of samples.
+    fn squared_error(frames: &[Vec<Image<f32>>], reference: &[Vec<Image<f32>>]) -> (f64, usize) {
+        assert_eq!(frames.len(), reference.len());
+        let mut squared_error = 0.0;
+        let mut num_samples = 0;
+        for (frame, reference) in frames.iter().zip(reference) {
+            for (image, reference) in frame.iter().zip(reference) {
+                for y in 0..image.size().1 {
+                    for (a, b) in image.row(y).iter().zip(reference.row(y)) {
+                        squared_error += (*a as f64 - *b as f64).powi(2);
+                    }
+                    num_samples += image.size().0;
+                }
+            }
+        }
+        (squared_error, num_samples)
+    }
+
+    fn psnr((squared_error, num_samples): (f64, usize)) -> f64 {
+        -10.0 * (squared_error / num_samples as f64).log10()
+    }
+
+    fn decode_with_profile(file: &[u8], profile: SpeedProfile) -> Vec<Vec<Image<f32>>> {
+        let options = JxlDecoderOptions {
+            speed_profile: profile,
+            ..Default::default()
+        };
+        decode_with_input_ends(file, |_| usize::MAX, options, false, false, None)
+            .unwrap()
+            .1
+    }
+
+    /// The fast profile stays within 40 dB PSNR of the faithful one over the test images, and
+    /// within 20 dB on each of them (tiny images are the most affected by the approximation of
+    /// Gaborish). Noise is skipped in both, as synthesized noise only matches its original
+    /// statistically.
+    #[test]
+    fn fast_profile_stays_close() {
+        let reference_simplifications = Simplifications {
+            skip_noise: true,
+            ..Default::default()
+        };
+        let mut total = (0.0, 0);
+        let mut per_file = vec![];
+        for dir in ["resources/test", "resources/test/conformance_test_images"] {
+            let mut paths: Vec<_> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.extension().is_some_and(|e| e == "jxl"))
+                .collect();
+            paths.sort();
+            for path in paths {
+                let file = std::fs::read(&path).unwrap();
+                let reference =
+                    decode_with_profile(&file, SpeedProfile::Custom(reference_simplifications));
+                let fast = decode_with_profile(&file, SpeedProfile::Fast);
+                let error = squared_error(&fast, &reference);
+                total = (total.0 + error.0, total.1 + error.1);
+                let file_psnr = format!("{}: {:.2} dB", path.display(), psnr(error));
+                assert!(psnr(error) >= 20.0, "{file_psnr}");
+                per_file.push(file_psnr);
+            }
+        }
+        let psnr = psnr(total);
+        assert!(psnr >= 40.0, "{psnr:.2} dB\n{}", per_file.join("\n"));
+    }
+
+    /// Run with `cargo test --release -- --ignored fast_profile_speedup`.
+    #[test]
+    #[ignore = "benchmark, only meaningful in release builds on an idle machine"]
+    fn fast_profile_speedup() {
+        // Uses all three passes of the edge-preserving filter, and Gaborish.
+        let file = std::fs::read("resources/test/conformance_test_images/bike.jxl").unwrap();
+        let time = |profile| {
+            // The fastest of several runs is the least affected by other processes.
+            (0..5)
+                .map(|_| {
+                    let start = crate::time::clock().now();
+                    decode_with_profile(&file, profile);
+                    crate::time::clock().now() - start
+                })
+                .min()
+                .unwrap()
+        };
+        let faithful = time(SpeedProfile::Faithful);
+        let fast = time(SpeedProfile::Fast);
+        let speedup = faithful.as_secs_f64() / fast.as_secs_f64();
+        println!("faithful: {faithful:?}, fast: {fast:?}, speedup: {speedup:.2}x");
+        assert!(speedup >= 1.25, "{speedup:.2}x");
+    }
+
     #[test]
     fn test_preview_size_none_for_regular_files() {
         let file = std::fs::read("resources/test/basic.jxl").unwrap();
@@ -594,6 +1219,128 @@ pub(crate) mod tests {
         assert_eq!(decoder.basic_info().preview_size, Some((16, 16)));
     }
 
+    /// Returns `large_header.jxl` with the type of its first extra channel changed from
+    /// SelectionMask to 17, which the specification does not define.
+    fn unrecognized_extra_channel_file() -> Vec<u8> {
+        let mut file = std::fs::read("resources/test/large_header.jxl").unwrap();
+        // The codestream starts at byte 0x31, and the type is stored as 4 bits (type - 2) from
+        // bit 54 of it.
+        let start = 0x31 * 8 + 54;
+        for bit in start..start + 4 {
+            file[bit / 8] |= 1 << (bit % 8);
+        }
+        file
+    }
+
+    #[test]
+    fn extra_channel_names_and_bit_depths() {
+        use crate::api::JxlBitDepth;
+
+        let file = std::fs::read("resources/test/large_header.jxl").unwrap();
+        let mut decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let mut input = file.as_slice();
+        let decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        let channels = &decoder.basic_info().extra_channels;
+        assert_eq!(channels.len(), 256);
+        for (i, channel) in channels.iter().enumerate() {
+            assert!(
+                channel.name.starts_with(&format!("Channel_{i}_")),
+                "{}",
+                channel.name
+            );
+            assert_eq!(channel.bit_depth, JxlBitDepth::Int { bits_per_sample: 8 });
+            assert_eq!(channel.dim_shift, 0);
+        }
+    }
+
+    #[test]
+    fn unrecognized_extra_channel_strict() {
+        let file = unrecognized_extra_channel_file();
+        let mut decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let mut input = file.as_slice();
+        let err = loop {
+            match decoder.process(&mut input) {
+                Ok(ProcessingResult::Complete { .. }) => panic!("expected an error"),
+                Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => decoder = fallback,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::InvalidEnum(17, _)), "{err:?}");
+    }
+
+    #[test]
+    fn unrecognized_extra_channel_permissive() {
+        use crate::api::{JxlDataFormat, JxlExtraChannelType, JxlPixelFormat};
+
+        let file = unrecognized_extra_channel_file();
+        let options = JxlDecoderOptions {
+            permissive: true,
+            ..Default::default()
+        };
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut input = file.as_slice();
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        let info = decoder.basic_info().clone();
+        assert_eq!(
+            info.extra_channels[0].ec_type,
+            JxlExtraChannelType::Unknown(17)
+        );
+        assert_eq!(
+            info.extra_channels[1].ec_type,
+            JxlExtraChannelType::SelectionMask
+        );
+
+        // The channel is delivered as a plain plane.
+        let mut extra_channel_format = vec![None; info.extra_channels.len()];
+        extra_channel_format[0] = Some(JxlDataFormat::f32());
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type: decoder.current_pixel_format().color_type,
+            color_data_format: Some(JxlDataFormat::f32()),
+            extra_channel_format,
+        });
+        let samples = decoder
+            .current_pixel_format()
+            .color_type
+            .samples_per_pixel();
+        let mut color = Image::<f32>::new((info.size.0 * samples, info.size.1)).unwrap();
+        let mut extra = Image::<f32>::new_with_value(info.size, f32::NAN).unwrap();
+        let mut bufs: Vec<_> = [&mut color, &mut extra]
+            .into_iter()
+            .map(|image| {
+                let rect = Rect {
+                    origin: (0, 0),
+                    size: image.size(),
+                };
+                JxlOutputBuffer::from_image_rect_mut(image.get_rect_mut(rect).into_raw())
+            })
+            .collect();
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        loop {
+            match decoder.process(&mut input, &mut bufs).unwrap() {
+                ProcessingResult::Complete { .. } => break,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        }
+        for y in 0..info.size.1 {
+            assert!(extra.row(y).iter().all(|v| v.is_finite()));
+        }
+    }
+
     #[test]
     fn test_num_completed_passes() {
         use crate::image::{Image, Rect};
@@ -637,33 +1384,493 @@ pub(crate) mod tests {
     }
 
     #[test]
-    fn test_set_pixel_format() {
+    fn frame_diffs_match_decoded_frames() {
         use crate::api::{JxlColorType, JxlDataFormat, JxlPixelFormat};
 
-        let file = std::fs::read("resources/test/basic.jxl").unwrap();
-        let options = JxlDecoderOptions::default();
+        let file =
+            std::fs::read("resources/test/conformance_test_images/animation_newtons_cradle.jxl")
+                .unwrap();
+        let options = JxlDecoderOptions {
+            compute_frame_diffs: true,
+            ..Default::default()
+        };
         let mut decoder = JxlDecoder::<states::Initialized>::new(options);
         let mut input = file.as_slice();
-        let mut decoder = loop {
+        let mut decoder_with_info = loop {
             match decoder.process(&mut input).unwrap() {
                 ProcessingResult::Complete { result } => break result,
                 ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
             }
         };
-        // Check default pixel format
-        let default_format = decoder.current_pixel_format().clone();
-        assert_eq!(default_format.color_type, JxlColorType::Rgb);
-
-        // Set a new pixel format
-        let new_format = JxlPixelFormat {
-            color_type: JxlColorType::Grayscale,
+        let num_extra_channels = decoder_with_info.basic_info().extra_channels.len();
+        decoder_with_info.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
             color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
-            extra_channel_format: vec![],
-        };
-        decoder.set_pixel_format(new_format.clone());
-
-        // Verify it was set
-        assert_eq!(decoder.current_pixel_format(), &new_format);
+            extra_channel_format: vec![None; num_extra_channels],
+        });
+        let (width, height) = decoder_with_info.basic_info().size;
+        assert!(decoder_with_info.frame_diff().is_none());
+
+        let mut previous: Option<Vec<u8>> = None;
+        let mut num_partial_diffs = 0;
+        while decoder_with_info.has_more_frames() {
+            let mut decoder_with_frame = loop {
+                match decoder_with_info.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        decoder_with_info = fallback
+                    }
+                }
+            };
+            let mut pixels = vec![0u8; width * height * 3];
+            let mut bufs = [JxlOutputBuffer::new(&mut pixels, height, width * 3)];
+            decoder_with_info = loop {
+                match decoder_with_frame.process(&mut input, &mut bufs).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        decoder_with_frame = fallback
+                    }
+                }
+            };
+            let diff = decoder_with_info.frame_diff().unwrap();
+
+            let Some(previous) = previous.replace(pixels.clone()) else {
+                let full = Rect {
+                    origin: (0, 0),
+                    size: (width, height),
+                };
+                assert_eq!(diff.changed_rect, Some(full));
+                assert_eq!(diff.changed_pixels, width * height);
+                continue;
+            };
+            let changed: Vec<(usize, usize)> = (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .filter(|&(x, y)| {
+                    let i = (y * width + x) * 3;
+                    pixels[i..i + 3] != previous[i..i + 3]
+                })
+                .collect();
+            assert_eq!(diff.changed_pixels, changed.len());
+            let expected_rect = (!changed.is_empty()).then(|| {
+                let x0 = changed.iter().map(|p| p.0).min().unwrap();
+                let x1 = changed.iter().map(|p| p.0).max().unwrap();
+                let y0 = changed.iter().map(|p| p.1).min().unwrap();
+                let y1 = changed.iter().map(|p| p.1).max().unwrap();
+                Rect {
+                    origin: (x0, y0),
+                    size: (x1 - x0 + 1, y1 - y0 + 1),
+                }
+            });
+            assert_eq!(diff.changed_rect, expected_rect);
+            if changed.len() < width * height {
+                num_partial_diffs += 1;
+            }
+        }
+        assert!(num_partial_diffs > 0);
+    }
+
+    #[cfg(feature = "timing-stats")]
+    #[test]
+    fn decode_timings_match_wall_time() {
+        use crate::api::{JxlColorType, JxlDataFormat, JxlDecodeStage, JxlPixelFormat};
+
+        let file = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        // Read the same clock as the decoder.
+        let start = crate::time::clock().now();
+        let mut decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let mut input = file.as_slice();
+        let mut decoder_with_info = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        let num_extra_channels = decoder_with_info.basic_info().extra_channels.len();
+        decoder_with_info.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![None; num_extra_channels],
+        });
+        let (width, height) = decoder_with_info.basic_info().size;
+        let mut decoder_with_frame = loop {
+            match decoder_with_info.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder_with_info = fallback,
+            }
+        };
+        let mut pixels = vec![0u8; width * height * 3];
+        let mut bufs = [JxlOutputBuffer::new(&mut pixels, height, width * 3)];
+        let decoder_with_info = loop {
+            match decoder_with_frame.process(&mut input, &mut bufs).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder_with_frame = fallback,
+            }
+        };
+        let wall_time = crate::time::clock().now() - start;
+
+        let timings = decoder_with_info.decode_timings();
+        for stage in [
+            JxlDecodeStage::EntropyDecode,
+            JxlDecodeStage::DequantIdct,
+            JxlDecodeStage::ColorConvert,
+            JxlDecodeStage::OutputWrite,
+        ] {
+            assert!(!timings.stage(stage).is_zero(), "{stage:?}");
+        }
+        // Stages are timed on a sample of rows, so timings are only estimates, but they should
+        // account for most of the decoding time.
+        assert!(
+            timings.total() <= wall_time * 10,
+            "{timings:?} {wall_time:?}"
+        );
+        assert!(
+            timings.total() * 10 >= wall_time,
+            "{timings:?} {wall_time:?}"
+        );
+    }
+
+    #[cfg(feature = "timing-stats")]
+    #[test]
+    fn modular_stats_match_encoder_settings() {
+        use crate::api::{JxlColorType, JxlDataFormat, JxlPixelFormat, TransformDesc};
+        use crate::frame::modular::Predictor;
+        use crate::headers::modular::TransformId;
+
+        let decode_stats = |path: &str| {
+            let file = std::fs::read(path).unwrap();
+            let mut decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+            let mut input = file.as_slice();
+            let mut decoder_with_info = loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            };
+            let num_extra_channels = decoder_with_info.basic_info().extra_channels.len();
+            decoder_with_info.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Rgb,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![None; num_extra_channels],
+            });
+            let (width, height) = decoder_with_info.basic_info().size;
+            assert!(decoder_with_info.modular_stats().is_none());
+            let mut decoder_with_frame = loop {
+                match decoder_with_info.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        decoder_with_info = fallback
+                    }
+                }
+            };
+            let mut pixels = vec![0u8; width * height * 3];
+            let mut bufs = [JxlOutputBuffer::new(&mut pixels, height, width * 3)];
+            let decoder_with_info = loop {
+                match decoder_with_frame.process(&mut input, &mut bufs).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                        decoder_with_frame = fallback
+                    }
+                }
+            };
+            decoder_with_info.modular_stats().unwrap().clone()
+        };
+
+        // Encoded at effort 3, which only uses the weighted predictor, after a YCoCg RCT of the
+        // whole image.
+        let stats = decode_stats("resources/test/green_queen_modular_e3.jxl");
+        assert_eq!(stats.dominant_predictor(), Some(Predictor::Weighted));
+        assert_eq!(
+            stats.predictor_pixel_counts[Predictor::Weighted as usize],
+            stats.predictor_pixel_counts.iter().sum::<u64>()
+        );
+        let [TransformDesc { stream, transform }] = &stats.transforms[..] else {
+            panic!("{:?}", stats.transforms);
+        };
+        assert_eq!(*stream, 0);
+        assert_eq!(transform.id, TransformId::Rct);
+        assert_eq!(transform.rct_type, 6);
+        assert_eq!(stats.channels, 3);
+        assert!(stats.tree_nodes > 0);
+
+        // Encoded with the default squeeze transform, which leaves mostly zero residuals.
+        let stats = decode_stats("resources/test/squeeze_alpha.jxl");
+        assert_eq!(stats.dominant_predictor(), Some(Predictor::Zero));
+        let [TransformDesc { stream, transform }] = &stats.transforms[..] else {
+            panic!("{:?}", stats.transforms);
+        };
+        assert_eq!(*stream, 0);
+        assert_eq!(transform.id, TransformId::Squeeze);
+        assert!(transform.squeezes.is_empty());
+    }
+
+    /// Tests of [`JxlDecoderOptions::verify_modular`].
+    #[cfg(feature = "verify")]
+    mod verify {
+        use super::*;
+        use crate::api::{JxlDataFormat, JxlPixelFormat, ModularChannelCheck, ModularCheckOutcome};
+
+        /// Decodes every frame of `file` with `verify_modular`, and returns their checks.
+        fn decode_modular_checks(file: &[u8]) -> Result<Vec<ModularChannelCheck>, Error> {
+            let options = JxlDecoderOptions {
+                verify_modular: true,
+                ..JxlDecoderOptions::default()
+            };
+            let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut input = file;
+            let mut decoder_with_info = loop {
+                match decoder.process(&mut input)? {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            };
+            let color_type = decoder_with_info.current_pixel_format().color_type;
+            let num_extra_channels = decoder_with_info.basic_info().extra_channels.len();
+            decoder_with_info.set_pixel_format(JxlPixelFormat {
+                color_type,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![None; num_extra_channels],
+            });
+            let (width, height) = decoder_with_info.basic_info().size;
+            let bytes_per_row = width * color_type.samples_per_pixel();
+            let mut pixels = vec![0u8; bytes_per_row * height];
+            let mut checks = vec![];
+            loop {
+                let mut decoder_with_frame = loop {
+                    match decoder_with_info.process(&mut input)? {
+                        ProcessingResult::Complete { result } => break result,
+                        ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                            decoder_with_info = fallback
+                        }
+                    }
+                };
+                let mut bufs = [JxlOutputBuffer::new(&mut pixels, height, bytes_per_row)];
+                decoder_with_info = loop {
+                    match decoder_with_frame.process(&mut input, &mut bufs)? {
+                        ProcessingResult::Complete { result } => break result,
+                        ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                            decoder_with_frame = fallback
+                        }
+                    }
+                };
+                checks.extend_from_slice(decoder_with_info.modular_checks().unwrap());
+                if !decoder_with_info.has_more_frames() {
+                    return Ok(checks);
+                }
+            }
+        }
+
+        fn modular_checks_pass(path: &Path) -> Result<(), Error> {
+            for check in decode_modular_checks(&std::fs::read(path)?)? {
+                assert!(
+                    !matches!(check.outcome, ModularCheckOutcome::Mismatch { .. }),
+                    "{check:?}"
+                );
+            }
+            Ok(())
+        }
+
+        for_each_test_file!(modular_checks_pass);
+
+        #[test]
+        fn modular_checks_pass_with_local_transforms() {
+            // Palettes local to groups, and a YCoCg RCT of the whole image.
+            for name in [
+                "grayscale_patches_modular.jxl",
+                "green_queen_modular_e3.jxl",
+            ] {
+                let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+                let checks = decode_modular_checks(&file).unwrap();
+                assert!(!checks.is_empty(), "{name}");
+                assert!(
+                    checks
+                        .iter()
+                        .all(|check| check.outcome == ModularCheckOutcome::Passed),
+                    "{name}: {checks:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn modular_checks_catch_bit_flip() {
+            let file = std::fs::read("resources/test/green_queen_modular_e3.jxl").unwrap();
+            crate::frame::modular::inject_bit_flip(1, 3, 2);
+            let checks = decode_modular_checks(&file).unwrap();
+            let mismatches: Vec<_> = checks
+                .iter()
+                .filter(|check| check.outcome != ModularCheckOutcome::Passed)
+                .collect();
+            let [mismatch] = &mismatches[..] else {
+                panic!("{mismatches:?}");
+            };
+            assert!(
+                matches!(mismatch.outcome, ModularCheckOutcome::Mismatch { x, y } if (x, y) <= (3, 2)),
+                "{mismatch:?}"
+            );
+        }
+    }
+
+    /// Tests of [`JxlDecoderOptions::dump_entropy_codes`].
+    #[cfg(feature = "debug-tools")]
+    mod entropy_dump {
+        use super::*;
+        use crate::api::{
+            EntropyCodeInfo, EntropyCodePurpose, JxlDataFormat, JxlPixelFormat, SymbolDistribution,
+        };
+        use crate::frame::Section;
+
+        /// Decodes every frame of `file`, making at most `chunk_size` more bytes available at a
+        /// time, and returns their entropy codes.
+        fn decode_entropy_codes(
+            file: &[u8],
+            chunk_size: usize,
+        ) -> Result<Vec<EntropyCodeInfo>, Error> {
+            let options = JxlDecoderOptions {
+                dump_entropy_codes: true,
+                ..JxlDecoderOptions::default()
+            };
+            let mut data = file;
+            let mut chunk = &data[..0];
+            macro_rules! advance {
+                ($decoder: ident $(, $buffers: expr)?) => {
+                    loop {
+                        chunk = &data[..chunk.len().saturating_add(chunk_size).min(data.len())];
+                        let available = chunk.len();
+                        let result = $decoder.process(&mut chunk $(, $buffers)?)?;
+                        data = &data[available - chunk.len()..];
+                        match result {
+                            ProcessingResult::Complete { result } => break result,
+                            ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                                $decoder = fallback
+                            }
+                        }
+                    }
+                };
+            }
+            let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut decoder_with_info = advance!(decoder);
+            let color_type = decoder_with_info.current_pixel_format().color_type;
+            let num_extra_channels = decoder_with_info.basic_info().extra_channels.len();
+            decoder_with_info.set_pixel_format(JxlPixelFormat {
+                color_type,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![None; num_extra_channels],
+            });
+            let (width, height) = decoder_with_info.basic_info().size;
+            let bytes_per_row = width * color_type.samples_per_pixel();
+            let mut pixels = vec![0u8; bytes_per_row * height];
+            let mut codes = vec![];
+            loop {
+                let mut decoder_with_frame = advance!(decoder_with_info);
+                let mut bufs = [JxlOutputBuffer::new(&mut pixels, height, bytes_per_row)];
+                decoder_with_info = advance!(decoder_with_frame, &mut bufs);
+                codes.extend_from_slice(decoder_with_info.entropy_codes().unwrap());
+                if !decoder_with_info.has_more_frames() {
+                    return Ok(codes);
+                }
+            }
+        }
+
+        fn entropy_codes_are_consistent(path: &Path) -> Result<(), Error> {
+            let codes = decode_entropy_codes(&std::fs::read(path)?, usize::MAX)?;
+            assert!(!codes.is_empty());
+            for code in codes {
+                let num_clusters = *code.context_map.iter().max().unwrap() as usize + 1;
+                assert_eq!(code.clusters.len(), num_clusters, "{code:?}");
+                for cluster in &code.clusters {
+                    match &cluster.distribution {
+                        SymbolDistribution::Ans { counts } => assert_eq!(
+                            counts.iter().map(|&c| c as u32).sum::<u32>(),
+                            SymbolDistribution::ANS_TOTAL,
+                            "{code:?}"
+                        ),
+                        // Prefix codes are complete: the lengths satisfy Kraft's equality.
+                        SymbolDistribution::Prefix { code_lengths } => assert_eq!(
+                            code_lengths
+                                .iter()
+                                .filter(|&&l| l > 0)
+                                .map(|&l| 1u32 << (15 - l))
+                                .sum::<u32>(),
+                            1 << 15,
+                            "{code:?}"
+                        ),
+                        SymbolDistribution::PrefixSingle { .. } => {}
+                    }
+                }
+                let in_section = !matches!(
+                    code.purpose,
+                    EntropyCodePurpose::IccProfile | EntropyCodePurpose::TocPermutation
+                );
+                assert_eq!(code.section.is_some(), in_section, "{code:?}");
+            }
+            Ok(())
+        }
+
+        for_each_test_file!(entropy_codes_are_consistent);
+
+        #[test]
+        fn entropy_codes_identify_their_section() {
+            let file = std::fs::read("resources/test/has_permutation.jxl").unwrap();
+            let codes = decode_entropy_codes(&file, usize::MAX).unwrap();
+            let purposes: Vec<_> = codes.iter().map(|c| (c.purpose, c.section)).collect();
+            assert!(purposes.contains(&(EntropyCodePurpose::TocPermutation, None)));
+            assert!(
+                purposes.contains(&(EntropyCodePurpose::HfCoefficients, Some(Section::HfGlobal)))
+            );
+            assert!(codes.iter().all(|c| c.frame == Some(0)));
+
+            let file = std::fs::read("resources/test/with_icc.jxl").unwrap();
+            let codes = decode_entropy_codes(&file, usize::MAX).unwrap();
+            assert_eq!(codes[0].purpose, EntropyCodePurpose::IccProfile);
+            assert_eq!((codes[0].frame, codes[0].section), (None, None));
+
+            let file = std::fs::read("resources/test/with_preview.jxl").unwrap();
+            let codes = decode_entropy_codes(&file, usize::MAX).unwrap();
+            assert!(codes.iter().any(|c| c.frame.is_none()));
+            assert!(codes.iter().any(|c| c.frame == Some(0)));
+        }
+
+        #[test]
+        fn entropy_codes_of_incremental_input() {
+            // Headers that are read again once more input is available are only recorded once.
+            for name in ["with_icc.jxl", "has_permutation.jxl", "with_preview.jxl"] {
+                let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+                assert_eq!(
+                    decode_entropy_codes(&file, 7).unwrap(),
+                    decode_entropy_codes(&file, usize::MAX).unwrap(),
+                    "{name}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_set_pixel_format() {
+        use crate::api::{JxlColorType, JxlDataFormat, JxlPixelFormat};
+
+        let file = std::fs::read("resources/test/basic.jxl").unwrap();
+        let options = JxlDecoderOptions::default();
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut input = file.as_slice();
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        // Check default pixel format
+        let default_format = decoder.current_pixel_format().clone();
+        assert_eq!(default_format.color_type, JxlColorType::Rgb);
+
+        // Set a new pixel format
+        let new_format = JxlPixelFormat {
+            color_type: JxlColorType::Grayscale,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![],
+        };
+        decoder.set_pixel_format(new_format.clone());
+
+        // Verify it was set
+        assert_eq!(decoder.current_pixel_format(), &new_format);
     }
 
     #[test]
@@ -692,6 +1899,68 @@ pub(crate) mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_icc_profile_disagreeing_with_color_space() {
+        use crate::api::{JxlColorEncoding, JxlColorProfile, JxlColorProfileSource};
+        use crate::bit_reader::BitReader;
+        use crate::headers::{FileHeader, JxlHeader, color_encoding::ColorSpace};
+
+        let image_info = |file: &[u8], prefer_icc_profile: bool| {
+            let options = JxlDecoderOptions {
+                prefer_icc_profile,
+                ..Default::default()
+            };
+            let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut input = file;
+            loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            }
+        };
+
+        // Flipping a bit of the color space turns the grayscale image with a grayscale ICC profile
+        // into a color one, and the color image with a color ICC profile into a grayscale one.
+        for (name, byte, bit, color_space) in [
+            ("with_icc.jxl", 6, 6, ColorSpace::RGB),
+            ("lossy_with_icc.jxl", 5, 2, ColorSpace::Gray),
+        ] {
+            let mut file = std::fs::read(format!("resources/test/{name}")).unwrap();
+            let decoder = image_info(&file, false);
+            assert_eq!(decoder.profile_source(), JxlColorProfileSource::Icc);
+            assert!(decoder.color_profile_mismatch().is_none());
+            let icc = decoder.embedded_color_profile().clone();
+
+            file[byte] ^= 1 << bit;
+            let header = FileHeader::read(&mut BitReader::new(&file)).unwrap();
+            assert!(header.image_metadata.color_encoding.want_icc);
+            assert_eq!(
+                header.image_metadata.color_encoding.color_space,
+                color_space
+            );
+
+            let enumerated = JxlColorEncoding::srgb(color_space == ColorSpace::Gray);
+            let decoder = image_info(&file, false);
+            assert_eq!(decoder.profile_source(), JxlColorProfileSource::Enumerated);
+            assert!(
+                *decoder.embedded_color_profile() == JxlColorProfile::Simple(enumerated.clone())
+            );
+            let mismatch = decoder.color_profile_mismatch().unwrap();
+            assert_eq!(mismatch.used, JxlColorProfileSource::Enumerated);
+            assert_eq!(mismatch.enumerated, enumerated);
+            assert!(JxlColorProfile::Icc(mismatch.icc.clone()) == icc);
+
+            let decoder = image_info(&file, true);
+            assert_eq!(decoder.profile_source(), JxlColorProfileSource::Icc);
+            assert!(*decoder.embedded_color_profile() == icc);
+            assert_eq!(
+                decoder.color_profile_mismatch().unwrap().used,
+                JxlColorProfileSource::Icc
+            );
+        }
+    }
+
     #[test]
     fn test_default_output_tf_by_pixel_format() {
         use crate::api::{JxlColorEncoding, JxlTransferFunction};
@@ -1347,6 +2616,67 @@ pub(crate) mod tests {
         }
     }
 
+    /// Test that u16 and f32 output is written in the requested byte order, both for planar and
+    /// interleaved outputs.
+    #[test]
+    fn test_output_endianness() {
+        use crate::api::{Endianness, JxlColorType, JxlDataFormat, JxlPixelFormat};
+
+        let format = |color_type, color_data_format| JxlPixelFormat {
+            color_type,
+            color_data_format: Some(color_data_format),
+            extra_channel_format: vec![],
+        };
+        let u16_format = |endianness| JxlDataFormat::U16 {
+            endianness,
+            bit_depth: 16,
+        };
+        let f32_format = |endianness| JxlDataFormat::F32 { endianness };
+
+        for (path, color_type, num_samples) in [
+            ("grayscale_patches_modular.jxl", JxlColorType::Grayscale, 1),
+            ("conformance_test_images/bicycles.jxl", JxlColorType::Rgb, 3),
+            (
+                "conformance_test_images/bicycles.jxl",
+                JxlColorType::Rgba,
+                4,
+            ),
+        ] {
+            let file = std::fs::read(format!("resources/test/{path}")).unwrap();
+            for use_simple in [true, false] {
+                let decode_u16 = |endianness| {
+                    let format = format(color_type, u16_format(endianness));
+                    decode_with_format::<u16>(&file, &format, use_simple, false)
+                };
+                let (native, width, height) = decode_u16(Endianness::native());
+                let (little, _, _) = decode_u16(Endianness::LittleEndian);
+                let (big, _, _) = decode_u16(Endianness::BigEndian);
+                for y in 0..height {
+                    for x in 0..width * num_samples {
+                        let value = native.row(y)[x];
+                        assert_eq!(little.row(y)[x].to_ne_bytes(), value.to_le_bytes());
+                        assert_eq!(big.row(y)[x].to_ne_bytes(), value.to_be_bytes());
+                    }
+                }
+
+                let format = format(color_type, f32_format(Endianness::BigEndian));
+                let (big, _, _) = decode_with_format::<f32>(&file, &format, use_simple, false);
+                let format = JxlPixelFormat {
+                    color_type,
+                    color_data_format: Some(JxlDataFormat::f32()),
+                    extra_channel_format: vec![],
+                };
+                let (native, _, _) = decode_with_format::<f32>(&file, &format, use_simple, false);
+                for y in 0..height {
+                    for x in 0..width * num_samples {
+                        let value = native.row(y)[x];
+                        assert_eq!(big.row(y)[x].to_ne_bytes(), value.to_be_bytes());
+                    }
+                }
+            }
+        }
+    }
+
     /// Helper function to decode an image with a specific format.
     fn decode_with_format<T: crate::image::ImageDataType>(
         file: &[u8],
@@ -1421,21 +2751,1000 @@ pub(crate) mod tests {
         (buffer, width, height)
     }
 
-    /// Regression test for ClusterFuzz issue 5342436251336704
-    /// Tests that malformed JXL files with overflow-inducing data don't panic
+    fn advance_to_frame_info(
+        mut input: &[u8],
+        options: JxlDecoderOptions,
+        pixel_format: Option<JxlPixelFormat>,
+    ) -> (JxlDecoder<WithFrameInfo>, &[u8]) {
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        if let Some(pixel_format) = pixel_format {
+            decoder.set_pixel_format(pixel_format);
+        }
+        let decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        (decoder, input)
+    }
+
+    /// Allocates buffers following `output_buffer_requirements` and decodes the current frame.
+    fn decode_frame_with_requirements(
+        mut decoder: JxlDecoder<WithFrameInfo>,
+        mut input: &[u8],
+    ) -> Vec<crate::image::OwnedRawImage> {
+        let mut images: Vec<_> = decoder
+            .output_buffer_requirements()
+            .iter()
+            .map(|req| crate::image::OwnedRawImage::new(req.byte_size()).unwrap())
+            .collect();
+        let mut buffers: Vec<_> = images
+            .iter_mut()
+            .map(|img| {
+                let rect = Rect {
+                    origin: (0, 0),
+                    size: img.byte_size(),
+                };
+                JxlOutputBuffer::from_image_rect_mut(img.get_rect_mut(rect))
+            })
+            .collect();
+        loop {
+            match decoder.process(&mut input, &mut buffers).unwrap() {
+                ProcessingResult::Complete { .. } => break,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        }
+        drop(buffers);
+        images
+    }
+
     #[test]
-    fn test_fuzzer_smallbuffer_overflow() {
-        use std::panic;
+    fn frame_header_passes() {
+        let file = std::fs::read("resources/test/progressive_ac.jxl").unwrap();
+        let (decoder, _) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        let pass = |downsample| PassInfo {
+            downsample,
+            last_pass: true,
+            coefficient_shift: 0,
+        };
+        let passes = decoder.frame_header().passes;
+        assert_eq!(passes.passes, [pass(4), pass(2), pass(1)]);
+        assert_eq!(passes.to_string(), "1/4, 1/2, 1/1");
 
-        let data = include_bytes!("../../tests/testdata/fuzzer_smallbuffer_overflow.jxl");
+        let file = std::fs::read("resources/test/basic.jxl").unwrap();
+        let (decoder, _) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        assert_eq!(decoder.frame_header().passes, PassesInfo::default());
+    }
 
-        // The test passes if it doesn't panic with "attempt to add with overflow"
-        // It's OK if it returns an error or panics with "Unexpected end of input"
-        let result = panic::catch_unwind(|| {
-            let _ = decode(data, 1024, false, false, None);
-        });
+    #[test]
+    fn frame_header_simplifications() {
+        let file = std::fs::read("resources/test/stp2_520x260_d25_e6.jxl").unwrap();
+        let (decoder, _) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        assert!(decoder.frame_header().simplifications.is_empty());
 
-        // If it panicked, make sure it wasn't an overflow panic
+        let options = JxlDecoderOptions {
+            speed_profile: SpeedProfile::Fast,
+            ..Default::default()
+        };
+        let (decoder, _) = advance_to_frame_info(&file, options, None);
+        let simplifications = decoder.frame_header().simplifications;
+        assert!(simplifications.skip_extra_epf_passes && simplifications.separable_gaborish);
+        // The frame has no noise and no extra channels.
+        assert!(!simplifications.skip_noise && !simplifications.nearest_extra_channel_upsampling);
+        assert_eq!(
+            simplifications.to_string(),
+            "skipped extra EPF passes, separable Gaborish"
+        );
+
+        let file = std::fs::read("resources/test/8x8_noise.jxl").unwrap();
+        let simplifications = Simplifications {
+            skip_noise: true,
+            ..Default::default()
+        };
+        let options = JxlDecoderOptions {
+            speed_profile: SpeedProfile::Custom(simplifications),
+            ..Default::default()
+        };
+        let (decoder, _) = advance_to_frame_info(&file, options, None);
+        assert_eq!(decoder.frame_header().simplifications, simplifications);
+    }
+
+    #[test]
+    fn all_default_frame_header() {
+        use crate::container::ContainerParser;
+        use crate::test_utils::{DefaultFrameHeader, with_default_frame_header};
+
+        for name in ["3x3_srgb_lossy.jxl", "3x3a_srgb_lossy.jxl"] {
+            let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+            let codestream = ContainerParser::collect_codestream(&file).unwrap();
+            let decoded: Vec<_> = DefaultFrameHeader::ALL
+                .into_iter()
+                .map(|header| {
+                    let file = with_default_frame_header(&codestream, header);
+                    let (decoder, _) =
+                        advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+                    assert_eq!(
+                        decoder.frame_header().all_default,
+                        header == DefaultFrameHeader::AllDefault
+                    );
+                    decode(&file, usize::MAX, false, false, None).unwrap().1
+                })
+                .collect();
+            // Frames decode identically, however their header is coded.
+            for frames in &decoded[1..] {
+                assert_eq!(frames.len(), decoded[0].len(), "{name}");
+                for (frame, expected) in frames.iter().zip(&decoded[0]) {
+                    for (channel, expected) in frame.iter().zip(expected) {
+                        crate::util::test::check_equal_images(channel, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_buffer_requirements_grayscale() {
+        let file = std::fs::read("resources/test/conformance_test_images/grayscale.jxl").unwrap();
+        let (decoder, input) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        let requirements = decoder.output_buffer_requirements();
+        assert_eq!(requirements.len(), 1);
+        assert_eq!(requirements[0].samples_per_pixel, 1);
+        assert_eq!(
+            (requirements[0].width, requirements[0].height),
+            decoder.frame_header().size
+        );
+        let images = decode_frame_with_requirements(decoder, input);
+        assert_eq!(images[0].byte_size(), requirements[0].byte_size());
+    }
+
+    #[test]
+    fn test_output_buffer_requirements_rgb_alpha() {
+        use crate::api::{JxlColorType, JxlDataFormat};
+        let file =
+            std::fs::read("resources/test/conformance_test_images/alpha_nonpremultiplied.jxl")
+                .unwrap();
+
+        // Alpha interleaved with color.
+        let interleaved = JxlPixelFormat {
+            color_type: JxlColorType::Rgba,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![None],
+        };
+        let (decoder, input) =
+            advance_to_frame_info(&file, JxlDecoderOptions::default(), Some(interleaved));
+        let (width, height) = decoder.frame_header().size;
+        let requirements = decoder.output_buffer_requirements();
+        assert_eq!(
+            requirements,
+            vec![BufferRequirement {
+                width,
+                height,
+                samples_per_pixel: 4,
+                data_type: JxlDataFormat::U8 { bit_depth: 8 },
+            }]
+        );
+        decode_frame_with_requirements(decoder, input);
+
+        // Alpha in a separate buffer.
+        let separate = JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::f32()),
+            extra_channel_format: vec![Some(JxlDataFormat::U8 { bit_depth: 8 })],
+        };
+        let (decoder, input) =
+            advance_to_frame_info(&file, JxlDecoderOptions::default(), Some(separate));
+        let requirements = decoder.output_buffer_requirements();
+        assert_eq!(requirements.len(), 2);
+        assert_eq!(requirements[0].samples_per_pixel, 3);
+        assert_eq!(requirements[0].bytes_per_row(), width * 3 * 4);
+        assert_eq!(requirements[1].samples_per_pixel, 1);
+        assert_eq!(requirements[1].bytes_per_row(), width);
+        decode_frame_with_requirements(decoder, input);
+    }
+
+    #[test]
+    fn test_output_buffer_requirements_downsampled() {
+        let file = std::fs::read("resources/test/progressive_ac.jxl").unwrap();
+        let (decoder, _) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        let (width, height) = decoder.frame_header().size;
+        for downsample in [2, 8] {
+            // Downsampled frames are still rendered at their full size...
+            let options = JxlDecoderOptions {
+                downsample,
+                ..Default::default()
+            };
+            let (decoder, input) = advance_to_frame_info(&file, options, None);
+            let requirements = decoder.output_buffer_requirements();
+            assert_eq!(
+                (requirements[0].width, requirements[0].height),
+                (width, height)
+            );
+            let images = decode_frame_with_requirements(decoder, input);
+            assert_eq!(images[0].byte_size(), requirements[0].byte_size());
+
+            // ...unless they are also resized.
+            let size = (
+                width.div_ceil(downsample as usize),
+                height.div_ceil(downsample as usize),
+            );
+            let options = JxlDecoderOptions {
+                downsample,
+                resize_to: Some(size),
+                ..Default::default()
+            };
+            let (decoder, input) = advance_to_frame_info(&file, options, None);
+            assert_eq!(decoder.frame_header().downsample, downsample);
+            let requirements = decoder.output_buffer_requirements();
+            assert_eq!((requirements[0].width, requirements[0].height), size);
+            let images = decode_frame_with_requirements(decoder, input);
+            assert_eq!(images[0].byte_size(), requirements[0].byte_size());
+        }
+    }
+
+    #[test]
+    fn resized_output_matches_resampled_full_output() {
+        use crate::api::{JxlColorType, JxlDataFormat};
+        use crate::render::resample::{ColorSamples, ResampleFilter, resample_colors};
+        use crate::render::stages::TransferFunction;
+
+        let file =
+            std::fs::read("resources/test/conformance_test_images/alpha_nonpremultiplied.jxl")
+                .unwrap();
+        let format = |data_type| JxlPixelFormat {
+            color_type: JxlColorType::Rgba,
+            color_data_format: Some(data_type),
+            extra_channel_format: vec![None],
+        };
+        let (decoder, input) = advance_to_frame_info(
+            &file,
+            JxlDecoderOptions::default(),
+            Some(format(JxlDataFormat::f32())),
+        );
+        let (width, height) = decoder.frame_header().size;
+        let full = decode_frame_with_requirements(decoder, input);
+
+        let size = (width / 3, height / 2 + 1);
+        let options = JxlDecoderOptions {
+            resize_to: Some(size),
+            resize_filter: ResampleFilter::Lanczos3,
+            ..Default::default()
+        };
+        let (decoder, input) = advance_to_frame_info(
+            &file,
+            options,
+            Some(format(JxlDataFormat::U8 { bit_depth: 8 })),
+        );
+        assert_eq!(decoder.frame_header().size, size);
+        let resized = decode_frame_with_requirements(decoder, input);
+        assert_eq!(resized[0].byte_size(), (size.0 * 4, size.1));
+
+        let mut image = Image::<f32>::new((width * 4, height)).unwrap();
+        for y in 0..height {
+            for (value, bytes) in image
+                .row_mut(y)
+                .iter_mut()
+                .zip(full[0].row(y).chunks_exact(4))
+            {
+                *value = f32::from_ne_bytes(bytes.try_into().unwrap());
+            }
+        }
+        let samples = ColorSamples {
+            num_channels: 4,
+            has_alpha: true,
+            premultiplied: false,
+            transfer_function: Some(TransferFunction::Srgb),
+        };
+        let expected = resample_colors(&image, &samples, size, ResampleFilter::Lanczos3).unwrap();
+        for y in 0..size.1 {
+            for (value, expected) in resized[0].row(y).iter().zip(expected.row(y)) {
+                let expected = (expected.clamp(0.0, 1.0) * 255.0).round() as u8;
+                assert!(
+                    value.abs_diff(expected) <= 1,
+                    "{value} vs {expected} in row {y}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resized_alpha_in_separate_buffer_matches_interleaved_alpha() {
+        use crate::api::{JxlColorType, JxlDataFormat};
+
+        // The transparent background hides vivid colors, which must not bleed into the dice.
+        let file = std::fs::read("resources/test/dice.jxl").unwrap();
+        let decode = |color_type, extra_channel_format| {
+            let options = JxlDecoderOptions {
+                resize_to: Some((200, 150)),
+                ..Default::default()
+            };
+            let format = JxlPixelFormat {
+                color_type,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![extra_channel_format],
+            };
+            let (decoder, input) = advance_to_frame_info(&file, options, Some(format));
+            decode_frame_with_requirements(decoder, input)
+        };
+        let interleaved = decode(JxlColorType::Rgba, None);
+        let separate = decode(JxlColorType::Rgb, Some(JxlDataFormat::U8 { bit_depth: 8 }));
+        for y in 0..150 {
+            let pixels = interleaved[0].row(y).chunks_exact(4);
+            let colors = separate[0].row(y).chunks_exact(3);
+            for (x, ((pixel, color), alpha)) in
+                pixels.zip(colors).zip(separate[1].row(y)).enumerate()
+            {
+                assert_eq!(pixel[3], *alpha, "alpha at ({x}, {y})");
+                if *alpha > 0 {
+                    assert_eq!(&pixel[..3], color, "color at ({x}, {y})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cropped_output_matches_full_output() {
+        let files = [
+            "orientation1_identity.jxl",
+            "orientation2_flip_horizontal.jxl",
+            "orientation3_rotate_180.jxl",
+            "orientation4_flip_vertical.jxl",
+            "orientation5_transpose.jxl",
+            "orientation6_rotate_90_cw.jxl",
+            "orientation7_anti_transpose.jxl",
+            "orientation8_rotate_90_ccw.jxl",
+            "extra_channels.jxl",
+            "with_preview.jxl",
+            "conformance_test_images/animation_spline.jxl",
+            "conformance_test_images/upsampling.jxl",
+        ];
+        for name in files {
+            let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+            let mut input = file.as_slice();
+            let ProcessingResult::Complete { result: decoder } =
+                JxlDecoder::<states::Initialized>::new(Default::default())
+                    .process(&mut input)
+                    .unwrap()
+            else {
+                panic!("incomplete header of {name}");
+            };
+            let (width, height) = decoder.basic_info().size;
+            let crop = Rect {
+                origin: (width / 3, height / 4),
+                size: (width / 2, height / 2 + 1),
+            };
+            for use_simple in [false, true] {
+                let decode = |crop| {
+                    let options = JxlDecoderOptions {
+                        crop,
+                        ..Default::default()
+                    };
+                    decode_with_input_ends(&file, |_| usize::MAX, options, use_simple, false, None)
+                        .unwrap()
+                        .1
+                };
+                let full = decode(None);
+                let cropped = decode(Some(crop));
+                assert_eq!(full.len(), cropped.len(), "{name}");
+                for (full, cropped) in full.iter().zip(&cropped) {
+                    for (c, (full, cropped)) in full.iter().zip(cropped).enumerate() {
+                        let samples = full.size().0 / width;
+                        assert_eq!(cropped.size(), (crop.size.0 * samples, crop.size.1));
+                        for y in 0..crop.size.1 {
+                            assert_eq!(
+                                cropped.row(y),
+                                &full.row(crop.origin.1 + y)[crop.origin.0 * samples..]
+                                    [..crop.size.0 * samples],
+                                "{name}, buffer {c}, row {y}, simple pipeline: {use_simple}"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crop_past_the_image_is_rejected() {
+        let file = std::fs::read("resources/test/orientation6_rotate_90_cw.jxl").unwrap();
+        // The image is displayed with a size of 256x100 pixels.
+        for crop in [
+            Rect {
+                origin: (200, 0),
+                size: (57, 10),
+            },
+            Rect {
+                origin: (0, 50),
+                size: (10, 51),
+            },
+            Rect {
+                origin: (0, 0),
+                size: (0, 10),
+            },
+        ] {
+            let options = JxlDecoderOptions {
+                crop: Some(crop),
+                ..Default::default()
+            };
+            let result = decode_with_input_ends(&file, |_| usize::MAX, options, false, false, None);
+            assert!(
+                matches!(result, Err(Error::InvalidCrop(..))),
+                "{crop:?}: {:?}",
+                result.map(|_| ())
+            );
+        }
+    }
+
+    #[test]
+    fn downsampled_frames_report_the_factor_they_are_decoded_at() {
+        // progressive_ac.jxl completes the image downsampled by 4, 2 and 1, basic.jxl only has
+        // one VarDCT pass, and squeeze_edge.jxl is a modular image that is complete downsampled
+        // by 2.
+        for (name, downsample, expected) in [
+            ("progressive_ac.jxl", 2, 2),
+            ("progressive_ac.jxl", 4, 4),
+            ("progressive_ac.jxl", 8, 8),
+            ("basic.jxl", 1, 1),
+            ("basic.jxl", 4, 1),
+            ("basic.jxl", 8, 8),
+            ("squeeze_edge.jxl", 8, 2),
+        ] {
+            let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+            let options = JxlDecoderOptions {
+                downsample,
+                ..Default::default()
+            };
+            let (decoder, _) = advance_to_frame_info(&file, options, None);
+            assert_eq!(
+                decoder.frame_header().downsample,
+                expected,
+                "{name} downsampled by {downsample}"
+            );
+        }
+    }
+
+    #[test]
+    fn downsampled_decode_renders_the_decoded_passes() {
+        // Downsampled frames are rendered like partial renders, which the simple pipeline does
+        // not do.
+        let file = std::fs::read("resources/test/progressive_ac.jxl").unwrap();
+        let decode = |downsample, next_end: fn(usize) -> usize| {
+            let options = JxlDecoderOptions {
+                downsample,
+                ..Default::default()
+            };
+            decode_with_input_ends(&file, next_end, options, false, false, None)
+                .unwrap()
+                .1
+        };
+        let full = decode(1, |_| usize::MAX);
+        for downsample in [2, 4, 8] {
+            let downsampled = decode(downsample, |_| usize::MAX);
+            // Passes that are not decoded must not change the output, however the input is split.
+            let chunked = decode(downsample, |end| end + 997);
+            for (image, expected) in chunked[0].iter().zip(&downsampled[0]) {
+                crate::util::test::check_equal_images(image, expected);
+            }
+            let (mut total, mut count) = (0.0, 0);
+            for (full, downsampled) in full[0].iter().zip(&downsampled[0]) {
+                assert_eq!(full.size(), downsampled.size());
+                for y in 0..full.size().1 {
+                    for (a, b) in full.row(y).iter().zip(downsampled.row(y)) {
+                        total += (a - b).abs() as f64;
+                        count += 1;
+                    }
+                }
+            }
+            let error = total / count as f64;
+            assert!(
+                error > 0.0 && error < 0.05,
+                "downsampled by {downsample}: {error}"
+            );
+        }
+    }
+
+    /// Returns a copy of `data` in which the bytes of the sections of its last frame for which
+    /// `corrupt` returns true are inverted.
+    fn corrupt_sections(data: &[u8], corrupt: impl Fn(Section) -> bool) -> Vec<u8> {
+        let map = crate::api::map_file(data).unwrap();
+        let mut corrupted = data.to_vec();
+        for span in map.frames.last().unwrap().sections.iter() {
+            if span.section.is_some_and(&corrupt) {
+                for offset in span.offset..span.offset + span.len {
+                    let offset = map.file_offset(offset).unwrap() as usize;
+                    corrupted[offset] = !corrupted[offset];
+                }
+            }
+        }
+        corrupted
+    }
+
+    /// Decodes `data` concealing corrupt sections, and returns the groups concealed in its last
+    /// frame with its group layout, and its color buffer.
+    fn decode_concealing(data: &[u8]) -> (Vec<ConcealedGroup>, GroupLayout, Image<f32>) {
+        let report = Arc::new(std::sync::Mutex::new(None));
+        let callback = {
+            let report = report.clone();
+            move |frame: &Frame, _| {
+                let groups = frame.concealed_groups().to_vec();
+                *report.lock().unwrap() = Some((groups, frame.header().group_layout()));
+                Ok(())
+            }
+        };
+        let options = JxlDecoderOptions {
+            skip_corrupt_sections: true,
+            ..Default::default()
+        };
+        let (_, mut frames) = decode_with_input_ends(
+            data,
+            |_| usize::MAX,
+            options,
+            false,
+            false,
+            Some(Box::new(callback)),
+        )
+        .unwrap();
+        let (groups, layout) = report.lock().unwrap().take().unwrap();
+        (groups, layout, frames.pop().unwrap().swap_remove(0))
+    }
+
+    /// Asserts that the samples of `image`, with `channels` interleaved samples per pixel, are
+    /// those of `expected` farther than `margin` pixels from all of `rects`.
+    fn check_equal_outside(
+        image: &Image<f32>,
+        expected: &Image<f32>,
+        channels: usize,
+        rects: &[Rect],
+        margin: usize,
+    ) {
+        let near = |x: usize, y: usize| {
+            rects.iter().any(|r| {
+                x + margin >= r.origin.0
+                    && x < r.end().0 + margin
+                    && y + margin >= r.origin.1
+                    && y < r.end().1 + margin
+            })
+        };
+        let mut compared = 0;
+        for y in 0..image.size().1 {
+            for (i, (a, b)) in image.row(y).iter().zip(expected.row(y)).enumerate() {
+                if !near(i / channels, y) {
+                    assert_eq!(a.to_bits(), b.to_bits(), "at {},{y}", i / channels);
+                    compared += 1;
+                }
+            }
+        }
+        assert!(compared > 0);
+    }
+
+    /// Returns the mean absolute difference of the samples of `image` and `expected` in `rect`.
+    fn mean_error_in(
+        image: &Image<f32>,
+        expected: &Image<f32>,
+        channels: usize,
+        rect: Rect,
+    ) -> f64 {
+        let (mut total, mut count) = (0.0, 0);
+        let (x0, x1) = (rect.origin.0 * channels, rect.end().0 * channels);
+        for y in rect.origin.1..rect.end().1 {
+            for (a, b) in image.row(y)[x0..x1].iter().zip(&expected.row(y)[x0..x1]) {
+                total += (a - b).abs() as f64;
+                count += 1;
+            }
+        }
+        total / count as f64
+    }
+
+    #[test]
+    fn corrupt_hf_groups_are_concealed() {
+        for name in ["green_queen_vardct_e3.jxl", "green_queen_modular_e3.jxl"] {
+            let file = std::fs::read(format!("resources/test/{name}")).unwrap();
+            let corrupt = |section| {
+                [0, 5].into_iter().any(|g| {
+                    section
+                        == Section::Hf {
+                            group: GroupId::new(g),
+                            pass: PassId::new(0),
+                        }
+                })
+            };
+            let corrupted = corrupt_sections(&file, corrupt);
+            assert!(
+                decode(&corrupted, usize::MAX, false, false, None).is_err(),
+                "{name}"
+            );
+
+            let (concealed, layout, image) = decode_concealing(&corrupted);
+            let sections: Vec<_> = concealed.iter().map(|c| c.section).collect();
+            let coords: Vec<_> = concealed.iter().map(|c| c.coords).collect();
+            assert!(sections.iter().all(|&s| corrupt(s)), "{name}: {sections:?}");
+            assert_eq!(coords, [(0, 0), (1, 2)], "{name}");
+
+            let (_, clean) = decode(&file, usize::MAX, false, false, None).unwrap();
+            let rects: Vec<_> = coords
+                .iter()
+                .map(|&(x, y)| Rect {
+                    origin: (x * layout.group_dim, y * layout.group_dim),
+                    size: (layout.group_dim, layout.group_dim),
+                })
+                .collect();
+            // Filters reach a few pixels into the neighbouring groups.
+            check_equal_outside(&image, &clean[0][0], 3, &rects, 16);
+            if name.contains("vardct") {
+                // Without AC coefficients, the group is still a blurred version of itself.
+                let error = mean_error_in(&image, &clean[0][0], 3, rects[0]);
+                assert!(error < 0.15, "{name}: {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_lf_groups_are_interpolated() {
+        let file = std::fs::read("resources/test/multiple_lf_420.jxl").unwrap();
+        let lf_group = LfGroupId::new(1);
+        let corrupted = corrupt_sections(&file, |s| s == Section::Lf { group: lf_group });
+        assert!(decode(&corrupted, usize::MAX, false, false, None).is_err());
+
+        let (concealed, layout, image) = decode_concealing(&corrupted);
+        assert_eq!(
+            concealed[0],
+            ConcealedGroup {
+                section: Section::Lf { group: lf_group },
+                coords: (1, 0),
+            }
+        );
+        // The groups of the LF group are all rendered from the interpolated DC.
+        let groups: Vec<_> = concealed[1..].iter().map(|c| c.coords).collect();
+        let expected: Vec<_> = (0..8)
+            .flat_map(|y| (8..layout.num_groups.0).map(move |x| (x, y)))
+            .collect();
+        assert_eq!(groups, expected);
+
+        let (_, clean) = decode(&file, usize::MAX, false, false, None).unwrap();
+        let rect = Rect {
+            origin: (layout.lf_group_dim, 0),
+            size: (layout.lf_group_dim, layout.lf_group_dim),
+        };
+        check_equal_outside(&image, &clean[0][0], 3, &[rect], 32);
+        // Each LF group of this image has its own color, so the concealed one takes colors
+        // between those of the LF groups to its left and below.
+        let dim = layout.lf_group_dim;
+        let (width, height) = (image.size().0 / 3, image.size().1);
+        let sample =
+            |image: &Image<f32>, x: usize, y: usize| image.row(y)[x * 3..x * 3 + 3].to_vec();
+        let left = sample(&clean[0][0], dim / 2, dim / 2);
+        let below = sample(&clean[0][0], (dim + width) / 2, (dim + height) / 2);
+        for (x, y) in [
+            (dim + 16, 16),
+            ((dim + width) / 2, dim / 2),
+            (width - 16, dim - 16),
+        ] {
+            let concealed = sample(&image, x, y);
+            for c in 0..3 {
+                let (min, max) = (left[c].min(below[c]), left[c].max(below[c]));
+                assert!(
+                    (min - 1e-3..=max + 1e-3).contains(&concealed[c]),
+                    "{x},{y}: {concealed:?} not between {left:?} and {below:?}"
+                );
+            }
+        }
+    }
+
+    /// Decodes the first frame of `file` in the default pixel format of `options`, which is
+    /// returned with the samples of each output buffer.
+    fn decode_default_format_f32(
+        file: &[u8],
+        options: JxlDecoderOptions,
+        use_simple: bool,
+    ) -> (JxlPixelFormat, Vec<Vec<Vec<f32>>>) {
+        let mut input = file;
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        decoder.set_use_simple_pipeline(use_simple);
+        let format = decoder.current_pixel_format().clone();
+        let decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        let images = decode_frame_with_requirements(decoder, input);
+        let samples = images
+            .iter()
+            .map(|image| {
+                (0..image.byte_size().1)
+                    .map(|y| {
+                        (image.row(y).chunks_exact(4))
+                            .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect();
+        (format, samples)
+    }
+
+    #[test]
+    fn forced_rgb_replicates_gray() {
+        use crate::api::JxlColorType;
+
+        for (file, has_alpha) in [
+            ("resources/test/grayscale_patches_modular.jxl", false),
+            (
+                "resources/test/conformance_test_images/grayscale.jxl",
+                false,
+            ),
+            ("resources/test/gray_alpha_lossless.jxl", true),
+        ] {
+            let data = std::fs::read(file).unwrap();
+            for use_simple in [false, true] {
+                let (format, gray) =
+                    decode_default_format_f32(&data, JxlDecoderOptions::default(), use_simple);
+                assert_eq!(format.color_type, JxlColorType::Grayscale, "{file}");
+                assert_eq!(gray.len(), if has_alpha { 2 } else { 1 }, "{file}");
+
+                let options = JxlDecoderOptions {
+                    force_rgb: true,
+                    ..Default::default()
+                };
+                let (format, rgb) = decode_default_format_f32(&data, options, use_simple);
+                assert_eq!(format.color_type, JxlColorType::Rgb, "{file}");
+                assert_eq!(rgb.len(), gray.len(), "{file}");
+                if has_alpha {
+                    assert_eq!(rgb[1], gray[1], "{file}");
+                }
+
+                let options = JxlDecoderOptions {
+                    force_rgba: true,
+                    ..Default::default()
+                };
+                let (format, rgba) = decode_default_format_f32(&data, options, use_simple);
+                assert_eq!(format.color_type, JxlColorType::Rgba, "{file}");
+                assert_eq!(rgba.len(), 1, "{file}");
+
+                for (y, gray_row) in gray[0].iter().enumerate() {
+                    for (x, value) in gray_row.iter().enumerate() {
+                        let at = format!("{file} at ({x}, {y}), simple: {use_simple}");
+                        assert_eq!(rgb[0][y][3 * x..3 * x + 3], [*value; 3], "{at}");
+                        assert_eq!(rgba[0][y][4 * x..4 * x + 3], [*value; 3], "{at}");
+                        let alpha = if has_alpha { gray[1][y][x] } else { 1.0 };
+                        assert_eq!(rgba[0][y][4 * x + 3], alpha, "{at}");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Maps the samples of `samples` to 0 below 0.5 and to 1 above.
+    fn threshold(samples: &mut crate::image::ImageRectMut<f32>) {
+        for y in 0..samples.size().1 {
+            for value in samples.row(y) {
+                *value = if *value < 0.5 { 0.0 } else { 1.0 };
+            }
+        }
+    }
+
+    #[test]
+    fn extra_channel_processor_sees_each_complete_frame_once() {
+        use crate::api::JxlExtraChannelType;
+        use crate::util::test::check_equal_images;
+        use std::sync::Mutex;
+
+        // Frames are cropped and blended over the previous ones, and decoded in chunks with
+        // flushes in between.
+        let file =
+            std::fs::read("resources/test/conformance_test_images/animation_icos4d.jxl").unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let options = JxlDecoderOptions {
+            extra_channel_processor: Some(Box::new({
+                let calls = calls.clone();
+                move |index, channel, samples| {
+                    let call = (index, channel.ec_type, samples.size());
+                    calls.lock().unwrap().push(call);
+                    threshold(samples);
+                }
+            })),
+            ..Default::default()
+        };
+        let (num_frames, frames) =
+            decode_with_input_ends(&file, |end| end + 997, options, false, true, None).unwrap();
+        let (_, expected) = decode(&file, usize::MAX, false, false, None).unwrap();
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![(0, JxlExtraChannelType::Alpha, (128, 128)); num_frames]
+        );
+        for (buffers, mut expected) in frames.into_iter().zip(expected) {
+            // Alpha interleaved with the colors is not processed.
+            check_equal_images(&buffers[0], &expected[0]);
+            let size = expected[1].size();
+            threshold(&mut expected[1].get_rect_mut(Rect {
+                origin: (0, 0),
+                size,
+            }));
+            check_equal_images(&buffers[1], &expected[1]);
+        }
+    }
+
+    #[test]
+    fn extra_channel_processor_runs_before_resizing() {
+        use crate::api::{JxlColorType, JxlDataFormat, JxlExtraChannelType};
+        use std::sync::Mutex;
+
+        let file = std::fs::read("resources/test/dice.jxl").unwrap();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let options = JxlDecoderOptions {
+            resize_to: Some((200, 150)),
+            extra_channel_processor: Some(Box::new({
+                let calls = calls.clone();
+                move |index, channel, samples| {
+                    let call = (index, channel.ec_type, samples.size());
+                    calls.lock().unwrap().push(call);
+                    for y in 0..samples.size().1 {
+                        samples.row(y).fill(1.0);
+                    }
+                }
+            })),
+            ..Default::default()
+        };
+        let format = JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+            extra_channel_format: vec![Some(JxlDataFormat::U8 { bit_depth: 8 })],
+        };
+        let (decoder, input) = advance_to_frame_info(&file, options, Some(format));
+        let size = decoder.inner.basic_info().unwrap().size;
+        let resized = decode_frame_with_requirements(decoder, input);
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [(0, JxlExtraChannelType::Alpha, size)]
+        );
+        for y in 0..150 {
+            assert!(resized[1].row(y).iter().all(|&alpha| alpha == 255));
+        }
+    }
+
+    #[test]
+    fn test_output_buffer_requirements_preview_frame() {
+        let file = std::fs::read("resources/test/with_preview.jxl").unwrap();
+        let options = JxlDecoderOptions {
+            skip_preview: false,
+            ..Default::default()
+        };
+        let (decoder, input) = advance_to_frame_info(&file, options, None);
+        let requirements = decoder.output_buffer_requirements();
+        assert_eq!((requirements[0].width, requirements[0].height), (16, 16));
+        decode_frame_with_requirements(decoder, input);
+    }
+
+    #[test]
+    fn test_output_buffer_requirements_transposed_orientation() {
+        let file = std::fs::read("resources/test/orientation6_rotate_90_cw.jxl").unwrap();
+        let (decoder, input) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        let requirements = decoder.output_buffer_requirements();
+        assert_eq!(
+            (requirements[0].width, requirements[0].height),
+            decoder.frame_header().size
+        );
+        assert_ne!(requirements[0].width, requirements[0].height);
+        decode_frame_with_requirements(decoder, input);
+    }
+
+    #[test]
+    fn test_column_major_output_is_transposed() {
+        let big_endian_rgb = JxlPixelFormat {
+            color_type: JxlColorType::Rgb,
+            color_data_format: Some(JxlDataFormat::U16 {
+                endianness: Endianness::BigEndian,
+                bit_depth: 16,
+            }),
+            extra_channel_format: vec![],
+        };
+        let names = [
+            "identity",
+            "flip_horizontal",
+            "rotate_180",
+            "flip_vertical",
+            "transpose",
+            "rotate_90_cw",
+            "anti_transpose",
+            "rotate_90_ccw",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            let path = format!("resources/test/orientation{}_{name}.jxl", i + 1);
+            let file = std::fs::read(path).unwrap();
+            for pixel_format in [None, Some(big_endian_rgb.clone())] {
+                let decode = |output_layout| {
+                    let options = JxlDecoderOptions {
+                        output_layout,
+                        ..Default::default()
+                    };
+                    let (decoder, input) =
+                        advance_to_frame_info(&file, options, pixel_format.clone());
+                    let requirement = decoder.output_buffer_requirements()[0];
+                    let images = decode_frame_with_requirements(decoder, input);
+                    (requirement, images.into_iter().next().unwrap())
+                };
+                let (row_req, row_major) = decode(OutputLayout::RowMajor);
+                let (column_req, column_major) = decode(OutputLayout::ColumnMajor);
+                assert_eq!(
+                    (column_req.width, column_req.height),
+                    (row_req.height, row_req.width)
+                );
+                assert_ne!(row_req.width, row_req.height);
+                let pixel_bytes = row_req.bytes_per_row() / row_req.width;
+                for y in 0..row_req.height {
+                    for x in 0..row_req.width {
+                        assert_eq!(
+                            row_major.row(y)[x * pixel_bytes..][..pixel_bytes],
+                            column_major.row(x)[y * pixel_bytes..][..pixel_bytes],
+                            "{name} at ({x}, {y})"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_buffer_mismatch_error() {
+        let file = std::fs::read("resources/test/basic.jxl").unwrap();
+        let (decoder, mut input) = advance_to_frame_info(&file, JxlDecoderOptions::default(), None);
+        let req = decoder.output_buffer_requirements()[0];
+        let mut image =
+            crate::image::OwnedRawImage::new((req.bytes_per_row(), req.height + 1)).unwrap();
+        let rect = Rect {
+            origin: (0, 0),
+            size: image.byte_size(),
+        };
+        let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+            image.get_rect_mut(rect),
+        )];
+        let Err(err) = decoder.process(&mut input, &mut buffers) else {
+            panic!("decoding with a wrongly sized buffer should fail");
+        };
+        match err {
+            Error::OutputBufferMismatch {
+                index,
+                actual_rows,
+                expected_rows,
+                ..
+            } => {
+                assert_eq!(index, 0);
+                assert_eq!(actual_rows, req.height + 1);
+                assert_eq!(expected_rows, req.height);
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    /// Regression test for ClusterFuzz issue 5342436251336704
+    /// Tests that malformed JXL files with overflow-inducing data don't panic
+    #[test]
+    fn test_fuzzer_smallbuffer_overflow() {
+        use std::panic;
+
+        let data = include_bytes!("../../tests/testdata/fuzzer_smallbuffer_overflow.jxl");
+
+        // The test passes if it doesn't panic with "attempt to add with overflow"
+        // It's OK if it returns an error or panics with "Unexpected end of input"
+        let result = panic::catch_unwind(|| {
+            let _ = decode(data, 1024, false, false, None);
+        });
+
+        // If it panicked, make sure it wasn't an overflow panic
         if let Err(e) = result {
             let panic_msg = e
                 .downcast_ref::<&str>()
@@ -1450,6 +3759,167 @@ pub(crate) mod tests {
         }
     }
 
+    /// Decodes `data` through the public API like the fuzzer does, without the assertions of
+    /// `decode`.
+    fn decode_untrusted(
+        mut data: &[u8],
+        chunk_size: usize,
+        max_total_tokens: Option<u64>,
+    ) -> Result<(), Error> {
+        let options = JxlDecoderOptions {
+            pixel_limit: Some(1 << 22),
+            max_total_tokens,
+            ..Default::default()
+        };
+        let mut chunk = &data[..0];
+        macro_rules! advance {
+            ($decoder: ident $(, $buffers: expr)?) => {
+                loop {
+                    chunk = &data[..chunk.len().saturating_add(chunk_size).min(data.len())];
+                    let available = chunk.len();
+                    let result = $decoder.process(&mut chunk $(, $buffers)?)?;
+                    data = &data[available - chunk.len()..];
+                    match result {
+                        ProcessingResult::Complete { result } => break result,
+                        ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                            if data.is_empty() {
+                                return Ok(());
+                            }
+                            $decoder = fallback;
+                        }
+                    }
+                }
+            };
+        }
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut decoder = advance!(decoder);
+        loop {
+            let (width, height) = decoder.basic_info().size;
+            let pixel_format = decoder.current_pixel_format().clone();
+            let mut buffers = vec![Image::<f32>::new((
+                width * pixel_format.color_type.samples_per_pixel(),
+                height,
+            ))?];
+            for _ in pixel_format.extra_channel_format.iter().flatten() {
+                buffers.push(Image::<f32>::new((width, height))?);
+            }
+            let mut api_buffers: Vec<_> = buffers
+                .iter_mut()
+                .map(|b| {
+                    let size = b.size();
+                    JxlOutputBuffer::from_image_rect_mut(
+                        b.get_rect_mut(Rect {
+                            origin: (0, 0),
+                            size,
+                        })
+                        .into_raw(),
+                    )
+                })
+                .collect();
+            let mut with_frame_info = advance!(decoder);
+            decoder = advance!(with_frame_info, &mut api_buffers);
+            if !decoder.has_more_frames() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Deterministic corruptions of `data`: single byte changes, bursts of garbage and
+    /// truncations, spread over the whole file.
+    fn corrupted_variants(data: &[u8], count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545f4914f6cdd1d_u64 ^ data.len() as u64;
+        let mut rng = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u32
+        };
+        let mut variants = vec![];
+        for i in 0..count {
+            let mut d = data.to_vec();
+            let pos = rng() as usize % data.len();
+            match i % 4 {
+                0 => d[pos] ^= 1 << (rng() % 8),
+                1 => d[pos] = rng() as u8,
+                2 => {
+                    for b in d[pos..].iter_mut().take(8) {
+                        *b = rng() as u8;
+                    }
+                }
+                _ => d.truncate(pos),
+            }
+            variants.push(d);
+        }
+        variants
+    }
+
+    #[test]
+    fn token_limit_stops_decompression_bombs() {
+        // 64 frames of 1024x1024 pixels, coded with zero bits per sample and cropped to an 8x8
+        // image: less than 1KB that takes seconds to decode without a limit.
+        let data = include_bytes!("../../tests/testdata/token_bomb.jxl");
+        let limit = 8 * 8 * 1024;
+        for chunk_size in [usize::MAX, 7] {
+            let err = decode_untrusted(data, chunk_size, Some(limit)).unwrap_err();
+            assert!(
+                matches!(err.root_cause(), Error::WorkLimitExceeded(_, l) if *l == limit),
+                "{err}"
+            );
+        }
+        // Tokens add up over frames, and are charged once each row of 1024 samples is decoded.
+        let channel = 1024 * 1024;
+        let err = decode_untrusted(data, usize::MAX, Some(9 * channel)).unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            Error::WorkLimitExceeded(used, _) if *used == 9 * channel + 1024
+        ));
+    }
+
+    #[test]
+    fn token_limit_counts_decoded_coefficients() {
+        // Blocks of this VarDCT image code far fewer than the 64 coefficients per channel that
+        // they could, so it decodes within an eighth of that.
+        let data = std::fs::read("resources/test/stp2_520x260_d25_e6.jxl").unwrap();
+        let blocks = 520usize.div_ceil(8) * 260usize.div_ceil(8);
+        let limit = (blocks * 3 * 64 / 8) as u64;
+        decode_untrusted(&data, usize::MAX, Some(limit)).unwrap();
+    }
+
+    /// Decoding corrupt input must return errors, never panic, with or without debug assertions
+    /// (run the tests in both debug and release mode, as overflow checks differ).
+    #[test]
+    fn corrupt_inputs_do_not_panic() {
+        let mut corpus: Vec<(String, Vec<u8>)> = vec![(
+            "fuzzer_smallbuffer_overflow".into(),
+            include_bytes!("../../tests/testdata/fuzzer_smallbuffer_overflow.jxl").to_vec(),
+        )];
+        for entry in std::fs::read_dir("resources/test").unwrap() {
+            let path = entry.unwrap().path();
+            let data = std::fs::read(&path).unwrap_or_default();
+            if path.extension().is_some_and(|e| e == "jxl") && data.len() <= 6000 {
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                for (i, variant) in corrupted_variants(&data, 24).into_iter().enumerate() {
+                    corpus.push((format!("{name} variant {i}"), variant));
+                }
+            }
+        }
+        let mut panics = vec![];
+        for (name, data) in corpus.iter() {
+            for chunk_size in [usize::MAX, 7] {
+                let result = std::panic::catch_unwind(|| decode_untrusted(data, chunk_size, None));
+                if let Err(e) = result {
+                    let msg = e
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| e.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    panics.push(format!("{name} (chunk size {chunk_size}): {msg}"));
+                }
+            }
+        }
+        assert!(panics.is_empty(), "{}", panics.join("\n"));
+    }
+
     fn make_box(ty: &[u8; 4], content: &[u8]) -> Vec<u8> {
         let len = (8 + content.len()) as u32;
         let mut buf = Vec::new();
@@ -1528,6 +3998,203 @@ pub(crate) mod tests {
         container
     }
 
+    #[test]
+    fn test_nested_gain_maps_are_not_decoded() {
+        let codestream = std::fs::read("resources/test/basic.jxl").unwrap();
+        // Each gain map embeds a file that declares another gain map.
+        let mut gain_map = codestream.clone();
+        for _ in 0..16 {
+            let mut embedded = Vec::new();
+            add_container_header(&mut embedded);
+            // Version, metadata size, color encoding size and ICC size.
+            let header = [0u8; 8];
+            embedded.extend(make_box(b"jhgm", &[&header[..], &gain_map].concat()));
+            embedded.extend(make_box(b"jxlc", &codestream));
+            gain_map = embedded;
+        }
+        let (_, expected) = decode(&codestream, usize::MAX, false, false, None).unwrap();
+        for chunk_size in [1, 1000, usize::MAX] {
+            let (_, frames) = decode(&gain_map, chunk_size, false, false, None).unwrap();
+            assert_eq!(frames.len(), expected.len());
+            for (frame, expected) in frames.iter().zip(expected.iter()) {
+                for (channel, expected) in frame.iter().zip(expected.iter()) {
+                    crate::util::test::check_equal_images(channel, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compressed_structural_boxes_rejected() {
+        let codestream = std::fs::read("resources/test/basic.jxl").unwrap();
+        for (inner, valid) in [
+            (b"xml ", true),
+            (b"Exif", true),
+            (b"jxlc", false),
+            (b"jxlp", false),
+            (b"brob", false),
+            (b"ftyp", false),
+        ] {
+            let mut container = Vec::new();
+            add_container_header(&mut container);
+            container.extend(make_box(b"brob", &[&inner[..], &[0x0b; 16]].concat()));
+            container.extend(make_box(b"jxlc", &codestream));
+            let result = decode(&container, usize::MAX, false, false, None);
+            if valid {
+                assert!(result.is_ok(), "{:?}", result.err());
+            } else {
+                assert!(
+                    matches!(result, Err(Error::InvalidCompressedBox(ref ty)) if ty.as_bytes() == inner),
+                    "{:?}",
+                    result.err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_boxes_are_skipped() {
+        let codestream = std::fs::read("resources/test/3x3_srgb_lossless.jxl").unwrap();
+        let (_, expected) = decode(&codestream, usize::MAX, false, false, None).unwrap();
+        let (first, last) = codestream.split_at(codestream.len() / 2);
+        let box_to_end = |ty: &[u8; 4], content: &[u8]| {
+            let mut buf = make_box(ty, content);
+            buf[..4].fill(0);
+            buf
+        };
+        let mut truncated = make_box(b"abcd", &[1; 1000]);
+        truncated.truncate(20);
+        let layouts = [
+            vec![
+                make_box(b"free", &[]),
+                make_box(b"abcd", &[1; 20]),
+                make_box(b"jxlc", &codestream),
+                box_to_end(b"efgh", &[2; 30]),
+            ],
+            vec![make_box(b"jxlc", &codestream), truncated],
+            vec![
+                make_box(b"jxlp", &[&[0, 0, 0, 0], first].concat()),
+                make_box(b"abcd", &[3; 5]),
+                make_box(b"jxlp", &[&[0x80, 0, 0, 1], last].concat()),
+                box_to_end(b"efgh", &[]),
+            ],
+        ];
+        for boxes in layouts {
+            let mut container = Vec::new();
+            add_container_header(&mut container);
+            container.extend(boxes.concat());
+            for chunk_size in [1, 7, usize::MAX] {
+                let (_, frames) = decode(&container, chunk_size, false, false, None).unwrap();
+                assert_eq!(frames.len(), expected.len());
+                for (frame, expected) in frames.iter().zip(expected.iter()) {
+                    for (channel, expected) in frame.iter().zip(expected.iter()) {
+                        crate::util::test::check_equal_images(channel, expected);
+                    }
+                }
+            }
+        }
+
+        // A box that extends to the end of the file cannot be followed by the codestream.
+        let mut container = Vec::new();
+        add_container_header(&mut container);
+        container.extend(box_to_end(b"abcd", &[1; 20]));
+        container.extend(make_box(b"jxlc", &codestream));
+        let result = decode(&container, usize::MAX, false, false, None);
+        assert!(
+            matches!(result, Err(Error::LastBoxBeforeCodestreamEnd(ref ty)) if ty == "abcd"),
+            "{:?}",
+            result.err()
+        );
+    }
+
+    #[test]
+    fn truncated_unknown_box_size_hint() {
+        let codestream = std::fs::read("resources/test/3x3_srgb_lossless.jxl").unwrap();
+        let size_hint = |boxes: &[Vec<u8>], available: usize| {
+            let mut container = Vec::new();
+            add_container_header(&mut container);
+            let header_len = container.len();
+            container.extend(boxes.concat());
+            let mut input = &container[..header_len + available];
+            let decoder = JxlDecoder::<states::Initialized>::new(Default::default());
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::NeedsMoreInput { size_hint, .. } => size_hint,
+                ProcessingResult::Complete { .. } => panic!("decoded a truncated file"),
+            }
+        };
+        let jxlc = make_box(b"jxlc", &codestream);
+        // Before the codestream starts, the rest of the unknown box is needed on top of it.
+        let without_box = size_hint(std::slice::from_ref(&jxlc), 8);
+        let with_box = size_hint(&[make_box(b"abcd", &[1; 500]), jxlc], 8 + 100);
+        assert_eq!(with_box, without_box + 400);
+    }
+
+    #[test]
+    fn test_container_size_mismatch() {
+        use crate::container::exif::tests::exif_with_size;
+
+        // Exif stores the size of the image before it is rotated.
+        let codestream = std::fs::read("resources/test/orientation6_rotate_90_cw.jxl").unwrap();
+        let stored_size = (100, 256);
+        let with_exif = |(width, height): (usize, usize), before_codestream| {
+            let exif = make_box(b"Exif", &exif_with_size(width as u32, height as u32));
+            let jxlc = make_box(b"jxlc", &codestream);
+            let mut container = Vec::new();
+            add_container_header(&mut container);
+            match before_codestream {
+                true => container.extend([exif, jxlc].concat()),
+                false => container.extend([jxlc, exif].concat()),
+            }
+            container
+        };
+        let size_mismatch = |file: &[u8], options| {
+            let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+            let mut input = file;
+            let decoder = loop {
+                match decoder.process(&mut input)? {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            };
+            assert_eq!(decoder.basic_info().size, (256, 100));
+            Ok::<_, Error>(decoder.size_mismatch().copied())
+        };
+
+        let wrong_size = with_exif((100, 200), true);
+        let mismatch = size_mismatch(&wrong_size, JxlDecoderOptions::default()).unwrap();
+        let mismatch = mismatch.unwrap();
+        assert_eq!(mismatch.container, (100, 200));
+        assert_eq!(mismatch.codestream, stored_size);
+        // The size of the codestream is used.
+        let (_, expected) = decode(&codestream, usize::MAX, false, false, None).unwrap();
+        for chunk_size in [1, usize::MAX] {
+            let (_, frames) = decode(&wrong_size, chunk_size, false, false, None).unwrap();
+            assert_eq!(frames.len(), expected.len());
+            for (frame, expected) in frames.iter().zip(expected.iter()) {
+                for (channel, expected) in frame.iter().zip(expected.iter()) {
+                    crate::util::test::check_equal_images(channel, expected);
+                }
+            }
+        }
+
+        let strict = || JxlDecoderOptions {
+            reject_size_mismatch: true,
+            ..Default::default()
+        };
+        let err = size_mismatch(&wrong_size, strict()).unwrap_err();
+        assert!(
+            matches!(err, Error::ContainerSizeMismatch(100, 200, 100, 256)),
+            "{err:?}"
+        );
+        for file in [
+            with_exif(stored_size, true),
+            // Exif metadata after the codestream is not read before decoding it.
+            with_exif((100, 200), false),
+        ] {
+            assert_eq!(size_mismatch(&file, strict()).unwrap(), None);
+        }
+    }
+
     #[test]
     fn test_frame_index_parsed_from_container() {
         // Read a bare animation codestream and wrap it in a container with a jxli box.
@@ -1595,12 +4262,20 @@ pub(crate) mod tests {
         assert!(dec.frame_index().is_none());
     }
 
-    fn scan_frames_with_decoder(mut input: &[u8], chunk_size: usize) -> Vec<VisibleFrameInfo> {
+    fn scan_frames_with_decoder(input: &[u8], chunk_size: usize) -> Vec<VisibleFrameInfo> {
+        scan_frames_with_options(input, chunk_size, JxlDecoderOptions::default())
+    }
+
+    fn scan_frames_with_options(
+        mut input: &[u8],
+        chunk_size: usize,
+        options: JxlDecoderOptions,
+    ) -> Vec<VisibleFrameInfo> {
         let mut chunk_input = &input[0..0];
         let options = JxlDecoderOptions {
             scan_frames_only: true,
             skip_preview: false,
-            ..Default::default()
+            ..options
         };
         let mut initialized_decoder = JxlDecoder::<states::Initialized>::new(options);
 
@@ -1718,7 +4393,7 @@ pub(crate) mod tests {
 
         // 4. Seek to decode-start and advance to the target visible frame.
         decoder.start_new_frame(seek_target);
-        let mut input = &data[seek_target.decode_start_file_offset..];
+        let mut input = &data[seek_target.decode_start_file_offset as usize..];
 
         for _ in 0..seek_target.visible_frames_to_skip {
             let mut decoder_frame = loop {
@@ -1829,7 +4504,10 @@ pub(crate) mod tests {
             "test file should have one codestream frame per visible frame",
         );
 
-        let mut chunk_starts: Vec<usize> = scanned_frames.iter().map(|f| f.file_offset).collect();
+        let mut chunk_starts: Vec<usize> = scanned_frames
+            .iter()
+            .map(|f| f.file_offset as usize)
+            .collect();
         chunk_starts.sort_unstable();
         chunk_starts.dedup();
         assert_eq!(chunk_starts.len(), scanned_frames.len());
@@ -1838,6 +4516,50 @@ pub(crate) mod tests {
         assert_start_new_frame_matches_sequential(&container, false);
     }
 
+    #[test]
+    fn noise_is_independent_of_decode_order() {
+        use crate::test_utils::scenarios;
+        let spec = scenarios::noisy_animation(2);
+        let (_, expected) = decode(&spec.build(), usize::MAX, false, false, None).unwrap();
+        let mut without_noise = spec.clone();
+        for frame in &mut without_noise.frames {
+            frame.noise = None;
+        }
+        let (_, noiseless) =
+            decode(&without_noise.build(), usize::MAX, false, false, None).unwrap();
+        assert_ne!(expected[1][0].row(0), noiseless[1][0].row(0));
+
+        // Groups are stored in reverse order, so that they are decoded in that order.
+        let mut reversed = spec.clone();
+        for frame in &mut reversed.frames {
+            frame.toc_permutation = Some(vec![0, 1, 2, 8, 7, 6, 5, 4, 3]);
+        }
+        let reversed = reversed.build();
+        let check = |frames: Vec<Vec<Image<f32>>>| {
+            assert_eq!(frames.len(), expected.len());
+            for (frame, expected) in frames.iter().zip(&expected) {
+                for (channel, expected) in frame.iter().zip(expected) {
+                    crate::util::test::check_equal_images(channel, expected);
+                }
+            }
+        };
+        check(decode(&reversed, usize::MAX, false, false, None).unwrap().1);
+        // Groups that are rendered early by flushes are rendered again with the same noise.
+        for data in [spec.build(), reversed] {
+            check(decode(&data, 997, false, true, None).unwrap().1);
+        }
+    }
+
+    /// Test that frames that are decoded after a seek have the noise of a sequential decode,
+    /// which is seeded with the number of frames before them.
+    #[test]
+    fn test_start_new_frame_noise() {
+        let data = crate::test_utils::scenarios::noisy_animation(3).build();
+        let scanned_frames = scan_frames_with_decoder(&data, usize::MAX);
+        assert!(scanned_frames.iter().all(|frame| frame.is_keyframe));
+        assert_start_new_frame_matches_sequential(&data, true);
+    }
+
     #[test]
     fn test_scan_still_image() {
         let data = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
@@ -1850,6 +4572,47 @@ pub(crate) mod tests {
         assert_eq!(total_duration_ms, 0.0);
     }
 
+    #[test]
+    fn test_scan_many_long_frame_names() {
+        use crate::test_utils::scenarios;
+        let data = scenarios::long_named_frames(5000, 1000).build();
+        let scan = |max_frame_names_size| {
+            let options = JxlDecoderOptions {
+                max_frame_names_size,
+                ..Default::default()
+            };
+            scan_frames_with_options(&data, usize::MAX, options)
+        };
+        let kept_size = |frames: &[VisibleFrameInfo]| -> usize {
+            frames.iter().filter_map(|f| f.name()).map(str::len).sum()
+        };
+
+        let frames = scan(1 << 20);
+        assert_eq!(frames.len(), 5000);
+        assert!(frames.iter().all(|f| f.name_len == 1000));
+        // Names are kept in frame order until the next one does not fit.
+        let num_kept = (1 << 20) / 1000;
+        assert_eq!(kept_size(&frames), num_kept * 1000);
+        for (i, frame) in frames.iter().enumerate() {
+            match frame.name() {
+                Some(name) => {
+                    assert!(i < num_kept);
+                    assert_eq!(name, format!("{i:0>1000}"));
+                }
+                None => assert!(i >= num_kept),
+            }
+        }
+
+        let frames = scan(usize::MAX);
+        assert_eq!(kept_size(&frames), 5000 * 1000);
+        let frames = scan(0);
+        assert!(
+            frames
+                .iter()
+                .all(|f| f.name().is_none() && f.name_len == 1000)
+        );
+    }
+
     #[test]
     fn test_scan_bare_animation() {
         let data =
@@ -1959,16 +4722,92 @@ pub(crate) mod tests {
         assert!(!frames.is_empty());
     }
 
+    fn embedded_profile(
+        mut data: &[u8],
+        chunk_size: usize,
+        options: JxlDecoderOptions,
+    ) -> Result<JxlColorProfile, Error> {
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut chunk = &data[..0];
+        loop {
+            chunk = &data[..chunk.len().saturating_add(chunk_size).min(data.len())];
+            let available = chunk.len();
+            let result = decoder.process(&mut chunk)?;
+            data = &data[available - chunk.len()..];
+            match result {
+                ProcessingResult::Complete { result } => {
+                    return Ok(result.embedded_color_profile().clone());
+                }
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        }
+    }
+
+    #[test]
+    fn icc_profile_in_small_chunks() {
+        for file in [
+            "with_icc.jxl",
+            "small_grayscale_patches_modular_with_icc.jxl",
+        ] {
+            let data = std::fs::read(format!("resources/test/{file}")).unwrap();
+            let profile = embedded_profile(&data, usize::MAX, JxlDecoderOptions::default());
+            let JxlColorProfile::Icc(icc) = profile.unwrap() else {
+                panic!("{file} has no ICC profile");
+            };
+            for chunk_size in [1, 2, 3, 7] {
+                let profile = embedded_profile(&data, chunk_size, JxlDecoderOptions::default());
+                assert!(
+                    matches!(profile, Ok(JxlColorProfile::Icc(ref chunked)) if *chunked == icc),
+                    "{file} {chunk_size}"
+                );
+            }
+
+            // The declared size of the profile is checked against the limit.
+            let options = |max_icc_size| JxlDecoderOptions {
+                max_icc_size,
+                ..Default::default()
+            };
+            assert!(embedded_profile(&data, 1, options(icc.len())).is_ok());
+            for chunk_size in [1, usize::MAX] {
+                assert!(matches!(
+                    embedded_profile(&data, chunk_size, options(icc.len() - 1)),
+                    Err(Error::IccTooLarge)
+                ));
+            }
+        }
+    }
+
     /// Regression test for Chromium ClusterFuzz issue 474401148.
     #[test]
     fn test_fuzzer_xyb_icc_no_panic() {
         use crate::api::ProcessingResult;
-
-        #[rustfmt::skip]
-        let data: &[u8] = &[
-            0xff, 0x0a, 0x01, 0x00, 0x00, 0x04, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x11, 0x25, 0x00,
-        ];
+        use crate::headers::color_encoding::ColorSpace;
+        use crate::test_utils::BitstreamBuilder;
+
+        let mut builder = BitstreamBuilder::new();
+        builder.write(8, 0xff).write(8, 0x0a);
+        // Small 8x8 size.
+        builder.write_bool(true).write(5, 0).write(3, 0).write(5, 0);
+        // Image metadata: not all default, no extra fields, 8 bits per sample, not
+        // modular_16bit_sufficient, no extra channels, not XYB encoded.
+        builder
+            .write_bool(false)
+            .write_bool(false)
+            .write_bool(false)
+            .write(2, 0)
+            .write_bool(false)
+            .write(2, 0)
+            .write_bool(false);
+        // Color encoding: not all default, no ICC, XYB color space, perceptual rendering intent.
+        builder
+            .write_bool(false)
+            .write_bool(false)
+            .write_enum(ColorSpace::XYB as u32)
+            .write(2, 0);
+        // Rest of the fuzzer input.
+        builder.zero_pad_to_byte().write(48, 0).write(24, 0x2511);
+        let data = builder.finish();
+        let data = data.as_slice();
 
         let opts = JxlDecoderOptions {
             pixel_limit: Some(1024 * 1024 * 1024),
@@ -1977,10 +4816,233 @@ pub(crate) mod tests {
         let mut decoder = JxlDecoderInner::new(opts);
         let mut input = data;
 
-        if let Ok(ProcessingResult::Complete { .. }) = decoder.process(&mut input, None)
-            && let Some(profile) = decoder.output_color_profile()
-        {
-            let _ = profile.try_as_icc();
+        if let Ok(ProcessingResult::Complete { .. }) = decoder.process(&mut input, None) {
+            if let Some(profile) = decoder.output_color_profile() {
+                let _ = profile.try_as_icc();
+            }
+        }
+    }
+
+    fn scan_compression_summary(path: &str) -> Option<CompressionSummary> {
+        let data = std::fs::read(path).unwrap();
+        let mut input = data.as_slice();
+        let options = JxlDecoderOptions {
+            scan_frames_only: true,
+            ..Default::default()
+        };
+        let decoder = JxlDecoder::<states::Initialized>::new(options);
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input).unwrap()
+        else {
+            panic!("truncated file");
+        };
+        assert!(decoder.compression_summary().is_none());
+        while decoder.has_more_frames() {
+            let ProcessingResult::Complete { result: frame } = decoder.process(&mut input).unwrap()
+            else {
+                panic!("truncated file");
+            };
+            let ProcessingResult::Complete { result } = frame.skip_frame(&mut input).unwrap()
+            else {
+                panic!("truncated file");
+            };
+            decoder = result;
+        }
+        let summary = decoder.compression_summary();
+        // Bare codestreams only contain codestream bytes.
+        if let Some(summary) = summary.as_ref().filter(|_| data.starts_with(&[0xff, 0x0a])) {
+            let (xsize, ysize) = decoder.basic_info().size;
+            let visible = summary.per_frame.iter().filter(|f| f.is_visible).count();
+            let expected = data.len() as f32 * 8.0 / (xsize * ysize * visible) as f32;
+            assert!((summary.bits_per_pixel - expected).abs() < 1e-3);
+        }
+        summary
+    }
+
+    #[test]
+    fn compression_summary_lossless() {
+        for file in [
+            "resources/test/3x3_srgb_lossless.jxl",
+            "resources/test/3x3a_srgb_lossless.jxl",
+            "resources/test/gray_alpha_lossless.jxl",
+        ] {
+            let summary = scan_compression_summary(file).unwrap();
+            assert!(summary.all_modular_lossless, "{file}");
+            assert!(!summary.any_vardct, "{file}");
+            assert!(!summary.xyb_encoded, "{file}");
+            assert!(summary.to_string().starts_with("lossless (modular), "));
+        }
+    }
+
+    #[test]
+    fn compression_summary_lossy() {
+        let summary = scan_compression_summary("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        assert!(!summary.all_modular_lossless);
+        assert!(summary.any_vardct);
+        assert!(summary.xyb_encoded);
+        assert_eq!(summary.per_frame.len(), 1);
+        assert!(summary.to_string().starts_with("lossy (VarDCT, XYB), "));
+
+        let summary = scan_compression_summary("resources/test/3x3_srgb_lossy.jxl").unwrap();
+        assert!(!summary.all_modular_lossless);
+        assert!(summary.to_string().starts_with("lossy ("));
+    }
+
+    #[test]
+    fn compression_summary_display() {
+        let frame = crate::api::FrameCompressionInfo {
+            is_visible: true,
+            modular: true,
+            lossless: true,
+            section_bytes: 10,
+        };
+        let mut summary = CompressionSummary {
+            all_modular_lossless: true,
+            any_vardct: false,
+            xyb_encoded: false,
+            bits_per_pixel: 3.21,
+            per_frame: vec![frame.clone()],
+        };
+        assert_eq!(summary.to_string(), "lossless (modular), 3.2 bpp");
+        summary.all_modular_lossless = false;
+        summary.xyb_encoded = true;
+        summary.any_vardct = true;
+        summary.bits_per_pixel = 0.94;
+        summary.per_frame[0].modular = false;
+        assert_eq!(summary.to_string(), "lossy (VarDCT, XYB), 0.9 bpp");
+        summary.per_frame.push(frame);
+        summary.xyb_encoded = false;
+        assert_eq!(summary.to_string(), "lossy (VarDCT + modular), 0.9 bpp");
+    }
+
+    #[test]
+    fn corrupted_group_error_chain() {
+        let mut data = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        // Corrupt the data of the second HF group.
+        for b in &mut data[40000..40016] {
+            *b ^= 0x5a;
+        }
+        let err = decode(&data, usize::MAX, false, false, None).unwrap_err();
+        let mut chain = vec![err.to_string()];
+        let mut source = std::error::Error::source(&err);
+        while let Some(err) = source {
+            chain.push(err.to_string());
+            source = err.source();
+        }
+        assert_eq!(chain.len(), 3, "{chain:?}");
+        assert_eq!(chain[0], "Failed to decode frame 0");
+        assert_eq!(chain[1], "Failed to decode HF group 1");
+        assert!(
+            matches!(err.root_cause(), Error::EndOfBlockResidualNonZeros(_)),
+            "{chain:?}"
+        );
+        assert_eq!(chain[2], err.root_cause().to_string());
+    }
+
+    /// Allocator that accounts for the memory it hands out from the global allocator.
+    #[derive(Default)]
+    struct TrackingAllocator {
+        num_allocations: AtomicUsize,
+        current_bytes: AtomicUsize,
+        peak_bytes: AtomicUsize,
+    }
+
+    #[allow(unsafe_code)]
+    // SAFETY: memory comes from the global allocator, with the same layout.
+    unsafe impl crate::api::JxlAllocator for TrackingAllocator {
+        fn allocate(&self, layout: std::alloc::Layout) -> *mut u8 {
+            self.num_allocations.fetch_add(1, Ordering::Relaxed);
+            let current = self
+                .current_bytes
+                .fetch_add(layout.size(), Ordering::Relaxed);
+            self.peak_bytes
+                .fetch_max(current + layout.size(), Ordering::Relaxed);
+            // SAFETY: the size of the layout is not 0.
+            unsafe { std::alloc::alloc(layout) }
+        }
+
+        unsafe fn deallocate(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            self.current_bytes
+                .fetch_sub(layout.size(), Ordering::Relaxed);
+            // SAFETY: `ptr` was allocated by `allocate` with `layout`.
+            unsafe { std::alloc::dealloc(ptr, layout) }
+        }
+    }
+
+    /// Decodes all the frames of `input` to RGBA u8 samples, and returns them with the decoder.
+    fn decode_all_frames(
+        mut input: &[u8],
+        options: JxlDecoderOptions,
+    ) -> (Vec<Vec<u8>>, JxlDecoder<WithImageInfo>) {
+        let mut decoder = JxlDecoder::<states::Initialized>::new(options);
+        let mut decoder = loop {
+            match decoder.process(&mut input).unwrap() {
+                ProcessingResult::Complete { result } => break result,
+                ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+            }
+        };
+        decoder.set_pixel_format(JxlPixelFormat::rgba8(0));
+        let mut frames: Vec<Vec<u8>> = vec![];
+        while decoder.has_more_frames() {
+            let mut frame_decoder = loop {
+                match decoder.process(&mut input).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => decoder = fallback,
+                }
+            };
+            let requirement = frame_decoder.output_buffer_requirements()[0];
+            let mut image = crate::image::OwnedRawImage::new(requirement.byte_size()).unwrap();
+            let rect = Rect {
+                origin: (0, 0),
+                size: image.byte_size(),
+            };
+            let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+                image.get_rect_mut(rect),
+            )];
+            decoder = loop {
+                match frame_decoder.process(&mut input, &mut buffers).unwrap() {
+                    ProcessingResult::Complete { result } => break result,
+                    ProcessingResult::NeedsMoreInput { fallback, .. } => frame_decoder = fallback,
+                }
+            };
+            frames.push(
+                (0..requirement.height)
+                    .flat_map(|y| image.row(y).to_vec())
+                    .collect(),
+            );
         }
+        (frames, decoder)
+    }
+
+    #[test]
+    fn test_allocator_memory_accounting() {
+        let file = std::fs::read("resources/test/grayscale_patches_var_dct.jxl").unwrap();
+        let (expected, decoder) = decode_all_frames(&file, JxlDecoderOptions::default());
+        let default_usage = decoder.memory_usage();
+        assert!(default_usage.peak_bytes > 0);
+
+        let allocator = Arc::new(TrackingAllocator::default());
+        let options = JxlDecoderOptions {
+            allocator: Some(allocator.clone()),
+            ..Default::default()
+        };
+        let (frames, decoder) = decode_all_frames(&file, options);
+        assert_eq!(frames, expected);
+        assert!(allocator.num_allocations.load(Ordering::Relaxed) > 0);
+        // The decoder accounts for exactly the memory that comes from the allocator, and the
+        // output buffers allocated above do not come from it.
+        let usage = decoder.memory_usage();
+        assert_eq!(usage, default_usage);
+        assert_eq!(
+            usage,
+            JxlMemoryUsage {
+                current_bytes: allocator.current_bytes.load(Ordering::Relaxed),
+                peak_bytes: allocator.peak_bytes.load(Ordering::Relaxed),
+            }
+        );
+        // Images that the decoder keeps, like reference frames, go back to the allocator with it.
+        drop(decoder);
+        assert_eq!(allocator.current_bytes.load(Ordering::Relaxed), 0);
     }
 }
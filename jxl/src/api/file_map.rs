@@ -0,0 +1,421 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::JxlDecoderOptions,
+    bit_reader::BitReader,
+    container::{BoxSpan, ContainerParser, ParseEvent, box_header::ContainerBoxType},
+    error::Result,
+    frame::Section,
+    headers::{
+        FileHeader, JxlHeader,
+        encodings::UnconditionalCoder,
+        frame_header::FrameHeader,
+        toc::{Toc, TocNonserialized},
+    },
+    icc::IncrementalIccReader,
+    util::saturating_usize,
+};
+
+/// Location of the boxes, frames and sections of a file, as returned by [`map_file`].
+///
+/// Frame and section offsets are codestream offsets, which only differ from file offsets for
+/// container files; [`FileMap::file_offset`] converts between them.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMap {
+    /// The boxes of the file in file order, or nothing for bare codestreams.
+    pub boxes: Vec<BoxSpan>,
+    /// Size of the codestream, that is, of the `jxlc` payload or of the `jxlp` payloads without
+    /// their box indices.
+    pub codestream_len: u64,
+    /// The frames of the codestream, including the preview frame. The image header and ICC
+    /// profile take up the bytes before the first frame.
+    pub frames: Vec<FrameSpan>,
+    /// Pieces of the codestream, as (codestream offset, file offset, size).
+    codestream_parts: Vec<(u64, u64, u64)>,
+}
+
+/// Location of a frame in the codestream.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameSpan {
+    /// Offset of the frame header in bits. Frame headers start at byte boundaries, so this is
+    /// always a multiple of 8.
+    pub header_bits_offset: u64,
+    pub is_preview: bool,
+    /// The sections in the order they are stored. The frame header and TOC take up the bytes
+    /// between `header_bits_offset` and the first section.
+    pub sections: Vec<SectionSpan>,
+}
+
+/// Location of a section of a frame in the codestream.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionSpan {
+    /// The section, or `None` for frames with a single TOC entry, whose section holds all of
+    /// them.
+    pub section: Option<Section>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Incremental hash function used by [`FileMap::section_digests`], so that embedders can pick the
+/// hash that suits them.
+pub trait SectionHasher {
+    type Digest;
+
+    /// Adds `bytes` to the hashed data.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Returns the digest of all the bytes passed to [`update`](Self::update).
+    fn finalize(self) -> Self::Digest;
+}
+
+/// Digests of the parts of a codestream, as returned by [`FileMap::section_digests`]. Comparing
+/// them with digests stored earlier locates corrupted bytes to a frame header or a section.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionDigests<D> {
+    /// Digest of the whole codestream.
+    pub codestream: D,
+    /// Digest of the image header and ICC profile, before the first frame.
+    pub image_header: D,
+    /// Digests of the frames, in the order of [`FileMap::frames`].
+    pub frames: Vec<FrameDigests<D>>,
+}
+
+/// Digests of a frame of a codestream.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameDigests<D> {
+    /// Digest of the frame header and TOC.
+    pub header: D,
+    /// Digests of the sections, in the order of [`FrameSpan::sections`].
+    pub sections: Vec<D>,
+}
+
+impl FileMap {
+    /// Returns the file offset of the codestream byte at `codestream_offset`, or `None` if it is
+    /// past the end of the file.
+    pub fn file_offset(&self, codestream_offset: u64) -> Option<u64> {
+        self.codestream_parts
+            .iter()
+            .find(|(start, _, len)| (*start..start + len).contains(&codestream_offset))
+            .map(|(start, file_offset, _)| file_offset + codestream_offset - start)
+    }
+
+    /// Hashes the codestream of `bytes`, the file that this map was built from, and each of its
+    /// frame headers and sections, with hashers created by `new_hasher`. The bytes of truncated
+    /// spans that are missing from the file are not hashed.
+    pub fn section_digests<H: SectionHasher>(
+        &self,
+        bytes: &[u8],
+        mut new_hasher: impl FnMut() -> H,
+    ) -> SectionDigests<H::Digest> {
+        let mut digest = |offset: u64, len: u64| {
+            let mut hasher = new_hasher();
+            self.hash_codestream_range(bytes, offset, len, &mut hasher);
+            hasher.finalize()
+        };
+        let first_frame = self
+            .frames
+            .first()
+            .map_or(self.codestream_len, |f| f.header_bits_offset / 8);
+        SectionDigests {
+            codestream: digest(0, self.codestream_len),
+            image_header: digest(0, first_frame),
+            frames: self
+                .frames
+                .iter()
+                .map(|frame| {
+                    let header_offset = frame.header_bits_offset / 8;
+                    let sections_offset =
+                        frame.sections.first().map_or(header_offset, |s| s.offset);
+                    FrameDigests {
+                        header: digest(header_offset, sections_offset - header_offset),
+                        sections: frame
+                            .sections
+                            .iter()
+                            .map(|s| digest(s.offset, s.len))
+                            .collect(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Passes the `len` codestream bytes at `offset` to `hasher`, as stored in the pieces of the
+    /// codestream of `bytes`.
+    fn hash_codestream_range(
+        &self,
+        bytes: &[u8],
+        offset: u64,
+        len: u64,
+        hasher: &mut impl SectionHasher,
+    ) {
+        let end = offset.saturating_add(len);
+        for &(part_start, file_offset, part_len) in &self.codestream_parts {
+            let start = offset.max(part_start);
+            let part_end = end.min(part_start + part_len);
+            if start >= part_end {
+                continue;
+            }
+            let file_start = (file_offset + start - part_start).min(bytes.len() as u64) as usize;
+            let file_end = (file_offset + part_end - part_start).min(bytes.len() as u64) as usize;
+            hasher.update(&bytes[file_start..file_end]);
+        }
+    }
+}
+
+impl FrameSpan {
+    /// Returns the codestream offset of the first byte after the frame.
+    pub fn end(&self) -> u64 {
+        self.sections
+            .last()
+            .map_or(self.header_bits_offset / 8, |s| s.offset + s.len)
+    }
+}
+
+/// Locates the boxes of `bytes`, and the frames and sections of its codestream, by reading only
+/// the headers and TOCs of the frames.
+///
+/// The sizes come from the file itself, so spans of truncated files can extend past the end of
+/// the codestream. The walk stops after the last frame, or at the end of the codestream if it
+/// ends between two frames.
+pub fn map_file(bytes: &[u8]) -> Result<FileMap> {
+    let mut parser = ContainerParser::recording_box_spans();
+    let mut codestream = Vec::new();
+    for event in parser.process_bytes(bytes) {
+        if let ParseEvent::Codestream(buf) = event? {
+            codestream.extend_from_slice(buf);
+        }
+    }
+    let boxes = parser.box_spans().to_vec();
+    let codestream_parts = if boxes.is_empty() {
+        vec![(0, 0, codestream.len() as u64)]
+    } else {
+        let mut parts = vec![];
+        let mut start = 0;
+        for b in &boxes {
+            let index_len = match b.kind {
+                ContainerBoxType::CODESTREAM => 0,
+                ContainerBoxType::PARTIAL_CODESTREAM => 4,
+                _ => continue,
+            };
+            let file_offset = b.payload_offset + index_len;
+            let available = (bytes.len() as u64).saturating_sub(file_offset);
+            let len = b
+                .payload_len
+                .map_or(available, |len| (len - index_len).min(available));
+            parts.push((start, file_offset, len));
+            start += len;
+        }
+        parts
+    };
+
+    let mut br = BitReader::new(&codestream);
+    let file_header = FileHeader::read(&mut br)?;
+    if file_header.image_metadata.color_encoding.want_icc {
+        let mut icc_reader =
+            IncrementalIccReader::new(&mut br, JxlDecoderOptions::default().max_icc_size)?;
+        icc_reader.read_all(&mut br)?;
+        icc_reader.finalize(&mut br)?;
+    }
+    br.jump_to_byte_boundary()?;
+
+    let mut frames = vec![];
+    let mut offset = br.total_bits_read() / 8;
+    let mut preview_nonserialized = file_header.preview_frame_header_nonserialized();
+    while offset < codestream.len() {
+        let is_preview = preview_nonserialized.is_some();
+        let nonserialized = preview_nonserialized
+            .take()
+            .unwrap_or_else(|| file_header.frame_header_nonserialized());
+        let mut br = BitReader::new(&codestream[offset..]);
+        let mut header = FrameHeader::read_unconditional(&(), &mut br, &nonserialized)?;
+        header.postprocess(&nonserialized);
+        let toc = Toc::read_unconditional(
+            &(),
+            &mut br,
+            &TocNonserialized {
+                num_entries: header.num_toc_entries() as u32,
+            },
+        )?;
+        br.jump_to_byte_boundary()?;
+
+        let mut stored_sections = vec![None; toc.entries.len()];
+        if toc.entries.len() > 1 {
+            let layout = header.group_layout();
+            for section in layout.sections() {
+                stored_sections[toc.position(layout.section_id(section))] = Some(section);
+            }
+        }
+        let mut section_offset = (offset + br.total_bits_read() / 8) as u64;
+        let sections = stored_sections
+            .into_iter()
+            .zip(&toc.entries)
+            .map(|(section, &len)| {
+                let span = SectionSpan {
+                    section,
+                    offset: section_offset,
+                    len: len as u64,
+                };
+                section_offset += len as u64;
+                span
+            })
+            .collect();
+        let frame = FrameSpan {
+            header_bits_offset: offset as u64 * 8,
+            is_preview,
+            sections,
+        };
+        // Frames of truncated files may end past the codestream, or even past what is
+        // addressable.
+        offset = saturating_usize(frame.end());
+        frames.push(frame);
+        if header.is_last && !is_preview {
+            break;
+        }
+    }
+
+    Ok(FileMap {
+        boxes,
+        codestream_len: codestream.len() as u64,
+        frames,
+        codestream_parts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use jxl_macros::for_each_test_file;
+    use std::path::Path;
+
+    /// Checks that the boxes cover the file, and that the header and frames cover the
+    /// codestream, without gaps or overlaps.
+    fn map_test_file(path: &Path) -> Result<(), Error> {
+        let bytes = std::fs::read(path)?;
+        let map = map_file(&bytes)?;
+
+        let mut end = 0;
+        for b in &map.boxes {
+            assert_eq!(b.header_offset, end, "{b:?}");
+            assert!(b.payload_offset > b.header_offset);
+            end = b
+                .payload_len
+                .map_or(bytes.len() as u64, |len| b.payload_offset + len);
+        }
+        assert!(map.boxes.is_empty() || end == bytes.len() as u64);
+
+        assert!(!map.frames.is_empty());
+        let mut end = map.frames[0].header_bits_offset / 8;
+        assert!(end > 0);
+        for frame in &map.frames {
+            assert_eq!(frame.header_bits_offset, end * 8);
+            end = frame.sections[0].offset;
+            assert!(end > frame.header_bits_offset / 8);
+            for section in &frame.sections {
+                assert_eq!(section.offset, end);
+                end += section.len;
+            }
+        }
+        assert_eq!(end, map.codestream_len);
+        assert!(!map.frames.iter().skip(1).any(|f| f.is_preview));
+
+        // The codestream bytes at the mapped file offsets are those of the codestream.
+        let codestream = ContainerParser::collect_codestream(&bytes)?;
+        for frame in &map.frames {
+            for offset in [frame.header_bits_offset / 8, frame.end() - 1] {
+                let file_offset = map.file_offset(offset).unwrap() as usize;
+                assert_eq!(bytes[file_offset], codestream[offset as usize]);
+            }
+        }
+        assert_eq!(map.file_offset(map.codestream_len), None);
+
+        // The digested bytes are those of the spans.
+        let digests = map.section_digests(&bytes, CollectBytes::default);
+        assert_eq!(digests.codestream.0, codestream);
+        let first_frame = map.frames[0].header_bits_offset as usize / 8;
+        assert_eq!(digests.image_header.0, codestream[..first_frame]);
+        for (frame, frame_digests) in map.frames.iter().zip(&digests.frames) {
+            let header_offset = frame.header_bits_offset as usize / 8;
+            let header = &codestream[header_offset..frame.sections[0].offset as usize];
+            assert_eq!(frame_digests.header.0, header);
+            for (section, digest) in frame.sections.iter().zip(&frame_digests.sections) {
+                let start = (section.offset as usize).min(codestream.len());
+                let end = ((section.offset + section.len) as usize).min(codestream.len());
+                assert_eq!(digest.0, codestream[start..end]);
+            }
+        }
+        Ok(())
+    }
+
+    for_each_test_file!(map_test_file);
+
+    /// Hashes bytes to themselves.
+    #[derive(Debug, Default, PartialEq)]
+    struct CollectBytes(Vec<u8>);
+
+    impl SectionHasher for CollectBytes {
+        type Digest = Self;
+
+        fn update(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+
+        fn finalize(self) -> Self {
+            self
+        }
+    }
+
+    #[test]
+    fn corrupted_section_digest() {
+        let mut bytes =
+            include_bytes!("../../resources/test/has_permutation_with_container.jxl").to_vec();
+        let map = map_file(&bytes).unwrap();
+        let digests = map.section_digests(&bytes, CollectBytes::default);
+        let section = &map.frames[0].sections[3];
+        let file_offset = map.file_offset(section.offset + section.len / 2).unwrap();
+        bytes[file_offset as usize] ^= 1;
+        let corrupted = map.section_digests(&bytes, CollectBytes::default);
+        assert_ne!(corrupted.codestream, digests.codestream);
+        assert_eq!(corrupted.image_header, digests.image_header);
+        assert_eq!(corrupted.frames[0].header, digests.frames[0].header);
+        for (i, (a, b)) in corrupted.frames[0]
+            .sections
+            .iter()
+            .zip(&digests.frames[0].sections)
+            .enumerate()
+        {
+            assert_eq!(a == b, i != 3, "section {i}");
+        }
+    }
+
+    #[test]
+    fn permuted_sections() {
+        let bytes = include_bytes!("../../resources/test/has_permutation_with_container.jxl");
+        let map = map_file(bytes).unwrap();
+        assert!(
+            map.boxes
+                .iter()
+                .any(|b| b.kind == ContainerBoxType::PARTIAL_CODESTREAM
+                    || b.kind == ContainerBoxType::CODESTREAM)
+        );
+        let (_, header, _) = crate::util::test::read_headers_and_toc(bytes).unwrap();
+        let stored: Vec<_> = map.frames[0]
+            .sections
+            .iter()
+            .map(|s| s.section.unwrap())
+            .collect();
+        let mut in_toc_order = stored.clone();
+        let layout = header.group_layout();
+        in_toc_order.sort_by_key(|&s| layout.section_id(s));
+        assert_ne!(stored, in_toc_order);
+        assert_eq!(in_toc_order, layout.sections().collect::<Vec<_>>());
+    }
+}
@@ -5,21 +5,25 @@
 
 use std::io::IoSliceMut;
 
-use crate::container::frame_index::FrameIndexBox;
+use crate::container::{box_header::ContainerBoxType, exif, frame_index::FrameIndexBox};
 use crate::error::{Error, Result};
+use crate::util::saturating_usize;
 
 use crate::api::{
     JxlBitstreamInput, JxlSignatureType, check_signature_internal, inner::process::SmallBuffer,
 };
 
+/// Size of the largest Exif box that is read to find the image size it declares.
+const MAX_EXIF_SIZE: u64 = 1 << 20;
+
 #[derive(Clone)]
 enum ParseState {
     SignatureNeeded,
     BoxNeeded,
     CodestreamBox(u64),
     SkippableBox(u64),
-    /// Buffering a jxli box: (remaining bytes, accumulated content).
-    BufferingFrameIndex(u64, Vec<u8>),
+    /// Buffering a jxli or Exif box: (box type, remaining bytes, accumulated content).
+    BufferingBox(ContainerBoxType, u64, Vec<u8>),
 }
 
 enum CodestreamBoxType {
@@ -33,10 +37,21 @@ pub(super) struct BoxParser {
     pub(super) box_buffer: SmallBuffer,
     state: ParseState,
     box_type: CodestreamBoxType,
+    /// Whether the current codestream extends to the end of the file, as bare codestreams and
+    /// boxes of size 0 do.
+    codestream_to_end: bool,
     /// Parsed frame index box, if present in the file.
     pub(super) frame_index: Option<FrameIndexBox>,
+    /// Image size declared by an Exif box before the codestream, if any.
+    pub(super) exif_image_size: Option<(usize, usize)>,
     /// Total file bytes consumed from the underlying input.
     pub(super) total_file_consumed: u64,
+    /// Total codestream bytes consumed, excluding container boxes.
+    pub(super) total_codestream_consumed: u64,
+    /// Number of bytes of container boxes, such as the rest of a box that is being skipped, that
+    /// were missing from the input before the next codestream byte, the last time more
+    /// codestream was requested.
+    pub(super) missing_box_bytes: usize,
 }
 
 impl BoxParser {
@@ -45,8 +60,12 @@ impl BoxParser {
             box_buffer: SmallBuffer::new(128),
             state: ParseState::SignatureNeeded,
             box_type: CodestreamBoxType::None,
+            codestream_to_end: false,
             frame_index: None,
+            exif_image_size: None,
             total_file_consumed: 0,
+            total_codestream_consumed: 0,
+            missing_box_bytes: 0,
         }
     }
 
@@ -56,7 +75,18 @@ impl BoxParser {
     // Returns the number of codestream bytes that will be available to be read after this call,
     // including any bytes in self.box_buffer.
     // Might return `u64::MAX`, indicating that the rest of the file is codestream.
+    // Fails with `Error::OutOfBounds` if container bytes are missing, whose number is also kept
+    // in `self.missing_box_bytes`.
     pub(super) fn get_more_codestream(&mut self, input: &mut dyn JxlBitstreamInput) -> Result<u64> {
+        let result = self.read_box_headers(input);
+        self.missing_box_bytes = match result {
+            Err(Error::OutOfBounds(missing)) => missing,
+            _ => 0,
+        };
+        result
+    }
+
+    fn read_box_headers(&mut self, input: &mut dyn JxlBitstreamInput) -> Result<u64> {
         loop {
             match self.state.clone() {
                 ParseState::SignatureNeeded => {
@@ -66,6 +96,7 @@ impl BoxParser {
                         None => return Err(Error::InvalidSignature),
                         Some(JxlSignatureType::Codestream) => {
                             self.state = ParseState::CodestreamBox(u64::MAX);
+                            self.codestream_to_end = true;
                             return Ok(u64::MAX);
                         }
                         Some(JxlSignatureType::Container) => {
@@ -78,8 +109,11 @@ impl BoxParser {
                 ParseState::CodestreamBox(b) => {
                     return Ok(b);
                 }
+                ParseState::SkippableBox(0) => {
+                    self.state = ParseState::BoxNeeded;
+                }
                 ParseState::SkippableBox(mut s) => {
-                    let num = s.min(usize::MAX as u64) as usize;
+                    let num = saturating_usize(s);
                     let skipped = if !self.box_buffer.is_empty() {
                         self.box_buffer.consume(num)
                     } else {
@@ -88,7 +122,9 @@ impl BoxParser {
                         skipped
                     };
                     if skipped == 0 {
-                        return Err(Error::OutOfBounds(num));
+                        // Boxes that extend to the end of the file have no known size.
+                        let missing = if s == u64::MAX { 0 } else { num };
+                        return Err(Error::OutOfBounds(missing));
                     }
                     s -= skipped as u64;
                     if s == 0 {
@@ -97,9 +133,11 @@ impl BoxParser {
                         self.state = ParseState::SkippableBox(s);
                     }
                 }
-                ParseState::BufferingFrameIndex(mut remaining, mut buf) => {
-                    let num = remaining.min(usize::MAX as u64) as usize;
-                    if !self.box_buffer.is_empty() {
+                ParseState::BufferingBox(ty, mut remaining, mut buf) => {
+                    let num = saturating_usize(remaining);
+                    if remaining == 0 {
+                        // Empty boxes have nothing to read.
+                    } else if !self.box_buffer.is_empty() {
                         let take = num.min(self.box_buffer.len());
                         buf.extend_from_slice(&self.box_buffer[..take]);
                         self.box_buffer.consume(take);
@@ -116,11 +154,14 @@ impl BoxParser {
                         remaining -= read as u64;
                     }
                     if remaining == 0 {
-                        // Parse the buffered frame index box.
-                        self.frame_index = Some(FrameIndexBox::parse(&buf)?);
+                        if ty == ContainerBoxType::FRAME_INDEX {
+                            self.frame_index = Some(FrameIndexBox::parse(&buf)?);
+                        } else {
+                            self.exif_image_size = exif::declared_image_size(&buf);
+                        }
                         self.state = ParseState::BoxNeeded;
                     } else {
-                        self.state = ParseState::BufferingFrameIndex(remaining, buf);
+                        self.state = ParseState::BufferingBox(ty, remaining, buf);
                     }
                 }
                 ParseState::BoxNeeded => {
@@ -130,12 +171,18 @@ impl BoxParser {
                         [0, 0, 0, 1, ..] => 16,
                         _ => 8,
                     };
-                    if self.box_buffer.len() <= min_len {
+                    if self.box_buffer.len() < min_len {
                         return Err(Error::OutOfBounds(min_len - self.box_buffer.len()));
                     }
                     let ty: [_; 4] = self.box_buffer[4..8].try_into().unwrap();
-                    let extra_len = if &ty == b"jxlp" { 4 } else { 0 };
-                    if self.box_buffer.len() <= min_len + extra_len {
+                    // jxlp boxes start with their index, and brob boxes with the type of the
+                    // compressed box.
+                    let extra_len = if &ty == b"jxlp" || &ty == b"brob" {
+                        4
+                    } else {
+                        0
+                    };
+                    if self.box_buffer.len() < min_len + extra_len {
                         return Err(Error::OutOfBounds(
                             min_len + extra_len - self.box_buffer.len(),
                         ));
@@ -146,14 +193,28 @@ impl BoxParser {
                         }
                         _ => u32::from_be_bytes(self.box_buffer[0..4].try_into().unwrap()) as u64,
                     };
-                    // Per JXL spec: jxlc box with length 0 has special meaning "extends to EOF"
-                    let content_len = if box_len == 0 && (&ty == b"jxlp" || &ty == b"jxlc") {
+                    // Boxes of size 0 extend to the end of the file, so they must be the last box,
+                    // which other boxes can only be once the codestream is complete. The box
+                    // after the codestream is read when the codestream box ends.
+                    let codestream_complete = matches!(
+                        self.box_type,
+                        CodestreamBoxType::Jxlc | CodestreamBoxType::LastJxlp
+                    );
+                    let content_len = if box_len == 0 {
+                        if &ty != b"jxlp" && &ty != b"jxlc" && !codestream_complete {
+                            return Err(Error::LastBoxBeforeCodestreamEnd(
+                                String::from_utf8_lossy(&ty).into_owned(),
+                            ));
+                        }
                         u64::MAX
                     } else {
-                        if box_len <= (min_len + extra_len) as u64 {
+                        let header_len = (min_len + extra_len) as u64;
+                        // Other boxes may be empty, but codestream boxes must have contents.
+                        let is_codestream = &ty == b"jxlp" || &ty == b"jxlc";
+                        if box_len < header_len || (box_len == header_len && is_codestream) {
                             return Err(Error::InvalidBox);
                         }
-                        box_len - min_len as u64 - extra_len as u64
+                        box_len - header_len
                     };
                     match &ty {
                         b"jxlc" => {
@@ -165,6 +226,7 @@ impl BoxParser {
                             }
                             self.box_type = CodestreamBoxType::Jxlc;
                             self.state = ParseState::CodestreamBox(content_len);
+                            self.codestream_to_end = content_len == u64::MAX;
                         }
                         b"jxlp" => {
                             let index = u32::from_be_bytes(
@@ -188,21 +250,44 @@ impl BoxParser {
                                 CodestreamBoxType::Jxlp(idx)
                             };
                             self.state = ParseState::CodestreamBox(content_len);
+                            self.codestream_to_end = content_len == u64::MAX;
                         }
                         b"jxli" => {
-                            if content_len == u64::MAX {
-                                return Err(Error::InvalidBox);
-                            }
                             // Reasonable size limit for a frame index box (16 MB).
                             if content_len > 16 * 1024 * 1024 {
                                 self.state = ParseState::SkippableBox(content_len);
                             } else {
-                                self.state = ParseState::BufferingFrameIndex(
+                                self.state = ParseState::BufferingBox(
+                                    ContainerBoxType::FRAME_INDEX,
                                     content_len,
                                     Vec::with_capacity(content_len as usize),
                                 );
                             }
                         }
+                        // Only Exif boxes before the codestream can be compared with its size.
+                        b"Exif"
+                            if matches!(self.box_type, CodestreamBoxType::None)
+                                && self.exif_image_size.is_none()
+                                && content_len <= MAX_EXIF_SIZE =>
+                        {
+                            self.state = ParseState::BufferingBox(
+                                ContainerBoxType::EXIF,
+                                content_len,
+                                Vec::with_capacity(content_len as usize),
+                            );
+                        }
+                        b"brob" => {
+                            let inner: [u8; 4] =
+                                self.box_buffer[min_len..min_len + 4].try_into().unwrap();
+                            if !ContainerBoxType(inner).can_be_compressed() {
+                                return Err(Error::InvalidCompressedBox(
+                                    String::from_utf8_lossy(&inner).into_owned(),
+                                ));
+                            }
+                            // Compressed metadata is not used for decoding, so it is skipped
+                            // without being decompressed.
+                            self.state = ParseState::SkippableBox(content_len);
+                        }
                         _ => {
                             self.state = ParseState::SkippableBox(content_len);
                         }
@@ -213,6 +298,27 @@ impl BoxParser {
         }
     }
 
+    /// Returns whether the current codestream box continues after the `buffered` bytes the
+    /// codestream parser has read ahead, with bytes that are already available, without reading
+    /// them. Codestream in later `jxlp` boxes is not detected, and neither are bytes after bare
+    /// codestreams or boxes that extend to the end of the file, whose length is unknown: they may
+    /// be followed by unrelated data, such as that of a file the codestream is embedded in.
+    pub(super) fn has_more_codestream(
+        &mut self,
+        input: &mut dyn JxlBitstreamInput,
+        buffered: usize,
+    ) -> Result<bool> {
+        if self.codestream_to_end {
+            return Ok(false);
+        }
+        match self.state {
+            ParseState::CodestreamBox(remaining) if remaining > 0 => {
+                Ok(buffered > 0 || !self.box_buffer.is_empty() || input.available_bytes()? > 0)
+            }
+            _ => Ok(buffered > 0),
+        }
+    }
+
     /// Accounts file bytes consumed directly by codestream parser reads/skips.
     pub(super) fn mark_file_consumed(&mut self, amount: usize) {
         self.total_file_consumed += amount as u64;
@@ -230,17 +336,200 @@ impl BoxParser {
     pub(super) fn reset_for_codestream_seek(&mut self, remaining: u64) {
         self.box_buffer = SmallBuffer::new(128);
         self.state = ParseState::CodestreamBox(remaining);
+        self.codestream_to_end = remaining == u64::MAX;
         // Keep frame_index unchanged.
     }
 
-    pub(super) fn consume_codestream(&mut self, amount: u64) {
+    pub(super) fn consume_codestream(&mut self, amount: u64) -> Result<()> {
+        self.total_codestream_consumed += amount;
         if let ParseState::CodestreamBox(cb) = &mut self.state {
-            *cb = cb.checked_sub(amount).unwrap();
+            *cb = cb
+                .checked_sub(amount)
+                .ok_or_else(|| Error::internal("consumed more than the codestream box"))?;
             if *cb == 0 {
                 self.state = ParseState::BoxNeeded;
             }
         } else if amount != 0 {
-            unreachable!()
+            return Err(Error::internal(
+                "consumed codestream outside of a codestream box",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Input made of data and of runs of zeros that are too long to be stored, as stand-ins for
+    /// the contents of boxes of more than 4GB.
+    struct SparseInput {
+        parts: VecDeque<(Vec<u8>, u64)>,
+    }
+
+    impl SparseInput {
+        fn new(parts: &[(&[u8], u64)]) -> Self {
+            Self {
+                parts: parts.iter().map(|(d, z)| (d.to_vec(), *z)).collect(),
+            }
+        }
+    }
+
+    impl JxlBitstreamInput for SparseInput {
+        fn available_bytes(&mut self) -> std::io::Result<usize> {
+            Ok(self
+                .parts
+                .iter()
+                .map(|(d, z)| saturating_usize(d.len() as u64 + z))
+                .fold(0, usize::saturating_add))
         }
+
+        fn read(&mut self, bufs: &mut [IoSliceMut]) -> std::io::Result<usize> {
+            let mut read = 0;
+            for buf in bufs.iter_mut() {
+                let mut pos = 0;
+                while pos < buf.len() {
+                    let Some((data, zeros)) = self.parts.front_mut() else {
+                        return Ok(read + pos);
+                    };
+                    let n = if !data.is_empty() {
+                        let n = data.len().min(buf.len() - pos);
+                        buf[pos..pos + n].copy_from_slice(&data[..n]);
+                        data.drain(..n);
+                        n
+                    } else {
+                        let n = saturating_usize(*zeros).min(buf.len() - pos);
+                        buf[pos..pos + n].fill(0);
+                        *zeros -= n as u64;
+                        n
+                    };
+                    if data.is_empty() && *zeros == 0 {
+                        self.parts.pop_front();
+                    }
+                    pos += n;
+                }
+                read += pos;
+            }
+            Ok(read)
+        }
+
+        fn skip(&mut self, bytes: usize) -> std::io::Result<usize> {
+            let Some((data, zeros)) = self.parts.front_mut() else {
+                return Ok(0);
+            };
+            if !data.is_empty() {
+                let n = data.len().min(bytes);
+                data.drain(..n);
+                return Ok(n);
+            }
+            let n = saturating_usize(*zeros).min(bytes);
+            *zeros -= n as u64;
+            if *zeros == 0 {
+                self.parts.pop_front();
+            }
+            Ok(n)
+        }
+    }
+
+    fn extended_box(ty: &[u8; 4], payload_len: u64) -> Vec<u8> {
+        let mut header = vec![0, 0, 0, 1];
+        header.extend_from_slice(ty);
+        header.extend_from_slice(&(16 + payload_len).to_be_bytes());
+        header
+    }
+
+    const CODESTREAM_START: [u8; 4] = [0xff, 0x0a, 0xfa, 0x00];
+
+    #[test]
+    fn skips_boxes_larger_than_4gb() -> Result<()> {
+        let skipped = (1u64 << 33) + 7;
+        let signature = JxlSignatureType::Container.signature();
+        let free = extended_box(b"free", skipped);
+        let mut jxlc = vec![0, 0, 0, 12];
+        jxlc.extend_from_slice(b"jxlc");
+        jxlc.extend_from_slice(&CODESTREAM_START);
+        let mut input = SparseInput::new(&[(signature, 0), (&free, skipped), (&jxlc, 0)]);
+
+        let mut parser = BoxParser::new();
+        assert_eq!(parser.get_more_codestream(&mut input)?, 4);
+        assert_eq!(&parser.box_buffer[..], &CODESTREAM_START);
+        let header_bytes = (signature.len() + free.len() + 8) as u64;
+        assert_eq!(
+            parser.total_file_consumed - parser.box_buffer.len() as u64,
+            header_bytes + skipped
+        );
+        Ok(())
+    }
+
+    fn small_box(ty: &[u8; 4], declared_len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = declared_len.to_be_bytes().to_vec();
+        buf.extend_from_slice(ty);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn truncated_unknown_box_needs_its_remaining_bytes() -> Result<()> {
+        let signature = JxlSignatureType::Container.signature();
+        let empty = small_box(b"free", 8, &[]);
+        let unknown = small_box(b"abcd", 8 + 300, &[7; 200]);
+        let mut parser = BoxParser::new();
+        let mut input = SparseInput::new(&[(signature, 0), (&empty, 0), (&unknown, 0)]);
+        assert!(matches!(
+            parser.get_more_codestream(&mut input),
+            Err(Error::OutOfBounds(100))
+        ));
+        assert_eq!(parser.missing_box_bytes, 100);
+
+        let jxlc = small_box(b"jxlc", 12, &CODESTREAM_START);
+        let mut input = SparseInput::new(&[(&[7; 40], 0)]);
+        assert!(matches!(
+            parser.get_more_codestream(&mut input),
+            Err(Error::OutOfBounds(60))
+        ));
+        let mut input = SparseInput::new(&[(&[7; 60], 0), (&jxlc[..5], 0)]);
+        assert!(matches!(
+            parser.get_more_codestream(&mut input),
+            Err(Error::OutOfBounds(3))
+        ));
+        let mut input = SparseInput::new(&[(&jxlc[5..], 0)]);
+        assert_eq!(parser.get_more_codestream(&mut input)?, 4);
+        assert_eq!(parser.missing_box_bytes, 0);
+        assert_eq!(&parser.box_buffer[..], &CODESTREAM_START);
+        Ok(())
+    }
+
+    #[test]
+    fn box_extending_to_the_end_before_the_codestream_is_rejected() {
+        let signature = JxlSignatureType::Container.signature();
+        let unknown = small_box(b"abcd", 0, &[1, 2, 3]);
+        let mut input = SparseInput::new(&[(signature, 0), (&unknown, 0)]);
+        assert!(matches!(
+            BoxParser::new().get_more_codestream(&mut input),
+            Err(Error::LastBoxBeforeCodestreamEnd(ty)) if ty == "abcd"
+        ));
+    }
+
+    #[test]
+    fn codestream_boxes_larger_than_4gb() -> Result<()> {
+        let payload_len = (1u64 << 32) + 2;
+        let signature = JxlSignatureType::Container.signature();
+        let jxlc = extended_box(b"jxlc", payload_len);
+        let mut input = SparseInput::new(&[
+            (signature, 0),
+            (&jxlc, 0),
+            (&CODESTREAM_START, payload_len - 4),
+        ]);
+
+        let mut parser = BoxParser::new();
+        assert_eq!(parser.get_more_codestream(&mut input)?, payload_len);
+        parser.consume_codestream(payload_len - 1)?;
+        assert_eq!(parser.get_more_codestream(&mut input)?, 1);
+        parser.consume_codestream(1)?;
+        assert!(matches!(parser.state, ParseState::BoxNeeded));
+        assert_eq!(parser.total_codestream_consumed, payload_len);
+        Ok(())
     }
 }
@@ -0,0 +1,63 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use super::resize::{staging_buffers, write_samples};
+use crate::{
+    api::{BufferRequirement, ExtraChannelProcessor, JxlExtraChannel, JxlOutputBuffer},
+    error::Result,
+    image::{Image, Rect},
+};
+
+/// Renders the extra channels to `f32` staging buffers, so that they can be transformed by the
+/// [`extra_channel_processor`](crate::api::JxlDecoderOptions::extra_channel_processor) once
+/// their frame is complete.
+#[derive(Default)]
+pub(super) struct ExtraChannelStaging {
+    /// Rendering of each extra channel output buffer.
+    staging: Vec<Image<f32>>,
+}
+
+impl ExtraChannelStaging {
+    /// Returns buffers with the geometry of `requirements`, which describe the extra channel
+    /// output buffers, to render the frame to.
+    pub(super) fn staging_buffers(
+        &mut self,
+        requirements: &[BufferRequirement],
+    ) -> Result<Vec<JxlOutputBuffer<'_>>> {
+        staging_buffers(&mut self.staging, requirements)
+    }
+
+    /// Returns the rendering of each extra channel output buffer.
+    pub(super) fn staging_mut(&mut self) -> &mut [Image<f32>] {
+        &mut self.staging
+    }
+
+    /// Converts the staging buffers to `buffers`, which have the geometry of `requirements`.
+    pub(super) fn write_output(
+        &self,
+        buffers: &mut [JxlOutputBuffer],
+        requirements: &[BufferRequirement],
+    ) {
+        for ((staging, buffer), req) in self.staging.iter().zip(buffers).zip(requirements) {
+            write_samples(staging, req.data_type, buffer);
+        }
+    }
+}
+
+/// Calls `processor` on each of `channels`, given with the index of its extra channel in
+/// `extra_channels`.
+pub(super) fn process_extra_channels<'a>(
+    processor: &ExtraChannelProcessor,
+    extra_channels: &[JxlExtraChannel],
+    channels: impl Iterator<Item = (usize, &'a mut Image<f32>)>,
+) {
+    for (index, image) in channels {
+        let rect = Rect {
+            origin: (0, 0),
+            size: image.size(),
+        };
+        processor(index, &extra_channels[index], &mut image.get_rect_mut(rect));
+    }
+}
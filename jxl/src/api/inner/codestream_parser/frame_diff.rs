@@ -0,0 +1,179 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+#![allow(unsafe_code)]
+
+use crate::{
+    api::{BufferRequirement, JxlFrameDiff, JxlOutputBuffer},
+    image::Rect,
+};
+
+/// Keeps a copy of the last decoded frame to compute [`JxlFrameDiff`]s.
+#[derive(Default)]
+pub(super) struct FrameDiffer {
+    /// Packed rows of each output buffer of the previous frame.
+    previous: Vec<Vec<u8>>,
+    /// Geometry of the buffers in `previous`.
+    requirements: Vec<BufferRequirement>,
+    /// Per-pixel changed flags for the row being compared.
+    changed: Vec<bool>,
+}
+
+impl FrameDiffer {
+    /// Forgets the previous frame, so that the next one is reported as entirely changed.
+    pub(super) fn reset(&mut self) {
+        self.previous.clear();
+        self.requirements.clear();
+    }
+
+    /// Compares `buffers` with the previous frame, and stores them as the new previous frame.
+    ///
+    /// # Safety
+    /// The caller must guarantee that all the rows of `buffers` have been written.
+    pub(super) unsafe fn update(
+        &mut self,
+        buffers: &[JxlOutputBuffer],
+        requirements: &[BufferRequirement],
+    ) -> JxlFrameDiff {
+        let (width, height) = requirements
+            .first()
+            .map_or((0, 0), |req| (req.width, req.height));
+        if self.requirements != requirements {
+            self.requirements = requirements.to_vec();
+            self.previous = buffers
+                .iter()
+                .zip(requirements)
+                .map(|(buf, req)| {
+                    let bytes_per_row = req.bytes_per_row();
+                    let mut data = vec![0; bytes_per_row * req.height];
+                    for (y, row) in data.chunks_exact_mut(bytes_per_row).enumerate() {
+                        // SAFETY: the caller guarantees that the row has been written.
+                        row.copy_from_slice(unsafe { buf.initialized_row(y) });
+                    }
+                    data
+                })
+                .collect();
+            let changed_rect = (width != 0 && height != 0).then_some(Rect {
+                origin: (0, 0),
+                size: (width, height),
+            });
+            return JxlFrameDiff {
+                changed_rect,
+                changed_pixels: width * height,
+            };
+        }
+
+        self.changed.resize(width, false);
+        let mut changed_pixels = 0;
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for y in 0..height {
+            let mut row_changed = false;
+            for ((buf, req), previous) in buffers.iter().zip(requirements).zip(&mut self.previous) {
+                let bytes_per_row = req.bytes_per_row();
+                // SAFETY: the caller guarantees that the row has been written.
+                let row = unsafe { buf.initialized_row(y) };
+                let previous_row = &mut previous[y * bytes_per_row..(y + 1) * bytes_per_row];
+                // Slice comparison compiles to a vectorized memcmp that stops at the first
+                // difference, which is all we need for the common case of unchanged rows.
+                if row == previous_row {
+                    continue;
+                }
+                if !row_changed {
+                    self.changed.fill(false);
+                    row_changed = true;
+                }
+                let bytes_per_pixel = req.samples_per_pixel * req.data_type.bytes_per_sample();
+                for (changed, (a, b)) in self.changed.iter_mut().zip(
+                    row.chunks_exact(bytes_per_pixel)
+                        .zip(previous_row.chunks_exact(bytes_per_pixel)),
+                ) {
+                    *changed |= a != b;
+                }
+                previous_row.copy_from_slice(row);
+            }
+            if !row_changed {
+                continue;
+            }
+            let first = self.changed.iter().position(|&c| c);
+            let last = self.changed.iter().rposition(|&c| c);
+            let (Some(first), Some(last)) = (first, last) else {
+                continue;
+            };
+            changed_pixels += self.changed[first..=last].iter().filter(|&&c| c).count();
+            bounds = Some(match bounds {
+                None => (first, y, last, y),
+                Some((x0, y0, x1, _)) => (x0.min(first), y0, x1.max(last), y),
+            });
+        }
+
+        JxlFrameDiff {
+            changed_rect: bounds.map(|(x0, y0, x1, y1)| Rect {
+                origin: (x0, y0),
+                size: (x1 - x0 + 1, y1 - y0 + 1),
+            }),
+            changed_pixels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::JxlDataFormat;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    fn diff_frame(differ: &mut FrameDiffer, pixels: &mut [u8]) -> JxlFrameDiff {
+        let requirement = BufferRequirement {
+            width: WIDTH,
+            height: HEIGHT,
+            samples_per_pixel: 3,
+            data_type: JxlDataFormat::U8 { bit_depth: 8 },
+        };
+        let buffers = [JxlOutputBuffer::new(pixels, HEIGHT, WIDTH * 3)];
+        // SAFETY: the buffer was created from initialized memory.
+        unsafe { differ.update(&buffers, &[requirement]) }
+    }
+
+    #[test]
+    fn changed_square() {
+        let mut differ = FrameDiffer::default();
+        let mut pixels: Vec<u8> = (0..WIDTH * HEIGHT * 3).map(|i| (i % 251) as u8).collect();
+
+        let first = diff_frame(&mut differ, &mut pixels);
+        assert_eq!(
+            first.changed_rect,
+            Some(Rect {
+                origin: (0, 0),
+                size: (WIDTH, HEIGHT)
+            })
+        );
+
+        let unchanged = diff_frame(&mut differ, &mut pixels);
+        assert_eq!(unchanged.changed_rect, None);
+        assert_eq!(unchanged.changed_pixels, 0);
+
+        // Only change a 10x10 square, touching a single sample of each pixel.
+        for y in 20..30 {
+            for x in 13..23 {
+                pixels[(y * WIDTH + x) * 3 + 1] ^= 1;
+            }
+        }
+        let changed = diff_frame(&mut differ, &mut pixels);
+        assert_eq!(
+            changed.changed_rect,
+            Some(Rect {
+                origin: (13, 20),
+                size: (10, 10)
+            })
+        );
+        assert_eq!(changed.changed_pixels, 100);
+
+        differ.reset();
+        let after_reset = diff_frame(&mut differ, &mut pixels);
+        assert_eq!(after_reset.changed_pixels, WIDTH * HEIGHT);
+    }
+}
@@ -8,25 +8,58 @@ use std::{
     io::IoSliceMut,
 };
 
+use extra_channels::{ExtraChannelStaging, process_extra_channels};
+use frame_diff::FrameDiffer;
+use non_section::{check_size_limit, new_decoder_state};
+use resize::{ColorOutput, Resizer};
 use sections::SectionState;
+pub(crate) use sections::register_progressive_decoding;
+use sequence::FrameSequence;
 
 #[cfg(test)]
 use crate::api::FrameCallback;
 use crate::{
     api::{
-        JxlBasicInfo, JxlBitstreamInput, JxlColorEncoding, JxlColorProfile, JxlDataFormat,
-        JxlDecoderOptions, JxlOutputBuffer, JxlPixelFormat, VisibleFrameInfo,
-        VisibleFrameSeekTarget,
+        BufferRequirement, CompressionSummary, ConcealedGroup, EntropyCodeInfo,
+        FrameCompressionInfo, JxlBasicInfo, JxlBitstreamInput, JxlColorEncoding, JxlColorProfile,
+        JxlColorProfileMismatch, JxlColorProfileSource, JxlDataFormat, JxlDecoderOptions,
+        JxlExtraChannelType, JxlFrameDiff, JxlOutputBuffer, JxlPixelFormat, JxlSizeMismatch,
+        ModularChannelCheck, ModularStats, OutputLayout, VisibleFrameInfo, VisibleFrameSeekTarget,
         inner::{box_parser::BoxParser, process::SmallBuffer},
     },
-    error::{Error, Result},
-    frame::{DecoderState, Frame, Section},
-    headers::{Animation, FileHeader, frame_header::FrameHeader, toc::IncrementalTocReader},
+    entropy_coding::dump::{
+        set_thread_entropy_dump, set_thread_entropy_frame, take_thread_entropy_codes,
+    },
+    error::{Error, ErrorContext, Result},
+    frame::{
+        DecoderState, Frame, FrameIndices, GroupId, Section,
+        modular::{set_thread_verification, take_thread_modular_checks, take_thread_modular_stats},
+    },
+    headers::{
+        Animation, FileHeader,
+        frame_header::{Encoding, FrameHeader},
+        image_metadata::Orientation,
+        toc::IncrementalTocReader,
+    },
     icc::IncrementalIccReader,
+    image::Rect,
+    render::{
+        resample::ColorSamples,
+        stages::{OutputColorInfo, TransferFunction},
+    },
+    util::{saturating_usize, tracing_wrappers::warn},
 };
 
+/// Minimum size hint while reading headers, which are parsed field by field, so that they are
+/// not requested a few bytes at a time.
+const HEADER_SIZE_HINT: usize = 1024;
+
+mod extra_channels;
+mod frame_diff;
 mod non_section;
+mod resize;
 mod sections;
+mod sequence;
 
 struct SectionBuffer {
     len: usize,
@@ -36,9 +69,10 @@ struct SectionBuffer {
 
 #[derive(Clone, Copy)]
 struct FrameStartInfo {
-    file_offset: usize,
+    file_offset: u64,
     remaining_in_box: u64,
     visible_count_before: usize,
+    frame_indices: FrameIndices,
 }
 
 pub(super) struct CodestreamParser {
@@ -50,8 +84,15 @@ pub(super) struct CodestreamParser {
     pub(super) basic_info: Option<JxlBasicInfo>,
     pub(super) animation: Option<Animation>,
     pub(super) embedded_color_profile: Option<JxlColorProfile>,
+    pub(super) profile_source: Option<JxlColorProfileSource>,
+    pub(super) color_profile_mismatch: Option<JxlColorProfileMismatch>,
+    /// How the image size declared by the container disagrees with the codestream, if it does.
+    pub(super) size_mismatch: Option<JxlSizeMismatch>,
     pub(super) output_color_profile: Option<JxlColorProfile>,
     pub(super) pixel_format: Option<JxlPixelFormat>,
+    /// Luminances and intensity target of the output, to convert it to linear light for
+    /// resizing.
+    output_color_info: Option<OutputColorInfo>,
     xyb_encoded: bool,
     is_gray: bool,
     pub(super) output_color_profile_set_by_user: bool,
@@ -86,16 +127,23 @@ pub(super) struct CodestreamParser {
     hf_global_section: Option<SectionBuffer>,
     // indexed by group, then by pass.
     hf_sections: Vec<Vec<Option<SectionBuffer>>>,
-    // group indices that *might* have new renderable data.
-    candidate_hf_sections: HashSet<usize>,
+    // groups that *might* have new renderable data.
+    candidate_hf_sections: HashSet<GroupId>,
 
-    pub(super) has_more_frames: bool,
+    /// Checks the order of frames, and tells whether more frames follow.
+    sequence: FrameSequence,
 
     header_needed_bytes: Option<u64>,
 
     // --- Frame info tracking (for frame scanning) ---
     /// Collected visible frame info entries.
     pub(super) scanned_frames: Vec<VisibleFrameInfo>,
+    /// Total length of the names kept in `scanned_frames`.
+    scanned_names_size: usize,
+    /// Whether the name of a frame in `scanned_frames` was omitted.
+    omitted_frame_names: bool,
+    /// Compression properties of every non-preview frame, in parse order.
+    pub(super) frame_compression: Vec<FrameCompressionInfo>,
     /// Zero-based visible frame index counter.
     visible_frame_index: usize,
     /// File offsets and visibility info for every non-preview frame (visible
@@ -107,13 +155,53 @@ pub(super) struct CodestreamParser {
     /// For each LF slot, earliest frame index required to reconstruct the
     /// current contents of that slot.
     lf_slot_decode_start: [Option<usize>; DecoderState::NUM_LF_FRAMES],
+    /// Index of the frame being decoded among non-preview frames, or `None` while decoding the
+    /// preview frame. Used to report where errors happened.
+    current_frame_index: Option<usize>,
     /// File byte offset where the current frame header parse started.
     /// Set when we begin parsing a frame header.
-    current_frame_file_offset: usize,
+    current_frame_file_offset: u64,
     /// Remaining codestream bytes in the current box at frame start.
     /// Captured alongside `current_frame_file_offset`.
     current_frame_remaining_in_box: u64,
 
+    /// Previous output frame, kept if `compute_frame_diffs` is enabled.
+    frame_differ: FrameDiffer,
+    /// Difference between the last decoded frame and the one before it.
+    pub(super) frame_diff: Option<JxlFrameDiff>,
+    /// True if the last call to `process_input` completed a visible frame.
+    frame_finished: bool,
+    /// Number of groups outside of the image in the frames decoded since the last visible frame
+    /// was completed.
+    pending_offcanvas_groups: usize,
+    /// Number of groups of the last visible frame, and of the frames it was composited from,
+    /// that were outside of the image and so were not rendered.
+    pub(super) offcanvas_groups: Option<usize>,
+    /// Groups concealed in the frames decoded since the last visible frame was completed.
+    pending_concealed_groups: Vec<ConcealedGroup>,
+    /// Groups of the last visible frame, and of the frames it was composited from, that failed
+    /// to decode and were concealed.
+    pub(super) concealed_groups: Option<Vec<ConcealedGroup>>,
+    /// Modular statistics of the frames decoded since the last visible frame was completed.
+    pending_modular_stats: ModularStats,
+    /// Modular statistics of the last visible frame, if the `timing-stats` feature is enabled.
+    pub(super) modular_stats: Option<ModularStats>,
+    /// Checks of the modular streams decoded since the last visible frame was completed.
+    pending_modular_checks: Vec<ModularChannelCheck>,
+    /// Checks of the modular streams of the last visible frame, if the `verify` feature is
+    /// enabled and [`JxlDecoderOptions::verify_modular`] is set.
+    pub(super) modular_checks: Option<Vec<ModularChannelCheck>>,
+    /// Entropy codes decoded since the last visible frame was completed.
+    pending_entropy_codes: Vec<EntropyCodeInfo>,
+    /// Entropy codes of the last visible frame, if the `debug-tools` feature is enabled and
+    /// [`JxlDecoderOptions::dump_entropy_codes`] is set.
+    pub(super) entropy_codes: Option<Vec<EntropyCodeInfo>>,
+    /// Full-size rendering of the current frame, if `resize_to` is set.
+    resizer: Resizer,
+    /// Full-size rendering of the extra channels of the current frame, if
+    /// `extra_channel_processor` is set and `resize_to` is not.
+    extra_channel_staging: ExtraChannelStaging,
+
     #[cfg(test)]
     pub frame_callback: Option<Box<FrameCallback>>,
     #[cfg(test)]
@@ -129,8 +217,12 @@ impl CodestreamParser {
             basic_info: None,
             animation: None,
             embedded_color_profile: None,
+            profile_source: None,
+            color_profile_mismatch: None,
+            size_mismatch: None,
             output_color_profile: None,
             pixel_format: None,
+            output_color_info: None,
             xyb_encoded: false,
             is_gray: false,
             output_color_profile_set_by_user: false,
@@ -146,21 +238,40 @@ impl CodestreamParser {
             preview_done: false,
             visible_frames_to_skip: 0,
             saved_file_header: None,
-            section_state: SectionState::new(0, 0),
+            section_state: SectionState::new(0, 0, 0),
             lf_global_section: None,
             lf_sections: vec![],
             hf_global_section: None,
             hf_sections: vec![],
             candidate_hf_sections: HashSet::new(),
-            has_more_frames: true,
+            sequence: FrameSequence::default(),
             header_needed_bytes: None,
             scanned_frames: Vec::new(),
+            scanned_names_size: 0,
+            omitted_frame_names: false,
+            frame_compression: Vec::new(),
             visible_frame_index: 0,
             frame_starts: Vec::new(),
+            current_frame_index: None,
             reference_slot_decode_start: [None; DecoderState::MAX_STORED_FRAMES],
             lf_slot_decode_start: [None; DecoderState::NUM_LF_FRAMES],
             current_frame_file_offset: 0,
             current_frame_remaining_in_box: u64::MAX,
+            frame_differ: FrameDiffer::default(),
+            frame_diff: None,
+            frame_finished: false,
+            pending_offcanvas_groups: 0,
+            offcanvas_groups: None,
+            pending_concealed_groups: Vec::new(),
+            concealed_groups: None,
+            pending_modular_stats: ModularStats::default(),
+            modular_stats: None,
+            pending_modular_checks: Vec::new(),
+            modular_checks: None,
+            pending_entropy_codes: Vec::new(),
+            entropy_codes: None,
+            resizer: Resizer::default(),
+            extra_channel_staging: ExtraChannelStaging::default(),
             #[cfg(test)]
             frame_callback: None,
             #[cfg(test)]
@@ -176,9 +287,10 @@ impl CodestreamParser {
         }
     }
 
-    /// Record frame info for the just-parsed frame.
+    /// Record frame info for the just-parsed frame, keeping its name if the names recorded so far
+    /// leave room for it in `max_names_size` bytes.
     /// Called after process_non_section() creates a Frame, for frame scanning.
-    fn record_frame_info(&mut self) {
+    fn record_frame_info(&mut self, max_names_size: usize) {
         let frame = match self.frame.as_ref() {
             Some(f) => f,
             None => return,
@@ -186,11 +298,27 @@ impl CodestreamParser {
         let header = frame.header();
 
         let current_frame_index = self.frame_starts.len();
+        self.current_frame_index = Some(current_frame_index);
         let is_visible = header.is_visible();
+        let modular = header.encoding == Encoding::Modular;
+        self.frame_compression.push(FrameCompressionInfo {
+            is_visible,
+            modular,
+            lossless: modular
+                && !self.xyb_encoded
+                && !header.do_ycbcr
+                && header.upsampling == 1
+                && header.ec_upsampling.iter().all(|u| *u == 1)
+                && !header.restoration_filter.gab
+                && header.restoration_filter.epf_iters == 0
+                && !header.has_noise(),
+            section_bytes: frame.total_bytes_in_toc(),
+        });
         self.frame_starts.push(FrameStartInfo {
             file_offset: self.current_frame_file_offset,
             remaining_in_box: self.current_frame_remaining_in_box,
             visible_count_before: self.visible_frame_index,
+            frame_indices: frame.frame_indices_before(),
         });
 
         let mut decode_start_frame_index = current_frame_index;
@@ -219,7 +347,7 @@ impl CodestreamParser {
         }
 
         for (slot, used) in used_reference_slots.iter().enumerate() {
-            if *used && let Some(dep_start) = self.reference_slot_decode_start[slot] {
+            if let (true, Some(dep_start)) = (*used, self.reference_slot_decode_start[slot]) {
                 decode_start_frame_index = decode_start_frame_index.min(dep_start);
             }
         }
@@ -256,9 +384,21 @@ impl CodestreamParser {
                 visible_frames_to_skip: self
                     .visible_frame_index
                     .saturating_sub(decode_start.visible_count_before),
+                frame_indices: decode_start.frame_indices,
             };
             let is_keyframe = seek_target.visible_frames_to_skip == 0;
 
+            let name_fits =
+                header.name.len() <= max_names_size.saturating_sub(self.scanned_names_size);
+            if name_fits {
+                self.scanned_names_size += header.name.len();
+            } else if !self.omitted_frame_names {
+                warn!(
+                    max_names_size,
+                    "frame names take more than the maximum size, omitting them"
+                );
+                self.omitted_frame_names = true;
+            }
             self.scanned_frames.push(VisibleFrameInfo {
                 index: self.visible_frame_index,
                 duration_ms,
@@ -267,7 +407,8 @@ impl CodestreamParser {
                 is_last: header.is_last,
                 is_keyframe,
                 seek_target,
-                name: header.name.clone(),
+                name_len: header.name.len(),
+                name: name_fits.then(|| header.name.as_str().into()),
             });
 
             self.visible_frame_index += 1;
@@ -295,6 +436,274 @@ impl CodestreamParser {
         }
     }
 
+    /// Computes the geometry of the output buffers for the current pixel format.
+    ///
+    /// If a visible frame is being decoded, this takes into account the size of that frame
+    /// (i.e. for preview frames); otherwise, buffers are assumed to cover the whole image.
+    /// Output that is resized always has the size it is resized to.
+    pub(super) fn output_buffer_requirements(
+        &self,
+        decode_options: &JxlDecoderOptions,
+    ) -> Option<Vec<BufferRequirement>> {
+        self.buffer_requirements(
+            self.pixel_format.as_ref()?,
+            decode_options.resize_to,
+            decode_options,
+        )
+    }
+
+    /// Computes the geometry of buffers for `pixel_format` in the orientation and layout of
+    /// `decode_options`, with the size of the frame being decoded unless `size` is set.
+    fn buffer_requirements(
+        &self,
+        pixel_format: &JxlPixelFormat,
+        size: Option<(usize, usize)>,
+        decode_options: &JxlDecoderOptions,
+    ) -> Option<Vec<BufferRequirement>> {
+        let size = match (size, decode_options.crop) {
+            (Some(size), _) => size,
+            (None, Some(crop)) => crop.size,
+            (None, None) => {
+                self.frame_output_size(self.frame.as_ref().map(|f| f.header()), decode_options)?
+            }
+        };
+        let size = match decode_options.output_layout {
+            OutputLayout::RowMajor => size,
+            OutputLayout::ColumnMajor => (size.1, size.0),
+        };
+        let color = pixel_format
+            .color_data_format
+            .map(|data_type| BufferRequirement {
+                width: size.0,
+                height: size.1,
+                samples_per_pixel: pixel_format.color_type.samples_per_pixel(),
+                data_type,
+            });
+        let extra = pixel_format
+            .extra_channel_format
+            .iter()
+            .flatten()
+            .map(|data_type| BufferRequirement {
+                width: size.0,
+                height: size.1,
+                samples_per_pixel: 1,
+                data_type: *data_type,
+            });
+        Some(color.into_iter().chain(extra).collect())
+    }
+
+    /// Returns the size in display orientation of the frame with the given header when it is not
+    /// resized or cropped, or of the image without a frame.
+    fn frame_output_size(
+        &self,
+        header: Option<&FrameHeader>,
+        decode_options: &JxlDecoderOptions,
+    ) -> Option<(usize, usize)> {
+        let basic_info = self.basic_info.as_ref()?;
+        Some(match header {
+            // Frames that need blending are extended to the image dimensions.
+            Some(header) if header.is_visible() && !header.needs_blending() => {
+                match decode_options.adjust_orientation {
+                    true => basic_info.orientation.map_size(header.size_upsampled()),
+                    false => header.size_upsampled(),
+                }
+            }
+            _ => basic_info.size,
+        })
+    }
+
+    /// Returns the part of the frame with the given header that is written to the output
+    /// buffers, in image coordinates before the orientation is applied, if the output is
+    /// cropped. Fails if the crop does not lie within the frame. Preview frames that are skipped
+    /// are not written, so they are not cropped either.
+    fn frame_crop(
+        &self,
+        header: &FrameHeader,
+        decode_options: &JxlDecoderOptions,
+    ) -> Result<Option<Rect>> {
+        let Some(crop) = decode_options.crop else {
+            return Ok(None);
+        };
+        let is_preview_frame = !self.preview_done
+            && self
+                .basic_info
+                .as_ref()
+                .is_some_and(|info| info.preview_size.is_some());
+        if is_preview_frame && decode_options.skip_preview {
+            return Ok(None);
+        }
+        let size = self
+            .frame_output_size(Some(header), decode_options)
+            .unwrap();
+        let end = (
+            crop.origin.0.checked_add(crop.size.0),
+            crop.origin.1.checked_add(crop.size.1),
+        );
+        if crop.size.0 == 0
+            || crop.size.1 == 0
+            || end.0.is_none_or(|end| end > size.0)
+            || end.1.is_none_or(|end| end > size.1)
+        {
+            return Err(Error::InvalidCrop(
+                crop.size.0,
+                crop.size.1,
+                crop.origin.0,
+                crop.origin.1,
+                size.0,
+                size.1,
+            ));
+        }
+        let orientation = match decode_options.adjust_orientation {
+            true => self.basic_info.as_ref().unwrap().orientation,
+            false => Orientation::Identity,
+        };
+        Ok(Some(orientation.inverse().display_rect(crop, size)))
+    }
+
+    /// Returns the pixel format that frames are rendered with: the requested one, or `f32`
+    /// samples for the buffers that are resampled or post-processed afterwards.
+    fn render_pixel_format(&self, decode_options: &JxlDecoderOptions) -> JxlPixelFormat {
+        let pixel_format = self.pixel_format.as_ref().unwrap();
+        let resized = decode_options.resize_to.is_some();
+        let extra_channels_staged = resized || decode_options.extra_channel_processor.is_some();
+        if !extra_channels_staged {
+            return pixel_format.clone();
+        }
+        JxlPixelFormat {
+            color_type: pixel_format.color_type,
+            color_data_format: pixel_format.color_data_format.map(|format| {
+                if resized {
+                    JxlDataFormat::f32()
+                } else {
+                    format
+                }
+            }),
+            extra_channel_format: pixel_format
+                .extra_channel_format
+                .iter()
+                .map(|format| format.map(|_| JxlDataFormat::f32()))
+                .collect(),
+        }
+    }
+
+    /// Returns the index of the extra channel of each output buffer, or `None` for the color
+    /// buffer.
+    fn output_buffer_channels(&self) -> Vec<Option<usize>> {
+        let pixel_format = self.pixel_format.as_ref().unwrap();
+        let color = pixel_format.color_data_format.map(|_| None);
+        let extra = (pixel_format.extra_channel_format.iter().enumerate())
+            .filter(|(_, format)| format.is_some())
+            .map(|(index, _)| Some(index));
+        color.into_iter().chain(extra).collect()
+    }
+
+    /// Describes the samples of the color output buffer, if any, for resampling.
+    fn color_output(&self, decode_options: &JxlDecoderOptions) -> Option<ColorOutput> {
+        let pixel_format = self.pixel_format.as_ref()?;
+        pixel_format.color_data_format?;
+        let basic_info = self.basic_info.as_ref()?;
+        let alpha = basic_info
+            .extra_channels
+            .iter()
+            .position(|ec| ec.ec_type == JxlExtraChannelType::Alpha);
+        let alpha_associated = alpha.is_some_and(|i| basic_info.extra_channels[i].alpha_associated);
+        // Without alpha in the color buffer, the main alpha channel may have its own buffer,
+        // after the color buffer and those of the preceding extra channels.
+        let alpha_buffer = alpha
+            .filter(|&i| {
+                !pixel_format.color_type.has_alpha()
+                    && pixel_format.extra_channel_format[i].is_some()
+            })
+            .map(|i| {
+                1 + pixel_format.extra_channel_format[..i]
+                    .iter()
+                    .flatten()
+                    .count()
+            });
+        let transfer_function = self
+            .output_color_profile
+            .as_ref()?
+            .transfer_function()
+            .zip(self.output_color_info.as_ref())
+            .map(|(tf, info)| {
+                TransferFunction::from_api_tf(tf, info.intensity_target, info.luminances)
+            });
+        // Colors in the color buffer are only premultiplied by alpha in a separate buffer if the
+        // image stores them that way.
+        let premultiplied = match alpha_buffer {
+            Some(_) => alpha_associated,
+            None => {
+                pixel_format.color_type.has_alpha()
+                    && (decode_options.premultiply_output || alpha_associated)
+            }
+        };
+        Some(ColorOutput {
+            samples: ColorSamples {
+                num_channels: pixel_format.color_type.samples_per_pixel()
+                    + alpha_buffer.is_some() as usize,
+                has_alpha: pixel_format.color_type.has_alpha() || alpha_buffer.is_some(),
+                premultiplied,
+                transfer_function,
+            },
+            alpha_buffer,
+        })
+    }
+
+    /// Finalizes a fully decoded or skipped frame, keeping the decoder state for the next one.
+    fn finalize_frame(
+        &mut self,
+        mut frame: Frame,
+        decode_options: &JxlDecoderOptions,
+    ) -> Result<()> {
+        self.pending_offcanvas_groups += frame.num_skipped_groups();
+        self.pending_concealed_groups
+            .extend_from_slice(frame.concealed_groups());
+        if let Some(decoder_state) = frame.finalize()? {
+            self.decoder_state = Some(decoder_state);
+        } else if self.sequence.in_preview() {
+            // The preview frame is marked as last, but the main frames follow it. Recreate the
+            // decoder state from the saved file header for them.
+            if let Some(file_header) = self.saved_file_header.take() {
+                self.decoder_state = Some(new_decoder_state(file_header, decode_options));
+            }
+        } else {
+            self.sequence.finish_last_frame();
+        }
+        Ok(())
+    }
+
+    pub(super) fn has_more_frames(&self) -> bool {
+        self.sequence.has_more_frames()
+    }
+
+    /// Handles the input ending between frames, before the frame marked as last.
+    pub(super) fn end_input(&mut self, decode_options: &JxlDecoderOptions) -> Result<()> {
+        self.sequence.end_input(decode_options.permissive)
+    }
+
+    /// Summarizes the compression of all frames, once the headers of the last frame are parsed.
+    pub(super) fn compression_summary(&self, codestream_bytes: u64) -> Option<CompressionSummary> {
+        if self.sequence.has_more_frames() {
+            return None;
+        }
+        let (xsize, ysize) = self.basic_info.as_ref()?.size;
+        let visible_frames = self
+            .frame_compression
+            .iter()
+            .filter(|f| f.is_visible)
+            .count()
+            .max(1);
+        let num_pixels = (xsize * ysize * visible_frames) as f64;
+        Some(CompressionSummary {
+            all_modular_lossless: !self.frame_compression.is_empty()
+                && self.frame_compression.iter().all(|f| f.lossless),
+            any_vardct: self.frame_compression.iter().any(|f| !f.modular),
+            xyb_encoded: self.xyb_encoded,
+            bits_per_pixel: (codestream_bytes as f64 * 8.0 / num_pixels) as f32,
+            per_frame: self.frame_compression.clone(),
+        })
+    }
+
     /// Returns the number of passes that are fully completed across all groups.
     pub(super) fn num_completed_passes(&self) -> usize {
         self.section_state.num_completed_passes()
@@ -318,13 +727,21 @@ impl CodestreamParser {
 
     /// Resets frame-level state for seeking to a new frame.
     ///
-    /// Preserves: file_header, decoder_state (including reference frames),
-    /// basic_info, animation, color profiles, pixel_format, xyb_encoded,
-    /// is_gray, output_color_profile_set_by_user, preview_done.
+    /// Preserves: file_header, decoder_state (including reference frames, but
+    /// with the frame counts of the seek point), basic_info, animation, color
+    /// profiles, pixel_format, xyb_encoded, is_gray,
+    /// output_color_profile_set_by_user, preview_done.
     ///
     /// Clears: frame_header, toc_parser, frame, all section buffers,
     /// non_section_buf, and processing flags.
-    pub(super) fn start_new_frame(&mut self, visible_frames_to_skip: usize) {
+    pub(super) fn start_new_frame(
+        &mut self,
+        visible_frames_to_skip: usize,
+        frame_indices: FrameIndices,
+    ) {
+        if let Some(decoder_state) = &mut self.decoder_state {
+            decoder_state.set_frame_indices(frame_indices);
+        }
         self.frame_header = None;
         self.toc_parser = None;
         self.frame = None;
@@ -335,14 +752,26 @@ impl CodestreamParser {
         self.skip_sections = false;
         self.process_without_output = false;
         self.visible_frames_to_skip = visible_frames_to_skip;
-        self.section_state = SectionState::new(0, 0);
+        self.section_state = SectionState::new(0, 0, 0);
         self.lf_global_section = None;
         self.lf_sections.clear();
         self.hf_global_section = None;
         self.hf_sections.clear();
         self.candidate_hf_sections.clear();
-        self.has_more_frames = true;
+        self.sequence.restart();
         self.header_needed_bytes = None;
+        self.frame_differ.reset();
+        self.frame_diff = None;
+        self.pending_offcanvas_groups = 0;
+        self.offcanvas_groups = None;
+        self.pending_concealed_groups.clear();
+        self.concealed_groups = None;
+        self.pending_modular_stats = ModularStats::default();
+        self.modular_stats = None;
+        self.pending_modular_checks.clear();
+        self.modular_checks = None;
+        self.pending_entropy_codes.clear();
+        self.entropy_codes = None;
     }
 
     pub(super) fn process(
@@ -353,6 +782,7 @@ impl CodestreamParser {
         mut output_buffers: Option<&mut [JxlOutputBuffer]>,
         do_flush: bool,
     ) -> Result<()> {
+        let mut requirements = None;
         if let Some(output_buffers) = &output_buffers {
             let px = self.pixel_format.as_ref().unwrap();
             let expected_len = std::iter::once(&px.color_data_format)
@@ -362,7 +792,275 @@ impl CodestreamParser {
             if output_buffers.len() != expected_len {
                 return Err(Error::WrongBufferCount(output_buffers.len(), expected_len));
             }
+            requirements = self.output_buffer_requirements(decode_options);
+            if let Some(requirements) = &requirements {
+                for (index, (buf, req)) in output_buffers.iter().zip(requirements).enumerate() {
+                    let (actual_bytes_per_row, actual_rows) = buf.byte_size();
+                    if (actual_bytes_per_row, actual_rows) != req.byte_size() {
+                        return Err(Error::OutputBufferMismatch {
+                            index,
+                            actual_bytes_per_row,
+                            actual_rows,
+                            expected_bytes_per_row: req.bytes_per_row(),
+                            expected_rows: req.height,
+                            width: req.width,
+                            height: req.height,
+                            samples_per_pixel: req.samples_per_pixel,
+                            data_type: req.data_type,
+                        });
+                    }
+                }
+            }
+        }
+
+        self.frame_finished = false;
+        box_parser.missing_box_bytes = 0;
+        take_thread_modular_stats();
+        take_thread_modular_checks();
+        set_thread_verification(decode_options.verify_modular);
+        take_thread_entropy_codes();
+        set_thread_entropy_dump(decode_options.dump_entropy_codes);
+        set_thread_entropy_frame(self.current_frame_index);
+        let result = match output_buffers.as_deref_mut() {
+            Some(buffers) if decode_options.resize_to.is_some() => {
+                self.process_resized(box_parser, input, decode_options, buffers, do_flush)
+            }
+            Some(buffers) if decode_options.extra_channel_processor.is_some() => {
+                self.process_extra_channels(box_parser, input, decode_options, buffers, do_flush)
+            }
+            buffers => self.process_input(box_parser, input, decode_options, buffers, do_flush),
+        };
+        if self.frame_finished {
+            self.offcanvas_groups = Some(std::mem::take(&mut self.pending_offcanvas_groups));
+            self.concealed_groups = Some(std::mem::take(&mut self.pending_concealed_groups));
+        }
+        if cfg!(feature = "timing-stats") {
+            self.pending_modular_stats
+                .merge(&take_thread_modular_stats());
+            if self.frame_finished {
+                self.modular_stats = Some(std::mem::take(&mut self.pending_modular_stats));
+            }
+        }
+        set_thread_verification(false);
+        if cfg!(feature = "verify") && decode_options.verify_modular {
+            self.pending_modular_checks
+                .extend(take_thread_modular_checks());
+            if self.frame_finished {
+                self.modular_checks = Some(std::mem::take(&mut self.pending_modular_checks));
+            }
+        }
+        set_thread_entropy_dump(false);
+        if cfg!(feature = "debug-tools") && decode_options.dump_entropy_codes {
+            self.pending_entropy_codes
+                .extend(take_thread_entropy_codes());
+            if self.frame_finished {
+                self.entropy_codes = Some(std::mem::take(&mut self.pending_entropy_codes));
+            }
+        }
+        let compute_frame_diff = self.frame_finished
+            && decode_options.compute_frame_diffs
+            && !decode_options.scan_frames_only;
+        if let (true, Some(buffers), Some(requirements)) =
+            (compute_frame_diff, &output_buffers, &requirements)
+        {
+            // SAFETY: the frame is complete, so all of its pixels were written to the output
+            // buffers.
+            #[allow(unsafe_code)]
+            let diff = unsafe { self.frame_differ.update(buffers, requirements) };
+            self.frame_diff = Some(diff);
+        }
+        // Container bytes that are missing come before the missing codestream.
+        match result {
+            Err(Error::OutOfBounds(missing)) => Err(Error::OutOfBounds(
+                self.size_hint(missing, decode_options.input_readahead)
+                    .saturating_add(box_parser.missing_box_bytes),
+            )),
+            result => result,
+        }
+    }
+
+    /// Turns the number of bytes `missing` to make progress into the size hint of
+    /// `NeedsMoreInput`. Headers are requested at least `HEADER_SIZE_HINT` bytes at a time, and
+    /// at least as many bytes as are already buffered, so that large headers need a logarithmic
+    /// number of round trips. Once the TOC of a frame is read, the section sizes are known, so
+    /// the rest of the next incomplete section is requested, together with the sections after it
+    /// that fit in `readahead` more bytes.
+    fn size_hint(&self, missing: usize, readahead: usize) -> usize {
+        if self.sections.is_empty() {
+            return missing
+                .max(HEADER_SIZE_HINT)
+                .max(self.non_section_buf.len());
+        }
+        let mut ready = self.ready_section_data;
+        let mut next_section = None;
+        let mut readahead_bytes = 0;
+        for section in self.sections.iter() {
+            let remaining = section.len.saturating_sub(ready);
+            ready = ready.saturating_sub(section.len);
+            if remaining == 0 {
+                continue;
+            }
+            if next_section.is_none() {
+                next_section = Some(remaining);
+            } else if readahead_bytes + remaining <= readahead {
+                readahead_bytes += remaining;
+            } else {
+                break;
+            }
+        }
+        missing.max(next_section.unwrap_or(0) + readahead_bytes)
+    }
+
+    /// Renders frames at their full size to staging buffers, and resamples them to `buffers`
+    /// once they are complete, or when flushing.
+    fn process_resized(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+        decode_options: &JxlDecoderOptions,
+        buffers: &mut [JxlOutputBuffer],
+        do_flush: bool,
+    ) -> Result<()> {
+        let (Some(size), Some(basic_info)) = (decode_options.resize_to, &self.basic_info) else {
+            return self.process_input(box_parser, input, decode_options, Some(buffers), do_flush);
+        };
+        if size.0 == 0 || size.1 == 0 {
+            return Err(Error::InvalidResizeSize(size.0, size.1));
+        }
+        check_size_limit(
+            decode_options.pixel_limit,
+            size,
+            basic_info.extra_channels.len(),
+        )?;
+        let render_format = self.render_pixel_format(decode_options);
+        let staging_requirements = self
+            .buffer_requirements(&render_format, None, decode_options)
+            .unwrap();
+        let mut resizer = std::mem::take(&mut self.resizer);
+        let result = resizer
+            .staging_buffers(&staging_requirements)
+            .and_then(|mut staging| {
+                self.process_input(
+                    box_parser,
+                    input,
+                    decode_options,
+                    Some(&mut staging),
+                    do_flush,
+                )
+            });
+        let rendered = match &result {
+            Ok(()) => self.frame_finished || do_flush,
+            Err(Error::OutOfBounds(_)) => do_flush,
+            Err(_) => false,
+        };
+        if let (Some(processor), true, Ok(())) = (
+            &decode_options.extra_channel_processor,
+            self.frame_finished,
+            &result,
+        ) {
+            let extra_channels = &self.basic_info.as_ref().unwrap().extra_channels;
+            let staging = (self.output_buffer_channels().into_iter()).zip(resizer.staging_mut());
+            process_extra_channels(
+                processor.as_ref(),
+                extra_channels,
+                staging.filter_map(|(channel, image)| Some((channel?, image))),
+            );
         }
+        let written = if rendered {
+            let requirements = self.output_buffer_requirements(decode_options).unwrap();
+            resizer.write_output(
+                buffers,
+                &requirements,
+                self.color_output(decode_options).as_ref(),
+                decode_options.resize_filter,
+            )
+        } else {
+            Ok(())
+        };
+        self.resizer = resizer;
+        written.and(result)
+    }
+
+    /// Renders the extra channels of frames to staging buffers, and writes them to their output
+    /// buffers once they are complete and post-processed, or unprocessed when flushing. The
+    /// color buffer is rendered to directly.
+    fn process_extra_channels(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+        decode_options: &JxlDecoderOptions,
+        buffers: &mut [JxlOutputBuffer],
+        do_flush: bool,
+    ) -> Result<()> {
+        let (Some(processor), Some(requirements)) = (
+            &decode_options.extra_channel_processor,
+            self.output_buffer_requirements(decode_options),
+        ) else {
+            return self.process_input(box_parser, input, decode_options, Some(buffers), do_flush);
+        };
+        let channels = self.output_buffer_channels();
+        let staging_requirements: Vec<_> = (requirements.iter().zip(&channels))
+            .filter(|(_, channel)| channel.is_some())
+            .map(|(req, _)| BufferRequirement {
+                data_type: JxlDataFormat::f32(),
+                ..*req
+            })
+            .collect();
+        let mut staging = std::mem::take(&mut self.extra_channel_staging);
+        let result = staging
+            .staging_buffers(&staging_requirements)
+            .and_then(|staged| {
+                let mut staged = staged.into_iter();
+                let mut render_buffers: Vec<_> = (buffers.iter_mut().zip(&channels))
+                    .map(|(buffer, channel)| match channel {
+                        None => JxlOutputBuffer::reborrow(buffer),
+                        Some(_) => staged.next().unwrap(),
+                    })
+                    .collect();
+                self.process_input(
+                    box_parser,
+                    input,
+                    decode_options,
+                    Some(&mut render_buffers),
+                    do_flush,
+                )
+            });
+        let rendered = match &result {
+            Ok(()) => self.frame_finished || do_flush,
+            Err(Error::OutOfBounds(_)) => do_flush,
+            Err(_) => false,
+        };
+        if self.frame_finished && result.is_ok() {
+            let extra_channels = &self.basic_info.as_ref().unwrap().extra_channels;
+            let indices = channels.iter().flatten().copied();
+            process_extra_channels(
+                processor.as_ref(),
+                extra_channels,
+                indices.zip(staging.staging_mut()),
+            );
+        }
+        if rendered {
+            let extra_buffers = (buffers.iter_mut().zip(&channels))
+                .filter(|(_, channel)| channel.is_some())
+                .map(|(buffer, _)| JxlOutputBuffer::reborrow(buffer));
+            let extra_requirements: Vec<_> = (requirements.iter().zip(&channels))
+                .filter(|(_, channel)| channel.is_some())
+                .map(|(req, _)| *req)
+                .collect();
+            staging.write_output(&mut extra_buffers.collect::<Vec<_>>(), &extra_requirements);
+        }
+        self.extra_channel_staging = staging;
+        result
+    }
+
+    fn process_input(
+        &mut self,
+        box_parser: &mut BoxParser,
+        input: &mut dyn JxlBitstreamInput,
+        decode_options: &JxlDecoderOptions,
+        mut output_buffers: Option<&mut [JxlOutputBuffer]>,
+        do_flush: bool,
+    ) -> Result<()> {
         // If we have sections to read, read into sections; otherwise, read into the local buffer.
         loop {
             if !self.sections.is_empty() {
@@ -402,7 +1100,7 @@ impl CodestreamParser {
                     // Read sections up to the end of the current box.
                     let mut available_codestream = match box_parser.get_more_codestream(input) {
                         Err(Error::OutOfBounds(_)) => 0,
-                        Ok(c) => c as usize,
+                        Ok(c) => saturating_usize(c),
                         Err(e) => return Err(e),
                     };
                     let mut section_buffers = vec![];
@@ -433,18 +1131,23 @@ impl CodestreamParser {
                             num
                         };
                         self.ready_section_data += num;
-                        box_parser.consume_codestream(num as u64);
+                        box_parser.consume_codestream(num as u64)?;
                         IoSliceMut::advance_slices(&mut buffers, num);
                         if num == 0 || buffers.is_empty() {
                             break;
                         }
                     }
+                    let frame_context = match self.current_frame_index {
+                        Some(_) => "frame",
+                        None => "preview frame",
+                    };
                     match self.process_sections(decode_options, &mut output_buffers, do_flush) {
                         Ok(None) => Ok(()),
                         Ok(Some(missing)) => Err(Error::OutOfBounds(missing)),
                         Err(Error::OutOfBounds(_)) => Err(Error::SectionTooShort),
                         Err(err) => Err(err),
-                    }?;
+                    }
+                    .context(frame_context, self.current_frame_index)?;
                 } else {
                     let total_size = self.sections.iter().map(|x| x.len).sum::<usize>();
                     loop {
@@ -452,7 +1155,11 @@ impl CodestreamParser {
                         if to_skip == 0 {
                             break;
                         }
-                        let available_codestream = box_parser.get_more_codestream(input)? as usize;
+                        let available_codestream = match box_parser.get_more_codestream(input) {
+                            Err(Error::OutOfBounds(_)) => 0,
+                            Ok(c) => saturating_usize(c),
+                            Err(e) => return Err(e),
+                        };
                         let to_skip = to_skip.min(available_codestream);
                         let skipped = if !box_parser.box_buffer.is_empty() {
                             box_parser.box_buffer.consume(to_skip)
@@ -461,7 +1168,7 @@ impl CodestreamParser {
                             box_parser.mark_file_consumed(skipped);
                             skipped
                         };
-                        box_parser.consume_codestream(skipped as u64);
+                        box_parser.consume_codestream(skipped as u64)?;
                         self.ready_section_data += skipped;
                         if skipped == 0 {
                             break;
@@ -476,14 +1183,16 @@ impl CodestreamParser {
                             .frame
                             .take()
                             .expect("frame must be set when skip_sections is true");
-                        if let Some(decoder_state) = frame.finalize()? {
-                            self.decoder_state = Some(decoder_state);
-                        } else {
-                            self.has_more_frames = false;
-                        }
+                        self.finalize_frame(frame, decode_options)?;
                         self.skip_sections = false;
                     }
                 }
+                if self.sections.is_empty() && !self.sequence.has_more_frames() {
+                    let trailing_data =
+                        box_parser.has_more_codestream(input, self.non_section_buf.len())?;
+                    self.sequence
+                        .check_trailing_data(trailing_data, decode_options.permissive)?;
+                }
                 if self.sections.is_empty() {
                     // Go back to parsing a new frame header, if any.
                     // Only return if this was a regular visible frame that was actually decoded
@@ -491,6 +1200,7 @@ impl CodestreamParser {
                     let was_skipping = self.process_without_output;
                     self.process_without_output = false;
                     if regular_frame && !was_skipping {
+                        self.frame_finished = true;
                         return Ok(());
                     }
                     continue;
@@ -498,11 +1208,13 @@ impl CodestreamParser {
             } else {
                 // Trying to read a frame or a file header.
                 assert!(self.frame.is_none());
-                if !self.has_more_frames {
-                    // If this is a flush request and the file is complete, we are done.
-                    // Otherwise, this is an API usage error.
-                    assert!(do_flush);
-                    return Ok(());
+                if !self.sequence.has_more_frames() {
+                    // Flushing a complete file has nothing left to draw.
+                    return if do_flush {
+                        Ok(())
+                    } else {
+                        Err(Error::NoMoreFrames)
+                    };
                 }
 
                 // Capture frame-start metadata once before parsing the next
@@ -525,9 +1237,10 @@ impl CodestreamParser {
                         // total_file_consumed counts bytes read/skipped from
                         // raw input. non_section_buf and box_buffer contain
                         // unread bytes already accounted there.
-                        self.current_frame_file_offset = (box_parser.total_file_consumed as usize)
-                            .saturating_sub(self.non_section_buf.len())
-                            .saturating_sub(box_parser.box_buffer.len());
+                        self.current_frame_file_offset = box_parser
+                            .total_file_consumed
+                            .saturating_sub(self.non_section_buf.len() as u64)
+                            .saturating_sub(box_parser.box_buffer.len() as u64);
 
                         // `available_codestream` includes bytes still in
                         // box_buffer and not yet in non_section_buf.
@@ -550,9 +1263,9 @@ impl CodestreamParser {
                                 Ok(read)
                             }
                         },
-                        Some(available_codestream as usize),
+                        Some(saturating_usize(available_codestream)),
                     )? as u64;
-                    box_parser.consume_codestream(c);
+                    box_parser.consume_codestream(c)?;
 
                     // If we know that non-section parsing will require more bytes than what
                     // we added to the codestream, don't even try to parse non-section data.
@@ -566,14 +1279,14 @@ impl CodestreamParser {
                             if input.available_bytes().unwrap_or(0) > 0 {
                                 continue;
                             } else {
-                                return Err(Error::OutOfBounds(*needed as usize));
+                                return Err(Error::OutOfBounds(saturating_usize(*needed)));
                             }
                         }
                     }
 
                     let range = self.non_section_buf.range();
 
-                    match self.process_non_section(decode_options) {
+                    match self.process_non_section(decode_options, box_parser.exif_image_size) {
                         Ok(()) => {
                             self.header_needed_bytes = None;
                             break;
@@ -610,6 +1323,7 @@ impl CodestreamParser {
                             .is_some_and(|info| info.preview_size.is_some());
                     if is_preview_frame {
                         self.preview_done = true;
+                        self.current_frame_index = None;
                         if decode_options.skip_preview {
                             self.process_without_output = true;
                             continue;
@@ -618,7 +1332,7 @@ impl CodestreamParser {
 
                     // Record frame info for scanning (after preview check).
                     if !is_preview_frame {
-                        self.record_frame_info();
+                        self.record_frame_info(decode_options.max_frame_names_size);
                     }
 
                     if self.has_visible_frame() {
@@ -7,24 +7,32 @@ use std::io::IoSliceMut;
 
 use crate::{
     api::{
-        Endianness, JxlBasicInfo, JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlColorType,
-        JxlDataFormat, JxlDecoderOptions, JxlExtraChannel, JxlPixelFormat,
-        inner::codestream_parser::SectionState,
+        Endianness, JxlBasicInfo, JxlBitDepth, JxlColorEncoding, JxlColorProfile,
+        JxlColorProfileMismatch, JxlColorProfileSource, JxlColorType, JxlDataFormat,
+        JxlDecoderOptions, JxlExtraChannel, JxlExtraChannelType, JxlLevel, JxlPixelFormat,
+        JxlSizeMismatch, inner::codestream_parser::SectionState,
     },
     bit_reader::BitReader,
+    entropy_coding::dump::{discard_entropy_codes_on_error, set_thread_entropy_frame},
     error::{Error, Result},
     frame::{DecoderState, Frame, Section},
     headers::{
-        FileHeader, JxlHeader, color_encoding::ColorSpace, encodings::UnconditionalCoder,
-        frame_header::FrameHeader, toc::IncrementalTocReader,
+        FileHeader, JxlHeader,
+        color_encoding::ColorSpace,
+        encodings::UnconditionalCoder,
+        extra_channels::{ExtraChannel, ExtraChannelInfo},
+        frame_header::FrameHeader,
+        toc::IncrementalTocReader,
     },
     icc::IncrementalIccReader,
+    render::stages::OutputColorInfo,
+    util::{tracing_wrappers::warn, usize_from_file_offset},
 };
 
 use super::{CodestreamParser, SectionBuffer};
 use crate::api::ToneMapping;
 
-fn check_size_limit(
+pub(super) fn check_size_limit(
     pixel_limit: Option<usize>,
     (xs, ys): (usize, usize),
     num_ec: usize,
@@ -39,14 +47,70 @@ fn check_size_limit(
     Ok(())
 }
 
+/// Creates the state shared by the frames of an image decoded with `decode_options`.
+pub(super) fn new_decoder_state(
+    file_header: FileHeader,
+    decode_options: &JxlDecoderOptions,
+) -> DecoderState {
+    let mut decoder_state = DecoderState::new(file_header);
+    decoder_state.render_spotcolors = decode_options.render_spot_colors;
+    decoder_state.high_precision = decode_options.high_precision;
+    decoder_state.premultiply_output = decode_options.premultiply_output;
+    decoder_state.rendering = decode_options.rendering_intent_override;
+    decoder_state.adjust_orientation = decode_options.adjust_orientation;
+    decoder_state.output_layout = decode_options.output_layout;
+    decoder_state.permissive = decode_options.permissive;
+    decoder_state.skip_corrupt_sections = decode_options.skip_corrupt_sections;
+    decoder_state.simplifications = decode_options.speed_profile.simplifications();
+    decoder_state
+}
+
+/// Rejects extra channel types that are not defined by the specification, unless in permissive
+/// mode, where they are decoded as channels without any special meaning.
+fn check_extra_channel_types(info: &[ExtraChannelInfo], permissive: bool) -> Result<()> {
+    for ec in info {
+        if let ExtraChannel::Unrecognized(ec_type) = ec.ec_type {
+            if !permissive {
+                return Err(Error::InvalidEnum(ec_type, "ExtraChannel".to_string()));
+            }
+            warn!(ec_type, "unrecognized extra channel type");
+        }
+    }
+    Ok(())
+}
+
+/// Rejects images with more extra channels than `level` allows, if it is set.
+fn check_level(info: &[ExtraChannelInfo], level: Option<JxlLevel>) -> Result<()> {
+    if let Some(max) = level.map(|level| level.max_extra_channels()) {
+        if info.len() > max {
+            return Err(Error::TooManyExtraChannels(info.len(), max));
+        }
+    }
+    Ok(())
+}
+
 impl CodestreamParser {
+    /// Parses the headers that precede the next frame. `declared_size` is the image size declared
+    /// by the container, if any, which is checked against the file header.
     #[cold]
-    pub(super) fn process_non_section(&mut self, decode_options: &JxlDecoderOptions) -> Result<()> {
+    pub(super) fn process_non_section(
+        &mut self,
+        decode_options: &JxlDecoderOptions,
+        declared_size: Option<(usize, usize)>,
+    ) -> Result<()> {
         if self.decoder_state.is_none() && self.file_header.is_none() {
             // We don't have a file header yet. Try parsing that.
             let mut br = BitReader::new(&self.non_section_buf);
             br.skip_bits(self.non_section_bit_offset as usize)?;
             let file_header = FileHeader::read(&mut br)?;
+            check_extra_channel_types(
+                &file_header.image_metadata.extra_channel_info,
+                decode_options.permissive,
+            )?;
+            check_level(
+                &file_header.image_metadata.extra_channel_info,
+                decode_options.enforce_level,
+            )?;
             let xsize = file_header.size.xsize() as usize;
             let ysize = file_header.size.ysize() as usize;
             check_size_limit(
@@ -54,6 +118,26 @@ impl CodestreamParser {
                 (xsize, ysize),
                 file_header.image_metadata.extra_channel_info.len(),
             )?;
+            // The codestream determines the decoded pixels, so its size always takes precedence.
+            self.size_mismatch =
+                declared_size
+                    .filter(|&size| size != (xsize, ysize))
+                    .map(|container| JxlSizeMismatch {
+                        container,
+                        codestream: (xsize, ysize),
+                    });
+            if let Some(mismatch) = &self.size_mismatch {
+                if decode_options.reject_size_mismatch {
+                    let (container, codestream) = (mismatch.container, mismatch.codestream);
+                    return Err(Error::ContainerSizeMismatch(
+                        container.0,
+                        container.1,
+                        codestream.0,
+                        codestream.1,
+                    ));
+                }
+                warn!("{mismatch}");
+            }
             if let Some(preview) = &file_header.image_metadata.preview {
                 check_size_limit(
                     decode_options.pixel_limit,
@@ -64,28 +148,22 @@ impl CodestreamParser {
             let data = &file_header.image_metadata;
             self.animation = data.animation.clone();
             self.basic_info = Some(JxlBasicInfo {
-                size: if data.orientation.is_transposing() {
+                size: if decode_options.adjust_orientation && data.orientation.is_transposing() {
                     (ysize, xsize)
                 } else {
                     (xsize, ysize)
                 },
-                bit_depth: if data.bit_depth.floating_point_sample() {
-                    JxlBitDepth::Float {
-                        bits_per_sample: data.bit_depth.bits_per_sample(),
-                        exponent_bits_per_sample: data.bit_depth.exponent_bits_per_sample(),
-                    }
-                } else {
-                    JxlBitDepth::Int {
-                        bits_per_sample: data.bit_depth.bits_per_sample(),
-                    }
-                },
+                bit_depth: JxlBitDepth::new(&data.bit_depth),
                 orientation: data.orientation,
                 extra_channels: data
                     .extra_channel_info
                     .iter()
                     .map(|info| JxlExtraChannel {
-                        ec_type: info.ec_type,
+                        ec_type: JxlExtraChannelType::new(info.ec_type),
                         alpha_associated: info.alpha_associated(),
+                        name: info.name().to_string(),
+                        bit_depth: JxlBitDepth::new(&info.bit_depth()),
+                        dim_shift: info.dim_shift(),
                     })
                     .collect(),
                 animation: data
@@ -123,7 +201,10 @@ impl CodestreamParser {
             br.skip_bits(self.non_section_bit_offset as usize)?;
             let embedded_color_profile = if file_header.image_metadata.color_encoding.want_icc {
                 if self.icc_parser.is_none() {
-                    self.icc_parser = Some(IncrementalIccReader::new(&mut br)?);
+                    // The ICC header is read again from the start if it is incomplete.
+                    self.icc_parser = Some(discard_entropy_codes_on_error(|| {
+                        IncrementalIccReader::new(&mut br, decode_options.max_icc_size)
+                    })?);
                 }
                 let icc_parser = self.icc_parser.as_mut().unwrap();
                 let mut bits = br.total_bits_read();
@@ -142,8 +223,26 @@ impl CodestreamParser {
                 let icc_result = self.icc_parser.take().unwrap().finalize(&mut br);
                 self.non_section_buf.consume(bits / 8);
                 self.non_section_bit_offset = (bits % 8) as u8;
-                JxlColorProfile::Icc(icc_result?)
+                let icc = icc_result?;
+                let color_space = file_header.image_metadata.color_encoding.color_space;
+                self.color_profile_mismatch = JxlColorProfileMismatch::check(
+                    color_space,
+                    &icc,
+                    decode_options.prefer_icc_profile,
+                );
+                match &self.color_profile_mismatch {
+                    Some(mismatch) => {
+                        warn!("{mismatch}");
+                        self.profile_source = Some(mismatch.used);
+                        mismatch.used_profile()
+                    }
+                    None => {
+                        self.profile_source = Some(JxlColorProfileSource::Icc);
+                        JxlColorProfile::Icc(icc)
+                    }
+                }
             } else {
+                self.profile_source = Some(JxlColorProfileSource::Enumerated);
                 JxlColorProfile::Simple(JxlColorEncoding::from_internal(
                     &file_header.image_metadata.color_encoding,
                 )?)
@@ -157,8 +256,24 @@ impl CodestreamParser {
 
             // Only set default pixel_format if not already configured (e.g. via rewind)
             if self.pixel_format.is_none() {
+                let extra_channel_info = &file_header.image_metadata.extra_channel_info;
+                // With `force_rgba`, the main alpha channel is interleaved with the colors.
+                let interleaved_alpha = if decode_options.force_rgba {
+                    extra_channel_info
+                        .iter()
+                        .position(|info| info.ec_type == ExtraChannel::Alpha)
+                } else {
+                    None
+                };
+                // Rendering spot colors makes grayscale images colored.
+                let renders_spot_colors = decode_options.render_spot_colors
+                    && extra_channel_info
+                        .iter()
+                        .any(|info| info.ec_type == ExtraChannel::SpotColor);
                 self.pixel_format = Some(JxlPixelFormat {
-                    color_type: if is_gray {
+                    color_type: if decode_options.force_rgba {
+                        JxlColorType::Rgba
+                    } else if is_gray && !decode_options.force_rgb && !renders_spot_colors {
                         JxlColorType::Grayscale
                     } else {
                         JxlColorType::Rgb
@@ -166,12 +281,13 @@ impl CodestreamParser {
                     color_data_format: Some(JxlDataFormat::F32 {
                         endianness: Endianness::native(),
                     }),
-                    extra_channel_format: vec![
-                        Some(JxlDataFormat::F32 {
-                            endianness: Endianness::native()
-                        });
-                        file_header.image_metadata.extra_channel_info.len()
-                    ],
+                    extra_channel_format: (0..extra_channel_info.len())
+                        .map(|i| {
+                            (Some(i) != interleaved_alpha).then_some(JxlDataFormat::F32 {
+                                endianness: Endianness::native(),
+                            })
+                        })
+                        .collect(),
                 });
             }
 
@@ -194,11 +310,10 @@ impl CodestreamParser {
             self.non_section_buf.consume(br.total_bits_read() / 8);
 
             // We now have image information.
-            let mut decoder_state = DecoderState::new(self.file_header.take().unwrap());
-            decoder_state.render_spotcolors = decode_options.render_spot_colors;
-            decoder_state.high_precision = decode_options.high_precision;
-            decoder_state.premultiply_output = decode_options.premultiply_output;
-            self.decoder_state = Some(decoder_state);
+            let file_header = self.file_header.take().unwrap();
+            // Errors are reported when preparing the render pipeline of the first frame.
+            self.output_color_info = OutputColorInfo::from_header(&file_header).ok();
+            self.decoder_state = Some(new_decoder_state(file_header, decode_options));
             // Reset bit offset to 0 since we've consumed everything up to a byte boundary
             self.non_section_bit_offset = 0;
             return Ok(());
@@ -212,7 +327,7 @@ impl CodestreamParser {
             br.skip_bits(self.non_section_bit_offset as usize)?;
 
             // For preview frames, use the preview dimensions instead of main image dimensions
-            let nonserialized = if !self.preview_done {
+            let mut nonserialized = if !self.preview_done {
                 decoder_state
                     .file_header
                     .preview_frame_header_nonserialized()
@@ -220,9 +335,18 @@ impl CodestreamParser {
             } else {
                 decoder_state.file_header.frame_header_nonserialized()
             };
+            nonserialized.permissive = decode_options.permissive;
 
             let mut frame_header = FrameHeader::read_unconditional(&(), &mut br, &nonserialized)?;
             frame_header.postprocess(&nonserialized);
+            let is_preview =
+                !self.preview_done && decoder_state.file_header.image_metadata.preview.is_some();
+            self.sequence.check_frame(
+                &frame_header,
+                decoder_state,
+                is_preview,
+                decode_options.permissive,
+            )?;
             check_size_limit(
                 decode_options.pixel_limit,
                 frame_header.size(),
@@ -244,6 +368,9 @@ impl CodestreamParser {
             self.non_section_bit_offset = (bits % 8) as u8;
         }
 
+        let is_preview =
+            !self.preview_done && decoder_state.file_header.image_metadata.preview.is_some();
+        set_thread_entropy_frame((!is_preview).then_some(self.frame_starts.len()));
         let toc = {
             let mut br = BitReader::new(&self.non_section_buf);
             br.skip_bits(self.non_section_bit_offset as usize)?;
@@ -279,12 +406,19 @@ impl CodestreamParser {
         // Save file_header before creating frame (for preview frame recovery)
         self.saved_file_header = self.decoder_state.as_ref().map(|ds| ds.file_header.clone());
 
+        let crop = self.frame_crop(self.frame_header.as_ref().unwrap(), decode_options)?;
+        self.decoder_state.as_mut().unwrap().crop = crop;
+
         let mut frame = Frame::from_header_and_toc(
             self.frame_header.take().unwrap(),
             toc,
             self.decoder_state.take().unwrap(),
         )?;
 
+        // Sections are buffered or skipped with usize bookkeeping, so frames larger than what
+        // can be addressed cannot be decoded.
+        usize_from_file_offset(frame.total_bytes_in_toc())?;
+
         let mut sections: Vec<_> = frame
             .toc()
             .entries
@@ -296,28 +430,9 @@ impl CodestreamParser {
             })
             .collect();
 
-        let order = if frame.toc().permuted {
-            frame.toc().permutation.0.clone()
-        } else {
-            (0..sections.len() as u32).collect()
-        };
-
         if sections.len() > 1 {
-            let base_sections = [Section::LfGlobal, Section::HfGlobal];
-            let lf_sections = (0..frame.header().num_lf_groups()).map(|x| Section::Lf { group: x });
-            let hf_sections = (0..frame.header().passes.num_passes).flat_map(|p| {
-                (0..frame.header().num_groups()).map(move |g| Section::Hf {
-                    group: g,
-                    pass: p as usize,
-                })
-            });
-
-            for section in base_sections
-                .into_iter()
-                .chain(lf_sections)
-                .chain(hf_sections)
-            {
-                sections[order[frame.get_section_idx(section)] as usize].section = section;
+            for section in frame.header().group_layout().sections() {
+                sections[frame.toc().position(frame.section_id(section))].section = section;
             }
         }
 
@@ -338,11 +453,17 @@ impl CodestreamParser {
                 .take(&mut [IoSliceMut::new(&mut buf.data)]);
         }
 
-        self.section_state =
-            SectionState::new(frame.header().num_lf_groups(), frame.header().num_groups());
+        self.section_state = SectionState::new(
+            frame.header().num_lf_groups(),
+            frame.header().num_groups(),
+            frame
+                .header()
+                .passes_for_downsample(decode_options.downsample)
+                .0,
+        );
 
         frame.prepare_render_pipeline(
-            self.pixel_format.as_ref().unwrap(),
+            &self.render_pixel_format(decode_options),
             decode_options.cms.as_deref(),
             self.embedded_color_profile
                 .as_ref()
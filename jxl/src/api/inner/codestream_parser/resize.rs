@@ -0,0 +1,237 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::{BufferRequirement, Endianness, JxlDataFormat, JxlOutputBuffer},
+    error::Result,
+    image::{Image, Rect},
+    render::resample::{ColorSamples, ResampleFilter, resample, resample_colors},
+    util::f16,
+};
+
+/// Describes the color output buffer, for resampling.
+pub(super) struct ColorOutput {
+    /// The color samples, followed by alpha from `alpha_buffer` if it is set.
+    pub samples: ColorSamples,
+    /// Index of the buffer holding the alpha channel, if it is not interleaved with the colors.
+    pub alpha_buffer: Option<usize>,
+}
+
+/// Renders frames at their full size, to resample them to the size of the output buffers.
+#[derive(Default)]
+pub(super) struct Resizer {
+    /// Full-size `f32` rendering of each output buffer.
+    staging: Vec<Image<f32>>,
+}
+
+impl Resizer {
+    /// Returns buffers with the geometry of `requirements` to render the frame to. The contents
+    /// of the buffers are kept across calls, as long as their geometry does not change.
+    pub(super) fn staging_buffers(
+        &mut self,
+        requirements: &[BufferRequirement],
+    ) -> Result<Vec<JxlOutputBuffer<'_>>> {
+        staging_buffers(&mut self.staging, requirements)
+    }
+
+    /// Returns the full-size rendering of each output buffer.
+    pub(super) fn staging_mut(&mut self) -> &mut [Image<f32>] {
+        &mut self.staging
+    }
+
+    /// Resamples the staging buffers to `buffers`, which have the geometry of `requirements`.
+    /// `colors` describes the first buffer, if it holds the color channels.
+    pub(super) fn write_output(
+        &self,
+        buffers: &mut [JxlOutputBuffer],
+        requirements: &[BufferRequirement],
+        colors: Option<&ColorOutput>,
+        filter: ResampleFilter,
+    ) -> Result<()> {
+        // Alpha in a separate buffer is resampled together with the colors, which come first.
+        let mut resized_alpha = None;
+        for (index, ((staging, buffer), req)) in self
+            .staging
+            .iter()
+            .zip(buffers.iter_mut())
+            .zip(requirements)
+            .enumerate()
+        {
+            let size = (req.width, req.height);
+            let resized = match colors {
+                Some(colors) if index == 0 => match colors.alpha_buffer {
+                    None => resample_colors(staging, &colors.samples, size, filter)?,
+                    Some(alpha) => {
+                        let merged =
+                            append_channel(staging, req.samples_per_pixel, &self.staging[alpha])?;
+                        let resized = resample_colors(&merged, &colors.samples, size, filter)?;
+                        let (color, alpha) =
+                            split_last_channel(&resized, colors.samples.num_channels)?;
+                        resized_alpha = Some(alpha);
+                        color
+                    }
+                },
+                Some(colors) if colors.alpha_buffer == Some(index) => resized_alpha.take().unwrap(),
+                _ => resample(staging, req.samples_per_pixel, size, filter)?,
+            };
+            write_samples(&resized, req.data_type, buffer);
+        }
+        Ok(())
+    }
+}
+
+/// Returns buffers with the geometry of `requirements` that write to `images`, which are
+/// reallocated if their sizes do not match.
+pub(super) fn staging_buffers<'a>(
+    images: &'a mut Vec<Image<f32>>,
+    requirements: &[BufferRequirement],
+) -> Result<Vec<JxlOutputBuffer<'a>>> {
+    let sizes = requirements
+        .iter()
+        .map(|req| (req.width * req.samples_per_pixel, req.height));
+    if !images.iter().map(Image::size).eq(sizes.clone()) {
+        *images = sizes.map(Image::new).collect::<Result<_>>()?;
+    }
+    Ok(images
+        .iter_mut()
+        .map(|image| {
+            let rect = Rect {
+                origin: (0, 0),
+                size: image.size(),
+            };
+            JxlOutputBuffer::from_image_rect_mut(image.get_rect_mut(rect).into_raw())
+        })
+        .collect())
+}
+
+/// Interleaves the single-channel `channel` after the `num_channels` channels of `image`.
+fn append_channel(
+    image: &Image<f32>,
+    num_channels: usize,
+    channel: &Image<f32>,
+) -> Result<Image<f32>> {
+    let (width, height) = channel.size();
+    let mut merged = Image::new((width * (num_channels + 1), height))?;
+    for y in 0..height {
+        let pixels = image.row(y).chunks_exact(num_channels).zip(channel.row(y));
+        for (out, (pixel, &value)) in merged
+            .row_mut(y)
+            .chunks_exact_mut(num_channels + 1)
+            .zip(pixels)
+        {
+            out[..num_channels].copy_from_slice(pixel);
+            out[num_channels] = value;
+        }
+    }
+    Ok(merged)
+}
+
+/// Splits the last of the `num_channels` interleaved channels of `image` from the others.
+fn split_last_channel(image: &Image<f32>, num_channels: usize) -> Result<(Image<f32>, Image<f32>)> {
+    let (width, height) = (image.size().0 / num_channels, image.size().1);
+    let mut others = Image::new((width * (num_channels - 1), height))?;
+    let mut last = Image::new((width, height))?;
+    for y in 0..height {
+        let (others_row, last_row) = (others.row_mut(y), last.row_mut(y));
+        for (x, pixel) in image.row(y).chunks_exact(num_channels).enumerate() {
+            let (&value, rest) = pixel.split_last().unwrap();
+            others_row[x * (num_channels - 1)..(x + 1) * (num_channels - 1)].copy_from_slice(rest);
+            last_row[x] = value;
+        }
+    }
+    Ok((others, last))
+}
+
+/// Converts the samples of `image` to `data_format`, and writes them to `buffer`.
+pub(super) fn write_samples(
+    image: &Image<f32>,
+    data_format: JxlDataFormat,
+    buffer: &mut JxlOutputBuffer,
+) {
+    let mut bytes = Vec::with_capacity(image.size().0 * data_format.bytes_per_sample());
+    for y in 0..image.size().1 {
+        bytes.clear();
+        for &value in image.row(y) {
+            let integer = |bit_depth: u8| {
+                let max = ((1u32 << bit_depth) - 1) as f32;
+                (value.clamp(0.0, 1.0) * max).round() as u16
+            };
+            macro_rules! push {
+                ($value: expr, $endianness: expr) => {
+                    bytes.extend_from_slice(&match $endianness {
+                        Endianness::LittleEndian => $value.to_le_bytes(),
+                        Endianness::BigEndian => $value.to_be_bytes(),
+                    })
+                };
+            }
+            match data_format {
+                JxlDataFormat::U8 { bit_depth } => bytes.push(integer(bit_depth) as u8),
+                JxlDataFormat::U16 {
+                    endianness,
+                    bit_depth,
+                } => push!(integer(bit_depth), endianness),
+                JxlDataFormat::F16 { endianness } => push!(f16::from_f32(value), endianness),
+                JxlDataFormat::F32 { endianness } => push!(value, endianness),
+            }
+        }
+        buffer.write_bytes(y, 0, &bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separate_channels_round_trip() {
+        let mut image = Image::new((6, 2)).unwrap();
+        let mut channel = Image::new((2, 2)).unwrap();
+        for y in 0..2 {
+            for (x, v) in image.row_mut(y).iter_mut().enumerate() {
+                *v = (y * 10 + x) as f32;
+            }
+            channel.row_mut(y).copy_from_slice(&[-1.0, -2.0]);
+        }
+        let merged = append_channel(&image, 3, &channel).unwrap();
+        assert_eq!(
+            merged.row(1),
+            [10.0, 11.0, 12.0, -1.0, 13.0, 14.0, 15.0, -2.0]
+        );
+        let (colors, alpha) = split_last_channel(&merged, 4).unwrap();
+        for y in 0..2 {
+            assert_eq!(colors.row(y), image.row(y));
+            assert_eq!(alpha.row(y), channel.row(y));
+        }
+    }
+
+    #[test]
+    fn samples_are_converted_to_the_output_format() {
+        let mut image = Image::new((4, 1)).unwrap();
+        image.row_mut(0).copy_from_slice(&[0.0, 0.5, 1.0, 2.0]);
+        let formats = [
+            (JxlDataFormat::U8 { bit_depth: 8 }, vec![0, 128, 255, 255]),
+            (JxlDataFormat::U8 { bit_depth: 4 }, vec![0, 8, 15, 15]),
+            (
+                JxlDataFormat::U16 {
+                    endianness: Endianness::BigEndian,
+                    bit_depth: 16,
+                },
+                vec![0, 0, 128, 0, 255, 255, 255, 255],
+            ),
+            (
+                JxlDataFormat::F16 {
+                    endianness: Endianness::LittleEndian,
+                },
+                vec![0, 0, 0, 0x38, 0, 0x3c, 0, 0x40],
+            ),
+        ];
+        for (data_format, expected) in formats {
+            let mut bytes = vec![0; expected.len()];
+            let mut buffer = JxlOutputBuffer::new(&mut bytes, 1, expected.len());
+            write_samples(&image, data_format, &mut buffer);
+            assert_eq!(bytes, expected, "{data_format:?}");
+        }
+    }
+}
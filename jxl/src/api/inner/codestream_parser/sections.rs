@@ -6,13 +6,21 @@
 use crate::{
     api::{JxlDecoderOptions, JxlOutputBuffer},
     bit_reader::BitReader,
-    error::Result,
-    frame::Section,
+    entropy_coding::dump::entropy_dump_enabled,
+    error::{Error, ErrorContext, Result},
+    frame::{Frame, GroupId, LfGroupId, PassId, Section},
     headers::frame_header::{Encoding, FrameType},
+    util::tracing_wrappers::warn,
 };
 
 use super::CodestreamParser;
 
+/// Partial renders and downsampled decoding both render frames from the sections decoded so far.
+pub(crate) fn register_progressive_decoding(capabilities: &mut crate::Capabilities) {
+    capabilities.progressive_flush = true;
+    capabilities.downsample = true;
+}
+
 #[derive(Debug)]
 pub(super) struct SectionState {
     lf_global_done: bool,
@@ -20,16 +28,27 @@ pub(super) struct SectionState {
     hf_global_done: bool,
     completed_passes: Vec<u8>,
     lf_global_flush_len: usize,
+    /// Number of passes to decode, fewer than those of the frame if it is downsampled.
+    num_passes: usize,
 }
 
 impl SectionState {
-    pub(super) fn new(num_lf_groups: usize, num_groups: usize) -> Self {
+    pub(super) fn new(num_lf_groups: usize, num_groups: usize, num_passes: usize) -> Self {
         Self {
             lf_global_done: false,
             remaining_lf: num_lf_groups,
             hf_global_done: false,
             completed_passes: vec![0; num_groups],
             lf_global_flush_len: 0,
+            num_passes,
+        }
+    }
+
+    /// Decodes all passes of frames that can only be rendered once they are complete, which is
+    /// known after the LF global section.
+    fn check_downsampling(&mut self, frame: &Frame) {
+        if !frame.allow_rendering_before_last_pass() {
+            self.num_passes = frame.header().passes.num_passes as usize;
         }
     }
 
@@ -47,7 +66,11 @@ impl CodestreamParser {
         output_buffers: &mut Option<&mut [JxlOutputBuffer<'_>]>,
         do_flush: bool,
     ) -> Result<Option<usize>> {
+        let pixel_format = &self.render_pixel_format(decode_options);
         let frame = self.frame.as_mut().unwrap();
+        // SkipProgressive frames are only rendered once they are complete, so flushing one only
+        // decodes the sections that are available.
+        let do_flush = do_flush && frame.header().frame_type != FrameType::SkipProgressive;
 
         let output_profile = self
             .output_color_profile
@@ -55,6 +78,7 @@ impl CodestreamParser {
             .expect("output_color_profile should be set before pipeline preparation");
 
         let frame_header = frame.header();
+        let mut processed_section = false;
 
         // Dequeue ready sections.
         while self
@@ -75,16 +99,21 @@ impl CodestreamParser {
                 Section::Lf { .. } => {
                     self.lf_sections.push(s);
                 }
+                // Passes beyond the requested downsampling are not decoded.
+                Section::Hf { pass, .. }
+                    if self.section_state.lf_global_done
+                        && pass.index() >= self.section_state.num_passes =>
+                {
+                    processed_section = true;
+                }
                 Section::Hf { group, pass } => {
-                    self.hf_sections[group][pass] = Some(s);
+                    self.hf_sections[group.index()][pass.index()] = Some(s);
                     self.candidate_hf_sections.insert(group);
                 }
             }
         }
 
-        let mut processed_section = false;
         let mut called_render_hf = false;
-        let pixel_format = self.pixel_format.as_ref().unwrap();
 
         let complete_lf_global;
         let (lf_global, lf_global_is_complete) = if let Some(d) = self.lf_global_section.take() {
@@ -94,6 +123,9 @@ impl CodestreamParser {
                 true,
             )
         } else if do_flush
+            // Partially decoded sections are decoded again from the start, which would record
+            // their entropy codes again.
+            && !entropy_dump_enabled()
             && self
                 .sections
                 .front()
@@ -123,14 +155,29 @@ impl CodestreamParser {
                 assert!(self.sections.is_empty() || !lf_global_is_complete);
                 let mut br = BitReader::new(buf);
                 let res = (|| -> Result<()> {
-                    frame.decode_lf_global(&mut br, !lf_global_is_complete)?;
-                    frame.decode_lf_group(0, &mut br)?;
-                    frame.decode_hf_global(&mut br)?;
+                    frame
+                        .decode_lf_global(&mut br, !lf_global_is_complete)
+                        .at_section(Section::LfGlobal)?;
+                    self.section_state.check_downsampling(frame);
+                    frame
+                        .decode_lf_group(LfGroupId::new(0), &mut br)
+                        .at_section(Section::Lf {
+                            group: LfGroupId::new(0),
+                        })?;
+                    frame
+                        .decode_hf_global(&mut br)
+                        .at_section(Section::HfGlobal)?;
                     frame.finalize_lf()?;
+                    // Frames decoded without their pass are rendered once they are complete.
+                    let groups = if self.section_state.num_passes == 0 {
+                        vec![]
+                    } else {
+                        vec![(GroupId::new(0), vec![(PassId::new(0), br)])]
+                    };
                     frame.decode_and_render_hf_groups(
                         output_buffers,
                         pixel_format,
-                        vec![(0, vec![(0, br)])],
+                        groups,
                         do_flush,
                         output_profile,
                     )?;
@@ -148,9 +195,13 @@ impl CodestreamParser {
                 }
             } else {
                 if let Some(buf) = lf_global {
-                    match frame.decode_lf_global(&mut BitReader::new(buf), !lf_global_is_complete) {
+                    match frame
+                        .decode_lf_global(&mut BitReader::new(buf), !lf_global_is_complete)
+                        .at_section(Section::LfGlobal)
+                    {
                         Ok(_) => {
                             self.section_state.lf_global_done = true;
+                            self.section_state.check_downsampling(frame);
                             processed_section = true;
                         }
                         Err(_) if !lf_global_is_complete => {
@@ -166,9 +217,19 @@ impl CodestreamParser {
 
                 for lf_section in self.lf_sections.drain(..) {
                     let Section::Lf { group } = lf_section.section else {
-                        unreachable!()
+                        return Err(Error::internal("non-LF section queued as LF section"));
                     };
-                    frame.decode_lf_group(group, &mut BitReader::new(&lf_section.data))?;
+                    match frame
+                        .decode_lf_group(group, &mut BitReader::new(&lf_section.data))
+                        .at_section(lf_section.section)
+                    {
+                        Ok(()) => {}
+                        Err(e) if frame.can_conceal(&e) => {
+                            warn!("Concealing LF group {group}: {e}");
+                            frame.conceal_lf_group(group)?;
+                        }
+                        Err(e) => return Err(e),
+                    }
                     processed_section = true;
                     self.section_state.remaining_lf -= 1;
                 }
@@ -178,7 +239,9 @@ impl CodestreamParser {
                 }
 
                 if let Some(hf_global) = self.hf_global_section.take() {
-                    frame.decode_hf_global(&mut BitReader::new(&hf_global.data))?;
+                    frame
+                        .decode_hf_global(&mut BitReader::new(&hf_global.data))
+                        .at_section(Section::HfGlobal)?;
                     frame.finalize_lf()?;
                     self.section_state.hf_global_done = true;
                     processed_section = true;
@@ -191,17 +254,21 @@ impl CodestreamParser {
                 let mut group_readers = vec![];
                 let mut processed_groups = vec![];
 
-                let mut check_group = |g: usize| {
+                let layout = frame.header().group_layout();
+                let num_passes = self.section_state.num_passes;
+                let mut check_group = |g: GroupId| {
                     let mut sections = vec![];
-                    for (pass, grp) in self.hf_sections[g]
-                        .iter()
-                        .enumerate()
-                        .skip(self.section_state.completed_passes[g] as usize)
+                    let completed_passes = &mut self.section_state.completed_passes[g.index()];
+                    for (pass, grp) in layout
+                        .passes()
+                        .zip(&self.hf_sections[g.index()])
+                        .take(num_passes)
+                        .skip(*completed_passes as usize)
                     {
                         let Some(s) = &grp else {
                             break;
                         };
-                        self.section_state.completed_passes[g] += 1;
+                        *completed_passes += 1;
                         sections.push((pass, BitReader::new(&s.data)));
                     }
                     if !sections.is_empty() {
@@ -218,7 +285,7 @@ impl CodestreamParser {
                     // the pipeline faster.
                     group_readers.sort_by_key(|x| x.0);
                 } else {
-                    for g in 0..self.hf_sections.len() {
+                    for g in layout.groups() {
                         if self.candidate_hf_sections.contains(&g) {
                             check_group(g);
                         }
@@ -236,8 +303,8 @@ impl CodestreamParser {
                 called_render_hf = true;
 
                 for g in processed_groups.into_iter() {
-                    for i in 0..self.section_state.completed_passes[g] {
-                        self.hf_sections[g][i as usize] = None;
+                    for i in 0..self.section_state.completed_passes[g.index()] {
+                        self.hf_sections[g.index()][i as usize] = None;
                     }
                     processed_section = true;
                 }
@@ -265,6 +332,17 @@ impl CodestreamParser {
             return Ok(None);
         }
 
+        // Render downsampled frames from the passes that were decoded, like partial renders.
+        if self.section_state.num_passes < frame.header().passes.num_passes as usize {
+            frame.decode_and_render_hf_groups(
+                output_buffers,
+                pixel_format,
+                vec![],
+                true,
+                output_profile,
+            )?;
+        }
+
         #[cfg(test)]
         {
             self.frame_callback.as_mut().map_or(Ok(()), |cb| {
@@ -273,27 +351,8 @@ impl CodestreamParser {
             self.decoded_frames += 1;
         }
 
-        // Check if this might be a preview frame (skipped frame with preview enabled)
-        let has_preview = self
-            .basic_info
-            .as_ref()
-            .is_some_and(|info| info.preview_size.is_some());
-        let might_be_preview = self.process_without_output && has_preview;
-
-        let decoder_state = self.frame.take().unwrap().finalize()?;
-        if let Some(state) = decoder_state {
-            self.decoder_state = Some(state);
-        } else if might_be_preview {
-            // Preview frame has is_last=true but the main frame follows.
-            // Recreate decoder state from saved file header for the main frame.
-            if let Some(fh) = self.saved_file_header.take() {
-                let mut new_state = crate::frame::DecoderState::new(fh);
-                new_state.render_spotcolors = decode_options.render_spot_colors;
-                self.decoder_state = Some(new_state);
-            }
-        } else {
-            self.has_more_frames = false;
-        }
+        let frame = self.frame.take().unwrap();
+        self.finalize_frame(frame, decode_options)?;
         Ok(None)
     }
 }
@@ -0,0 +1,103 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    error::{Error, Result},
+    frame::DecoderState,
+    headers::frame_header::FrameHeader,
+    util::tracing_wrappers::warn,
+};
+
+/// Tracks the frames of a codestream to check that they are sequenced as the specification
+/// requires. Violations are errors, unless in permissive mode, where they are warned about and
+/// decoding continues as well as possible.
+#[derive(Default)]
+pub(super) struct FrameSequence {
+    /// Number of frames whose header was parsed, not counting the preview frame.
+    frames: usize,
+    /// True once the frame marked as last, or treated as such, was decoded.
+    ended: bool,
+    /// Whether the frame being decoded is the preview frame, which is marked as last too.
+    in_preview: bool,
+}
+
+impl FrameSequence {
+    pub(super) fn has_more_frames(&self) -> bool {
+        !self.ended
+    }
+
+    /// Checks the header of the next frame against the frames decoded before it.
+    pub(super) fn check_frame(
+        &mut self,
+        header: &FrameHeader,
+        decoder_state: &DecoderState,
+        is_preview: bool,
+        permissive: bool,
+    ) -> Result<()> {
+        if header.has_lf_frame() && decoder_state.lf_frames[header.lf_level as usize].is_none() {
+            // In permissive mode, the frame is decoded with an LF image of zeros instead.
+            violation(Error::MissingLfFrame(header.lf_level + 1), permissive)?;
+        }
+        self.in_preview = is_preview;
+        if is_preview {
+            return Ok(());
+        }
+        self.frames += 1;
+        let is_animation = decoder_state.file_header.image_metadata.animation.is_some();
+        // Other frames without a duration are not displayed but composited with the next one.
+        if is_animation && header.is_last && header.duration == 0 {
+            violation(Error::LastFrameWithoutDuration, permissive)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the frame being decoded is the preview frame.
+    pub(super) fn in_preview(&self) -> bool {
+        self.in_preview
+    }
+
+    /// Records that the frame marked as last was decoded.
+    pub(super) fn finish_last_frame(&mut self) {
+        self.ended = true;
+    }
+
+    /// Checks whether the codestream continues after the last frame. In permissive mode, the
+    /// rest of the codestream is ignored.
+    pub(super) fn check_trailing_data(&self, trailing_data: bool, permissive: bool) -> Result<()> {
+        debug_assert!(self.ended);
+        if trailing_data {
+            violation(Error::DataAfterLastFrame, permissive)?;
+        }
+        Ok(())
+    }
+
+    /// Handles the input ending before the frame marked as last. In permissive mode, the last
+    /// decoded frame is treated as the last one, if there is any.
+    pub(super) fn end_input(&mut self, permissive: bool) -> Result<()> {
+        if self.ended {
+            return Ok(());
+        }
+        if self.frames == 0 {
+            return Err(Error::MissingLastFrame);
+        }
+        violation(Error::MissingLastFrame, permissive)?;
+        self.ended = true;
+        Ok(())
+    }
+
+    /// Allows frames to be decoded again after seeking back to an earlier frame.
+    pub(super) fn restart(&mut self) {
+        self.ended = false;
+    }
+}
+
+/// Returns `error`, unless in permissive mode, where it is only warned about.
+fn violation(error: Error, permissive: bool) -> Result<()> {
+    if !permissive {
+        return Err(error);
+    }
+    warn!("{error}");
+    Ok(())
+}
@@ -6,14 +6,23 @@
 #[cfg(test)]
 use crate::api::FrameCallback;
 use crate::{
-    api::{JxlFrameHeader, VisibleFrameInfo, VisibleFrameSeekTarget},
+    api::{
+        FrameTiming, GroupLayout, JxlFrameHeader, PassesInfo, VisibleFrameInfo,
+        VisibleFrameSeekTarget,
+    },
     error::{Error, Result},
+    image::{ImageAllocator, Rect},
 };
 
-use super::{JxlBasicInfo, JxlColorProfile, JxlDecoderOptions, JxlPixelFormat};
+use super::{
+    BufferRequirement, CompressionSummary, JxlBasicInfo, JxlColorProfile, JxlColorProfileMismatch,
+    JxlColorProfileSource, JxlDecodeTimings, JxlDecoderOptions, JxlFrameDiff, JxlMemoryUsage,
+    JxlPixelFormat, JxlSizeMismatch,
+};
 use crate::container::frame_index::FrameIndexBox;
 use box_parser::BoxParser;
 use codestream_parser::CodestreamParser;
+pub(crate) use codestream_parser::register_progressive_decoding;
 
 mod box_parser;
 mod codestream_parser;
@@ -24,20 +33,26 @@ pub struct JxlDecoderInner {
     options: JxlDecoderOptions,
     box_parser: BoxParser,
     codestream_parser: CodestreamParser,
+    timings: JxlDecodeTimings,
+    tokens_used: u64,
+    allocator: ImageAllocator,
 }
 
 impl JxlDecoderInner {
     /// Creates a new decoder with the given options and, optionally, CMS.
     pub fn new(options: JxlDecoderOptions) -> Self {
         JxlDecoderInner {
+            allocator: ImageAllocator::new(options.allocator.clone()),
             options,
             box_parser: BoxParser::new(),
             codestream_parser: CodestreamParser::new(),
+            timings: JxlDecodeTimings::default(),
+            tokens_used: 0,
         }
     }
 
     #[cfg(test)]
-    pub fn set_frame_callback(&mut self, callback: Box<FrameCallback>) {
+    pub(crate) fn set_frame_callback(&mut self, callback: Box<FrameCallback>) {
         self.codestream_parser.frame_callback = Some(callback);
     }
 
@@ -51,11 +66,37 @@ impl JxlDecoderInner {
         self.codestream_parser.basic_info.as_ref()
     }
 
+    /// Returns the memory of the image buffers allocated while processing.
+    pub fn memory_usage(&self) -> JxlMemoryUsage {
+        self.allocator.usage()
+    }
+
+    /// Whether pixels are written in the orientation of the image.
+    pub fn orientation_applied(&self) -> bool {
+        self.options.adjust_orientation
+    }
+
     /// Retrieves the file's color profile, if available.
     pub fn embedded_color_profile(&self) -> Option<&JxlColorProfile> {
         self.codestream_parser.embedded_color_profile.as_ref()
     }
 
+    /// Returns where the file's color profile comes from, if available.
+    pub fn profile_source(&self) -> Option<JxlColorProfileSource> {
+        self.codestream_parser.profile_source
+    }
+
+    /// Returns how the file's ICC profile disagrees with its enumerated color space, if it does.
+    pub fn color_profile_mismatch(&self) -> Option<&JxlColorProfileMismatch> {
+        self.codestream_parser.color_profile_mismatch.as_ref()
+    }
+
+    /// Returns how the image size declared by the container disagrees with the codestream, if
+    /// it does.
+    pub fn size_mismatch(&self) -> Option<&JxlSizeMismatch> {
+        self.codestream_parser.size_mismatch.as_ref()
+    }
+
     /// Retrieves the current output color profile, if available.
     pub fn output_color_profile(&self) -> Option<&JxlColorProfile> {
         self.codestream_parser.output_color_profile.as_ref()
@@ -86,20 +127,44 @@ impl JxlDecoderInner {
     pub fn frame_header(&self) -> Option<JxlFrameHeader> {
         let frame_header = self.codestream_parser.frame.as_ref()?.header();
         // The render pipeline always adds ExtendToImageDimensionsStage which extends
-        // frames to the full image size. So the output size is always the image size,
-        // not the frame's upsampled size.
-        let size = self.codestream_parser.basic_info.as_ref()?.size;
+        // frames to the full image size. So the output size is always the image size
+        // (or the size it is resized or cropped to), not the frame's upsampled size.
+        let size = self
+            .options
+            .resize_to
+            .or(self.options.crop.map(|crop| crop.size))
+            .unwrap_or(self.codestream_parser.basic_info.as_ref()?.size);
         Some(JxlFrameHeader {
             name: frame_header.name.clone(),
-            duration: self
+            timing: self
                 .codestream_parser
                 .animation
                 .as_ref()
-                .map(|anim| frame_header.duration(anim)),
+                .map(|anim| FrameTiming {
+                    ticks: frame_header.duration,
+                    tps_num: anim.tps_numerator,
+                    tps_den: anim.tps_denominator,
+                }),
             size,
+            passes: PassesInfo::new(&frame_header.passes),
+            downsample: frame_header
+                .passes_for_downsample(self.options.downsample)
+                .1,
+            groups: GroupLayout::new(frame_header),
+            simplifications: self.codestream_parser.frame.as_ref()?.simplifications(),
+            all_default: frame_header.is_all_default(),
+            flags: frame_header.flags(),
+            raw_flags: frame_header.raw_flags(),
         })
     }
 
+    /// Returns the geometry of the buffers that should be passed to `process` or
+    /// `flush_pixels`, if image information is available.
+    pub fn output_buffer_requirements(&self) -> Option<Vec<BufferRequirement>> {
+        self.codestream_parser
+            .output_buffer_requirements(&self.options)
+    }
+
     /// Number of passes we have full data for.
     /// Returns the minimum number of passes completed across all groups.
     pub fn num_completed_passes(&self) -> Option<usize> {
@@ -116,6 +181,8 @@ impl JxlDecoderInner {
         // TODO(veluca): keep track of frame offsets for skipping.
         self.box_parser = BoxParser::new();
         self.codestream_parser = CodestreamParser::new();
+        self.timings = JxlDecodeTimings::default();
+        self.tokens_used = 0;
     }
 
     /// Rewinds for animation loop replay, keeping pixel_format setting.
@@ -129,11 +196,82 @@ impl JxlDecoderInner {
     /// Returns `true` if pixel_format was preserved, `false` if none was set.
     pub fn rewind(&mut self) -> bool {
         self.box_parser = BoxParser::new();
+        self.tokens_used = 0;
         self.codestream_parser.rewind().is_some()
     }
 
     pub fn has_more_frames(&self) -> bool {
-        self.codestream_parser.has_more_frames
+        self.codestream_parser.has_more_frames()
+    }
+
+    /// Handles the input ending between frames, before the frame marked as last.
+    pub fn end_input(&mut self) -> Result<()> {
+        self.codestream_parser.end_input(&self.options)
+    }
+
+    /// Returns the difference between the last decoded frame and the previous one.
+    pub fn frame_diff(&self) -> Option<JxlFrameDiff> {
+        self.codestream_parser.frame_diff
+    }
+
+    /// Returns the time spent in each decoding stage since the decoder was created or reset.
+    #[cfg(feature = "timing-stats")]
+    pub fn decode_timings(&self) -> &JxlDecodeTimings {
+        &self.timings
+    }
+
+    /// Returns how many groups of the last decoded frame were not rendered, being outside of the
+    /// image.
+    pub fn offcanvas_groups(&self) -> Option<usize> {
+        self.codestream_parser.offcanvas_groups
+    }
+
+    /// Returns the groups of the last decoded frame that failed to decode and were concealed.
+    pub fn concealed_groups(&self) -> Option<&[super::ConcealedGroup]> {
+        self.codestream_parser.concealed_groups.as_deref()
+    }
+
+    /// Returns the modular statistics of the last decoded frame.
+    #[cfg(feature = "timing-stats")]
+    pub fn modular_stats(&self) -> Option<&super::ModularStats> {
+        self.codestream_parser.modular_stats.as_ref()
+    }
+
+    /// Returns the checks of the modular streams of the last decoded frame.
+    #[cfg(feature = "verify")]
+    pub fn modular_checks(&self) -> Option<&[super::ModularChannelCheck]> {
+        self.codestream_parser.modular_checks.as_deref()
+    }
+
+    /// Returns the entropy codes of the last decoded frame.
+    #[cfg(feature = "debug-tools")]
+    pub fn entropy_codes(&self) -> Option<&[super::EntropyCodeInfo]> {
+        self.codestream_parser.entropy_codes.as_deref()
+    }
+
+    /// Sets [`JxlDecoderOptions::max_total_tokens`], e.g. to a limit derived from the image size.
+    pub fn set_max_total_tokens(&mut self, limit: Option<u64>) {
+        self.options.max_total_tokens = limit;
+    }
+
+    /// Sets [`JxlDecoderOptions::resize_to`], e.g. to a size derived from the image size.
+    pub fn set_resize_to(&mut self, size: Option<(usize, usize)>) {
+        self.options.resize_to = size;
+    }
+
+    /// Sets [`JxlDecoderOptions::downsample`], e.g. to a factor derived from the image size.
+    pub fn set_downsample(&mut self, downsample: u32) {
+        self.options.downsample = downsample;
+    }
+
+    /// Sets [`JxlDecoderOptions::crop`], e.g. to a region derived from the image size.
+    pub fn set_crop(&mut self, crop: Option<Rect>) {
+        self.options.crop = crop;
+    }
+
+    /// Returns the number of tokens decoded since the decoder was created, reset or rewound.
+    pub fn total_tokens(&self) -> u64 {
+        self.tokens_used
     }
 
     /// Returns the parsed frame index box, if the file contained one.
@@ -146,6 +284,12 @@ impl JxlDecoderInner {
         &self.codestream_parser.scanned_frames
     }
 
+    /// Returns a summary of how the image was compressed, once all frames have been parsed.
+    pub fn compression_summary(&self) -> Option<CompressionSummary> {
+        self.codestream_parser
+            .compression_summary(self.box_parser.total_codestream_consumed)
+    }
+
     /// Resets frame-level state to prepare for decoding a new frame.
     ///
     /// Preserves image-level state (file header, decoder state including
@@ -160,8 +304,10 @@ impl JxlDecoderInner {
     pub fn start_new_frame(&mut self, seek_target: VisibleFrameSeekTarget) {
         self.box_parser
             .reset_for_codestream_seek(seek_target.remaining_in_box);
-        self.codestream_parser
-            .start_new_frame(seek_target.visible_frames_to_skip);
+        self.codestream_parser.start_new_frame(
+            seek_target.visible_frames_to_skip,
+            seek_target.frame_indices,
+        );
     }
 
     #[cfg(test)]
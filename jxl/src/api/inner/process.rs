@@ -8,12 +8,18 @@ use std::{
     ops::{Deref, Range},
 };
 
-use crate::error::Result;
+use crate::{
+    error::Result,
+    image::set_thread_allocator,
+    util::{set_thread_token_budget, take_thread_timings, take_thread_tokens},
+};
 
 use crate::api::{JxlBitstreamInput, JxlDecoderInner, JxlOutputBuffer, ProcessingResult};
 
 // General implementation strategy:
-// - Anything that is not a section is read into a small buffer.
+// - Anything that is not a section is read into a small buffer. Headers are parsed again from
+//   the start of the buffer until they are complete, and only then consumed from it, so input
+//   can end anywhere in a header.
 // - As soon as we know section sizes, data is read directly into sections.
 // When the start of the populated range in `buf` goes past half of its length,
 // the data in the buffer is moved back to the beginning.
@@ -122,25 +128,39 @@ impl JxlDecoderInner {
         input: &mut dyn JxlBitstreamInput,
         buffers: Option<&mut [JxlOutputBuffer]>,
     ) -> Result<ProcessingResult<(), ()>> {
-        ProcessingResult::new(self.codestream_parser.process(
+        take_thread_timings();
+        set_thread_token_budget(self.tokens_used, self.options.max_total_tokens);
+        set_thread_allocator(Some(self.allocator.clone()));
+        let result = self.codestream_parser.process(
             &mut self.box_parser,
             input,
             &self.options,
             buffers,
             false,
-        ))
+        );
+        self.timings.merge(&take_thread_timings());
+        self.tokens_used = take_thread_tokens();
+        set_thread_allocator(None);
+        ProcessingResult::new(result)
     }
 
     /// Draws all the pixels we have data for.
     pub fn flush_pixels(&mut self, buffers: &mut [JxlOutputBuffer]) -> Result<()> {
         let mut input: &[u8] = &[];
-        match self.codestream_parser.process(
+        take_thread_timings();
+        set_thread_token_budget(self.tokens_used, self.options.max_total_tokens);
+        set_thread_allocator(Some(self.allocator.clone()));
+        let result = self.codestream_parser.process(
             &mut self.box_parser,
             &mut input,
             &self.options,
             Some(buffers),
             true,
-        ) {
+        );
+        self.timings.merge(&take_thread_timings());
+        self.tokens_used = take_thread_tokens();
+        set_thread_allocator(None);
+        match result {
             Ok(()) => Ok(()),
             Err(crate::error::Error::OutOfBounds(_)) => Ok(()),
             Err(e) => Err(e),
@@ -5,6 +5,17 @@
 
 use std::io::{BufRead, BufReader, Error, IoSliceMut, Read, Seek, SeekFrom};
 
+/// A source of codestream or container bytes.
+///
+/// The decoder copies every byte it reads into its own buffers, so the caller may discard bytes
+/// as soon as `read` or `skip` has consumed them, and input may be provided in chunks that end
+/// at arbitrary positions, including in the middle of a header field. Headers are parsed from
+/// the decoder's copy, restarting from the last byte boundary it reached, until they are
+/// complete. When the decoder returns
+/// [`NeedsMoreInput`](crate::api::ProcessingResult::NeedsMoreInput), bytes that were not
+/// consumed must be provided again, followed by new bytes, on the next call. Running out of
+/// bytes, or an empty input, means that no more bytes are available yet, never that the input
+/// ended, which is signaled with `JxlDecoder::end_input`.
 pub trait JxlBitstreamInput {
     /// Returns an estimate bound of the total number of bytes that can be read via `read`.
     /// Returning a too-low estimate here can impede parallelism. Returning a too-high
@@ -5,25 +5,38 @@
 
 // #![warn(missing_docs)]
 
+mod alpha;
 mod color;
 mod data_types;
 mod decoder;
+mod file_map;
 mod inner;
 mod input;
 mod options;
 mod signature;
+mod thumbnail;
 mod xyb_constants;
 
-pub use crate::image::JxlOutputBuffer;
+pub use crate::color::transfer;
+pub use crate::frame::{GroupId, LfGroupId, PassId, Section, SectionId, modular::Predictor};
+pub use crate::headers::{
+    color_encoding::RenderingIntent, frame_header::FrameFlags, image_metadata::Orientation,
+};
+#[cfg(feature = "debug-tools")]
+pub use crate::icc::{IccIssue, validate as validate_icc};
+pub use crate::image::{JxlAllocator, JxlMemoryUsage, JxlOutputBuffer};
+pub use crate::render::blit::{BlendMode, blit, blit_at, blit_channels};
+pub use crate::render::resample::{ResampleFilter, resample, resample_with_alpha};
+pub use alpha::*;
 pub use color::*;
 pub use data_types::*;
 pub use decoder::*;
+pub use file_map::*;
 pub use inner::*;
 pub use input::*;
 pub use options::*;
 pub use signature::*;
-
-use crate::headers::image_metadata::Orientation;
+pub use thumbnail::*;
 
 /// This type represents the return value of a function that reads input from a bitstream. The
 /// variant `Complete` indicates that the operation was completed successfully, and its return
@@ -33,6 +46,7 @@ use crate::headers::image_metadata::Orientation;
 /// information that might be needed to call the function again (i.e. because it takes a decoder
 /// object by value).
 #[derive(Debug, PartialEq)]
+#[must_use = "the decoder is returned either as `result` or as `fallback`"]
 pub enum ProcessingResult<T, U> {
     Complete { result: T },
     NeedsMoreInput { size_hint: usize, fallback: U },
@@ -53,6 +67,7 @@ impl<T> ProcessingResult<T, ()> {
     }
 }
 
+#[non_exhaustive]
 #[derive(Clone)]
 pub struct ToneMapping {
     pub intensity_target: f32,
@@ -61,6 +76,7 @@ pub struct ToneMapping {
     pub linear_below: f32,
 }
 
+#[non_exhaustive]
 #[derive(Clone)]
 pub struct JxlBasicInfo {
     pub size: (usize, usize),
@@ -3,7 +3,14 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::api::JxlCms;
+use std::sync::Arc;
+
+use crate::{
+    api::{JxlAllocator, JxlCms, JxlExtraChannel},
+    headers::frame_header::FrameHeader,
+    image::{ImageRectMut, Rect},
+    render::resample::ResampleFilter,
+};
 
 pub enum JxlProgressiveMode {
     /// Renders all pixels in every call to Process.
@@ -14,9 +21,180 @@ pub enum JxlProgressiveMode {
     FullFrame,
 }
 
+/// How to render images whose intensity target exceeds the luminance of SDR displays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderingChoice {
+    /// Map the luminances of the image to the SDR range with its tone mapping metadata, so that
+    /// the brightest parts of the image are compressed instead of clipped.
+    Sdr,
+    /// Pass through the full dynamic range of the image, with 1.0 corresponding to its intensity
+    /// target in linear outputs.
+    Hdr,
+}
+
+/// Order in which the pixels of the output are stored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputLayout {
+    /// Each row of the image is stored after the previous one, as in C and NumPy arrays.
+    #[default]
+    RowMajor,
+    /// Each column of the image is stored after the previous one, as in Fortran and MATLAB
+    /// arrays. Output buffers have one row per column of the image, so their width is the height
+    /// of the image and vice versa.
+    ColumnMajor,
+}
+
+/// Conformance level of the specification, which bounds what files can use so that decoders
+/// with limited resources can support them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JxlLevel {
+    /// The level of most images, such as those on the web.
+    Level5,
+    /// The level for professional and multispectral images, with higher limits.
+    Level10,
+}
+
+impl JxlLevel {
+    /// The largest number of extra channels that files of the level can have.
+    pub fn max_extra_channels(&self) -> usize {
+        match self {
+            Self::Level5 => 4,
+            Self::Level10 => 256,
+        }
+    }
+}
+
+/// Shortcuts in rendering that trade fidelity for decoding speed, each of which only applies to
+/// frames that use the corresponding feature. See [`SpeedProfile`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Simplifications {
+    /// Apply only the main pass of the edge-preserving filter, skipping the first and last of
+    /// the three passes that frames can signal.
+    pub skip_extra_epf_passes: bool,
+    /// Apply the Gaborish filter as a horizontal and a vertical 3-tap filter that blur as much as
+    /// the signaled 3x3 filter, which saves a third of its multiplications.
+    pub separable_gaborish: bool,
+    /// Do not synthesize the noise signaled by frames.
+    pub skip_noise: bool,
+    /// Upsample extra channels, such as alpha, by repeating their samples instead of with the
+    /// upsampling weights of the image.
+    pub nearest_extra_channel_upsampling: bool,
+}
+
+impl Simplifications {
+    /// All the simplifications.
+    pub const ALL: Self = Self {
+        skip_extra_epf_passes: true,
+        separable_gaborish: true,
+        skip_noise: true,
+        nearest_extra_channel_upsampling: true,
+    };
+
+    /// Returns whether no simplification is enabled.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns the simplifications that change the rendering of frames with `header`.
+    pub(crate) fn applied_to(&self, header: &FrameHeader) -> Self {
+        let filters = &header.restoration_filter;
+        Self {
+            skip_extra_epf_passes: self.skip_extra_epf_passes && filters.epf_iters >= 2,
+            separable_gaborish: self.separable_gaborish && filters.gab,
+            skip_noise: self.skip_noise && header.has_noise(),
+            nearest_extra_channel_upsampling: self.nearest_extra_channel_upsampling
+                && header.ec_upsampling.iter().any(|&up| up > 1),
+        }
+    }
+}
+
+impl std::fmt::Display for Simplifications {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = [
+            (self.skip_extra_epf_passes, "skipped extra EPF passes"),
+            (self.separable_gaborish, "separable Gaborish"),
+            (self.skip_noise, "skipped noise"),
+            (
+                self.nearest_extra_channel_upsampling,
+                "nearest extra channel upsampling",
+            ),
+        ];
+        let mut names = names.iter().filter(|(on, _)| *on).map(|(_, name)| *name);
+        match names.next() {
+            None => write!(f, "none"),
+            Some(first) => {
+                write!(f, "{first}")?;
+                names.try_for_each(|name| write!(f, ", {name}"))
+            }
+        }
+    }
+}
+
+/// Balance between fidelity and decoding speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpeedProfile {
+    /// Render frames exactly as signaled.
+    #[default]
+    Faithful,
+    /// Apply all of [`Simplifications::ALL`], for devices where battery life matters more than
+    /// the last bit of fidelity. Outside of noise, outputs stay within 40 dB PSNR of faithful
+    /// ones over the test images.
+    Fast,
+    /// Apply the given simplifications.
+    Custom(Simplifications),
+}
+
+impl SpeedProfile {
+    /// Returns the simplifications that the profile enables.
+    pub fn simplifications(&self) -> Simplifications {
+        match self {
+            Self::Faithful => Simplifications::default(),
+            Self::Fast => Simplifications::ALL,
+            Self::Custom(simplifications) => *simplifications,
+        }
+    }
+}
+
+/// Post-processing of extra channels, called with the index of the channel in
+/// [`JxlBasicInfo::extra_channels`](crate::api::JxlBasicInfo::extra_channels), its description
+/// and its samples. See [`JxlDecoderOptions::extra_channel_processor`].
+pub type ExtraChannelProcessor =
+    dyn Fn(usize, &JxlExtraChannel, &mut ImageRectMut<f32>) + Send + Sync;
+
+/// Options of [`JxlDecoder`](crate::api::JxlDecoder).
+///
+/// # Nesting limits
+///
+/// Besides the limits that can be configured here, the decoder bounds every structure of the
+/// file that can nest, so that the stack usage and the work per byte do not depend on the input:
+///
+/// - Container boxes are parsed one after the other and never nested. A `brob` box that
+///   compresses a box that cannot be compressed, such as a codestream or another `brob` box, is
+///   rejected with [`Error::InvalidCompressedBox`]. Other `brob` boxes are skipped without being
+///   decompressed.
+/// - The decoder never decodes a codestream from within another one: boxes that embed
+///   codestreams, such as gain maps (`jhgm`), are skipped, so nested codestreams are never
+///   recursed into, however deep they go.
+/// - Patches and blending read the frames saved in the 4 reference slots, which already store
+///   composited pixels, so a frame depends on at most one level of references and no chain of
+///   references is ever resolved.
+/// - Modular MA trees are decoded iteratively, and rejected with [`Error::TreeTooTall`] if they
+///   are more than 2048 levels deep.
+///
+/// [`Error::InvalidCompressedBox`]: crate::error::Error::InvalidCompressedBox
+/// [`Error::TreeTooTall`]: crate::error::Error::TreeTooTall
 #[non_exhaustive]
 pub struct JxlDecoderOptions {
+    /// Whether to write pixels in the orientation of the image, as a permutation of the samples
+    /// of all channels, instead of as stored. The size in `JxlBasicInfo` and the buffer
+    /// requirements are in the orientation pixels are written in. Default: true
     pub adjust_orientation: bool,
+    /// Whether to composite spot color channels onto the color channels, in the output color
+    /// space. Spot colors are stored in linear RGB, and converted to that space first. Grayscale
+    /// images with spot colors are then RGB: their default pixel format is
+    /// [`JxlColorType::Rgb`](crate::api::JxlColorType::Rgb), grayscale output is rejected, and
+    /// the output color profile stays grayscale. Default: true
     pub render_spot_colors: bool,
     pub coalescing: bool,
     pub desired_intensity_target: Option<f32>,
@@ -28,6 +206,10 @@ pub struct JxlDecoderOptions {
     /// channels, so for example an image with 1 extra channel of size 1024x1024 has 4
     /// million pixels.
     pub pixel_limit: Option<usize>,
+    /// Fail decoding once more than this number of entropy-coded tokens (such as modular
+    /// residuals and VarDCT coefficients) has been decoded, counting all frames. This bounds the
+    /// CPU time spent on small files that expand to a huge amount of work.
+    pub max_total_tokens: Option<u64>,
     /// Use high precision mode for decoding.
     /// When false (default), uses lower precision settings that match libjxl's default.
     /// When true, uses higher precision at the cost of performance.
@@ -39,11 +221,134 @@ pub struct JxlDecoderOptions {
     /// This produces premultiplied alpha output, which is useful for compositing.
     /// Default: false (output straight alpha)
     pub premultiply_output: bool,
+    /// Output grayscale images as RGB by default: the pixel format reported by
+    /// `JxlDecoder::current_pixel_format` has [`JxlColorType::Rgb`](crate::api::JxlColorType::Rgb)
+    /// instead of `Grayscale`. The gray channel is still rendered alone and is replicated when
+    /// writing the output, and the output color profile stays grayscale. Pixel formats set with
+    /// `JxlDecoder::set_pixel_format` are used as given. Default: false
+    pub force_rgb: bool,
+    /// Like `force_rgb`, but output all images as RGBA by default, with the alpha channel
+    /// interleaved with the colors instead of in its own buffer, and filled with 1.0 for images
+    /// without alpha. Default: false
+    pub force_rgba: bool,
     /// If true, only parse frame headers/TOC and skip section decoding.
     ///
     /// This is useful for collecting [`VisibleFrameInfo`](crate::api::VisibleFrameInfo)
     /// via the regular decoder API without producing pixels.
     pub scan_frames_only: bool,
+    /// Fail decoding images whose embedded ICC profile declares a size of more than this number
+    /// of bytes. Default: 16MiB
+    pub max_icc_size: usize,
+    /// Total number of bytes of frame names that [`VisibleFrameInfo`] entries keep while
+    /// scanning. The names of later frames are omitted, but their length is still recorded, so
+    /// that files with many long frame names need bounded memory. Default: 1MiB
+    ///
+    /// [`VisibleFrameInfo`]: crate::api::VisibleFrameInfo
+    pub max_frame_names_size: usize,
+    /// Fail decoding images with more extra channels than this level allows. Without it, images
+    /// can have up to the 4096 extra channels that the codestream can signal. Default: None
+    pub enforce_level: Option<JxlLevel>,
+    /// Number of bytes of whole sections past the next incomplete one that the size hint of
+    /// [`NeedsMoreInput`](crate::api::ProcessingResult::NeedsMoreInput) can include while
+    /// reading a frame, so that callers that supply the hinted bytes get several sections per
+    /// round trip. Headers are hinted in chunks of at least 1 KiB. Default: 0
+    pub input_readahead: usize,
+    /// Tolerate malformed metadata that does not affect pixels, such as frame names that are
+    /// not valid UTF-8, instead of failing. Also blend with reference frames that were saved
+    /// before the color transform, which libjxl rejects, by converting them first, and blend
+    /// with extra channels that are not alpha channels as alpha, clamped to [0, 1].
+    /// Default: false
+    pub permissive: bool,
+    /// Conceal the groups of frames whose sections fail to decode instead of failing, for files
+    /// with corrupted bytes. VarDCT groups are rendered without their AC coefficients, from their
+    /// DC alone, and the DC of VarDCT LF groups is interpolated from the neighbouring LF groups,
+    /// while the modular data of either is zeroed. The concealed groups of each frame are
+    /// reported by `JxlDecoder::concealed_groups`. Headers, global sections and frames stored in
+    /// a single section are still required to decode. Default: false
+    pub skip_corrupt_sections: bool,
+    /// Compare every decoded frame with the previous one and report the changed region through
+    /// `JxlDecoder::frame_diff`. Default: false
+    pub compute_frame_diffs: bool,
+    /// Resample the output to this size (in display orientation) with `resize_filter`, so
+    /// output buffers must have this size. Colors are filtered in linear light, except when
+    /// converting to an ICC profile. Default: None
+    pub resize_to: Option<(usize, usize)>,
+    /// Filter used to resample the output if `resize_to` is set. Default: Catmull-Rom
+    pub resize_filter: ResampleFilter,
+    /// Only output this region of the image (in display orientation, like `resize_to`), so
+    /// output buffers must have its size. The parts of frames that do not land on it are not
+    /// rendered, unless later frames need them. The region is resampled if `resize_to` is also
+    /// set. Decoding fails with `Error::InvalidCrop` if the region is empty or extends past the
+    /// image, or past the frames that are not extended to the image, such as the preview frame.
+    /// Default: None
+    pub crop: Option<Rect>,
+    /// Only decode the data of regular frames that is needed for an image at 1/`downsample` of
+    /// their resolution: the progressive passes up to the one that completes it, or only the LF
+    /// image of VarDCT frames for a factor of 8. Frames whose passes do not complete the factor
+    /// are decoded at the closest larger resolution, as reported by
+    /// [`JxlFrameHeader::downsample`](crate::api::JxlFrameHeader::downsample). Frames are still
+    /// rendered at their full size, so this is usually combined with `resize_to`. Default: 1
+    pub downsample: u32,
+    /// Keep the embedded ICC profile even when it disagrees with the color space of the image
+    /// header, for files that depend on decoders that always use it. See
+    /// [`JxlColorProfileMismatch`](crate::api::JxlColorProfileMismatch). Default: false
+    pub prefer_icc_profile: bool,
+    /// Fail decoding files whose container declares an image size that disagrees with the size
+    /// in the codestream, instead of decoding them with the size in the codestream. See
+    /// [`JxlSizeMismatch`](crate::api::JxlSizeMismatch). Default: false
+    pub reject_size_mismatch: bool,
+    /// Re-check every decoded modular stream against its residuals, and report the result of
+    /// each channel through `JxlDecoder::modular_checks`. Transforms local to a stream are
+    /// re-applied to its decoded channels first; those of the whole frame are only undone after
+    /// all of its streams are decoded, so they are not. Only has an effect with the `verify`
+    /// feature. Default: false
+    pub verify_modular: bool,
+    /// Keep every entropy code read from the codestream, with its symbol distributions, and
+    /// report them through `JxlDecoder::entropy_codes`. Only has an effect with the `debug-tools`
+    /// feature. Default: false
+    pub dump_entropy_codes: bool,
+    /// Whether to render HDR images for SDR or HDR displays. Outputs with a PQ or HLG transfer
+    /// function are always rendered as [`RenderingChoice::Hdr`], and so are images with an ICC
+    /// profile that are not XYB encoded, whose luminances cannot be mapped. `None` renders as
+    /// `Hdr`. Default: None
+    pub rendering_intent_override: Option<RenderingChoice>,
+    /// Whether to store the output row by row or column by column. Output buffer requirements
+    /// account for the layout, and `resize_to` stays in display orientation. Default: RowMajor
+    pub output_layout: OutputLayout,
+    /// Simplifications of the rendering to apply for speed. The ones that apply to each frame
+    /// are reported through `JxlFrameHeader::simplifications`. Default: Faithful
+    pub speed_profile: SpeedProfile,
+    /// Transforms each extra channel that has an output buffer once its frame is fully decoded,
+    /// before its samples are converted to the output format. The processor is called once per
+    /// channel and frame, with all the samples of the channel as `f32`, in the orientation and
+    /// layout of the output and at the full size of the frame, even with `resize_to`, which
+    /// resamples its result.
+    ///
+    /// The frame is rendered from the unprocessed channels: upsampling, blending, patches,
+    /// spot colors (with `render_spot_colors`) and premultiplication (with `premultiply_output`)
+    /// all happen before, and alpha that is interleaved with the colors is not processed.
+    /// Flushing writes unprocessed samples of the incomplete frame. Default: None
+    ///
+    /// ```
+    /// # use jxl::api::{JxlDecoderOptions, JxlExtraChannelType};
+    /// // Turn the alpha channel into a binary mask.
+    /// let mut options = JxlDecoderOptions::default();
+    /// options.extra_channel_processor = Some(Box::new(|_, channel, samples| {
+    ///     if channel.ec_type != JxlExtraChannelType::Alpha {
+    ///         return;
+    ///     }
+    ///     for y in 0..samples.size().1 {
+    ///         for value in samples.row(y) {
+    ///             *value = if *value < 0.5 { 0.0 } else { 1.0 };
+    ///         }
+    ///     }
+    /// }));
+    /// ```
+    pub extra_channel_processor: Option<Box<ExtraChannelProcessor>>,
+    /// Allocator of the image buffers of the decoder, which hold all of its large allocations
+    /// (pixels, coefficients, reference frames). `None` uses the global allocator. Either way,
+    /// the memory of these buffers is reported by `JxlDecoder::memory_usage`. Default: None
+    pub allocator: Option<Arc<dyn JxlAllocator>>,
 }
 
 impl Default for JxlDecoderOptions {
@@ -57,9 +362,32 @@ impl Default for JxlDecoderOptions {
             progressive_mode: JxlProgressiveMode::Pass,
             cms: None,
             pixel_limit: None,
+            max_total_tokens: None,
             high_precision: false,
             premultiply_output: false,
+            force_rgb: false,
+            force_rgba: false,
             scan_frames_only: false,
+            max_icc_size: 16 << 20,
+            max_frame_names_size: 1 << 20,
+            enforce_level: None,
+            input_readahead: 0,
+            permissive: false,
+            skip_corrupt_sections: false,
+            compute_frame_diffs: false,
+            resize_to: None,
+            resize_filter: ResampleFilter::default(),
+            crop: None,
+            downsample: 1,
+            prefer_icc_profile: false,
+            reject_size_mismatch: false,
+            verify_modular: false,
+            dump_entropy_codes: false,
+            rendering_intent_override: None,
+            output_layout: OutputLayout::RowMajor,
+            speed_profile: SpeedProfile::Faithful,
+            extra_channel_processor: None,
+            allocator: None,
         }
     }
 }
@@ -5,7 +5,9 @@
 
 use crate::{
     api::ProcessingResult,
+    bit_reader::BitReader,
     error::{Error, Result},
+    headers::{FileHeader, JxlHeader},
 };
 
 /// The magic bytes for a bare JPEG XL codestream.
@@ -62,11 +64,62 @@ pub fn check_signature(file_prefix: &[u8]) -> ProcessingResult<Option<JxlSignatu
     ProcessingResult::new(check_signature_internal(file_prefix)).unwrap()
 }
 
+/// The box that must follow the signature box of a container.
+const FTYP_BOX: [u8; 20] = [
+    0, 0, 0, 0x14, b'f', b't', b'y', b'p', b'j', b'x', b'l', b' ', 0, 0, 0, 0, b'j', b'x', b'l',
+    b' ',
+];
+
+/// Maximum number of bytes following a signature that [`find_stream`] looks at to validate it.
+pub const FIND_STREAM_LOOKAHEAD: usize = 64 * 1024;
+
+/// Returns whether a plausible stream of type `signature_type` starts at the beginning of `data`.
+/// Streams that are cut short by the end of `data` are considered plausible.
+fn is_plausible_stream(data: &[u8], signature_type: &JxlSignatureType) -> bool {
+    match signature_type {
+        JxlSignatureType::Container => {
+            let ftyp = &data[CONTAINER_SIGNATURE.len()..];
+            let len = ftyp.len().min(FTYP_BOX.len());
+            ftyp[..len] == FTYP_BOX[..len]
+        }
+        JxlSignatureType::Codestream => {
+            let data = &data[..data.len().min(FIND_STREAM_LOOKAHEAD)];
+            match FileHeader::read(&mut BitReader::new(data)) {
+                Ok(_) | Err(Error::OutOfBounds(_)) => true,
+                Err(_) => false,
+            }
+        }
+    }
+}
+
+/// Finds the offsets of JPEG XL codestreams and containers embedded in `haystack`, for example
+/// in a memory dump or inside another file format.
+///
+/// Signatures are validated by checking the headers that follow them, which rejects most
+/// accidental matches; decoding may still fail at the returned offsets.
+pub fn find_stream(haystack: &[u8]) -> Vec<usize> {
+    let mut offsets = vec![];
+    for (offset, window) in haystack.windows(CODESTREAM_SIGNATURE.len()).enumerate() {
+        let data = &haystack[offset..];
+        let signature_type = if window == CODESTREAM_SIGNATURE {
+            JxlSignatureType::Codestream
+        } else if data.starts_with(&CONTAINER_SIGNATURE) {
+            JxlSignatureType::Container
+        } else {
+            continue;
+        };
+        if is_plausible_stream(data, &signature_type) {
+            offsets.push(offset);
+        }
+    }
+    offsets
+}
+
 #[cfg(test)]
 mod tests {
     use crate::api::{
         CODESTREAM_SIGNATURE, CONTAINER_SIGNATURE, JxlSignatureType, ProcessingResult,
-        check_signature,
+        check_signature, find_stream,
     };
 
     macro_rules! signature_test {
@@ -159,4 +212,57 @@ mod tests {
         },
         Complete(Some(JxlSignatureType::Codestream))
     );
+
+    /// Random bytes without any signature in them.
+    fn garbage(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        let mut data = vec![];
+        while data.len() < len {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let b = (state >> 32) as u8;
+            // Avoid accidental signatures.
+            if b != 0xff && b != 0 {
+                data.push(b);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn find_embedded_streams() {
+        for file in ["basic.jxl", "has_permutation_with_container.jxl"] {
+            let stream = std::fs::read(format!("resources/test/{file}")).unwrap();
+            let mut haystack = garbage(1337, 1);
+            haystack.extend(&stream);
+            haystack.extend(garbage(100, 2));
+            // The codestream inside of a container is found too.
+            let offsets = find_stream(&haystack);
+            assert_eq!(offsets[0], 1337, "{file}");
+            assert!(offsets.len() <= 2, "{file}");
+            // Truncated streams are still found.
+            assert_eq!(find_stream(&haystack[..1337 + 16]), [1337], "{file}");
+        }
+        let stream = std::fs::read("resources/test/basic.jxl").unwrap();
+        let mut haystack = garbage(10, 6);
+        haystack.extend(&stream);
+        assert_eq!(find_stream(&haystack), [10]);
+        assert!(find_stream(&garbage(5000, 3)).is_empty());
+        assert!(find_stream(&[]).is_empty());
+    }
+
+    #[test]
+    fn find_stream_skips_false_positives() {
+        let stream = std::fs::read("resources/test/basic.jxl").unwrap();
+        let mut haystack = garbage(100, 4);
+        // A codestream signature followed by an invalid image header...
+        haystack.extend(CODESTREAM_SIGNATURE);
+        haystack.extend([0x55; 16]);
+        // ...and a container signature not followed by a file type box.
+        haystack.extend(CONTAINER_SIGNATURE);
+        haystack.extend(garbage(1337 - haystack.len(), 5));
+        haystack.extend(&stream);
+        assert_eq!(find_stream(&haystack), [1337]);
+    }
 }
@@ -0,0 +1,475 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::{
+        JxlColorEncoding, JxlColorProfile, JxlColorType, JxlDataFormat, JxlDecoder,
+        JxlDecoderOptions, JxlExtraChannelType, JxlOutputBuffer, JxlPixelFormat, ProcessingResult,
+        states,
+    },
+    bit_reader::BitReader,
+    container::ContainerParser,
+    error::{Error, ErrorContext, Result},
+    frame::{DecoderState, Frame, LfGroupId, Section},
+    headers::{
+        FileHeader, JxlHeader, Orientation,
+        encodings::UnconditionalCoder,
+        frame_header::{Encoding, FrameHeader, FrameType},
+        toc::{Toc, TocNonserialized},
+    },
+    icc::IncrementalIccReader,
+    image::{Image, Rect},
+    render::{
+        RenderPipelineInPlaceStage,
+        resample::{ColorSamples, ResampleFilter, resample_colors},
+        stages::{FromLinearStage, OutputColorInfo, TransferFunction, XybStage},
+    },
+};
+
+/// Decodes a small preview of the first frame of a JPEG XL file, for example for file managers.
+///
+/// The thumbnail is converted to sRGB, composited over a white background if the image has
+/// alpha, oriented for display, and resampled to fit within `max_dim` x `max_dim` pixels while
+/// preserving the aspect ratio. Images that are already small enough are not upsampled.
+///
+/// For VarDCT images with no extra channels, only the LF (1:8) image of the first frame is
+/// decoded, which is several times faster than a full decode. All other images fall back to a
+/// full decode of the first frame. As no CMS is involved, images that are not XYB-encoded are
+/// returned in their original color space.
+///
+/// Returns the thumbnail as an interleaved RGB image (so its width is 3 times the number of
+/// pixels per row), together with the display size of the full image.
+pub fn decode_thumbnail(bytes: &[u8], max_dim: usize) -> Result<(Image<u8>, (usize, usize))> {
+    if max_dim == 0 {
+        return Err(Error::InvalidThumbnailSize(max_dim));
+    }
+    let codestream = ContainerParser::collect_codestream(bytes)?;
+    let DecodedPlanes {
+        planes,
+        orientation,
+        image_size,
+    } = match decode_lf_planes(&codestream, max_dim)? {
+        Some(lf) => lf,
+        None => decode_full_planes(bytes)?,
+    };
+    // The LF image can be slightly smaller than a thumbnail computed from the full image size.
+    let (xsize, ysize) = thumbnail_size(image_size, max_dim);
+    let size = (xsize.min(planes[0].size().0), ysize.min(planes[0].size().1));
+    let samples = ColorSamples {
+        num_channels: 3,
+        has_alpha: false,
+        premultiplied: false,
+        transfer_function: Some(TransferFunction::Srgb),
+    };
+    let thumbnail = resample_colors(
+        &interleave(&planes)?,
+        &samples,
+        size,
+        ResampleFilter::default(),
+    )?;
+    Ok((
+        to_oriented_u8(&thumbnail, orientation)?,
+        orientation.map_size(image_size),
+    ))
+}
+
+/// Returns the largest size with the aspect ratio of `size` that fits within `max_dim`.
+pub(super) fn thumbnail_size((xsize, ysize): (usize, usize), max_dim: usize) -> (usize, usize) {
+    let largest = xsize.max(ysize);
+    if largest <= max_dim {
+        return (xsize, ysize);
+    }
+    let scale = |v: usize| ((v * max_dim + largest / 2) / largest).max(1);
+    (scale(xsize), scale(ysize))
+}
+
+type Planes = [Image<f32>; 3];
+
+/// An sRGB rendering of the first frame, possibly at reduced resolution.
+struct DecodedPlanes {
+    planes: Planes,
+    /// Orientation that still has to be applied to `planes`.
+    orientation: Orientation,
+    /// Size of the full image, in the same orientation as `planes`.
+    image_size: (usize, usize),
+}
+
+/// Decodes the LF image of the first frame of `codestream` and converts it to sRGB.
+///
+/// Returns `None` if the first frame cannot be rendered from its LF image alone, or if the LF
+/// image is too small to produce a thumbnail of size `max_dim`.
+fn decode_lf_planes(codestream: &[u8], max_dim: usize) -> Result<Option<DecodedPlanes>> {
+    let mut br = BitReader::new(codestream);
+    let file_header = FileHeader::read(&mut br)?;
+    let metadata = &file_header.image_metadata;
+    // We only render LF images for XYB VarDCT images with no extra channels, as in
+    // `Frame::maybe_preview_lf_frame`.
+    if !metadata.xyb_encoded || !metadata.extra_channel_info.is_empty() {
+        return Ok(None);
+    }
+    // The preview frame comes before the first frame; skipping it is not worth the complexity.
+    if metadata.preview.is_some() {
+        return Ok(None);
+    }
+    let image_size = (
+        file_header.size.xsize() as usize,
+        file_header.size.ysize() as usize,
+    );
+    let orientation = metadata.orientation;
+    let is_animation = metadata.animation.is_some();
+    if metadata.color_encoding.want_icc {
+        // The output is always sRGB, so the profile is only read to skip past it.
+        let mut icc_reader =
+            IncrementalIccReader::new(&mut br, JxlDecoderOptions::default().max_icc_size)?;
+        icc_reader.read_all(&mut br)?;
+        icc_reader.finalize(&mut br)?;
+    }
+    br.jump_to_byte_boundary()?;
+
+    let nonserialized = file_header.frame_header_nonserialized();
+    let mut frame_header = FrameHeader::read_unconditional(&(), &mut br, &nonserialized)?;
+    frame_header.postprocess(&nonserialized);
+    let lf_size = (
+        frame_header.size().0.div_ceil(8),
+        frame_header.size().1.div_ceil(8),
+    );
+    let standalone = frame_header.frame_type == FrameType::RegularFrame
+        && frame_header.encoding == Encoding::VarDCT
+        && !frame_header.has_lf_frame()
+        // Patches and splines are only added when rendering the full-resolution image.
+        && !frame_header.has_patches()
+        && !frame_header.has_splines()
+        && !frame_header.needs_blending()
+        && frame_header.upsampling == 1
+        && frame_header.size() == image_size
+        && frame_header.is_visible()
+        && (frame_header.is_last || is_animation);
+    if !standalone || lf_size.0.max(lf_size.1) < max_dim.min(image_size.0.max(image_size.1)) {
+        return Ok(None);
+    }
+
+    let toc = Toc::read_unconditional(
+        &(),
+        &mut br,
+        &TocNonserialized {
+            num_entries: frame_header.num_toc_entries() as u32,
+        },
+    )?;
+    br.jump_to_byte_boundary()?;
+
+    let color_info = OutputColorInfo::srgb(&file_header);
+    let mut frame = Frame::from_header_and_toc(frame_header, toc, DecoderState::new(file_header))?;
+    let mut sections = frame.sections(&mut br)?;
+    if let [section] = &mut sections[..] {
+        frame
+            .decode_lf_global(section, false)
+            .at_section(Section::LfGlobal)?;
+        frame
+            .decode_lf_group(LfGroupId::new(0), section)
+            .at_section(Section::Lf {
+                group: LfGroupId::new(0),
+            })?;
+    } else {
+        let id = frame.section_id(Section::LfGlobal);
+        frame
+            .decode_lf_global(&mut sections[id.index()], false)
+            .at_section(Section::LfGlobal)?;
+        for group in frame.header().group_layout().lf_groups() {
+            let section = Section::Lf { group };
+            let id = frame.section_id(section);
+            frame
+                .decode_lf_group(group, &mut sections[id.index()])
+                .at_section(section)?;
+        }
+    }
+    frame.finalize_lf()?;
+    let Some(lf_image) = frame.take_lf_image() else {
+        return Err(Error::internal("VarDCT frame without LF image"));
+    };
+
+    let xyb_stage = XybStage::new(0, color_info);
+    let from_linear_stage = FromLinearStage::new(0, TransferFunction::Srgb);
+    let mut planes = [
+        Image::new(lf_size)?,
+        Image::new(lf_size)?,
+        Image::new(lf_size)?,
+    ];
+    // Stages process whole SIMD vectors, so rows are padded to the largest vector size.
+    let mut rows = [(); 3].map(|_| vec![0.0f32; lf_size.0.next_multiple_of(16)]);
+    for y in 0..lf_size.1 {
+        for (row, lf) in rows.iter_mut().zip(lf_image.iter()) {
+            row[..lf_size.0].copy_from_slice(&lf.row(y)[..lf_size.0]);
+        }
+        let mut row_refs = rows.each_mut().map(|row| &mut row[..]);
+        xyb_stage.process_row_chunk((0, y), lf_size.0, &mut row_refs, None);
+        from_linear_stage.process_row_chunk((0, y), lf_size.0, &mut row_refs, None);
+        for (plane, row) in planes.iter_mut().zip(rows.iter()) {
+            plane.row_mut(y).copy_from_slice(&row[..lf_size.0]);
+        }
+    }
+    Ok(Some(DecodedPlanes {
+        planes,
+        orientation,
+        image_size,
+    }))
+}
+
+/// Decodes the first frame of `bytes` to oriented sRGB, composited over a white background.
+fn decode_full_planes(bytes: &[u8]) -> Result<DecodedPlanes> {
+    let options = JxlDecoderOptions {
+        premultiply_output: true,
+        ..Default::default()
+    };
+    let mut input = bytes;
+    let mut decoder = match JxlDecoder::<states::Initialized>::new(options).process(&mut input)? {
+        ProcessingResult::Complete { result } => result,
+        ProcessingResult::NeedsMoreInput { size_hint, .. } => {
+            return Err(Error::OutOfBounds(size_hint));
+        }
+    };
+    let basic_info = decoder.basic_info().clone();
+    let is_gray = decoder.current_pixel_format().color_type.is_grayscale();
+    let alpha_channel = basic_info
+        .extra_channels
+        .iter()
+        .position(|ec| ec.ec_type == JxlExtraChannelType::Alpha);
+    let color_type = match (is_gray, alpha_channel.is_some()) {
+        (false, false) => JxlColorType::Rgb,
+        (false, true) => JxlColorType::Rgba,
+        (true, false) => JxlColorType::Grayscale,
+        (true, true) => JxlColorType::GrayscaleAlpha,
+    };
+    decoder.set_pixel_format(JxlPixelFormat {
+        color_type,
+        color_data_format: Some(JxlDataFormat::f32()),
+        extra_channel_format: vec![None; basic_info.extra_channels.len()],
+    });
+    if !basic_info.uses_original_profile {
+        decoder
+            .set_output_color_profile(JxlColorProfile::Simple(JxlColorEncoding::srgb(is_gray)))?;
+    }
+
+    let (xsize, ysize) = basic_info.size;
+    let samples = color_type.samples_per_pixel();
+    let mut buffer = Image::<f32>::new((xsize * samples, ysize))?;
+    let mut output = [JxlOutputBuffer::from_image_rect_mut(
+        buffer
+            .get_rect_mut(Rect {
+                origin: (0, 0),
+                size: buffer.size(),
+            })
+            .into_raw(),
+    )];
+    let decoder = match decoder.process(&mut input)? {
+        ProcessingResult::Complete { result } => result,
+        ProcessingResult::NeedsMoreInput { size_hint, .. } => {
+            return Err(Error::OutOfBounds(size_hint));
+        }
+    };
+    if let ProcessingResult::NeedsMoreInput { size_hint, .. } =
+        decoder.process(&mut input, &mut output)?
+    {
+        return Err(Error::OutOfBounds(size_hint));
+    }
+
+    let mut planes = [
+        Image::new((xsize, ysize))?,
+        Image::new((xsize, ysize))?,
+        Image::new((xsize, ysize))?,
+    ];
+    for y in 0..ysize {
+        let row = buffer.row(y);
+        for (x, pixel) in row.chunks_exact(samples).enumerate() {
+            // Colors are premultiplied, so compositing over white only adds the background.
+            let background = if color_type.has_alpha() {
+                1.0 - pixel[samples - 1]
+            } else {
+                0.0
+            };
+            for (c, plane) in planes.iter_mut().enumerate() {
+                let value = if is_gray { pixel[0] } else { pixel[c] };
+                plane.row_mut(y)[x] = value + background;
+            }
+        }
+    }
+    Ok(DecodedPlanes {
+        planes,
+        orientation: Orientation::Identity,
+        image_size: basic_info.size,
+    })
+}
+
+/// Interleaves the samples of `planes`, as expected by the resampler.
+fn interleave(planes: &Planes) -> Result<Image<f32>> {
+    let (xsize, ysize) = planes[0].size();
+    let mut out = Image::new((xsize * 3, ysize))?;
+    for y in 0..ysize {
+        for (x, pixel) in out.row_mut(y).chunks_exact_mut(3).enumerate() {
+            for (value, plane) in pixel.iter_mut().zip(planes.iter()) {
+                *value = plane.row(y)[x];
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Converts interleaved RGB `image` to 8 bits, in display orientation.
+fn to_oriented_u8(image: &Image<f32>, orientation: Orientation) -> Result<Image<u8>> {
+    let size = (image.size().0 / 3, image.size().1);
+    let (xsize, ysize) = orientation.map_size(size);
+    let mut out = Image::<u8>::new((xsize * 3, ysize))?;
+    for y in 0..size.1 {
+        for (x, pixel) in image.row(y).chunks_exact(3).enumerate() {
+            let (dx, dy) = orientation.display_pixel((x, y), size);
+            for (value, sample) in out.row_mut(dy)[dx * 3..dx * 3 + 3].iter_mut().zip(pixel) {
+                *value = (sample.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::transfer::srgb_to_linear_u8_lut;
+
+    fn full_decode_u8(bytes: &[u8]) -> (Image<u8>, (usize, usize)) {
+        let DecodedPlanes {
+            planes,
+            orientation,
+            image_size: size,
+        } = decode_full_planes(bytes).unwrap();
+        (
+            to_oriented_u8(&interleave(&planes).unwrap(), orientation).unwrap(),
+            orientation.map_size(size),
+        )
+    }
+
+    /// Averages colors in linear light, as thumbnails are resampled, scaled to `0..255`.
+    fn average_color(image: &Image<u8>) -> [f32; 3] {
+        let (xsize, ysize) = image.size();
+        let to_linear = srgb_to_linear_u8_lut();
+        let mut sum = [0.0f32; 3];
+        for y in 0..ysize {
+            for pixel in image.row(y).chunks_exact(3) {
+                for c in 0..3 {
+                    sum[c] += to_linear[pixel[c] as usize];
+                }
+            }
+        }
+        sum.map(|s| s * 255.0 / (xsize / 3 * ysize) as f32)
+    }
+
+    fn check_average_color(path: &str, max_dim: usize) {
+        let bytes = std::fs::read(path).unwrap();
+        let (thumbnail, size) = decode_thumbnail(&bytes, max_dim).unwrap();
+        let (full, full_size) = full_decode_u8(&bytes);
+        assert_eq!(size, full_size);
+        let (xsize, ysize) = thumbnail.size();
+        let (xsize, ysize) = (xsize / 3, ysize);
+        assert!(xsize.max(ysize) <= max_dim);
+        assert!(xsize.max(ysize) >= max_dim.min(size.0.max(size.1)) - 1);
+        // Aspect ratio is preserved up to rounding.
+        let expected_ysize = xsize as f32 * size.1 as f32 / size.0 as f32;
+        assert!((expected_ysize - ysize as f32).abs() <= 1.0 + expected_ysize / xsize as f32);
+        let thumbnail_color = average_color(&thumbnail);
+        let full_color = average_color(&full);
+        for c in 0..3 {
+            // LF images average blocks in XYB rather than in linear light, which is darker.
+            assert!(
+                (thumbnail_color[c] - full_color[c]).abs() < 4.0 + full_color[c] * 0.1,
+                "{path}: average color {thumbnail_color:?} vs {full_color:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn average_color_vardct() {
+        for path in [
+            "resources/test/zoltan_tasi_unsplash.jxl",
+            "resources/test/progressive_ac.jxl",
+        ] {
+            let codestream =
+                ContainerParser::collect_codestream(&std::fs::read(path).unwrap()).unwrap();
+            assert!(decode_lf_planes(&codestream, 64).unwrap().is_some());
+            check_average_color(path, 64);
+        }
+    }
+
+    #[test]
+    fn splines_need_full_decode() {
+        let path = "resources/test/conformance_test_images/animation_spline.jxl";
+        let codestream =
+            ContainerParser::collect_codestream(&std::fs::read(path).unwrap()).unwrap();
+        assert!(decode_lf_planes(&codestream, 16).unwrap().is_none());
+        check_average_color(path, 16);
+    }
+
+    #[test]
+    fn average_color_modular() {
+        check_average_color("resources/test/green_queen_modular_e3.jxl", 48);
+    }
+
+    #[test]
+    fn alpha_over_white() {
+        check_average_color(
+            "resources/test/conformance_test_images/alpha_nonpremultiplied.jxl",
+            32,
+        );
+    }
+
+    #[test]
+    fn orientation() {
+        let path = "resources/test/conformance_test_images/bench_oriented_brg.jxl";
+        let bytes = std::fs::read(path).unwrap();
+        let (thumbnail, size) = decode_thumbnail(&bytes, 16).unwrap();
+        let (full, _) = full_decode_u8(&bytes);
+        assert_eq!(
+            size.0 >= size.1,
+            thumbnail.size().0 / 3 >= thumbnail.size().1
+        );
+        // The top-left corners of both renders should match.
+        let corner = &thumbnail.row(0)[..3];
+        let full_corner = &full.row(0)[..3];
+        for c in 0..3 {
+            assert!(
+                corner[c].abs_diff(full_corner[c]) < 32,
+                "{corner:?} vs {full_corner:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn oriented_output() {
+        // A 2x1 image with a red and a green pixel.
+        let mut image = Image::new((6, 1)).unwrap();
+        image
+            .row_mut(0)
+            .copy_from_slice(&[1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        let out = to_oriented_u8(&image, Orientation::Rotate90Cw).unwrap();
+        assert_eq!(out.size(), (3, 2));
+        assert_eq!(out.row(0), &[255, 0, 0]);
+        assert_eq!(out.row(1), &[0, 255, 0]);
+        let out = to_oriented_u8(&image, Orientation::FlipHorizontal).unwrap();
+        assert_eq!(out.row(0), &[0, 255, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    fn small_images_are_not_upsampled() {
+        assert_eq!(thumbnail_size((10, 5), 64), (10, 5));
+        assert_eq!(thumbnail_size((1000, 500), 64), (64, 32));
+        assert_eq!(thumbnail_size((3, 1000), 64), (1, 64));
+    }
+
+    #[test]
+    fn zero_max_dim() {
+        let bytes = std::fs::read("resources/test/green_queen_vardct_e3.jxl").unwrap();
+        assert!(matches!(
+            decode_thumbnail(&bytes, 0),
+            Err(Error::InvalidThumbnailSize(0))
+        ));
+    }
+}
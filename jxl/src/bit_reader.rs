@@ -74,16 +74,6 @@ impl<'a> BitReader<'a> {
     }
 
     /// Reads `num` bits from the buffer.
-    /// ```
-    /// # use jxl::bit_reader::BitReader;
-    /// let mut br = BitReader::new(&[0, 1]);
-    /// assert_eq!(br.read(8)?, 0);
-    /// assert_eq!(br.read(4)?, 1);
-    /// assert_eq!(br.read(4)?, 0);
-    /// assert_eq!(br.total_bits_read(), 16);
-    /// assert!(br.read(1).is_err());
-    /// # Ok::<(), jxl::error::Error>(())
-    /// ```
     #[inline]
     pub fn read(&mut self, num: usize) -> Result<u64, Error> {
         let ret = self.peek(num);
@@ -106,7 +96,9 @@ impl<'a> BitReader<'a> {
 
     pub fn check_for_error(&self) -> Result<(), Error> {
         if self.total_bits_read > self.initial_bits {
-            Err(Error::OutOfBounds(self.total_bits_read - self.initial_bits))
+            Err(Error::OutOfBounds(
+                (self.total_bits_read - self.initial_bits).div_ceil(8),
+            ))
         } else {
             Ok(())
         }
@@ -118,19 +110,12 @@ impl<'a> BitReader<'a> {
     }
 
     /// Returns the total number of bits that can still be read or skipped.
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
     pub fn total_bits_available(&self) -> usize {
         self.data.len() * 8 + self.bits_in_buf
     }
 
     /// Skips `num` bits.
-    /// ```
-    /// # use jxl::bit_reader::BitReader;
-    /// let mut br = BitReader::new(&[0, 1]);
-    /// assert_eq!(br.read(8)?, 0);
-    /// br.skip_bits(4)?;
-    /// assert_eq!(br.total_bits_read(), 12);
-    /// # Ok::<(), jxl::error::Error>(())
-    /// ```
     #[inline(never)]
     pub fn skip_bits(&mut self, mut n: usize) -> Result<(), Error> {
         // Check if we can skip within the current buffer
@@ -151,7 +136,7 @@ impl<'a> BitReader<'a> {
         let bits_available = self.data.len() * 8;
         if n > bits_available {
             self.total_bits_read += bits_available;
-            return Err(Error::OutOfBounds(n - bits_available));
+            return Err(Error::OutOfBounds((n - bits_available).div_ceil(8)));
         }
 
         // Skip bytes directly in `data`, then handle leftover bits
@@ -171,7 +156,7 @@ impl<'a> BitReader<'a> {
         self.bit_buf >>= to_consume;
         self.bits_in_buf -= to_consume;
         if n > 0 {
-            Err(Error::OutOfBounds(n))
+            Err(Error::OutOfBounds(n.div_ceil(8)))
         } else {
             Ok(())
         }
@@ -184,15 +169,6 @@ impl<'a> BitReader<'a> {
     }
 
     /// Jumps to the next byte boundary. The skipped bytes have to be 0.
-    /// ```
-    /// # use jxl::bit_reader::BitReader;
-    /// let mut br = BitReader::new(&[0, 1]);
-    /// assert_eq!(br.read(8)?, 0);
-    /// br.skip_bits(4)?;
-    /// br.jump_to_byte_boundary()?;
-    /// assert_eq!(br.total_bits_read(), 16);
-    /// # Ok::<(), jxl::error::Error>(())
-    /// ```
     #[inline(never)]
     pub fn jump_to_byte_boundary(&mut self) -> Result<(), Error> {
         if self.read(self.bits_to_next_byte())? != 0 {
@@ -253,6 +229,36 @@ impl<'a> BitReader<'a> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn read() -> Result<(), Error> {
+        let mut br = BitReader::new(&[0, 1]);
+        assert_eq!(br.read(8)?, 0);
+        assert_eq!(br.read(4)?, 1);
+        assert_eq!(br.read(4)?, 0);
+        assert_eq!(br.total_bits_read(), 16);
+        assert!(br.read(1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn skip_bits() -> Result<(), Error> {
+        let mut br = BitReader::new(&[0, 1]);
+        assert_eq!(br.read(8)?, 0);
+        br.skip_bits(4)?;
+        assert_eq!(br.total_bits_read(), 12);
+        Ok(())
+    }
+
+    #[test]
+    fn jump_to_byte_boundary() -> Result<(), Error> {
+        let mut br = BitReader::new(&[0, 1]);
+        assert_eq!(br.read(8)?, 0);
+        br.skip_bits(4)?;
+        br.jump_to_byte_boundary()?;
+        assert_eq!(br.total_bits_read(), 16);
+        Ok(())
+    }
+
     #[test]
     fn test_skip_bits_on_fresh_reader() {
         // This test checks if skip_bits works correctly on a fresh BitReader
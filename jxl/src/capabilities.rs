@@ -0,0 +1,228 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! What this build of the crate can do, for applications that cannot see its cargo features,
+//! such as bindings to other languages.
+
+use crate::simd::{Dispatch, SimdTier};
+
+/// Capabilities of this build of the crate, see [`capabilities`].
+///
+/// The flags are set by the modules that implement them, so a capability that is compiled out
+/// or not implemented is never reported.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Frames can be rendered before they are fully decoded, with
+    /// [`JxlDecoder::flush_pixels`](crate::api::JxlDecoder::flush_pixels).
+    pub progressive_flush: bool,
+    /// A region of the image can be decoded alone, see
+    /// [`JxlDecoderOptions::crop`](crate::api::JxlDecoderOptions::crop).
+    pub crop: bool,
+    /// Progressive frames can be decoded at a lower resolution, see
+    /// [`JxlDecoderOptions::downsample`](crate::api::JxlDecoderOptions::downsample).
+    pub downsample: bool,
+    /// The output can be resampled to another size, see
+    /// [`JxlDecoderOptions::resize_to`](crate::api::JxlDecoderOptions::resize_to).
+    pub resize: bool,
+    /// HDR images can be tone mapped to a lower intensity target, see
+    /// [`JxlDecoderOptions::desired_intensity_target`](crate::api::JxlDecoderOptions::desired_intensity_target).
+    pub tone_mapping: bool,
+    /// JPEG files can be reconstructed from their `jbrd` box.
+    pub jpeg_reconstruction: bool,
+    /// The time spent in each decoding stage is measured, with the `timing-stats` feature.
+    pub decode_timings: bool,
+    /// Statistics about modular streams are collected, with the `timing-stats` feature.
+    pub modular_stats: bool,
+    /// Decoded modular streams can be re-checked, with the `verify` feature.
+    pub modular_verification: bool,
+    /// Entropy codes can be dumped, with the `debug-tools` feature.
+    pub entropy_code_dump: bool,
+    /// Boxes can be written Brotli-compressed, with the `brotli` feature.
+    pub brotli_boxes: bool,
+    /// Internal invariants are validated, with the `paranoid-checks` feature.
+    pub paranoid_checks: bool,
+    /// Largest number of threads that decoding a single image uses.
+    pub max_threads: usize,
+    /// SIMD tiers that the crate has kernels for and the CPU supports, from the least capable.
+    pub simd_tiers: Vec<SimdTier>,
+    /// SIMD tier that kernels are dispatched to.
+    pub simd_tier: SimdTier,
+}
+
+/// Name of a boolean capability, its bit in [`Capabilities::bits`] and how to read it.
+type Flag = (&'static str, u64, fn(&Capabilities) -> bool);
+
+const FLAGS: &[Flag] = &[
+    ("progressive_flush", Capabilities::PROGRESSIVE_FLUSH, |c| {
+        c.progressive_flush
+    }),
+    ("crop", Capabilities::CROP, |c| c.crop),
+    ("downsample", Capabilities::DOWNSAMPLE, |c| c.downsample),
+    ("resize", Capabilities::RESIZE, |c| c.resize),
+    ("tone_mapping", Capabilities::TONE_MAPPING, |c| {
+        c.tone_mapping
+    }),
+    (
+        "jpeg_reconstruction",
+        Capabilities::JPEG_RECONSTRUCTION,
+        |c| c.jpeg_reconstruction,
+    ),
+    ("decode_timings", Capabilities::DECODE_TIMINGS, |c| {
+        c.decode_timings
+    }),
+    ("modular_stats", Capabilities::MODULAR_STATS, |c| {
+        c.modular_stats
+    }),
+    (
+        "modular_verification",
+        Capabilities::MODULAR_VERIFICATION,
+        |c| c.modular_verification,
+    ),
+    ("entropy_code_dump", Capabilities::ENTROPY_CODE_DUMP, |c| {
+        c.entropy_code_dump
+    }),
+    ("brotli_boxes", Capabilities::BROTLI_BOXES, |c| {
+        c.brotli_boxes
+    }),
+    ("paranoid_checks", Capabilities::PARANOID_CHECKS, |c| {
+        c.paranoid_checks
+    }),
+];
+
+/// Functions of the modules that implement capabilities, each of which sets its own flags.
+const REGISTRATIONS: &[fn(&mut Capabilities)] = &[
+    crate::api::register_progressive_decoding,
+    crate::render::save::register_crop,
+    crate::render::resample::register_resize,
+    crate::render::stages::register_tone_mapping,
+    crate::util::register_decode_timings,
+    crate::frame::modular::register_modular_stats,
+    crate::frame::modular::register_modular_verification,
+    crate::entropy_coding::dump::register_entropy_code_dump,
+    crate::container::writer::register_brotli_boxes,
+    crate::util::register_paranoid_checks,
+];
+
+impl Capabilities {
+    pub const PROGRESSIVE_FLUSH: u64 = 1 << 0;
+    pub const CROP: u64 = 1 << 1;
+    pub const DOWNSAMPLE: u64 = 1 << 2;
+    pub const RESIZE: u64 = 1 << 3;
+    pub const TONE_MAPPING: u64 = 1 << 4;
+    pub const JPEG_RECONSTRUCTION: u64 = 1 << 5;
+    pub const DECODE_TIMINGS: u64 = 1 << 6;
+    pub const MODULAR_STATS: u64 = 1 << 7;
+    pub const MODULAR_VERIFICATION: u64 = 1 << 8;
+    pub const ENTROPY_CODE_DUMP: u64 = 1 << 9;
+    pub const BROTLI_BOXES: u64 = 1 << 10;
+    pub const PARANOID_CHECKS: u64 = 1 << 11;
+
+    /// Returns the boolean capabilities as a bitmask of the constants above, for bindings that
+    /// cannot use this struct. Bits are never reassigned.
+    pub fn bits(&self) -> u64 {
+        FLAGS
+            .iter()
+            .filter(|(_, _, get)| get(self))
+            .fold(0, |bits, (_, bit, _)| bits | bit)
+    }
+
+    /// Returns the name and value of each boolean capability.
+    pub fn flags(&self) -> impl Iterator<Item = (&'static str, bool)> + '_ {
+        FLAGS.iter().map(|(name, _, get)| (*name, get(self)))
+    }
+}
+
+/// Returns the capabilities of this build of the crate, on this CPU.
+pub fn capabilities() -> Capabilities {
+    let mut capabilities = Capabilities {
+        progressive_flush: false,
+        crop: false,
+        downsample: false,
+        resize: false,
+        tone_mapping: false,
+        jpeg_reconstruction: false,
+        decode_timings: false,
+        modular_stats: false,
+        modular_verification: false,
+        entropy_code_dump: false,
+        brotli_boxes: false,
+        paranoid_checks: false,
+        // Images are decoded on the thread that calls the decoder.
+        max_threads: 1,
+        simd_tiers: [
+            SimdTier::Scalar,
+            SimdTier::Sse42,
+            SimdTier::Avx2,
+            SimdTier::Avx512,
+            SimdTier::Neon,
+        ]
+        .into_iter()
+        .filter(|tier| tier.is_supported())
+        .collect(),
+        simd_tier: Dispatch::active_tier(),
+    };
+    for register in REGISTRATIONS {
+        register(&mut capabilities);
+    }
+    capabilities
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn capabilities_match_enabled_features() {
+        let capabilities = capabilities();
+        assert!(capabilities.progressive_flush);
+        assert!(capabilities.crop);
+        assert!(capabilities.downsample);
+        assert!(capabilities.resize);
+        assert!(capabilities.tone_mapping);
+        assert!(!capabilities.jpeg_reconstruction);
+        assert_eq!(capabilities.decode_timings, cfg!(feature = "timing-stats"));
+        assert_eq!(capabilities.modular_stats, cfg!(feature = "timing-stats"));
+        assert_eq!(capabilities.modular_verification, cfg!(feature = "verify"));
+        assert_eq!(
+            capabilities.entropy_code_dump,
+            cfg!(feature = "debug-tools")
+        );
+        assert_eq!(capabilities.brotli_boxes, cfg!(feature = "brotli"));
+        assert_eq!(
+            capabilities.paranoid_checks,
+            cfg!(feature = "paranoid-checks")
+        );
+        assert_eq!(capabilities.max_threads, 1);
+        assert!(capabilities.simd_tiers.contains(&capabilities.simd_tier));
+        if !cfg!(any(
+            feature = "all-simd",
+            feature = "sse42",
+            feature = "avx",
+            feature = "avx512",
+            feature = "neon"
+        )) {
+            assert_eq!(capabilities.simd_tiers, [SimdTier::Scalar]);
+        }
+    }
+
+    #[test]
+    fn bits_match_flags() {
+        let capabilities = capabilities();
+        let bits = capabilities.bits();
+        assert_eq!(
+            bits & Capabilities::DECODE_TIMINGS != 0,
+            capabilities.decode_timings
+        );
+        assert_eq!(bits & Capabilities::JPEG_RECONSTRUCTION, 0);
+        let mut all = 0;
+        for (&(name, bit, _), (_, value)) in FLAGS.iter().zip(capabilities.flags()) {
+            assert_eq!(bit.count_ones(), 1, "{name}");
+            assert_eq!(all & bit, 0, "{name} reuses a bit");
+            all |= bit;
+            assert_eq!(bits & bit != 0, value, "{name}");
+        }
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Extraction of the colorimetry of simple (matrix/TRC) ICC profiles, so that profiles written
+//! by different tools can be compared.
+
+/// Number of points at which tone curves are sampled when comparing them.
+const CURVE_SAMPLES: usize = 64;
+
+/// Tone reproduction curve of a single channel.
+#[derive(Clone, Debug, PartialEq)]
+enum ToneCurve {
+    Gamma(f32),
+    /// Samples evenly spaced over [0, 1], interpolated linearly.
+    Table(Vec<f32>),
+    /// ICC parametric curve, with parameters g, a, b, c, d, e, f.
+    Parametric {
+        function_type: u16,
+        params: [f32; 7],
+    },
+}
+
+impl ToneCurve {
+    fn eval(&self, x: f32) -> f32 {
+        match self {
+            ToneCurve::Gamma(g) => x.powf(*g),
+            ToneCurve::Table(table) => {
+                let pos = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+                let i = (pos as usize).min(table.len() - 2);
+                let frac = pos - i as f32;
+                table[i] + (table[i + 1] - table[i]) * frac
+            }
+            ToneCurve::Parametric {
+                function_type,
+                params: [g, a, b, c, d, e, f],
+            } => {
+                let power = |x: f32| (a * x + b).max(0.0).powf(*g);
+                match function_type {
+                    0 => x.powf(*g),
+                    1 if x >= -b / a => power(x),
+                    1 => 0.0,
+                    2 if x >= -b / a => power(x) + c,
+                    2 => *c,
+                    3 if x >= *d => power(x),
+                    3 => c * x,
+                    _ if x >= *d => power(x) + e,
+                    _ => c * x + f,
+                }
+            }
+        }
+    }
+
+    fn parse(data: &[u8]) -> Option<ToneCurve> {
+        match data.get(0..4)? {
+            b"curv" => {
+                let count = read_u32(data, 8)? as usize;
+                match count {
+                    0 => Some(ToneCurve::Gamma(1.0)),
+                    1 => Some(ToneCurve::Gamma(read_u16(data, 12)? as f32 / 256.0)),
+                    _ => (0..count)
+                        .map(|i| Some(read_u16(data, 12 + 2 * i)? as f32 / 65535.0))
+                        .collect::<Option<_>>()
+                        .map(ToneCurve::Table),
+                }
+            }
+            b"para" => {
+                let function_type = read_u16(data, 8)?;
+                let num_params = [1, 3, 4, 5, 7].get(function_type as usize)?;
+                let mut params = [0.0; 7];
+                for (i, param) in params.iter_mut().enumerate().take(*num_params) {
+                    *param = read_s15_fixed16(data, 12 + 4 * i)?;
+                }
+                Some(ToneCurve::Parametric {
+                    function_type,
+                    params,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Colorimetry of an RGB or grayscale ICC profile that is defined by colorants and tone curves
+/// only.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MatrixTrcProfile {
+    /// D50-adapted XYZ of the red, green and blue colorants, or `None` for grayscale profiles.
+    colorants: Option<[[f32; 3]; 3]>,
+    /// One curve for grayscale profiles, three for RGB.
+    curves: Vec<ToneCurve>,
+}
+
+impl MatrixTrcProfile {
+    /// Parses `icc`, returning `None` if it is not a matrix/TRC RGB or grayscale profile.
+    /// Profiles with lookup-table transforms are rejected, as CMSs give those precedence.
+    pub(crate) fn parse(icc: &[u8]) -> Option<MatrixTrcProfile> {
+        let tag = |signature: &[u8; 4]| -> Option<&[u8]> {
+            let num_tags = read_u32(icc, 128)? as usize;
+            (0..num_tags).find_map(|i| {
+                let entry = 132 + 12 * i;
+                if icc.get(entry..entry + 4)? != signature {
+                    return None;
+                }
+                let offset = read_u32(icc, entry + 4)? as usize;
+                let size = read_u32(icc, entry + 8)? as usize;
+                icc.get(offset..offset.checked_add(size)?)
+            })
+        };
+        if tag(b"A2B0").is_some() || tag(b"B2A0").is_some() {
+            return None;
+        }
+        let curve = |signature| ToneCurve::parse(tag(signature)?);
+        match icc.get(16..20)? {
+            b"GRAY" => Some(MatrixTrcProfile {
+                colorants: None,
+                curves: vec![curve(b"kTRC")?],
+            }),
+            b"RGB " => {
+                let xyz = |signature| -> Option<[f32; 3]> {
+                    let data = tag(signature)?;
+                    if data.get(0..4)? != b"XYZ " {
+                        return None;
+                    }
+                    Some([
+                        read_s15_fixed16(data, 8)?,
+                        read_s15_fixed16(data, 12)?,
+                        read_s15_fixed16(data, 16)?,
+                    ])
+                };
+                Some(MatrixTrcProfile {
+                    colorants: Some([xyz(b"rXYZ")?, xyz(b"gXYZ")?, xyz(b"bXYZ")?]),
+                    curves: vec![curve(b"rTRC")?, curve(b"gTRC")?, curve(b"bTRC")?],
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns true if colorants and tone curves (sampled at evenly spaced points) all differ by
+    /// at most `tolerance`.
+    pub(crate) fn is_approx_equivalent(&self, other: &MatrixTrcProfile, tolerance: f32) -> bool {
+        let colorants_match = match (&self.colorants, &other.colorants) {
+            (Some(a), Some(b)) => a
+                .iter()
+                .flatten()
+                .zip(b.iter().flatten())
+                .all(|(a, b)| (a - b).abs() <= tolerance),
+            (None, None) => true,
+            _ => false,
+        };
+        colorants_match
+            && self.curves.len() == other.curves.len()
+            && self.curves.iter().zip(&other.curves).all(|(a, b)| {
+                (0..CURVE_SAMPLES).all(|i| {
+                    let x = i as f32 / (CURVE_SAMPLES - 1) as f32;
+                    (a.eval(x) - b.eval(x)).abs() <= tolerance
+                })
+            })
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_s15_fixed16(data: &[u8], pos: usize) -> Option<f32> {
+    Some(read_u32(data, pos)? as i32 as f32 / 65536.0)
+}
@@ -3,4 +3,6 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+pub(crate) mod icc_profile;
 pub mod tf;
+pub mod transfer;
@@ -3,7 +3,12 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::util::{eval_rational_poly, eval_rational_poly_simd};
+#[cfg(test)]
+use super::transfer;
+use super::transfer::{HLG_A, HLG_B, HLG_C};
+#[cfg(test)]
+use crate::util::eval_rational_poly;
+use crate::util::eval_rational_poly_simd;
 use jxl_simd::{F32SimdVec, SimdDescriptor, SimdMask};
 
 /// Converts the linear samples with the sRGB transfer curve (SIMD version).
@@ -43,6 +48,7 @@ pub fn linear_to_srgb_simd<D: SimdDescriptor>(d: D, samples: &mut [f32]) {
 }
 
 /// Converts samples in sRGB transfer curve to linear. Inverse of `linear_to_srgb`.
+#[cfg(test)]
 pub fn srgb_to_linear(samples: &mut [f32]) {
     #[allow(clippy::excessive_precision)]
     const P: [f32; 5] = [
@@ -147,6 +153,7 @@ pub fn linear_to_bt709_simd<D: SimdDescriptor>(d: D, samples: &mut [f32]) {
 }
 
 /// Converts samples in BT.709 transfer curve to linear. Inverse of `linear_to_bt709_simd`.
+#[cfg(test)]
 pub fn bt709_to_linear(samples: &mut [f32]) {
     for s in samples {
         let a = s.abs();
@@ -184,30 +191,16 @@ pub fn bt709_to_linear_simd<D: SimdDescriptor>(d: D, xsize: usize, samples: &mut
     }
 }
 
-const PQ_M1: f64 = 2610.0 / 16384.0;
-const PQ_M2: f64 = (2523.0 / 4096.0) * 128.0;
-const PQ_C1: f64 = 3424.0 / 4096.0;
-const PQ_C2: f64 = (2413.0 / 4096.0) * 32.0;
-const PQ_C3: f64 = (2392.0 / 4096.0) * 32.0;
-
 /// Converts linear sample to PQ signal using PQ inverse EOTF, where linear sample value of 1.0
 /// represents `intensity_target` display nits.
 ///
 /// This version uses original EOTF using double precision arithmetic internally.
+#[cfg(test)]
 pub fn linear_to_pq_precise(intensity_target: f32, samples: &mut [f32]) {
     let mult = intensity_target as f64 * 10000f64.recip();
 
     for s in samples {
-        if *s == 0.0 {
-            continue;
-        }
-
-        let a = s.abs() as f64;
-        let xp = (a * mult).powf(PQ_M1);
-        let num = PQ_C1 + xp * PQ_C2;
-        let den = 1.0 + xp * PQ_C3;
-        let e = (num / den).powf(PQ_M2);
-        *s = (e as f32).copysign(*s);
+        *s = transfer::linear_to_pq(*s as f64 * mult) as f32;
     }
 }
 
@@ -215,20 +208,12 @@ pub fn linear_to_pq_precise(intensity_target: f32, samples: &mut [f32]) {
 /// `intensity_target` display nits.
 ///
 /// This version uses original EOTF using double precision arithmetic internally.
+#[cfg(test)]
 pub fn pq_to_linear_precise(intensity_target: f32, samples: &mut [f32]) {
     let mult = 10000.0 / intensity_target as f64;
 
     for s in samples {
-        if *s == 0.0 {
-            continue;
-        }
-
-        let a = s.abs() as f64;
-        let xp = a.powf(PQ_M2.recip());
-        let num = (xp - PQ_C1).max(0.0);
-        let den = PQ_C2 - PQ_C3 * xp;
-        let y = (num / den).powf(PQ_M1.recip());
-        *s = ((y * mult) as f32).copysign(*s);
+        *s = (transfer::pq_to_linear(*s as f64) * mult) as f32;
     }
 }
 
@@ -264,6 +249,7 @@ const PQ_INV_EOTF_Q_SMALL: [f32; 5] =
 ///
 /// This version uses approximate curve using rational polynomial.
 // Max error: ~7e-7 at intensity_target = 10000
+#[cfg(test)]
 pub fn linear_to_pq(intensity_target: f32, samples: &mut [f32]) {
     let y_mult = intensity_target * 10000f32.recip();
 
@@ -316,6 +302,7 @@ pub fn linear_to_pq_simd<D: SimdDescriptor>(
 ///
 /// This version uses approximate curve using rational polynomial.
 // Max error: ~3e-6 at intensity_target = 10000
+#[cfg(test)]
 pub fn pq_to_linear(intensity_target: f32, samples: &mut [f32]) {
     let y_mult = 10000.0 / intensity_target;
 
@@ -351,30 +338,6 @@ pub fn pq_to_linear_simd<D: SimdDescriptor>(
     }
 }
 
-const HLG_A: f64 = 0.17883277;
-const HLG_B: f64 = 1.0 - 4.0 * HLG_A;
-const HLG_C: f64 = 0.5599107295;
-
-fn hlg_ootf_inner_precise(exp: f64, [lr, lg, lb]: [f32; 3], [sr, sg, sb]: [&mut [f32]; 3]) {
-    if exp.abs() < 0.1 {
-        return;
-    }
-
-    let lr = lr as f64;
-    let lg = lg as f64;
-    let lb = lb as f64;
-    for ((r, g), b) in std::iter::zip(sr, sg).zip(sb) {
-        let dr = *r as f64;
-        let dg = *g as f64;
-        let db = *b as f64;
-        let mixed = dr.mul_add(lr, dg.mul_add(lg, db * lb));
-        let mult = mixed.powf(exp);
-        *r = (dr * mult) as f32;
-        *g = (dg * mult) as f32;
-        *b = (db * mult) as f32;
-    }
-}
-
 fn hlg_ootf_inner(exp: f32, [lr, lg, lb]: [f32; 3], [sr, sg, sb]: [&mut [f32]; 3]) {
     if exp.abs() < 0.1 {
         return;
@@ -389,33 +352,6 @@ fn hlg_ootf_inner(exp: f32, [lr, lg, lb]: [f32; 3], [sr, sg, sb]: [&mut [f32]; 3
     }
 }
 
-/// Converts scene-referred linear samples to display-referred linear samples using HLG OOTF.
-///
-/// This version uses double precision arithmetic internally.
-pub fn hlg_scene_to_display_precise(
-    intensity_display: f32,
-    luminance_rgb: [f32; 3],
-    samples_rgb: [&mut [f32]; 3],
-) {
-    let system_gamma = 1.2f64 * 1.111f64.powf((intensity_display as f64 / 1e3).log2());
-    let gamma_sub_one = system_gamma - 1.0;
-    hlg_ootf_inner_precise(gamma_sub_one, luminance_rgb, samples_rgb);
-}
-
-/// Converts display-referred linear samples to scene-referred linear samples using HLG inverse
-/// OOTF.
-///
-/// This version uses double precision arithmetic internally.
-pub fn hlg_display_to_scene_precise(
-    intensity_display: f32,
-    luminance_rgb: [f32; 3],
-    samples_rgb: [&mut [f32]; 3],
-) {
-    let system_gamma = 1.2f64 * 1.111f64.powf((intensity_display as f64 / 1e3).log2());
-    let one_sub_gamma = 1.0 - system_gamma;
-    hlg_ootf_inner_precise(one_sub_gamma / system_gamma, luminance_rgb, samples_rgb);
-}
-
 /// Converts scene-referred linear samples to display-referred linear samples using HLG OOTF.
 ///
 /// This version uses `fast_powf` to compute power function.
@@ -446,31 +382,20 @@ pub fn hlg_display_to_scene(
 /// Converts scene-referred linear sample to HLG signal.
 ///
 /// This version uses double precision arithmetic internally.
+#[cfg(test)]
 pub fn scene_to_hlg_precise(samples: &mut [f32]) {
     for s in samples {
-        let a = s.abs() as f64;
-        let y = if a <= 1.0 / 12.0 {
-            (3.0 * a).sqrt()
-        } else {
-            // TODO(tirr-c): maybe use mul_add?
-            HLG_A * (12.0 * a - HLG_B).ln() + HLG_C
-        };
-        *s = (y as f32).copysign(*s);
+        *s = transfer::scene_to_hlg(*s as f64) as f32;
     }
 }
 
 /// Converts HLG signal to scene-referred linear sample.
 ///
 /// This version uses double precision arithmetic internally.
+#[cfg(test)]
 pub fn hlg_to_scene_precise(samples: &mut [f32]) {
     for s in samples {
-        let a = s.abs() as f64;
-        let y = if a <= 0.5 {
-            a * a / 3.0
-        } else {
-            (((a - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
-        };
-        *s = (y as f32).copysign(*s);
+        *s = transfer::hlg_to_scene(*s as f64) as f32;
     }
 }
 
@@ -544,16 +469,9 @@ mod test {
         Ok(samples)
     }
 
-    /// Naive linear to sRGB using actual pow for testing.
     fn linear_to_srgb_naive(samples: &mut [f32]) {
         for x in samples {
-            let a = x.abs();
-            *x = if a <= 0.0031308 {
-                a * 12.92
-            } else {
-                a.powf(1.0 / 2.4).mul_add(1.055, -0.055)
-            }
-            .copysign(*x);
+            *x = transfer::linear_to_srgb(*x as f64) as f32;
         }
     }
 
@@ -0,0 +1,230 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Reference implementations of transfer functions, and lookup tables built from them.
+//!
+//! The functions in this module evaluate the defining equations in double precision, and are
+//! meant to be the single source of truth for every place that converts individual values or
+//! builds tables: code converting whole rows should use the faster approximations in the
+//! `color::tf` module, which are tested against these functions. All functions are mirrored for
+//! negative inputs, that is `f(-x) = -f(x)`.
+
+const PQ_M1: f64 = 2610.0 / 16384.0;
+const PQ_M2: f64 = (2523.0 / 4096.0) * 128.0;
+const PQ_C1: f64 = 3424.0 / 4096.0;
+const PQ_C2: f64 = (2413.0 / 4096.0) * 32.0;
+const PQ_C3: f64 = (2392.0 / 4096.0) * 32.0;
+
+pub(crate) const HLG_A: f64 = 0.17883277;
+pub(crate) const HLG_B: f64 = 1.0 - 4.0 * HLG_A;
+pub(crate) const HLG_C: f64 = 0.5599107295;
+
+/// Converts an sRGB encoded value to linear light, as defined in IEC 61966-2-1.
+pub fn srgb_to_linear(x: f64) -> f64 {
+    let a = x.abs();
+    if a <= 0.04045 {
+        a / 12.92
+    } else {
+        ((a + 0.055) / 1.055).powf(2.4)
+    }
+    .copysign(x)
+}
+
+/// Converts linear light to an sRGB encoded value. Inverse of [`srgb_to_linear`].
+pub fn linear_to_srgb(x: f64) -> f64 {
+    let a = x.abs();
+    if a <= 0.0031308 {
+        a * 12.92
+    } else {
+        1.055 * a.powf(1.0 / 2.4) - 0.055
+    }
+    .copysign(x)
+}
+
+/// Converts a value encoded with a pure 2.2 gamma curve to linear light.
+pub fn gamma22_to_linear(x: f64) -> f64 {
+    x.abs().powf(2.2).copysign(x)
+}
+
+/// Converts linear light to a value encoded with a pure 2.2 gamma curve. Inverse of
+/// [`gamma22_to_linear`].
+pub fn linear_to_gamma22(x: f64) -> f64 {
+    x.abs().powf(1.0 / 2.2).copysign(x)
+}
+
+/// Converts a PQ signal to linear light with the EOTF of SMPTE ST 2084, where 1.0 represents
+/// 10000 nits.
+pub fn pq_to_linear(x: f64) -> f64 {
+    if x == 0.0 {
+        return x;
+    }
+    let xp = x.abs().powf(PQ_M2.recip());
+    let num = (xp - PQ_C1).max(0.0);
+    let den = PQ_C2 - PQ_C3 * xp;
+    (num / den).powf(PQ_M1.recip()).copysign(x)
+}
+
+/// Converts linear light, where 1.0 represents 10000 nits, to a PQ signal. Inverse of
+/// [`pq_to_linear`].
+pub fn linear_to_pq(x: f64) -> f64 {
+    if x == 0.0 {
+        return x;
+    }
+    let xp = x.abs().powf(PQ_M1);
+    let num = PQ_C1 + xp * PQ_C2;
+    let den = 1.0 + xp * PQ_C3;
+    (num / den).powf(PQ_M2).copysign(x)
+}
+
+/// Converts an HLG signal to scene-referred linear light with the inverse OETF of BT.2100.
+pub fn hlg_to_scene(x: f64) -> f64 {
+    let a = x.abs();
+    if a <= 0.5 {
+        a * a / 3.0
+    } else {
+        (((a - HLG_C) / HLG_A).exp() + HLG_B) / 12.0
+    }
+    .copysign(x)
+}
+
+/// Converts scene-referred linear light to an HLG signal with the OETF of BT.2100. Inverse of
+/// [`hlg_to_scene`].
+pub fn scene_to_hlg(x: f64) -> f64 {
+    let a = x.abs();
+    if a <= 1.0 / 12.0 {
+        (3.0 * a).sqrt()
+    } else {
+        HLG_A * (12.0 * a - HLG_B).ln() + HLG_C
+    }
+    .copysign(x)
+}
+
+/// Returns the linear light value of each 8-bit sRGB encoded value.
+///
+/// Each entry is the exact value rounded to the nearest `f32`, so the error is at most half an
+/// `f32` ULP. Converting an entry back with [`linear_to_srgb`] and rounding to 8 bits gives back
+/// its index.
+pub fn srgb_to_linear_u8_lut() -> [f32; 256] {
+    std::array::from_fn(|i| srgb_to_linear(i as f64 / 255.0) as f32)
+}
+
+/// Returns the sRGB encoded value, with `bits` bits of precision, of each 16-bit linear value.
+///
+/// The table has `1 << 16` entries and is indexed by the linear value scaled to `0..=65535`
+/// and rounded. Each entry is the exact value rounded to the target depth, so the error is at
+/// most 0.5 ULP of the target depth for linear values that are multiples of `1 / 65535`.
+/// Rounding other linear values to the nearest index adds an error of at most
+/// `12.92 / 2 * ((1 << bits) - 1) / 65535` ULP, which is below 0.03 ULP for 8-bit targets, but
+/// grows to about 6.5 ULP for 16-bit targets in the linear segment near black.
+///
+/// Panics if `bits` is not in `1..=16`.
+pub fn linear_to_srgb_u16_lut(bits: u32) -> Vec<u16> {
+    assert!((1..=16).contains(&bits), "unsupported bit depth {bits}");
+    let max = ((1u32 << bits) - 1) as f64;
+    (0..=u16::MAX)
+        .map(|i| (linear_to_srgb(i as f64 / 65535.0) * max).round() as u16)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn srgb_u8_roundtrip() {
+        let to_linear = srgb_to_linear_u8_lut();
+        let to_srgb = linear_to_srgb_u16_lut(8);
+        for (i, linear) in to_linear.iter().enumerate() {
+            assert_eq!((linear_to_srgb(*linear as f64) * 255.0).round() as usize, i);
+            let index = (*linear as f64 * 65535.0).round() as usize;
+            assert_eq!(to_srgb[index] as usize, i);
+        }
+    }
+
+    #[test]
+    fn srgb_u16_lut_error() {
+        for bits in 1..=16 {
+            let lut = linear_to_srgb_u16_lut(bits);
+            let max = ((1u32 << bits) - 1) as f64;
+            for (i, value) in lut.iter().enumerate() {
+                let exact = linear_to_srgb(i as f64 / 65535.0) * max;
+                assert!(
+                    (*value as f64 - exact).abs() <= 0.5,
+                    "{value} vs {exact} at {i} for {bits} bits"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        type Tf = fn(f64) -> f64;
+        let pairs: [(Tf, Tf); 4] = [
+            (srgb_to_linear, linear_to_srgb),
+            (gamma22_to_linear, linear_to_gamma22),
+            (pq_to_linear, linear_to_pq),
+            (hlg_to_scene, scene_to_hlg),
+        ];
+        for (to_linear, from_linear) in pairs {
+            for i in -1000..=1000 {
+                let x = i as f64 / 1000.0;
+                let roundtrip = from_linear(to_linear(x));
+                assert!((roundtrip - x).abs() < 1e-12, "{roundtrip} vs {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn pq_matches_st2084() {
+        // The constants as they are published in SMPTE ST 2084.
+        const M1: f64 = 0.1593017578125;
+        const M2: f64 = 78.84375;
+        const C1: f64 = 0.8359375;
+        const C2: f64 = 18.8515625;
+        const C3: f64 = 18.6875;
+        for i in 0..=1000 {
+            let e = i as f64 / 1000.0;
+            let p = e.powf(1.0 / M2);
+            let expected = ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1);
+            assert!((pq_to_linear(e) - expected).abs() < 1e-12);
+            // The equation maps 0 to a tiny positive signal, which is snapped to 0.
+            let y = i.max(1) as f64 / 1000.0;
+            let p = y.powf(M1);
+            let expected = ((C1 + C2 * p) / (1.0 + C3 * p)).powf(M2);
+            assert!((linear_to_pq(y) - expected).abs() < 1e-12);
+        }
+        // Signal levels of common luminances, from ITU-R BT.2408.
+        for (nits, signal) in [(100.0, 0.5081), (203.0, 0.5807), (1000.0, 0.7518)] {
+            assert!((linear_to_pq(nits / 10000.0) - signal).abs() < 1e-4);
+        }
+        assert_eq!(linear_to_pq(0.0), 0.0);
+        assert_eq!(linear_to_pq(1.0), 1.0);
+    }
+
+    #[test]
+    fn hlg_matches_bt2100() {
+        // The constants as they are published in ITU-R BT.2100.
+        const A: f64 = 0.17883277;
+        const B: f64 = 0.28466892;
+        const C: f64 = 0.55991073;
+        for i in 0..=1000 {
+            let e = i as f64 / 1000.0;
+            let expected = if e <= 1.0 / 12.0 {
+                (3.0 * e).sqrt()
+            } else {
+                A * (12.0 * e - B).ln() + C
+            };
+            assert!((scene_to_hlg(e) - expected).abs() < 1e-8);
+            let expected = if e <= 0.5 {
+                e * e / 3.0
+            } else {
+                (((e - C) / A).exp() + B) / 12.0
+            };
+            assert!((hlg_to_scene(e) - expected).abs() < 1e-8);
+        }
+        assert!((scene_to_hlg(1.0 / 12.0) - 0.5).abs() < 1e-12);
+        assert!((scene_to_hlg(1.0) - 1.0).abs() < 1e-8);
+    }
+}
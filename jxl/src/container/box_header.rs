@@ -24,7 +24,8 @@ pub enum HeaderParseResult {
 }
 
 impl ContainerBoxHeader {
-    pub(super) fn parse(buf: &[u8]) -> Result<HeaderParseResult, Error> {
+    /// Parses the box header at the start of `buf`.
+    pub fn parse(buf: &[u8]) -> Result<HeaderParseResult, Error> {
         let (tbox, box_size, header_size) = match *buf {
             [
                 0,
@@ -110,4 +111,20 @@ impl ContainerBoxType {
     pub const CODESTREAM: Self = Self(*b"jxlc");
     pub const PARTIAL_CODESTREAM: Self = Self(*b"jxlp");
     pub const JPEG_RECONSTRUCTION: Self = Self(*b"jbrd");
+
+    /// Returns whether boxes of this type may be stored Brotli-compressed in a `brob` box. This
+    /// excludes the boxes that make up the structure of the file, and `brob` boxes themselves.
+    pub fn can_be_compressed(&self) -> bool {
+        ![
+            Self::JXL,
+            Self::FILE_TYPE,
+            Self::JXL_LEVEL,
+            Self::BROTLI_COMPRESSED,
+            Self::FRAME_INDEX,
+            Self::CODESTREAM,
+            Self::PARTIAL_CODESTREAM,
+            Self::JPEG_RECONSTRUCTION,
+        ]
+        .contains(self)
+    }
 }
@@ -0,0 +1,200 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Reader of the image size declared by the Exif box (`Exif`), whose payload is the offset of a
+//! TIFF header followed by Exif data in TIFF format.
+
+/// Tag of the width of the image in the first IFD.
+const IMAGE_WIDTH: u16 = 0x100;
+/// Tag of the height of the image in the first IFD.
+const IMAGE_LENGTH: u16 = 0x101;
+/// Tag of the offset of the Exif IFD in the first IFD.
+const EXIF_IFD_POINTER: u16 = 0x8769;
+/// Tag of the width of the image in the Exif IFD.
+const PIXEL_X_DIMENSION: u16 = 0xa002;
+/// Tag of the height of the image in the Exif IFD.
+const PIXEL_Y_DIMENSION: u16 = 0xa003;
+
+/// Type of 16-bit unsigned values.
+const SHORT: u16 = 3;
+/// Type of 32-bit unsigned values.
+const LONG: u16 = 4;
+
+/// TIFF data, whose offsets are relative to its start.
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    }
+
+    /// Returns the tag of each entry of the IFD at `offset`, with its value if it is a single
+    /// SHORT or LONG.
+    fn entries(&self, offset: u32) -> Option<Vec<(u16, Option<u32>)>> {
+        let offset = offset as usize;
+        let num_entries = self.u16(offset)? as usize;
+        (0..num_entries)
+            .map(|i| {
+                let entry = offset + 2 + i * 12;
+                let value = match (self.u16(entry + 2)?, self.u32(entry + 4)?) {
+                    (SHORT, 1) => Some(self.u16(entry + 8)? as u32),
+                    (LONG, 1) => Some(self.u32(entry + 8)?),
+                    _ => None,
+                };
+                Some((self.u16(entry)?, value))
+            })
+            .collect()
+    }
+}
+
+/// Returns the width and height that the Exif box with `payload` declares for the image, if any.
+/// The pixel dimensions of the Exif IFD take precedence over the image width and length of the
+/// first IFD, as in JPEG files.
+pub(crate) fn declared_image_size(payload: &[u8]) -> Option<(usize, usize)> {
+    let tiff_offset = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?) as usize;
+    let data = payload.get(4..)?.get(tiff_offset..)?;
+    let big_endian = match data.get(..4)? {
+        b"MM\0*" => true,
+        b"II*\0" => false,
+        _ => return None,
+    };
+    let tiff = Tiff { data, big_endian };
+    let (mut width, mut height, mut exif_ifd) = (None, None, None);
+    for (tag, value) in tiff.entries(tiff.u32(4)?)? {
+        match tag {
+            IMAGE_WIDTH => width = value,
+            IMAGE_LENGTH => height = value,
+            EXIF_IFD_POINTER => exif_ifd = value,
+            _ => {}
+        }
+    }
+    let (mut pixel_width, mut pixel_height) = (None, None);
+    for (tag, value) in exif_ifd
+        .and_then(|offset| tiff.entries(offset))
+        .unwrap_or_default()
+    {
+        match tag {
+            PIXEL_X_DIMENSION => pixel_width = value,
+            PIXEL_Y_DIMENSION => pixel_height = value,
+            _ => {}
+        }
+    }
+    let (width, height) = pixel_width.zip(pixel_height).or(width.zip(height))?;
+    Some((width as usize, height as usize))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Returns the payload of an Exif box with TIFF data in the given byte order, whose first IFD
+    /// holds the `ifd0` entries, followed by an Exif IFD with the `exif` entries if there are
+    /// any. Entries are given as a tag, a type, and their single value.
+    pub(crate) fn exif_payload(
+        big_endian: bool,
+        ifd0: &[(u16, u16, u32)],
+        exif: &[(u16, u16, u32)],
+    ) -> Vec<u8> {
+        let u16_bytes = |v: u16| match big_endian {
+            true => v.to_be_bytes(),
+            false => v.to_le_bytes(),
+        };
+        let u32_bytes = |v: u32| match big_endian {
+            true => v.to_be_bytes(),
+            false => v.to_le_bytes(),
+        };
+        let mut ifd0 = ifd0.to_vec();
+        if !exif.is_empty() {
+            let exif_offset = 8 + 2 + 12 * (ifd0.len() + 1) + 4;
+            ifd0.push((EXIF_IFD_POINTER, LONG, exif_offset as u32));
+        }
+        // The TIFF header directly follows the offset that starts the payload.
+        let mut payload = vec![0; 4];
+        payload.extend(if big_endian { b"MM\0*" } else { b"II*\0" });
+        payload.extend(u32_bytes(8));
+        for ifd in [&ifd0[..], exif].into_iter().filter(|ifd| !ifd.is_empty()) {
+            payload.extend(u16_bytes(ifd.len() as u16));
+            for &(tag, ty, value) in ifd {
+                payload.extend(u16_bytes(tag));
+                payload.extend(u16_bytes(ty));
+                payload.extend(u32_bytes(1));
+                match ty {
+                    SHORT => payload.extend([&u16_bytes(value as u16)[..], &[0, 0]].concat()),
+                    _ => payload.extend(u32_bytes(value)),
+                }
+            }
+            payload.extend(u32_bytes(0));
+        }
+        payload
+    }
+
+    /// Returns the payload of an Exif box that declares an image size of `width`x`height`.
+    pub(crate) fn exif_with_size(width: u32, height: u32) -> Vec<u8> {
+        let exif = [
+            (PIXEL_X_DIMENSION, LONG, width),
+            (PIXEL_Y_DIMENSION, LONG, height),
+        ];
+        exif_payload(true, &[], &exif)
+    }
+
+    #[test]
+    fn declared_sizes() {
+        let ifd0 = [(IMAGE_WIDTH, SHORT, 640), (IMAGE_LENGTH, LONG, 480)];
+        let exif = [
+            (PIXEL_X_DIMENSION, LONG, 320),
+            (PIXEL_Y_DIMENSION, SHORT, 240),
+        ];
+        for big_endian in [false, true] {
+            let payload = exif_payload(big_endian, &ifd0, &[]);
+            assert_eq!(declared_image_size(&payload), Some((640, 480)));
+            // The pixel dimensions of the Exif IFD take precedence.
+            let payload = exif_payload(big_endian, &ifd0, &exif);
+            assert_eq!(declared_image_size(&payload), Some((320, 240)));
+            let payload = exif_payload(big_endian, &[], &exif);
+            assert_eq!(declared_image_size(&payload), Some((320, 240)));
+            // A single dimension does not declare a size.
+            let payload = exif_payload(big_endian, &ifd0[..1], &exif[1..]);
+            assert_eq!(declared_image_size(&payload), None);
+        }
+    }
+
+    #[test]
+    fn malformed_exif_declares_no_size() {
+        let payload = exif_with_size(320, 240);
+        // The last 4 bytes link to the next IFD, which is not read.
+        for len in 0..payload.len() - 4 {
+            assert_eq!(declared_image_size(&payload[..len]), None, "{len} bytes");
+        }
+        let mut bad_offset = payload.clone();
+        bad_offset[3] = 1;
+        assert_eq!(declared_image_size(&bad_offset), None);
+        let mut bad_magic = payload;
+        bad_magic[7] = b'+';
+        assert_eq!(declared_image_size(&bad_magic), None);
+    }
+}
@@ -19,6 +19,7 @@ use crate::icc::read_varint_from_reader;
 use crate::util::NewWithCapacity;
 
 /// A single entry in the frame index.
+#[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameIndexEntry {
     /// Absolute byte offset of this keyframe in the codestream.
@@ -33,6 +34,7 @@ pub struct FrameIndexEntry {
 }
 
 /// Parsed contents of a Frame Index box (`jxli`).
+#[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FrameIndexBox {
     /// Tick numerator. A tick lasts `tnum / tden` seconds.
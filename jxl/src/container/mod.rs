@@ -6,9 +6,12 @@
 // Originally written for jxl-oxide.
 
 pub mod box_header;
+pub(crate) mod exif;
 pub mod frame_index;
 pub mod parse;
+pub mod writer;
 
+use crate::util::tracing_wrappers::*;
 use box_header::*;
 pub use parse::ParseEvent;
 use parse::*;
@@ -19,6 +22,22 @@ pub struct ContainerParser {
     state: DetectState,
     jxlp_index_state: JxlpIndexState,
     previous_consumed_bytes: usize,
+    /// Number of bytes consumed by all calls to `process_bytes`.
+    position: u64,
+    box_spans: Option<Vec<BoxSpan>>,
+}
+
+/// Location of a box in the file.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BoxSpan {
+    pub kind: ContainerBoxType,
+    /// Offset of the box header.
+    pub header_offset: u64,
+    /// Offset of the box payload, which for `jxlp` boxes starts with the 4-byte box index.
+    pub payload_offset: u64,
+    /// Size of the payload, or `None` if the box extends to the end of the file.
+    pub payload_len: Option<u64>,
 }
 
 #[derive(Debug, Default)]
@@ -30,11 +49,11 @@ enum DetectState {
     InAuxBox {
         #[allow(unused)]
         header: ContainerBoxHeader,
-        bytes_left: Option<usize>,
+        bytes_left: Option<u64>,
     },
     InCodestream {
         kind: BitstreamKind,
-        bytes_left: Option<usize>,
+        bytes_left: Option<u64>,
     },
 }
 
@@ -65,6 +84,25 @@ impl ContainerParser {
         Self::default()
     }
 
+    /// Creates a parser that records the location of every box it reads, including the
+    /// signature box, see [`box_spans`].
+    ///
+    /// [`box_spans`]: ContainerParser::box_spans
+    pub fn recording_box_spans() -> Self {
+        Self {
+            box_spans: Some(vec![]),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the boxes read so far if the parser was created with [`recording_box_spans`], in
+    /// file order.
+    ///
+    /// [`recording_box_spans`]: ContainerParser::recording_box_spans
+    pub fn box_spans(&self) -> &[BoxSpan] {
+        self.box_spans.as_deref().unwrap_or_default()
+    }
+
     pub fn kind(&self) -> BitstreamKind {
         match self.state {
             DetectState::WaitingSignature => BitstreamKind::Unknown,
@@ -96,10 +134,24 @@ impl ContainerParser {
     pub fn previous_consumed_bytes(&self) -> usize {
         self.previous_consumed_bytes
     }
+
+    /// Returns the type of the box other than a codestream box that the input read so far ends
+    /// in, and the number of bytes of that box that are still missing, if any.
+    pub fn truncated_box(&self) -> Option<(ContainerBoxType, u64)> {
+        match &self.state {
+            DetectState::InAuxBox {
+                header,
+                bytes_left: Some(bytes_left),
+            } if *bytes_left > 0 => Some((header.box_type(), *bytes_left)),
+            _ => None,
+        }
+    }
 }
 
-#[cfg(test)]
 impl ContainerParser {
+    /// Concatenates all the codestream data in `input`, which must be a complete file. Boxes
+    /// after the codestream that are truncated are only warned about, since the codestream does
+    /// not depend on them.
     pub(crate) fn collect_codestream(input: &[u8]) -> crate::error::Result<Vec<u8>> {
         let mut parser = Self::new();
         let mut codestream = Vec::new();
@@ -111,6 +163,10 @@ impl ContainerParser {
                 }
             }
         }
+        let truncated_box = parser.truncated_box();
+        if truncated_box.is_some() {
+            warn!(?truncated_box, "Truncated box at the end of the file");
+        }
         Ok(codestream)
     }
 }
@@ -118,6 +174,7 @@ impl ContainerParser {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::error::Error;
     use test_log::test;
 
     #[rustfmt::skip]
@@ -185,4 +242,124 @@ mod test {
             Ok(())
         });
     }
+
+    #[test]
+    fn extended_box_sizes() {
+        // A jxlc box of more than 4GB, of which only the start is available, and that would be
+        // much shorter if its size was truncated to 32 bits.
+        let payload_len = (1u64 << 32) + 3;
+        let mut container = HEADER.to_vec();
+        container.extend_from_slice(&[0, 0, 0, 1]);
+        container.extend_from_slice(b"jxlc");
+        container.extend_from_slice(&(16 + payload_len).to_be_bytes());
+        container.extend_from_slice(&[0xff, 0x0a, 1, 2, 3, 4, 5, 6]);
+        // Something that is not a box header, to be read as codestream.
+        container.extend_from_slice(&[0, 0, 0, 8, b'f', b'r', b'e', b'e']);
+
+        let mut parser = ContainerParser::recording_box_spans();
+        let mut codestream = vec![];
+        for event in parser.process_bytes(&container) {
+            if let ParseEvent::Codestream(data) = event.unwrap() {
+                codestream.extend_from_slice(data);
+            }
+        }
+        assert_eq!(codestream, container[HEADER.len() + 16..]);
+        assert_eq!(parser.previous_consumed_bytes(), container.len());
+        let jxlc = parser.box_spans()[2];
+        assert_eq!(jxlc.kind, ContainerBoxType::CODESTREAM);
+        assert_eq!(jxlc.payload_offset, HEADER.len() as u64 + 16);
+        assert_eq!(jxlc.payload_len, Some(payload_len));
+        assert!(matches!(
+            parser.state,
+            DetectState::InCodestream { bytes_left: Some(left), .. } if left == payload_len - 16
+        ));
+    }
+
+    fn make_box(ty: &[u8; 4], declared_len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut buf = declared_len.to_be_bytes().to_vec();
+        buf.extend_from_slice(ty);
+        buf.extend_from_slice(payload);
+        buf
+    }
+
+    #[test]
+    fn unknown_boxes_are_skipped() {
+        let codestream = [0xff, 0x0a, 1, 2, 3];
+        let container = [
+            HEADER,
+            &make_box(b"free", 8, &[]),
+            &make_box(b"abcd", 13, &[0, 0, 0, 8, b'j']),
+            &make_box(b"jxlc", 13, &codestream),
+            // Extends to the end of the file.
+            &make_box(b"efgh", 0, &[0, 0, 0, 13, b'j', b'x', b'l', b'c', 1]),
+        ]
+        .concat();
+        let mut parser = ContainerParser::recording_box_spans();
+        let mut collected = vec![];
+        for event in parser.process_bytes(&container) {
+            if let ParseEvent::Codestream(data) = event.unwrap() {
+                collected.extend_from_slice(data);
+            }
+        }
+        assert_eq!(collected, codestream);
+        assert_eq!(parser.previous_consumed_bytes(), container.len());
+        assert_eq!(parser.truncated_box(), None);
+        let last = parser.box_spans().last().unwrap();
+        assert_eq!(last.kind, ContainerBoxType(*b"efgh"));
+        assert_eq!(last.payload_len, None);
+    }
+
+    #[test]
+    fn box_extending_to_the_end_before_the_codestream_is_rejected() {
+        let jxlp = make_box(b"jxlp", 14, &[0, 0, 0, 0, 0xff, 0x0a]);
+        let unknown = make_box(b"abcd", 0, &[1, 2, 3]);
+        for container in [
+            [HEADER, &unknown].concat(),
+            [HEADER, &jxlp, &unknown].concat(),
+        ] {
+            assert!(
+                ContainerParser::new()
+                    .process_bytes(&container)
+                    .any(|event| matches!(
+                        event,
+                        Err(Error::LastBoxBeforeCodestreamEnd(ref ty)) if ty == "abcd"
+                    ))
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_unknown_box_after_the_codestream() {
+        let codestream = [0xff, 0x0a, 1, 2, 3];
+        let container = [
+            HEADER,
+            &make_box(b"jxlc", 13, &codestream),
+            &make_box(b"abcd", 100, &[1; 10]),
+        ]
+        .concat();
+        let mut parser = ContainerParser::new();
+        assert!(parser.process_bytes(&container).all(|event| event.is_ok()));
+        assert_eq!(
+            parser.truncated_box(),
+            Some((ContainerBoxType(*b"abcd"), 100 - 8 - 10))
+        );
+        assert_eq!(
+            ContainerParser::collect_codestream(&container).unwrap(),
+            codestream
+        );
+    }
+
+    #[test]
+    fn extended_box_size_smaller_than_header() {
+        let mut container = HEADER.to_vec();
+        container.extend_from_slice(&[0, 0, 0, 1]);
+        container.extend_from_slice(b"jxlc");
+        container.extend_from_slice(&15u64.to_be_bytes());
+        let mut parser = ContainerParser::new();
+        assert!(
+            parser
+                .process_bytes(&container)
+                .any(|event| matches!(event, Err(Error::InvalidBox)))
+        );
+    }
 }
@@ -5,11 +5,11 @@
 //
 // Originally written for jxl-oxide.
 
-use super::{BitstreamKind, ContainerParser, DetectState, JxlpIndexState, box_header::*};
+use super::{BitstreamKind, BoxSpan, ContainerParser, DetectState, JxlpIndexState, box_header::*};
 use crate::{
     api::{CODESTREAM_SIGNATURE, CONTAINER_SIGNATURE},
     error::{Error, Result},
-    util::tracing_wrappers::*,
+    util::{saturating_usize, tracing_wrappers::*},
 };
 
 /// Iterator that reads over a buffer and emits parser events.
@@ -33,6 +33,10 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
         let state = &mut self.inner.state;
         let jxlp_index_state = &mut self.inner.jxlp_index_state;
         let buf = &mut self.remaining_input;
+        let box_spans = &mut self.inner.box_spans;
+        // File offset of the start of `buf`.
+        let start = (self.inner.position, buf.len());
+        let offset = |buf: &[u8]| start.0 + (start.1 - buf.len()) as u64;
 
         loop {
             if buf.is_empty() {
@@ -53,6 +57,15 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
                         )));
                     } else if buf.starts_with(&CONTAINER_SIGNATURE) {
                         info!("Container signature found");
+                        if let Some(box_spans) = box_spans {
+                            let header_offset = offset(buf);
+                            box_spans.push(BoxSpan {
+                                kind: ContainerBoxType::JXL,
+                                header_offset,
+                                payload_offset: header_offset + 8,
+                                payload_len: Some(CONTAINER_SIGNATURE.len() as u64 - 8),
+                            });
+                        }
                         *state = DetectState::WaitingBoxHeader;
                         *buf = &buf[CONTAINER_SIGNATURE.len()..];
                         return Ok(Some(ParseEvent::BitstreamKind(BitstreamKind::Container)));
@@ -74,6 +87,15 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
                         header,
                         header_size,
                     } => {
+                        if let Some(box_spans) = box_spans {
+                            let header_offset = offset(buf);
+                            box_spans.push(BoxSpan {
+                                kind: header.box_type(),
+                                header_offset,
+                                payload_offset: header_offset + header_size as u64,
+                                payload_len: header.box_size(),
+                            });
+                        }
                         *buf = &buf[header_size..];
                         let tbox = header.box_type();
                         if tbox == ContainerBoxType::CODESTREAM {
@@ -93,12 +115,10 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
 
                             *state = DetectState::InCodestream {
                                 kind: BitstreamKind::Container,
-                                bytes_left: header.box_size().map(|x| x as usize),
+                                bytes_left: header.box_size(),
                             };
                         } else if tbox == ContainerBoxType::PARTIAL_CODESTREAM {
-                            if let Some(box_size) = header.box_size()
-                                && box_size < 4
-                            {
+                            if header.box_size().is_some_and(|box_size| box_size < 4) {
                                 return Err(Error::InvalidBox);
                             }
 
@@ -121,7 +141,22 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
 
                             *state = DetectState::WaitingJxlpIndex(header);
                         } else {
-                            let bytes_left = header.box_size().map(|x| x as usize);
+                            // A box that extends to the end of the file must be the last one, so
+                            // the codestream has to be complete before it.
+                            let codestream_complete = matches!(
+                                jxlp_index_state,
+                                JxlpIndexState::SingleJxlc | JxlpIndexState::JxlpFinished
+                            );
+                            if header.is_last() && !codestream_complete {
+                                warn!(
+                                    ?tbox,
+                                    "Box extends to the end of the file before the codestream"
+                                );
+                                return Err(Error::LastBoxBeforeCodestreamEnd(
+                                    String::from_utf8_lossy(&tbox.0).into_owned(),
+                                ));
+                            }
+                            let bytes_left = header.box_size();
                             *state = DetectState::InAuxBox { header, bytes_left };
                         }
                     }
@@ -159,7 +194,7 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
 
                     *state = DetectState::InCodestream {
                         kind: BitstreamKind::Container,
-                        bytes_left: header.box_size().map(|x| x as usize - 4),
+                        bytes_left: header.box_size().map(|x| x - 4),
                     };
                 }
                 DetectState::InCodestream {
@@ -173,17 +208,10 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
                     bytes_left: Some(bytes_left),
                     ..
                 } => {
-                    let payload = if buf.len() >= *bytes_left {
-                        let (payload, remaining) = buf.split_at(*bytes_left);
+                    let payload = take_box_payload(buf, bytes_left);
+                    if *bytes_left == 0 {
                         *state = DetectState::WaitingBoxHeader;
-                        *buf = remaining;
-                        payload
-                    } else {
-                        let payload = *buf;
-                        *bytes_left -= buf.len();
-                        *buf = &[];
-                        payload
-                    };
+                    }
                     return Ok(Some(ParseEvent::Codestream(payload)));
                 }
                 DetectState::InAuxBox {
@@ -198,17 +226,10 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
                     header: _,
                     bytes_left: Some(bytes_left),
                 } => {
-                    let _payload = if buf.len() >= *bytes_left {
-                        let (payload, remaining) = buf.split_at(*bytes_left);
+                    let _payload = take_box_payload(buf, bytes_left);
+                    if *bytes_left == 0 {
                         *state = DetectState::WaitingBoxHeader;
-                        *buf = remaining;
-                        payload
-                    } else {
-                        let payload = *buf;
-                        *bytes_left -= buf.len();
-                        *buf = &[];
-                        payload
-                    };
+                    }
                     // FIXME: emit auxiliary box event
                 }
             }
@@ -216,6 +237,16 @@ impl<'inner, 'buf> ParseEvents<'inner, 'buf> {
     }
 }
 
+/// Splits off the start of `buf` that belongs to a box with `bytes_left` bytes left, which may be
+/// many more than can be addressed in memory.
+fn take_box_payload<'buf>(buf: &mut &'buf [u8], bytes_left: &mut u64) -> &'buf [u8] {
+    let len = buf.len().min(saturating_usize(*bytes_left));
+    let (payload, remaining) = buf.split_at(len);
+    *bytes_left -= len as u64;
+    *buf = remaining;
+    payload
+}
+
 impl std::fmt::Debug for ParseEvents<'_, '_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("ParseEvents")
@@ -244,7 +275,9 @@ impl<'buf> Iterator for ParseEvents<'_, 'buf> {
             self.finished = true;
         }
 
-        self.inner.previous_consumed_bytes += initial_buf.len() - self.remaining_input.len();
+        let consumed = initial_buf.len() - self.remaining_input.len();
+        self.inner.previous_consumed_bytes += consumed;
+        self.inner.position += consumed as u64;
         event.transpose()
     }
 }
@@ -0,0 +1,365 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Container format writer, to wrap an existing codestream in a container together with metadata
+//! boxes without re-encoding it.
+
+use std::io::Write;
+
+use super::box_header::ContainerBoxType;
+use crate::{
+    api::CONTAINER_SIGNATURE,
+    error::{Error, Result},
+};
+
+const FILE_TYPE: [u8; 12] = *b"jxl \0\0\0\0jxl ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CodestreamState {
+    NotStarted,
+    /// `jxlp` boxes were written, and the next one has the given index.
+    Partial(u32),
+    Finished,
+}
+
+/// Writes a JPEG XL container to `sink`, one box at a time.
+///
+/// Boxes are written in the order in which the methods are called, which must follow the rules
+/// of the container format: [`write_signature`](Self::write_signature) comes first, followed by
+/// [`write_level`](Self::write_level) if needed. The codestream can either be written at once with
+/// [`write_codestream`](Self::write_codestream), or in parts with
+/// [`write_codestream_part`](Self::write_codestream_part), and metadata boxes can be written
+/// before, between or after them.
+#[derive(Debug)]
+pub struct ContainerWriter<W: Write> {
+    sink: W,
+    boxes_written: usize,
+    codestream: CodestreamState,
+}
+
+impl<W: Write> ContainerWriter<W> {
+    pub fn new(sink: W) -> Self {
+        Self {
+            sink,
+            boxes_written: 0,
+            codestream: CodestreamState::NotStarted,
+        }
+    }
+
+    /// Writes the signature and file type boxes that start every container.
+    pub fn write_signature(&mut self) -> Result<()> {
+        if self.boxes_written != 0 {
+            return Err(Error::InvalidContainerLayout(
+                "the signature must be the first box",
+            ));
+        }
+        self.sink.write_all(&CONTAINER_SIGNATURE)?;
+        self.write_box(ContainerBoxType::FILE_TYPE, &FILE_TYPE)?;
+        self.boxes_written = 2;
+        Ok(())
+    }
+
+    /// Writes the codestream level box, which must directly follow the signature.
+    pub fn write_level(&mut self, level: u8) -> Result<()> {
+        if self.boxes_written != 2 {
+            return Err(Error::InvalidContainerLayout(
+                "the level must directly follow the signature",
+            ));
+        }
+        self.write_box(ContainerBoxType::JXL_LEVEL, &[level])
+    }
+
+    /// Writes a metadata box of type `kind`, such as [`ContainerBoxType::EXIF`] or
+    /// [`ContainerBoxType::XML`]. With `compress`, the payload is compressed with Brotli and
+    /// stored in a `brob` box, which requires the `brotli` feature.
+    pub fn write_metadata_box(
+        &mut self,
+        kind: ContainerBoxType,
+        payload: &[u8],
+        compress: bool,
+    ) -> Result<()> {
+        if !kind.can_be_compressed() {
+            return Err(Error::InvalidContainerLayout(
+                "structural boxes cannot be written as metadata",
+            ));
+        }
+        if !compress {
+            return self.write_box(kind, payload);
+        }
+        let mut compressed = kind.0.to_vec();
+        compress_brotli(payload, &mut compressed)?;
+        self.write_box(ContainerBoxType::BROTLI_COMPRESSED, &compressed)
+    }
+
+    /// Writes the whole codestream in a single `jxlc` box.
+    pub fn write_codestream(&mut self, codestream: &[u8]) -> Result<()> {
+        if self.codestream != CodestreamState::NotStarted {
+            return Err(Error::InvalidContainerLayout(
+                "the codestream was already written",
+            ));
+        }
+        self.write_box(ContainerBoxType::CODESTREAM, codestream)?;
+        self.codestream = CodestreamState::Finished;
+        Ok(())
+    }
+
+    /// Writes the next part of the codestream in a `jxlp` box. `is_last` must be set for the last
+    /// part.
+    pub fn write_codestream_part(&mut self, part: &[u8], is_last: bool) -> Result<()> {
+        let index = match self.codestream {
+            CodestreamState::NotStarted => 0,
+            CodestreamState::Partial(index) => index,
+            CodestreamState::Finished => {
+                return Err(Error::InvalidContainerLayout(
+                    "the codestream was already written",
+                ));
+            }
+        };
+        if index > 0x7fffffff {
+            return Err(Error::InvalidContainerLayout("too many codestream parts"));
+        }
+        let header = index | if is_last { 0x80000000 } else { 0 };
+        self.write_box_header(ContainerBoxType::PARTIAL_CODESTREAM, part.len() as u64 + 4)?;
+        self.sink.write_all(&header.to_be_bytes())?;
+        self.sink.write_all(part)?;
+        self.codestream = if is_last {
+            CodestreamState::Finished
+        } else {
+            CodestreamState::Partial(index + 1)
+        };
+        Ok(())
+    }
+
+    /// Writes a box of any type with the given payload, for example to copy a box from another
+    /// container.
+    pub fn write_box(&mut self, ty: ContainerBoxType, payload: &[u8]) -> Result<()> {
+        self.write_box_header(ty, payload.len() as u64)?;
+        self.sink.write_all(payload)?;
+        Ok(())
+    }
+
+    /// Returns the sink, after checking that the codestream was completely written.
+    pub fn finish(mut self) -> Result<W> {
+        if self.codestream != CodestreamState::Finished {
+            return Err(Error::InvalidContainerLayout(
+                "the codestream is missing or incomplete",
+            ));
+        }
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+
+    fn write_box_header(&mut self, ty: ContainerBoxType, payload_size: u64) -> Result<()> {
+        self.sink.write_all(&box_header(ty, payload_size))?;
+        self.boxes_written += 1;
+        Ok(())
+    }
+}
+
+/// Returns the header of a box of type `ty` with `payload_size` bytes of payload, which uses a
+/// 64-bit size if the box does not fit in a 32-bit one.
+fn box_header(ty: ContainerBoxType, payload_size: u64) -> Vec<u8> {
+    match u32::try_from(payload_size + 8) {
+        Ok(size) => [&size.to_be_bytes()[..], &ty.0].concat(),
+        Err(_) => [
+            &1u32.to_be_bytes()[..],
+            &ty.0,
+            &(payload_size + 16).to_be_bytes(),
+        ]
+        .concat(),
+    }
+}
+
+pub(crate) fn register_brotli_boxes(capabilities: &mut crate::Capabilities) {
+    capabilities.brotli_boxes = cfg!(feature = "brotli");
+}
+
+#[cfg(feature = "brotli")]
+fn compress_brotli(payload: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let params = brotli::enc::BrotliEncoderParams::default();
+    brotli::BrotliCompress(&mut &payload[..], out, &params)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "brotli"))]
+fn compress_brotli(_payload: &[u8], _out: &mut Vec<u8>) -> Result<()> {
+    Err(Error::BrotliUnsupported)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::tests::decode,
+        container::box_header::{ContainerBoxHeader, HeaderParseResult},
+        util::test::check_equal_images,
+    };
+
+    /// Splits `file` into its boxes, as (type, payload) pairs.
+    fn parse_boxes(mut file: &[u8]) -> Vec<(ContainerBoxType, &[u8])> {
+        let mut boxes = vec![];
+        while !file.is_empty() {
+            let HeaderParseResult::Done {
+                header,
+                header_size,
+            } = ContainerBoxHeader::parse(file).unwrap()
+            else {
+                panic!("truncated box header");
+            };
+            let size = header.box_size().unwrap() as usize;
+            boxes.push((header.box_type(), &file[header_size..header_size + size]));
+            file = &file[header_size + size..];
+        }
+        boxes
+    }
+
+    fn assert_same_decode(a: &[u8], b: &[u8]) {
+        let (_, a) = decode(a, usize::MAX, false, false, None).unwrap();
+        let (_, b) = decode(b, usize::MAX, false, false, None).unwrap();
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            for (a, b) in a.iter().zip(b.iter()) {
+                check_equal_images(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn remux_codestream() {
+        let codestream = std::fs::read("resources/test/basic.jxl").unwrap();
+        let mut writer = ContainerWriter::new(vec![]);
+        writer.write_signature().unwrap();
+        writer.write_level(5).unwrap();
+        writer.write_codestream(&codestream).unwrap();
+        let file = writer.finish().unwrap();
+        assert_eq!(
+            parse_boxes(&file),
+            [
+                (ContainerBoxType::JXL, &CONTAINER_SIGNATURE[8..]),
+                (ContainerBoxType::FILE_TYPE, &FILE_TYPE[..]),
+                (ContainerBoxType::JXL_LEVEL, &[5][..]),
+                (ContainerBoxType::CODESTREAM, &codestream[..]),
+            ]
+        );
+        assert_same_decode(&codestream, &file);
+    }
+
+    #[test]
+    fn remux_codestream_parts() {
+        let codestream = std::fs::read("resources/test/basic.jxl").unwrap();
+        let mut writer = ContainerWriter::new(vec![]);
+        writer.write_signature().unwrap();
+        let mut parts = codestream.chunks(100).peekable();
+        while let Some(part) = parts.next() {
+            writer
+                .write_codestream_part(part, parts.peek().is_none())
+                .unwrap();
+            writer
+                .write_metadata_box(ContainerBoxType::XML, b"<x/>", false)
+                .unwrap();
+        }
+        let file = writer.finish().unwrap();
+        let indices: Vec<_> = parse_boxes(&file)
+            .into_iter()
+            .filter(|(ty, _)| *ty == ContainerBoxType::PARTIAL_CODESTREAM)
+            .map(|(_, payload)| u32::from_be_bytes(payload[..4].try_into().unwrap()))
+            .collect();
+        let num_parts = codestream.len().div_ceil(100) as u32;
+        assert_eq!(indices.len(), num_parts as usize);
+        for (i, index) in indices.iter().enumerate() {
+            let last = if i as u32 + 1 == num_parts {
+                0x80000000
+            } else {
+                0
+            };
+            assert_eq!(*index, i as u32 | last);
+        }
+        assert_same_decode(&codestream, &file);
+    }
+
+    #[test]
+    fn metadata_boxes() {
+        let exif = b"\0\0\0\0MM\0*\0\0\0\x08\0\0".repeat(10);
+        let mut writer = ContainerWriter::new(vec![]);
+        writer.write_signature().unwrap();
+        writer
+            .write_metadata_box(ContainerBoxType::EXIF, &exif, false)
+            .unwrap();
+        writer.write_codestream(&[0xff, 0x0a]).unwrap();
+        let file = writer.finish().unwrap();
+        assert_eq!(parse_boxes(&file)[2], (ContainerBoxType::EXIF, &exif[..]));
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn compressed_metadata_boxes() {
+        use std::io::Read;
+        let xmp = b"<x:xmpmeta xmlns:x='adobe:ns:meta/'></x:xmpmeta>".repeat(20);
+        let mut writer = ContainerWriter::new(vec![]);
+        writer.write_signature().unwrap();
+        writer.write_codestream(&[0xff, 0x0a]).unwrap();
+        writer
+            .write_metadata_box(ContainerBoxType::XML, &xmp, true)
+            .unwrap();
+        let file = writer.finish().unwrap();
+        let (ty, payload) = parse_boxes(&file)[3];
+        assert_eq!(ty, ContainerBoxType::BROTLI_COMPRESSED);
+        assert_eq!(&payload[..4], b"xml ");
+        assert!(payload.len() < xmp.len());
+        let mut decompressed = vec![];
+        brotli::Decompressor::new(&payload[4..], 4096)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, xmp);
+    }
+
+    #[test]
+    fn large_box_header() {
+        for payload_size in [0, u32::MAX as u64 - 8, u32::MAX as u64 - 7, 1 << 40] {
+            let header = box_header(ContainerBoxType::JUMBF, payload_size);
+            assert_eq!(
+                header.len(),
+                if payload_size + 8 > u32::MAX as u64 {
+                    16
+                } else {
+                    8
+                }
+            );
+            let HeaderParseResult::Done {
+                header: parsed,
+                header_size,
+            } = ContainerBoxHeader::parse(&header).unwrap()
+            else {
+                panic!("truncated box header");
+            };
+            assert_eq!(header_size, header.len());
+            assert_eq!(parsed.box_type(), ContainerBoxType::JUMBF);
+            assert_eq!(parsed.box_size(), Some(payload_size));
+        }
+    }
+
+    #[test]
+    fn invalid_layouts() {
+        let mut writer = ContainerWriter::new(vec![]);
+        assert!(writer.write_level(5).is_err());
+        writer.write_signature().unwrap();
+        assert!(writer.write_signature().is_err());
+        assert!(
+            writer
+                .write_metadata_box(ContainerBoxType::CODESTREAM, &[], false)
+                .is_err()
+        );
+        writer.write_codestream_part(&[0xff], false).unwrap();
+        assert!(writer.write_codestream(&[0x0a]).is_err());
+        writer.write_codestream_part(&[0x0a], true).unwrap();
+        assert!(writer.write_codestream_part(&[], true).is_err());
+        writer.finish().unwrap();
+
+        let mut writer = ContainerWriter::new(vec![]);
+        writer.write_signature().unwrap();
+        writer.write_codestream_part(&[0xff], false).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
@@ -397,6 +397,12 @@ impl AnsHistogram {
     pub fn single_symbol(&self) -> Option<u32> {
         self.single_symbol
     }
+
+    /// Returns the frequency of each symbol. There is one bucket per symbol, which keeps the
+    /// frequency of that symbol.
+    fn counts(&self) -> Vec<u16> {
+        self.buckets.iter().map(|b| b.dist).collect()
+    }
 }
 
 #[derive(Debug)]
@@ -415,6 +421,10 @@ impl AnsCodes {
     pub fn single_symbol(&self, ctx: usize) -> Option<u32> {
         self.histograms[ctx].single_symbol()
     }
+
+    pub fn counts(&self, ctx: usize) -> Vec<u16> {
+        self.histograms[ctx].counts()
+    }
 }
 
 #[derive(Debug)]
@@ -5,12 +5,16 @@
 
 use jxl_macros::UnconditionalCoder;
 
+use crate::api::{
+    EntropyCluster, EntropyCodeInfo, EntropyCodePurpose, Lz77Info, SymbolDistribution,
+};
 use crate::bit_reader::BitReader;
 use crate::entropy_coding::ans::*;
 use crate::entropy_coding::context_map::*;
 use crate::entropy_coding::huffman::*;
 use crate::entropy_coding::hybrid_uint::*;
 use crate::error::{Error, Result};
+use crate::frame::Section;
 use crate::headers::encodings::*;
 use crate::util::NewWithCapacity;
 use crate::util::tracing_wrappers::*;
@@ -101,6 +105,9 @@ impl Lz77State {
         ( 8, 4), ( 6, 7), (-6, 7), ( 7, 6), (-7, 6), ( 8, 5), ( 7, 7), (-7, 7), ( 8, 6), ( 8, 7),
     ];
 
+    /// Starts copying `num_to_copy` symbols. As in the specification, distances are clamped to
+    /// the window size and to the number of symbols decoded so far, so copies never start before
+    /// the first symbol.
     #[inline]
     fn apply_copy(&mut self, distance_sym: u32, num_to_copy: u32) {
         let distance_sub_1 = if self.dist_multiplier == 0 {
@@ -108,9 +115,12 @@ impl Lz77State {
         } else if let Some(distance) = distance_sym.checked_sub(120) {
             distance
         } else {
+            // Image contexts use distances relative to the current position in the image, in
+            // units of rows (`dist_multiplier` symbols) and columns.
             let (offset, dist) = Lz77State::SPECIAL_DISTANCES[distance_sym as usize];
-            let dist = (self.dist_multiplier * dist as u32).checked_add_signed(offset as i32 - 1);
-            dist.unwrap_or(0)
+            self.dist_multiplier
+                .saturating_mul(dist as u32)
+                .saturating_add_signed(offset as i32 - 1)
         };
 
         let distance = (((1 << 20) - 1).min(distance_sub_1) + 1).min(self.num_decoded);
@@ -152,6 +162,7 @@ struct RleState {
 }
 
 impl RleState {
+    /// Reads the symbol or repeat of `token`. Returns false if the repeat count overflows.
     #[inline]
     fn push_token(
         &mut self,
@@ -159,18 +170,20 @@ impl RleState {
         histograms: &Histograms,
         br: &mut BitReader,
         cluster: usize,
-    ) {
+    ) -> bool {
         if let Some(token) = token.checked_sub(self.min_symbol) {
             let lz_length_conf = histograms.lz77_length_uint.as_ref().unwrap();
             let count = lz_length_conf.read(token, br);
-            // If this calculation overflows, the bitstream is invalid (it would be rejected
-            // on the LZ77 path), but we don't report an error.
-            self.repeat_count = count.wrapping_add(self.min_length);
+            let Some(repeat_count) = count.checked_add(self.min_length) else {
+                return false;
+            };
+            self.repeat_count = repeat_count;
         } else {
             let sym = histograms.uint_configs[cluster].read(token, br);
             self.last_sym = Some(sym);
             self.repeat_count = 1;
         }
+        true
     }
 
     #[inline]
@@ -194,7 +207,7 @@ enum SymbolReaderState {
 #[derive(Debug, Clone, Default)]
 struct ErrorState {
     lz77_repeat: bool,
-    arithmetic_overflow: bool,
+    lz77_length_overflow: bool,
 }
 
 impl ErrorState {
@@ -205,8 +218,8 @@ impl ErrorState {
     fn check_for_error(&self) -> Result<()> {
         if self.lz77_repeat {
             Err(Error::UnexpectedLz77Repeat)
-        } else if self.arithmetic_overflow {
-            Err(Error::ArithmeticOverflow)
+        } else if self.lz77_length_overflow {
+            Err(Error::Lz77LengthOverflow)
         } else {
             Ok(())
         }
@@ -366,7 +379,7 @@ impl SymbolReader {
                         num_to_copy,
                         lz77_state.min_length, "LZ77 num_to_copy overflow"
                     );
-                    self.errors.arithmetic_overflow = true;
+                    self.errors.lz77_length_overflow = true;
                     return 0;
                 };
 
@@ -392,7 +405,10 @@ impl SymbolReader {
                     Codes::Huffman(hc) => hc.read(br, cluster),
                     Codes::Ans(ans) => self.ans_reader.read(ans, br, cluster),
                 };
-                rle_state.push_token(token, histograms, br, cluster);
+                if !rle_state.push_token(token, histograms, br, cluster) {
+                    self.errors.lz77_length_overflow = true;
+                    return 0;
+                }
                 if let Some(sym) = rle_state.pull_symbol() {
                     sym
                 } else {
@@ -403,6 +419,7 @@ impl SymbolReader {
         }
     }
 
+    #[cfg(test)]
     #[inline(never)]
     pub fn read_unsigned_clustered(
         &mut self,
@@ -469,9 +486,21 @@ impl SymbolReader {
         unpack_signed(unsigned)
     }
 
+    /// Checks that the stream ended consistently. Besides errors found while reading symbols,
+    /// this rejects streams whose last copy extends past the symbols that were read, which would
+    /// otherwise make the number of symbols coded by the stream larger than the number of
+    /// symbols that the section holds.
     pub fn check_final_state(self, histograms: &Histograms, br: &mut BitReader) -> Result<()> {
         self.errors.check_for_error()?;
         br.check_for_error()?;
+        let pending = match &self.state {
+            SymbolReaderState::None => 0,
+            SymbolReaderState::Lz77(lz77_state) => lz77_state.num_to_copy,
+            SymbolReaderState::Rle(rle_state) => rle_state.repeat_count,
+        };
+        if pending > 0 {
+            return Err(Error::Lz77CopyPastEnd(pending));
+        }
         match &histograms.codes {
             Codes::Huffman(_) => Ok(()),
             Codes::Ans(_) => self.ans_reader.check_final_state(),
@@ -626,6 +655,7 @@ impl Histograms {
         self.context_map[context] as usize
     }
 
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
     pub fn num_histograms(&self) -> usize {
         *self.context_map.iter().max().unwrap() as usize + 1
     }
@@ -634,6 +664,53 @@ impl Histograms {
         self.context_map.resize(num_contexts, 0);
     }
 
+    /// Describes these histograms. Prefix code lengths are only known if entropy codes were being
+    /// dumped when they were decoded.
+    pub(super) fn info(
+        &self,
+        purpose: EntropyCodePurpose,
+        frame: Option<usize>,
+        section: Option<Section>,
+    ) -> EntropyCodeInfo {
+        let lz77 = match (&self.lz77_params, &self.lz77_length_uint) {
+            (
+                Lz77Params {
+                    enabled: true,
+                    min_symbol: Some(min_symbol),
+                    min_length: Some(min_length),
+                },
+                Some(length_uint),
+            ) => Some(Lz77Info {
+                min_symbol: *min_symbol,
+                min_length: *min_length,
+                length_uint: length_uint.info(),
+            }),
+            _ => None,
+        };
+        let clusters = self
+            .uint_configs
+            .iter()
+            .enumerate()
+            .map(|(cluster, uint)| EntropyCluster {
+                uint: uint.info(),
+                distribution: match &self.codes {
+                    Codes::Huffman(hc) => hc.distribution(cluster),
+                    Codes::Ans(ans) => SymbolDistribution::Ans {
+                        counts: ans.counts(cluster),
+                    },
+                },
+            })
+            .collect();
+        EntropyCodeInfo {
+            frame,
+            section,
+            purpose,
+            context_map: self.context_map.clone(),
+            lz77,
+            clusters,
+        }
+    }
+
     /// Returns true if the config 420 fast path can be safely used.
     /// Config 420: split_exponent=4, msb_in_token=2, lsb_in_token=0 (common pattern)
     /// Requires: all configs are 420 AND LZ77 is disabled
@@ -644,25 +721,6 @@ impl Histograms {
 
 #[cfg(test)]
 impl Histograms {
-    /// Builds a decoder that reads an octet at a time and emits its bit-reversed value.
-    pub fn reverse_octet(num_contexts: usize) -> Self {
-        let d = HuffmanCodes::byte_histogram();
-        let codes = Codes::Huffman(d);
-        let uint_configs = vec![HybridUint::new(8, 0, 0)];
-        Self {
-            lz77_params: Lz77Params {
-                enabled: false,
-                min_symbol: None,
-                min_length: None,
-            },
-            lz77_length_uint: None,
-            uint_configs,
-            log_alpha_size: 15,
-            context_map: vec![0u8; num_contexts],
-            codes,
-        }
-    }
-
     pub fn rle(num_contexts: usize, min_symbol: u32, min_length: u32) -> Self {
         let d = HuffmanCodes::byte_histogram_rle();
         let codes = Codes::Huffman(d);
@@ -713,6 +771,7 @@ mod test {
     use test_log::test;
 
     use super::*;
+    use crate::test_utils::BitstreamBuilder;
 
     #[test]
     fn rle_arb() {
@@ -768,4 +827,109 @@ mod test {
             Ok(())
         });
     }
+
+    /// Decodes `count` symbols of context 0 from `builder`, which starts with LZ77 histograms.
+    fn read_lz77_symbols(
+        builder: &BitstreamBuilder,
+        image_width: Option<usize>,
+        count: usize,
+    ) -> Result<Vec<u32>> {
+        let mut builder = builder.clone();
+        builder.zero_pad_to_byte();
+        let bytes = builder.finish();
+        let mut br = BitReader::new(&bytes);
+        let histograms = Histograms::decode(1, &mut br, /*allow_lz77=*/ true)?;
+        let mut reader = SymbolReader::new(&histograms, &mut br, image_width)?;
+        let symbols = (0..count)
+            .map(|_| reader.read_unsigned(&histograms, &mut br, 0))
+            .collect();
+        reader.check_final_state(&histograms, &mut br)?;
+        Ok(symbols)
+    }
+
+    #[test]
+    fn lz77_distance_is_clamped() {
+        let mut builder = BitstreamBuilder::new();
+        builder
+            .write_lz77_histograms(1, 20, 3)
+            .write_symbol(5)
+            .write_symbol(7)
+            // A distance of 10 symbols, with only 2 decoded so far.
+            .write_lz77_copy(4, 9)
+            .write_symbol(200);
+        assert_eq!(
+            read_lz77_symbols(&builder, None, 7).unwrap(),
+            [5, 7, 5, 7, 5, 7, 200]
+        );
+    }
+
+    #[test]
+    fn lz77_copy_past_end() {
+        let mut builder = BitstreamBuilder::new();
+        builder
+            .write_lz77_histograms(1, 20, 3)
+            .write_symbol(5)
+            .write_lz77_copy(6, 0);
+        assert!(matches!(
+            read_lz77_symbols(&builder, None, 4),
+            Err(Error::Lz77CopyPastEnd(3))
+        ));
+        assert_eq!(read_lz77_symbols(&builder, None, 7).unwrap(), [5; 7]);
+    }
+
+    #[test]
+    fn lz77_length_overflow() {
+        let mut builder = BitstreamBuilder::new();
+        builder
+            .write_lz77_histograms(1, 20, 3)
+            .write_symbol(5)
+            .write_lz77_copy(u32::MAX as u64 + 1, 0);
+        assert!(matches!(
+            read_lz77_symbols(&builder, None, 2),
+            Err(Error::Lz77LengthOverflow)
+        ));
+    }
+
+    #[test]
+    fn lz77_special_distances() {
+        let mut builder = BitstreamBuilder::new();
+        builder.write_lz77_histograms(1, 20, 3);
+        let row: Vec<u32> = (0..10).map(|x| x * 10).collect();
+        for &sym in &row {
+            builder.write_symbol(sym);
+        }
+        builder
+            // (0, 1): the symbol above, 10 symbols back.
+            .write_lz77_copy(3, 0)
+            // (1, 0): the symbol to the left.
+            .write_lz77_copy(3, 1)
+            // (-1, 1): the symbol above and to the left, 9 symbols back.
+            .write_lz77_copy(4, 3)
+            // Symbol 120 is a plain distance of 1.
+            .write_symbol(255)
+            .write_lz77_copy(3, 120)
+            // (8, 7): 78 symbols back, clamped to the 24 symbols decoded so far.
+            .write_lz77_copy(5, 119);
+        let mut expected = row.clone();
+        expected.extend([0, 10, 20]);
+        expected.extend([20, 20, 20]);
+        expected.extend([70, 80, 90, 0]);
+        expected.extend([255, 255, 255, 255]);
+        expected.extend([0, 10, 20, 30, 40]);
+        assert_eq!(read_lz77_symbols(&builder, Some(10), 29).unwrap(), expected);
+    }
+
+    #[test]
+    fn lz77_special_distance_of_huge_image() {
+        let mut builder = BitstreamBuilder::new();
+        builder
+            .write_lz77_histograms(1, 20, 3)
+            .write_symbol(5)
+            .write_symbol(7)
+            .write_lz77_copy(3, 119);
+        assert_eq!(
+            read_lz77_symbols(&builder, Some(1 << 30), 5).unwrap(),
+            [5, 7, 5, 7, 5]
+        );
+    }
 }
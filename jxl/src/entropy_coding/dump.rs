@@ -0,0 +1,102 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::cell::{Cell, RefCell};
+
+use crate::{
+    api::{EntropyCodeInfo, EntropyCodePurpose},
+    entropy_coding::decode::Histograms,
+    error::Result,
+    frame::Section,
+};
+
+/// Whether entropy codes can be dumped, which is tied to the `debug-tools` feature. When it is
+/// not enabled, all the code below is optimized out.
+pub(crate) const DUMP: bool = cfg!(feature = "debug-tools");
+
+pub(crate) fn register_entropy_code_dump(capabilities: &mut crate::Capabilities) {
+    capabilities.entropy_code_dump = DUMP;
+}
+
+thread_local! {
+    static THREAD_DUMP: Cell<bool> = const { Cell::new(false) };
+    static THREAD_FRAME: Cell<Option<usize>> = const { Cell::new(None) };
+    static THREAD_SECTION: Cell<Option<Section>> = const { Cell::new(None) };
+    static THREAD_CODES: RefCell<Vec<EntropyCodeInfo>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Makes the current thread record the entropy codes it decodes, until this is called again
+/// with `false`.
+pub(crate) fn set_thread_entropy_dump(enabled: bool) {
+    if DUMP {
+        THREAD_DUMP.with(|d| d.set(enabled));
+    }
+}
+
+#[inline(always)]
+pub(crate) fn entropy_dump_enabled() -> bool {
+    DUMP && THREAD_DUMP.with(Cell::get)
+}
+
+/// Sets the frame that the entropy codes decoded next belong to, `None` for the preview frame
+/// and for codes that are not part of a frame.
+pub(crate) fn set_thread_entropy_frame(frame: Option<usize>) {
+    if DUMP {
+        THREAD_FRAME.with(|f| f.set(frame));
+    }
+}
+
+/// Restores the previous section when dropped, see [`enter_entropy_section`].
+pub(crate) struct EntropySectionGuard(Option<Section>);
+
+impl Drop for EntropySectionGuard {
+    fn drop(&mut self) {
+        if DUMP {
+            THREAD_SECTION.with(|s| s.set(self.0));
+        }
+    }
+}
+
+/// Makes the entropy codes decoded until the returned guard is dropped belong to `section`.
+pub(crate) fn enter_entropy_section(section: Section) -> EntropySectionGuard {
+    if !DUMP {
+        return EntropySectionGuard(None);
+    }
+    EntropySectionGuard(THREAD_SECTION.with(|s| s.replace(Some(section))))
+}
+
+/// Records `histograms`, which were just decoded, if the current thread dumps entropy codes.
+pub(crate) fn record_entropy_code(purpose: EntropyCodePurpose, histograms: &Histograms) {
+    if entropy_dump_enabled() {
+        let info = histograms.info(
+            purpose,
+            THREAD_FRAME.with(Cell::get),
+            THREAD_SECTION.with(Cell::get),
+        );
+        THREAD_CODES.with(|c| c.borrow_mut().push(info));
+    }
+}
+
+/// Runs `f`, and forgets the entropy codes it recorded if it fails. Used where headers are read
+/// again from the start once more input is available.
+pub(crate) fn discard_entropy_codes_on_error<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if !entropy_dump_enabled() {
+        return f();
+    }
+    let recorded = THREAD_CODES.with(|c| c.borrow().len());
+    let result = f();
+    if result.is_err() {
+        THREAD_CODES.with(|c| c.borrow_mut().truncate(recorded));
+    }
+    result
+}
+
+/// Returns the entropy codes recorded on the current thread since the last call.
+pub(crate) fn take_thread_entropy_codes() -> Vec<EntropyCodeInfo> {
+    if !DUMP {
+        return vec![];
+    }
+    THREAD_CODES.with(|c| std::mem::take(&mut *c.borrow_mut()))
+}
@@ -5,8 +5,9 @@
 
 use std::fmt::Debug;
 
+use crate::api::SymbolDistribution;
 use crate::bit_reader::BitReader;
-use crate::entropy_coding::decode::*;
+use crate::entropy_coding::{decode::*, dump::entropy_dump_enabled};
 use crate::error::{Error, Result};
 use crate::util::{CeilLog2, NewWithCapacity, tracing_wrappers::*};
 
@@ -32,6 +33,8 @@ impl Debug for TableEntry {
 #[derive(Debug)]
 struct Table {
     entries: Vec<TableEntry>,
+    /// Length of the code of each symbol, only kept when entropy codes are dumped.
+    code_lengths: Vec<u8>,
 }
 
 /* Returns reverse(reverse(key, len) + 1, len), where reverse(key, len) is the
@@ -401,13 +404,22 @@ impl Table {
 
     #[instrument(level = "trace", skip(br), ret, err)]
     pub fn decode(al_size: usize, br: &mut BitReader) -> Result<Table> {
+        let mut code_lengths = vec![];
         let entries = if al_size == 1 {
             vec![TableEntry { bits: 0, value: 0 }; TABLE_SIZE]
         } else {
             assert!(al_size < 1 << HUFFMAN_MAX_BITS);
             let simple_code_or_skip = br.read(2)? as usize;
             if simple_code_or_skip == 1 {
-                Table::decode_simple_table(al_size, br)?
+                let entries = Table::decode_simple_table(al_size, br)?;
+                if entropy_dump_enabled() {
+                    // Simple codes are at most 3 bits long, so every symbol has root entries.
+                    code_lengths = vec![0; al_size];
+                    for entry in &entries {
+                        code_lengths[entry.value as usize] = entry.bits;
+                    }
+                }
+                entries
             } else {
                 let mut code_length_code_lengths = [0u8; CODE_LENGTHS_CODE];
                 let mut space = 32;
@@ -432,13 +444,20 @@ impl Table {
                 if num_codes != 1 && space != 0 {
                     return Err(Error::InvalidHuffman);
                 }
-                let code_lengths =
+                let lengths =
                     Table::decode_huffman_code_lengths(code_length_code_lengths, al_size, br)?;
-                debug!(?code_lengths);
-                Table::build(TABLE_BITS, &code_lengths)?
+                debug!(?lengths);
+                let entries = Table::build(TABLE_BITS, &lengths)?;
+                if entropy_dump_enabled() {
+                    code_lengths = lengths;
+                }
+                entries
             }
         };
-        Ok(Table { entries })
+        Ok(Table {
+            entries,
+            code_lengths,
+        })
     }
 
     #[inline]
@@ -489,6 +508,17 @@ impl HuffmanCodes {
             None
         }
     }
+
+    /// Returns the code lengths of `ctx`, which are only known if entropy codes were being
+    /// dumped when it was decoded.
+    pub fn distribution(&self, ctx: usize) -> SymbolDistribution {
+        match self.single_symbol(ctx) {
+            Some(symbol) => SymbolDistribution::PrefixSingle { symbol },
+            None => SymbolDistribution::Prefix {
+                code_lengths: self.tables[ctx].code_lengths.clone(),
+            },
+        }
+    }
 }
 
 #[cfg(test)]
@@ -502,6 +532,7 @@ impl Table {
                 };
                 TABLE_SIZE
             ],
+            code_lengths: vec![],
         }
     }
 }
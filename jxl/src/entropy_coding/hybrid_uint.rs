@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::HybridUintInfo;
 use crate::bit_reader::BitReader;
 use crate::error::Error;
 
@@ -53,6 +54,14 @@ impl HybridUint {
         })
     }
 
+    pub fn info(&self) -> HybridUintInfo {
+        HybridUintInfo {
+            split_exponent: self.split_exponent,
+            msb_in_token: self.msb_in_token,
+            lsb_in_token: self.lsb_in_token,
+        }
+    }
+
     /// Returns true if this config matches the 420 pattern (common in e3 images):
     /// split_exponent=4, msb_in_token=2, lsb_in_token=0
     #[inline(always)]
@@ -6,5 +6,6 @@
 pub mod ans;
 pub mod context_map;
 pub mod decode;
+pub(crate) mod dump;
 pub mod huffman;
 pub mod hybrid_uint;
@@ -8,12 +8,15 @@ use std::collections::TryReserveError;
 use thiserror::Error;
 
 use crate::{
-    api::{JxlColorType, JxlDataFormat},
+    api::{JxlColorType, JxlDataFormat, JxlExtraChannelType},
     entropy_coding::huffman::HUFFMAN_MAX_BITS,
-    features::spline::Point,
+    frame::Section,
     image::DataTypeTag,
 };
 
+/// A spline control point, as reported by spline errors.
+pub use crate::features::spline::Point;
+
 #[derive(Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
@@ -25,6 +28,8 @@ pub enum Error {
     InvalidAFVBands,
     #[error("Invalid quantization table weight: {0}")]
     InvalidQuantizationTableWeight(f32),
+    /// More input is needed; the size hint is an estimate of the number of missing bytes, which
+    /// should not exceed the actual number.
     #[error("Read out of bounds; size hint: {0}")]
     OutOfBounds(usize),
     #[error("Section is too short")]
@@ -33,10 +38,11 @@ pub enum Error {
     NonZeroPadding,
     #[error("Invalid signature")]
     InvalidSignature,
-    #[error("Invalid exponent_bits_per_sample: {0}")]
-    InvalidExponent(u32),
-    #[error("Invalid mantissa_bits: {0}")]
-    InvalidMantissa(i32),
+    #[error(
+        "Unsupported float samples of {0} bits with {1} exponent bits: exponents need 2 to 8 \
+         bits, and mantissas 2 to 23 bits"
+    )]
+    InvalidFloatBitDepth(u32, u32),
     #[error("Invalid bits_per_sample: {0}")]
     InvalidBitsPerSample(u32),
     #[error("Invalid enum value {0} for {1}")]
@@ -61,8 +67,18 @@ pub enum Error {
     InvalidLinearBelow(bool, f32),
     #[error("Overflow when computing a bitstream size")]
     SizeOverflow,
+    #[error("File offset {0} cannot be addressed on this platform")]
+    FileOffsetTooLarge(u64),
     #[error("Invalid ISOBMMF container")]
     InvalidBox,
+    #[error("{0:?} box extends to the end of the file, before the end of the codestream")]
+    LastBoxBeforeCodestreamEnd(String),
+    #[error("Brotli-compressed box contains a {0:?} box, which cannot be compressed")]
+    InvalidCompressedBox(String),
+    #[error("Invalid container layout: {0}")]
+    InvalidContainerLayout(&'static str),
+    #[error("Brotli compression requires the `brotli` feature")]
+    BrotliUnsupported,
     #[error("ICC is too large")]
     IccTooLarge,
     #[error("Invalid ICC stream: unexpected end of stream")]
@@ -75,6 +91,10 @@ pub enum Error {
     Lz77Disallowed,
     #[error("LZ77 repeat symbol encountered without decoding any symbols")]
     UnexpectedLz77Repeat,
+    #[error("LZ77 copy length overflows")]
+    Lz77LengthOverflow,
+    #[error("LZ77 copy extends {0} symbols past the end of the stream")]
+    Lz77CopyPastEnd(u32),
     #[error("Huffman alphabet too large: {0}, max is {max}", max = 1 << HUFFMAN_MAX_BITS)]
     AlphabetTooLargeHuff(usize),
     #[error("Invalid Huffman code")]
@@ -106,6 +126,20 @@ pub enum Error {
     InvalidEcUpsampling(u32, u32, u32),
     #[error("Invalid lf level in UseLFFrame frame: {0}")]
     InvalidLfLevel(u32),
+    #[error("Frame uses an LF frame of level {0}, which was not decoded before it")]
+    MissingLfFrame(u32),
+    #[error("Last frame of an animation has no duration")]
+    LastFrameWithoutDuration,
+    #[error("Codestream continues after the frame marked as last")]
+    DataAfterLastFrame,
+    #[error("Codestream ends without a frame marked as last")]
+    MissingLastFrame,
+    #[error("Input ends before the image header is complete")]
+    TruncatedHeader,
+    #[error("Input ends within a frame")]
+    TruncatedFrame,
+    #[error("All frames have been decoded")]
+    NoMoreFrames,
     #[error("Num_ds: {0} should be smaller than num_passes: {1}")]
     NumPassesTooLarge(u32, u32),
     #[error("Passes::downsample is non-decreasing")]
@@ -116,6 +150,10 @@ pub enum Error {
     PassesLastPassTooLarge,
     #[error("Non-patch reference frame with a crop")]
     NonPatchReferenceWithCrop,
+    #[error("Frame saved as reference {0}, but there are only {1} reference slots")]
+    InvalidSaveAsReference(usize, usize),
+    #[error("Frame header sets reserved flags {0:#x}")]
+    ReservedFrameFlags(u64),
     #[error("Non-444 chroma subsampling is not allowed when adaptive DC smoothing is enabled")]
     Non444ChromaSubsampling,
     #[error("Non-444 chroma subsampling is not allowed for bigger than 8x8 transforms")]
@@ -128,6 +166,8 @@ pub enum Error {
     ImageSizeTooLarge(usize, usize),
     #[error("Image dimension too large: {0}")]
     ImageDimensionTooLarge(u64),
+    #[error("Decoded {0} tokens, more than the limit of {1}")]
+    WorkLimitExceeded(u64, u64),
     #[error("Invalid image size: {0}x{1}")]
     InvalidImageSize(usize, usize),
     // Generic arithmetic overflow. Prefer using other errors if possible.
@@ -151,8 +191,16 @@ pub enum Error {
     InvalidProperty(u32),
     #[error("Invalid alpha channel for blending: {0}, limit is {1}")]
     InvalidBlendingAlphaChannel(usize, usize),
+    #[error("Blending uses extra channel {0} as alpha, but its type is {1:?}")]
+    NonAlphaBlendingChannel(usize, JxlExtraChannelType),
     #[error("Invalid alpha channel for blending: {0}, limit is {1}")]
     PatchesInvalidAlphaChannel(usize, usize),
+    #[error("Cannot blend with reference frame {0}, which was saved before the color transform")]
+    BlendingBeforeColorTransform(usize),
+    #[error(
+        "Cannot blend with reference frame {0} of size {1}x{2}, which does not cover the image"
+    )]
+    BlendingBackgroundTooSmall(usize, usize, usize),
     #[error("Invalid patch blend mode: {0}, limit is {1}")]
     PatchesInvalidBlendMode(u8, u8),
     #[error("Invalid Patch: negative {0}-coordinate: {1} base {0},  {2} delta {0}")]
@@ -263,14 +311,32 @@ pub enum Error {
     IOError(#[from] std::io::Error),
     #[error("Wrong buffer count: {0} buffers given, {1} buffers expected")]
     WrongBufferCount(usize, usize),
+    #[error(
+        "Invalid output buffer {index}: byte size is {actual_bytes_per_row}x{actual_rows}, expected {expected_bytes_per_row}x{expected_rows} ({width}x{height} pixels, {samples_per_pixel} samples per pixel, {data_type:?})"
+    )]
+    OutputBufferMismatch {
+        index: usize,
+        actual_bytes_per_row: usize,
+        actual_rows: usize,
+        expected_bytes_per_row: usize,
+        expected_rows: usize,
+        width: usize,
+        height: usize,
+        samples_per_pixel: usize,
+        data_type: JxlDataFormat,
+    },
     #[error("Image is not grayscale, but grayscale output was requested")]
     NotGrayscale,
+    #[error(
+        "Rendered spot colors of grayscale images need RGB output, but grayscale was requested"
+    )]
+    GrayscaleSpotColors,
     #[error("Invalid output buffer byte size {0}x{1} for {2}x{3} image with type {4:?} {5:?}")]
     InvalidOutputBufferSize(usize, usize, usize, usize, JxlColorType, JxlDataFormat),
     #[error("Attempting to save channels with different downsample amounts: {0:?} and {1:?}")]
     SaveDifferentDownsample((u8, u8), (u8, u8)),
-    #[error("Image has {0} extra channels, more than the maximum of 256")]
-    TooManyExtraChannels(usize),
+    #[error("Image has {0} extra channels, more than the maximum of {1}")]
+    TooManyExtraChannels(usize, usize),
     #[error(
         "CMS transform increases channel count from {in_channels} to {out_channels}, which is not supported"
     )]
@@ -287,6 +353,92 @@ pub enum Error {
     },
     #[error("CMS error: {0}")]
     CmsError(String),
+    #[error("String is not valid UTF-8")]
+    InvalidUtf8String,
+    #[error("Invalid thumbnail size: {0}")]
+    InvalidThumbnailSize(usize),
+    #[error("Invalid output size for resizing: {0}x{1}")]
+    InvalidResizeSize(usize, usize),
+    #[error("Invalid crop of {0}x{1} pixels at ({2}, {3}) for an output of {4}x{5} pixels")]
+    InvalidCrop(usize, usize, usize, usize, usize, usize),
+    #[error(
+        "Container declares an image size of {0}x{1}, but the codestream has a size of {2}x{3}"
+    )]
+    ContainerSizeMismatch(usize, usize, usize, usize),
+    #[error("Internal error: {0}")]
+    Internal(&'static str),
+    #[error("Failed to decode {what}{}", .index.map(|i| format!(" {i}")).unwrap_or_default())]
+    Context {
+        what: &'static str,
+        index: Option<usize>,
+        #[source]
+        source: Box<Error>,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Reports a violated internal invariant. Panics if debug assertions are enabled, so that
+    /// tests catch it, and returns [`Error::Internal`] otherwise, as embedders cannot recover
+    /// from panics.
+    #[track_caller]
+    pub(crate) fn internal(what: &'static str) -> Error {
+        debug_assert!(false, "internal error: {what}");
+        Error::Internal(what)
+    }
+
+    /// Whether the error comes from the data being decoded, rather than from a resource limit or
+    /// an internal error, so that the section holding it can be concealed.
+    pub(crate) fn is_corrupt_data(&self) -> bool {
+        !matches!(
+            self.root_cause(),
+            Error::OutOfMemory(_)
+                | Error::ImageOutOfMemory(..)
+                | Error::WorkLimitExceeded(..)
+                | Error::Internal(_)
+        )
+    }
+
+    /// Returns the innermost error, skipping any [`Error::Context`] wrappers.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
+}
+
+/// Attaches the location in the codestream at which an error happened. Only the error path
+/// allocates.
+pub(crate) trait ErrorContext<T> {
+    fn context(self, what: &'static str, index: Option<usize>) -> Result<T>;
+
+    fn at_section(self, section: Section) -> Result<T>
+    where
+        Self: Sized,
+    {
+        // A single call may decode several passes of a HF group, so the pass is not reported.
+        let (what, index) = match section {
+            Section::LfGlobal => ("LF global section", None),
+            Section::Lf { group } => ("LF group", Some(group.index())),
+            Section::HfGlobal => ("HF global section", None),
+            Section::Hf { group, .. } => ("HF group", Some(group.index())),
+        };
+        self.context(what, index)
+    }
+}
+
+impl<T> ErrorContext<T> for Result<T> {
+    fn context(self, what: &'static str, index: Option<usize>) -> Result<T> {
+        self.map_err(|err| match err {
+            // Signals that more input is needed, which callers match on.
+            Error::OutOfBounds(_) => err,
+            _ => Error::Context {
+                what,
+                index,
+                source: Box::new(err),
+            },
+        })
+    }
+}
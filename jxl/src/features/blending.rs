@@ -8,12 +8,21 @@
 use crate::headers::extra_channels::{ExtraChannel, ExtraChannelInfo};
 
 use super::patches::{PatchBlendMode, PatchBlending};
+use crate::render::blit::{BlendMode, blend_row};
 
 #[inline]
 fn maybe_clamp(v: f32, clamp: bool) -> f32 {
     if clamp { v.clamp(0.0, 1.0) } else { v }
 }
 
+/// Clamps the samples of an extra channel that is not an alpha channel to [0, 1], for it to be
+/// used as alpha. NaNs become 0.
+fn clamped_alpha(row: &[f32]) -> Vec<f32> {
+    row.iter()
+        .map(|&v| if v.is_nan() { 0.0 } else { v.clamp(0.0, 1.0) })
+        .collect()
+}
+
 pub fn perform_blending<T: AsRef<[f32]>, V: AsMut<[f32]>>(
     bg: &mut [V],
     fg: &[T],
@@ -26,238 +35,207 @@ pub fn perform_blending<T: AsRef<[f32]>, V: AsMut<[f32]>>(
         .any(|info| info.ec_type == ExtraChannel::Alpha);
     let num_ec = extra_channel_info.len();
     let xsize = bg[0].as_mut().len();
-
-    let mut tmp = vec![vec![0.0f32; xsize]; 3 + num_ec];
+    // Rows of `fg` may be longer than those of `bg`.
+    let fg: Vec<&[f32]> = fg.iter().map(|row| &row.as_ref()[..xsize]).collect();
+
+    // Channels are blended in place, one after the other, so only the extra channels that are
+    // used as alpha are copied, to blend the others with their values before blending. This
+    // keeps the cost of images with many extra channels to a single pass over each channel.
+    // Extra channels of other types than alpha can be used as alpha too (which is only allowed in
+    // permissive mode), and are clamped to [0, 1] for that, in both the background and the
+    // foreground.
+    let is_alpha = |ec: usize| extra_channel_info[ec].ec_type == ExtraChannel::Alpha;
+    let mut alpha_channels: Vec<usize> = ec_blending[..num_ec]
+        .iter()
+        .map(|blending| blending.alpha_channel)
+        .chain(has_alpha.then_some(color_blending.alpha_channel))
+        .collect();
+    alpha_channels.sort_unstable();
+    alpha_channels.dedup();
+    let saved_alpha: Vec<Vec<f32>> = alpha_channels
+        .iter()
+        .map(|&alpha| match is_alpha(alpha) {
+            true => bg[3 + alpha].as_mut().to_vec(),
+            false => clamped_alpha(bg[3 + alpha].as_mut()),
+        })
+        .collect();
+    let clamped_fg_alpha: Vec<Option<Vec<f32>>> = alpha_channels
+        .iter()
+        .map(|&alpha| (!is_alpha(alpha)).then(|| clamped_alpha(fg[3 + alpha])))
+        .collect();
+    let bg_alpha =
+        |alpha: usize| -> &[f32] { &saved_alpha[alpha_channels.binary_search(&alpha).unwrap()] };
+    let fg_alpha = |alpha: usize| -> &[f32] {
+        clamped_fg_alpha[alpha_channels.binary_search(&alpha).unwrap()]
+            .as_deref()
+            .unwrap_or(fg[3 + alpha])
+    };
+    // Only alpha channels can hold premultiplied (associated) alpha.
+    let alpha_associated =
+        |alpha: usize| is_alpha(alpha) && extra_channel_info[alpha].alpha_associated();
+
+    // Blends the background `out` onto the foreground `src`, and stores the result in `out`.
+    let mut scratch = Vec::new();
+    let mut blend_below = |out: &mut [f32], src: &[f32], mode, alpha, clamp| {
+        scratch.clear();
+        scratch.extend_from_slice(src);
+        blend_row(&mut scratch, out, mode, alpha, clamp);
+        out.copy_from_slice(&scratch);
+    };
 
     for i in 0..num_ec {
         let alpha = ec_blending[i].alpha_channel;
         let clamp = ec_blending[i].clamp;
-        let alpha_associated = extra_channel_info[alpha].alpha_associated();
+        let alpha_associated = alpha_associated(alpha);
+        let out = bg[3 + i].as_mut();
+        let (bg_a, fg_a) = (bg_alpha(alpha), fg_alpha(alpha));
 
         match ec_blending[i].mode {
-            PatchBlendMode::Add => {
-                for x in 0..xsize {
-                    tmp[3 + i][x] = bg[3 + i].as_mut()[x] + fg[3 + i].as_ref()[x];
-                }
-            }
+            PatchBlendMode::Add => blend_row(out, fg[3 + i], BlendMode::Add, None, clamp),
             PatchBlendMode::BlendAbove => {
                 if i == alpha {
                     for x in 0..xsize {
-                        let fa = maybe_clamp(fg[3 + alpha].as_ref()[x], clamp);
-                        tmp[3 + i][x] = 1.0 - (1.0 - fa) * (1.0 - bg[3 + i].as_mut()[x]);
+                        let fa = maybe_clamp(fg_a[x], clamp);
+                        out[x] = 1.0 - (1.0 - fa) * (1.0 - bg_a[x]);
                     }
                 } else if alpha_associated {
-                    for x in 0..xsize {
-                        let fa = maybe_clamp(fg[3 + alpha].as_ref()[x], clamp);
-                        tmp[3 + i][x] = fg[3 + i].as_ref()[x] + bg[3 + i].as_mut()[x] * (1.0 - fa);
-                    }
+                    blend_row(out, fg[3 + i], BlendMode::Blend, Some(fg_a), clamp);
                 } else {
                     for x in 0..xsize {
-                        let fa = maybe_clamp(fg[3 + alpha].as_ref()[x], clamp);
-                        let new_a = 1.0 - (1.0 - fa) * (1.0 - bg[3 + alpha].as_mut()[x]);
+                        let fa = maybe_clamp(fg_a[x], clamp);
+                        let new_a = 1.0 - (1.0 - fa) * (1.0 - bg_a[x]);
                         let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
-                        tmp[3 + i][x] = (fg[3 + i].as_ref()[x] * fa
-                            + bg[3 + i].as_mut()[x] * bg[3 + alpha].as_mut()[x] * (1.0 - fa))
-                            * rnew_a;
+                        out[x] = (fg[3 + i][x] * fa + out[x] * bg_a[x] * (1.0 - fa)) * rnew_a;
                     }
                 }
             }
             PatchBlendMode::BlendBelow => {
                 if i == alpha {
                     for x in 0..xsize {
-                        let ba = maybe_clamp(bg[3 + alpha].as_mut()[x], clamp);
-                        tmp[3 + i][x] = 1.0 - (1.0 - ba) * (1.0 - fg[3 + i].as_ref()[x]);
+                        let ba = maybe_clamp(bg_a[x], clamp);
+                        out[x] = 1.0 - (1.0 - ba) * (1.0 - fg_a[x]);
                     }
                 } else if alpha_associated {
-                    for x in 0..xsize {
-                        let ba = maybe_clamp(bg[3 + alpha].as_mut()[x], clamp);
-                        tmp[3 + i][x] = bg[3 + i].as_mut()[x] + fg[3 + i].as_ref()[x] * (1.0 - ba);
-                    }
+                    blend_below(out, fg[3 + i], BlendMode::Blend, Some(bg_a), clamp);
                 } else {
                     for x in 0..xsize {
-                        let ba = maybe_clamp(bg[3 + alpha].as_mut()[x], clamp);
-                        let new_a = 1.0 - (1.0 - ba) * (1.0 - fg[3 + alpha].as_ref()[x]);
+                        let ba = maybe_clamp(bg_a[x], clamp);
+                        let new_a = 1.0 - (1.0 - ba) * (1.0 - fg_a[x]);
                         let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
-                        tmp[3 + i][x] = (bg[3 + i].as_mut()[x] * ba
-                            + fg[3 + i].as_ref()[x] * fg[3 + alpha].as_ref()[x] * (1.0 - ba))
-                            * rnew_a;
+                        out[x] = (out[x] * ba + fg[3 + i][x] * fg_a[x] * (1.0 - ba)) * rnew_a;
                     }
                 }
             }
             PatchBlendMode::AlphaWeightedAddAbove => {
-                if i == alpha {
-                    tmp[3 + i].copy_from_slice(bg[3 + i].as_mut());
-                } else if clamp {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] = bg[3 + i].as_mut()[x]
-                            + fg[3 + i].as_ref()[x] * fg[3 + alpha].as_ref()[x].clamp(0.0, 1.0);
-                    }
-                } else {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] = bg[3 + i].as_mut()[x]
-                            + fg[3 + i].as_ref()[x] * fg[3 + alpha].as_ref()[x];
-                    }
+                if i != alpha {
+                    let mode = BlendMode::AlphaWeightedAdd;
+                    blend_row(out, fg[3 + i], mode, Some(fg_a), clamp);
                 }
             }
             PatchBlendMode::AlphaWeightedAddBelow => {
                 if i == alpha {
-                    tmp[3 + i].copy_from_slice(fg[3 + i].as_ref());
-                } else if clamp {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] = fg[3 + i].as_ref()[x]
-                            + bg[3 + i].as_mut()[x] * bg[3 + alpha].as_mut()[x].clamp(0.0, 1.0);
-                    }
+                    out.copy_from_slice(fg_a);
                 } else {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] = fg[3 + i].as_ref()[x]
-                            + bg[3 + i].as_mut()[x] * bg[3 + alpha].as_mut()[x];
-                    }
+                    let mode = BlendMode::AlphaWeightedAdd;
+                    blend_below(out, fg[3 + i], mode, Some(bg_a), clamp);
                 }
             }
-            PatchBlendMode::Mul => {
-                if clamp {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] =
-                            bg[3 + i].as_mut()[x] * fg[3 + i].as_ref()[x].clamp(0.0, 1.0);
-                    }
-                } else {
-                    for x in 0..xsize {
-                        tmp[3 + i][x] = bg[3 + i].as_mut()[x] * fg[3 + i].as_ref()[x];
-                    }
-                }
-            }
-            PatchBlendMode::Replace => {
-                tmp[3 + i].copy_from_slice(fg[3 + i].as_ref());
-            }
-            PatchBlendMode::None => {
-                tmp[3 + i].copy_from_slice(bg[3 + i].as_mut());
-            }
+            PatchBlendMode::Mul => blend_row(out, fg[3 + i], BlendMode::Mul, None, clamp),
+            PatchBlendMode::Replace => out.copy_from_slice(fg[3 + i]),
+            PatchBlendMode::None => {}
         }
     }
 
     let alpha = color_blending.alpha_channel;
     let clamp = color_blending.clamp;
+    let (bg_a, fg_a) = if has_alpha {
+        (Some(bg_alpha(alpha)), Some(fg_alpha(alpha)))
+    } else {
+        (None, None)
+    };
+    let alpha_associated = has_alpha && alpha_associated(alpha);
 
     match color_blending.mode {
         PatchBlendMode::Add => {
             for c in 0..3 {
-                for x in 0..xsize {
-                    tmp[c][x] = bg[c].as_mut()[x] + fg[c].as_ref()[x];
-                }
+                blend_row(bg[c].as_mut(), fg[c], BlendMode::Add, None, clamp);
             }
         }
         PatchBlendMode::AlphaWeightedAddAbove => {
             for c in 0..3 {
-                if !has_alpha {
-                    for x in 0..xsize {
-                        tmp[c][x] = bg[c].as_mut()[x] + fg[c].as_ref()[x];
-                    }
-                } else if clamp {
-                    for x in 0..xsize {
-                        tmp[c][x] = bg[c].as_mut()[x]
-                            + fg[c].as_ref()[x] * fg[3 + alpha].as_ref()[x].clamp(0.0, 1.0);
-                    }
-                } else {
-                    for x in 0..xsize {
-                        tmp[c][x] =
-                            bg[c].as_mut()[x] + fg[c].as_ref()[x] * fg[3 + alpha].as_ref()[x];
-                    }
-                }
+                let mode = BlendMode::AlphaWeightedAdd;
+                blend_row(bg[c].as_mut(), fg[c], mode, fg_a, clamp);
             }
         }
         PatchBlendMode::AlphaWeightedAddBelow => {
             for c in 0..3 {
-                if !has_alpha {
-                    for x in 0..xsize {
-                        tmp[c][x] = bg[c].as_mut()[x] + fg[c].as_ref()[x];
-                    }
-                } else if clamp {
-                    for x in 0..xsize {
-                        tmp[c][x] = fg[c].as_ref()[x]
-                            + bg[c].as_mut()[x] * bg[3 + alpha].as_mut()[x].clamp(0.0, 1.0);
-                    }
-                } else {
-                    for x in 0..xsize {
-                        tmp[c][x] =
-                            fg[c].as_ref()[x] + bg[c].as_mut()[x] * bg[3 + alpha].as_mut()[x];
-                    }
-                }
+                let mode = BlendMode::AlphaWeightedAdd;
+                blend_below(bg[c].as_mut(), fg[c], mode, bg_a, clamp);
             }
         }
         PatchBlendMode::BlendAbove => {
-            if !has_alpha {
-                for c in 0..3 {
-                    tmp[c].copy_from_slice(fg[c].as_ref());
-                }
-            } else if extra_channel_info[alpha].alpha_associated() {
-                for x in 0..xsize {
-                    let fa = maybe_clamp(fg[3 + alpha].as_ref()[x], clamp);
+            if let (Some(bg_a), Some(fg_a)) = (bg_a, fg_a) {
+                if alpha_associated {
                     for c in 0..3 {
-                        tmp[c][x] = fg[c].as_ref()[x] + bg[c].as_mut()[x] * (1.0 - fa);
+                        blend_row(bg[c].as_mut(), fg[c], BlendMode::Blend, Some(fg_a), clamp);
                     }
-                    tmp[3 + alpha][x] = 1.0 - (1.0 - fa) * (1.0 - bg[3 + alpha].as_mut()[x]);
-                }
-            } else {
-                for x in 0..xsize {
-                    let fa = maybe_clamp(fg[3 + alpha].as_ref()[x], clamp);
-                    let new_a = 1.0 - (1.0 - fa) * (1.0 - bg[3 + alpha].as_mut()[x]);
-                    let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
+                } else {
                     for c in 0..3 {
-                        tmp[c][x] = (fg[c].as_ref()[x] * fa
-                            + bg[c].as_mut()[x] * bg[3 + alpha].as_mut()[x] * (1.0 - fa))
-                            * rnew_a;
+                        let out = bg[c].as_mut();
+                        for x in 0..xsize {
+                            let fa = maybe_clamp(fg_a[x], clamp);
+                            let new_a = 1.0 - (1.0 - fa) * (1.0 - bg_a[x]);
+                            let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
+                            out[x] = (fg[c][x] * fa + out[x] * bg_a[x] * (1.0 - fa)) * rnew_a;
+                        }
                     }
-                    tmp[3 + alpha][x] = new_a;
+                }
+                let out = bg[3 + alpha].as_mut();
+                for x in 0..xsize {
+                    let fa = maybe_clamp(fg_a[x], clamp);
+                    out[x] = 1.0 - (1.0 - fa) * (1.0 - bg_a[x]);
+                }
+            } else {
+                for c in 0..3 {
+                    blend_row(bg[c].as_mut(), fg[c], BlendMode::Blend, None, clamp);
                 }
             }
         }
         PatchBlendMode::BlendBelow => {
-            if !has_alpha {
-                for c in 0..3 {
-                    tmp[c].copy_from_slice(bg[c].as_mut());
-                }
-            } else if extra_channel_info[alpha].alpha_associated() {
-                for x in 0..xsize {
-                    let ba = maybe_clamp(bg[3 + alpha].as_mut()[x], clamp);
+            if let (Some(bg_a), Some(fg_a)) = (bg_a, fg_a) {
+                if alpha_associated {
                     for c in 0..3 {
-                        tmp[c][x] = bg[c].as_mut()[x] + fg[c].as_ref()[x] * (1.0 - ba);
+                        blend_below(bg[c].as_mut(), fg[c], BlendMode::Blend, Some(bg_a), clamp);
                     }
-                    tmp[3 + alpha][x] = 1.0 - (1.0 - ba) * (1.0 - fg[3 + alpha].as_ref()[x]);
-                }
-            } else {
-                for x in 0..xsize {
-                    let ba = maybe_clamp(bg[3 + alpha].as_mut()[x], clamp);
-                    let new_a = 1.0 - (1.0 - ba) * (1.0 - fg[3 + alpha].as_ref()[x]);
-                    let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
+                } else {
                     for c in 0..3 {
-                        tmp[c][x] = (bg[c].as_mut()[x] * ba
-                            + fg[c].as_ref()[x] * fg[3 + alpha].as_ref()[x] * (1.0 - ba))
-                            * rnew_a;
+                        let out = bg[c].as_mut();
+                        for x in 0..xsize {
+                            let ba = maybe_clamp(bg_a[x], clamp);
+                            let new_a = 1.0 - (1.0 - ba) * (1.0 - fg_a[x]);
+                            let rnew_a = if new_a > 0.0 { 1.0 / new_a } else { 0.0 };
+                            out[x] = (out[x] * ba + fg[c][x] * fg_a[x] * (1.0 - ba)) * rnew_a;
+                        }
                     }
-                    tmp[3 + alpha][x] = new_a;
                 }
-            }
-        }
-        PatchBlendMode::Mul => {
-            for c in 0..3 {
+                let out = bg[3 + alpha].as_mut();
                 for x in 0..xsize {
-                    tmp[c][x] = bg[c].as_mut()[x] * maybe_clamp(fg[c].as_ref()[x], clamp);
+                    let ba = maybe_clamp(bg_a[x], clamp);
+                    out[x] = 1.0 - (1.0 - ba) * (1.0 - fg_a[x]);
                 }
             }
         }
-        PatchBlendMode::Replace => {
+        PatchBlendMode::Mul => {
             for c in 0..3 {
-                tmp[c].copy_from_slice(fg[c].as_ref());
+                blend_row(bg[c].as_mut(), fg[c], BlendMode::Mul, None, clamp);
             }
         }
-        PatchBlendMode::None => {
+        PatchBlendMode::Replace => {
             for c in 0..3 {
-                tmp[c].copy_from_slice(bg[c].as_mut());
+                bg[c].as_mut().copy_from_slice(fg[c]);
             }
         }
-    }
-    for i in 0..(3 + num_ec) {
-        bg[i].as_mut().copy_from_slice(&tmp[i]);
+        PatchBlendMode::None => {}
     }
 }
 
@@ -746,6 +724,57 @@ mod tests {
             assert_all_almost_abs_eq(&bg_b, &fg_b, ABS_DELTA);
         }
 
+        #[test]
+        fn test_non_alpha_channel_as_alpha_is_clamped() {
+            // Depth samples used as alpha, which are infinite, NaN or out of [0, 1] in all but
+            // the last pixel.
+            let mut bg_color = [[0.2; 5], [0.2; 5], [0.2; 5]];
+            let mut bg_depth = [f32::INFINITY, f32::NEG_INFINITY, f32::NAN, 2.0, 1.0];
+            let mut bg_alpha = [0.5; 5];
+            let fg_color = [0.8; 5];
+            let fg_depth = [f32::NAN, 3.0, -1.0, f32::INFINITY, 0.4];
+            let fg_alpha = [1.0; 5];
+
+            let [bg_r, bg_g, bg_b] = &mut bg_color;
+            let mut bg_channels: [&mut [f32]; 5] = [bg_r, bg_g, bg_b, &mut bg_depth, &mut bg_alpha];
+            let fg_channels: [&[f32]; 5] = [&fg_color, &fg_color, &fg_color, &fg_depth, &fg_alpha];
+
+            let blending = PatchBlending {
+                mode: PatchBlendMode::BlendAbove,
+                alpha_channel: 0,
+                clamp: false,
+            };
+            let extra_channel_info = [ExtraChannel::Depth, ExtraChannel::Alpha].map(|ec_type| {
+                ExtraChannelInfo::new(
+                    false,
+                    ec_type,
+                    BitDepth::f32(),
+                    0,
+                    String::new(),
+                    // Only used for alpha channels.
+                    true,
+                    None,
+                    None,
+                )
+            });
+
+            perform_blending(
+                &mut bg_channels,
+                &fg_channels,
+                &blending,
+                &[blending; 2],
+                &extra_channel_info,
+            );
+
+            // Clamped, the foreground and background alpha values are (0, 1), (1, 0), (0, 0),
+            // (1, 1) and (0.4, 1).
+            for bg_c in &bg_color {
+                assert_all_almost_abs_eq(bg_c, &[0.2, 0.8, 0.0, 0.8, 0.44], ABS_DELTA);
+            }
+            assert_all_almost_abs_eq(&bg_depth, &[1.0, 1.0, 0.0, 1.0, 1.0], ABS_DELTA);
+            assert_all_almost_abs_eq(&bg_alpha, &[0.5, 1.0, 0.0, 1.0, 0.7], ABS_DELTA);
+        }
+
         #[test]
         fn test_empty_pixels() {
             let mut bg_r: [f32; 0] = [];
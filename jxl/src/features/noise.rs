@@ -3,7 +3,20 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{bit_reader::BitReader, error::Result};
+use crate::{bit_reader::BitReader, error::Result, image::Image, util::Xorshift128Plus};
+
+/// What the noise of a frame is generated from, besides the coordinates of each group.
+///
+/// The noise of a group is a pure function of the seed and of the group coordinates, so that it
+/// does not depend on which groups are rendered, in which order, or how many times.
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct NoiseSeed {
+    /// Number of visible frames up to and including this frame, in the codestream.
+    pub visible_frame_index: u32,
+    /// Number of non-visible frames since the last visible frame, including this frame.
+    pub nonvisible_frame_index: u32,
+}
+
 #[derive(Debug, PartialEq, Default, Clone, Copy)]
 pub struct Noise {
     pub lut: [f32; 8],
@@ -38,3 +51,131 @@ impl Noise {
         ((hi - low) * frac_x + low).clamp(0.0, 1.0)
     }
 }
+
+/// Fills the three noise channels of group `(gx, gy)` of a frame, whose samples cover
+/// `size` upsampled pixels, with random values in `[1, 2)`.
+pub fn fill_group_noise(
+    seed: NoiseSeed,
+    (gx, gy): (u32, u32),
+    group_dim: u32,
+    upsampling: u32,
+    size: (usize, usize),
+    bufs: &mut [Image<f32>; 3],
+) {
+    // TODO(veluca): SIMD.
+    let bits_to_float = |bits: u32| f32::from_bits((bits >> 9) | 0x3F800000);
+    const FLOATS_PER_BATCH: usize =
+        Xorshift128Plus::N * std::mem::size_of::<u64>() / std::mem::size_of::<f32>();
+    let mut batch = [0u64; Xorshift128Plus::N];
+
+    // libjxl iterates through upsampling subdivisions with separate RNG seeds.
+    // For each subregion, a single RNG is shared across all 3 channels.
+    for iy in 0..upsampling {
+        for ix in 0..upsampling {
+            // Seed coordinates for this subregion (matches libjxl)
+            let x0 = (gx * upsampling + ix) * group_dim;
+            let y0 = (gy * upsampling + iy) * group_dim;
+            let mut rng = Xorshift128Plus::new_with_seeds(
+                seed.visible_frame_index,
+                seed.nonvisible_frame_index,
+                x0,
+                y0,
+            );
+
+            // Subregion boundaries within the buffer, clamped to its size.
+            let sub_x0 = (ix * group_dim) as usize;
+            let sub_y0 = (iy * group_dim) as usize;
+            let sub_xsize = (sub_x0 + group_dim as usize)
+                .min(size.0)
+                .saturating_sub(sub_x0);
+            let sub_ysize = (sub_y0 + group_dim as usize)
+                .min(size.1)
+                .saturating_sub(sub_y0);
+            if sub_xsize == 0 || sub_ysize == 0 {
+                continue;
+            }
+
+            for buf in bufs.iter_mut() {
+                for y in 0..sub_ysize {
+                    let row = buf.row_mut(sub_y0 + y);
+                    for batch_index in 0..sub_xsize.div_ceil(FLOATS_PER_BATCH) {
+                        rng.fill(&mut batch);
+                        let batch_size =
+                            (sub_xsize - batch_index * FLOATS_PER_BATCH).min(FLOATS_PER_BATCH);
+                        for i in 0..batch_size {
+                            let x = sub_x0 + FLOATS_PER_BATCH * batch_index + i;
+                            let k = i / 2;
+                            let bits = if i % 2 != 0 {
+                                (batch[k] >> 32) as u32
+                            } else {
+                                batch[k] as u32
+                            };
+                            row[x] = bits_to_float(bits);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group_noise(seed: NoiseSeed, group: (u32, u32), upsampling: u32) -> [Image<f32>; 3] {
+        let size = (28 * upsampling as usize, 20 * upsampling as usize);
+        let mut bufs = std::array::from_fn(|_| Image::new(size).unwrap());
+        fill_group_noise(seed, group, 32, upsampling, size, &mut bufs);
+        bufs
+    }
+
+    fn rows(bufs: &[Image<f32>; 3]) -> Vec<Vec<f32>> {
+        bufs.iter()
+            .flat_map(|buf| (0..buf.size().1).map(|y| buf.row(y).to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn group_noise_only_depends_on_seed_and_group() {
+        let seed = NoiseSeed {
+            visible_frame_index: 3,
+            nonvisible_frame_index: 1,
+        };
+        for upsampling in [1, 2] {
+            let groups: Vec<_> = (0..3)
+                .flat_map(|gy| (0..2).map(move |gx| (gx, gy)))
+                .collect();
+            let forward: Vec<_> = groups
+                .iter()
+                .map(|group| rows(&group_noise(seed, *group, upsampling)))
+                .collect();
+            let backward: Vec<_> = groups
+                .iter()
+                .rev()
+                .map(|group| rows(&group_noise(seed, *group, upsampling)))
+                .collect();
+            assert!(forward.iter().eq(backward.iter().rev()));
+            for noise in &forward {
+                assert!(noise.iter().flatten().all(|x| (1.0..2.0).contains(x)));
+            }
+            // Every group, and every subregion of upsampled groups, has its own noise.
+            for (i, a) in forward.iter().enumerate() {
+                for b in &forward[i + 1..] {
+                    assert_ne!(a, b);
+                }
+            }
+            if upsampling == 2 {
+                assert_ne!(forward[0][0][..24], forward[0][0][32..56]);
+            }
+        }
+        let other_frame = NoiseSeed {
+            visible_frame_index: 4,
+            nonvisible_frame_index: 0,
+        };
+        assert_ne!(
+            rows(&group_noise(seed, (0, 0), 1)),
+            rows(&group_noise(other_frame, (0, 0), 1))
+        );
+    }
+}
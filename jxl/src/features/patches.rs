@@ -7,9 +7,11 @@ use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
 use crate::{
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
     entropy_coding::decode::Histograms,
     entropy_coding::decode::SymbolReader,
+    entropy_coding::dump::record_entropy_code,
     error::{Error, Result},
     features::blending::perform_blending,
     frame::{DecoderState, ReferenceFrame},
@@ -370,6 +372,7 @@ impl PatchesDictionary {
     ) -> Result<PatchesDictionary> {
         let blendings_stride = num_extra_channels + 1;
         let patches_histograms = Histograms::decode(PatchContext::NUM, br, true)?;
+        record_entropy_code(EntropyCodePurpose::Patches, &patches_histograms);
         let mut patches_reader = SymbolReader::new(&patches_histograms, br, None)?;
         let num_ref_patch = patches_reader.read_unsigned(
             &patches_histograms,
@@ -432,23 +435,23 @@ impl PatchesDictionary {
             match reference_frame {
                 None => return Err(Error::PatchesInvalidReference(reference)),
                 Some(reference) => {
-                    if !reference.saved_before_color_transform {
+                    if !reference.saved_before_color_transform() {
                         return Err(Error::PatchesPostColorTransform());
                     }
-                    if x0 + ref_pos_xsize > reference.frame[0].size().0 {
+                    if x0 + ref_pos_xsize > reference.size().0 {
                         return Err(Error::PatchesInvalidPosition(
                             "x".to_string(),
                             x0,
                             ref_pos_xsize,
-                            reference.frame[0].size().0,
+                            reference.size().0,
                         ));
                     }
-                    if y0 + ref_pos_ysize > reference.frame[0].size().1 {
+                    if y0 + ref_pos_ysize > reference.size().1 {
                         return Err(Error::PatchesInvalidPosition(
                             "y".to_string(),
                             y0,
                             ref_pos_ysize,
-                            reference.frame[0].size().1,
+                            reference.size().1,
                         ));
                     }
                 }
@@ -1454,6 +1457,7 @@ mod tests {
     mod add_one_row_tests {
         use super::super::*;
         use crate::{
+            frame::ReferenceColorSpace,
             headers::{bit_depth::BitDepth, extra_channels::ExtraChannel},
             image::Image,
             util::test::assert_all_almost_abs_eq,
@@ -1473,7 +1477,7 @@ mod tests {
             }
             Ok(Some(ReferenceFrame {
                 frame: frame_channels,
-                saved_before_color_transform: true,
+                color_space: ReferenceColorSpace::BeforeColorTransform { ycbcr: false },
             }))
         }
 
@@ -1488,7 +1492,7 @@ mod tests {
             }
             Ok(Some(ReferenceFrame {
                 frame: frame_channels,
-                saved_before_color_transform: true,
+                color_space: ReferenceColorSpace::BeforeColorTransform { ycbcr: false },
             }))
         }
 
@@ -10,8 +10,12 @@ use std::{
 };
 
 use crate::{
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
-    entropy_coding::decode::{Histograms, SymbolReader, unpack_signed},
+    entropy_coding::{
+        decode::{Histograms, SymbolReader, unpack_signed},
+        dump::record_entropy_code,
+    },
     error::{Error, Result},
     frame::color_correlation_map::ColorCorrelationParams,
     util::{CeilLog2, NewWithCapacity, fast_cos, fast_erff_simd, tracing_wrappers::*},
@@ -823,6 +827,7 @@ impl Splines {
     pub fn read(br: &mut BitReader, num_pixels: u32) -> Result<Splines> {
         trace!(pos = br.total_bits_read());
         let splines_histograms = Histograms::decode(NUM_SPLINE_CONTEXTS, br, true)?;
+        record_entropy_code(EntropyCodePurpose::Splines, &splines_histograms);
         let mut splines_reader = SymbolReader::new(&splines_histograms, br, None)?;
         let num_splines = splines_reader
             .read_unsigned(&splines_histograms, br, NUM_SPLINES_CONTEXT)
@@ -5,8 +5,9 @@
 
 use crate::{
     BLOCK_DIM, BLOCK_SIZE,
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
-    entropy_coding::decode::SymbolReader,
+    entropy_coding::{decode::SymbolReader, dump::record_entropy_code},
     error::Result,
     frame::Histograms,
     headers::permutation::Permutation,
@@ -130,6 +131,7 @@ pub fn decode_coeff_orders(used_orders: u32, br: &mut BitReader) -> Result<Vec<P
         return Ok(permutations);
     }
     let histograms = Histograms::decode(NUM_PERMUTATION_CONTEXTS, br, true)?;
+    record_entropy_code(EntropyCodePurpose::CoeffOrder, &histograms);
     let mut reader = SymbolReader::new(&histograms, br, None)?;
     for (ord, transform_type) in TRANSFORM_TYPE_LUT.iter().enumerate() {
         if used_orders & (1 << ord) == 0 {
@@ -0,0 +1,213 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::{
+    api::ConcealedGroup,
+    error::{Error, Result},
+    frame::{Frame, GroupId, LfGroupId, PassId, Section, modular::ModularStreamId},
+    headers::frame_header::Encoding,
+    image::{Image, ImageDataType, Rect},
+    render::buffer_splitter::BufferSplitter,
+};
+use jxl_transforms::transform_map::HfTransformType;
+
+fn fill_rect<T: ImageDataType>(image: &mut Image<T>, rect: Rect, value: T) {
+    let mut rect = image.get_rect_mut(rect);
+    for y in 0..rect.size().1 {
+        rect.row(y).fill(value);
+    }
+}
+
+/// Fills `rect` of `plane` by interpolating linearly between the columns left and right of it and
+/// the rows above and below it, averaging both directions. Only the sides in `sides` (left,
+/// right, top, bottom) are used; without any, `rect` is filled with zeros.
+fn interpolate_rect(plane: &mut Image<f32>, rect: Rect, sides: [bool; 4]) {
+    let [left, right, top, bottom] = sides;
+    let (x0, y0) = rect.origin;
+    let (x1, y1) = rect.end();
+    let lerp = |a: f32, b: f32, pos: usize, len: usize| {
+        let t = (pos + 1) as f32 / (len + 1) as f32;
+        a + (b - a) * t
+    };
+    let row_above = top.then(|| plane.row(y0 - 1)[x0..x1].to_vec());
+    let row_below = bottom.then(|| plane.row(y1)[x0..x1].to_vec());
+    for y in y0..y1 {
+        let row = plane.row_mut(y);
+        let horizontal = |x: usize| match (left, right) {
+            (true, true) => Some(lerp(row[x0 - 1], row[x1], x - x0, x1 - x0)),
+            (true, false) => Some(row[x0 - 1]),
+            (false, true) => Some(row[x1]),
+            (false, false) => None,
+        };
+        let values: Vec<f32> = (x0..x1)
+            .map(|x| {
+                let vertical = match (&row_above, &row_below) {
+                    (Some(a), Some(b)) => Some(lerp(a[x - x0], b[x - x0], y - y0, y1 - y0)),
+                    (Some(a), None) => Some(a[x - x0]),
+                    (None, Some(b)) => Some(b[x - x0]),
+                    (None, None) => None,
+                };
+                match (horizontal(x), vertical) {
+                    (Some(h), Some(v)) => (h + v) * 0.5,
+                    (Some(value), None) | (None, Some(value)) => value,
+                    (None, None) => 0.0,
+                }
+            })
+            .collect();
+        row[x0..x1].copy_from_slice(&values);
+    }
+}
+
+impl Frame {
+    /// Whether `error`, returned by the section of a group, is concealed instead of returned.
+    pub fn can_conceal(&self, error: &Error) -> bool {
+        self.decoder_state.skip_corrupt_sections && error.is_corrupt_data()
+    }
+
+    /// Returns the groups of this frame that were concealed so far.
+    pub fn concealed_groups(&self) -> &[ConcealedGroup] {
+        &self.concealed_groups
+    }
+
+    fn report_concealed(&mut self, section: Section) {
+        let layout = self.header.group_layout();
+        let coords = match section {
+            Section::Lf { group } => layout.lf_group_coords(group),
+            Section::Hf { group, .. } => layout.group_coords(group),
+            Section::LfGlobal | Section::HfGlobal => (0, 0),
+        };
+        self.concealed_groups
+            .push(ConcealedGroup { section, coords });
+    }
+
+    /// Conceals LF group `group`, whose section failed to decode. Its modular data is
+    /// zeroed. For VarDCT frames, its DC is interpolated from the neighbouring LF groups once they
+    /// are all decoded, by [`finalize_lf`](Self::finalize_lf), and its groups are rendered from
+    /// the DC alone, with the default transform of each block.
+    pub fn conceal_lf_group(&mut self, group: LfGroupId) -> Result<()> {
+        self.report_concealed(Section::Lf { group });
+        let modular_global = &mut self.lf_global.as_mut().unwrap().modular_global;
+        modular_global.mark_group_to_be_read(ModularStreamId::ModularLF(group));
+        modular_global.zero_fill_stream(ModularStreamId::ModularLF(group))?;
+        if self.header.encoding != Encoding::VarDCT {
+            return Ok(());
+        }
+
+        // Metadata that a partially decoded section might have set is overwritten as well.
+        let r = self.header.lf_group_rect(group);
+        let color_tiles = Rect {
+            origin: (r.origin.0 >> 3, r.origin.1 >> 3),
+            size: (r.size.0.div_ceil(8), r.size.1.div_ceil(8)),
+        };
+        let hf_meta = self.hf_meta.as_mut().unwrap();
+        fill_rect(&mut hf_meta.ytox_map, color_tiles, 0);
+        fill_rect(&mut hf_meta.ytob_map, color_tiles, 0);
+        // Every block is the first (and only) block of an 8x8 DCT.
+        fill_rect(
+            &mut hf_meta.transform_map,
+            r,
+            HfTransformType::DCT as u8 + 128,
+        );
+        fill_rect(&mut hf_meta.raw_quant_map, r, 1);
+        fill_rect(&mut hf_meta.epf_map, r, 0);
+        hf_meta.used_hf_types |= 1 << HfTransformType::DCT as u32;
+        fill_rect(&mut self.quant_lf, r, 0);
+        if !self.header.has_lf_frame() {
+            self.concealed_lf_groups.push(group);
+        }
+
+        let layout = self.header.group_layout();
+        let (lf_x, lf_y) = layout.lf_group_coords(group);
+        let groups_x = lf_x * 8..((lf_x + 1) * 8).min(layout.num_groups.0);
+        for gy in lf_y * 8..((lf_y + 1) * 8).min(layout.num_groups.1) {
+            for gx in groups_x.clone() {
+                let group = GroupId::new(gy * layout.num_groups.0 + gx);
+                if self.concealed_hf_groups.insert(group) {
+                    self.report_concealed(Section::Hf {
+                        group,
+                        pass: PassId::new(0),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Interpolates the DC of the LF groups concealed by
+    /// [`conceal_lf_group`](Self::conceal_lf_group), in raster order, so that each of them is
+    /// interpolated from the decoded or already interpolated LF groups around it.
+    pub(super) fn interpolate_concealed_lf(&mut self) {
+        let Some(lf_image) = self.lf_image.as_mut() else {
+            return;
+        };
+        let mut concealed = std::mem::take(&mut self.concealed_lf_groups);
+        concealed.sort();
+        let layout = self.header.group_layout();
+        for (i, &group) in concealed.iter().enumerate() {
+            let (x, y) = layout.lf_group_coords(group);
+            let (width, height) = layout.num_lf_groups;
+            // LF groups to the right and below come later in raster order.
+            let later_concealed =
+                |neighbor: usize| concealed[i + 1..].contains(&LfGroupId::new(neighbor));
+            let sides = [
+                x > 0,
+                x + 1 < width && !later_concealed(group.index() + 1),
+                y > 0,
+                y + 1 < height && !later_concealed(group.index() + width),
+            ];
+            let r = self.header.lf_group_rect(group);
+            for (c, plane) in lf_image.iter_mut().enumerate() {
+                let shift = (self.header.hshift(c) as u8, self.header.vshift(c) as u8);
+                interpolate_rect(plane, r.downsample(shift), sides);
+            }
+        }
+    }
+
+    /// Whether `group` is rendered from its DC alone, without decoding its sections.
+    pub(super) fn is_concealed(&self, group: GroupId) -> bool {
+        self.concealed_hf_groups.contains(&group)
+    }
+
+    /// Renders `group` from its DC alone, after its section of the first of `passes` `failed` to
+    /// decode, or after the group was concealed. Its AC coefficients and its modular data of
+    /// `passes` are zeroed, and its later passes are not decoded.
+    pub(super) fn conceal_hf_group(
+        &mut self,
+        group: GroupId,
+        passes: &[PassId],
+        failed: bool,
+        buffer_splitter: &mut BufferSplitter,
+    ) -> Result<()> {
+        if failed {
+            self.concealed_hf_groups.insert(group);
+            self.report_concealed(Section::Hf {
+                group,
+                pass: passes.first().copied().unwrap_or(PassId::new(0)),
+            });
+        }
+        if let Some(coefficients) = self
+            .hf_global
+            .as_mut()
+            .and_then(|hf_global| hf_global.hf_coefficients.as_mut())
+        {
+            coefficients.0.row_mut(group.index()).fill(0);
+            coefficients.1.row_mut(group.index()).fill(0);
+            coefficients.2.row_mut(group.index()).fill(0);
+        }
+        let modular_global = &mut self.lf_global.as_mut().unwrap().modular_global;
+        for &pass in passes {
+            modular_global.zero_fill_stream(ModularStreamId::ModularHF { group, pass })?;
+        }
+        // The group is complete, since its remaining passes are not decoded.
+        let last_pass = PassId::new(self.header.passes.num_passes as usize - 1);
+        let last_rendered_pass = &mut self.last_rendered_pass[group.index()];
+        if !last_rendered_pass.is_some_and(|p| p >= last_pass) {
+            *last_rendered_pass = Some(last_pass);
+            self.incomplete_groups = self.incomplete_groups.checked_sub(1).unwrap();
+        }
+        self.decode_hf_group(group, &mut [], buffer_splitter, true)?;
+        Ok(())
+    }
+}
@@ -16,7 +16,6 @@ use super::{
     quant_weights::DequantMatrices,
     quantizer::{LfQuantFactors, QuantizerParams},
 };
-use crate::error::Error;
 use crate::features::epf::SigmaSource;
 use crate::frame::block_context_map::{ZERO_DENSITY_CONTEXT_COUNT, ZERO_DENSITY_CONTEXT_LIMIT};
 use crate::headers::frame_header::FrameType;
@@ -26,13 +25,21 @@ use crate::render::buffer_splitter::BufferSplitter;
 use crate::util::AtomicRefCell;
 use crate::util::{ShiftRightCeil, mirror};
 use crate::{
-    GROUP_DIM,
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
-    entropy_coding::decode::Histograms,
+    entropy_coding::{
+        decode::Histograms,
+        dump::{enter_entropy_section, record_entropy_code},
+    },
     error::Result,
-    features::{noise::Noise, patches::PatchesDictionary, spline::Splines},
+    features::{
+        noise::{Noise, fill_group_noise},
+        patches::PatchesDictionary,
+        spline::Splines,
+    },
     frame::{
-        DecoderState, Frame, HfGlobalState, HfMetadata, LfGlobalState, PassState, coeff_order,
+        DecoderState, Frame, GroupId, HfGlobalState, HfMetadata, LfGlobalState, LfGroupId, PassId,
+        PassState, Section, SectionId, coeff_order,
     },
     headers::{
         color_encoding::ColorSpace,
@@ -41,7 +48,7 @@ use crate::{
     },
     image::Image,
     render::RenderPipeline,
-    util::{CeilLog2, Xorshift128Plus, tracing_wrappers::*},
+    util::{CeilLog2, tracing_wrappers::*},
 };
 use jxl_transforms::transform_map::*;
 
@@ -51,7 +58,7 @@ use crate::render::stages::Upsample8x;
 use crate::render::{Channels, ChannelsMut};
 
 fn upsample_lf_group(
-    group: usize,
+    group: GroupId,
     pixels: &mut [Image<f32>; 3],
     lf_image: &[Image<f32>; 3],
     header: &FrameHeader,
@@ -59,9 +66,7 @@ fn upsample_lf_group(
 ) -> Result<()> {
     let group_dim = header.group_dim();
     let lf_group_dim = group_dim / 8;
-    let (width_groups, _) = header.size_groups();
-    let gx = group % width_groups;
-    let gy = group / width_groups;
+    let (gx, gy) = header.group_layout().group_coords(group);
 
     let upsample = Upsample8x::new(factors, 0);
     let mut state = upsample.init_local_state(0)?.unwrap();
@@ -172,20 +177,18 @@ impl Frame {
         let color_channels = if is_gray { 1 } else { 3 };
         let size_blocks = frame_header.size_blocks();
         let lf_image = if frame_header.encoding == Encoding::VarDCT {
-            if frame_header.has_lf_frame() {
-                decoder_state.lf_frames[frame_header.lf_level as usize]
-                    .as_ref()
-                    .map(|[a, b, c]| {
-                        Ok::<_, Error>([a.try_clone()?, b.try_clone()?, c.try_clone()?])
-                    })
-                    .transpose()?
-            } else {
-                Some([
+            // A missing LF frame is only allowed in permissive mode, where it is all zeros.
+            let lf_frame = decoder_state.lf_frames[frame_header.lf_level as usize]
+                .as_ref()
+                .filter(|_| frame_header.has_lf_frame());
+            Some(match lf_frame {
+                Some([a, b, c]) => [a.try_clone()?, b.try_clone()?, c.try_clone()?],
+                None => [
                     Image::new(size_blocks)?,
                     Image::new(size_blocks)?,
                     Image::new(size_blocks)?,
-                ])
-            }
+                ],
+            })
         } else {
             None
         };
@@ -261,6 +264,9 @@ impl Frame {
             vardct_buffers: None,
             groups_to_flush: BTreeSet::new(),
             changed_since_last_flush: BTreeSet::new(),
+            concealed_groups: Vec::new(),
+            concealed_hf_groups: BTreeSet::new(),
+            concealed_lf_groups: Vec::new(),
             patches: Arc::new(AtomicRefCell::new(PatchesDictionary::new(
                 num_extra_channels,
             ))),
@@ -291,32 +297,24 @@ impl Frame {
     }
 
     /// Given a bit reader pointing at the end of the TOC, returns a vector of `BitReader`s, each
-    /// of which reads a specific section.
+    /// of which reads a specific section. The vector is indexed by [`SectionId`].
     pub fn sections<'a>(&self, br: &'a mut BitReader) -> Result<Vec<BitReader<'a>>> {
         debug!(toc = ?self.toc);
-        let ret = self
+        let stored = self
             .toc
             .entries
             .iter()
             .scan(br, |br, count| Some(br.split_at(*count as usize)))
             .collect::<Result<Vec<_>>>()?;
-        if !self.toc.permuted {
-            return Ok(ret);
-        }
-        let mut inv_perm = vec![0; ret.len()];
-        for (i, pos) in self.toc.permutation.iter().enumerate() {
-            inv_perm[*pos as usize] = i;
-        }
-        let mut shuffled_ret = ret.clone();
-        for (br, pos) in ret.into_iter().zip(inv_perm.into_iter()) {
-            shuffled_ret[pos] = br;
-        }
-        Ok(shuffled_ret)
+        Ok((0..stored.len())
+            .map(|id| stored[self.toc.position(SectionId::new(id))].clone())
+            .collect())
     }
 
     #[instrument(level = "debug", skip_all)]
     pub fn decode_lf_global(&mut self, br: &mut BitReader, allow_partial: bool) -> Result<()> {
         debug!(section_size = br.total_bits_available());
+        let _section = enter_entropy_section(Section::LfGlobal);
 
         if let Some(lfg) = &self.lf_global {
             br.skip_bits(lfg.total_bits_read)?;
@@ -421,8 +419,9 @@ impl Frame {
     }
 
     #[instrument(level = "debug", skip(self, br))]
-    pub fn decode_lf_group(&mut self, group: usize, br: &mut BitReader) -> Result<()> {
+    pub fn decode_lf_group(&mut self, group: LfGroupId, br: &mut BitReader) -> Result<()> {
         debug!(section_size = br.total_bits_available());
+        let _section = enter_entropy_section(Section::Lf { group });
         let lf_global = self.lf_global.as_mut().unwrap();
         if self.header.encoding == Encoding::VarDCT && !self.header.has_lf_frame() {
             info!("decoding VarDCT LF with group id {}", group);
@@ -441,7 +440,9 @@ impl Frame {
             )?;
         }
 
-        lf_global.modular_global.mark_group_to_be_read(1, group);
+        lf_global
+            .modular_global
+            .mark_group_to_be_read(ModularStreamId::ModularLF(group));
 
         lf_global.modular_global.read_stream(
             ModularStreamId::ModularLF(group),
@@ -467,6 +468,7 @@ impl Frame {
     #[instrument(level = "debug", skip_all)]
     pub fn decode_hf_global(&mut self, br: &mut BitReader) -> Result<()> {
         debug!(section_size = br.total_bits_available());
+        let _section = enter_entropy_section(Section::HfGlobal);
         if self.header.encoding == Encoding::VarDCT {
             let lf_global = self.lf_global.as_mut().unwrap();
             let dequant_matrices = DequantMatrices::decode(&self.header, lf_global, br)?;
@@ -495,6 +497,7 @@ impl Frame {
                     i, num_contexts
                 );
                 let mut histograms = Histograms::decode(num_contexts, br, true)?;
+                record_entropy_code(EntropyCodePurpose::HfCoefficients, &histograms);
                 // Pad the context map to avoid index out of bounds in decode_vardct_group (group.rs#L514@752e6a4).
                 let padding = ZERO_DENSITY_CONTEXT_LIMIT - ZERO_DENSITY_CONTEXT_COUNT;
                 histograms.resize(num_contexts + padding);
@@ -520,7 +523,7 @@ impl Frame {
             {
                 None
             } else {
-                let xs = GROUP_DIM * GROUP_DIM;
+                let xs = self.header.group_dim() * self.header.group_dim();
                 let ys = self.header.num_groups();
                 Some((
                     Image::new((xs, ys))?,
@@ -549,18 +552,16 @@ impl Frame {
 
     pub fn render_noise_for_group(
         &mut self,
-        group: usize,
+        group: GroupId,
         complete: bool,
         buffer_splitter: &mut BufferSplitter,
     ) -> Result<()> {
         // TODO(sboukortt): consider making this a dedicated stage
-        // TODO(veluca): SIMD.
         let num_channels = self.header.num_extra_channels as usize + 3;
 
         let group_dim = self.header.group_dim() as u32;
-        let xsize_groups = self.header.size_groups().0;
-        let gx = (group % xsize_groups) as u32;
-        let gy = (group / xsize_groups) as u32;
+        let (gx, gy) = self.header.group_layout().group_coords(group);
+        let (gx, gy) = (gx as u32, gy as u32);
         let upsampling = self.header.upsampling;
         let upsampled_size = self.header.size_upsampled();
 
@@ -570,91 +571,49 @@ impl Frame {
         let buf_xsize = buf_x1.min(upsampled_size.0) - (gx * upsampling * group_dim) as usize;
         let buf_ysize = buf_y1.min(upsampled_size.1) - (gy * upsampling * group_dim) as usize;
 
-        let bits_to_float = |bits: u32| f32::from_bits((bits >> 9) | 0x3F800000);
-
         // Get all 3 noise channel buffers upfront
         let mut bufs = [
             pipeline!(self, p, p.get_buffer(num_channels)?),
             pipeline!(self, p, p.get_buffer(num_channels + 1)?),
             pipeline!(self, p, p.get_buffer(num_channels + 2)?),
         ];
-
-        const FLOATS_PER_BATCH: usize =
-            Xorshift128Plus::N * std::mem::size_of::<u64>() / std::mem::size_of::<f32>();
-        let mut batch = [0u64; Xorshift128Plus::N];
-
-        // libjxl iterates through upsampling subdivisions with separate RNG seeds.
-        // For each subregion, a single RNG is shared across all 3 channels.
-        for iy in 0..upsampling {
-            for ix in 0..upsampling {
-                // Seed coordinates for this subregion (matches libjxl)
-                let x0 = (gx * upsampling + ix) * group_dim;
-                let y0 = (gy * upsampling + iy) * group_dim;
-
-                // Create RNG with this subregion's seed - shared across all 3 channels
-                let mut rng = Xorshift128Plus::new_with_seeds(
-                    self.decoder_state.visible_frame_index as u32,
-                    self.decoder_state.nonvisible_frame_index as u32,
-                    x0,
-                    y0,
-                );
-
-                // Subregion boundaries within the buffer
-                let sub_x0 = (ix * group_dim) as usize;
-                let sub_y0 = (iy * group_dim) as usize;
-                let sub_x1 = ((ix + 1) * group_dim) as usize;
-                let sub_y1 = ((iy + 1) * group_dim) as usize;
-
-                // Clamp to actual buffer size
-                let sub_xsize = sub_x1.min(buf_xsize).saturating_sub(sub_x0);
-                let sub_ysize = sub_y1.min(buf_ysize).saturating_sub(sub_y0);
-
-                // Skip if this subregion is entirely outside the buffer
-                if sub_xsize == 0 || sub_ysize == 0 {
-                    continue;
-                }
-
-                // Fill all 3 channels with this subregion's noise, sharing the RNG
-                for buf in &mut bufs {
-                    for y in 0..sub_ysize {
-                        let row = buf.row_mut(sub_y0 + y);
-                        for batch_index in 0..sub_xsize.div_ceil(FLOATS_PER_BATCH) {
-                            rng.fill(&mut batch);
-                            let batch_size =
-                                (sub_xsize - batch_index * FLOATS_PER_BATCH).min(FLOATS_PER_BATCH);
-                            for i in 0..batch_size {
-                                let x = sub_x0 + FLOATS_PER_BATCH * batch_index + i;
-                                let k = i / 2;
-                                let high_bytes = i % 2 != 0;
-                                let bits = if high_bytes {
-                                    ((batch[k] & 0xFFFFFFFF00000000) >> 32) as u32
-                                } else {
-                                    (batch[k] & 0xFFFFFFFF) as u32
-                                };
-                                row[x] = bits_to_float(bits);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+        fill_group_noise(
+            self.decoder_state.noise_seed(),
+            (gx, gy),
+            group_dim,
+            upsampling,
+            (buf_xsize, buf_ysize),
+            &mut bufs,
+        );
 
         // Set all buffers after filling
         let [buf0, buf1, buf2] = bufs;
         pipeline!(
             self,
             p,
-            p.set_buffer_for_group(num_channels, group, complete, buf0, buffer_splitter)?
+            p.set_buffer_for_group(num_channels, group.index(), complete, buf0, buffer_splitter)?
         );
         pipeline!(
             self,
             p,
-            p.set_buffer_for_group(num_channels + 1, group, complete, buf1, buffer_splitter)?
+            p.set_buffer_for_group(
+                num_channels + 1,
+                group.index(),
+                complete,
+                buf1,
+                buffer_splitter
+            )?
         );
         pipeline!(
             self,
             p,
-            p.set_buffer_for_group(num_channels + 2, group, complete, buf2, buffer_splitter)?
+            p.set_buffer_for_group(
+                num_channels + 2,
+                group.index(),
+                complete,
+                buf2,
+                buffer_splitter
+            )?
         );
         Ok(())
     }
@@ -663,8 +622,8 @@ impl Frame {
     #[instrument(level = "debug", skip(self, passes, buffer_splitter))]
     pub fn decode_hf_group(
         &mut self,
-        group: usize,
-        passes: &mut [(usize, BitReader)],
+        group: GroupId,
+        passes: &mut [(PassId, BitReader)],
         buffer_splitter: &mut BufferSplitter,
         force_render: bool,
     ) -> Result<bool> {
@@ -672,13 +631,14 @@ impl Frame {
             assert!(force_render);
         }
 
-        let last_pass_in_file = self.header.passes.num_passes as usize - 1;
-        let was_complete = self.last_rendered_pass[group].is_some_and(|p| p >= last_pass_in_file);
+        let last_pass_in_file = PassId::new(self.header.passes.num_passes as usize - 1);
+        let last_rendered_pass = &mut self.last_rendered_pass[group.index()];
+        let was_complete = last_rendered_pass.is_some_and(|p| p >= last_pass_in_file);
 
         if let Some((p, _)) = passes.last() {
-            self.last_rendered_pass[group] = Some(*p);
+            *last_rendered_pass = Some(*p);
         };
-        let pass_to_render = self.last_rendered_pass[group];
+        let pass_to_render = *last_rendered_pass;
         let complete = pass_to_render.is_some_and(|p| p >= last_pass_in_file);
 
         if complete && !was_complete {
@@ -699,13 +659,18 @@ impl Frame {
             return Ok(false);
         }
 
-        if self.header.has_noise() && do_render {
+        if self.header.has_noise() && !self.simplifications().skip_noise && do_render {
             self.render_noise_for_group(group, complete, buffer_splitter)?;
         }
 
         let lf_global = self.lf_global.as_mut().unwrap();
         if self.header.encoding == Encoding::VarDCT {
-            let mut pixels = if do_render {
+            // Without outputs that use the color channels, such as when only extra channels are
+            // requested, the coefficients are still read to reach the modular data that follows
+            // them, but they are not transformed to pixels.
+            let render_color =
+                do_render && pipeline!(self, p, p.used_channel_mask())[..3].contains(&true);
+            let mut pixels = if render_color {
                 Some([
                     pipeline!(self, p, p.get_buffer(0))?,
                     pipeline!(self, p, p.get_buffer(1))?,
@@ -715,14 +680,16 @@ impl Frame {
                 None
             };
             if pass_to_render.is_none() && do_render {
-                info!("Upsampling LF for group {group}");
-                upsample_lf_group(
-                    group,
-                    pixels.as_mut().unwrap(),
-                    self.lf_image.as_ref().unwrap(),
-                    &self.header,
-                    &self.decoder_state.file_header.transform_data,
-                )?;
+                if let Some(pixels) = pixels.as_mut() {
+                    info!("Upsampling LF for group {group}");
+                    upsample_lf_group(
+                        group,
+                        pixels,
+                        self.lf_image.as_ref().unwrap(),
+                        &self.header,
+                        &self.decoder_state.file_header.transform_data,
+                    )?;
+                }
             } else {
                 info!("Decoding VarDCT group {group}");
                 let hf_global = self.hf_global.as_mut().unwrap();
@@ -752,13 +719,14 @@ impl Frame {
                     pipeline!(
                         self,
                         p,
-                        p.set_buffer_for_group(c, group, complete, img, buffer_splitter)?
+                        p.set_buffer_for_group(c, group.index(), complete, img, buffer_splitter)?
                     );
                 }
             }
         }
 
         for (pass, br) in passes.iter_mut() {
+            let _section = enter_entropy_section(Section::Hf { group, pass: *pass });
             lf_global.modular_global.read_stream(
                 ModularStreamId::ModularHF { group, pass: *pass },
                 &self.header,
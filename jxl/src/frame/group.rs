@@ -9,16 +9,20 @@ use jxl_transforms::{transform::*, transform_map::*};
 
 use crate::{
     BLOCK_DIM, BLOCK_SIZE, GROUP_DIM,
+    api::JxlDecodeStage,
     bit_reader::BitReader,
     entropy_coding::decode::SymbolReader,
     error::{Error, Result},
     frame::{
-        HfGlobalState, HfMetadata, LfGlobalState, block_context_map::*,
+        GroupId, HfGlobalState, HfMetadata, LfGlobalState, PassId, block_context_map::*,
         color_correlation_map::COLOR_TILE_DIM_IN_BLOCKS, quant_weights::DequantMatrices,
     },
     headers::frame_header::FrameHeader,
     image::{Image, ImageRect, Rect},
-    util::{CeilLog2, ShiftRightCeil, SmallVec, tracing_wrappers::*},
+    util::{
+        CeilLog2, ShiftRightCeil, SmallVec, StageClock, charge_tokens, paranoid_check,
+        tracing_wrappers::*,
+    },
 };
 use jxl_simd::{F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask, simd_function};
 
@@ -310,7 +314,7 @@ struct PassInfo<'a, 'b> {
     reader: Option<SymbolReader>,
     br: &'a mut BitReader<'b>,
     shift: u32,
-    pass: usize,
+    pass: PassId,
     // TODO(veluca): reuse this allocation.
     num_nzeros: [Image<u32>; 3],
 }
@@ -320,7 +324,7 @@ impl<'a, 'b> PassInfo<'a, 'b> {
         hf_global: &HfGlobalState,
         frame_header: &FrameHeader,
         block_group_rect: Rect,
-        pass: usize,
+        pass: PassId,
         br: &'a mut BitReader<'b>,
     ) -> Result<Self> {
         let num_histo_bits = hf_global.num_histograms.ceil_log2();
@@ -328,15 +332,11 @@ impl<'a, 'b> PassInfo<'a, 'b> {
         let histogram_index = br.read(num_histo_bits as usize)? as usize;
         debug!(?histogram_index);
         let reader = Some(SymbolReader::new(
-            &hf_global.passes[pass].histograms,
+            &hf_global.passes[pass.index()].histograms,
             br,
             None,
         )?);
-        let shift = if pass < frame_header.passes.shift.len() {
-            frame_header.passes.shift[pass]
-        } else {
-            0
-        };
+        let shift = frame_header.passes.coefficient_shift(pass.index());
         let num_nzeros = [
             Image::new((
                 block_group_rect.size.0 >> frame_header.hshift(0),
@@ -366,8 +366,8 @@ impl<'a, 'b> PassInfo<'a, 'b> {
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
 pub fn decode_vardct_group(
-    group: usize,
-    passes: &mut [(usize, BitReader)],
+    group: GroupId,
+    passes: &mut [(PassId, BitReader)],
     frame_header: &FrameHeader,
     lf_global: &mut LfGlobalState,
     hf_global: &mut HfGlobalState,
@@ -378,6 +378,9 @@ pub fn decode_vardct_group(
     pixels: &mut Option<[Image<f32>; 3]>,
     buffers: &mut VarDctBuffers,
 ) -> Result<(), Error> {
+    // `group_size_shift` is only signaled for modular frames, so the buffers can be sized for
+    // the fixed group size of VarDCT frames.
+    debug_assert_eq!(frame_header.group_dim(), GROUP_DIM);
     let x_dm_multiplier = (1.0 / (1.25)).powf(frame_header.x_qm_scale as f32 - 2.0);
     let b_dm_multiplier = (1.0 / (1.25)).powf(frame_header.b_qm_scale as f32 - 2.0);
 
@@ -413,9 +416,9 @@ pub fn decode_vardct_group(
     // TODO(veluca): improve coefficient storage (smaller allocations, use 16 bits if possible).
     let coeffs = match hf_global.hf_coefficients.as_mut() {
         Some(hf_coefficients) => [
-            hf_coefficients.0.row_mut(group),
-            hf_coefficients.1.row_mut(group),
-            hf_coefficients.2.row_mut(group),
+            hf_coefficients.0.row_mut(group.index()),
+            hf_coefficients.1.row_mut(group.index()),
+            hf_coefficients.2.row_mut(group.index()),
         ],
         None => {
             // Use pooled buffer (already reset to zero in buffers.reset() above)
@@ -460,7 +463,11 @@ pub fn decode_vardct_group(
         }
     };
     for by in 0..block_group_rect.size.1 {
+        // Tokens decoded in this row of blocks, charged once the row is decoded.
+        let mut num_tokens = 0;
         let sby = [by >> vshift[0], by >> vshift[1], by >> vshift[2]];
+        // Entropy decoding and dequantization alternate for every block.
+        let mut clock = StageClock::sample_row(by);
         let ty = by / COLOR_TILE_DIM_IN_BLOCKS;
 
         let row_cmap_x = ytox_map.row(ty);
@@ -523,7 +530,7 @@ pub fn decode_vardct_group(
             } in pass_info.iter_mut()
             {
                 let reader = reader.as_mut().unwrap();
-                let pass_info = &hf_global.passes[*pass];
+                let pass_info = &hf_global.passes[pass.index()];
                 let context_offset = *histogram_index * block_context_map.num_ac_contexts();
                 for c in [1, 0, 2] {
                     if (sbx[c] << hshift[c]) != bx || (sby[c] << vshift[c] != by) {
@@ -542,6 +549,7 @@ pub fn decode_vardct_group(
                     let mut nonzeros =
                         reader.read_unsigned_inline(&pass_info.histograms, br, nonzero_context)
                             as usize;
+                    num_tokens += 1;
                     trace!(
                         "block ({},{},{c}) predicted_nzeros: {predicted_nzeros} \
                        nzero_ctx: {nonzero_context} (offset: {context_offset}) \
@@ -561,6 +569,15 @@ pub fn decode_vardct_group(
                         + context_offset;
                     let mut prev = if nonzeros > num_coeffs / 16 { 0 } else { 1 };
                     let permutation = &pass_info.coeff_orders[shape_id * 3 + c];
+                    paranoid_check(
+                        || {
+                            coeffs_offset + num_coeffs <= coeffs[c].len()
+                                && permutation[num_blocks..num_coeffs]
+                                    .iter()
+                                    .all(|&k| (k as usize) < num_coeffs)
+                        },
+                        "coefficient index out of block bounds",
+                    )?;
                     let current_coeffs = &mut coeffs[c][coeffs_offset..coeffs_offset + num_coeffs];
                     for k in num_blocks..num_coeffs {
                         if nonzeros == 0 {
@@ -570,6 +587,7 @@ pub fn decode_vardct_group(
                             histo_offset + zero_density_context(nonzeros, k, log_num_blocks, prev);
                         let coeff =
                             reader.read_signed_inline(&pass_info.histograms, br, ctx) << *shift;
+                        num_tokens += 1;
                         prev = if coeff != 0 { 1 } else { 0 };
                         nonzeros -= prev;
                         let coeff_index = permutation[k] as usize;
@@ -580,6 +598,7 @@ pub fn decode_vardct_group(
                     }
                 }
             }
+            clock.lap(JxlDecodeStage::EntropyDecode);
             if let Some(pixels) = pixels {
                 let qblock = [
                     &coeffs[0][coeffs_offset..],
@@ -612,9 +631,11 @@ pub fn decode_vardct_group(
                     &qblock,
                     dequant_matrices,
                 )?;
+                clock.lap(JxlDecodeStage::DequantIdct);
             }
             coeffs_offset += num_coeffs;
         }
+        charge_tokens(num_tokens)?;
     }
     for PassInfo {
         pass, br, reader, ..
@@ -622,7 +643,7 @@ pub fn decode_vardct_group(
     {
         std::mem::take(reader)
             .unwrap()
-            .check_final_state(&hf_global.passes[*pass].histograms, br)?;
+            .check_final_state(&hf_global.passes[pass.index()].histograms, br)?;
     }
     Ok(())
 }
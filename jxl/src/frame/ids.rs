@@ -0,0 +1,52 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Typed indices of the groups, LF groups, passes and sections of a frame, so that they cannot be
+//! mixed up with each other. [`GroupLayout`](crate::api::GroupLayout) converts between them.
+
+use std::fmt;
+
+/// Index of a group of a frame, in raster order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct GroupId(usize);
+
+/// Index of an LF group of a frame, in raster order. LF groups are called DC groups in the
+/// specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LfGroupId(usize);
+
+/// Index of a progressive pass of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PassId(usize);
+
+/// Index of a section of a frame in the table of contents, before the TOC permutation is
+/// applied. Sections may be stored in a different order, given by the permutation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SectionId(usize);
+
+macro_rules! impl_id {
+    ($($name:ident),*) => {
+        $(
+            impl $name {
+                pub(crate) const fn new(index: usize) -> Self {
+                    Self(index)
+                }
+
+                /// Returns the index as a number.
+                pub const fn index(self) -> usize {
+                    self.0
+                }
+            }
+
+            impl fmt::Display for $name {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    self.0.fmt(f)
+                }
+            }
+        )*
+    };
+}
+
+impl_id!(GroupId, LfGroupId, PassId, SectionId);
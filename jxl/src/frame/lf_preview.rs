@@ -16,7 +16,7 @@ use crate::{
         save::SaveStage,
         stages::{
             ConvertF32ToF16Stage, ConvertF32ToU8Stage, ConvertF32ToU16Stage, FromLinearStage,
-            OutputColorInfo, TransferFunction, Upsample8x, XybStage,
+            OutputColorInfo, ToneMappingStage, TransferFunction, Upsample8x, XybStage,
         },
     },
     util::{f16, mirror},
@@ -34,6 +34,7 @@ impl Frame {
         output_buffers: &mut [Option<JxlOutputBuffer<'_>>],
         full_size: (usize, usize),
         output_color_info: &OutputColorInfo,
+        tone_mapping: Option<&ToneMappingStage>,
         output_tf: &TransferFunction,
     ) -> Result<()> {
         let save_stage = SaveStage::new(
@@ -47,6 +48,7 @@ impl Frame {
             color_type,
             data_format,
             color_type.has_alpha(),
+            self.decoder_state.crop,
         );
         let len = rect.size.0;
         let ulen = len * 8;
@@ -181,6 +183,9 @@ impl Frame {
                     &mut b.get_row_mut(uy)[off..],
                 ];
                 xyb_stage.process_row_chunk((0, 0), ulen, &mut rows, None);
+                if let Some(stage) = tone_mapping {
+                    stage.process_row_chunk((0, 0), ulen, &mut rows, None);
+                }
                 from_linear_stage.process_row_chunk((0, 0), ulen, &mut rows, None);
 
                 macro_rules! convert {
@@ -280,6 +285,9 @@ impl Frame {
         if output_tf.is_linear() {
             return Ok(());
         }
+        let tone_mapping = self
+            .decoder_state
+            .sdr_tone_mapping(&output_color_info, &output_tf);
 
         let image_metadata = &self.decoder_state.file_header.image_metadata;
         if !image_metadata.xyb_encoded || !image_metadata.extra_channel_info.is_empty() {
@@ -329,12 +337,13 @@ impl Frame {
             &regions_storage[..]
         };
 
-        let orientation = image_metadata.orientation;
+        let orientation = self.decoder_state.output_orientation();
         let info = SaveStageBufferInfo {
             downsample: (0, 0),
             orientation,
             byte_size: data_format.bytes_per_sample() * color_type.samples_per_pixel(),
             after_extend: false,
+            crop: self.decoder_state.crop,
         };
         let info = [Some(info)];
         let mut bufs = [Some(JxlOutputBuffer::reborrow(&mut output_buffers[0]))];
@@ -362,6 +371,7 @@ impl Frame {
                 &mut bufs,
                 (xsize, ysize),
                 &output_color_info,
+                tone_mapping.as_ref(),
                 &output_tf,
             )?;
         }
@@ -6,18 +6,24 @@
 use std::{collections::BTreeSet, sync::Arc};
 
 use crate::{
+    api::{ConcealedGroup, OutputLayout, RenderingChoice, Simplifications},
     entropy_coding::decode::Histograms,
-    error::Result,
-    features::{noise::Noise, patches::PatchesDictionary, spline::Splines},
+    error::{Error, Result},
+    features::{
+        noise::{Noise, NoiseSeed},
+        patches::PatchesDictionary,
+        spline::Splines,
+    },
     headers::{
-        FileHeader,
+        FileHeader, Orientation,
         extra_channels::ExtraChannelInfo,
         frame_header::{Encoding, FrameHeader, FrameType},
         permutation::Permutation,
         toc::Toc,
     },
-    image::Image,
-    util::tracing_wrappers::*,
+    image::{Image, Rect},
+    render::stages::{OutputColorInfo, ToneMappingStage, TransferFunction},
+    util::{paranoid_check, tracing_wrappers::*},
 };
 use adaptive_lf_smoothing::adaptive_lf_smoothing;
 use block_context_map::BlockContextMap;
@@ -33,20 +39,24 @@ mod adaptive_lf_smoothing;
 mod block_context_map;
 mod coeff_order;
 pub mod color_correlation_map;
+mod concealment;
 pub mod decode;
 mod group;
+mod ids;
 pub mod lf_preview;
 pub mod modular;
 mod quant_weights;
 pub mod quantizer;
 pub mod render;
 
+pub use ids::{GroupId, LfGroupId, PassId, SectionId};
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Section {
     LfGlobal,
-    Lf { group: usize },
+    Lf { group: LfGroupId },
     HfGlobal,
-    Hf { group: usize, pass: usize },
+    Hf { group: GroupId, pass: PassId },
 }
 
 #[derive(Debug)]
@@ -72,13 +82,41 @@ pub struct HfGlobalState {
     hf_coefficients: Option<(Image<i32>, Image<i32>, Image<i32>)>,
 }
 
+/// Color space of the pixels of a [`ReferenceFrame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceColorSpace {
+    /// Saved before the color transform, so in XYB for XYB encoded images, and in YCbCr if
+    /// the frame used YCbCr.
+    BeforeColorTransform { ycbcr: bool },
+    /// Saved after the color transform, in the color space in which frames are blended.
+    AfterColorTransform,
+}
+
 #[derive(Debug)]
 pub struct ReferenceFrame {
     pub frame: Vec<Image<f32>>,
-    pub saved_before_color_transform: bool,
+    pub color_space: ReferenceColorSpace,
 }
 
 impl ReferenceFrame {
+    /// Size of the saved frame, the same for all of its channels.
+    pub fn size(&self) -> (usize, usize) {
+        self.frame[0].size()
+    }
+
+    pub fn saved_before_color_transform(&self) -> bool {
+        self.color_space != ReferenceColorSpace::AfterColorTransform
+    }
+
+    #[cfg(test)]
+    fn color_space_of(saved_before_color_transform: bool) -> ReferenceColorSpace {
+        if saved_before_color_transform {
+            ReferenceColorSpace::BeforeColorTransform { ycbcr: false }
+        } else {
+            ReferenceColorSpace::AfterColorTransform
+        }
+    }
+
     #[cfg(test)]
     pub fn blank(
         width: usize,
@@ -91,7 +129,7 @@ impl ReferenceFrame {
             .collect::<Result<_>>()?;
         Ok(Self {
             frame,
-            saved_before_color_transform,
+            color_space: Self::color_space_of(saved_before_color_transform),
         })
     }
     #[cfg(test)]
@@ -107,11 +145,19 @@ impl ReferenceFrame {
             .collect::<Result<_>>()?;
         Ok(Self {
             frame,
-            saved_before_color_transform,
+            color_space: Self::color_space_of(saved_before_color_transform),
         })
     }
 }
 
+/// Numbers of frames counted by a [`DecoderState`], which seed the noise of the frames that
+/// follow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameIndices {
+    pub visible: usize,
+    pub nonvisible: usize,
+}
+
 #[derive(Debug)]
 pub struct DecoderState {
     pub(super) file_header: FileHeader,
@@ -124,6 +170,22 @@ pub struct DecoderState {
     pub nonvisible_frame_index: usize,
     pub high_precision: bool,
     pub premultiply_output: bool,
+    /// How to render images with luminances beyond the SDR range.
+    pub rendering: Option<RenderingChoice>,
+    /// Whether pixels are written in the orientation of the image, or as stored.
+    pub adjust_orientation: bool,
+    /// Whether pixels are written row by row or column by column.
+    pub output_layout: OutputLayout,
+    /// Part of the image written to the output buffers of the current frame, in image
+    /// coordinates before the orientation is applied.
+    pub crop: Option<Rect>,
+    /// Whether to convert reference frames saved before the color transform when blending with
+    /// them, instead of failing.
+    pub permissive: bool,
+    /// Whether to conceal the groups whose sections fail to decode, instead of failing.
+    pub skip_corrupt_sections: bool,
+    /// Simplifications of the rendering to apply to the frames that they change.
+    pub simplifications: Simplifications,
     // Whether the latest level 1 LF frame was fully rendered.
     // If this is set to `true`, early flushing in the main frame
     // (before HF is available) will do nothing.
@@ -146,6 +208,13 @@ impl DecoderState {
             nonvisible_frame_index: 0,
             high_precision: false,
             premultiply_output: false,
+            rendering: None,
+            adjust_orientation: true,
+            output_layout: OutputLayout::RowMajor,
+            crop: None,
+            permissive: false,
+            skip_corrupt_sections: false,
+            simplifications: Simplifications::default(),
             lf_frame_was_rendered: false,
         }
     }
@@ -154,9 +223,64 @@ impl DecoderState {
         &self.file_header.image_metadata.extra_channel_info
     }
 
-    pub fn reference_frame(&self, i: usize) -> Option<&ReferenceFrame> {
-        assert!(i < Self::MAX_STORED_FRAMES);
-        self.reference_frames[i].as_ref()
+    /// Returns the seed of the noise of the current frame, which only depends on its position in
+    /// the codestream.
+    pub fn noise_seed(&self) -> NoiseSeed {
+        NoiseSeed {
+            visible_frame_index: self.visible_frame_index as u32,
+            nonvisible_frame_index: self.nonvisible_frame_index as u32,
+        }
+    }
+
+    /// Restores the frame counts to the ones at a frame that decoding restarts from, so that the
+    /// noise of the following frames does not depend on where decoding started.
+    pub fn set_frame_indices(&mut self, indices: FrameIndices) {
+        self.visible_frame_index = indices.visible;
+        self.nonvisible_frame_index = indices.nonvisible;
+    }
+
+    /// Saves a complete frame in reference slot `slot`, replacing what an earlier frame saved
+    /// there. Slots follow the bitstream order: a frame that blends with, or takes patches from,
+    /// the slot it is saved to reads the previous contents while it is decoded, and only replaces
+    /// them once it is finalized.
+    pub fn save_reference_frame(&mut self, slot: usize, frame: ReferenceFrame) -> Result<()> {
+        if slot >= Self::MAX_STORED_FRAMES {
+            return Err(Error::InvalidSaveAsReference(slot, Self::MAX_STORED_FRAMES));
+        }
+        let reference_frames = Arc::get_mut(&mut self.reference_frames)
+            .expect("remaining references to reference_frames");
+        reference_frames[slot] = Some(frame);
+        Ok(())
+    }
+
+    /// Returns the orientation in which pixels are written to the output buffers: the one of the
+    /// image if it is applied, transposed for column-major output.
+    pub fn output_orientation(&self) -> Orientation {
+        let orientation = match self.adjust_orientation {
+            true => self.file_header.image_metadata.orientation,
+            false => Orientation::Identity,
+        };
+        match self.output_layout {
+            OutputLayout::RowMajor => orientation,
+            OutputLayout::ColumnMajor => orientation.transposed(),
+        }
+    }
+
+    /// Returns the stage that maps linear samples with the luminances of `color_info` to the SDR
+    /// range, if the image must be rendered for SDR displays and is output with `output_tf`.
+    pub fn sdr_tone_mapping(
+        &self,
+        color_info: &OutputColorInfo,
+        output_tf: &TransferFunction,
+    ) -> Option<ToneMappingStage> {
+        if self.rendering != Some(RenderingChoice::Sdr) || output_tf.is_hdr() {
+            return None;
+        }
+        ToneMappingStage::to_sdr(
+            0,
+            &self.file_header.image_metadata.tone_mapping,
+            color_info.luminances,
+        )
     }
 
     #[cfg(test)]
@@ -204,11 +328,17 @@ pub struct Frame {
     /// Reusable buffers for VarDCT group decoding.
     vardct_buffers: Option<group::VarDctBuffers>,
     // Last pass rendered so far for each HF group.
-    last_rendered_pass: Vec<Option<usize>>,
+    last_rendered_pass: Vec<Option<PassId>>,
     // Groups that should be rendered on the next call to flush().
-    groups_to_flush: BTreeSet<usize>,
-    changed_since_last_flush: BTreeSet<(usize, RenderUnit)>,
+    groups_to_flush: BTreeSet<GroupId>,
+    changed_since_last_flush: BTreeSet<(GroupId, RenderUnit)>,
     incomplete_groups: usize,
+    /// Groups that failed to decode, or whose LF group did, in the order they were concealed.
+    concealed_groups: Vec<ConcealedGroup>,
+    /// HF groups rendered from their DC alone.
+    concealed_hf_groups: BTreeSet<GroupId>,
+    /// Concealed LF groups whose DC is still to be interpolated.
+    concealed_lf_groups: Vec<LfGroupId>,
     patches: Arc<AtomicRefCell<PatchesDictionary>>,
     splines: Arc<AtomicRefCell<Splines>>,
     noise: Arc<AtomicRefCell<Noise>>,
@@ -226,26 +356,37 @@ impl Frame {
         &self.header
     }
 
-    pub fn total_bytes_in_toc(&self) -> usize {
-        self.toc.entries.iter().map(|x| *x as usize).sum()
-    }
-
-    #[instrument(level = "debug", skip(self), ret)]
-    pub fn get_section_idx(&self, section: Section) -> usize {
-        if self.header.num_toc_entries() == 1 {
-            0
+    /// Returns the frame counts before this frame was counted. The number of non-visible frames
+    /// is only kept for non-visible frames, as visible frames reset it.
+    pub fn frame_indices_before(&self) -> FrameIndices {
+        let state = &self.decoder_state;
+        if self.header.is_visible() {
+            FrameIndices {
+                visible: state.visible_frame_index - 1,
+                nonvisible: 0,
+            }
         } else {
-            match section {
-                Section::LfGlobal => 0,
-                Section::Lf { group } => 1 + group,
-                Section::HfGlobal => self.header.num_lf_groups() + 1,
-                Section::Hf { group, pass } => {
-                    2 + self.header.num_lf_groups() + self.header.num_groups() * pass + group
-                }
+            FrameIndices {
+                visible: state.visible_frame_index,
+                nonvisible: state.nonvisible_frame_index - 1,
             }
         }
     }
 
+    /// Returns the simplifications that change the rendering of this frame.
+    pub fn simplifications(&self) -> Simplifications {
+        self.decoder_state.simplifications.applied_to(&self.header)
+    }
+
+    pub fn total_bytes_in_toc(&self) -> u64 {
+        self.toc.total_size()
+    }
+
+    #[instrument(level = "debug", skip(self), ret)]
+    pub fn section_id(&self, section: Section) -> SectionId {
+        self.header.group_layout().section_id(section)
+    }
+
     pub fn can_do_early_rendering(&self) -> bool {
         if matches!(
             self.header.frame_type,
@@ -266,6 +407,7 @@ impl Frame {
     }
 
     pub fn finalize_lf(&mut self) -> Result<()> {
+        self.interpolate_concealed_lf();
         if self.header.should_do_adaptive_lf_smoothing() {
             let lf_global = self.lf_global.as_mut().unwrap();
             let lf_quant = &lf_global.lf_quant;
@@ -283,6 +425,12 @@ impl Frame {
         }
     }
 
+    /// Takes the dequantized LF image of a VarDCT frame. Only complete once all the LF groups
+    /// have been decoded and [`finalize_lf`](Self::finalize_lf) was called.
+    pub(crate) fn take_lf_image(&mut self) -> Option<[Image<f32>; 3]> {
+        self.lf_image.take()
+    }
+
     pub fn finalize(mut self) -> Result<Option<DecoderState>> {
         // First, drop the render pipeline to ensure that no other references to the reference
         // frames are around.
@@ -290,16 +438,42 @@ impl Frame {
         // Save reference frame if this frame can be referenced and was actually decoded.
         // If reference_frame_data is None (frame was skipped), we don't save it.
         // Subsequent frames referencing this slot may fail.
-        if self.header.can_be_referenced
-            && let Some(frame_data) = self.reference_frame_data
+        if let (true, Some(frame_data)) = (self.header.can_be_referenced, self.reference_frame_data)
         {
             info!("Saving frame in slot {}", self.header.save_as_reference);
-            let rf = Arc::get_mut(&mut self.decoder_state.reference_frames)
-                .expect("remaining references to reference_frames");
-            rf[self.header.save_as_reference as usize] = Some(ReferenceFrame {
-                frame: frame_data,
-                saved_before_color_transform: self.header.save_before_ct,
-            });
+            paranoid_check(
+                || {
+                    let image_size = &self.decoder_state.file_header.size;
+                    let expected_size = if self.header.save_before_ct {
+                        self.header.size_upsampled()
+                    } else {
+                        (image_size.xsize() as usize, image_size.ysize() as usize)
+                    };
+                    let num_extra_channels = self
+                        .decoder_state
+                        .file_header
+                        .image_metadata
+                        .extra_channel_info
+                        .len();
+                    frame_data.len() == 3 + num_extra_channels
+                        && frame_data.iter().all(|c| c.size() == expected_size)
+                },
+                "reference frame does not match the slot geometry",
+            )?;
+            let color_space = if self.header.save_before_ct {
+                ReferenceColorSpace::BeforeColorTransform {
+                    ycbcr: self.header.do_ycbcr,
+                }
+            } else {
+                ReferenceColorSpace::AfterColorTransform
+            };
+            self.decoder_state.save_reference_frame(
+                self.header.save_as_reference as usize,
+                ReferenceFrame {
+                    frame: frame_data,
+                    color_space,
+                },
+            )?;
         }
 
         if self.header.lf_level != 0 {
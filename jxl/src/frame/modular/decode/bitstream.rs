@@ -8,7 +8,12 @@ use crate::{
     bit_reader::BitReader,
     entropy_coding::decode::SymbolReader,
     error::{Error, Result},
-    frame::modular::{ModularChannel, Tree, transforms::apply::meta_apply_local_transforms},
+    frame::modular::{
+        ModularChannel, Tree,
+        stats::record_transforms,
+        transforms::apply::{local_palettes, meta_apply_local_transforms},
+        verify::{StreamRecord, check_stream, take_residuals, verification_enabled},
+    },
     headers::{JxlHeader, modular::GroupHeader},
 };
 
@@ -21,6 +26,44 @@ pub fn decode_modular_subbitstream(
     global_tree: &Option<Tree>,
     br: &mut BitReader,
     partial_decoded_buffers: Option<&mut usize>,
+) -> Result<()> {
+    if !verification_enabled() {
+        return decode_modular_subbitstream_impl(
+            buffers,
+            stream_id,
+            header,
+            global_tree,
+            br,
+            partial_decoded_buffers,
+            None,
+        );
+    }
+    // Decode through reborrows, as undoing the transforms needs the channels back afterwards.
+    let mut buffers = buffers;
+    let mut record = None;
+    decode_modular_subbitstream_impl(
+        buffers.iter_mut().map(|b| &mut **b).collect(),
+        stream_id,
+        header,
+        global_tree,
+        br,
+        partial_decoded_buffers,
+        Some(&mut record),
+    )?;
+    if let Some(record) = record {
+        check_stream(&buffers, stream_id, global_tree, record)?;
+    }
+    Ok(())
+}
+
+fn decode_modular_subbitstream_impl(
+    buffers: Vec<&mut ModularChannel>,
+    stream_id: usize,
+    header: Option<GroupHeader>,
+    global_tree: &Option<Tree>,
+    br: &mut BitReader,
+    partial_decoded_buffers: Option<&mut usize>,
+    record: Option<&mut Option<StreamRecord>>,
 ) -> Result<()> {
     // Skip decoding if all grids are zero-sized.
     let is_empty = buffers
@@ -33,10 +76,14 @@ pub fn decode_modular_subbitstream(
     let mut buffer_storage = vec![];
 
     let buffers = buffers.into_iter().collect::<Vec<_>>();
+    // A given header has had its transforms applied to the whole image, so the channels of this
+    // stream are coded as they are.
+    let header_is_local = header.is_none();
     let (header, mut buffers) = match header {
         Some(h) => (h, buffers),
         None => {
             let h = GroupHeader::read(br)?;
+            record_transforms(stream_id, &h.transforms);
             if !h.transforms.is_empty() {
                 // Note: reassigning to `buffers` here convinces the borrow checker that the borrow of
                 // `buffer_storage` ought to outlive `buffers[..]`'s lifetime, which obviously breaks
@@ -80,10 +127,17 @@ pub fn decode_modular_subbitstream(
         .unwrap_or(0);
     let mut reader = SymbolReader::new(&tree.histograms, br, Some(image_width))?;
 
+    let mut residuals = vec![];
+    if record.is_some() {
+        take_residuals();
+    }
     for i in 0..buffers.len() {
         // Keep channel numbering stable, but skip actually decoding empty channels.
         // This matches libjxl, which continues the loop without renumbering.
         let (w, h) = buffers[i].data.size();
+        if record.is_some() {
+            residuals.push(((w, h), vec![]));
+        }
         if w == 0 || h == 0 {
             continue;
         }
@@ -96,12 +150,28 @@ pub fn decode_modular_subbitstream(
             }
             return Err(e);
         }
+        if let Some((_, r)) = residuals.last_mut() {
+            *r = take_residuals();
+        }
     }
 
     reader.check_final_state(&tree.histograms, br)?;
 
     drop(buffers);
 
+    if let Some(record) = record {
+        let mut header = header;
+        if !header_is_local {
+            header.transforms.clear();
+        }
+        *record = Some(StreamRecord {
+            palettes: local_palettes(&transform_steps, &mut buffer_storage)?,
+            header,
+            local_tree,
+            channels: residuals,
+        });
+    }
+
     for step in transform_steps.iter().rev() {
         step.local_apply(&mut buffer_storage)?;
     }
@@ -5,6 +5,7 @@
 
 use super::common::precompute_references;
 use crate::{
+    api::{JxlDecodeStage, NUM_MODULAR_PREDICTORS},
     bit_reader::BitReader,
     entropy_coding::decode::{Histograms, SymbolReader},
     error::Result,
@@ -15,11 +16,13 @@ use crate::{
             specialized_trees::{TreeSpecialCase, specialize_tree},
         },
         predict::{PredictionData, WeightedPredictorState},
+        stats::{MODULAR_STATS, record_predictors},
         tree::{NUM_NONREF_PROPERTIES, PROPERTIES_PER_PREVCHAN, predict},
+        verify::record_residual,
     },
     headers::modular::GroupHeader,
     image::Image,
-    util::tracing_wrappers::*,
+    util::{StageTimer, charge_tokens, tracing_wrappers::*},
 };
 
 const SMALL_CHANNEL_THRESHOLD: usize = 64;
@@ -44,6 +47,7 @@ fn decode_modular_channel_small(
     num_ref_props = num_ref_props.div_ceil(PROPERTIES_PER_PREVCHAN) * PROPERTIES_PER_PREVCHAN;
     let mut references = Image::<i32>::new((num_ref_props, size.0))?;
     let num_properties = NUM_NONREF_PROPERTIES + num_ref_props;
+    let mut predictor_counts = [0; NUM_MODULAR_PREDICTORS];
 
     const { assert!(IMAGE_OFFSET.1 == 2) };
 
@@ -69,12 +73,19 @@ fn decode_modular_channel_small(
                 &references,
                 &mut property_buffer,
             );
+            if MODULAR_STATS {
+                predictor_counts[prediction_result.predictor as usize] += 1;
+            }
             let dec = reader.read_signed(&tree.histograms, br, prediction_result.context as usize);
+            record_residual(dec);
             let val = make_pixel(dec, prediction_result.multiplier, prediction_result.guess);
             row[x] = val;
             wp_state.update_errors(val, (x, y), size.0);
         }
+        // Every sample is one token, possibly produced by a LZ77 copy.
+        charge_tokens(size.0)?;
     }
+    record_predictors(&predictor_counts);
 
     Ok(())
 }
@@ -92,6 +103,9 @@ pub(super) trait ModularChannelDecoder {
         br: &mut BitReader,
         histograms: &Histograms,
     ) -> i32;
+    /// Number of samples predicted with each predictor, out of the `num_samples` decoded so far.
+    /// Decoders that choose predictors per sample only count them if [`MODULAR_STATS`] is set.
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS];
 }
 
 #[inline(never)]
@@ -157,6 +171,11 @@ fn decode_modular_channel_impl<D: ModularChannelDecoder>(
             let val = do_decode_cold(&mut decoder, prediction_data, (x, y), reader, br);
             row[x] = val;
         }
+        // Every sample is one token, possibly produced by a LZ77 copy.
+        charge_tokens(size.0)?;
+    }
+    if MODULAR_STATS {
+        record_predictors(&decoder.predictor_counts(size.0 * size.1));
     }
     Ok(())
 }
@@ -173,6 +192,7 @@ pub(super) fn decode_modular_channel(
     br: &mut BitReader,
 ) -> Result<()> {
     debug!("reading channel");
+    let _timer = StageTimer::new(JxlDecodeStage::ModularPrediction);
     let size = buffers[chan].data.size();
     if size.0 <= IMAGE_PADDING.0
         || size.1 <= IMAGE_PADDING.1
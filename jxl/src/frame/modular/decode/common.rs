@@ -5,6 +5,7 @@
 
 use crate::{
     frame::{
+        GroupId, LfGroupId, PassId,
         modular::{ModularChannel, predict::clamped_gradient},
         quantizer::NUM_QUANT_TABLES,
     },
@@ -16,32 +17,43 @@ use num_traits::abs;
 #[derive(Debug)]
 pub enum ModularStreamId {
     GlobalData,
-    VarDCTLF(usize),
-    ModularLF(usize),
-    LFMeta(usize),
+    VarDCTLF(LfGroupId),
+    ModularLF(LfGroupId),
+    LFMeta(LfGroupId),
     QuantTable(usize),
-    ModularHF { pass: usize, group: usize },
+    ModularHF { pass: PassId, group: GroupId },
 }
 
 impl ModularStreamId {
     pub fn get_id(&self, frame_header: &FrameHeader) -> usize {
         match self {
             Self::GlobalData => 0,
-            Self::VarDCTLF(g) => 1 + g,
-            Self::ModularLF(g) => 1 + frame_header.num_lf_groups() + g,
-            Self::LFMeta(g) => 1 + frame_header.num_lf_groups() * 2 + g,
+            Self::VarDCTLF(g) => 1 + g.index(),
+            Self::ModularLF(g) => 1 + frame_header.num_lf_groups() + g.index(),
+            Self::LFMeta(g) => 1 + frame_header.num_lf_groups() * 2 + g.index(),
             Self::QuantTable(q) => 1 + frame_header.num_lf_groups() * 3 + q,
             Self::ModularHF { pass, group } => {
                 1 + frame_header.num_lf_groups() * 3
                     + NUM_QUANT_TABLES
-                    + frame_header.num_groups() * *pass
-                    + *group
+                    + frame_header.num_groups() * pass.index()
+                    + group.index()
             }
         }
     }
+
+    /// Returns the index of the section of the main modular image that this stream belongs to,
+    /// which is 1 for LF groups and 2 plus the pass for HF groups, and the index of the stream
+    /// in the grid of that section.
+    pub fn section_and_grid(&self) -> (usize, usize) {
+        match *self {
+            Self::ModularLF(group) => (1, group.index()),
+            Self::ModularHF { pass, group } => (2 + pass.index(), group.index()),
+            _ => unreachable!("only LF and HF groups are part of the main Modular image"),
+        }
+    }
 }
 
-pub(super) fn precompute_references(
+pub(in crate::frame::modular) fn precompute_references(
     buffers: &mut [&mut ModularChannel],
     chan: usize,
     y: usize,
@@ -82,6 +94,6 @@ pub(super) fn precompute_references(
     }
 }
 
-pub(super) fn make_pixel(dec: i32, mul: u32, guess: i64) -> i32 {
+pub(in crate::frame::modular) fn make_pixel(dec: i32, mul: u32, guess: i64) -> i32 {
     (guess + (mul as i64) * (dec as i64)) as i32
 }
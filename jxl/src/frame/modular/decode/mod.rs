@@ -10,3 +10,4 @@ mod specialized_trees;
 
 pub use bitstream::decode_modular_subbitstream;
 pub use common::ModularStreamId;
+pub(in crate::frame::modular) use common::{make_pixel, precompute_references};
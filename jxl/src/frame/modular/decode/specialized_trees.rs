@@ -6,6 +6,7 @@
 use std::{collections::VecDeque, ops::Range};
 
 use crate::{
+    api::NUM_MODULAR_PREDICTORS,
     bit_reader::BitReader,
     entropy_coding::decode::{Histograms, SymbolReader},
     error::Result,
@@ -16,18 +17,31 @@ use crate::{
             common::{make_pixel, precompute_references},
         },
         predict::{PredictionData, WeightedPredictorState, clamped_gradient},
+        stats::MODULAR_STATS,
         tree::{
             FlatTreeNode, NUM_NONREF_PROPERTIES, PROPERTIES_PER_PREVCHAN, TreeNode, predict_flat,
         },
+        verify::record_residual,
     },
     headers::modular::GroupHeader,
     image::Image,
 };
 
+/// Counts of a decoder that uses a single predictor for all samples.
+fn single_predictor_counts(
+    predictor: Predictor,
+    num_samples: usize,
+) -> [u64; NUM_MODULAR_PREDICTORS] {
+    let mut counts = [0; NUM_MODULAR_PREDICTORS];
+    counts[predictor as usize] = num_samples as u64;
+    counts
+}
+
 pub struct NoWpTree {
     flat_nodes: Vec<FlatTreeNode>,
     references: Image<i32>,
     property_buffer: Vec<i32>,
+    predictor_counts: [u64; NUM_MODULAR_PREDICTORS],
 }
 
 impl NoWpTree {
@@ -54,6 +68,7 @@ impl NoWpTree {
             flat_nodes,
             references,
             property_buffer,
+            predictor_counts: [0; NUM_MODULAR_PREDICTORS],
         })
     }
 }
@@ -86,9 +101,17 @@ impl ModularChannelDecoder for NoWpTree {
             &self.references,
             &mut self.property_buffer,
         );
+        if MODULAR_STATS {
+            self.predictor_counts[prediction_result.predictor as usize] += 1;
+        }
         let dec = reader.read_signed_clustered(histograms, br, prediction_result.context as usize);
+        record_residual(dec);
         make_pixel(dec, prediction_result.multiplier, prediction_result.guess)
     }
+
+    fn predictor_counts(&self, _: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        self.predictor_counts
+    }
 }
 
 pub struct GeneralTree {
@@ -140,11 +163,19 @@ impl ModularChannelDecoder for GeneralTree {
             &self.no_wp_tree.references,
             &mut self.no_wp_tree.property_buffer,
         );
+        if MODULAR_STATS {
+            self.no_wp_tree.predictor_counts[prediction_result.predictor as usize] += 1;
+        }
         let dec = reader.read_signed_clustered(histograms, br, prediction_result.context as usize);
+        record_residual(dec);
         let val = make_pixel(dec, prediction_result.multiplier, prediction_result.guess);
         self.wp_state.update_errors(val, pos, xsize);
         val
     }
+
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        self.no_wp_tree.predictor_counts(num_samples)
+    }
 }
 
 const LUT_MAX_SPLITVAL: i32 = 1023;
@@ -249,10 +280,14 @@ impl ModularChannelDecoder for WpOnlyLookupConfig420 {
             .clamp(0, LUT_TABLE_SIZE as i64 - 1) as usize];
         // Use the specialized 420 fast path
         let dec = reader.read_signed_clustered_config_420(histograms, br, ctx as usize);
+        record_residual(dec);
         let val = dec.wrapping_add(wp_pred as i32);
         self.wp_state.update_errors(val, pos, xsize);
         val
     }
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        single_predictor_counts(Predictor::Weighted, num_samples)
+    }
 }
 
 /// Property 9 is the "gradient property": left + top - topleft
@@ -324,8 +359,12 @@ impl ModularChannelDecoder for GradientLookupConfig420 {
 
         // Use the specialized config 420 fast path
         let dec = reader.read_signed_clustered_config_420(histograms, br, cluster as usize);
+        record_residual(dec);
         dec.wrapping_add(pred as i32)
     }
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        single_predictor_counts(Predictor::Gradient, num_samples)
+    }
 }
 
 pub struct SingleGradientOnly {
@@ -350,8 +389,12 @@ impl ModularChannelDecoder for SingleGradientOnly {
     ) -> i32 {
         let pred = Predictor::Gradient.predict_one(prediction_data, 0);
         let dec = reader.read_signed_clustered_inline(histograms, br, self.clustered_ctx);
+        record_residual(dec);
         make_pixel(dec, 1, pred)
     }
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        single_predictor_counts(Predictor::Gradient, num_samples)
+    }
 }
 
 pub struct NoTree {
@@ -375,8 +418,12 @@ impl ModularChannelDecoder for NoTree {
         histograms: &Histograms,
     ) -> i32 {
         let dec = reader.read_signed_clustered_inline(histograms, br, self.clustered_ctx);
+        record_residual(dec);
         make_pixel(dec, 1, 0)
     }
+    fn predictor_counts(&self, num_samples: usize) -> [u64; NUM_MODULAR_PREDICTORS] {
+        single_predictor_counts(Predictor::Zero, num_samples)
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
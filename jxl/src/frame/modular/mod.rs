@@ -15,7 +15,7 @@ use crate::{
     bit_reader::BitReader,
     error::{Error, Result},
     frame::{
-        ColorCorrelationParams, HfMetadata,
+        ColorCorrelationParams, HfMetadata, LfGroupId,
         block_context_map::BlockContextMap,
         quantizer::{self, LfQuantFactors, QuantizerParams},
     },
@@ -33,15 +33,23 @@ use jxl_transforms::transform_map::*;
 mod borrowed_buffers;
 pub(crate) mod decode;
 mod predict;
+mod stats;
 mod transforms;
 mod tree;
+mod verify;
 
 use borrowed_buffers::with_buffers;
 pub use decode::ModularStreamId;
 use decode::decode_modular_subbitstream;
 pub use predict::Predictor;
+pub(crate) use stats::{register_modular_stats, take_thread_modular_stats};
 use transforms::{TransformStepChunk, make_grids};
 pub use tree::Tree;
+#[cfg(all(test, feature = "verify"))]
+pub(crate) use verify::inject_bit_flip;
+pub(crate) use verify::{
+    register_modular_verification, set_thread_verification, take_thread_modular_checks,
+};
 
 // Two rows on top, two pixels to the left, two pixels to the right.
 const IMAGE_PADDING: (usize, usize) = (4, 2);
@@ -450,6 +458,10 @@ impl FullModularImage {
 
         trace!("reading modular header");
         let header = GroupHeader::read(br)?;
+        stats::record_transforms(
+            ModularStreamId::GlobalData.get_id(frame_header),
+            &header.transforms,
+        );
 
         // Disallow progressive rendering with multi-channel palette transforms
         // or delta-palette.
@@ -483,6 +495,7 @@ impl FullModularImage {
             .collect();
 
         sorted_buffers.sort_by_key(|x| x.0);
+        stats::record_channels(sorted_buffers.len());
 
         section_buffer_indices.push(
             sorted_buffers
@@ -675,7 +688,8 @@ impl FullModularImage {
         Ok(())
     }
 
-    pub fn mark_group_to_be_read(&mut self, section_id: usize, group: usize) {
+    pub fn mark_group_to_be_read(&mut self, stream: ModularStreamId) {
+        let (section_id, group) = stream.section_and_grid();
         for b in self.section_buffer_indices[section_id].iter() {
             self.buffer_info[*b].buffer_grid[group].set_status(BUFFER_STATUS_FINAL_RENDER);
             self.ready_buffers_dry_run.insert((*b, group));
@@ -695,15 +709,7 @@ impl FullModularImage {
             info!("No modular channels to decode");
             return Ok(());
         }
-        let (section_id, grid) = match stream {
-            ModularStreamId::ModularLF(group) => (1, group),
-            ModularStreamId::ModularHF { pass, group } => (2 + pass, group),
-            _ => {
-                unreachable!(
-                    "read_stream should only be used for streams that are part of the main Modular image"
-                );
-            }
-        };
+        let (section_id, grid) = stream.section_and_grid();
 
         with_buffers(
             &self.buffer_info,
@@ -725,6 +731,25 @@ impl FullModularImage {
         Ok(())
     }
 
+    /// Fills the buffers of `stream` with zeros, in place of data that failed to decode.
+    pub fn zero_fill_stream(&mut self, stream: ModularStreamId) -> Result<()> {
+        if self.buffer_info.is_empty() {
+            return Ok(());
+        }
+        let (section_id, grid) = stream.section_and_grid();
+        with_buffers(
+            &self.buffer_info,
+            &self.section_buffer_indices[section_id],
+            grid,
+            |bufs| {
+                for buf in bufs {
+                    buf.data.fill(0);
+                }
+                Ok(())
+            },
+        )
+    }
+
     fn maybe_output(
         &self,
         buf: usize,
@@ -1072,7 +1097,7 @@ fn dequant_lf(
 
 #[allow(clippy::too_many_arguments)]
 pub fn decode_vardct_lf(
-    group: usize,
+    group: LfGroupId,
     frame_header: &FrameHeader,
     image_metadata: &ImageMetadata,
     global_tree: &Option<Tree>,
@@ -1125,7 +1150,7 @@ pub fn decode_vardct_lf(
 }
 
 pub fn decode_hf_metadata(
-    group: usize,
+    group: LfGroupId,
     frame_header: &FrameHeader,
     image_metadata: &ImageMetadata,
     global_tree: &Option<Tree>,
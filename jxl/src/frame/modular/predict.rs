@@ -32,7 +32,7 @@ pub enum Predictor {
 }
 
 impl Predictor {
-    pub fn requires_full_row(&self) -> bool {
+    pub(crate) fn requires_full_row(&self) -> bool {
         matches!(
             self,
             Predictor::Weighted
@@ -263,7 +263,7 @@ impl Predictor {
     pub const NUM_PREDICTORS: u32 = Predictor::AverageAll as u32 + 1;
 
     #[inline]
-    pub fn predict_one(
+    pub(crate) fn predict_one(
         &self,
         PredictionData {
             left,
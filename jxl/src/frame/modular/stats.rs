@@ -0,0 +1,67 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::cell::RefCell;
+
+use crate::{
+    api::{ModularStats, NUM_MODULAR_PREDICTORS, TransformDesc},
+    headers::modular::Transform,
+};
+
+/// Whether modular statistics are collected, which is tied to the `timing-stats` feature. When
+/// it is not enabled, all the code below is optimized out.
+pub(crate) const MODULAR_STATS: bool = cfg!(feature = "timing-stats");
+
+pub(crate) fn register_modular_stats(capabilities: &mut crate::Capabilities) {
+    capabilities.modular_stats = MODULAR_STATS;
+}
+
+thread_local! {
+    static THREAD_STATS: RefCell<ModularStats> = RefCell::new(ModularStats::default());
+}
+
+#[inline(always)]
+fn with_thread_stats(f: impl FnOnce(&mut ModularStats)) {
+    if MODULAR_STATS {
+        THREAD_STATS.with(|stats| f(&mut stats.borrow_mut()));
+    }
+}
+
+/// Adds the number of samples predicted with each predictor in a channel. Decoders count
+/// predictors locally and report them once per channel, so this is not called per sample.
+pub(super) fn record_predictors(counts: &[u64; NUM_MODULAR_PREDICTORS]) {
+    with_thread_stats(|stats| {
+        for (total, count) in stats.predictor_pixel_counts.iter_mut().zip(counts) {
+            *total += count;
+        }
+    });
+}
+
+pub(super) fn record_transforms(stream: usize, transforms: &[Transform]) {
+    with_thread_stats(|stats| {
+        stats
+            .transforms
+            .extend(transforms.iter().map(|transform| TransformDesc {
+                stream,
+                transform: transform.clone(),
+            }));
+    });
+}
+
+pub(super) fn record_tree(num_nodes: usize) {
+    with_thread_stats(|stats| stats.tree_nodes += num_nodes);
+}
+
+pub(super) fn record_channels(num_channels: usize) {
+    with_thread_stats(|stats| stats.channels += num_channels);
+}
+
+/// Returns the statistics recorded on the current thread since the last call, and resets them.
+/// Threads that decode on behalf of a decoder must hand these over to it.
+pub(crate) fn take_thread_modular_stats() -> ModularStats {
+    let mut stats = ModularStats::default();
+    with_thread_stats(|thread_stats| stats = std::mem::take(thread_stats));
+    stats
+}
@@ -8,6 +8,7 @@ use std::fmt::Debug;
 use num_traits::FromPrimitive;
 
 use crate::{
+    api::JxlDecodeStage,
     error::{Error, Result},
     frame::modular::{
         ChannelInfo, ModularBufferInfo, ModularChannel, ModularGridKind, Predictor,
@@ -19,7 +20,7 @@ use crate::{
         modular::{TransformId, WeightedHeader},
     },
     image::Rect,
-    util::{AtomicRef, AtomicRefMut, tracing_wrappers::*},
+    util::{AtomicRef, AtomicRefMut, StageTimer, tracing_wrappers::*},
 };
 use std::ops::Deref;
 use std::ops::DerefMut;
@@ -93,6 +94,7 @@ impl TransformStepChunk {
         buffers: &[ModularBufferInfo],
         is_final: bool,
     ) -> Result<()> {
+        let _timer = StageTimer::new(JxlDecodeStage::InverseTransforms);
         let buf_out = self.buf_out();
         let out_grid_kind = buffers[buf_out[0]].grid_kind;
         let out_grid = buffers[buf_out[0]].get_grid_idx(out_grid_kind, self.grid_pos);
@@ -608,7 +610,9 @@ fn meta_apply_single_transform(
             trace!("applied palette: {channels:?}");
         }
         TransformId::Invalid => {
-            unreachable!("header decoding for invalid transforms should fail");
+            return Err(Error::internal(
+                "header decoding for invalid transforms should fail",
+            ));
         }
     }
     Ok(())
@@ -730,6 +734,25 @@ impl LocalTransformBuffer<'_> {
     }
 }
 
+/// Copies the palettes of the palette steps among `transform_steps`, in order. Must be called
+/// after decoding the coded channels, but before undoing any of the steps.
+pub fn local_palettes(
+    transform_steps: &[TransformStep],
+    buffer_storage: &mut [LocalTransformBuffer],
+) -> Result<Vec<ModularChannel>> {
+    transform_steps
+        .iter()
+        .filter_map(|step| match step {
+            TransformStep::Palette { buf_pal, .. } => Some(*buf_pal),
+            _ => None,
+        })
+        .map(|buf_pal| {
+            buffer_storage[buf_pal].allocate_if_needed()?;
+            buffer_storage[buf_pal].borrow_mut().try_clone()
+        })
+        .collect()
+}
+
 #[instrument(level = "trace", ret)]
 pub fn meta_apply_local_transforms<'a, 'b>(
     channels_in: Vec<&'a mut ModularChannel>,
@@ -790,7 +813,7 @@ pub fn meta_apply_local_transforms<'a, 'b>(
         for (new_pos, (ch_info, buf)) in buf_new_position
             .iter()
             .cloned()
-            .zip(channels.iter_mut().zip(buf_tmp.into_iter()))
+            .zip(channels.iter_mut().zip(buf_tmp))
         {
             assert!(matches!(
                 buffer_storage[new_pos],
@@ -889,6 +912,7 @@ impl TransformStep {
     // Marks that one dependency of this transform is ready, and potentially runs the transform,
     // returning the new buffers that are now ready.
     pub fn local_apply(&self, buffers: &mut [LocalTransformBuffer]) -> Result<()> {
+        let _timer = StageTimer::new(JxlDecodeStage::InverseTransforms);
         match self {
             TransformStep::Rct {
                 buf_in,
@@ -946,7 +970,7 @@ impl TransformStep {
                         wp_header,
                     );
                 }
-                for (pos, buf) in buf_out.iter().zip(out_bufs.into_iter()) {
+                for (pos, buf) in buf_out.iter().zip(out_bufs) {
                     buffers[*pos] = buf;
                 }
             }
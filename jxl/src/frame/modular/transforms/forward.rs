@@ -0,0 +1,300 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use num_traits::FromPrimitive;
+
+use crate::{
+    error::{Error, Result},
+    frame::modular::ModularChannel,
+    headers::modular::{GroupHeader, TransformId},
+};
+
+use super::{
+    RctOp, RctPermutation,
+    palette::forward_palette_step,
+    rct::forward_rct_step,
+    squeeze::{check_squeeze_params, default_squeeze, forward_squeeze_step},
+};
+
+/// Re-applies the transforms of `header`, in bitstream order, to the channels that undoing them
+/// produced, which turns those back into the coded channels. Palettes cannot be derived from
+/// pixels, so the meta channels of palette transforms are taken from `palettes`, in order.
+///
+/// Returns false, leaving `channels` partially transformed, if a transform cannot be re-applied
+/// unambiguously.
+pub fn forward_transforms(
+    header: &GroupHeader,
+    channels: &mut Vec<ModularChannel>,
+    palettes: Vec<ModularChannel>,
+) -> Result<bool> {
+    let mut palettes = palettes.into_iter();
+    for transform in &header.transforms {
+        match transform.id {
+            TransformId::Rct => {
+                let begin_channel = transform.begin_channel as usize;
+                let op = RctOp::from_u32(transform.rct_type % 7).unwrap();
+                let perm = RctPermutation::from_u32(transform.rct_type / 7)
+                    .expect("header decoding should ensure rct_type < 42");
+                let [r, g, b] = &mut channels[begin_channel..begin_channel + 3] else {
+                    unreachable!()
+                };
+                forward_rct_step([r, g, b], op, perm);
+            }
+            TransformId::Squeeze => {
+                let infos = |channels: &[ModularChannel]| {
+                    channels
+                        .iter()
+                        .map(|c| c.channel_info())
+                        .enumerate()
+                        .collect::<Vec<_>>()
+                };
+                let steps = if transform.squeezes.is_empty() {
+                    default_squeeze(&infos(channels))
+                } else {
+                    transform.squeezes.clone()
+                };
+                for step in steps {
+                    check_squeeze_params(&infos(channels), &step)?;
+                    let begin_channel = step.begin_channel as usize;
+                    let num_channels = step.num_channels as usize;
+                    let new_chan_offset = if step.in_place {
+                        begin_channel + num_channels
+                    } else {
+                        channels.len()
+                    };
+                    for ic in 0..num_channels {
+                        let (avg, res) =
+                            forward_squeeze_step(&channels[begin_channel + ic], step.horizontal)?;
+                        channels[begin_channel + ic] = avg;
+                        channels.insert(new_chan_offset + ic, res);
+                    }
+                }
+            }
+            TransformId::Palette => {
+                let begin_channel = transform.begin_channel as usize;
+                let num_channels = transform.num_channels as usize;
+                let palette = palettes
+                    .next()
+                    .ok_or_else(|| Error::internal("missing palette of palette transform"))?;
+                if transform.num_deltas > 0 {
+                    return Ok(false);
+                }
+                let Some(index) = forward_palette_step(
+                    &channels[begin_channel..begin_channel + num_channels],
+                    &palette,
+                    transform.num_colors as usize,
+                )?
+                else {
+                    return Ok(false);
+                };
+                channels.drain(begin_channel + 1..begin_channel + num_channels);
+                channels[begin_channel] = index;
+                channels.insert(0, palette);
+            }
+            TransformId::Invalid => {
+                return Err(Error::internal(
+                    "header decoding for invalid transforms should fail",
+                ));
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use num_traits::FromPrimitive;
+
+    use super::super::{
+        RctOp, RctPermutation,
+        apply::{LocalTransformBuffer, TransformStep},
+        palette::forward_palette_step,
+        rct::forward_rct_step,
+        squeeze::forward_squeeze_step,
+    };
+    use crate::{
+        error::Result,
+        frame::modular::{ModularChannel, Predictor},
+        headers::{bit_depth::BitDepth, modular::WeightedHeader},
+    };
+
+    struct SimpleRandom {
+        out: i64,
+    }
+
+    impl SimpleRandom {
+        fn new() -> SimpleRandom {
+            SimpleRandom { out: 1 }
+        }
+        fn next(&mut self) -> i64 {
+            self.out = self.out * 48271 % 0x7fffffff;
+            self.out
+        }
+    }
+
+    fn random_channel(
+        rng: &mut SimpleRandom,
+        size: (usize, usize),
+        range: i32,
+    ) -> Result<ModularChannel> {
+        let mut channel = ModularChannel::new(size, BitDepth::integer_samples(8))?;
+        for y in 0..size.1 {
+            for v in channel.data.row_mut(y) {
+                *v = (rng.next() % (2 * range as i64)) as i32 - range;
+            }
+        }
+        Ok(channel)
+    }
+
+    fn assert_same_pixels(a: &ModularChannel, b: &ModularChannel) {
+        assert_eq!(a.data.size(), b.data.size());
+        for y in 0..a.data.size().1 {
+            assert_eq!(a.data.row(y), b.data.row(y), "row {y}");
+        }
+    }
+
+    fn pixels<'a>(buffer: &'a LocalTransformBuffer<'_>) -> &'a ModularChannel {
+        match buffer {
+            LocalTransformBuffer::Owned(channel) => channel,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn rct_round_trip() -> Result<()> {
+        let mut rng = SimpleRandom::new();
+        for rct_type in 0..42 {
+            let op = RctOp::from_u32(rct_type % 7).unwrap();
+            let perm = RctPermutation::from_u32(rct_type / 7).unwrap();
+            let original = (0..3)
+                .map(|_| random_channel(&mut rng, (9, 5), 1 << 12))
+                .collect::<Result<Vec<_>>>()?;
+            let mut coded = original
+                .iter()
+                .map(ModularChannel::try_clone)
+                .collect::<Result<Vec<_>>>()?;
+            let [r, g, b] = &mut coded[..] else {
+                unreachable!()
+            };
+            forward_rct_step([r, g, b], op, perm);
+            let mut buffers: Vec<_> = coded.into_iter().map(LocalTransformBuffer::Owned).collect();
+            TransformStep::Rct {
+                buf_in: [0, 1, 2],
+                buf_out: [0, 1, 2],
+                op,
+                perm,
+            }
+            .local_apply(&mut buffers)?;
+            for (buffer, original) in buffers.iter_mut().zip(&original) {
+                assert_same_pixels(pixels(buffer), original);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn squeeze_round_trip() -> Result<()> {
+        let mut rng = SimpleRandom::new();
+        for horizontal in [false, true] {
+            for size in [(2, 1), (2, 2), (3, 3), (8, 5), (7, 6), (33, 17)] {
+                let original = random_channel(&mut rng, size, 1 << 10)?;
+                let (avg, res) = forward_squeeze_step(&original, horizontal)?;
+                let mut buffers = vec![
+                    LocalTransformBuffer::Owned(avg),
+                    LocalTransformBuffer::Owned(res),
+                    LocalTransformBuffer::Placeholder(original.channel_info()),
+                ];
+                let (buf_in, buf_out) = ([0, 1], 2);
+                if horizontal {
+                    TransformStep::HSqueeze { buf_in, buf_out }
+                } else {
+                    TransformStep::VSqueeze { buf_in, buf_out }
+                }
+                .local_apply(&mut buffers)?;
+                assert_same_pixels(pixels(&buffers[2]), &original);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn palette_round_trip() -> Result<()> {
+        let mut rng = SimpleRandom::new();
+        let num_colors = 6;
+        // Explicit colors outside of the range of the implicit ones, so that none repeats.
+        let mut palette = ModularChannel::new((num_colors, 3), BitDepth::integer_samples(8))?;
+        for c in 0..3 {
+            for (i, v) in palette.data.row_mut(c).iter_mut().enumerate() {
+                *v = 1000 + 10 * i as i32 + c as i32;
+            }
+        }
+        let mut index = ModularChannel::new((11, 7), BitDepth::integer_samples(8))?;
+        for y in 0..7 {
+            for v in index.data.row_mut(y) {
+                // Mostly explicit colors, and a few implicit ones.
+                *v = (rng.next() % (num_colors as i64 + 3)) as i32;
+            }
+        }
+        let undo = |index: &ModularChannel| -> Result<Vec<ModularChannel>> {
+            let info = index.channel_info();
+            let mut buffers = vec![
+                LocalTransformBuffer::Owned(index.try_clone()?),
+                LocalTransformBuffer::Owned(palette.try_clone()?),
+                LocalTransformBuffer::Placeholder(info),
+                LocalTransformBuffer::Placeholder(info),
+                LocalTransformBuffer::Placeholder(info),
+            ];
+            TransformStep::Palette {
+                buf_in: 0,
+                buf_pal: 1,
+                buf_out: vec![2, 3, 4],
+                num_colors,
+                num_deltas: 0,
+                predictor: Predictor::Zero,
+                wp_header: WeightedHeader {
+                    all_default: true,
+                    p1c: 16,
+                    p2c: 10,
+                    p3ca: 7,
+                    p3cb: 7,
+                    p3cc: 7,
+                    p3cd: 0,
+                    p3ce: 0,
+                    w0: 0xd,
+                    w1: 0xc,
+                    w2: 0xc,
+                    w3: 0xc,
+                },
+            }
+            .local_apply(&mut buffers)?;
+            buffers[2..]
+                .iter_mut()
+                .map(|b| pixels(b).try_clone())
+                .collect()
+        };
+        let decoded = undo(&index)?;
+        let coded = forward_palette_step(&decoded, &palette, num_colors)?.unwrap();
+        for y in 0..7 {
+            for (x, (&coded, &index)) in coded.data.row(y).iter().zip(index.data.row(y)).enumerate()
+            {
+                if index < num_colors as i32 {
+                    assert_eq!(coded, index, "at ({x}, {y})");
+                }
+            }
+        }
+        for (decoded, redecoded) in decoded.iter().zip(&undo(&coded)?) {
+            assert_same_pixels(decoded, redecoded);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn palette_with_repeated_color_is_ambiguous() -> Result<()> {
+        let palette = ModularChannel::new((2, 1), BitDepth::integer_samples(8))?;
+        let channel = ModularChannel::new((4, 4), BitDepth::integer_samples(8))?;
+        assert!(forward_palette_step(&[channel], &palette, 2)?.is_none());
+        Ok(())
+    }
+}
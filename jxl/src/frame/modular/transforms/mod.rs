@@ -8,6 +8,7 @@ use std::sync::atomic::Ordering;
 
 use apply::TransformStep;
 pub use apply::TransformStepChunk;
+pub(super) use forward::forward_transforms;
 use num_derive::FromPrimitive;
 
 use crate::frame::modular::BUFFER_STATUS_NOT_RENDERED;
@@ -19,6 +20,7 @@ use crate::util::tracing_wrappers::*;
 use super::{ModularBufferInfo, ModularGridKind, Predictor};
 
 pub(super) mod apply;
+mod forward;
 mod palette;
 mod rct;
 mod squeeze;
@@ -450,3 +450,49 @@ pub fn do_palette_step_group_row(
     }
     Ok(())
 }
+
+/// Index that no palette entry has, used for colors that are not in the palette.
+const NOT_IN_PALETTE: i32 = i32::MIN;
+
+/// Undoes [`do_palette_step_general`] for palettes without delta entries, by looking up the
+/// index of the color of every pixel of `channels`. Colors may be explicit or implicit palette
+/// entries; pixels whose color is in neither get an index that does not exist.
+///
+/// Returns `None` if the palette repeats a color, since the index of that color is then
+/// ambiguous.
+pub fn forward_palette_step(
+    channels: &[ModularChannel],
+    palette: &ModularChannel,
+    num_colors: usize,
+) -> Result<Option<ModularChannel>> {
+    let first = &channels[0];
+    let (w, h) = first.data.size();
+    let bit_depth = first.bit_depth.bits_per_sample().min(24) as usize;
+    let color = |index: usize| -> Vec<i32> {
+        (0..channels.len())
+            .map(|c| get_palette_value(&palette.data, index as isize, c, num_colors, bit_depth))
+            .collect()
+    };
+    let mut indices = std::collections::HashMap::new();
+    for index in 0..num_colors {
+        if indices.insert(color(index), index as i32).is_some() {
+            return Ok(None);
+        }
+    }
+    let num_implicit = LARGE_CUBE_OFFSET + LARGE_CUBE * LARGE_CUBE * LARGE_CUBE;
+    for index in num_colors..num_colors + num_implicit {
+        indices.entry(color(index)).or_insert(index as i32);
+    }
+
+    let mut out = ModularChannel::new_with_shift((w, h), first.shift, first.bit_depth)?;
+    let mut pixel = vec![0; channels.len()];
+    for y in 0..h {
+        for x in 0..w {
+            for (v, channel) in pixel.iter_mut().zip(channels) {
+                *v = channel.data.row(y)[x];
+            }
+            out.data.row_mut(y)[x] = *indices.get(&pixel).unwrap_or(&NOT_IN_PALETTE);
+        }
+    }
+    Ok(Some(out))
+}
@@ -159,3 +159,48 @@ pub fn do_rct_step(buffers: &mut [&mut ModularChannel], op: RctOp, perm: RctPerm
         }
     }
 }
+
+/// Undoes [`do_rct_step`], turning decoded channels back into the coded ones.
+pub fn forward_rct_step(buffers: [&mut ModularChannel; 3], op: RctOp, perm: RctPermutation) {
+    let [r, g, b] = buffers;
+
+    // Swaps in the reverse order of `do_rct_step`.
+    match perm {
+        RctPermutation::Rgb => {}
+        RctPermutation::Gbr => {
+            std::mem::swap(&mut r.data, &mut g.data);
+            std::mem::swap(&mut g.data, &mut b.data);
+        }
+        RctPermutation::Brg => {
+            std::mem::swap(&mut r.data, &mut g.data);
+            std::mem::swap(&mut r.data, &mut b.data);
+        }
+        RctPermutation::Rbg => std::mem::swap(&mut b.data, &mut g.data),
+        RctPermutation::Grb => std::mem::swap(&mut r.data, &mut g.data),
+        RctPermutation::Bgr => std::mem::swap(&mut r.data, &mut b.data),
+    }
+
+    for y in 0..r.data.size().1 {
+        let (row_r, row_g, row_b) = (r.data.row_mut(y), g.data.row_mut(y), b.data.row_mut(y));
+        for ((v0, v1), v2) in row_r.iter_mut().zip(row_g.iter_mut()).zip(row_b.iter_mut()) {
+            let (w0, w1, w2) = (*v0, *v1, *v2);
+            let avg = |a: i32, b: i32| a.wrapping_add(b) >> 1;
+            (*v0, *v1, *v2) = match op {
+                RctOp::Noop => (w0, w1, w2),
+                RctOp::AddFirstToThird => (w0, w1, w2.wrapping_sub(w0)),
+                RctOp::AddFirstToSecond => (w0, w1.wrapping_sub(w0), w2),
+                RctOp::AddFirstToSecondAndThird => (w0, w1.wrapping_sub(w0), w2.wrapping_sub(w0)),
+                RctOp::AddAvgToSecond => (w0, w1.wrapping_sub(avg(w0, w2)), w2),
+                RctOp::AddFirstToThirdAndAvgToSecond => {
+                    (w0, w1.wrapping_sub(avg(w0, w2)), w2.wrapping_sub(w0))
+                }
+                RctOp::YCoCg => {
+                    let co = w0.wrapping_sub(w2);
+                    let t = w2.wrapping_add(co >> 1);
+                    let cg = w1.wrapping_sub(t);
+                    (t.wrapping_add(cg >> 1), co, cg)
+                }
+            };
+        }
+    }
+}
@@ -670,3 +670,67 @@ pub fn do_vsqueeze_step(
 
     vsqueeze(in_avg, in_res, in_next_avg, out_prev, out);
 }
+
+/// Splits `line` into the averages and residuals that [`unsqueeze_scalar`] combines back into
+/// pairs of samples, for a squeeze without neighboring groups.
+fn squeeze_line(line: &[i32], avg: &mut [i32], res: &mut [i32]) {
+    for (k, avg) in avg.iter_mut().enumerate() {
+        *avg = match line.get(2 * k..2 * k + 2) {
+            Some(&[a, b]) => (a as i64 - (a as i64 - b as i64) / 2) as i32,
+            _ => line[2 * k],
+        };
+    }
+    for (k, res) in res.iter_mut().enumerate() {
+        let next_avg = *avg.get(k + 1).unwrap_or(&avg[k]);
+        let prev = if k == 0 { avg[0] } else { line[2 * k - 1] };
+        let tendency = smooth_tendency_scalar(prev as i64, avg[k] as i64, next_avg as i64);
+        *res = (line[2 * k] as i64 - line[2 * k + 1] as i64 - tendency) as i32;
+    }
+}
+
+/// Undoes a horizontal or vertical squeeze step, splitting `channel` into the averages and the
+/// residuals that [`do_hsqueeze_step`] or [`do_vsqueeze_step`] combine.
+pub fn forward_squeeze_step(
+    channel: &ModularChannel,
+    horizontal: bool,
+) -> Result<(ModularChannel, ModularChannel)> {
+    let (w, h) = channel.data.size();
+    let shift = match channel.shift {
+        Some(shift) if shift.0 > 30 || shift.1 > 30 => return Err(Error::TooManySqueezes),
+        Some((sx, sy)) if horizontal => Some((sx + 1, sy)),
+        Some((sx, sy)) => Some((sx, sy + 1)),
+        None => None,
+    };
+    let (avg_size, res_size) = if horizontal {
+        ((w.div_ceil(2), h), (w / 2, h))
+    } else {
+        ((w, h.div_ceil(2)), (w, h / 2))
+    };
+    let mut avg = ModularChannel::new_with_shift(avg_size, shift, channel.bit_depth)?;
+    let mut res = ModularChannel::new_with_shift(res_size, shift, channel.bit_depth)?;
+    if horizontal {
+        for y in 0..h {
+            squeeze_line(
+                channel.data.row(y),
+                avg.data.row_mut(y),
+                res.data.row_mut(y),
+            );
+        }
+    } else {
+        let (mut line, mut avg_line, mut res_line) =
+            (vec![0; h], vec![0; avg_size.1], vec![0; h / 2]);
+        for x in 0..w {
+            for (y, v) in line.iter_mut().enumerate() {
+                *v = channel.data.row(y)[x];
+            }
+            squeeze_line(&line, &mut avg_line, &mut res_line);
+            for (y, v) in avg_line.iter().enumerate() {
+                avg.data.row_mut(y)[x] = *v;
+            }
+            for (y, v) in res_line.iter().enumerate() {
+                res.data.row_mut(y)[x] = *v;
+            }
+        }
+    }
+    Ok((avg, res))
+}
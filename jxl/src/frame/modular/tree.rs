@@ -7,9 +7,11 @@ use std::fmt::Debug;
 
 use super::{Predictor, predict::WeightedPredictorState};
 use crate::{
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
     entropy_coding::decode::Histograms,
     entropy_coding::decode::SymbolReader,
+    entropy_coding::dump::record_entropy_code,
     error::{Error, Result},
     frame::modular::predict::PredictionData,
     image::Image,
@@ -174,7 +176,7 @@ fn validate_tree(tree: &[TreeNode], num_properties: usize) -> Result<()> {
                     property_ranges[p] = old;
                 }
             }
-            _ => unreachable!("invalid tree validation state"),
+            _ => return Err(Error::internal("invalid tree validation state")),
         }
     }
 
@@ -189,6 +191,7 @@ impl Debug for Tree {
 
 #[derive(Debug)]
 pub struct PredictionResult {
+    pub predictor: Predictor,
     pub guess: i64,
     pub multiplier: u32,
     pub context: u32,
@@ -334,6 +337,7 @@ pub(super) fn predict(
     let pred = predictor.predict_one(prediction_data, wp_pred);
 
     PredictionResult {
+        predictor,
         guess: pred + offset as i64,
         multiplier,
         context: id,
@@ -378,6 +382,7 @@ pub(super) fn predict_flat(
             let pred = predictor.predict_one(prediction_data, wp_pred);
 
             return PredictionResult {
+                predictor,
                 guess: pred + offset as i64,
                 multiplier,
                 context,
@@ -411,6 +416,7 @@ impl Tree {
         assert!(size_limit <= u32::MAX as usize);
         trace!(pos = br.total_bits_read());
         let tree_histograms = Histograms::decode(NUM_TREE_CONTEXTS, br, true)?;
+        record_entropy_code(EntropyCodePurpose::ModularTree, &tree_histograms);
         let mut tree_reader = SymbolReader::new(&tree_histograms, br, None)?;
         // TODO(veluca): consider early-exiting for trees known to be infinite.
         let mut tree: Vec<TreeNode> = vec![];
@@ -479,6 +485,8 @@ impl Tree {
         validate_tree(&tree, num_properties)?;
 
         let histograms = Histograms::decode(tree.len().div_ceil(2), br, true)?;
+        record_entropy_code(EntropyCodePurpose::ModularStream, &histograms);
+        super::stats::record_tree(tree.len());
 
         Ok(Tree {
             nodes: tree,
@@ -570,10 +578,4 @@ impl Tree {
             .unwrap_or_default() as usize
             + 1
     }
-
-    pub fn num_prev_channels(&self) -> usize {
-        self.max_property_count()
-            .saturating_sub(NUM_NONREF_PROPERTIES)
-            .div_ceil(PROPERTIES_PER_PREVCHAN)
-    }
 }
@@ -0,0 +1,204 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::cell::{Cell, RefCell};
+
+use crate::{
+    api::{ModularChannelCheck, ModularCheckOutcome},
+    error::Result,
+    frame::modular::{
+        ModularChannel, Tree,
+        decode::{make_pixel, precompute_references},
+        predict::{PredictionData, WeightedPredictorState},
+        transforms::forward_transforms,
+        tree::{NUM_NONREF_PROPERTIES, PROPERTIES_PER_PREVCHAN, predict},
+    },
+    headers::modular::GroupHeader,
+    image::Image,
+};
+
+/// Whether decoded modular streams can be re-checked, which is tied to the `verify` feature.
+/// When it is not enabled, all the code below is optimized out.
+pub(crate) const VERIFY: bool = cfg!(feature = "verify");
+
+pub(crate) fn register_modular_verification(capabilities: &mut crate::Capabilities) {
+    capabilities.modular_verification = VERIFY;
+}
+
+thread_local! {
+    static THREAD_VERIFY: Cell<bool> = const { Cell::new(false) };
+    static THREAD_RESIDUALS: RefCell<Vec<i32>> = const { RefCell::new(Vec::new()) };
+    static THREAD_CHECKS: RefCell<Vec<ModularChannelCheck>> = const { RefCell::new(Vec::new()) };
+}
+
+#[cfg(test)]
+thread_local! {
+    static INJECTED_BIT_FLIP: Cell<Option<(usize, usize, usize)>> = const { Cell::new(None) };
+}
+
+/// Flips the lowest bit of sample `(x, y)` of channel `channel` of the next stream checked on
+/// the current thread, as if it had been corrupted after being decoded.
+#[cfg(all(test, feature = "verify"))]
+pub(crate) fn inject_bit_flip(channel: usize, x: usize, y: usize) {
+    INJECTED_BIT_FLIP.with(|f| f.set(Some((channel, x, y))));
+}
+
+/// Makes the current thread record residuals and check the modular streams it decodes, until
+/// this is called again with `false`.
+pub(crate) fn set_thread_verification(enabled: bool) {
+    if VERIFY {
+        THREAD_VERIFY.with(|v| v.set(enabled));
+    }
+}
+
+#[inline(always)]
+pub(super) fn verification_enabled() -> bool {
+    VERIFY && THREAD_VERIFY.with(Cell::get)
+}
+
+/// Records the residual of the sample that was just decoded. Unlike statistics, residuals are
+/// recorded per sample, which is only acceptable because they are never recorded in regular
+/// builds.
+#[inline(always)]
+pub(super) fn record_residual(residual: i32) {
+    if verification_enabled() {
+        THREAD_RESIDUALS.with(|r| r.borrow_mut().push(residual));
+    }
+}
+
+/// Returns the residuals recorded since the last call.
+pub(super) fn take_residuals() -> Vec<i32> {
+    THREAD_RESIDUALS.with(|r| std::mem::take(&mut *r.borrow_mut()))
+}
+
+/// Returns the results of the checks done on the current thread since the last call. Threads
+/// that decode on behalf of a decoder must hand these over to it.
+pub(crate) fn take_thread_modular_checks() -> Vec<ModularChannelCheck> {
+    if !VERIFY {
+        return vec![];
+    }
+    THREAD_CHECKS.with(|c| std::mem::take(&mut *c.borrow_mut()))
+}
+
+/// What is needed to check a modular stream once its transforms have been undone.
+pub(super) struct StreamRecord {
+    pub header: GroupHeader,
+    pub local_tree: Option<Tree>,
+    /// Size and residuals of each coded channel, in decoding order.
+    pub channels: Vec<((usize, usize), Vec<i32>)>,
+    /// Meta channels of the palette transforms of the stream, in bitstream order.
+    pub palettes: Vec<ModularChannel>,
+}
+
+/// Re-applies the transforms of a stream to its decoded `channels` and compares the residuals
+/// of the resulting coded channels with the recorded ones.
+pub(super) fn check_stream(
+    channels: &[&mut ModularChannel],
+    stream_id: usize,
+    global_tree: &Option<Tree>,
+    record: StreamRecord,
+) -> Result<()> {
+    let mut coded = channels
+        .iter()
+        .map(|c| c.try_clone())
+        .collect::<Result<Vec<_>>>()?;
+    #[cfg(test)]
+    if let Some((c, x, y)) = INJECTED_BIT_FLIP.with(Cell::take) {
+        coded[c].data.row_mut(y)[x] ^= 1;
+    }
+    let reapplied = forward_transforms(&record.header, &mut coded, record.palettes)?;
+    let tree = record
+        .local_tree
+        .as_ref()
+        .or(global_tree.as_ref())
+        .expect("decoded streams have a tree");
+    debug_assert!(
+        !reapplied
+            || coded
+                .iter()
+                .map(|c| c.data.size())
+                .eq(record.channels.iter().map(|(size, _)| *size))
+    );
+    let mut coded: Vec<_> = coded.iter_mut().collect();
+    let mut checks = vec![];
+    for (channel, (size, residuals)) in record.channels.iter().enumerate() {
+        if size.0 == 0 || size.1 == 0 {
+            continue;
+        }
+        let outcome = if !reapplied {
+            ModularCheckOutcome::Skipped
+        } else {
+            match first_mismatch(
+                &mut coded,
+                channel,
+                stream_id,
+                &record.header,
+                tree,
+                residuals,
+            )? {
+                Some((x, y)) => ModularCheckOutcome::Mismatch { x, y },
+                None => ModularCheckOutcome::Passed,
+            }
+        };
+        checks.push(ModularChannelCheck {
+            stream: stream_id,
+            channel,
+            size: *size,
+            outcome,
+        });
+    }
+    THREAD_CHECKS.with(|c| c.borrow_mut().extend(checks));
+    Ok(())
+}
+
+/// Re-predicts every sample of channel `chan` with the general (not specialized) predictor, and
+/// returns the first one that the corresponding residual of `residuals` does not reproduce.
+/// Residuals left over are reported as a mismatch just below the channel.
+fn first_mismatch(
+    buffers: &mut [&mut ModularChannel],
+    chan: usize,
+    stream_id: usize,
+    header: &GroupHeader,
+    tree: &Tree,
+    residuals: &[i32],
+) -> Result<Option<(usize, usize)>> {
+    let size = buffers[chan].data.size();
+    let mut wp_state = WeightedPredictorState::new(&header.wp_header, size.0);
+    let num_ref_props = tree
+        .max_property_count()
+        .saturating_sub(NUM_NONREF_PROPERTIES)
+        .div_ceil(PROPERTIES_PER_PREVCHAN)
+        * PROPERTIES_PER_PREVCHAN;
+    let mut references = Image::<i32>::new((num_ref_props, size.0))?;
+    let mut residuals = residuals.iter();
+    for y in 0..size.1 {
+        precompute_references(buffers, chan, y, &mut references);
+        let mut property_buffer = vec![0; NUM_NONREF_PROPERTIES + num_ref_props];
+        property_buffer[0] = chan as i32;
+        property_buffer[1] = stream_id as i32;
+        let data = &buffers[chan].data;
+        for x in 0..size.0 {
+            let prediction = predict(
+                &tree.nodes,
+                PredictionData::get(data, x, y),
+                size.0,
+                Some(&mut wp_state),
+                x,
+                y,
+                &references,
+                &mut property_buffer,
+            );
+            let val = data.row(y)[x];
+            let Some(&residual) = residuals.next() else {
+                return Ok(Some((x, y)));
+            };
+            if make_pixel(residual, prediction.multiplier, prediction.guess) != val {
+                return Ok(Some((x, y)));
+            }
+            wp_state.update_errors(val, (x, y), size.0);
+        }
+    }
+    Ok(residuals.next().map(|_| (0, size.1)))
+}
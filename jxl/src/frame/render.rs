@@ -10,32 +10,43 @@ use crate::api::JxlColorType;
 use crate::api::JxlDataFormat;
 use crate::api::JxlOutputBuffer;
 use crate::bit_reader::BitReader;
-use crate::error::{Error, Result};
+use crate::error::{Error, ErrorContext, Result};
 use crate::features::epf::SigmaSource;
 use crate::features::noise::Noise;
 use crate::features::patches::PatchesDictionary;
 use crate::features::spline::Splines;
 use crate::frame::RenderUnit;
 use crate::frame::color_correlation_map::ColorCorrelationParams;
+use crate::frame::modular::ModularStreamId;
 use crate::frame::quantizer::LfQuantFactors;
+use crate::frame::{GroupId, PassId, Section};
+use crate::frame::{ReferenceColorSpace, ReferenceFrame};
 use crate::headers::frame_header::Encoding;
 use crate::headers::frame_header::FrameType;
 use crate::headers::{Orientation, color_encoding::ColorSpace, extra_channels::ExtraChannel};
 use crate::image::Image;
 use crate::image::Rect;
-use crate::util::AtomicRefCell;
+use crate::util::tracing_wrappers::warn;
+use crate::util::{AtomicRefCell, round_up_size_to_cache_line};
 use std::sync::Arc;
 
 #[cfg(test)]
 use crate::render::SimpleRenderPipeline;
 use crate::render::buffer_splitter::BufferSplitter;
-use crate::render::{LowMemoryRenderPipeline, RenderPipeline, RenderPipelineBuilder, stages::*};
+use crate::render::{
+    LowMemoryRenderPipeline, RenderPipeline, RenderPipelineBuilder, RenderPipelineInPlaceStage,
+    stages::*,
+};
 use crate::{
     api::JxlPixelFormat,
     frame::{DecoderState, Frame},
     headers::frame_header::FrameHeader,
 };
 
+/// Maximum difference in colorants and tone curves for which input and output color profiles
+/// are considered the same, and no color conversion is done.
+const EQUIVALENT_PROFILE_TOLERANCE: f32 = 5e-4;
+
 #[cfg(test)]
 macro_rules! pipeline {
     ($frame: expr, $pipeline: ident, $op: expr) => {
@@ -113,9 +124,7 @@ impl Frame {
         out_channels: usize,
         pixel_format: &JxlPixelFormat,
     ) -> Result<()> {
-        if let Some(k_pipeline_idx) = black_channel
-            && out_channels < in_channels
-        {
+        if let Some(k_pipeline_idx) = black_channel.filter(|_| out_channels < in_channels) {
             // K channel is consumed (4->3 conversion)
             let k_ec_idx = k_pipeline_idx - 3;
             if pixel_format
@@ -136,7 +145,7 @@ impl Frame {
         &mut self,
         api_buffers: &mut Option<&mut [JxlOutputBuffer<'_>]>,
         pixel_format: &JxlPixelFormat,
-        groups: Vec<(usize, Vec<(usize, BitReader)>)>,
+        groups: Vec<(GroupId, Vec<(PassId, BitReader)>)>,
         do_flush: bool,
         output_profile: &JxlColorProfile,
     ) -> Result<()> {
@@ -215,7 +224,8 @@ impl Frame {
         // as having been decoded as 0.
         if !self.was_flushed_once && do_flush {
             self.was_flushed_once = true;
-            self.groups_to_flush.extend(0..self.header.num_groups());
+            self.groups_to_flush
+                .extend(self.header.group_layout().groups());
             modular_global.zero_fill_empty_channels(
                 self.header.passes.num_passes as usize,
                 self.header.num_groups(),
@@ -227,18 +237,21 @@ impl Frame {
         // VarDCT data to be rendered.
         for (g, _) in groups.iter() {
             self.groups_to_flush.insert(*g);
-            pipeline!(self, p, p.mark_group_to_rerender(*g));
+            pipeline!(self, p, p.mark_group_to_rerender(g.index()));
         }
         // Modular data to be re-rendered.
         {
             let modular_global = &mut self.lf_global.as_mut().unwrap().modular_global;
             for (group, passes) in groups.iter() {
                 for (pass, _) in passes.iter() {
-                    modular_global.mark_group_to_be_read(2 + *pass, *group);
+                    modular_global.mark_group_to_be_read(ModularStreamId::ModularHF {
+                        pass: *pass,
+                        group: *group,
+                    });
                 }
             }
             let mut pass_to_pipeline = |_, group, _, _| {
-                self.groups_to_flush.insert(group);
+                self.groups_to_flush.insert(GroupId::new(group));
                 pipeline!(self, p, p.mark_group_to_rerender(group));
                 Ok(())
             };
@@ -247,7 +260,27 @@ impl Frame {
 
         // STEP 3: decode the groups, eagerly rendering VarDCT channels and noise.
         for (group, mut passes) in groups {
-            if self.decode_hf_group(group, &mut passes, &mut buffer_splitter, do_flush)? {
+            let pass = passes.first().map_or(PassId::new(0), |p| p.0);
+            let rendered = if self.is_concealed(group) {
+                let passes: Vec<_> = passes.iter().map(|p| p.0).collect();
+                self.conceal_hf_group(group, &passes, false, &mut buffer_splitter)?;
+                true
+            } else {
+                match self
+                    .decode_hf_group(group, &mut passes, &mut buffer_splitter, do_flush)
+                    .at_section(Section::Hf { group, pass })
+                {
+                    Ok(rendered) => rendered,
+                    Err(e) if self.can_conceal(&e) => {
+                        warn!("Concealing HF group {group}: {e}");
+                        let passes: Vec<_> = passes.iter().map(|p| p.0).collect();
+                        self.conceal_hf_group(group, &passes, true, &mut buffer_splitter)?;
+                        true
+                    }
+                    Err(e) => return Err(e),
+                }
+            };
+            if rendered {
                 self.changed_since_last_flush
                     .insert((group, RenderUnit::VarDCT));
             }
@@ -260,7 +293,7 @@ impl Frame {
             let modular_global = &mut self.lf_global.as_mut().unwrap().modular_global;
             let mut pass_to_pipeline = |chan, group, complete, image: Option<Image<i32>>| {
                 self.changed_since_last_flush
-                    .insert((group, RenderUnit::Modular(chan)));
+                    .insert((GroupId::new(group), RenderUnit::Modular(chan)));
                 pipeline!(
                     self,
                     p,
@@ -302,7 +335,7 @@ impl Frame {
                         .take(&(g, RenderUnit::Modular(c)))
                         .is_none()
                     {
-                        modular_global.flush_output(g, c, &mut pass_to_pipeline)?;
+                        modular_global.flush_output(g.index(), c, &mut pass_to_pipeline)?;
                     }
                 }
             }
@@ -314,7 +347,7 @@ impl Frame {
         self.lf_frame_data = lf_frame_data;
 
         if self.header.frame_type == FrameType::LFFrame && self.header.lf_level == 1 {
-            if do_flush && let Some(buffers) = api_buffers {
+            if let (true, Some(buffers)) = (do_flush, api_buffers) {
                 self.maybe_preview_lf_frame(
                     pixel_format,
                     buffers,
@@ -348,7 +381,9 @@ impl Frame {
         output_profile: &JxlColorProfile,
     ) -> Result<Box<T>> {
         let num_channels = frame_header.num_extra_channels as usize + 3;
-        let num_temp_channels = if frame_header.has_noise() { 3 } else { 0 };
+        let simplifications = decoder_state.simplifications.applied_to(frame_header);
+        let render_noise = frame_header.has_noise() && !simplifications.skip_noise;
+        let num_temp_channels = if render_noise { 3 } else { 0 };
         let metadata = &decoder_state.file_header.image_metadata;
         let mut pipeline = RenderPipelineBuilder::<T>::new(
             num_channels + num_temp_channels,
@@ -382,7 +417,17 @@ impl Frame {
         }
 
         let filters = &frame_header.restoration_filter;
-        if filters.gab {
+        if simplifications.separable_gaborish {
+            for (c, weight1, weight2) in [
+                (0, filters.gab_x_weight1, filters.gab_x_weight2),
+                (1, filters.gab_y_weight1, filters.gab_y_weight2),
+                (2, filters.gab_b_weight1, filters.gab_b_weight2),
+            ] {
+                pipeline = pipeline
+                    .add_inout_stage(HorizontalGaborishStage::new(c, weight1, weight2))
+                    .add_inout_stage(VerticalGaborishStage::new(c, weight1, weight2));
+            }
+        } else if filters.gab {
             pipeline = pipeline
                 .add_inout_stage(GaborishStage::new(
                     0,
@@ -402,7 +447,7 @@ impl Frame {
         }
 
         let rf = &frame_header.restoration_filter;
-        if rf.epf_iters >= 3 {
+        if rf.epf_iters >= 3 && !simplifications.skip_extra_epf_passes {
             pipeline = pipeline.add_inout_stage(Epf0Stage::new(
                 rf.epf_pass0_sigma_scale,
                 rf.epf_border_sad_mul,
@@ -418,7 +463,7 @@ impl Frame {
                 epf_sigma.clone(),
             ))
         }
-        if rf.epf_iters >= 2 {
+        if rf.epf_iters >= 2 && !simplifications.skip_extra_epf_passes {
             pipeline = pipeline.add_inout_stage(Epf2Stage::new(
                 rf.epf_pass2_sigma_scale,
                 rf.epf_border_sad_mul,
@@ -436,12 +481,16 @@ impl Frame {
         if !late_ec_upsample {
             let transform_data = &decoder_state.file_header.transform_data;
             for (ec, ec_up) in frame_header.ec_upsampling.iter().enumerate() {
-                if *ec_up > 1 {
+                if *ec_up > 1 && simplifications.nearest_extra_channel_upsampling {
+                    for _ in 0..ec_up.ilog2() {
+                        pipeline = pipeline.add_inout_stage(NearestNeighbourUpsample::new(3 + ec));
+                    }
+                } else if *ec_up > 1 {
                     pipeline = match *ec_up {
                         2 => pipeline.add_inout_stage(Upsample2x::new(transform_data, 3 + ec)),
                         4 => pipeline.add_inout_stage(Upsample4x::new(transform_data, 3 + ec)),
                         8 => pipeline.add_inout_stage(Upsample8x::new(transform_data, 3 + ec)),
-                        _ => unreachable!(),
+                        _ => return Err(Error::internal("invalid extra channel upsampling")),
                     };
                 }
             }
@@ -472,16 +521,22 @@ impl Frame {
                 3
             };
             for c in 0..nb_channels {
+                if c >= 3 && simplifications.nearest_extra_channel_upsampling {
+                    for _ in 0..frame_header.upsampling.ilog2() {
+                        pipeline = pipeline.add_inout_stage(NearestNeighbourUpsample::new(c));
+                    }
+                    continue;
+                }
                 pipeline = match frame_header.upsampling {
                     2 => pipeline.add_inout_stage(Upsample2x::new(transform_data, c)),
                     4 => pipeline.add_inout_stage(Upsample4x::new(transform_data, c)),
                     8 => pipeline.add_inout_stage(Upsample8x::new(transform_data, c)),
-                    _ => unreachable!(),
+                    _ => return Err(Error::internal("invalid upsampling")),
                 };
             }
         }
 
-        if frame_header.has_noise() {
+        if render_noise {
             pipeline = pipeline
                 .add_inout_stage(ConvolveNoiseStage::new(num_channels))
                 .add_inout_stage(ConvolveNoiseStage::new(num_channels + 1))
@@ -516,6 +571,7 @@ impl Frame {
                     JxlColorType::Grayscale,
                     JxlDataFormat::f32(),
                     false,
+                    None,
                 );
             }
         }
@@ -528,6 +584,7 @@ impl Frame {
                     JxlColorType::Grayscale,
                     JxlDataFormat::f32(),
                     false,
+                    None,
                 );
             }
         }
@@ -562,11 +619,32 @@ impl Frame {
 
         let xyb_encoded = decoder_state.file_header.image_metadata.xyb_encoded;
 
+        // Images rendered for SDR displays are tone mapped in linear light, so samples of images
+        // that are not XYB encoded must be linearized first, which needs their transfer function.
+        let tone_mapping = decoder_state
+            .sdr_tone_mapping(&output_color_info, &output_tf)
+            .filter(|_| xyb_encoded || matches!(input_profile, JxlColorProfile::Simple(_)));
+        // Whether samples are linear after the color transform and tone mapping, and the
+        // luminance that 1.0 then corresponds to.
+        let linear = xyb_encoded || tone_mapping.is_some();
+        let intensity_target = if tone_mapping.is_some() {
+            SDR_INTENSITY_TARGET
+        } else {
+            output_color_info.intensity_target
+        };
+
         if frame_header.do_ycbcr {
             pipeline = pipeline.add_inplace_stage(YcbcrToRgbStage::new(0));
         } else if xyb_encoded {
             pipeline = pipeline.add_inplace_stage(XybStage::new(0, output_color_info.clone()));
         }
+        if let Some(stage) = &tone_mapping {
+            if !xyb_encoded {
+                pipeline =
+                    pipeline.add_inplace_stage(ToLinearStage::new(0, output_color_info.tf.clone()));
+            }
+            pipeline = pipeline.add_inplace_stage(stage.clone());
+        }
 
         // Insert CMS stage if profiles differ.
         // Following libjxl: use EITHER CMS OR FromLinearStage, never both.
@@ -576,7 +654,8 @@ impl Frame {
         // For XYB images, XybStage outputs LINEAR data in the embedded profile's primaries,
         // so the CMS input should be the LINEAR version of the embedded profile.
         // For ICC embedded profiles with XYB, XybStage outputs linear sRGB (see xyb.rs).
-        let cms_input_profile = if xyb_encoded {
+        // Tone mapped samples are linear too.
+        let cms_input_profile = if linear {
             // XYB outputs linear, so use linear version of input profile for CMS
             input_profile.with_linear_tf().or_else(|| {
                 // For ICC profiles with XYB, XybStage outputs linear sRGB
@@ -592,8 +671,18 @@ impl Frame {
         // Compare ORIGINAL input profile (not linearized cms_input_profile) with output.
         // This matches libjxl (53042ec5) dec_xyb.cc:184:
         //   color_encoding_is_original = orig_color_encoding.SameColorEncoding(c_desired);
-        let color_encoding_is_original = input_profile.same_color_encoding(output_profile);
+        //
+        // Profiles that are equivalent but represented differently, such as ICC profiles written
+        // by different tools, need no conversion either. This does not apply to XYB images with
+        // ICC profiles, as XybStage produces linear sRGB for those.
+        let color_encoding_is_original = input_profile.same_color_encoding(output_profile)
+            || ((!xyb_encoded || matches!(input_profile, JxlColorProfile::Simple(_)))
+                && input_profile
+                    .is_approx_equivalent(output_profile, EQUIVALENT_PROFILE_TOLERANCE));
         let mut cms_used = false;
+        // The CMS transform, if any, to repeat on reference frames saved before the color
+        // transform.
+        let mut reference_cms = None;
 
         // Skip CMS if channel counts differ (grayscale↔RGB) - like libjxl's not_mixing_color_and_grey.
         // Exception: CMYK (4) → RGB (3) is allowed via CMS.
@@ -605,11 +694,12 @@ impl Frame {
         let channel_counts_compatible =
             src_channels == dst_channels || (src_channels == 4 && dst_channels == 3);
 
-        if !color_encoding_is_original
-            && channel_counts_compatible
-            && let Some(cms) = cms
-            && let Some(cms_input) = cms_input_profile
-        {
+        if let (true, Some(cms), Some(cms_input)) = (
+            !color_encoding_is_original && channel_counts_compatible,
+            cms,
+            cms_input_profile,
+        ) {
+            let cms_input_for_references = cms_input.clone();
             // Use frame width as max_pixels since rows can be that wide
             let max_pixels = frame_header.size_upsampled().0;
             // Use CMS input profile's channel count, matching libjxl's c_src_.Channels()
@@ -620,7 +710,7 @@ impl Frame {
                 max_pixels,
                 cms_input,
                 output_profile.clone(),
-                output_color_info.intensity_target,
+                intensity_target,
             )?;
             // CMS cannot add channels - reject transforms that would
             if out_channels > in_channels {
@@ -643,6 +733,7 @@ impl Frame {
                 pixel_format,
             )?;
             if !transformers.is_empty() {
+                reference_cms = Some((cms, cms_input_for_references, cms_black_channel));
                 pipeline = pipeline.add_inplace_stage(CmsStage::new(
                     transformers,
                     in_channels,
@@ -654,25 +745,83 @@ impl Frame {
             }
         }
 
-        // XYB output is linear, so apply transfer function:
+        // XYB and tone mapped output is linear, so apply transfer function:
         // - Only if output is non-linear AND
         // - CMS was not used (CMS already handles the full conversion including TF)
-        if xyb_encoded && !output_tf.is_linear() && !cms_used {
-            pipeline = pipeline.add_inplace_stage(FromLinearStage::new(0, output_tf.clone()));
+        let from_linear_tf =
+            (linear && !output_tf.is_linear() && !cms_used).then(|| output_tf.clone());
+        if let Some(tf) = &from_linear_tf {
+            pipeline = pipeline.add_inplace_stage(FromLinearStage::new(0, tf.clone()));
         }
 
+        // Applies the stages above that follow the color transform to `frame`.
+        let convert_to_output = |frame: &mut [Image<f32>]| -> Result<()> {
+            if let Some(stage) = &tone_mapping {
+                if !xyb_encoded {
+                    let to_linear = ToLinearStage::new(0, output_color_info.tf.clone());
+                    apply_stage_to_frame(&to_linear, frame)?;
+                }
+                apply_stage_to_frame(stage, frame)?;
+            }
+            if let Some((cms, cms_input, black_channel)) = &reference_cms {
+                let max_pixels = frame[0].size().0;
+                let in_channels = cms_input.channels();
+                let (out_channels, transformers) = cms.initialize_transforms(
+                    1,
+                    max_pixels,
+                    cms_input.clone(),
+                    output_profile.clone(),
+                    intensity_target,
+                )?;
+                let stage = CmsStage::new(
+                    transformers,
+                    in_channels,
+                    out_channels,
+                    *black_channel,
+                    max_pixels,
+                );
+                apply_stage_to_frame(&stage, frame)?;
+            }
+            if let Some(tf) = &from_linear_tf {
+                apply_stage_to_frame(&FromLinearStage::new(0, tf.clone()), frame)?;
+            }
+            Ok(())
+        };
+
         if frame_header.needs_blending() {
+            // Frames saved before the color transform are converted with the stages above before
+            // blending with them.
+            let convert_reference = |reference: &ReferenceFrame, ycbcr: bool| {
+                let mut frame = reference
+                    .frame
+                    .iter()
+                    .map(Image::try_clone)
+                    .collect::<Result<Vec<_>>>()?;
+                if ycbcr {
+                    apply_stage_to_frame(&YcbcrToRgbStage::new(0), &mut frame)?;
+                } else if xyb_encoded {
+                    let stage = XybStage::new(0, output_color_info.clone());
+                    apply_stage_to_frame(&stage, &mut frame)?;
+                }
+                convert_to_output(&mut frame)?;
+                Ok(ReferenceFrame {
+                    frame,
+                    color_space: ReferenceColorSpace::AfterColorTransform,
+                })
+            };
+            let reference_frames =
+                Self::blending_reference_frames(decoder_state, frame_header, convert_reference)?;
             pipeline = pipeline.add_inplace_stage(BlendingStage::new(
                 frame_header,
                 &decoder_state.file_header,
-                decoder_state.reference_frames.clone(),
+                reference_frames.clone(),
             )?);
             // TODO(veluca): we might not need to add an extend stage if the image size is
             // compatible with the frame size.
             pipeline = pipeline.add_extend_stage(ExtendToImageDimensionsStage::new(
                 frame_header,
                 &decoder_state.file_header,
-                decoder_state.reference_frames.clone(),
+                reference_frames,
             )?);
         }
 
@@ -685,32 +834,74 @@ impl Frame {
                     JxlColorType::Grayscale,
                     JxlDataFormat::f32(),
                     false,
+                    None,
                 );
             }
         }
 
+        let image_metadata = &decoder_state.file_header.image_metadata;
+        let is_gray = image_metadata.color_encoding.color_space == ColorSpace::Gray;
+        let mut renders_spot_colors = false;
         if decoder_state.render_spotcolors {
-            for (i, info) in decoder_state
-                .file_header
-                .image_metadata
+            // Premultiplied color channels are composited with a premultiplied spot color.
+            let associated_alpha = image_metadata
                 .extra_channel_info
                 .iter()
-                .enumerate()
-            {
-                if info.ec_type == ExtraChannel::SpotColor {
-                    pipeline = pipeline
-                        .add_inplace_stage(SpotColorStage::new(i, info.spot_color.unwrap()));
+                .position(|info| info.ec_type == ExtraChannel::Alpha)
+                .filter(|i| image_metadata.extra_channel_info[*i].alpha_associated())
+                .map(|i| i + 3);
+            for (i, info) in image_metadata.extra_channel_info.iter().enumerate() {
+                if info.ec_type != ExtraChannel::SpotColor {
+                    continue;
+                }
+                let spot_color = info.spot_color.unwrap();
+                // Spot colors are stored as linear samples in the color space of the image, so
+                // they are converted like the color channels to be composited in the output
+                // color space. Gray images convert the three components as three gray pixels.
+                let size = if is_gray { (3, 1) } else { (1, 1) };
+                let num_channels = reference_cms
+                    .as_ref()
+                    .and_then(|(_, _, black_channel)| *black_channel)
+                    .map_or(3, |k| k + 1);
+                let mut frame = (0..num_channels)
+                    .map(|_| Image::new(size))
+                    .collect::<Result<Vec<_>>>()?;
+                for c in 0..3 {
+                    if is_gray {
+                        frame[c].row_mut(0).copy_from_slice(&spot_color[..3]);
+                    } else {
+                        frame[c].row_mut(0)[0] = spot_color[c];
+                    }
+                }
+                if !xyb_encoded {
+                    let stage = FromLinearStage::new(0, output_color_info.tf.clone());
+                    apply_stage_to_frame(&stage, &mut frame)?;
+                }
+                convert_to_output(&mut frame)?;
+                let mut converted = [0.0; 4];
+                for c in 0..3 {
+                    converted[c] = if is_gray {
+                        frame[0].row(0)[c]
+                    } else {
+                        frame[c].row(0)[0]
+                    };
                 }
+                converted[3] = spot_color[3];
+                // The first spot color makes grayscale images colored.
+                let gray = is_gray && !renders_spot_colors;
+                pipeline = pipeline.add_inplace_stage(SpotColorStage::new(
+                    i,
+                    converted,
+                    gray,
+                    associated_alpha,
+                ));
+                renders_spot_colors = true;
             }
         }
 
         if frame_header.is_visible() {
-            let color_space = decoder_state
-                .file_header
-                .image_metadata
-                .color_encoding
-                .color_space;
-            let num_color_channels = if color_space == ColorSpace::Gray {
+            // Gray images with spot colors have RGB channels once the spot colors are rendered.
+            let num_color_channels = if is_gray && !renders_spot_colors {
                 1
             } else {
                 3
@@ -732,7 +923,11 @@ impl Frame {
             let source_alpha_associated =
                 alpha_channel_info.is_some_and(|(_, info)| info.alpha_associated());
             if pixel_format.color_type.is_grayscale() && num_color_channels == 3 {
-                return Err(Error::NotGrayscale);
+                return Err(if is_gray {
+                    Error::GrayscaleSpotColors
+                } else {
+                    Error::NotGrayscale
+                });
             }
             // Determine if we need to fill opaque alpha:
             // - color_type requests alpha (has_alpha() is true)
@@ -747,16 +942,26 @@ impl Frame {
                 && alpha_in_color.is_some()
                 && !source_alpha_associated;
 
-            let color_source_channels: &[usize] =
-                match (pixel_format.color_type.is_grayscale(), alpha_in_color) {
-                    (true, None) => &[0],
-                    (true, Some(c)) => &[0, c],
-                    (false, None) => &[0, 1, 2],
-                    (false, Some(c)) => &[0, 1, 2, c],
-                };
+            // RGB output of grayscale images repeats the gray channel when saving, so that only
+            // that channel is rendered. An RGB output profile has actual RGB channels.
+            let replicate_gray = !pixel_format.color_type.is_grayscale()
+                && num_color_channels == 1
+                && output_profile.channels() == 1;
+            let color_source_channels: &[usize] = match (
+                pixel_format.color_type.is_grayscale(),
+                replicate_gray,
+                alpha_in_color,
+            ) {
+                (true, _, None) => &[0],
+                (true, _, Some(c)) => &[0, c],
+                (false, true, None) => &[0, 0, 0],
+                (false, true, Some(c)) => &[0, 0, 0, c],
+                (false, false, None) => &[0, 1, 2],
+                (false, false, Some(c)) => &[0, 1, 2, c],
+            };
             if let Some(df) = &pixel_format.color_data_format {
                 // Add premultiply stage if needed (before conversion to output format)
-                if should_premultiply && let Some(alpha_channel) = alpha_in_color {
+                if let (true, Some(alpha_channel)) = (should_premultiply, alpha_in_color) {
                     pipeline = pipeline.add_inplace_stage(PremultiplyAlphaStage::new(
                         0,
                         num_color_channels,
@@ -764,14 +969,17 @@ impl Frame {
                     ));
                 }
                 // Add conversion stages for non-float output formats
-                pipeline = Self::add_conversion_stages(pipeline, color_source_channels, *df);
+                let mut converted_channels = color_source_channels.to_vec();
+                converted_channels.dedup();
+                pipeline = Self::add_conversion_stages(pipeline, &converted_channels, *df);
                 pipeline = pipeline.add_save_stage(
                     color_source_channels,
-                    metadata.orientation,
+                    decoder_state.output_orientation(),
                     0,
                     pixel_format.color_type,
                     *df,
                     fill_opaque_alpha,
+                    decoder_state.crop,
                 );
             }
             let mut save_idx = if pixel_format.color_data_format.is_some() {
@@ -785,11 +993,12 @@ impl Frame {
                     pipeline = Self::add_conversion_stages(pipeline, &[3 + i], *df);
                     pipeline = pipeline.add_save_stage(
                         &[3 + i],
-                        metadata.orientation,
+                        decoder_state.output_orientation(),
                         save_idx,
                         JxlColorType::Grayscale,
                         *df,
                         false,
+                        decoder_state.crop,
                     );
                     save_idx += 1;
                 }
@@ -798,6 +1007,68 @@ impl Frame {
         pipeline.build()
     }
 
+    /// Returns the reference frames to blend with, after checking that they cover the image.
+    /// Frames saved before the color transform are rejected, as in libjxl, unless in
+    /// permissive mode, where `convert` is used to bring them to the color space in which frames
+    /// are blended.
+    fn blending_reference_frames(
+        decoder_state: &DecoderState,
+        frame_header: &FrameHeader,
+        convert: impl Fn(&ReferenceFrame, bool) -> Result<ReferenceFrame>,
+    ) -> Result<Arc<[Option<ReferenceFrame>; DecoderState::MAX_STORED_FRAMES]>> {
+        let image_size = &decoder_state.file_header.size;
+        let mut needs_conversion = false;
+        for source in frame_header.blending_sources() {
+            let Some(reference) = decoder_state.reference_frames[source].as_ref() else {
+                continue;
+            };
+            let (xsize, ysize) = reference.size();
+            if xsize < image_size.xsize() as usize || ysize < image_size.ysize() as usize {
+                return Err(Error::BlendingBackgroundTooSmall(source, xsize, ysize));
+            }
+            if reference.saved_before_color_transform() {
+                if !decoder_state.permissive {
+                    return Err(Error::BlendingBeforeColorTransform(source));
+                }
+                needs_conversion = true;
+            }
+        }
+        if !needs_conversion {
+            return Ok(decoder_state.reference_frames.clone());
+        }
+        let mut reference_frames: [Option<ReferenceFrame>; DecoderState::MAX_STORED_FRAMES] =
+            Default::default();
+        for source in frame_header.blending_sources() {
+            let Some(reference) = decoder_state.reference_frames[source].as_ref() else {
+                continue;
+            };
+            if reference_frames[source].is_some() {
+                continue;
+            }
+            reference_frames[source] = Some(match reference.color_space {
+                ReferenceColorSpace::BeforeColorTransform { ycbcr } => convert(reference, ycbcr)?,
+                ReferenceColorSpace::AfterColorTransform => ReferenceFrame {
+                    frame: reference
+                        .frame
+                        .iter()
+                        .map(Image::try_clone)
+                        .collect::<Result<_>>()?,
+                    color_space: reference.color_space,
+                },
+            });
+        }
+        Ok(Arc::new(reference_frames))
+    }
+
+    /// Returns how many groups of the frame were decoded but not rendered, because they are
+    /// entirely outside of the image.
+    pub fn num_skipped_groups(&mut self) -> usize {
+        if self.render_pipeline.is_none() {
+            return 0;
+        }
+        pipeline!(self, p, p.num_skipped_groups())
+    }
+
     pub fn prepare_render_pipeline(
         &mut self,
         pixel_format: &JxlPixelFormat,
@@ -805,6 +1076,10 @@ impl Frame {
         input_profile: &JxlColorProfile,
         output_profile: &JxlColorProfile,
     ) -> Result<()> {
+        let simplifications = self.simplifications();
+        if !simplifications.is_empty() {
+            warn!("rendering frame with simplifications: {simplifications}");
+        }
         #[cfg(test)]
         let render_pipeline = if self.use_simple_pipeline {
             Self::build_render_pipeline::<SimpleRenderPipeline>(
@@ -857,3 +1132,27 @@ impl Frame {
         Ok(())
     }
 }
+
+/// Runs `stage` on all rows of `frame`, whose channels are indexed like in the render pipeline.
+fn apply_stage_to_frame<S: RenderPipelineInPlaceStage<Type = f32>>(
+    stage: &S,
+    frame: &mut [Image<f32>],
+) -> Result<()> {
+    let channels: Vec<usize> = (0..frame.len())
+        .filter(|c| stage.uses_channel(*c))
+        .collect();
+    let (xsize, ysize) = frame[0].size();
+    let mut state = stage.init_local_state(0)?;
+    let mut rows = vec![vec![0f32; round_up_size_to_cache_line::<f32>(xsize)]; channels.len()];
+    for y in 0..ysize {
+        for (row, c) in rows.iter_mut().zip(channels.iter()) {
+            row[..xsize].copy_from_slice(frame[*c].row(y));
+        }
+        let mut row_slices: Vec<&mut [f32]> = rows.iter_mut().map(|row| &mut row[..]).collect();
+        stage.process_row_chunk((0, y), xsize, &mut row_slices, state.as_deref_mut());
+        for (row, c) in rows.iter().zip(channels.iter()) {
+            frame[*c].row_mut(y).copy_from_slice(&row[..xsize]);
+        }
+    }
+    Ok(())
+}
@@ -39,7 +39,7 @@ impl Debug for BitDepth {
                         f,
                         "FloatE{}M{}",
                         self.exponent_bits_per_sample,
-                        self.bits_per_sample - self.exponent_bits_per_sample - 1
+                        self.mantissa_bits()
                     )
                 }
             }
@@ -73,6 +73,14 @@ impl BitDepth {
             exponent_bits_per_sample: 5,
         }
     }
+    #[cfg(test)]
+    pub fn float(bits_per_sample: u32, exponent_bits_per_sample: u32) -> BitDepth {
+        BitDepth {
+            floating_point_sample: true,
+            bits_per_sample,
+            exponent_bits_per_sample,
+        }
+    }
     pub fn bits_per_sample(&self) -> u32 {
         self.bits_per_sample
     }
@@ -82,18 +90,23 @@ impl BitDepth {
     pub fn floating_point_sample(&self) -> bool {
         self.floating_point_sample
     }
+    /// Number of mantissa bits of float samples, which may be negative for invalid headers.
+    fn mantissa_bits(&self) -> i64 {
+        self.bits_per_sample as i64 - self.exponent_bits_per_sample as i64 - 1
+    }
     fn check(&self, _: &Empty) -> Result<(), Error> {
+        // Float samples are converted to f32, so they can have at most as many exponent and
+        // mantissa bits.
         if self.floating_point_sample {
-            if self.exponent_bits_per_sample < 2 || self.exponent_bits_per_sample > 8 {
-                Err(Error::InvalidExponent(self.exponent_bits_per_sample))
+            if (2..=8).contains(&self.exponent_bits_per_sample)
+                && (2..=23).contains(&self.mantissa_bits())
+            {
+                Ok(())
             } else {
-                let mantissa_bits =
-                    self.bits_per_sample as i32 - self.exponent_bits_per_sample as i32 - 1;
-                if !(2..=23).contains(&mantissa_bits) {
-                    Err(Error::InvalidMantissa(mantissa_bits))
-                } else {
-                    Ok(())
-                }
+                Err(Error::InvalidFloatBitDepth(
+                    self.bits_per_sample,
+                    self.exponent_bits_per_sample,
+                ))
             }
         } else if self.bits_per_sample > 31 {
             Err(Error::InvalidBitsPerSample(self.bits_per_sample))
@@ -102,3 +115,30 @@ impl BitDepth {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn float_layouts() {
+        for (bits, exponent_bits) in [(16, 5), (32, 8), (16, 8), (24, 7), (11, 4), (5, 2)] {
+            BitDepth::float(bits, exponent_bits)
+                .check(&Empty {})
+                .unwrap();
+        }
+        // Exponents of 1 or more than 8 bits, mantissas of less than 2 or more than 23 bits, and
+        // samples too short for their exponent.
+        for (bits, exponent_bits) in [(16, 1), (32, 9), (4, 2), (33, 8), (26, 1), (3, 5), (64, 8)] {
+            let err = BitDepth::float(bits, exponent_bits)
+                .check(&Empty {})
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidFloatBitDepth(b, e) if (b, e) == (bits, exponent_bits)),
+                "{err:?}"
+            );
+        }
+        assert_eq!(format!("{:?}", BitDepth::float(24, 7)), "FloatE7M16");
+        assert_eq!(format!("{:?}", BitDepth::float(16, 5)), "F16");
+    }
+}
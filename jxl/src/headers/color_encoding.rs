@@ -100,13 +100,6 @@ impl CustomXY {
     pub fn as_f32_coords(&self) -> (f32, f32) {
         (self.x as f32 / 1_000_000.0, self.y as f32 / 1_000_000.0)
     }
-
-    pub fn from_f32_coords(x: f32, y: f32) -> Self {
-        Self {
-            x: (x * 1_000_000.0).round() as i32,
-            y: (y * 1_000_000.0).round() as i32,
-        }
-    }
 }
 
 pub struct CustomTransferFunctionNonserialized {
@@ -130,14 +123,6 @@ pub struct CustomTransferFunction {
 }
 
 impl CustomTransferFunction {
-    #[cfg(test)]
-    pub fn empty() -> CustomTransferFunction {
-        CustomTransferFunction {
-            have_gamma: false,
-            gamma: 0,
-            transfer_function: TransferFunction::Unknown,
-        }
-    }
     pub fn gamma(&self) -> f32 {
         assert!(self.have_gamma);
         self.gamma as f32 * 0.0000001
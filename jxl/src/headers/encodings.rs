@@ -5,9 +5,14 @@
 
 use super::{frame_header::PermutationNonserialized, permutation::Permutation};
 use crate::{
+    api::EntropyCodePurpose,
     bit_reader::BitReader,
-    entropy_coding::decode::{Histograms, SymbolReader, unpack_signed},
+    entropy_coding::{
+        decode::{Histograms, SymbolReader, unpack_signed},
+        dump::record_entropy_code,
+    },
     error::Error,
+    util::tracing_wrappers::*,
 };
 
 pub enum U32 {
@@ -137,8 +142,17 @@ impl UnconditionalCoder<()> for u64 {
     }
 }
 
+/// Maximum length of a string in bytes, as bounded by the coding of its length.
+pub const MAX_STRING_LEN: usize = 1023 + 48;
+
+pub struct StringNonserialized {
+    /// If true, strings that are not valid UTF-8 are converted lossily, with a warning, instead
+    /// of failing.
+    pub permissive: bool,
+}
+
 impl UnconditionalCoder<()> for String {
-    type Nonserialized = Empty;
+    type Nonserialized = StringNonserialized;
     fn read_unconditional(
         _: &(),
         br: &mut BitReader,
@@ -152,25 +166,34 @@ impl UnconditionalCoder<()> for String {
                 U32::BitsOffset { n: 10, off: 48 },
             ),
             br,
-            nonserialized,
-        )?;
-        let mut ret = String::new();
-        ret.reserve(len as usize);
+            &Empty {},
+        )? as usize;
+        debug_assert!(len <= MAX_STRING_LEN);
+        if len == 0 {
+            return Ok(String::new());
+        }
+        let mut bytes = Vec::with_capacity(len);
         for _ in 0..len {
             match br.read_noinline(8) {
-                Ok(c) => ret.push(c as u8 as char),
+                Ok(c) => bytes.push(c as u8),
                 Err(Error::OutOfBounds(n)) => {
                     // Use saturating arithmetic to prevent underflow on malformed input
-                    // ret.len()+1 cannot overflow since ret.len() <= isize::MAX
-                    let remaining = (len as usize)
-                        .saturating_add(n)
-                        .saturating_sub(ret.len() + 1);
+                    // bytes.len()+1 cannot overflow since bytes.len() <= isize::MAX
+                    let remaining = len.saturating_add(n).saturating_sub(bytes.len() + 1);
                     return Err(Error::OutOfBounds(remaining));
                 }
                 Err(e) => return Err(e),
             }
         }
-        Ok(ret)
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            Err(e) if nonserialized.permissive => {
+                let s = String::from_utf8_lossy(e.as_bytes()).into_owned();
+                warn!("string {s:?} is not valid UTF-8");
+                Ok(s)
+            }
+            Err(_) => Err(Error::InvalidUtf8String),
+        }
     }
 }
 
@@ -187,6 +210,7 @@ impl UnconditionalCoder<()> for Permutation {
             let size = nonserialized.num_entries;
             let num_contexts = 8;
             let histograms = Histograms::decode(num_contexts, br, /*allow_lz77=*/ true)?;
+            record_entropy_code(EntropyCodePurpose::TocPermutation, &histograms);
             let mut reader = SymbolReader::new(&histograms, br, None)?;
             Permutation::decode(size, 0, &histograms, br, &mut reader)
         } else {
@@ -265,12 +289,12 @@ impl<Config, T: UnconditionalCoder<Config>> ConditionalCoder<Config> for Option<
 }
 
 impl ConditionalCoder<()> for String {
-    type Nonserialized = Empty;
+    type Nonserialized = StringNonserialized;
     fn read_conditional(
         _: &(),
         condition: bool,
         br: &mut BitReader,
-        nonserialized: &Empty,
+        nonserialized: &StringNonserialized,
     ) -> Result<String, Error> {
         if condition {
             String::read_unconditional(&(), br, nonserialized)
@@ -406,3 +430,50 @@ impl UnconditionalCoder<()> for Extensions {
         Ok(Extensions {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::BitstreamBuilder;
+
+    fn encode_string(bytes: &[u8]) -> Vec<u8> {
+        let mut builder = BitstreamBuilder::new();
+        builder.write_string(bytes);
+        builder.finish()
+    }
+
+    fn read_string(data: &[u8], permissive: bool) -> Result<String, Error> {
+        String::read_unconditional(
+            &(),
+            &mut BitReader::new(data),
+            &StringNonserialized { permissive },
+        )
+    }
+
+    #[test]
+    fn max_length_string() {
+        let name = "ü".repeat(MAX_STRING_LEN / 2) + "x";
+        assert_eq!(name.len(), MAX_STRING_LEN);
+        let data = encode_string(name.as_bytes());
+        assert_eq!(read_string(&data, false).unwrap(), name);
+    }
+
+    #[test]
+    fn empty_string() {
+        assert_eq!(read_string(&[0], false).unwrap(), "");
+    }
+
+    #[test]
+    fn invalid_utf8_string() {
+        let mut bytes = b"thumbnail".repeat(6);
+        bytes[0] = 0xff;
+        let data = encode_string(&bytes);
+        assert!(matches!(
+            read_string(&data, false),
+            Err(Error::InvalidUtf8String)
+        ));
+        let name = read_string(&data, true).unwrap();
+        assert!(name.starts_with('\u{fffd}'));
+        assert!(name.ends_with("thumbnail"));
+    }
+}
@@ -9,10 +9,9 @@ use crate::{
     headers::{bit_depth::BitDepth, encodings::*},
 };
 use jxl_macros::UnconditionalCoder;
-use num_derive::FromPrimitive;
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(UnconditionalCoder, Copy, Clone, PartialEq, Debug, FromPrimitive, Eq)]
+#[derive(Copy, Clone, PartialEq, Debug, Eq)]
 pub enum ExtraChannel {
     Alpha,
     Depth,
@@ -31,6 +30,77 @@ pub enum ExtraChannel {
     Reserved7,
     Unknown,
     Optional,
+    /// A type that is not defined by the specification, for example one added by a later version
+    /// of it. Holds the raw value; the channel is decoded without any special semantics.
+    Unrecognized(u32),
+}
+
+impl ExtraChannel {
+    fn from_raw(value: u32) -> ExtraChannel {
+        match value {
+            0 => ExtraChannel::Alpha,
+            1 => ExtraChannel::Depth,
+            2 => ExtraChannel::SpotColor,
+            3 => ExtraChannel::SelectionMask,
+            4 => ExtraChannel::Black,
+            5 => ExtraChannel::CFA,
+            6 => ExtraChannel::Thermal,
+            7 => ExtraChannel::Reserved0,
+            8 => ExtraChannel::Reserved1,
+            9 => ExtraChannel::Reserved2,
+            10 => ExtraChannel::Reserved3,
+            11 => ExtraChannel::Reserved4,
+            12 => ExtraChannel::Reserved5,
+            13 => ExtraChannel::Reserved6,
+            14 => ExtraChannel::Reserved7,
+            15 => ExtraChannel::Unknown,
+            16 => ExtraChannel::Optional,
+            _ => ExtraChannel::Unrecognized(value),
+        }
+    }
+
+    /// Returns the raw value of the type, as stored in the bitstream.
+    pub fn raw(self) -> u32 {
+        match self {
+            ExtraChannel::Alpha => 0,
+            ExtraChannel::Depth => 1,
+            ExtraChannel::SpotColor => 2,
+            ExtraChannel::SelectionMask => 3,
+            ExtraChannel::Black => 4,
+            ExtraChannel::CFA => 5,
+            ExtraChannel::Thermal => 6,
+            ExtraChannel::Reserved0 => 7,
+            ExtraChannel::Reserved1 => 8,
+            ExtraChannel::Reserved2 => 9,
+            ExtraChannel::Reserved3 => 10,
+            ExtraChannel::Reserved4 => 11,
+            ExtraChannel::Reserved5 => 12,
+            ExtraChannel::Reserved6 => 13,
+            ExtraChannel::Reserved7 => 14,
+            ExtraChannel::Unknown => 15,
+            ExtraChannel::Optional => 16,
+            ExtraChannel::Unrecognized(value) => value,
+        }
+    }
+}
+
+// Unlike other enums, values outside of the ones defined by the specification are not rejected
+// here: the decoder decides whether to accept them depending on its options.
+impl UnconditionalCoder<()> for ExtraChannel {
+    type Nonserialized = Empty;
+    fn read_unconditional(_: &(), br: &mut BitReader, _: &Empty) -> Result<ExtraChannel, Error> {
+        let value = u32::read_unconditional(
+            &U32Coder::Select(
+                U32::Val(0),
+                U32::Val(1),
+                U32::BitsOffset { n: 4, off: 2 },
+                U32::BitsOffset { n: 6, off: 18 },
+            ),
+            br,
+            &Empty {},
+        )?;
+        Ok(ExtraChannel::from_raw(value))
+    }
 }
 
 // TODO(veluca): figure out if these fields should be unused.
@@ -47,6 +117,9 @@ pub struct ExtraChannelInfo {
     #[coder(u2S(0, 3, 4, Bits(3) + 1))]
     #[default(0)]
     dim_shift: u32,
+    // Names are purely informational, and decoding options are not available yet when reading
+    // the file header.
+    #[nonserialized(permissive: true)]
     name: String,
     // TODO(veluca93): if using Option<bool>, this is None when all_default.
     #[condition(ec_type == ExtraChannel::Alpha)]
@@ -92,6 +165,9 @@ impl ExtraChannelInfo {
     pub fn bit_depth(&self) -> BitDepth {
         self.bit_depth
     }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     fn check(&self, _: &Empty) -> Result<(), Error> {
         if self.dim_shift > 3 {
             Err(Error::DimShiftTooLarge(self.dim_shift))
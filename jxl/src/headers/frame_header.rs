@@ -7,19 +7,22 @@
 
 use crate::{
     BLOCK_DIM, GROUP_DIM,
+    api::{GroupLayout, JxlExtraChannelType},
     bit_reader::BitReader,
     error::Error,
-    headers::{encodings::*, extra_channels::ExtraChannelInfo},
+    frame::{DecoderState, GroupId, LfGroupId},
+    headers::{
+        encodings::*,
+        extra_channels::{ExtraChannel, ExtraChannelInfo},
+    },
     image::Rect,
-    util::FloorLog2,
+    util::{FloorLog2, tracing_wrappers::warn},
 };
 
 use jxl_macros::UnconditionalCoder;
 use num_derive::FromPrimitive;
 use std::cmp::min;
 
-use super::Animation;
-
 #[derive(UnconditionalCoder, Copy, Clone, PartialEq, Debug, FromPrimitive)]
 pub enum FrameType {
     RegularFrame = 0,
@@ -34,7 +37,8 @@ pub enum Encoding {
     Modular = 1,
 }
 
-struct Flags;
+/// Bits of the `flags` field of frame headers.
+pub struct Flags;
 
 impl Flags {
     pub const ENABLE_NOISE: u64 = 1;
@@ -42,6 +46,52 @@ impl Flags {
     pub const ENABLE_SPLINES: u64 = 0x10;
     pub const USE_LF_FRAME: u64 = 0x20;
     pub const SKIP_ADAPTIVE_LF_SMOOTHING: u64 = 0x80;
+    /// All the bits defined by the specification. The others are reserved.
+    pub const KNOWN: u64 = Self::ENABLE_NOISE
+        | Self::ENABLE_PATCHES
+        | Self::ENABLE_SPLINES
+        | Self::USE_LF_FRAME
+        | Self::SKIP_ADAPTIVE_LF_SMOOTHING;
+}
+
+/// The features that the flags of a frame header enable.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameFlags {
+    pub noise: bool,
+    pub patches: bool,
+    pub splines: bool,
+    /// The LF coefficients are taken from an LF frame instead of being coded in the frame.
+    pub use_lf_frame: bool,
+    pub skip_adaptive_lf_smoothing: bool,
+}
+
+impl FrameFlags {
+    /// Decodes the known bits of `bits`, ignoring reserved ones.
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            noise: bits & Flags::ENABLE_NOISE != 0,
+            patches: bits & Flags::ENABLE_PATCHES != 0,
+            splines: bits & Flags::ENABLE_SPLINES != 0,
+            use_lf_frame: bits & Flags::USE_LF_FRAME != 0,
+            skip_adaptive_lf_smoothing: bits & Flags::SKIP_ADAPTIVE_LF_SMOOTHING != 0,
+        }
+    }
+
+    pub fn bits(&self) -> u64 {
+        [
+            (self.noise, Flags::ENABLE_NOISE),
+            (self.patches, Flags::ENABLE_PATCHES),
+            (self.splines, Flags::ENABLE_SPLINES),
+            (self.use_lf_frame, Flags::USE_LF_FRAME),
+            (
+                self.skip_adaptive_lf_smoothing,
+                Flags::SKIP_ADAPTIVE_LF_SMOOTHING,
+            ),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
 }
 
 #[derive(UnconditionalCoder, Debug, PartialEq)]
@@ -93,6 +143,34 @@ impl Passes {
         }
         (min_shift as usize, max_shift as usize)
     }
+
+    /// Returns the smallest downsampling factor at which the image is complete once `pass` has
+    /// been decoded, and whether `pass` is the pass that completes it.
+    ///
+    /// The last pass always completes the full resolution image, even if it is not listed in
+    /// `last_pass`. Passes before the first one listed only refine the LF image, which has a
+    /// downsampling factor of 8.
+    pub fn completed_downsample(&self, pass: usize) -> (u32, bool) {
+        if pass + 1 == self.num_passes as usize {
+            return (1, true);
+        }
+        let mut downsample = 8;
+        for (&ds, &last_pass) in self.downsample.iter().zip(self.last_pass.iter()) {
+            if last_pass as usize == pass {
+                return (ds, true);
+            }
+            if (last_pass as usize) < pass {
+                downsample = ds;
+            }
+        }
+        (downsample, false)
+    }
+
+    /// Returns the number of bits by which the VarDCT coefficients decoded in `pass` are shifted
+    /// left. The last pass is never shifted.
+    pub fn coefficient_shift(&self, pass: usize) -> u32 {
+        self.shift.get(pass).copied().unwrap_or(0)
+    }
 }
 
 #[derive(UnconditionalCoder, Copy, Clone, PartialEq, Debug, FromPrimitive)]
@@ -247,6 +325,9 @@ pub struct FrameHeaderNonserialized {
     pub have_timecode: bool,
     pub img_width: u32,
     pub img_height: u32,
+    /// Whether to tolerate frame names that are not valid UTF-8, reserved flags, and blending
+    /// with extra channels other than alpha channels as alpha.
+    pub permissive: bool,
 }
 
 const H_SHIFT: [usize; 4] = [0, 1, 1, 0];
@@ -299,6 +380,8 @@ pub struct FrameHeader {
     #[condition(flags & Flags::USE_LF_FRAME == 0)]
     pub ec_upsampling: Vec<u32>,
 
+    /// Groups are `128 << group_size_shift` pixels wide. This is at most 3, as it is coded with 2
+    /// bits, and is only signaled for modular frames.
     #[coder(Bits(2))]
     #[default(1)]
     #[condition(encoding == Encoding::Modular)]
@@ -407,6 +490,7 @@ pub struct FrameHeader {
     #[condition(frame_type == FrameType::ReferenceOnly || save_before_ct_def_false)]
     pub save_before_ct: bool,
 
+    #[nonserialized(permissive: nonserialized.permissive)]
     pub name: String,
 
     #[default(RestorationFilter::default(&field_nonserialized))]
@@ -448,6 +532,7 @@ impl FrameHeader {
     pub fn log_group_dim(&self) -> usize {
         (GROUP_DIM.ilog2() - 1 + self.group_size_shift) as usize
     }
+    /// Side of the groups of this frame, in pixels. See [`GroupLayout`] for the full geometry.
     pub fn group_dim(&self) -> usize {
         1 << self.log_group_dim()
     }
@@ -464,40 +549,69 @@ impl FrameHeader {
     }
 
     pub fn num_toc_entries(&self) -> usize {
-        let num_groups = self.num_groups();
-        let num_dc_groups = self.num_lf_groups();
+        self.group_layout().num_sections()
+    }
 
-        if num_groups == 1 && self.passes.num_passes == 1 {
-            1
-        } else {
-            2 + num_dc_groups + num_groups * self.passes.num_passes as usize
-        }
+    /// Returns the geometry of the groups of this frame, which converts between the ids of its
+    /// groups, passes and sections.
+    pub fn group_layout(&self) -> GroupLayout {
+        GroupLayout::new(self)
     }
 
-    pub fn duration(&self, animation: &Animation) -> f64 {
-        (self.duration as f64) * 1000.0 * (animation.tps_denominator as f64)
-            / (animation.tps_numerator as f64)
+    /// Whether the header was coded with `all_default`, which gives all fields their default
+    /// values without coding them.
+    pub fn is_all_default(&self) -> bool {
+        self.all_default
+    }
+
+    /// The features enabled by the flags of the frame. Reserved flags are not included.
+    pub fn flags(&self) -> FrameFlags {
+        FrameFlags::from_bits(self.flags)
+    }
+
+    /// The flags as they were coded, including reserved ones.
+    pub fn raw_flags(&self) -> u64 {
+        self.flags
     }
 
     pub fn has_patches(&self) -> bool {
-        self.flags & Flags::ENABLE_PATCHES != 0
+        self.flags().patches
     }
 
     pub fn has_noise(&self) -> bool {
-        self.flags & Flags::ENABLE_NOISE != 0
+        self.flags().noise
     }
 
     pub fn has_splines(&self) -> bool {
-        self.flags & Flags::ENABLE_SPLINES != 0
+        self.flags().splines
     }
     pub fn has_lf_frame(&self) -> bool {
-        self.flags & Flags::USE_LF_FRAME != 0
+        self.flags().use_lf_frame
     }
     pub fn should_do_adaptive_lf_smoothing(&self) -> bool {
-        self.flags & Flags::SKIP_ADAPTIVE_LF_SMOOTHING == 0
+        !self.flags().skip_adaptive_lf_smoothing
             && !self.has_lf_frame()
             && self.encoding == Encoding::VarDCT
     }
+
+    /// Returns the number of passes to decode for an image at 1/`downsample` of the resolution
+    /// of the frame, and the downsampling factor that they complete, which is the largest one
+    /// the frame supports that is not above `downsample`. VarDCT frames are complete at a
+    /// factor of 8 without any pass, from their LF image. Only regular frames are downsampled.
+    pub fn passes_for_downsample(&self, downsample: u32) -> (usize, u32) {
+        let num_passes = self.passes.num_passes as usize;
+        if self.frame_type != FrameType::RegularFrame || downsample <= 1 {
+            return (num_passes, 1);
+        }
+        if self.encoding == Encoding::VarDCT && downsample >= 8 {
+            return (0, 8);
+        }
+        (0..num_passes)
+            .map(|pass| (pass + 1, self.passes.completed_downsample(pass)))
+            .find(|(_, (ds, last_pass))| *last_pass && *ds <= downsample)
+            .map_or((num_passes, 1), |(passes, (ds, _))| (passes, ds))
+    }
+
     pub fn raw_hshift(&self, c: usize) -> usize {
         H_SHIFT[self.jpeg_upsampling[c] as usize]
     }
@@ -515,21 +629,12 @@ impl FrameHeader {
         self.hshift(2) == 0 && self.vshift(2) == 0 &&  // Cr
         self.hshift(1) == 0 && self.vshift(1) == 0 // Y
     }
+    #[cfg(test)]
     pub fn is420(&self) -> bool {
         self.hshift(0) == 1 && self.vshift(0) == 1 &&  // Cb
         self.hshift(2) == 1 && self.vshift(2) == 1 &&  // Cr
         self.hshift(1) == 0 && self.vshift(1) == 0 // Y
     }
-    pub fn is422(&self) -> bool {
-        self.hshift(0) == 1 && self.vshift(0) == 0 &&  // Cb
-        self.hshift(2) == 1 && self.vshift(2) == 0 &&  // Cr
-        self.hshift(1) == 0 && self.vshift(1) == 0 // Y
-    }
-    pub fn is440(&self) -> bool {
-        self.hshift(0) == 0 && self.vshift(0) == 1 &&  // Cb
-        self.hshift(2) == 0 && self.vshift(2) == 1 &&  // Cr
-        self.hshift(1) == 0 && self.vshift(1) == 0 // Y
-    }
 
     pub fn is_visible(&self) -> bool {
         (self.is_last || self.duration > 0)
@@ -551,6 +656,14 @@ impl FrameHeader {
         self.have_crop || !replace_all
     }
 
+    /// Reference frame slots that blending reads from, for the color channels and then for each
+    /// extra channel.
+    pub fn blending_sources(&self) -> impl Iterator<Item = usize> + '_ {
+        std::iter::once(&self.blending_info)
+            .chain(&self.ec_blending_info)
+            .map(|info| info.source as usize)
+    }
+
     /// The dimensions of this frame, as coded in the codestream, excluding padding pixels.
     pub fn size(&self) -> (usize, usize) {
         let (width, height) = self.size_upsampled();
@@ -588,14 +701,6 @@ impl FrameHeader {
         )
     }
 
-    pub fn size_padded_upsampled(&self) -> (usize, usize) {
-        let (xsize, ysize) = self.size_padded();
-        (
-            xsize * self.upsampling as usize,
-            ysize * self.upsampling as usize,
-        )
-    }
-
     /// The dimensions of this frame, in groups.
     pub fn size_groups(&self) -> (usize, usize) {
         (
@@ -612,12 +717,10 @@ impl FrameHeader {
         )
     }
 
-    pub fn block_group_rect(&self, group: usize) -> Rect {
-        let group_dims = self.size_groups();
+    pub fn block_group_rect(&self, group: GroupId) -> Rect {
         let block_dims = self.size_blocks();
         let group_dim_in_blocks = self.group_dim() >> 3;
-        let gx = group % group_dims.0;
-        let gy = group / group_dims.0;
+        let (gx, gy) = self.group_layout().group_coords(group);
         let origin = (gx * group_dim_in_blocks, gy * group_dim_in_blocks);
         let size = (
             min(
@@ -632,11 +735,9 @@ impl FrameHeader {
         Rect { origin, size }
     }
 
-    pub fn lf_group_rect(&self, group: usize) -> Rect {
-        let lf_dims = self.size_lf_groups();
+    pub fn lf_group_rect(&self, group: LfGroupId) -> Rect {
         let block_dims = self.size_blocks();
-        let gx = group % lf_dims.0;
-        let gy = group / lf_dims.0;
+        let (gx, gy) = self.group_layout().lf_group_coords(group);
         let origin = (gx * self.group_dim(), gy * self.group_dim());
         let size = (
             min(
@@ -664,16 +765,22 @@ impl FrameHeader {
     }
 
     fn check(&self, nonserialized: &FrameHeaderNonserialized) -> Result<(), Error> {
-        if self.upsampling > 1
-            && let Some((info, upsampling)) = nonserialized
-                .extra_channel_info
-                .iter()
-                .zip(&self.ec_upsampling)
-                .find(|(info, ec_upsampling)| {
-                    ((*ec_upsampling << info.dim_shift()) < self.upsampling)
-                        || (**ec_upsampling > 8)
-                })
-        {
+        let reserved_flags = self.flags & !Flags::KNOWN;
+        if reserved_flags != 0 {
+            if !nonserialized.permissive {
+                return Err(Error::ReservedFrameFlags(reserved_flags));
+            }
+            warn!(reserved_flags, "ignoring reserved frame flags");
+        }
+
+        let invalid_ec_upsampling = nonserialized
+            .extra_channel_info
+            .iter()
+            .zip(&self.ec_upsampling)
+            .find(|(info, ec_upsampling)| {
+                ((*ec_upsampling << info.dim_shift()) < self.upsampling) || (**ec_upsampling > 8)
+            });
+        if let (true, Some((info, upsampling))) = (self.upsampling > 1, invalid_ec_upsampling) {
             return Err(Error::InvalidEcUpsampling(
                 self.upsampling,
                 info.dim_shift(),
@@ -709,6 +816,46 @@ impl FrameHeader {
             }
         }
 
+        // Blending can use an extra channel of any type as alpha. Since implementations disagree
+        // on how to handle this, it is an error unless in permissive mode, where the samples of
+        // the channel are clamped to [0, 1] and it is treated as unassociated alpha. Color
+        // channels only use an alpha channel if the image has one.
+        let has_alpha = nonserialized
+            .extra_channel_info
+            .iter()
+            .any(|info| info.ec_type == ExtraChannel::Alpha);
+        let alpha_channels = has_alpha
+            .then_some(&self.blending_info)
+            .into_iter()
+            .chain(&self.ec_blending_info)
+            .filter(|info| num_extra_channels > 0 && uses_alpha(info.mode))
+            .map(|info| info.alpha_channel as usize);
+        for alpha_channel in alpha_channels {
+            let ec_type = nonserialized.extra_channel_info[alpha_channel].ec_type;
+            if ec_type != ExtraChannel::Alpha {
+                if !nonserialized.permissive {
+                    return Err(Error::NonAlphaBlendingChannel(
+                        alpha_channel,
+                        JxlExtraChannelType::new(ec_type),
+                    ));
+                }
+                warn!(
+                    alpha_channel,
+                    ?ec_type,
+                    "blending with a non-alpha channel as alpha"
+                );
+            }
+        }
+
+        if self.can_be_referenced
+            && self.save_as_reference as usize >= DecoderState::MAX_STORED_FRAMES
+        {
+            return Err(Error::InvalidSaveAsReference(
+                self.save_as_reference as usize,
+                DecoderState::MAX_STORED_FRAMES,
+            ));
+        }
+
         if self.has_lf_frame() && self.lf_level >= 4 {
             return Err(Error::InvalidLfLevel(self.lf_level));
         }
@@ -744,7 +891,7 @@ impl FrameHeader {
             return Err(Error::NonPatchReferenceWithCrop);
         }
         if !self.is444()
-            && ((self.flags & Flags::SKIP_ADAPTIVE_LF_SMOOTHING) == 0)
+            && !self.flags().skip_adaptive_lf_smoothing
             && self.encoding == Encoding::VarDCT
         {
             return Err(Error::Non444ChromaSubsampling);
@@ -820,6 +967,20 @@ mod test_frame_header {
         assert!(matches!(err, Error::InvalidBlendingAlphaChannel(_, _)));
     }
 
+    #[test]
+    fn test_invalid_save_as_reference() {
+        let (file_header, mut frame_header, _) =
+            read_headers_and_toc(include_bytes!("../../resources/test/extra_channels.jxl"))
+                .unwrap();
+        let nonserialized = file_header.frame_header_nonserialized();
+        frame_header.can_be_referenced = true;
+        frame_header.save_as_reference = 3;
+        frame_header.check(&nonserialized).unwrap();
+        frame_header.save_as_reference = 4;
+        let err = frame_header.check(&nonserialized).unwrap_err();
+        assert!(matches!(err, Error::InvalidSaveAsReference(4, 4)));
+    }
+
     #[test]
     fn test_has_permutation() {
         let (_, frame_header, toc) =
@@ -852,6 +1013,87 @@ mod test_frame_header {
         )
     }
 
+    #[test]
+    fn test_all_default_spellings() {
+        use crate::bit_reader::BitReader;
+        use crate::headers::bit_depth::BitDepth;
+        use crate::headers::extra_channels::{ExtraChannel, ExtraChannelInfo};
+        use crate::test_utils::{BitstreamBuilder, DefaultFrameHeader};
+
+        for (xyb_encoded, num_extra_channels, have_animation, have_timecode) in [
+            (true, 0, false, false),
+            (false, 0, false, false),
+            (true, 2, true, false),
+            (false, 1, true, true),
+        ] {
+            let nonserialized = FrameHeaderNonserialized {
+                xyb_encoded,
+                num_extra_channels,
+                extra_channel_info: (0..num_extra_channels)
+                    .map(|_| {
+                        ExtraChannelInfo::new(
+                            true,
+                            ExtraChannel::Alpha,
+                            BitDepth::f32(),
+                            0,
+                            String::new(),
+                            false,
+                            None,
+                            None,
+                        )
+                    })
+                    .collect(),
+                have_animation,
+                have_timecode,
+                img_width: 300,
+                img_height: 200,
+                permissive: false,
+            };
+            let read = |header: DefaultFrameHeader| {
+                let mut builder = BitstreamBuilder::new();
+                header.write(&mut builder, &nonserialized);
+                let num_bits = builder.num_bits();
+                let bytes = builder.finish();
+                let mut br = BitReader::new(&bytes);
+                let frame_header =
+                    FrameHeader::read_unconditional(&(), &mut br, &nonserialized).unwrap();
+                assert_eq!(br.total_bits_read(), num_bits, "{header:?}");
+                frame_header
+            };
+
+            let all_default = read(DefaultFrameHeader::AllDefault);
+            assert!(all_default.is_all_default());
+            assert!(all_default.is_last && all_default.full_frame);
+            // Fields whose defaults depend on other fields.
+            assert!(!all_default.can_be_referenced && !all_default.save_before_ct);
+            assert_eq!(all_default.size(), (300, 200));
+            assert_eq!(all_default.ec_upsampling.len(), num_extra_channels as usize);
+            assert_eq!(
+                all_default.ec_blending_info.len(),
+                num_extra_channels as usize
+            );
+            assert_eq!(all_default.restoration_filter.epf_iters, 2);
+            assert!(all_default.restoration_filter.gab);
+            all_default.check(&nonserialized).unwrap();
+
+            for header in [
+                DefaultFrameHeader::DefaultRestorationFilter,
+                DefaultFrameHeader::Explicit,
+            ] {
+                let mut frame_header = read(header);
+                assert!(!frame_header.is_all_default());
+                // The headers only differ in how they are coded.
+                frame_header.all_default = true;
+                frame_header.restoration_filter.all_default = true;
+                assert_eq!(
+                    frame_header, all_default,
+                    "{header:?} with xyb_encoded: {xyb_encoded}, {num_extra_channels} extra \
+                    channels, animation: {have_animation}, timecode: {have_timecode}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn test_frame_name() {
         let (_, frame_header, _) =
@@ -861,4 +1103,79 @@ mod test_frame_header {
         assert_eq!(frame_header.name, "TestFrameName");
         assert_eq!(frame_header.name.len(), 13);
     }
+
+    #[test]
+    fn test_frame_flags_round_trip() {
+        for bits in 0..=Flags::KNOWN {
+            let flags = FrameFlags::from_bits(bits);
+            assert_eq!(flags.bits(), bits & Flags::KNOWN, "{bits:#x}");
+            assert_eq!(FrameFlags::from_bits(flags.bits()), flags);
+        }
+        let flags = FrameFlags::from_bits(Flags::USE_LF_FRAME | 0x100);
+        assert_eq!(
+            flags,
+            FrameFlags {
+                use_lf_frame: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    /// Returns `(completed_downsample, coefficient_shift)` for every pass.
+    fn pass_geometry(
+        num_passes: u32,
+        shift: &[u32],
+        downsample: &[u32],
+        last_pass: &[u32],
+    ) -> Vec<((u32, bool), u32)> {
+        let passes = Passes {
+            num_passes,
+            num_ds: downsample.len() as u32,
+            shift: shift.to_vec(),
+            downsample: downsample.to_vec(),
+            last_pass: last_pass.to_vec(),
+        };
+        (0..num_passes as usize)
+            .map(|pass| {
+                (
+                    passes.completed_downsample(pass),
+                    passes.coefficient_shift(pass),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pass_geometry() {
+        // A single pass completes the image.
+        assert_eq!(pass_geometry(1, &[], &[], &[]), [((1, true), 0)]);
+        // Passes that only send the most significant bits of all coefficients first: only the
+        // LF image is complete before the last pass.
+        assert_eq!(
+            pass_geometry(3, &[2, 1], &[], &[]),
+            [((8, false), 2), ((8, false), 1), ((1, true), 0)]
+        );
+        // Each pass completes a finer resolution.
+        assert_eq!(
+            pass_geometry(3, &[0, 0], &[4, 2], &[0, 1]),
+            [((4, true), 0), ((2, true), 0), ((1, true), 0)]
+        );
+        // Passes between two listed ones refine towards the next downsampling factor, and keep
+        // reporting the last completed one.
+        assert_eq!(
+            pass_geometry(4, &[3, 2, 1], &[2], &[1]),
+            [
+                ((8, false), 3),
+                ((2, true), 2),
+                ((2, false), 1),
+                ((1, true), 0)
+            ]
+        );
+        // The last pass does not need to be listed, and a full resolution image completed before
+        // it is only refined by later passes.
+        assert_eq!(
+            pass_geometry(3, &[1, 0], &[1], &[0]),
+            [((1, true), 1), ((1, false), 0), ((1, true), 0)]
+        );
+    }
 }
@@ -15,12 +15,6 @@ use num_derive::FromPrimitive;
 #[derive(Debug, Default, Clone)]
 pub struct Signature;
 
-impl Signature {
-    pub fn new() -> Signature {
-        Signature {}
-    }
-}
-
 impl crate::headers::encodings::UnconditionalCoder<()> for Signature {
     type Nonserialized = Empty;
     fn read_unconditional(_: &(), br: &mut BitReader, _: &Empty) -> Result<Signature, Error> {
@@ -57,6 +51,31 @@ impl Orientation {
         )
     }
 
+    /// Returns the orientation that displays the image transposed, so that the columns of the
+    /// image in this orientation become rows.
+    pub fn transposed(&self) -> Self {
+        match self {
+            Orientation::Identity => Orientation::Transpose,
+            Orientation::FlipHorizontal => Orientation::Rotate90Ccw,
+            Orientation::Rotate180 => Orientation::AntiTranspose,
+            Orientation::FlipVertical => Orientation::Rotate90Cw,
+            Orientation::Transpose => Orientation::Identity,
+            Orientation::Rotate90Cw => Orientation::FlipVertical,
+            Orientation::AntiTranspose => Orientation::Rotate180,
+            Orientation::Rotate90Ccw => Orientation::FlipHorizontal,
+        }
+    }
+
+    /// Returns the orientation that undoes this one, mapping displayed pixels back to the
+    /// pixels of the image as stored.
+    pub fn inverse(&self) -> Self {
+        match self {
+            Orientation::Rotate90Cw => Orientation::Rotate90Ccw,
+            Orientation::Rotate90Ccw => Orientation::Rotate90Cw,
+            orientation => *orientation,
+        }
+    }
+
     pub fn map_size(&self, size: (usize, usize)) -> (usize, usize) {
         if self.is_transposing() {
             (size.1, size.0)
@@ -137,6 +156,8 @@ pub struct Animation {
 #[derive(UnconditionalCoder, Debug, Clone)]
 #[validate]
 pub struct ToneMapping {
+    // all_default is never read.
+    #[allow(dead_code)]
     #[all_default]
     pub all_default: bool,
     #[default(255.0)]
@@ -150,16 +171,6 @@ pub struct ToneMapping {
 }
 
 impl ToneMapping {
-    #[cfg(test)]
-    pub fn empty() -> ToneMapping {
-        ToneMapping {
-            all_default: false,
-            intensity_target: 0f32,
-            min_nits: 0f32,
-            relative_to_max_display: false,
-            linear_below: 0f32,
-        }
-    }
     pub fn check(&self, _: &Empty) -> Result<(), Error> {
         if self.intensity_target <= 0.0 {
             Err(Error::InvalidIntensityTarget(self.intensity_target))
@@ -223,8 +234,13 @@ pub struct ImageMetadata {
 
 impl ImageMetadata {
     fn check(&self, _: &Empty) -> Result<(), Error> {
-        if self.extra_channel_info.len() > 256 {
-            return Err(Error::TooManyExtraChannels(self.extra_channel_info.len()));
+        // The largest number of extra channels allowed by the specification, at any level.
+        const MAX_EXTRA_CHANNELS: usize = 4096;
+        if self.extra_channel_info.len() > MAX_EXTRA_CHANNELS {
+            return Err(Error::TooManyExtraChannels(
+                self.extra_channel_info.len(),
+                MAX_EXTRA_CHANNELS,
+            ));
         }
         Ok(())
     }
@@ -77,6 +77,7 @@ impl FileHeader {
             have_timecode,
             img_width,
             img_height,
+            permissive: false,
         }
     }
 }
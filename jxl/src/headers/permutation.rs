@@ -58,7 +58,7 @@ impl Permutation {
                 Err(Error::OutOfBounds(_)) => {
                     // Estimate 1.5 bits for each remaining code
                     let bits = (((skip + end) - idx) as usize).saturating_mul(3) / 2;
-                    return Err(Error::OutOfBounds(bits));
+                    return Err(Error::OutOfBounds(bits.div_ceil(8)));
                 }
                 Err(e) => return Err(e),
             };
@@ -7,12 +7,25 @@ use jxl_macros::UnconditionalCoder;
 
 use crate::{
     bit_reader::BitReader,
+    entropy_coding::dump::discard_entropy_codes_on_error,
     error::{Error, Result},
+    frame::SectionId,
     headers::{encodings::*, frame_header::PermutationNonserialized},
 };
 
 use super::permutation::Permutation;
 
+/// The coder of TOC entries, which matches the `coder` attribute of [`Toc::entries`].
+pub(crate) const TOC_ENTRY_CODER: U32Coder = U32Coder::Select(
+    U32::Bits(10),
+    U32::BitsOffset { n: 14, off: 1024 },
+    U32::BitsOffset { n: 22, off: 17408 },
+    U32::BitsOffset {
+        n: 30,
+        off: 4211712,
+    },
+);
+
 pub struct TocNonserialized {
     pub num_entries: u32,
 }
@@ -33,6 +46,25 @@ pub struct Toc {
     pub entries: Vec<u32>,
 }
 
+impl Toc {
+    /// Total size of the sections. Entries are up to about 1GB each, so this may not fit in 32
+    /// bits.
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|&x| x as u64).sum()
+    }
+
+    /// Returns the position of the section `id` among the sections as they are stored in the
+    /// codestream, which is also the index of its size in `entries`. This is the only place
+    /// where the permutation is applied: everything else refers to sections by their id.
+    pub fn position(&self, id: SectionId) -> usize {
+        if self.permuted {
+            self.permutation[id.index()] as usize
+        } else {
+            id.index()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct IncrementalTocReader {
     num_entries: u32,
@@ -54,10 +86,6 @@ impl IncrementalTocReader {
         })
     }
 
-    pub fn num_read_entries(&self) -> u32 {
-        self.entries.len() as u32
-    }
-
     pub fn remaining_entries(&self) -> u32 {
         self.num_entries - self.entries.len() as u32
     }
@@ -71,29 +99,23 @@ impl IncrementalTocReader {
             return self.read_permutation(br);
         }
 
-        let entry_coder = U32Coder::Select(
-            U32::Bits(10),
-            U32::BitsOffset { n: 14, off: 1024 },
-            U32::BitsOffset { n: 22, off: 17408 },
-            U32::BitsOffset {
-                n: 30,
-                off: 4211712,
-            },
-        );
-        let entry = u32::read_unconditional(&entry_coder, br, &Empty {})?;
+        let entry = u32::read_unconditional(&TOC_ENTRY_CODER, br, &Empty {})?;
         self.entries.push(entry);
         Ok(())
     }
 
     fn read_permutation(&mut self, br: &mut BitReader) -> Result<()> {
-        let permutation = Permutation::read_unconditional(
-            &(),
-            br,
-            &PermutationNonserialized {
-                num_entries: self.num_entries,
-                permuted: self.permuted,
-            },
-        )?;
+        // The permutation is read again from the start if it is incomplete.
+        let permutation = discard_entropy_codes_on_error(|| {
+            Permutation::read_unconditional(
+                &(),
+                br,
+                &PermutationNonserialized {
+                    num_entries: self.num_entries,
+                    permuted: self.permuted,
+                },
+            )
+        })?;
         self.permutation = Some(permutation);
         Ok(())
     }
@@ -116,43 +138,29 @@ mod test {
     use test_log::test;
 
     use super::*;
+    use crate::{api::GroupLayout, test_utils::BitstreamBuilder};
 
     #[test]
     fn parse_arb() {
         arbtest::arbtest(|u| {
+            let mut builder = BitstreamBuilder::new();
             // Not permuted
-            let mut bytes = vec![0u8];
-            let mut buf = 0u64;
-            let mut buf_bits = 0u32;
+            builder.write_bool(false).zero_pad_to_byte();
             let mut num_entries = 0u32;
 
             u.arbitrary_loop(Some(1), Some(256), |u| {
-                let selector = u.int_in_range(0..=3)?;
-                let bits = match selector {
-                    0 => 10,
-                    1 => 14,
-                    2 => 22,
-                    _ => 30,
+                let (offset, bits) = match u.int_in_range(0..=3)? {
+                    0 => (0, 10),
+                    1 => (1024, 14),
+                    2 => (17408, 22),
+                    _ => (4211712, 30),
                 };
-                let val = u.int_in_range(0u64..=((1 << bits) - 1))?;
-                let val = (val << 2) | selector as u64;
-
-                buf |= val << buf_bits;
-                buf_bits += bits + 2;
-                if buf_bits >= 32 {
-                    let bytes_to_add = (buf as u32).to_le_bytes();
-                    bytes.extend_from_slice(&bytes_to_add);
-                    buf >>= 32;
-                    buf_bits -= 32;
-                }
-
+                let val = offset + u.int_in_range(0u32..=((1 << bits) - 1))?;
+                builder.write_u32(&TOC_ENTRY_CODER, val);
                 num_entries += 1;
                 Ok(ControlFlow::Continue(()))
             })?;
-            if buf_bits > 0 {
-                let bytes_to_add = (buf as u32).to_le_bytes();
-                bytes.extend_from_slice(&bytes_to_add);
-            }
+            let bytes = builder.finish();
 
             let mut br = BitReader::new(&bytes);
             let expected =
@@ -169,4 +177,65 @@ mod test {
             Ok(())
         });
     }
+
+    #[test]
+    fn total_size_exceeds_u32() {
+        let max_entry = 4211712 + (1 << 30) - 1;
+        let num_entries = 5;
+        let mut builder = BitstreamBuilder::new();
+        builder.write_bool(false).zero_pad_to_byte();
+        for _ in 0..num_entries {
+            builder.write_u32(&TOC_ENTRY_CODER, max_entry);
+        }
+        let bytes = builder.finish();
+        let mut br = BitReader::new(&bytes);
+        let toc = Toc::read_unconditional(&(), &mut br, &TocNonserialized { num_entries }).unwrap();
+        assert_eq!(toc.total_size(), num_entries as u64 * max_entry as u64);
+        assert!(toc.total_size() > u32::MAX as u64);
+    }
+
+    #[test]
+    fn permuted_positions() {
+        for (num_groups, num_passes) in [((2, 1), 1), ((3, 2), 1), ((5, 4), 3)] {
+            let layout = GroupLayout {
+                group_dim: 256,
+                lf_group_dim: 2048,
+                num_groups,
+                num_lf_groups: (1, 1),
+                num_passes,
+            };
+            let n = layout.num_sections();
+            let entries: Vec<u32> = (0..n as u32).map(|i| 10 + i).collect();
+            let unpermuted = Toc {
+                permuted: false,
+                permutation: Permutation::default(),
+                entries: entries.clone(),
+            };
+            for section in layout.sections() {
+                let id = layout.section_id(section);
+                assert_eq!(unpermuted.position(id), id.index());
+            }
+
+            let reversed: Vec<u32> = (0..n as u32).rev().collect();
+            let rotated: Vec<u32> = (0..n as u32).map(|i| (i + 3) % n as u32).collect();
+            for permutation in [reversed, rotated] {
+                let toc = Toc {
+                    permuted: true,
+                    permutation: Permutation(permutation.clone().into()),
+                    entries: entries.clone(),
+                };
+                let mut positions: Vec<_> = layout
+                    .sections()
+                    .map(|section| {
+                        let id = layout.section_id(section);
+                        let position = toc.position(id);
+                        assert_eq!(position, permutation[id.index()] as usize);
+                        position
+                    })
+                    .collect();
+                positions.sort();
+                assert_eq!(positions, (0..n).collect::<Vec<_>>());
+            }
+        }
+    }
 }
@@ -7,9 +7,11 @@ use std::io::Cursor;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 
+use crate::api::EntropyCodePurpose;
 use crate::bit_reader::*;
 use crate::entropy_coding::decode::Histograms;
 use crate::entropy_coding::decode::SymbolReader;
+use crate::entropy_coding::dump::record_entropy_code;
 use crate::error::{Error, Result};
 use crate::headers::encodings::*;
 use crate::util::NewWithCapacity;
@@ -18,16 +20,32 @@ use crate::util::tracing_wrappers::warn;
 mod header;
 mod stream;
 mod tag;
+#[cfg(any(test, feature = "debug-tools"))]
+mod validation;
 
 use header::read_header;
 use stream::IccStream;
 pub(crate) use stream::read_varint_from_reader;
 use tag::{read_single_command, read_tag_list};
+#[cfg(feature = "debug-tools")]
+pub use validation::{IccIssue, validate};
 
 const ICC_CONTEXTS: usize = 41;
 const ICC_HEADER_SIZE: u64 = 128;
+/// Upper bound on the size of ICC profiles, regardless of the limit chosen by the user.
+const ICC_MAX_SIZE: u64 = 1 << 28;
+/// Maximum number of bytes by which the ICC stream may exceed the profile it encodes.
+const ICC_MAX_STREAM_OVERHEAD: u64 = 65536;
+
+fn check_output_size(output_size: u64, max_size: usize) -> Result<()> {
+    if output_size > ICC_MAX_SIZE.min(max_size as u64) {
+        warn!(output_size, max_size, "ICC profile too large");
+        return Err(Error::IccTooLarge);
+    }
+    Ok(())
+}
 
-fn read_icc_inner(stream: &mut IccStream) -> Result<Vec<u8>, Error> {
+fn read_icc_inner(stream: &mut IccStream, max_size: usize) -> Result<Vec<u8>, Error> {
     let output_size = stream.read_varint()?;
     let commands_size = stream.read_varint()?;
     if stream.bytes_read().saturating_add(commands_size) > stream.len() {
@@ -35,11 +53,9 @@ fn read_icc_inner(stream: &mut IccStream) -> Result<Vec<u8>, Error> {
     }
 
     // Simple check to avoid allocating too large buffer.
-    if output_size > (1 << 28) {
-        return Err(Error::IccTooLarge);
-    }
+    check_output_size(output_size, max_size)?;
 
-    if output_size + 65536 < stream.len() {
+    if output_size + ICC_MAX_STREAM_OVERHEAD < stream.len() {
         return Err(Error::IccTooLarge);
     }
 
@@ -52,6 +68,11 @@ fn read_icc_inner(stream: &mut IccStream) -> Result<Vec<u8>, Error> {
     // Decode ICC profile header.
     let mut decoded_profile = read_header(data_stream, output_size)?;
     if output_size <= ICC_HEADER_SIZE {
+        // Profiles that consist of just (part of) the header cannot use any commands.
+        if commands_size != 0 {
+            warn!(output_size, commands_size, "unused ICC commands");
+            return Err(Error::InvalidIccStream);
+        }
         return Ok(decoded_profile);
     }
 
@@ -113,18 +134,24 @@ pub struct IncrementalIccReader {
     len: usize,
     // [prev, prev_prev]
     prev_bytes: [u8; 2],
+    max_size: usize,
+    // Whether the declared size of the profile at the start of `out_buf` was checked already.
+    output_size_checked: bool,
 }
 
 impl IncrementalIccReader {
-    pub fn new(br: &mut BitReader) -> Result<Self> {
+    /// Prepares to read an ICC profile, failing if its encoding is too large for a profile of
+    /// at most `max_size` bytes.
+    pub fn new(br: &mut BitReader, max_size: usize) -> Result<Self> {
         let len = u64::read_unconditional(&(), br, &Empty {})?;
-        if len > 1u64 << 20 {
+        if len > ICC_MAX_SIZE.min(max_size as u64) + ICC_MAX_STREAM_OVERHEAD {
             return Err(Error::IccTooLarge);
         }
 
         let len = len as usize;
 
         let histograms = Histograms::decode(ICC_CONTEXTS, br, true)?;
+        record_entropy_code(EntropyCodePurpose::IccProfile, &histograms);
         let reader = SymbolReader::new(&histograms, br, None)?;
         Ok(Self {
             histograms,
@@ -132,6 +159,8 @@ impl IncrementalIccReader {
             len,
             out_buf: Vec::new_with_capacity(len)?,
             prev_bytes: [0, 0],
+            max_size,
+            output_size_checked: false,
         })
     }
 
@@ -188,6 +217,14 @@ impl IncrementalIccReader {
         let b = sym as u8;
         self.out_buf.push(b);
         self.prev_bytes = [b, self.prev_bytes[0]];
+
+        // Reject oversized profiles as soon as their size is known, instead of after decoding
+        // all of the stream.
+        if !self.output_size_checked && b & 0x80 == 0 {
+            let output_size = read_varint_from_reader(&mut self.out_buf.as_slice())?;
+            check_output_size(output_size, self.max_size)?;
+            self.output_size_checked = true;
+        }
         Ok(())
     }
 
@@ -202,8 +239,75 @@ impl IncrementalIccReader {
         assert_eq!(self.num_coded_bytes(), self.out_buf.len());
         self.reader.check_final_state(&self.histograms, br)?;
         let mut stream = IccStream::new(self.out_buf);
-        let profile = read_icc_inner(&mut stream)?;
+        let profile = read_icc_inner(&mut stream, self.max_size)?;
         stream.finalize()?;
         Ok(profile)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{IccStream, read_icc_inner};
+    use crate::error::Error;
+
+    fn read_icc(stream: Vec<u8>, max_size: usize) -> Result<Vec<u8>, Error> {
+        let mut stream = IccStream::new(stream);
+        let profile = read_icc_inner(&mut stream, max_size)?;
+        stream.finalize()?;
+        Ok(profile)
+    }
+
+    #[test]
+    fn tiny_profile() {
+        // Output size, commands size, and the residuals of the header bytes.
+        assert_eq!(read_icc(vec![2, 0, 7, 9], 1024).unwrap(), [7, 9]);
+        assert_eq!(read_icc(vec![0, 0], 1024).unwrap(), []);
+        // Header-only profiles have no commands...
+        assert!(matches!(
+            read_icc(vec![2, 1, 1, 7, 9], 1024),
+            Err(Error::InvalidIccStream)
+        ));
+        // ...and no trailing data.
+        assert!(matches!(
+            read_icc(vec![2, 0, 7, 9, 0], 1024),
+            Err(Error::InvalidIccStream)
+        ));
+        assert!(matches!(
+            read_icc(vec![2, 0, 7], 1024),
+            Err(Error::IccEndOfStream)
+        ));
+    }
+
+    #[test]
+    fn header_prediction() {
+        let mut residuals = vec![0; 44];
+        residuals[40] = b'A';
+        let profile = read_icc([vec![44, 0], residuals.clone()].concat(), 1024).unwrap();
+        assert_eq!(&profile[..4], [0, 0, 0, 44]);
+        assert_eq!(profile[8], 4);
+        assert_eq!(&profile[12..24], b"mntrRGB XYZ ");
+        assert_eq!(&profile[36..44], b"acspAPPL");
+
+        // Bytes that do not match the prediction.
+        residuals[0] = 1;
+        residuals[40] = b'M';
+        residuals[42] = 2;
+        let profile = read_icc([vec![44, 0], residuals].concat(), 1024).unwrap();
+        assert_eq!(&profile[..4], [1, 0, 0, 44]);
+        assert_eq!(&profile[40..44], b"MSHT");
+    }
+
+    #[test]
+    fn declared_size_limit() {
+        // 2MiB profile, as a varint.
+        let stream = vec![0x80, 0x80, 0x80, 0x01, 0];
+        assert!(matches!(
+            read_icc(stream.clone(), 1 << 20),
+            Err(Error::IccTooLarge)
+        ));
+        assert!(matches!(
+            read_icc(stream, 2 << 20),
+            Err(Error::IccEndOfStream)
+        ));
+    }
+}
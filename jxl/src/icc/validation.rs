@@ -0,0 +1,580 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Checks of ICC profiles against the rules of the ICC specification that strict consumers
+//! enforce.
+
+use std::fmt;
+
+use crate::api::compute_md5;
+
+use super::ICC_HEADER_SIZE;
+
+/// The PCS illuminant, D50, as s15Fixed16 values.
+const D50: [u32; 3] = [0x0000F6D6, 0x00010000, 0x0000D32D];
+
+/// A violation of the ICC specification found in a profile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IccIssue {
+    /// The profile is too short to hold a header and a tag table.
+    Truncated { size: usize },
+    /// The profile size field does not match the size of the profile.
+    SizeMismatch { declared: u32, actual: usize },
+    /// The profile size is not a multiple of 4, although every tag must be padded to 4 bytes.
+    UnpaddedProfile { size: usize },
+    /// The header does not hold the `acsp` file signature.
+    MissingSignature,
+    /// The profile version is neither 2.x nor 4.x.
+    UnsupportedVersion { major: u8 },
+    /// The profile/device class is not one defined by the specification.
+    UnknownClass { class: [u8; 4] },
+    /// The data color space is not one defined by the specification.
+    UnknownColorSpace { color_space: [u8; 4] },
+    /// The profile connection space is neither `XYZ ` nor `Lab `.
+    UnknownPcs { pcs: [u8; 4] },
+    /// The creation date and time are not valid.
+    InvalidDate,
+    /// The rendering intent is not one of the four defined ones.
+    InvalidRenderingIntent { intent: u32 },
+    /// The PCS illuminant is not D50.
+    IlluminantNotD50,
+    /// The profile ID is not the MD5 hash of the profile.
+    ProfileIdMismatch,
+    /// The reserved bytes at the end of the header are not zero.
+    NonZeroReservedHeader,
+    /// A tag appears several times in the tag table.
+    DuplicateTag { tag: [u8; 4] },
+    /// The data of a tag is not inside the tagged element data of the profile.
+    TagOutOfBounds { tag: [u8; 4] },
+    /// The data of a tag does not start at a multiple of 4 bytes.
+    UnalignedTag { tag: [u8; 4] },
+    /// The data of two tags overlaps without being shared.
+    OverlappingTags { tag: [u8; 4], other: [u8; 4] },
+    /// The padding after the data of a tag is not zero.
+    NonZeroPadding { tag: [u8; 4] },
+    /// A tag that the profile class requires is missing.
+    MissingTag { tag: [u8; 4] },
+    /// The data of a tag has a type that is not allowed for the tag.
+    UnexpectedTagType { tag: [u8; 4], tag_type: [u8; 4] },
+    /// The data of a tag is not consistent with its size or with its type.
+    MalformedTag { tag: [u8; 4] },
+    /// A `curv` table decreases.
+    NonMonotonicCurve { tag: [u8; 4] },
+    /// The media white point of a display profile is not the PCS illuminant.
+    WhitePointNotD50,
+}
+
+/// Formats a tag or type signature, which is made of four ASCII characters.
+fn signature(sig: &[u8; 4]) -> String {
+    sig.iter()
+        .map(|&c| {
+            if c.is_ascii_graphic() || c == b' ' {
+                c as char
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
+impl fmt::Display for IccIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IccIssue::Truncated { size } => write!(f, "profile of {size} bytes is truncated"),
+            IccIssue::SizeMismatch { declared, actual } => write!(
+                f,
+                "profile size is {declared} bytes in the header, but {actual} bytes long"
+            ),
+            IccIssue::UnpaddedProfile { size } => {
+                write!(f, "profile size {size} is not a multiple of 4")
+            }
+            IccIssue::MissingSignature => f.write_str("missing 'acsp' file signature"),
+            IccIssue::UnsupportedVersion { major } => {
+                write!(f, "unsupported profile version {major}")
+            }
+            IccIssue::UnknownClass { class } => {
+                write!(f, "unknown profile class '{}'", signature(class))
+            }
+            IccIssue::UnknownColorSpace { color_space } => {
+                write!(f, "unknown data color space '{}'", signature(color_space))
+            }
+            IccIssue::UnknownPcs { pcs } => {
+                write!(f, "unknown connection space '{}'", signature(pcs))
+            }
+            IccIssue::InvalidDate => f.write_str("invalid creation date"),
+            IccIssue::InvalidRenderingIntent { intent } => {
+                write!(f, "invalid rendering intent {intent}")
+            }
+            IccIssue::IlluminantNotD50 => f.write_str("PCS illuminant is not D50"),
+            IccIssue::ProfileIdMismatch => f.write_str("profile ID is not the profile MD5"),
+            IccIssue::NonZeroReservedHeader => f.write_str("reserved header bytes are not zero"),
+            IccIssue::DuplicateTag { tag } => write!(f, "duplicate tag '{}'", signature(tag)),
+            IccIssue::TagOutOfBounds { tag } => {
+                write!(f, "tag '{}' is out of bounds", signature(tag))
+            }
+            IccIssue::UnalignedTag { tag } => {
+                write!(f, "tag '{}' is not 4-byte aligned", signature(tag))
+            }
+            IccIssue::OverlappingTags { tag, other } => write!(
+                f,
+                "tags '{}' and '{}' overlap",
+                signature(tag),
+                signature(other)
+            ),
+            IccIssue::NonZeroPadding { tag } => {
+                write!(f, "padding of tag '{}' is not zero", signature(tag))
+            }
+            IccIssue::MissingTag { tag } => {
+                write!(f, "missing required tag '{}'", signature(tag))
+            }
+            IccIssue::UnexpectedTagType { tag, tag_type } => write!(
+                f,
+                "tag '{}' has unexpected type '{}'",
+                signature(tag),
+                signature(tag_type)
+            ),
+            IccIssue::MalformedTag { tag } => write!(f, "tag '{}' is malformed", signature(tag)),
+            IccIssue::NonMonotonicCurve { tag } => {
+                write!(f, "curve of tag '{}' is not monotonic", signature(tag))
+            }
+            IccIssue::WhitePointNotD50 => {
+                f.write_str("media white point of a display profile is not D50")
+            }
+        }
+    }
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([data[pos], data[pos + 1]])
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn read_sig(data: &[u8], pos: usize) -> [u8; 4] {
+    [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]
+}
+
+struct TagEntry {
+    signature: [u8; 4],
+    offset: usize,
+    size: usize,
+}
+
+/// Returns the tag types allowed for a tag, or `None` for tags that are not checked.
+fn allowed_types(tag: &[u8; 4], major_version: u8) -> Option<&'static [&'static [u8; 4]]> {
+    Some(match tag {
+        b"desc" if major_version >= 4 => &[b"mluc"],
+        b"desc" => &[b"desc"],
+        b"cprt" if major_version >= 4 => &[b"mluc"],
+        b"cprt" => &[b"text"],
+        b"wtpt" | b"bkpt" | b"lumi" | b"rXYZ" | b"gXYZ" | b"bXYZ" => &[b"XYZ "],
+        b"chad" => &[b"sf32"],
+        b"rTRC" | b"gTRC" | b"bTRC" | b"kTRC" => &[b"curv", b"para"],
+        b"A2B0" | b"A2B1" | b"A2B2" => &[b"mAB ", b"mft1", b"mft2"],
+        b"B2A0" | b"B2A1" | b"B2A2" => &[b"mBA ", b"mft1", b"mft2"],
+        b"cicp" => &[b"cicp"],
+        _ => return None,
+    })
+}
+
+/// Checks the content of the data of a tag of a known type, returning the issues found.
+fn check_tag_data(tag: &[u8; 4], data: &[u8], issues: &mut Vec<IccIssue>) {
+    let malformed = |issues: &mut Vec<IccIssue>| issues.push(IccIssue::MalformedTag { tag: *tag });
+    if data.len() < 8 {
+        malformed(issues);
+        return;
+    }
+    let tag_type = read_sig(data, 0);
+    let well_formed = read_u32(data, 4) == 0
+        && match &tag_type {
+            b"XYZ " => data.len() >= 20 && (data.len() - 8).is_multiple_of(12),
+            b"sf32" if tag == b"chad" => data.len() == 44,
+            b"sf32" => (data.len() - 8).is_multiple_of(4),
+            b"cicp" => data.len() == 12,
+            b"curv" => data.len() >= 12 && data.len() == 12 + 2 * read_u32(data, 8) as usize,
+            b"para" => {
+                data.len() >= 12
+                    && [1, 3, 4, 5, 7]
+                        .get(read_u16(data, 8) as usize)
+                        .is_some_and(|num_params| data.len() == 12 + 4 * num_params)
+            }
+            b"mluc" => {
+                data.len() >= 16 && {
+                    let num_records = read_u32(data, 8) as usize;
+                    let record_size = read_u32(data, 12) as usize;
+                    record_size == 12
+                        && num_records
+                            .checked_mul(12)
+                            .is_some_and(|size| 16 + size <= data.len())
+                        && (0..num_records).all(|i| {
+                            let record = 16 + 12 * i;
+                            let length = read_u32(data, record + 4) as usize;
+                            let offset = read_u32(data, record + 8) as usize;
+                            length.is_multiple_of(2)
+                                && offset
+                                    .checked_add(length)
+                                    .is_some_and(|end| end <= data.len())
+                        })
+                }
+            }
+            _ => true,
+        };
+    if !well_formed {
+        malformed(issues);
+        return;
+    }
+    if &tag_type == b"curv" {
+        let count = read_u32(data, 8) as usize;
+        if count >= 2 {
+            let entries: Vec<u16> = (0..count).map(|i| read_u16(data, 12 + 2 * i)).collect();
+            if entries.windows(2).any(|w| w[1] < w[0]) {
+                issues.push(IccIssue::NonMonotonicCurve { tag: *tag });
+            }
+        }
+    }
+}
+
+/// Checks `icc` against the rules of the ICC specification about the header, the layout of the
+/// tags, the types of the tags this crate knows and the tags that each profile class requires,
+/// and returns the issues that were found.
+pub fn validate(icc: &[u8]) -> Vec<IccIssue> {
+    let mut issues = vec![];
+    if icc.len() < ICC_HEADER_SIZE as usize + 4 {
+        issues.push(IccIssue::Truncated { size: icc.len() });
+        return issues;
+    }
+
+    // Header.
+    let declared_size = read_u32(icc, 0);
+    if declared_size as usize != icc.len() {
+        issues.push(IccIssue::SizeMismatch {
+            declared: declared_size,
+            actual: icc.len(),
+        });
+    }
+    let major_version = icc[8];
+    if major_version != 2 && major_version != 4 {
+        issues.push(IccIssue::UnsupportedVersion {
+            major: major_version,
+        });
+    }
+    if major_version >= 4 && !icc.len().is_multiple_of(4) {
+        issues.push(IccIssue::UnpaddedProfile { size: icc.len() });
+    }
+    let class = read_sig(icc, 12);
+    if ![
+        b"scnr", b"mntr", b"prtr", b"link", b"spac", b"abst", b"nmcl",
+    ]
+    .contains(&&class)
+    {
+        issues.push(IccIssue::UnknownClass { class });
+    }
+    let color_space = read_sig(icc, 16);
+    let known_color_space = matches!(
+        &color_space,
+        b"XYZ "
+            | b"Lab "
+            | b"Luv "
+            | b"YCbr"
+            | b"Yxy "
+            | b"RGB "
+            | b"GRAY"
+            | b"HSV "
+            | b"HLS "
+            | b"CMYK"
+            | b"CMY "
+    ) || (color_space[1..] == *b"CLR"
+        && matches!(color_space[0], b'2'..=b'9' | b'A'..=b'F'));
+    if !known_color_space {
+        issues.push(IccIssue::UnknownColorSpace { color_space });
+    }
+    let pcs = read_sig(icc, 20);
+    // Device links have a data color space as connection space.
+    if &class != b"link" && &pcs != b"XYZ " && &pcs != b"Lab " {
+        issues.push(IccIssue::UnknownPcs { pcs });
+    }
+    let date: Vec<u16> = (0..6).map(|i| read_u16(icc, 24 + 2 * i)).collect();
+    if !(1..=12).contains(&date[1])
+        || !(1..=31).contains(&date[2])
+        || date[3] > 23
+        || date[4] > 59
+        || date[5] > 59
+    {
+        issues.push(IccIssue::InvalidDate);
+    }
+    if &read_sig(icc, 36) != b"acsp" {
+        issues.push(IccIssue::MissingSignature);
+    }
+    let intent = read_u32(icc, 64);
+    if intent > 3 {
+        issues.push(IccIssue::InvalidRenderingIntent { intent });
+    }
+    if (0..3).any(|i| read_u32(icc, 68 + 4 * i) != D50[i]) {
+        issues.push(IccIssue::IlluminantNotD50);
+    }
+    if icc[84..100].iter().any(|&b| b != 0) {
+        let mut zeroed = icc.to_vec();
+        zeroed[44..48].fill(0);
+        zeroed[64..68].fill(0);
+        zeroed[84..100].fill(0);
+        if compute_md5(&zeroed) != icc[84..100] {
+            issues.push(IccIssue::ProfileIdMismatch);
+        }
+    }
+    if icc[100..ICC_HEADER_SIZE as usize].iter().any(|&b| b != 0) {
+        issues.push(IccIssue::NonZeroReservedHeader);
+    }
+
+    // Tag table.
+    let num_tags = read_u32(icc, 128) as usize;
+    let tag_table_end = num_tags
+        .checked_mul(12)
+        .and_then(|size| size.checked_add(132))
+        .filter(|&end| end <= icc.len());
+    let Some(tag_table_end) = tag_table_end else {
+        issues.push(IccIssue::Truncated { size: icc.len() });
+        return issues;
+    };
+    let mut tags: Vec<TagEntry> = vec![];
+    for i in 0..num_tags {
+        let entry = 132 + 12 * i;
+        let tag = TagEntry {
+            signature: read_sig(icc, entry),
+            offset: read_u32(icc, entry + 4) as usize,
+            size: read_u32(icc, entry + 8) as usize,
+        };
+        if tags.iter().any(|t| t.signature == tag.signature) {
+            issues.push(IccIssue::DuplicateTag { tag: tag.signature });
+            continue;
+        }
+        if tag.offset < tag_table_end
+            || tag
+                .offset
+                .checked_add(tag.size)
+                .is_none_or(|end| end > icc.len())
+        {
+            issues.push(IccIssue::TagOutOfBounds { tag: tag.signature });
+            continue;
+        }
+        if !tag.offset.is_multiple_of(4) {
+            issues.push(IccIssue::UnalignedTag { tag: tag.signature });
+        }
+        tags.push(tag);
+    }
+
+    for (i, tag) in tags.iter().enumerate() {
+        // Tags may share their data, but not overlap partially.
+        for other in &tags[..i] {
+            let shared = other.offset == tag.offset && other.size == tag.size;
+            if !shared
+                && tag.offset < other.offset + other.size
+                && other.offset < tag.offset + tag.size
+            {
+                issues.push(IccIssue::OverlappingTags {
+                    tag: tag.signature,
+                    other: other.signature,
+                });
+            }
+        }
+        let end = tag.offset + tag.size;
+        let padding_end = end.next_multiple_of(4).min(icc.len());
+        let padding_is_data = tags
+            .iter()
+            .any(|t| t.offset < padding_end && end < t.offset + t.size);
+        if !padding_is_data && icc[end..padding_end].iter().any(|&b| b != 0) {
+            issues.push(IccIssue::NonZeroPadding { tag: tag.signature });
+        }
+        let data = &icc[tag.offset..end];
+        if let Some(types) = allowed_types(&tag.signature, major_version) {
+            if data.len() < 4 {
+                issues.push(IccIssue::MalformedTag { tag: tag.signature });
+                continue;
+            }
+            let tag_type = read_sig(data, 0);
+            if !types.contains(&&tag_type) {
+                issues.push(IccIssue::UnexpectedTagType {
+                    tag: tag.signature,
+                    tag_type,
+                });
+                continue;
+            }
+            check_tag_data(&tag.signature, data, &mut issues);
+        }
+    }
+
+    // Required tags.
+    let find = |sig: &[u8; 4]| tags.iter().find(|t| t.signature == *sig);
+    let has = |sig: &[u8; 4]| find(sig).is_some();
+    let mut required: Vec<&[u8; 4]> = vec![];
+    if &class != b"link" {
+        required.extend([b"desc", b"cprt", b"wtpt"]);
+    }
+    let input_or_display = &class == b"scnr" || &class == b"mntr";
+    // Display profiles with lookup tables need both directions.
+    let luts: &[&[u8; 4]] = if &class == b"mntr" {
+        &[b"A2B0", b"B2A0"]
+    } else {
+        &[b"A2B0"]
+    };
+    if input_or_display && !luts.iter().all(|sig| has(sig)) {
+        match &color_space {
+            b"RGB " => required.extend([b"rXYZ", b"gXYZ", b"bXYZ", b"rTRC", b"gTRC", b"bTRC"]),
+            b"GRAY" => required.push(b"kTRC"),
+            _ => required.extend(luts),
+        }
+    }
+    for sig in required {
+        if !has(sig) {
+            issues.push(IccIssue::MissingTag { tag: *sig });
+        }
+    }
+
+    if major_version >= 4 && &class == b"mntr" {
+        let white_point = find(b"wtpt").map(|t| &icc[t.offset..t.offset + t.size]);
+        if let Some(data) = white_point.filter(|data| data.len() >= 20 && &data[0..4] == b"XYZ ") {
+            // Allow for the rounding of values that were converted from floating point.
+            if (0..3).any(|i| (read_u32(data, 8 + 4 * i) as i32 - D50[i] as i32).abs() > 1) {
+                issues.push(IccIssue::WhitePointNotD50);
+            }
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{
+        JxlColorEncoding, JxlPrimaries, JxlTransferFunction, JxlWhitePoint, RenderingIntent,
+    };
+
+    fn all_encodings() -> Vec<JxlColorEncoding> {
+        let white_points = [
+            JxlWhitePoint::D65,
+            JxlWhitePoint::E,
+            JxlWhitePoint::DCI,
+            JxlWhitePoint::Chromaticity {
+                wx: 0.3457,
+                wy: 0.3585,
+            },
+        ];
+        let primaries = [
+            JxlPrimaries::SRGB,
+            JxlPrimaries::BT2100,
+            JxlPrimaries::P3,
+            JxlPrimaries::Chromaticities {
+                rx: 0.7347,
+                ry: 0.2653,
+                gx: 0.1596,
+                gy: 0.8404,
+                bx: 0.0366,
+                by: 0.0001,
+            },
+        ];
+        let transfer_functions = [
+            JxlTransferFunction::BT709,
+            JxlTransferFunction::Linear,
+            JxlTransferFunction::SRGB,
+            JxlTransferFunction::PQ,
+            JxlTransferFunction::DCI,
+            JxlTransferFunction::HLG,
+            JxlTransferFunction::Gamma(0.4),
+        ];
+        let intents = [
+            RenderingIntent::Perceptual,
+            RenderingIntent::Relative,
+            RenderingIntent::Saturation,
+            RenderingIntent::Absolute,
+        ];
+        let mut encodings = vec![JxlColorEncoding::XYB {
+            rendering_intent: RenderingIntent::Perceptual,
+        }];
+        for white_point in &white_points {
+            for transfer_function in &transfer_functions {
+                for rendering_intent in intents {
+                    encodings.push(JxlColorEncoding::GrayscaleColorSpace {
+                        white_point: white_point.clone(),
+                        transfer_function: transfer_function.clone(),
+                        rendering_intent,
+                    });
+                    for primaries in &primaries {
+                        encodings.push(JxlColorEncoding::RgbColorSpace {
+                            white_point: white_point.clone(),
+                            primaries: primaries.clone(),
+                            transfer_function: transfer_function.clone(),
+                            rendering_intent,
+                        });
+                    }
+                }
+            }
+        }
+        encodings
+    }
+
+    #[test]
+    fn generated_profiles_are_valid() {
+        for encoding in all_encodings() {
+            let icc = encoding.maybe_create_profile().unwrap().unwrap();
+            assert_eq!(
+                validate(&icc),
+                vec![],
+                "{}",
+                encoding.get_color_encoding_description()
+            );
+        }
+    }
+
+    #[test]
+    fn detects_issues() {
+        let icc = JxlColorEncoding::srgb(false)
+            .maybe_create_profile()
+            .unwrap()
+            .unwrap();
+        let corrupt = |f: &dyn Fn(&mut Vec<u8>)| {
+            let mut icc = icc.clone();
+            f(&mut icc);
+            validate(&icc)
+        };
+        assert_eq!(
+            corrupt(&|icc| icc[36] = b'x'),
+            [IccIssue::MissingSignature, IccIssue::ProfileIdMismatch]
+        );
+        // The profile ID does not cover the rendering intent.
+        assert_eq!(
+            corrupt(&|icc| icc[67] = 7),
+            [IccIssue::InvalidRenderingIntent { intent: 7 }]
+        );
+        assert!(corrupt(&|icc| icc.truncate(200)).contains(&IccIssue::Truncated { size: 200 }));
+        // Replaces the data of the tone curves, which is shared by the three channels, with a
+        // decreasing table.
+        let issues = corrupt(&|icc| {
+            icc[84..100].fill(0);
+            let num_tags = read_u32(icc, 128) as usize;
+            for entry in (0..num_tags).map(|i| 132 + 12 * i) {
+                if &icc[entry + 1..entry + 4] == b"TRC" {
+                    let offset = read_u32(icc, entry + 4) as usize;
+                    icc[entry + 8..entry + 12].copy_from_slice(&16u32.to_be_bytes());
+                    icc[offset..offset + 16].copy_from_slice(b"curv\0\0\0\0\0\0\0\x02\xff\xff\0\0");
+                }
+            }
+        });
+        assert!(issues.contains(&IccIssue::NonMonotonicCurve { tag: *b"rTRC" }));
+        assert!(!issues.contains(&IccIssue::ProfileIdMismatch));
+        let issues = corrupt(&|icc| {
+            let num_tags = read_u32(icc, 128) as usize;
+            for entry in (0..num_tags).map(|i| 132 + 12 * i) {
+                if &icc[entry..entry + 4] == b"cprt" {
+                    icc[entry..entry + 4].copy_from_slice(b"desc");
+                }
+            }
+        });
+        assert!(issues.contains(&IccIssue::DuplicateTag { tag: *b"desc" }));
+        assert!(issues.contains(&IccIssue::MissingTag { tag: *b"cprt" }));
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    alloc::{Layout, alloc, alloc_zeroed, dealloc},
+    cell::RefCell,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+/// Source of the memory of the image buffers of a decoder, set with
+/// [`JxlDecoderOptions::allocator`](crate::api::JxlDecoderOptions::allocator).
+///
+/// Image buffers hold all the large allocations of decoding: pixels, coefficients, reference
+/// frames and modular channels. Headers, entropy codes and other small allocations stay on the
+/// global allocator.
+///
+/// # Safety
+/// `allocate` must return either null or a pointer to `layout.size()` bytes aligned to
+/// `layout.align()`, which stay valid and are not used by anything else until they are passed
+/// to `deallocate`, like [`std::alloc::GlobalAlloc`].
+pub unsafe trait JxlAllocator: Send + Sync {
+    /// Allocates memory for `layout`, whose size is never 0, or returns null on failure.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Deallocates memory.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` on this allocator with the same `layout`, and
+    /// not have been deallocated yet.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+}
+
+/// Bytes of image buffers that a decoder allocated while processing, from its
+/// [`JxlAllocator`] or the global allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JxlMemoryUsage {
+    /// Bytes that are currently allocated.
+    pub current_bytes: usize,
+    /// Largest number of bytes that were allocated at the same time.
+    pub peak_bytes: usize,
+}
+
+#[derive(Default)]
+struct MemoryCounters {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+/// Allocator of the image buffers of a decoder, which accounts for the memory it hands out.
+/// Images keep the allocator they come from, so they can outlive the decoding call that
+/// allocated them.
+#[derive(Clone)]
+pub(crate) struct ImageAllocator {
+    allocator: Option<Arc<dyn JxlAllocator>>,
+    counters: Arc<MemoryCounters>,
+}
+
+thread_local! {
+    static THREAD_ALLOCATOR: RefCell<Option<ImageAllocator>> = const { RefCell::new(None) };
+}
+
+/// Makes the image buffers allocated on the current thread come from `allocator`, or from the
+/// global allocator without accounting if `None`.
+pub(crate) fn set_thread_allocator(allocator: Option<ImageAllocator>) {
+    THREAD_ALLOCATOR.with(|a| *a.borrow_mut() = allocator);
+}
+
+/// Returns the allocator of the image buffers allocated on the current thread.
+pub(super) fn thread_allocator() -> Option<ImageAllocator> {
+    THREAD_ALLOCATOR.with(|a| a.borrow().clone())
+}
+
+impl ImageAllocator {
+    pub(crate) fn new(allocator: Option<Arc<dyn JxlAllocator>>) -> Self {
+        Self {
+            allocator,
+            counters: Arc::default(),
+        }
+    }
+
+    pub(crate) fn usage(&self) -> JxlMemoryUsage {
+        JxlMemoryUsage {
+            current_bytes: self.counters.current.load(Ordering::Relaxed),
+            peak_bytes: self.counters.peak.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Allocates memory for `layout`, zeroed unless `uninit` is set, or returns null.
+    ///
+    /// # Safety
+    /// `layout` must have a non-zero size.
+    pub(super) unsafe fn allocate(&self, layout: Layout, uninit: bool) -> *mut u8 {
+        let memory = match &self.allocator {
+            Some(allocator) => {
+                let memory = allocator.allocate(layout);
+                if !memory.is_null() && !uninit {
+                    // SAFETY: the allocator returned `layout.size()` writable bytes.
+                    unsafe { memory.write_bytes(0, layout.size()) };
+                }
+                memory
+            }
+            // SAFETY: the caller guarantees that the size is not 0.
+            None if uninit => unsafe { alloc(layout) },
+            // SAFETY: the caller guarantees that the size is not 0.
+            None => unsafe { alloc_zeroed(layout) },
+        };
+        if !memory.is_null() {
+            let current = self
+                .counters
+                .current
+                .fetch_add(layout.size(), Ordering::Relaxed)
+                + layout.size();
+            self.counters.peak.fetch_max(current, Ordering::Relaxed);
+        }
+        memory
+    }
+
+    /// Deallocates memory.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` on this allocator with the same `layout`.
+    pub(super) unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        self.counters
+            .current
+            .fetch_sub(layout.size(), Ordering::Relaxed);
+        match &self.allocator {
+            // SAFETY: guaranteed by the caller.
+            Some(allocator) => unsafe { allocator.deallocate(ptr, layout) },
+            // SAFETY: guaranteed by the caller, since `allocate` used the global allocator.
+            None => unsafe { dealloc(ptr, layout) },
+        }
+    }
+}
@@ -6,6 +6,7 @@
 use std::fmt::Debug;
 
 mod private {
+    #[allow(unnameable_types)]
     pub trait Sealed {}
 }
 
@@ -15,7 +15,7 @@ use crate::{
     util::{CACHE_LINE_BYTE_SIZE, tracing_wrappers::*},
 };
 
-use super::Rect;
+use super::{Rect, allocator::ImageAllocator};
 
 #[derive(Debug, Clone, Copy)]
 pub(super) struct RawImageBuffer {
@@ -200,8 +200,12 @@ impl RawImageBuffer {
     /// Returns zeroed memory if `uninit` is `false`, otherwise it returns uninitialized
     /// memory. The returned buffer is aligned to CACHE_LINE_BYTE_SIZE bytes.
     /// The returned RawImageBuffer owns the memory it references, which belongs to a single
-    /// allocation of size minimum_allocation_size().
-    pub(super) fn try_allocate(byte_size: (usize, usize), uninit: bool) -> Result<RawImageBuffer> {
+    /// allocation of size minimum_allocation_size(), from `allocator` or else the global one.
+    pub(super) fn try_allocate(
+        byte_size: (usize, usize),
+        uninit: bool,
+        allocator: Option<&ImageAllocator>,
+    ) -> Result<RawImageBuffer> {
         let (bytes_per_row, num_rows) = byte_size;
         // To simplify modular transform logic, we allow empty images, because some modular
         // meta-images can have 0 bytes_per_row or num_rows (e.g. delta-palette, reference property image).
@@ -225,10 +229,10 @@ impl RawImageBuffer {
         let layout = Layout::from_size_align(allocation_len, CACHE_LINE_BYTE_SIZE).unwrap();
         // SAFETY: we just checked that allocation_len is not 0.
         let memory = unsafe {
-            if uninit {
-                alloc(layout)
-            } else {
-                alloc_zeroed(layout)
+            match allocator {
+                Some(allocator) => allocator.allocate(layout, uninit),
+                None if uninit => alloc(layout),
+                None => alloc_zeroed(layout),
             }
         };
         if memory.is_null() {
@@ -247,7 +251,8 @@ impl RawImageBuffer {
     }
 
     /// Returns a copy of the current buffer contents in a new buffer that owns the returned data.
-    /// The data is allocated with `try_allocate` so that it matches the size of the current image.
+    /// The data is allocated with `try_allocate`, from `allocator`, so that it matches the size of
+    /// the current image.
     ///
     /// This function is meant to be used when `self` is an owned buffer, and will panic if the
     /// bytes between rows of a newly allocated image with the same size does not match the value
@@ -257,8 +262,8 @@ impl RawImageBuffer {
     /// The caller must ensure that the data referenced by self -- *all*
     /// self.minimum_allocation_size() bytes starting from self.buf, not just the accessible bytes
     /// -- can be read.
-    pub(super) unsafe fn try_clone(&self) -> Result<Self> {
-        let out = RawImageBuffer::try_allocate(self.byte_size(), true)?;
+    pub(super) unsafe fn try_clone(&self, allocator: Option<&ImageAllocator>) -> Result<Self> {
+        let out = RawImageBuffer::try_allocate(self.byte_size(), true, allocator)?;
         assert_eq!(self.bytes_per_row, out.bytes_per_row);
         assert_eq!(self.bytes_between_rows, out.bytes_between_rows);
         assert_eq!(self.num_rows, out.num_rows);
@@ -280,14 +285,19 @@ impl RawImageBuffer {
     /// Deallocates an owning buffer that was allocated by try_allocate.
     ///
     /// # Safety
-    /// The data referenced by `self` must have been allocated with Self::try_allocate.
-    pub(super) unsafe fn deallocate(&mut self) {
+    /// The data referenced by `self` must have been allocated with Self::try_allocate, with the
+    /// same `allocator`.
+    pub(super) unsafe fn deallocate(&mut self, allocator: Option<&ImageAllocator>) {
         if !self.buf.is_null() {
             let allocation_len = self.minimum_allocation_size();
             let layout = Layout::from_size_align(allocation_len, CACHE_LINE_BYTE_SIZE).unwrap();
-            // SAFETY: the buffer was allocated in `try_allocate` with the same layout.
+            // SAFETY: the buffer was allocated in `try_allocate` with the same layout and
+            // allocator.
             unsafe {
-                dealloc(self.buf as *mut u8, layout);
+                match allocator {
+                    Some(allocator) => allocator.deallocate(self.buf as *mut u8, layout),
+                    None => dealloc(self.buf as *mut u8, layout),
+                }
             }
         }
     }
@@ -5,6 +5,7 @@
 
 #![allow(unsafe_code)]
 
+mod allocator;
 mod data_type;
 mod internal;
 mod output_buffer;
@@ -14,9 +15,13 @@ mod rect;
 mod test;
 mod typed;
 
+pub(crate) use allocator::{ImageAllocator, set_thread_allocator};
+pub use allocator::{JxlAllocator, JxlMemoryUsage};
 pub use data_type::DataTypeTag;
 pub use data_type::ImageDataType;
 pub use output_buffer::JxlOutputBuffer;
 pub use raw::{OwnedRawImage, RawImageRect, RawImageRectMut};
 pub use rect::Rect;
 pub use typed::{Image, ImageRect, ImageRectMut};
+
+pub use crate::util::f16;
@@ -119,6 +119,16 @@ impl<'a> JxlOutputBuffer<'a> {
         unsafe { self.inner.row_mut(row) }
     }
 
+    /// # Safety
+    /// The caller must guarantee that every byte of the row has been written.
+    pub(crate) unsafe fn initialized_row(&self, row: usize) -> &[u8] {
+        // SAFETY: we have shared access to the data through `&self`.
+        let row = unsafe { self.inner.row(row) };
+        // SAFETY: the caller guarantees that the row is initialized, and `MaybeUninit<u8>` has
+        // the same layout as `u8`.
+        unsafe { std::slice::from_raw_parts(row.as_ptr().cast::<u8>(), row.len()) }
+    }
+
     #[inline]
     pub fn write_bytes(&mut self, row: usize, col: usize, bytes: &[u8]) {
         // SAFETY: We never use the returned slice to write uninit data, and we have write access
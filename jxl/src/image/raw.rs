@@ -7,16 +7,23 @@ use std::{fmt::Debug, marker::PhantomData};
 
 use crate::{error::Result, util::CACHE_LINE_BYTE_SIZE};
 
-use super::{Rect, internal::RawImageBuffer};
+use super::{
+    Rect,
+    allocator::{ImageAllocator, thread_allocator},
+    internal::RawImageBuffer,
+};
 
 pub struct OwnedRawImage {
     // Safety invariant: all the accessible bytes of `self.data` are initialized, and
     // belongs to a single allocation that lives until `self` is dropped.
-    // The data referenced by self.data was allocated by RawImageBuffer::try_allocate.
+    // The data referenced by self.data was allocated by RawImageBuffer::try_allocate, with
+    // `self.allocator`.
     // `data.is_aligned(CACHE_LINE_BYTE_SIZE)` is true.
     pub(super) data: RawImageBuffer,
     offset: (usize, usize),
     padding: (usize, usize),
+    // The allocator of the decoder this image was allocated for, if any.
+    allocator: Option<ImageAllocator>,
 }
 
 impl OwnedRawImage {
@@ -34,15 +41,18 @@ impl OwnedRawImage {
         if !(padding.0 + byte_size.0).is_multiple_of(CACHE_LINE_BYTE_SIZE) {
             padding.0 += CACHE_LINE_BYTE_SIZE - (padding.0 + byte_size.0) % CACHE_LINE_BYTE_SIZE;
         }
+        let allocator = thread_allocator();
         Ok(Self {
             // Safety note: the returned memory is initialized and part of a single allocation of
             // the correct length.
             data: RawImageBuffer::try_allocate(
                 (byte_size.0 + padding.0, byte_size.1 + padding.1),
                 false,
+                allocator.as_ref(),
             )?,
             offset,
             padding,
+            allocator,
         })
     }
 
@@ -122,9 +132,10 @@ impl OwnedRawImage {
             // SAFETY: we own the data that self.data references, so it is all accessible.
             // Moreover, it is initialized and try_clone creates a copy, so the resulting data is
             // owned and initialized.
-            data: unsafe { self.data.try_clone()? },
+            data: unsafe { self.data.try_clone(self.allocator.as_ref())? },
             offset: self.offset,
             padding: self.padding,
+            allocator: self.allocator.clone(),
         })
     }
 }
@@ -132,9 +143,9 @@ impl OwnedRawImage {
 impl Drop for OwnedRawImage {
     fn drop(&mut self) {
         // SAFETY: we own the data referenced by self.data, and it was allocated by
-        // RawImageBuffer::try_allocate.
+        // RawImageBuffer::try_allocate with self.allocator.
         unsafe {
-            self.data.deallocate();
+            self.data.deallocate(self.allocator.as_ref());
         }
     }
 }
@@ -205,6 +216,54 @@ impl<'a> RawImageRectMut<'a> {
         }
     }
 
+    /// Splits this rect into the columns before and after byte `x`.
+    pub fn split_at_x(self, x: usize) -> (RawImageRectMut<'a>, RawImageRectMut<'a>) {
+        let (w, h) = self.byte_size();
+        assert!(x <= w);
+        (
+            Self {
+                // Safety note: the two returned rects have disjoint accessible bytes, all of
+                // which are accessible from `self`, which we consume.
+                data: self.data.rect(Rect {
+                    origin: (0, 0),
+                    size: (x, h),
+                }),
+                _ph: PhantomData,
+            },
+            Self {
+                data: self.data.rect(Rect {
+                    origin: (x, 0),
+                    size: (w - x, h),
+                }),
+                _ph: PhantomData,
+            },
+        )
+    }
+
+    /// Splits this rect into the rows before and after row `y`.
+    pub fn split_at_y(self, y: usize) -> (RawImageRectMut<'a>, RawImageRectMut<'a>) {
+        let (w, h) = self.byte_size();
+        assert!(y <= h);
+        (
+            Self {
+                // Safety note: the two returned rects have disjoint accessible bytes, all of
+                // which are accessible from `self`, which we consume.
+                data: self.data.rect(Rect {
+                    origin: (0, 0),
+                    size: (w, y),
+                }),
+                _ph: PhantomData,
+            },
+            Self {
+                data: self.data.rect(Rect {
+                    origin: (0, y),
+                    size: (w, h - y),
+                }),
+                _ph: PhantomData,
+            },
+        )
+    }
+
     pub fn byte_size(&self) -> (usize, usize) {
         self.data.byte_size()
     }
@@ -5,7 +5,7 @@
 
 use super::DataTypeTag;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Rect {
     pub origin: (usize, usize),
     // width, height
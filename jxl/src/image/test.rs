@@ -67,6 +67,83 @@ fn rect_basic() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn split_rect_mut() -> Result<()> {
+    let mut image = Image::<u16>::new_with_padding((37, 21), (3, 2), (5, 4))?;
+    let (top, bottom) = image
+        .get_rect_mut(Rect {
+            origin: (0, 0),
+            size: (37, 21),
+        })
+        .split_at_y(8);
+    let (mut top_left, mut top_right) = top.split_at_x(30);
+    let (mut bottom_left, bottom_right) = bottom.split_at_x(37);
+    assert_eq!(top_left.size(), (30, 8));
+    assert_eq!(top_right.size(), (7, 8));
+    assert_eq!(bottom_left.size(), (37, 13));
+    assert_eq!(bottom_right.size(), (0, 0));
+    // Interleave accesses to check that the halves do not alias.
+    for y in 0..8 {
+        top_left.row(y).fill(1);
+        top_right.row(y).fill(2);
+        assert!(top_left.row(y).iter().all(|&x| x == 1));
+        bottom_left.row(y).fill(3);
+    }
+    for y in 0..21 {
+        let row = image.row(y);
+        if y < 8 {
+            assert!(row[..30].iter().all(|&x| x == 1));
+            assert!(row[30..].iter().all(|&x| x == 2));
+        } else {
+            assert!(row.iter().all(|&x| x == if y < 16 { 3 } else { 0 }));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn split_into_tiles() -> Result<()> {
+    fn assert_send<T: Send>(_: &T) {}
+    let mut image = Image::<f32>::new((10, 7))?;
+    let tiles = image.split_into_tiles_mut(4, 3);
+    assert_send(&tiles);
+    let sizes: Vec<_> = tiles.iter().map(|t| t.size()).collect();
+    assert_eq!(
+        sizes,
+        [
+            (4, 3),
+            (4, 3),
+            (2, 3),
+            (4, 3),
+            (4, 3),
+            (2, 3),
+            (4, 1),
+            (4, 1),
+            (2, 1)
+        ]
+    );
+    std::thread::scope(|s| {
+        for (i, mut tile) in tiles.into_iter().enumerate() {
+            s.spawn(move || {
+                for y in 0..tile.size().1 {
+                    tile.row(y).fill(i as f32);
+                }
+            });
+        }
+    });
+    for y in 0..7 {
+        for x in 0..10 {
+            assert_eq!(image.row(y)[x], ((y / 3) * 3 + x / 4) as f32);
+        }
+    }
+    assert!(
+        Image::<u8>::new((0, 0))?
+            .split_into_tiles_mut(1, 1)
+            .is_empty()
+    );
+    Ok(())
+}
+
 fn f64_conversions<T: ImageDataType + Eq + for<'a> Arbitrary<'a>>() {
     arbtest::arbtest(|u| {
         let t = T::arbitrary(u)?;
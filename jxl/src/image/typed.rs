@@ -101,6 +101,37 @@ impl<T: ImageDataType> Image<T> {
         ImageRect::from_raw(self.raw.get_rect(rect.to_byte_rect(T::DATA_TYPE_ID)))
     }
 
+    /// Splits the image into tiles of at most `tile_w`x`tile_h` pixels, in row-major order.
+    /// The tiles can be mutated independently, for example from different threads:
+    ///
+    /// ```
+    /// use jxl::image::Image;
+    ///
+    /// let mut image = Image::<u8>::new((100, 70)).unwrap();
+    /// std::thread::scope(|s| {
+    ///     for (i, mut tile) in image.split_into_tiles_mut(32, 32).into_iter().enumerate() {
+    ///         s.spawn(move || {
+    ///             for y in 0..tile.size().1 {
+    ///                 tile.row(y).fill(i as u8);
+    ///             }
+    ///         });
+    ///     }
+    /// });
+    /// assert_eq!(image.row(40)[70], 6);
+    /// ```
+    pub fn split_into_tiles_mut(
+        &mut self,
+        tile_w: usize,
+        tile_h: usize,
+    ) -> Vec<ImageRectMut<'_, T>> {
+        let size = self.size();
+        self.get_rect_mut(Rect {
+            origin: (0, 0),
+            size,
+        })
+        .split_into_tiles(tile_w, tile_h)
+    }
+
     pub fn try_clone(&self) -> Result<Self> {
         Ok(Self::from_raw(self.raw.try_clone()?))
     }
@@ -152,7 +183,10 @@ impl<T: ImageDataType> Image<T> {
     /// the row at index 0 will be the first row of the *padding*, unlike with all the other row
     /// accessors.
     #[inline(always)]
-    pub fn distinct_full_rows_mut<I: DistinctRowsIndexes>(&mut self, rows: I) -> I::Output<'_, T> {
+    pub(crate) fn distinct_full_rows_mut<I: DistinctRowsIndexes>(
+        &mut self,
+        rows: I,
+    ) -> I::Output<'_, T> {
         // SAFETY: we don't write uninit data to the returned `rows`, and `self.raw` has ownership
         // of the accessible bytes of `self.raw.data`.
         let rows = unsafe { self.raw.data.distinct_rows_mut(rows) };
@@ -254,6 +288,37 @@ impl<'a, T: ImageDataType> ImageRectMut<'a, T> {
         ImageRect::from_raw(self.raw.as_rect())
     }
 
+    /// Splits this rect into the columns before and after column `x`.
+    pub fn split_at_x(self, x: usize) -> (ImageRectMut<'a, T>, ImageRectMut<'a, T>) {
+        let (left, right) = self.raw.split_at_x(x * T::DATA_TYPE_ID.size());
+        (Self::from_raw(left), Self::from_raw(right))
+    }
+
+    /// Splits this rect into the rows before and after row `y`.
+    pub fn split_at_y(self, y: usize) -> (ImageRectMut<'a, T>, ImageRectMut<'a, T>) {
+        let (top, bottom) = self.raw.split_at_y(y);
+        (Self::from_raw(top), Self::from_raw(bottom))
+    }
+
+    /// Splits this rect into tiles of at most `tile_w`x`tile_h` pixels, in row-major order.
+    pub fn split_into_tiles(self, tile_w: usize, tile_h: usize) -> Vec<ImageRectMut<'a, T>> {
+        assert!(tile_w > 0 && tile_h > 0);
+        let mut tiles = vec![];
+        let mut rest = self;
+        while rest.size().1 > 0 {
+            let h = tile_h.min(rest.size().1);
+            let (mut band, bottom) = rest.split_at_y(h);
+            rest = bottom;
+            while band.size().0 > 0 {
+                let w = tile_w.min(band.size().0);
+                let (tile, right) = band.split_at_x(w);
+                tiles.push(tile);
+                band = right;
+            }
+        }
+        tiles
+    }
+
     pub fn into_raw(self) -> RawImageRectMut<'a> {
         self.raw
     }
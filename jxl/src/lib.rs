@@ -4,21 +4,36 @@
 // license that can be found in the LICENSE file.
 
 #![deny(unsafe_code)]
+// Types of the internal modules that appear in the public API must be re-exported on purpose.
+#![warn(unnameable_types)]
 pub mod api;
-pub mod bit_reader;
-pub mod color;
+mod bit_reader;
+mod capabilities;
+mod color;
 pub mod container;
-pub mod entropy_coding;
+mod entropy_coding;
 pub mod error;
-pub mod features;
-pub mod frame;
-pub mod headers;
-pub mod icc;
+mod features;
+mod frame;
+mod headers;
+mod icc;
 pub mod image;
-pub mod render;
-pub mod util;
+pub mod prelude;
+mod render;
+/// Runtime selection of the instruction set used by SIMD kernels.
+pub mod simd {
+    pub use jxl_simd::{Dispatch, FORCE_SCALAR_ENV, SimdTier};
+}
+#[cfg(test)]
+mod test_utils;
+pub mod time;
+mod util;
+
+pub use capabilities::{Capabilities, capabilities};
 
 // TODO: Move these to a more appropriate location.
+/// Side of the groups of VarDCT frames, and of modular frames with the default
+/// `group_size_shift`. Use `FrameHeader::group_dim` for the groups of a given frame.
 const GROUP_DIM: usize = 256;
 const BLOCK_DIM: usize = 8;
 const BLOCK_SIZE: usize = BLOCK_DIM * BLOCK_DIM;
@@ -0,0 +1,26 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! The types needed to decode an image, for glob imports:
+//!
+//! ```
+//! use jxl::prelude::*;
+//!
+//! # fn print_size(mut input: &[u8]) -> Result<()> {
+//! let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+//! if let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? {
+//!     let (width, height) = decoder.basic_info().size;
+//!     println!("{width}x{height}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub use crate::api::{
+    JxlBasicInfo, JxlBitstreamInput, JxlColorProfile, JxlColorType, JxlDataFormat, JxlDecoder,
+    JxlDecoderOptions, JxlOutputBuffer, JxlPixelFormat, ProcessingResult, states,
+};
+pub use crate::error::{Error, Result};
+pub use crate::image::Image;
@@ -0,0 +1,393 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Blending of single image planes on top of each other, as done when compositing frames.
+
+use crate::image::{ImageRect, ImageRectMut};
+
+/// How [`blit`] combines the samples of the source with the samples of the destination below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    /// `dst = src`.
+    Replace,
+    /// `dst = dst + src`.
+    Add,
+    /// `dst = src + dst * (1 - alpha)`: the source, premultiplied by alpha, is composited over
+    /// the destination. This is also how alpha planes are blended, with themselves as alpha.
+    /// Without alpha, the source replaces the destination.
+    Blend,
+    /// `dst = dst + src * alpha`, or `dst + src` without alpha.
+    AlphaWeightedAdd,
+    /// `dst = dst * src`.
+    Mul,
+}
+
+#[inline]
+fn maybe_clamp(v: f32, clamp: bool) -> f32 {
+    if clamp { v.clamp(0.0, 1.0) } else { v }
+}
+
+/// Blends the row `src` onto the row `dst` of the same length. `alpha` is the alpha of `src`,
+/// and `clamp` clamps alpha, or the source of [`BlendMode::Mul`], to `[0, 1]`.
+pub(crate) fn blend_row(
+    dst: &mut [f32],
+    src: &[f32],
+    mode: BlendMode,
+    alpha: Option<&[f32]>,
+    clamp: bool,
+) {
+    assert_eq!(dst.len(), src.len());
+    if let Some(alpha) = alpha {
+        assert_eq!(alpha.len(), src.len());
+    }
+    match (mode, alpha) {
+        (BlendMode::Replace, _) | (BlendMode::Blend, None) => dst.copy_from_slice(src),
+        (BlendMode::Add, _) | (BlendMode::AlphaWeightedAdd, None) => {
+            for (d, s) in dst.iter_mut().zip(src) {
+                *d += s;
+            }
+        }
+        (BlendMode::Blend, Some(alpha)) => {
+            for ((d, s), a) in dst.iter_mut().zip(src).zip(alpha) {
+                *d = s + *d * (1.0 - maybe_clamp(*a, clamp));
+            }
+        }
+        (BlendMode::AlphaWeightedAdd, Some(alpha)) => {
+            for ((d, s), a) in dst.iter_mut().zip(src).zip(alpha) {
+                *d += s * maybe_clamp(*a, clamp);
+            }
+        }
+        (BlendMode::Mul, _) => {
+            for (d, s) in dst.iter_mut().zip(src) {
+                *d *= maybe_clamp(*s, clamp);
+            }
+        }
+    }
+}
+
+/// Overlap of a source of length `src_len` placed at `offset` in a destination of length
+/// `dst_len`, as the start of the overlap in the destination, its start in the source and its
+/// length, or `None` if they do not overlap.
+pub(crate) fn overlap(
+    dst_len: usize,
+    src_len: usize,
+    offset: isize,
+) -> Option<(usize, usize, usize)> {
+    let (dst_start, src_start) = if offset < 0 {
+        (0, offset.unsigned_abs())
+    } else {
+        (offset as usize, 0)
+    };
+    let len = dst_len
+        .saturating_sub(dst_start)
+        .min(src_len.saturating_sub(src_start));
+    (len > 0).then_some((dst_start, src_start, len))
+}
+
+/// Blends `src` onto `dst`, which must have the same size, with the given `mode`. `alpha` is
+/// the alpha of `src` and must have its size too; `clamp` clamps it to `[0, 1]`, as does the
+/// `clamp` field of the blending info of frames.
+pub fn blit(
+    dst: &mut ImageRectMut<f32>,
+    src: &ImageRect<f32>,
+    mode: BlendMode,
+    alpha: Option<&ImageRect<f32>>,
+    clamp: bool,
+) {
+    assert_eq!(dst.size(), src.size());
+    blit_at(dst, src, (0, 0), mode, alpha, clamp);
+}
+
+/// Like [`blit`], but with the top-left corner of `src` at `offset` in `dst`. Only the part of
+/// `src` that overlaps `dst` is blended, which may be nothing.
+pub fn blit_at(
+    dst: &mut ImageRectMut<f32>,
+    src: &ImageRect<f32>,
+    offset: (isize, isize),
+    mode: BlendMode,
+    alpha: Option<&ImageRect<f32>>,
+    clamp: bool,
+) {
+    if let Some(alpha) = alpha {
+        assert_eq!(alpha.size(), src.size());
+    }
+    let (dst_size, src_size) = (dst.size(), src.size());
+    let (Some((dst_x, src_x, xsize)), Some((dst_y, src_y, ysize))) = (
+        overlap(dst_size.0, src_size.0, offset.0),
+        overlap(dst_size.1, src_size.1, offset.1),
+    ) else {
+        return;
+    };
+    for y in 0..ysize {
+        blend_row(
+            &mut dst.row(dst_y + y)[dst_x..dst_x + xsize],
+            &src.row(src_y + y)[src_x..src_x + xsize],
+            mode,
+            alpha.map(|alpha| &alpha.row(src_y + y)[src_x..src_x + xsize]),
+            clamp,
+        );
+    }
+}
+
+/// Blends each plane of `src` onto the same plane of `dst`, like [`blit`], with the plane
+/// `alpha` of `src`, if any, as the alpha of all of them. All planes must have the same size.
+///
+/// The planes are blended one row at a time, so that the alpha row is read once per row instead
+/// of once per plane, which matters for images with many extra channels.
+pub fn blit_channels(
+    dst: &mut [ImageRectMut<f32>],
+    src: &[ImageRect<f32>],
+    mode: BlendMode,
+    alpha: Option<usize>,
+    clamp: bool,
+) {
+    assert_eq!(dst.len(), src.len());
+    let Some(size) = src.first().map(|src| src.size()) else {
+        return;
+    };
+    for (dst, src) in dst.iter().zip(src) {
+        assert_eq!(dst.size(), size);
+        assert_eq!(src.size(), size);
+    }
+    for y in 0..size.1 {
+        let alpha_row = alpha.map(|alpha| src[alpha].row(y));
+        for (dst, src) in dst.iter_mut().zip(src) {
+            blend_row(dst.row(y), src.row(y), mode, alpha_row, clamp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{Image, Rect};
+    use test_log::test;
+
+    const MODES: [BlendMode; 5] = [
+        BlendMode::Replace,
+        BlendMode::Add,
+        BlendMode::Blend,
+        BlendMode::AlphaWeightedAdd,
+        BlendMode::Mul,
+    ];
+
+    fn image(size: (usize, usize), f: impl Fn(usize, usize) -> f32) -> Image<f32> {
+        let mut image = Image::new(size).unwrap();
+        // Images without columns have no rows either.
+        for y in 0..image.size().1 {
+            for (x, v) in image.row_mut(y).iter_mut().enumerate() {
+                *v = f(x, y);
+            }
+        }
+        image
+    }
+
+    fn full_rect(size: (usize, usize)) -> Rect {
+        Rect {
+            origin: (0, 0),
+            size,
+        }
+    }
+
+    fn expected(mode: BlendMode, d: f32, s: f32, a: Option<f32>, clamp: bool) -> f32 {
+        let a = a.map(|a| if clamp { a.clamp(0.0, 1.0) } else { a });
+        match (mode, a) {
+            (BlendMode::Replace, _) | (BlendMode::Blend, None) => s,
+            (BlendMode::Add, _) | (BlendMode::AlphaWeightedAdd, None) => d + s,
+            (BlendMode::Blend, Some(a)) => s + d * (1.0 - a),
+            (BlendMode::AlphaWeightedAdd, Some(a)) => d + s * a,
+            (BlendMode::Mul, _) => d * if clamp { s.clamp(0.0, 1.0) } else { s },
+        }
+    }
+
+    /// Checks every mode with the source at `offset` in the destination against the formulas,
+    /// including that pixels outside the overlap are untouched.
+    fn check_offset(dst_size: (usize, usize), src_size: (usize, usize), offset: (isize, isize)) {
+        let dst_value = |x: usize, y: usize| 0.25 + 0.01 * (x + 3 * y) as f32;
+        let src_value = |x: usize, y: usize| 1.5 - 0.1 * (2 * x + y) as f32;
+        let alpha_value = |x: usize, y: usize| -0.25 + 0.2 * ((x + y) % 8) as f32;
+        let src = image(src_size, src_value);
+        let alpha = image(src_size, alpha_value);
+        for mode in MODES {
+            for with_alpha in [false, true] {
+                for clamp in [false, true] {
+                    let mut dst = image(dst_size, dst_value);
+                    blit_at(
+                        &mut dst.get_rect_mut(full_rect(dst.size())),
+                        &src.get_rect(full_rect(src.size())),
+                        offset,
+                        mode,
+                        with_alpha
+                            .then(|| alpha.get_rect(full_rect(src_size)))
+                            .as_ref(),
+                        clamp,
+                    );
+                    for y in 0..dst_size.1 {
+                        for x in 0..dst_size.0 {
+                            let d = dst_value(x, y);
+                            let sx = x as isize - offset.0;
+                            let sy = y as isize - offset.1;
+                            let inside = (0..src_size.0 as isize).contains(&sx)
+                                && (0..src_size.1 as isize).contains(&sy);
+                            let want = if inside {
+                                let (sx, sy) = (sx as usize, sy as usize);
+                                let a = with_alpha.then(|| alpha_value(sx, sy));
+                                expected(mode, d, src_value(sx, sy), a, clamp)
+                            } else {
+                                d
+                            };
+                            assert_eq!(
+                                dst.row(y)[x],
+                                want,
+                                "{mode:?} alpha {with_alpha} clamp {clamp} at ({x}, {y})"
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_size() {
+        check_offset((7, 5), (7, 5), (0, 0));
+    }
+
+    #[test]
+    fn inside() {
+        check_offset((9, 8), (4, 3), (2, 4));
+    }
+
+    #[test]
+    fn partial_overlap_at_edges() {
+        for offset in [
+            (-2, 0),
+            (0, -3),
+            (-2, -3),
+            (6, 0),
+            (0, 5),
+            (6, 5),
+            (-1, 6),
+            (7, -2),
+        ] {
+            check_offset((9, 8), (4, 5), offset);
+        }
+    }
+
+    #[test]
+    fn source_larger_than_destination() {
+        check_offset((3, 2), (9, 8), (-4, -3));
+    }
+
+    #[test]
+    fn no_overlap() {
+        for offset in [(-4, 0), (0, -5), (9, 0), (0, 8), (100, -100), (-1000, 1000)] {
+            check_offset((9, 8), (4, 5), offset);
+        }
+    }
+
+    #[test]
+    fn empty_images() {
+        check_offset((0, 0), (4, 5), (0, 0));
+        check_offset((9, 8), (0, 0), (1, 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn blit_requires_same_size() {
+        let mut dst = image((3, 3), |_, _| 0.0);
+        let src = image((2, 3), |_, _| 0.0);
+        blit(
+            &mut dst.get_rect_mut(full_rect((3, 3))),
+            &src.get_rect(full_rect((2, 3))),
+            BlendMode::Replace,
+            None,
+            false,
+        );
+    }
+
+    #[test]
+    fn blit_channels_matches_blit() {
+        let size = (11, 6);
+        let rect = full_rect(size);
+        let channel = |c: usize, offset: f32| {
+            image(size, move |x, y| {
+                offset + 0.05 * ((x + 2 * y + 3 * c) % 9) as f32
+            })
+        };
+        let src: Vec<_> = (0..16).map(|c| channel(c, -0.1)).collect();
+        for mode in MODES {
+            for alpha in [None, Some(0), Some(5)] {
+                for clamp in [false, true] {
+                    let mut fused: Vec<_> = (0..16).map(|c| channel(c, 0.3)).collect();
+                    let mut dst_rects: Vec<_> =
+                        fused.iter_mut().map(|dst| dst.get_rect_mut(rect)).collect();
+                    let src_rects: Vec<_> = src.iter().map(|src| src.get_rect(rect)).collect();
+                    blit_channels(&mut dst_rects, &src_rects, mode, alpha, clamp);
+                    for (c, fused) in fused.iter().enumerate() {
+                        let mut single = channel(c, 0.3);
+                        blit(
+                            &mut single.get_rect_mut(rect),
+                            &src[c].get_rect(rect),
+                            mode,
+                            alpha.map(|alpha| src[alpha].get_rect(rect)).as_ref(),
+                            clamp,
+                        );
+                        for y in 0..size.1 {
+                            assert_eq!(fused.row(y), single.row(y), "{mode:?} channel {c}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn replace_equals_opaque_blend() {
+        arbtest::arbtest(|u| {
+            let size = (u.int_in_range(1..=8)?, u.int_in_range(1..=8)?);
+            let mut values = || -> arbtest::arbitrary::Result<Image<f32>> {
+                let mut image = Image::new(size).unwrap();
+                for y in 0..size.1 {
+                    for v in image.row_mut(y).iter_mut() {
+                        *v = u.int_in_range(-1000..=1000)? as f32 / 100.0;
+                    }
+                }
+                Ok(image)
+            };
+            let (src, dst) = (values()?, values()?);
+            let clamp = u.arbitrary()?;
+            let rect = full_rect(size);
+            let opaque = image(size, |_, _| 1.0);
+            let mut replaced = dst.try_clone().unwrap();
+            blit(
+                &mut replaced.get_rect_mut(rect),
+                &src.get_rect(rect),
+                BlendMode::Replace,
+                None,
+                clamp,
+            );
+            let mut blended = dst.try_clone().unwrap();
+            blit(
+                &mut blended.get_rect_mut(rect),
+                &src.get_rect(rect),
+                BlendMode::Replace,
+                None,
+                clamp,
+            );
+            blit(
+                &mut blended.get_rect_mut(rect),
+                &src.get_rect(rect),
+                BlendMode::Blend,
+                Some(&opaque.get_rect(rect)),
+                clamp,
+            );
+            for y in 0..size.1 {
+                assert_eq!(replaced.row(y), blended.row(y));
+            }
+            Ok(())
+        });
+    }
+}
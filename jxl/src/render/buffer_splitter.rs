@@ -12,6 +12,33 @@ pub struct SaveStageBufferInfo {
     pub orientation: Orientation,
     pub byte_size: usize,
     pub after_extend: bool,
+    /// Part of the image that the buffer holds, in image coordinates, if it is cropped.
+    pub crop: Option<Rect>,
+}
+
+/// Returns the part of `rect`, in the coordinates of a frame at `frame_origin`, that lies within
+/// an image of size `image_size`, in image coordinates.
+fn visible_rect(rect: Rect, frame_origin: (isize, isize), image_size: (usize, usize)) -> Rect {
+    let origin = (
+        rect.origin.0 as isize + frame_origin.0,
+        rect.origin.1 as isize + frame_origin.1,
+    );
+    let end = (
+        origin.0 + rect.size.0 as isize,
+        origin.1 + rect.size.1 as isize,
+    );
+    let origin = (origin.0.max(0) as usize, origin.1.max(0) as usize);
+    let end = (
+        end.0.min(image_size.0 as isize).max(0) as usize,
+        end.1.min(image_size.1 as isize).max(0) as usize,
+    );
+    Rect {
+        origin,
+        size: (
+            end.0.saturating_sub(origin.0),
+            end.1.saturating_sub(origin.1),
+        ),
+    }
 }
 
 /// Data structure responsible for handing out access to portions of the output buffers.
@@ -62,6 +89,18 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
                 // Before-extend stages do not write to rects outside the current frame.
                 continue;
             }
+            // Cropped buffers only hold the crop, which takes the place of the image that the
+            // frame is extended to.
+            let (frame_origin, full_image_size) = match bi.crop {
+                Some(crop) => (
+                    (
+                        frame_origin.0 - crop.origin.0 as isize,
+                        frame_origin.1 - crop.origin.1 as isize,
+                    ),
+                    crop.size,
+                ),
+                None => (frame_origin, full_image_size),
+            };
             let mut channel_rect = rect.downsample(bi.downsample);
             if !outside_current_frame {
                 let frame_size = (
@@ -69,29 +108,9 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
                     frame_size.1.shrc(bi.downsample.1),
                 );
                 channel_rect = channel_rect.clip(frame_size);
-                if bi.after_extend {
-                    // clip this rect to its visible area in the full image (in full image coordinates).
-                    let origin = (
-                        rect.origin.0 as isize + frame_origin.0,
-                        rect.origin.1 as isize + frame_origin.1,
-                    );
-                    let end = (
-                        origin.0 + rect.size.0 as isize,
-                        origin.1 + rect.size.1 as isize,
-                    );
-                    let origin = (origin.0.max(0) as usize, origin.1.max(0) as usize);
-                    let end = (
-                        end.0.min(full_image_size.0 as isize).max(0) as usize,
-                        end.1.min(full_image_size.1 as isize).max(0) as usize,
-                    );
-                    channel_rect = Rect {
-                        origin,
-                        size: (
-                            end.0.saturating_sub(origin.0),
-                            end.1.saturating_sub(origin.1),
-                        ),
-                    };
-                }
+            }
+            if (!outside_current_frame && bi.after_extend) || bi.crop.is_some() {
+                channel_rect = visible_rect(rect, frame_origin, full_image_size);
             }
             if channel_rect.size.0 == 0 || channel_rect.size.1 == 0 {
                 // Buffer would be empty anyway.
@@ -108,6 +127,7 @@ impl<'a, 'b> BufferSplitter<'a, 'b> {
         self.requested_rects
     }
 
+    #[cfg(test)]
     pub fn get_full_buffers(&mut self) -> &mut [Option<JxlOutputBuffer<'b>>] {
         &mut *self.buffers
     }
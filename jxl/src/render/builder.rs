@@ -6,6 +6,7 @@
 use crate::api::{JxlColorType, JxlDataFormat};
 use crate::error::{Error, Result};
 use crate::headers::Orientation;
+use crate::image::Rect;
 use crate::render::StageSpecialCase;
 use crate::render::internal::ChannelInfo;
 use crate::render::save::SaveStage;
@@ -79,6 +80,7 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn add_save_stage(
         self,
         channels: &[usize],
@@ -87,6 +89,7 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
         color_type: JxlColorType,
         data_format: JxlDataFormat,
         fill_opaque_alpha: bool,
+        crop: Option<Rect>,
     ) -> Self {
         let stage = SaveStage::new(
             channels,
@@ -95,6 +98,7 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
             color_type,
             data_format,
             fill_opaque_alpha,
+            crop,
         );
         self.add_stage_internal(Stage::Save(stage))
     }
@@ -191,9 +195,7 @@ impl<Pipeline: RenderPipeline> RenderPipelineBuilder<Pipeline> {
                         downsample: (0, 0),
                     });
                 } else {
-                    if let Some(ty) = info.ty
-                        && ty != input_type
-                    {
+                    if let Some(ty) = info.ty.filter(|ty| *ty != input_type) {
                         return Err(Error::PipelineChannelTypeMismatch(
                             stage.to_string(),
                             c,
@@ -47,11 +47,6 @@ impl<'a, T> Channels<'a, T> {
         self.num_channels
     }
 
-    /// Returns true if there are no channels.
-    pub fn is_empty(&self) -> bool {
-        self.num_channels == 0
-    }
-
     /// Returns an iterator over channel slices.
     pub fn iter(&self) -> impl Iterator<Item = &[&'a [T]]> {
         (0..self.num_channels).map(move |ch| &self[ch])
@@ -108,11 +103,6 @@ impl<'a, T> ChannelsMut<'a, T> {
         self.num_channels
     }
 
-    /// Returns true if there are no channels.
-    pub fn is_empty(&self) -> bool {
-        self.num_channels == 0
-    }
-
     /// Splits the first 3 channels into separate mutable slices.
     /// Returns a tuple containing mutable references to each channel's rows.
     #[allow(clippy::type_complexity)]
@@ -6,6 +6,7 @@
 use std::any::Any;
 use std::fmt::Display;
 
+use crate::api::JxlDecodeStage;
 use crate::error::Result;
 use crate::image::{DataTypeTag, ImageDataType};
 use crate::render::StageSpecialCase;
@@ -82,6 +83,14 @@ impl<Buffer: 'static> Stage<Buffer> {
             _ => None,
         }
     }
+    pub(super) fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        match self {
+            Stage::InOut(s) => s.timing_stage(),
+            Stage::InPlace(s) => s.timing_stage(),
+            Stage::Save(_) => Some(JxlDecodeStage::OutputWrite),
+            Stage::Extend(_) => None,
+        }
+    }
 }
 
 impl<Buffer> Display for Stage<Buffer> {
@@ -184,6 +193,7 @@ pub trait InPlaceStage: Any + Display {
     fn uses_channel(&self, c: usize) -> bool;
     fn ty(&self) -> DataTypeTag;
     fn is_special_case(&self) -> Option<StageSpecialCase>;
+    fn timing_stage(&self) -> Option<JxlDecodeStage>;
 }
 
 pub trait RunInPlaceStage<Buffer: PipelineBuffer>: InPlaceStage {
@@ -208,6 +218,9 @@ impl<T: RenderPipelineInPlaceStage> InPlaceStage for T {
     fn is_special_case(&self) -> Option<StageSpecialCase> {
         self.is_special_case()
     }
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        self.timing_stage()
+    }
 }
 
 pub trait InOutStage: Any + Display {
@@ -218,6 +231,7 @@ pub trait InOutStage: Any + Display {
     fn input_type(&self) -> DataTypeTag;
     fn output_type(&self) -> DataTypeTag;
     fn is_special_case(&self) -> Option<StageSpecialCase>;
+    fn timing_stage(&self) -> Option<JxlDecodeStage>;
 }
 
 impl<T: RenderPipelineInOutStage> InOutStage for T {
@@ -242,6 +256,9 @@ impl<T: RenderPipelineInOutStage> InOutStage for T {
     fn is_special_case(&self) -> Option<StageSpecialCase> {
         self.is_special_case()
     }
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        self.timing_stage()
+    }
 }
 
 pub trait RunInOutStage<Buffer: PipelineBuffer>: InOutStage {
@@ -145,6 +145,18 @@ impl LowMemoryRenderPipeline {
             origin: (gsz * gx, gsz * gy),
         }
         .clip(self.shared.input_size);
+        let visible_rect = self.visible_rect;
+        let is_visible = |rect: &Rect| {
+            visible_rect.is_none_or(|visible| {
+                rect.origin.0 < visible.end().0
+                    && visible.origin.0 < rect.end().0
+                    && rect.origin.1 < visible.end().1
+                    && visible.origin.1 < rect.end().1
+            })
+        };
+        // The samples of groups outside the image still need to be kept, as the borders of the
+        // neighboring groups are read from them.
+        self.skipped_groups[g] = !is_visible(&group_rect);
 
         {
             for c in 0..self.shared.num_channels() {
@@ -265,6 +277,12 @@ impl LowMemoryRenderPipeline {
                 origin: (x0, y0),
                 size: (x1 - x0, y1 - y0),
             };
+            // Nothing of this area ends up in the image, so there is no need to render it.
+            // Filters near the edges of the frame still mirror within the frame, as the areas
+            // that are rendered read their borders from the input of the neighboring groups.
+            if !is_visible(&image_area) {
+                return Ok(());
+            }
 
             let mut local_buffers = buffer_splitter.get_local_buffers(
                 &self.save_buffer_info,
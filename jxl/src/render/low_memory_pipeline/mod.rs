@@ -10,13 +10,13 @@ use std::any::Any;
 use row_buffers::RowBuffer;
 
 use crate::api::JxlOutputBuffer;
-use crate::error::Result;
+use crate::error::{ErrorContext, Result};
 use crate::image::{DataTypeTag, Image, ImageDataType, OwnedRawImage, Rect};
 use crate::render::MAX_BORDER;
 use crate::render::buffer_splitter::{BufferSplitter, SaveStageBufferInfo};
-use crate::render::internal::Stage;
+use crate::render::internal::{ChannelInfo, Stage};
 use crate::render::low_memory_pipeline::group_scheduler::InputBuffer;
-use crate::util::{ShiftRightCeil, tracing_wrappers::*};
+use crate::util::{ShiftRightCeil, paranoid_check, tracing_wrappers::*};
 
 use super::RenderPipeline;
 use super::internal::{RenderPipelineShared, RunInOutStage, RunInPlaceStage};
@@ -61,6 +61,70 @@ pub struct LowMemoryRenderPipeline {
     // could be reused to store group data for that channel.
     // Indexed by [3*channel] = center, [3*channel+1] = topbottom, [3*channel+2] = leftright.
     scratch_channel_buffers: Vec<Vec<OwnedRawImage>>,
+    // The part of the frame that is blended onto the image, if rendering the rest of the frame
+    // can be skipped.
+    visible_rect: Option<Rect>,
+    // Whether each group was skipped for being entirely outside of `visible_rect`.
+    skipped_groups: Vec<bool>,
+}
+
+/// Returns the part of a frame of size `frame_size` that lands on the image when the frame is
+/// extended to the image dimensions, or on the crop if all buffers are cropped alike, or `None`
+/// if all of the frame must be rendered because a stage before the extension saves it (e.g. as a
+/// reference frame saved before the color transform, which patches can read from outside the
+/// image).
+fn visible_frame_rect<Buffer>(
+    stages: &[Stage<Buffer>],
+    extend_stage_index: Option<usize>,
+    frame_size: (usize, usize),
+) -> Option<Rect> {
+    let mut crops = stages.iter().filter_map(|stage| match stage {
+        Stage::Save(s) => Some(s.crop),
+        _ => None,
+    });
+    let first_crop = crops.next().flatten();
+    let crop = first_crop.filter(|_| crops.all(|crop| crop == first_crop));
+    let (frame_origin, image_area) = match extend_stage_index {
+        Some(e) => {
+            if stages[..e]
+                .iter()
+                .any(|stage| matches!(stage, Stage::Save(_)))
+            {
+                return None;
+            }
+            let Stage::Extend(e) = &stages[e] else {
+                unreachable!("extend stage is not an extend stage");
+            };
+            let image = Rect {
+                origin: (0, 0),
+                size: e.image_size,
+            };
+            (e.frame_origin, crop.unwrap_or(image))
+        }
+        // Without an extension the frame is the image, which is only partially saved if cropped.
+        None => ((0, 0), crop?),
+    };
+    let range = |origin: isize, frame_size: usize, start: usize, end: usize| {
+        let frame_start = (start as isize - origin).clamp(0, frame_size as isize) as usize;
+        let frame_end = (end as isize - origin).clamp(frame_start as isize, frame_size as isize);
+        (frame_start, frame_end as usize - frame_start)
+    };
+    let (x0, xsize) = range(
+        frame_origin.0,
+        frame_size.0,
+        image_area.origin.0,
+        image_area.end().0,
+    );
+    let (y0, ysize) = range(
+        frame_origin.1,
+        frame_size.1,
+        image_area.origin.1,
+        image_area.end().1,
+    );
+    Some(Rect {
+        origin: (x0, y0),
+        size: (xsize, ysize),
+    })
 }
 
 impl RenderPipeline for LowMemoryRenderPipeline {
@@ -145,6 +209,7 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                         orientation: s.orientation,
                         byte_size: s.data_format.bytes_per_sample() * s.output_channels(),
                         after_extend: shared.extend_stage_index.is_some_and(|e| i > e),
+                        crop: s.crop,
                     };
                     while save_buffer_info.len() <= s.output_buffer_index {
                         save_buffer_info.push(None);
@@ -204,7 +269,9 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                 if s.fill_opaque_alpha {
                     let (dx, _dy) = downsampling_for_stage[i];
                     let row_len = shared.chunk_size >> dx;
-                    let fill_pattern = s.data_format.opaque_alpha_bytes();
+                    // The save stage converts row buffers to the requested byte order, so
+                    // they hold native samples.
+                    let fill_pattern = s.data_format.with_native_endianness().opaque_alpha_bytes();
                     let buf =
                         RowBuffer::new_filled(s.data_format.data_type(), row_len, &fill_pattern)?;
                     opaque_alpha_buffers.push(Some(buf));
@@ -263,7 +330,12 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                 .max(border_pixels_per_stage[s].1 << downsampling_for_stage[s].1);
         }
 
+        let visible_rect =
+            visible_frame_rect(&shared.stages, shared.extend_stage_index, shared.input_size);
+        let num_groups = shared.group_chan_complete.len();
         Ok(Self {
+            visible_rect,
+            skipped_groups: vec![false; num_groups],
             input_buffers,
             stage_input_buffer_index,
             row_buffers,
@@ -309,6 +381,17 @@ impl RenderPipeline for LowMemoryRenderPipeline {
                 channel,
                 T::DATA_TYPE_ID,
             );
+            paranoid_check(
+                || {
+                    let ChannelInfo { ty, downsample } = self.shared.channel_info[0][channel];
+                    let group_size = self.shared.group_size(group_id);
+                    ty == Some(T::DATA_TYPE_ID)
+                        && buf.size().0 >= group_size.0.shrc(downsample.0)
+                        && buf.size().1 >= group_size.1.shrc(downsample.1)
+                },
+                "group buffer does not match the channel type and dimensions",
+            )
+            .context("channel", Some(channel))?;
             self.input_buffers[group_id].set_buffer(channel, buf.into_raw());
             self.shared.group_chan_complete[group_id][channel] = complete;
 
@@ -435,4 +518,11 @@ impl RenderPipeline for LowMemoryRenderPipeline {
     fn used_channel_mask(&self) -> &[bool] {
         &self.shared.channel_is_used
     }
+
+    fn num_skipped_groups(&self) -> usize {
+        self.skipped_groups
+            .iter()
+            .filter(|skipped| **skipped)
+            .count()
+    }
 }
@@ -7,13 +7,16 @@ use std::ops::Range;
 
 use crate::{
     api::JxlOutputBuffer,
-    error::Result,
+    error::{ErrorContext, Result},
     image::{DataTypeTag, Rect},
     render::{
         internal::{ChannelInfo, Stage},
         low_memory_pipeline::{helpers::get_distinct_indices, run_stage::ExtraInfo},
     },
-    util::{ShiftRightCeil, SmallVec, mirror, tracing_wrappers::*},
+    util::{
+        PARANOID_CHECKS, ShiftRightCeil, SmallVec, StageClock, mirror, paranoid_check,
+        tracing_wrappers::*,
+    },
 };
 
 use super::{LowMemoryRenderPipeline, row_buffers::RowBuffer};
@@ -66,6 +69,21 @@ fn apply_x_padding(
     }
 }
 
+/// Returns whether the f32 samples at `xrange` of rows `rows` of all of `buffers` are finite.
+fn rows_are_finite<'a>(
+    buffers: impl IntoIterator<Item = &'a RowBuffer>,
+    rows: impl Iterator<Item = usize> + Clone,
+    xrange: Range<usize>,
+) -> bool {
+    buffers.into_iter().all(|buf| {
+        rows.clone().all(|y| {
+            buf.get_row::<f32>(y)[xrange.clone()]
+                .iter()
+                .all(|x| x.is_finite())
+        })
+    })
+}
+
 impl LowMemoryRenderPipeline {
     fn fill_initial_buffers(
         &mut self,
@@ -230,6 +248,7 @@ impl LowMemoryRenderPipeline {
                 let y = y as usize;
                 self.fill_initial_buffers(c, y, (x0 >> dx, xsize >> dx), (gx, gy));
             }
+            let mut clock = StageClock::sample_row(vy);
             // Step 2: go through stages one by one.
             for (i, stage) in self.shared.stages.iter().enumerate() {
                 let (dx, dy) = self.downsampling_for_stage[i];
@@ -261,6 +280,15 @@ impl LowMemoryRenderPipeline {
                             &mut self.row_buffers,
                             &self.sorted_buffer_indices[i],
                         );
+                        // Stages working on floats must keep finite samples finite.
+                        let row_x0 = RowBuffer::x0_offset::<f32>();
+                        let check_finite = PARANOID_CHECKS
+                            && s.ty() == DataTypeTag::F32
+                            && rows_are_finite(
+                                buffers.iter().map(|b| &**b),
+                                y..y + 1,
+                                row_x0..row_x0 + shifted_xsize,
+                            );
                         s.run_stage_on(
                             ExtraInfo {
                                 xsize: shifted_xsize,
@@ -274,6 +302,18 @@ impl LowMemoryRenderPipeline {
                             &mut buffers,
                             self.local_states[i].as_deref_mut(),
                         );
+                        paranoid_check(
+                            || {
+                                !check_finite
+                                    || rows_are_finite(
+                                        buffers.iter().map(|b| &**b),
+                                        y..y + 1,
+                                        row_x0..row_x0 + shifted_xsize,
+                                    )
+                            },
+                            "stage produced non-finite samples from finite ones",
+                        )
+                        .context("render stage", Some(i))?;
                     }
                     Stage::Save(s) => {
                         // Find buffers for channels that will be saved.
@@ -340,6 +380,18 @@ impl LowMemoryRenderPipeline {
                             .iter()
                             .map(|(si, ci)| &inb[*si][*ci])
                             .collect();
+                        // Stages working on floats must keep finite samples finite, taking into
+                        // account all the input samples that they can access.
+                        let row_x0 = RowBuffer::x0_offset::<f32>();
+                        let check_finite = PARANOID_CHECKS
+                            && s.input_type() == DataTypeTag::F32
+                            && s.output_type() == DataTypeTag::F32
+                            && rows_are_finite(
+                                input_data.iter().copied(),
+                                (-bordery..=bordery)
+                                    .map(|iy| mirror(y as isize + iy, shifted_ysize)),
+                                row_x0 - borderx..row_x0 + shifted_xsize + borderx,
+                            );
                         s.run_stage_on(
                             ExtraInfo {
                                 xsize: shifted_xsize,
@@ -354,8 +406,22 @@ impl LowMemoryRenderPipeline {
                             &mut outb[0][..],
                             self.local_states[i].as_deref_mut(),
                         );
+                        let (shiftx, shifty) = s.shift();
+                        paranoid_check(
+                            || {
+                                !check_finite
+                                    || rows_are_finite(
+                                        &outb[0],
+                                        (y << shifty)..((y + 1) << shifty),
+                                        row_x0..row_x0 + (shifted_xsize << shiftx),
+                                    )
+                            },
+                            "stage produced non-finite samples from finite ones",
+                        )
+                        .context("render stage", Some(i))?;
                     }
                 }
+                clock.lap(stage.timing_stage());
             }
         }
         Ok(())
@@ -387,6 +453,7 @@ impl LowMemoryRenderPipeline {
                 let row = &mut buffer.get_row_mut(y)[RowBuffer::x0_offset::<f32>()..];
                 extend.process_row_chunk((x0, y), xsize, c, row);
             }
+            let mut clock = StageClock::sample_row(y);
             // Step 2: go through remaining stages one by one.
             for (i, stage) in self.shared.stages.iter().enumerate().skip(extend + 1) {
                 assert_eq!(self.downsampling_for_stage[i], (0, 0));
@@ -459,6 +526,7 @@ impl LowMemoryRenderPipeline {
                         );
                     }
                 }
+                clock.lap(stage.timing_stage());
             }
         }
         Ok(())
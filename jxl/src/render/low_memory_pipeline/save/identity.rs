@@ -8,7 +8,9 @@
 use std::mem::MaybeUninit;
 use std::ops::Range;
 
-use jxl_simd::{F32SimdVec, SimdDescriptor, U8SimdVec, U16SimdVec, simd_function};
+use jxl_simd::{
+    F32SimdVec, SimdDescriptor, U8SimdVec, U16SimdVec, compat::as_chunks_mut, simd_function,
+};
 
 use crate::{
     api::{Endianness, JxlDataFormat, JxlOutputBuffer},
@@ -226,12 +228,54 @@ pub(super) fn store(
     // SAFETY: we never write uninit memory to the `output_row`.
     let output_buf = unsafe { output_buf.row_mut(output_y) };
     let output_buf = &mut output_buf[0..(byte_end - byte_start) * input_buf.len()];
-    match (
-        input_buf.len(),
-        data_format.bytes_per_sample(),
-        is_native_endian,
-    ) {
-        (1, _, true) => {
+    let bytes_per_sample = data_format.bytes_per_sample();
+    let num_stored = store_native(
+        input_buf,
+        input_y,
+        byte_start..byte_end,
+        output_buf,
+        bytes_per_sample,
+    );
+    if !is_native_endian {
+        let num_bytes = num_stored * input_buf.len() * bytes_per_sample;
+        // SAFETY: `store_native` initialized the samples of the first `num_stored` pixels, and u8
+        // and MaybeUninit<u8> have the same layout.
+        let stored = unsafe {
+            std::slice::from_raw_parts_mut(output_buf.as_mut_ptr().cast::<u8>(), num_bytes)
+        };
+        match bytes_per_sample {
+            2 => swap_sample_bytes::<2>(stored),
+            4 => swap_sample_bytes::<4>(stored),
+            _ => {}
+        }
+    }
+    num_stored
+}
+
+/// Reverses the bytes of each `N`-byte sample, which compiles to vector shuffles.
+fn swap_sample_bytes<const N: usize>(bytes: &mut [u8]) {
+    let (samples, rest) = as_chunks_mut::<_, N>(bytes);
+    debug_assert!(rest.is_empty());
+    for sample in samples {
+        sample.reverse();
+    }
+}
+
+/// Stores the samples of the pixels at `byte_range` of `input_buf` in native byte order, and
+/// returns how many pixels were stored.
+fn store_native(
+    input_buf: &[&RowBuffer],
+    input_y: usize,
+    byte_range: Range<usize>,
+    output_buf: &mut [MaybeUninit<u8>],
+    bytes_per_sample: usize,
+) -> usize {
+    let Range {
+        start: byte_start,
+        end: byte_end,
+    } = byte_range;
+    match (input_buf.len(), bytes_per_sample) {
+        (1, _) => {
             // We can just do a memcpy.
             let input_buf = &input_buf[0].get_row::<u8>(input_y)[byte_start..byte_end];
             assert_eq!(input_buf.len(), output_buf.len());
@@ -246,9 +290,9 @@ pub(super) fn store(
                     output_buf.len(),
                 );
             }
-            input_buf.len() / data_format.bytes_per_sample()
+            input_buf.len() / bytes_per_sample
         }
-        (channels, 1, true) if (2..=4).contains(&channels) => {
+        (channels, 1) if (2..=4).contains(&channels) => {
             let start_u8 = byte_start;
             let end_u8 = byte_end;
             let mut slices = [&[] as &[u8]; 4];
@@ -259,7 +303,7 @@ pub(super) fn store(
             // never writes uninitialized memory.
             store_interleaved_u8(&slices[..channels], output_buf)
         }
-        (channels, 2, true) if (2..=4).contains(&channels) => {
+        (channels, 2) if (2..=4).contains(&channels) => {
             let ptr = output_buf.as_mut_ptr();
             if ptr.align_offset(std::mem::align_of::<u16>()) == 0 {
                 let len_u16 = output_buf.len() / 2;
@@ -285,7 +329,7 @@ pub(super) fn store(
                 0
             }
         }
-        (channels, 4, true) if (2..=4).contains(&channels) => {
+        (channels, 4) if (2..=4).contains(&channels) => {
             let ptr = output_buf.as_mut_ptr();
             if ptr.align_offset(std::mem::align_of::<f32>()) == 0 {
                 let len_f32 = output_buf.len() / std::mem::size_of::<f32>();
@@ -13,6 +13,8 @@ use crate::{
 use super::row_buffers::RowBuffer;
 
 mod identity;
+mod mirror;
+mod transpose;
 
 // Placeholder slow implementation.
 impl SaveStage {
@@ -34,6 +36,19 @@ impl SaveStage {
 
         let group_y = frame_y - group_origin.1;
 
+        // Cropped buffers only hold the crop, which takes the place of the image that the frame
+        // is extended to.
+        let (full_image_size, frame_origin) = match self.crop {
+            Some(crop) => (
+                crop.size,
+                (
+                    frame_origin.0 - crop.origin.0 as isize,
+                    frame_origin.1 - crop.origin.1 as isize,
+                ),
+            ),
+            None => (full_image_size, frame_origin),
+        };
+
         let relative_full_image_start = (
             -frame_origin.0 - (group_origin.0 as isize),
             -frame_origin.1 - (group_origin.1 as isize),
@@ -84,7 +99,41 @@ impl SaveStage {
                 save_size.1 - 1 - relative_y,
                 self.data_format,
             ),
-            _ => 0,
+            Orientation::FlipHorizontal => mirror::store(
+                data,
+                frame_y,
+                save_start.0..save_end.0,
+                buf,
+                relative_y,
+                self.data_format,
+            ),
+            Orientation::Rotate180 => mirror::store(
+                data,
+                frame_y,
+                save_start.0..save_end.0,
+                buf,
+                save_size.1 - 1 - relative_y,
+                self.data_format,
+            ),
+            Orientation::Transpose
+            | Orientation::Rotate90Cw
+            | Orientation::AntiTranspose
+            | Orientation::Rotate90Ccw => {
+                let (output_x, _) = self.orientation.display_pixel((0, relative_y), save_size);
+                let reverse_rows = matches!(
+                    self.orientation,
+                    Orientation::AntiTranspose | Orientation::Rotate90Ccw
+                );
+                transpose::store(
+                    data,
+                    frame_y,
+                    save_start.0..save_end.0,
+                    buf,
+                    output_x,
+                    reverse_rows,
+                    self.data_format,
+                )
+            }
         };
 
         macro_rules! write_pixel {
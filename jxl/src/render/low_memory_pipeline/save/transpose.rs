@@ -0,0 +1,75 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+#![allow(unsafe_code)]
+
+use std::ops::Range;
+
+use crate::{
+    api::{Endianness, JxlDataFormat, JxlOutputBuffer},
+    render::low_memory_pipeline::row_buffers::RowBuffer,
+};
+
+/// Maximum number of channels interleaved in an output buffer.
+const MAX_CHANNELS: usize = 4;
+
+/// Stores the pixels at `xrange` of row `input_y` of `input_buf` as column `output_x` of
+/// `output_buf`, for transposing orientations. The pixels are stored from the first row of the
+/// output down, or from the last one up if `reverse_rows` is set. Returns how many pixels were
+/// stored.
+///
+/// Rows of a group are stored one after the other, each as a column next to the previous one, so
+/// the output rows that a group spans stay in cache while it is stored: the group is the tile of
+/// the transposition.
+pub(super) fn store(
+    input_buf: &[&RowBuffer],
+    input_y: usize,
+    xrange: Range<usize>,
+    output_buf: &mut JxlOutputBuffer,
+    output_x: usize,
+    reverse_rows: bool,
+    data_format: JxlDataFormat,
+) -> usize {
+    let num_channels = input_buf.len();
+    if num_channels > MAX_CHANNELS {
+        return 0;
+    }
+    let swap_bytes = match data_format {
+        JxlDataFormat::U8 { .. } => false,
+        JxlDataFormat::F16 { endianness, .. }
+        | JxlDataFormat::U16 { endianness, .. }
+        | JxlDataFormat::F32 { endianness, .. } => endianness != Endianness::native(),
+    };
+    let bytes_per_sample = data_format.bytes_per_sample();
+    let pixel_bytes = bytes_per_sample * num_channels;
+    let byte_start = xrange.start * bytes_per_sample + RowBuffer::x0_byte_offset();
+    let byte_end = xrange.end * bytes_per_sample + RowBuffer::x0_byte_offset();
+    let mut rows = [&[][..]; MAX_CHANNELS];
+    for (row, buf) in rows.iter_mut().zip(input_buf) {
+        *row = &buf.get_row::<u8>(input_y)[byte_start..byte_end];
+    }
+    let rows = &rows[..num_channels];
+
+    let num_pixels = xrange.len();
+    for i in 0..num_pixels {
+        let output_y = if reverse_rows { num_pixels - 1 - i } else { i };
+        // SAFETY: we never write uninit memory to the output row.
+        let output_row = unsafe { output_buf.row_mut(output_y) };
+        let output_pixel = &mut output_row[output_x * pixel_bytes..][..pixel_bytes];
+        for (output_sample, row) in output_pixel.chunks_exact_mut(bytes_per_sample).zip(rows) {
+            let sample = &row[i * bytes_per_sample..][..bytes_per_sample];
+            if swap_bytes {
+                for (o, s) in output_sample.iter_mut().zip(sample.iter().rev()) {
+                    o.write(*s);
+                }
+            } else {
+                for (o, s) in output_sample.iter_mut().zip(sample) {
+                    o.write(*s);
+                }
+            }
+        }
+    }
+    num_pixels
+}
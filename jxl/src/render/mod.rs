@@ -7,22 +7,24 @@ use internal::{RenderPipelineShared, RunInOutStage, RunInPlaceStage};
 use std::any::Any;
 
 use crate::{
-    api::JxlOutputBuffer,
+    api::{JxlDecodeStage, JxlOutputBuffer},
     error::Result,
     image::{Image, ImageDataType},
     render::buffer_splitter::BufferSplitter,
 };
 
-pub mod buffer_splitter;
+pub(crate) mod blit;
+pub(crate) mod buffer_splitter;
 mod builder;
 mod channels;
 mod internal;
-pub mod low_memory_pipeline;
-pub mod save;
+pub(crate) mod low_memory_pipeline;
+pub(crate) mod resample;
+pub(crate) mod save;
 mod simd_utils;
 #[cfg(test)]
 mod simple_pipeline;
-pub mod stages;
+pub(crate) mod stages;
 #[cfg(test)]
 mod test;
 
@@ -42,13 +44,13 @@ pub(crate) use low_memory_pipeline::LowMemoryRenderPipeline;
 #[cfg(test)]
 pub(crate) use simple_pipeline::SimpleRenderPipeline;
 
-pub enum StageSpecialCase {
+pub(crate) enum StageSpecialCase {
     F32ToU8 { channel: usize, bit_depth: u8 },
     ModularToF32 { channel: usize, bit_depth: u8 },
 }
 
 /// Modifies channels in-place.
-pub trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
+pub(crate) trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
     type Type: ImageDataType;
 
     fn process_row_chunk(
@@ -69,6 +71,11 @@ pub trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
     fn is_special_case(&self) -> Option<StageSpecialCase> {
         None
     }
+
+    /// Decoding stage that time spent in this stage is attributed to.
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        None
+    }
 }
 
 /// Modifies data and writes it to a new buffer, of possibly different type.
@@ -83,7 +90,7 @@ pub trait RenderPipelineInPlaceStage: Any + std::fmt::Display {
 ///    padding on either side.
 ///  - the output slice contains 1 << SHIFT.1 slices, each of length xsize << SHIFT.0, the
 ///    corresponding output pixels.
-pub trait RenderPipelineInOutStage: Any + std::fmt::Display {
+pub(crate) trait RenderPipelineInOutStage: Any + std::fmt::Display {
     type InputT: ImageDataType;
     type OutputT: ImageDataType;
 
@@ -110,6 +117,11 @@ pub trait RenderPipelineInOutStage: Any + std::fmt::Display {
     fn is_special_case(&self) -> Option<StageSpecialCase> {
         None
     }
+
+    /// Decoding stage that time spent in this stage is attributed to.
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        None
+    }
 }
 
 // TODO(veluca): find a way to reduce the generated code due to having two builders, to integrate
@@ -155,4 +167,10 @@ pub(crate) trait RenderPipeline: Sized {
     ) -> Box<dyn RunInPlaceStage<Self::Buffer>>;
 
     fn used_channel_mask(&self) -> &[bool];
+
+    /// Returns how many groups were decoded but not rendered, because they are entirely outside
+    /// of the image.
+    fn num_skipped_groups(&self) -> usize {
+        0
+    }
 }
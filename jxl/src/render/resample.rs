@@ -0,0 +1,472 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Resampling of decoded images to arbitrary sizes, with separable filters.
+//!
+//! Filters should be applied to linear light values: [`resample_colors`] converts from and to
+//! the transfer function of the image, while [`resample`] filters samples as they are.
+
+use std::f32::consts::PI;
+
+use crate::{
+    error::Result,
+    image::Image,
+    render::{
+        RenderPipelineInPlaceStage,
+        stages::{FromLinearStage, ToLinearStage, TransferFunction},
+    },
+};
+
+pub(crate) fn register_resize(capabilities: &mut crate::Capabilities) {
+    capabilities.resize = true;
+}
+
+/// Filter used to compute output pixels from the input pixels around them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResampleFilter {
+    /// Cubic filter that is sharp and rings little.
+    #[default]
+    CatmullRom,
+    /// Windowed sinc with 3 lobes, sharper than [`ResampleFilter::CatmullRom`] but with more
+    /// ringing.
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    /// Distance from the center beyond which the filter is zero, in input pixels when upsampling.
+    pub fn support(self) -> f32 {
+        match self {
+            ResampleFilter::CatmullRom => 2.0,
+            ResampleFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Evaluates the filter at distance `x` from its center.
+    pub fn weight(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ResampleFilter::CatmullRom if x < 1.0 => (1.5 * x - 2.5) * x * x + 1.0,
+            ResampleFilter::CatmullRom if x < 2.0 => ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0,
+            ResampleFilter::Lanczos3 if x < 1e-6 => 1.0,
+            ResampleFilter::Lanczos3 if x < 3.0 => {
+                let px = PI * x;
+                3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+/// Weights of the input pixels that contribute to each output pixel, along one dimension.
+struct Contributions {
+    /// First contributing input pixel of each output pixel.
+    first: Vec<usize>,
+    /// `taps` weights per output pixel, summing to 1.
+    weights: Vec<f32>,
+    taps: usize,
+}
+
+impl Contributions {
+    fn new(from: usize, to: usize, filter: ResampleFilter) -> Contributions {
+        let scale = to as f32 / from as f32;
+        // When downsampling, the filter is stretched to cover all input pixels.
+        let stretch = scale.recip().max(1.0);
+        let radius = filter.support() * stretch;
+        let window = (2.0 * radius).ceil() as usize + 1;
+        let taps = window.min(from);
+        let mut first = Vec::with_capacity(to);
+        let mut weights = vec![0.0; to * taps];
+        for (i, weights) in weights.chunks_exact_mut(taps).enumerate() {
+            let center = (i as f32 + 0.5) / scale - 0.5;
+            let start = (center - radius).ceil() as isize;
+            let first_pixel = start.clamp(0, (from - taps) as isize) as usize;
+            // Pixels past the edges repeat the edge pixels.
+            for j in start..start + window as isize {
+                let pixel = j.clamp(0, from as isize - 1) as usize;
+                weights[pixel - first_pixel] += filter.weight((j as f32 - center) / stretch);
+            }
+            let sum: f32 = weights.iter().sum();
+            weights.iter_mut().for_each(|w| *w /= sum);
+            first.push(first_pixel);
+        }
+        Contributions {
+            first,
+            weights,
+            taps,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (usize, &[f32])> {
+        self.first
+            .iter()
+            .copied()
+            .zip(self.weights.chunks_exact(self.taps))
+    }
+}
+
+/// Resamples `image`, whose rows contain `num_channels` interleaved channels, to `size` pixels.
+pub fn resample(
+    image: &Image<f32>,
+    num_channels: usize,
+    size: (usize, usize),
+    filter: ResampleFilter,
+) -> Result<Image<f32>> {
+    let (xsize, ysize) = (image.size().0 / num_channels, image.size().1);
+    if (xsize, ysize) == size {
+        return image.try_clone();
+    }
+    let columns = Contributions::new(xsize, size.0, filter);
+    let rows = Contributions::new(ysize, size.1, filter);
+
+    let mut horizontal = Image::<f32>::new((size.0 * num_channels, ysize))?;
+    for y in 0..ysize {
+        let input = image.row(y);
+        let output = horizontal.row_mut(y);
+        for ((first, weights), pixel) in columns.iter().zip(output.chunks_exact_mut(num_channels)) {
+            let input = &input[first * num_channels..];
+            for (c, value) in pixel.iter_mut().enumerate() {
+                *value = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| w * input[i * num_channels + c])
+                    .sum();
+            }
+        }
+    }
+
+    let mut output = Image::<f32>::new((size.0 * num_channels, size.1))?;
+    for (y, (first, weights)) in rows.iter().enumerate() {
+        let output = output.row_mut(y);
+        for (i, w) in weights.iter().enumerate() {
+            for (value, input) in output.iter_mut().zip(horizontal.row(first + i)) {
+                *value += w * input;
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Like [`resample`], but with channel `alpha_channel` holding straight (not premultiplied)
+/// alpha. Colors are weighted by alpha while filtering, so that the colors of transparent
+/// pixels do not bleed into visible ones, and alpha is clamped to `[0, 1]`.
+pub fn resample_with_alpha(
+    image: &Image<f32>,
+    num_channels: usize,
+    alpha_channel: usize,
+    size: (usize, usize),
+    filter: ResampleFilter,
+) -> Result<Image<f32>> {
+    let mut premultiplied = image.try_clone()?;
+    for y in 0..premultiplied.size().1 {
+        for pixel in premultiplied.row_mut(y).chunks_exact_mut(num_channels) {
+            let alpha = pixel[alpha_channel];
+            for (c, value) in pixel.iter_mut().enumerate() {
+                if c != alpha_channel {
+                    *value *= alpha;
+                }
+            }
+        }
+    }
+    let mut output = resample(&premultiplied, num_channels, size, filter)?;
+    for y in 0..size.1 {
+        for pixel in output.row_mut(y).chunks_exact_mut(num_channels) {
+            // Colors are divided by the unclamped alpha, as they ring in the same way.
+            let alpha = pixel[alpha_channel];
+            let scale = if alpha > 0.0 { alpha.recip() } else { 0.0 };
+            for (c, value) in pixel.iter_mut().enumerate() {
+                *value = if c == alpha_channel {
+                    alpha.clamp(0.0, 1.0)
+                } else {
+                    *value * scale
+                };
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// How the samples of an interleaved color image are encoded.
+#[derive(Clone, Debug)]
+pub struct ColorSamples {
+    /// Number of interleaved channels: 1 or 3 colors, optionally followed by alpha.
+    pub num_channels: usize,
+    /// Whether the last channel is (straight or premultiplied) alpha.
+    pub has_alpha: bool,
+    /// Whether colors are premultiplied by alpha.
+    pub premultiplied: bool,
+    /// Transfer function of the colors, or `None` to filter them as they are, for example
+    /// because they are already linear.
+    pub transfer_function: Option<TransferFunction>,
+}
+
+/// Resamples a color image to `size` pixels in linear light, weighting colors by alpha.
+pub fn resample_colors(
+    image: &Image<f32>,
+    samples: &ColorSamples,
+    size: (usize, usize),
+    filter: ResampleFilter,
+) -> Result<Image<f32>> {
+    let num_channels = samples.num_channels;
+    let num_colors = num_channels - samples.has_alpha as usize;
+    let transfer_function = samples
+        .transfer_function
+        .as_ref()
+        .filter(|tf| !tf.is_linear());
+    let mut image = image.try_clone()?;
+    if samples.premultiplied {
+        apply_alpha(&mut image, num_channels, f32::recip);
+    }
+    if let Some(tf) = transfer_function {
+        convert_colors(
+            &mut image,
+            num_channels,
+            num_colors,
+            &ToLinearStage::new(0, tf.clone()),
+        );
+    }
+    let mut output = if samples.has_alpha {
+        resample_with_alpha(&image, num_channels, num_colors, size, filter)?
+    } else {
+        resample(&image, num_channels, size, filter)?
+    };
+    if let Some(tf) = transfer_function {
+        convert_colors(
+            &mut output,
+            num_channels,
+            num_colors,
+            &FromLinearStage::new(0, tf.clone()),
+        );
+    }
+    if samples.premultiplied {
+        apply_alpha(&mut output, num_channels, |alpha| alpha);
+    }
+    Ok(output)
+}
+
+/// Multiplies the colors of each pixel by `factor(alpha)`, with alpha in the last channel, or
+/// sets them to 0 if alpha is 0.
+fn apply_alpha(image: &mut Image<f32>, num_channels: usize, factor: impl Fn(f32) -> f32) {
+    for y in 0..image.size().1 {
+        for pixel in image.row_mut(y).chunks_exact_mut(num_channels) {
+            let (alpha, colors) = pixel.split_last_mut().unwrap();
+            let factor = if *alpha > 0.0 { factor(*alpha) } else { 0.0 };
+            colors.iter_mut().for_each(|v| *v *= factor);
+        }
+    }
+}
+
+/// Applies `stage`, which converts 3 color channels, to the first `num_colors` channels of
+/// `image`. A single color channel is converted as a gray color.
+fn convert_colors(
+    image: &mut Image<f32>,
+    num_channels: usize,
+    num_colors: usize,
+    stage: &impl RenderPipelineInPlaceStage<Type = f32>,
+) {
+    let xsize = image.size().0 / num_channels;
+    // Stages process whole SIMD vectors, so rows are padded to the largest vector size.
+    let mut rows = [(); 3].map(|_| vec![0.0f32; xsize.next_multiple_of(16)]);
+    for y in 0..image.size().1 {
+        let row = image.row_mut(y);
+        for (c, plane) in rows.iter_mut().enumerate() {
+            let c = c.min(num_colors - 1);
+            for (value, pixel) in plane.iter_mut().zip(row.chunks_exact(num_channels)) {
+                *value = pixel[c];
+            }
+        }
+        let mut row_refs = rows.each_mut().map(|row| &mut row[..]);
+        stage.process_row_chunk((0, y), xsize, &mut row_refs, None);
+        for (c, plane) in rows.iter().enumerate().take(num_colors) {
+            for (pixel, value) in row.chunks_exact_mut(num_channels).zip(plane) {
+                pixel[c] = *value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FILTERS: [ResampleFilter; 2] = [ResampleFilter::CatmullRom, ResampleFilter::Lanczos3];
+
+    fn image_from_rows(rows: &[&[f32]]) -> Image<f32> {
+        let mut image = Image::new((rows[0].len(), rows.len())).unwrap();
+        for (y, row) in rows.iter().enumerate() {
+            image.row_mut(y).copy_from_slice(row);
+        }
+        image
+    }
+
+    #[test]
+    fn filters_interpolate() {
+        for filter in FILTERS {
+            assert_eq!(filter.weight(0.0), 1.0);
+            for x in 1..=filter.support() as usize {
+                assert!(filter.weight(x as f32).abs() < 1e-6, "{filter:?} at {x}");
+            }
+            assert_eq!(filter.weight(filter.support()), 0.0);
+            assert_eq!(filter.weight(0.7), filter.weight(-0.7));
+        }
+    }
+
+    #[test]
+    fn impulse_response() {
+        let mut impulse = [0.0; 9];
+        impulse[4] = 1.0;
+        let image = image_from_rows(&[&impulse]);
+        for filter in FILTERS {
+            // Upsampling by 3 places output pixels exactly on input pixels.
+            let output = resample(&image, 1, (27, 1), filter).unwrap();
+            let row = output.row(0);
+            assert_eq!(row[13], 1.0);
+            for i in 0..13 {
+                assert!(
+                    (row[13 - i] - row[13 + i]).abs() < 1e-6,
+                    "{filter:?}: {row:?}"
+                );
+                // Only the filter support around the impulse is affected.
+                if i >= 3 * filter.support() as usize {
+                    assert_eq!(row[13 - i], 0.0);
+                }
+            }
+            assert!(row[12] > 0.0 && row[12] < 1.0);
+            // Negative lobes.
+            assert!(row.iter().any(|&v| v < 0.0));
+            // The energy of the impulse is spread over 3 output pixels per input pixel.
+            assert!((row.iter().sum::<f32>() - 3.0).abs() < 1e-4);
+
+            // Same size is the identity.
+            let output = resample(&image, 1, (9, 1), filter).unwrap();
+            assert_eq!(output.row(0), &impulse);
+        }
+    }
+
+    #[test]
+    fn constant_images_stay_constant() {
+        let row = [0.25, 0.5, 1.0].repeat(13);
+        let image = image_from_rows(&[&row[..]; 11]);
+        for filter in FILTERS {
+            for size in [(1, 1), (4, 3), (13, 11), (14, 5), (100, 70)] {
+                let output = resample(&image, 3, size, filter).unwrap();
+                assert_eq!(output.size(), (size.0 * 3, size.1));
+                for y in 0..size.1 {
+                    for pixel in output.row(y).chunks_exact(3) {
+                        for (value, expected) in pixel.iter().zip([0.25, 0.5, 1.0]) {
+                            assert!((value - expected).abs() < 1e-5, "{filter:?} {size:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn downsampling_averages() {
+        // Alternating columns average out when halving the width, instead of aliasing.
+        let row = [0.0, 1.0].repeat(16);
+        let image = image_from_rows(&[&row, &row]);
+        for filter in FILTERS {
+            let output = resample(&image, 1, (8, 2), filter).unwrap();
+            for &value in &output.row(1)[1..7] {
+                assert!((value - 0.5).abs() < 0.05, "{filter:?}: {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_colors_do_not_bleed() {
+        // Opaque red on the left, fully transparent green on the right.
+        let row = [
+            [1.0, 0.0, 0.0, 1.0].repeat(8),
+            [0.0, 1.0, 0.0, 0.0].repeat(8),
+        ]
+        .concat();
+        let image = image_from_rows(&[&row[..]; 4]);
+        for filter in FILTERS {
+            for size in [(5, 2), (16, 4), (37, 9)] {
+                let output = resample_with_alpha(&image, 4, 3, size, filter).unwrap();
+                let mut alphas = vec![];
+                for pixel in output.row(size.1 / 2).chunks_exact(4) {
+                    let alpha = pixel[3];
+                    assert!((0.0..=1.0).contains(&alpha));
+                    if alpha > 0.0 {
+                        assert!((pixel[0] - 1.0).abs() < 1e-4, "{filter:?}: {pixel:?}");
+                        assert!(pixel[1].abs() < 1e-4, "{filter:?}: {pixel:?}");
+                    } else {
+                        assert_eq!(&pixel[..3], &[0.0; 3]);
+                    }
+                    alphas.push(alpha);
+                }
+                assert!(alphas[0] > 0.9 && alphas[size.0 - 1] < 0.1, "{alphas:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_is_filtered_like_other_channels() {
+        let row = [0.5, 0.0, 0.5, 1.0, 0.5, 0.0];
+        let image = image_from_rows(&[&row]);
+        let output =
+            resample_with_alpha(&image, 2, 1, (11, 1), ResampleFilter::CatmullRom).unwrap();
+        let alpha = resample(&image, 2, (11, 1), ResampleFilter::CatmullRom).unwrap();
+        for (pixel, expected) in output
+            .row(0)
+            .chunks_exact(2)
+            .zip(alpha.row(0).chunks_exact(2))
+        {
+            assert_eq!(pixel[1], expected[1].clamp(0.0, 1.0));
+            if pixel[1] > 0.0 {
+                assert!((pixel[0] - 0.5).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn colors_are_filtered_in_linear_light() {
+        // Averaging black and white gives middle gray in linear light, which is brighter than
+        // the average of the sRGB values.
+        let row = [[0.0; 3], [1.0; 3]].concat().repeat(8);
+        let image = image_from_rows(&[&row[..]; 2]);
+        let samples = ColorSamples {
+            num_channels: 3,
+            has_alpha: false,
+            premultiplied: false,
+            transfer_function: Some(TransferFunction::Srgb),
+        };
+        let output = resample_colors(&image, &samples, (8, 1), ResampleFilter::CatmullRom).unwrap();
+        for &value in &output.row(0)[6..18] {
+            assert!((value - 0.735).abs() < 0.01, "{value}");
+        }
+        let samples = ColorSamples {
+            transfer_function: None,
+            ..samples
+        };
+        let output = resample_colors(&image, &samples, (8, 1), ResampleFilter::CatmullRom).unwrap();
+        for &value in &output.row(0)[6..18] {
+            assert!((value - 0.5).abs() < 0.01, "{value}");
+        }
+    }
+
+    #[test]
+    fn premultiplied_colors_stay_premultiplied() {
+        // Gray with alpha: opaque white next to half-transparent premultiplied white.
+        let row = [[1.0, 1.0].repeat(6), [0.5, 0.5].repeat(6)].concat();
+        let image = image_from_rows(&[&row[..]]);
+        let samples = ColorSamples {
+            num_channels: 2,
+            has_alpha: true,
+            premultiplied: true,
+            transfer_function: Some(TransferFunction::Srgb),
+        };
+        let output = resample_colors(&image, &samples, (5, 1), ResampleFilter::Lanczos3).unwrap();
+        for pixel in output.row(0).chunks_exact(2) {
+            // Unpremultiplied, all pixels are white.
+            assert!((pixel[0] - pixel[1]).abs() < 1e-4, "{pixel:?}");
+        }
+        assert!((output.row(0)[1] - 1.0).abs() < 0.05, "{:?}", output.row(0));
+        assert!((output.row(0)[9] - 0.5).abs() < 0.05, "{:?}", output.row(0));
+    }
+}
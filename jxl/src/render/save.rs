@@ -7,9 +7,13 @@ use crate::{
     api::{JxlColorType, JxlDataFormat, JxlOutputBuffer},
     error::{Error, Result},
     headers::Orientation,
-    image::DataTypeTag,
+    image::{DataTypeTag, Rect},
 };
 
+pub(crate) fn register_crop(capabilities: &mut crate::Capabilities) {
+    capabilities.crop = true;
+}
+
 #[derive(Debug)]
 pub struct SaveStage {
     pub(super) channels: Vec<usize>,
@@ -20,6 +24,9 @@ pub struct SaveStage {
     /// When true, fill alpha channel with opaque (1.0) values.
     /// Used when RGBA output is requested but image has no alpha channel.
     pub(super) fill_opaque_alpha: bool,
+    /// Part of the image to save, in image coordinates before the orientation is applied. The
+    /// output buffer only holds this part of the image.
+    pub(super) crop: Option<Rect>,
 }
 
 impl SaveStage {
@@ -30,6 +37,7 @@ impl SaveStage {
         mut color_type: JxlColorType,
         data_format: JxlDataFormat,
         fill_opaque_alpha: bool,
+        crop: Option<Rect>,
     ) -> SaveStage {
         let mut channels = channels.to_vec();
         if color_type == JxlColorType::Bgr {
@@ -47,6 +55,7 @@ impl SaveStage {
             color_type,
             data_format,
             fill_opaque_alpha,
+            crop,
         }
     }
 
@@ -71,6 +80,7 @@ impl SaveStage {
         let Some(buf) = buffer else {
             return Ok(());
         };
+        let size = self.crop.map_or(size, |crop| crop.size);
         let osize = self.orientation.map_size(size);
 
         let expected_w = self.output_channels() * self.data_format.bytes_per_sample() * osize.0;
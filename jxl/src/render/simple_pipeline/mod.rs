@@ -6,7 +6,7 @@
 use crate::{
     api::JxlOutputBuffer,
     error::Result,
-    image::{Image, ImageDataType},
+    image::{Image, ImageDataType, ImageRectMut, Rect},
     render::{buffer_splitter::BufferSplitter, internal::ChannelInfo},
     util::{ShiftRightCeil, tracing_wrappers::*},
 };
@@ -87,7 +87,7 @@ impl SimpleRenderPipeline {
                     );
                     let repl_iter = (0..self.shared.num_channels())
                         .filter(|c| stage.uses_channel(*c))
-                        .zip(output_buf.into_iter());
+                        .zip(output_buf);
                     for (c, chan) in repl_iter {
                         output_buffers[c] = chan;
                     }
@@ -176,12 +176,29 @@ impl RenderPipeline for SimpleRenderPipeline {
             let ty = ty.unwrap();
             assert_eq!(ty, T::DATA_TYPE_ID);
             let total_sz = self.input_buffers[channel].size();
-            for y in 0..sz.1.min(total_sz.1 - off.1) {
-                let row_in = buf.row(y);
-                let row_out = self.input_buffers[channel].row_mut(y + off.1);
-                for x in 0..sz.0.min(total_sz.0 - off.0) {
-                    row_out[x + off.0] = row_in[x].to_f64();
+            let size = (sz.0.min(total_sz.0 - off.0), sz.1.min(total_sz.1 - off.1));
+            let num_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+            let band_height = size.1.div_ceil(num_threads).max(1);
+            let bands = self.input_buffers[channel]
+                .get_rect_mut(Rect { origin: off, size })
+                .split_into_tiles(size.0.max(1), band_height);
+            let buf = &buf;
+            let convert = move |band_index: usize, mut band: ImageRectMut<'_, f64>| {
+                for y in 0..band.size().1 {
+                    let row_in = buf.row(band_index * band_height + y);
+                    for (out, inp) in band.row(y).iter_mut().zip(row_in) {
+                        *out = inp.to_f64();
+                    }
                 }
+            };
+            if bands.len() <= 1 {
+                bands.into_iter().for_each(|band| convert(0, band));
+            } else {
+                std::thread::scope(|s| {
+                    for (i, band) in bands.into_iter().enumerate() {
+                        s.spawn(move || convert(i, band));
+                    }
+                });
             }
             self.shared.group_chan_complete[group_id][channel] = complete;
         }
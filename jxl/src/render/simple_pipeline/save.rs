@@ -8,7 +8,7 @@ use crate::util::f16;
 use crate::{
     api::{Endianness, JxlDataFormat, JxlOutputBuffer},
     error::Result,
-    image::Image,
+    image::{Image, Rect},
     render::save::SaveStage,
 };
 
@@ -24,15 +24,18 @@ impl SaveStage {
         let Some(buf) = buffers[self.output_buffer_index].as_mut() else {
             return Ok(());
         };
-        let size = data[0].size();
-
-        self.check_buffer_size(size, Some(buf))?;
+        self.check_buffer_size(data[0].size(), Some(buf))?;
+        let rect = self.crop.unwrap_or(Rect {
+            origin: (0, 0),
+            size: data[0].size(),
+        });
+        let size = rect.size;
 
         let output_channels = self.output_channels();
 
         for (c, &chan) in self.channels.iter().enumerate() {
             for y in 0..size.1 {
-                let src_row = data[chan].row(y);
+                let src_row = &data[chan].row(rect.origin.1 + y)[rect.origin.0..][..size.0];
 
                 for (x, &px) in src_row.iter().enumerate() {
                     let (dx, dy) = self.orientation.display_pixel((x, y), size);
@@ -92,9 +95,7 @@ impl SaveStage {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::{
-        api::JxlColorType, headers::Orientation, image::Rect, util::test::assert_almost_eq,
-    };
+    use crate::{api::JxlColorType, headers::Orientation, util::test::assert_almost_eq};
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
     use test_log::test;
@@ -108,6 +109,7 @@ mod test {
             JxlColorType::Grayscale,
             JxlDataFormat::U8 { bit_depth: 8 },
             false,
+            None,
         );
         let mut rng = XorShiftRng::seed_from_u64(0);
         let src = [Image::<f64>::new_random((128, 128), &mut rng)?];
@@ -156,6 +158,7 @@ mod test {
             JxlColorType::Grayscale,
             JxlDataFormat::f32(),
             false,
+            None,
         );
 
         let mut rng = XorShiftRng::seed_from_u64(0);
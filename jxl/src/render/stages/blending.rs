@@ -13,8 +13,8 @@ use crate::{
     },
     frame::ReferenceFrame,
     headers::{FileHeader, extra_channels::ExtraChannelInfo, frame_header::*},
-    render::RenderPipelineInPlaceStage,
-    util::slice,
+    render::{RenderPipelineInPlaceStage, blit::overlap},
+    util::{paranoid_check, slice},
 };
 
 pub struct BlendingStage {
@@ -50,6 +50,35 @@ impl BlendingStage {
         file_header: &FileHeader,
         reference_frames: Arc<[Option<ReferenceFrame>; 4]>,
     ) -> Result<BlendingStage> {
+        paranoid_check(
+            || {
+                frame_header.blending_sources().all(|source| {
+                    reference_frames[source]
+                        .as_ref()
+                        .is_none_or(|reference| !reference.saved_before_color_transform())
+                })
+            },
+            "blending with a reference frame saved before the color transform",
+        )?;
+        // Empty slots blend as zeros, saved ones must have a sample for every pixel and channel.
+        let num_channels = 3 + file_header.image_metadata.extra_channel_info.len();
+        let image_size = (
+            file_header.size.xsize() as usize,
+            file_header.size.ysize() as usize,
+        );
+        paranoid_check(
+            || {
+                frame_header.blending_sources().all(|source| {
+                    reference_frames[source].as_ref().is_none_or(|reference| {
+                        let (xsize, ysize) = reference.size();
+                        reference.frame.len() == num_channels
+                            && xsize >= image_size.0
+                            && ysize >= image_size.1
+                    })
+                })
+            },
+            "blending with a reference frame that does not cover the image",
+        )?;
         let xsize = file_header.size.xsize();
         Ok(BlendingStage {
             frame_origin: (frame_header.x0 as isize, frame_header.y0 as isize),
@@ -85,29 +114,16 @@ impl RenderPipelineInPlaceStage for BlendingStage {
     ) {
         let num_ec = self.extra_channels.len();
         let fg_y0 = self.frame_origin.1 + position.1 as isize;
-        let mut fg_x0 = self.frame_origin.0 + position.0 as isize;
-        let mut fg_x1 = fg_x0 + xsize as isize;
-        let mut bg_x0: isize = 0;
-        let mut bg_x1: isize = xsize as isize;
-
-        if fg_x1 <= 0 || fg_x0 >= self.image_size.0 || fg_y0 < 0 || fg_y0 >= self.image_size.1 {
+        if fg_y0 < 0 || fg_y0 >= self.image_size.1 {
             return;
         }
-
-        if fg_x0 < 0 {
-            bg_x0 -= fg_x0;
-            fg_x0 = 0;
-        }
-        if fg_x1 > self.image_size.0 {
-            bg_x1 = bg_x0 + self.image_size.0 - fg_x0;
-            fg_x1 = self.image_size.0;
-        }
-
-        let fg_x0: usize = fg_x0 as usize;
-        let fg_x1: usize = fg_x1 as usize;
-        let bg_x0: usize = bg_x0 as usize;
-        let bg_x1: usize = bg_x1 as usize;
-        let fg_y0: usize = fg_y0 as usize;
+        let fg_y0 = fg_y0 as usize;
+        // The row of the reference frame starts at -fg_x0 in the row chunk.
+        let fg_x0 = self.frame_origin.0 + position.0 as isize;
+        let Some((bg_x0, fg_x0, len)) = overlap(xsize, self.image_size.0 as usize, -fg_x0) else {
+            return;
+        };
+        let (bg_x1, fg_x1) = (bg_x0 + len, fg_x0 + len);
 
         // TODO(szabadka): Allocate a buffer for this when building the stage instead of when
         // executing it.
@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::render::{Channels, ChannelsMut, RenderPipelineInOutStage};
 use jxl_simd::{F32SimdVec, simd_function};
 
@@ -70,6 +71,10 @@ impl RenderPipelineInOutStage for HorizontalChromaUpsample {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Upsample)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -157,6 +162,10 @@ impl RenderPipelineInOutStage for VerticalChromaUpsample {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Upsample)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -5,7 +5,7 @@
 
 use std::any::Any;
 
-use crate::api::JxlCmsTransformer;
+use crate::api::{JxlCmsTransformer, JxlDecodeStage};
 use crate::error::Result;
 use crate::render::RenderPipelineInPlaceStage;
 
@@ -132,6 +132,10 @@ impl RenderPipelineInPlaceStage for CmsStage {
         c < self.in_channels.min(3) || self.black_channel == Some(c)
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
     fn init_local_state(&self, thread_index: usize) -> Result<Option<Box<dyn Any>>> {
         if self.transformers.is_empty() {
             return Ok(None);
@@ -6,6 +6,7 @@
 use std::sync::Arc;
 
 use crate::{
+    api::JxlDecodeStage,
     frame::quantizer::LfQuantFactors,
     headers::bit_depth::BitDepth,
     render::{Channels, ChannelsMut, RenderPipelineInOutStage, StageSpecialCase},
@@ -339,6 +340,10 @@ impl RenderPipelineInOutStage for ConvertF32ToU8Stage {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::OutputWrite)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -423,6 +428,10 @@ impl RenderPipelineInOutStage for ConvertI32ToU8Stage {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::OutputWrite)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -494,6 +503,10 @@ impl RenderPipelineInOutStage for ConvertF32ToU16Stage {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::OutputWrite)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -536,6 +549,10 @@ impl RenderPipelineInOutStage for ConvertF32ToF16Stage {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::OutputWrite)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -681,4 +698,53 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn test_int_to_float_custom_layouts() {
+        // (bits, exponent bits, sample, expected value), with values computed by hand from the
+        // sign, biased exponent and mantissa fields.
+        let test_cases: Vec<(u32, u32, i32, f32)> = vec![
+            // bfloat16: the upper half of an f32.
+            (16, 8, 0x3F80, 1.0),
+            (16, 8, 0xC040, -3.0),
+            (16, 8, 0x0001, f32::from_bits(0x0001_0000)),
+            (16, 8, 0x7F80, f32::INFINITY),
+            // 24 bits, of which 7 exponent bits with a bias of 63 and 16 mantissa bits.
+            (24, 7, 0x3F_0000, 1.0),
+            (24, 7, 0x3F_8000, 1.5),
+            (24, 7, 0xBD_0000, -0.25),
+            (24, 7, 0x7E_FFFF, (2.0 - 1.0 / 65536.0) * 2f32.powi(63)),
+            // Smallest subnormal: 2^(1 - 63 - 16).
+            (24, 7, 0x00_0001, 2f32.powi(-78)),
+            (24, 7, 0xFF_0000, f32::NEG_INFINITY),
+            // 11 bits, of which 4 exponent bits with a bias of 7 and 6 mantissa bits.
+            (11, 4, 0x1C0, 1.0),
+            (11, 4, 0x3BF, 254.0),
+            (11, 4, 0x020, 2f32.powi(-7)),
+            (11, 4, 0x001, 2f32.powi(-12)),
+            (11, 4, 0x400, -0.0),
+            // 5 bits, of which 2 exponent bits with a bias of 1 and 2 mantissa bits.
+            (5, 2, 0b00110, 1.5),
+            (5, 2, 0b00011, 0.75),
+            (5, 2, 0b11011, -3.5),
+        ];
+        for (bits, exponent_bits, sample, expected) in test_cases {
+            let mut output = [0.0];
+            int_to_float(
+                &[sample],
+                &mut output,
+                &BitDepth::float(bits, exponent_bits),
+                1,
+            );
+            assert_eq!(
+                output[0].to_bits(),
+                expected.to_bits(),
+                "{sample:#x} with {bits} bits and {exponent_bits} exponent bits: {} != {expected}",
+                output[0]
+            );
+        }
+        let mut output = [0.0];
+        int_to_float(&[0x7FC0], &mut output, &BitDepth::float(16, 8), 1);
+        assert!(output[0].is_nan());
+    }
 }
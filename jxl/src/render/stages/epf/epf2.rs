@@ -7,6 +7,7 @@ use std::sync::Arc;
 
 use crate::{
     BLOCK_DIM, MIN_SIGMA,
+    api::JxlDecodeStage,
     features::epf::SigmaSource,
     render::{
         Channels, ChannelsMut, RenderPipelineInOutStage,
@@ -146,6 +147,10 @@ impl RenderPipelineInOutStage for Epf2Stage {
         c < 3
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Epf)
+    }
+
     fn process_row_chunk(
         &self,
         (xpos, ypos): (usize, usize),
@@ -8,7 +8,8 @@ use std::sync::Arc;
 use crate::{
     error::Result,
     frame::ReferenceFrame,
-    headers::{FileHeader, extra_channels::ExtraChannelInfo, frame_header::*},
+    headers::{FileHeader, frame_header::*},
+    util::paranoid_check,
 };
 
 /// Does not directly modify the current image pixels, but extends the current image with
@@ -25,7 +26,6 @@ pub struct ExtendToImageDimensionsStage {
     pub image_size: (usize, usize),
     pub blending_info: BlendingInfo,
     pub ec_blending_info: Vec<BlendingInfo>,
-    pub extra_channels: Vec<ExtraChannelInfo>,
     pub reference_frames: Arc<[Option<ReferenceFrame>; 4]>,
     pub zeros: Vec<f32>,
 }
@@ -37,13 +37,22 @@ impl ExtendToImageDimensionsStage {
         file_header: &FileHeader,
         reference_frames: Arc<[Option<ReferenceFrame>; 4]>,
     ) -> Result<ExtendToImageDimensionsStage> {
+        paranoid_check(
+            || {
+                frame_header.blending_sources().all(|source| {
+                    reference_frames[source]
+                        .as_ref()
+                        .is_none_or(|reference| !reference.saved_before_color_transform())
+                })
+            },
+            "blending with a reference frame saved before the color transform",
+        )?;
         let xsize = file_header.size.xsize() as usize;
         Ok(ExtendToImageDimensionsStage {
             frame_origin: (frame_header.x0 as isize, frame_header.y0 as isize),
             image_size: (xsize, file_header.size.ysize() as usize),
             blending_info: frame_header.blending_info.clone(),
             ec_blending_info: frame_header.ec_blending_info.clone(),
-            extra_channels: file_header.image_metadata.extra_channel_info.clone(),
             reference_frames,
             zeros: vec![0f32; xsize],
         })
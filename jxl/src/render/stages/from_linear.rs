@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::color::tf;
 use crate::headers::color_encoding::CustomTransferFunction;
 use crate::render::RenderPipelineInPlaceStage;
@@ -20,16 +21,13 @@ impl FromLinearStage {
         Self { first_channel, tf }
     }
 
-    pub fn sdr(first_channel: usize, tf: CustomTransferFunction) -> Self {
-        let tf = TransferFunction::try_from(tf).expect("transfer function is not an SDR one");
-        Self::new(first_channel, tf)
-    }
-
+    #[cfg(test)]
     pub fn pq(first_channel: usize, intensity_target: f32) -> Self {
         let tf = TransferFunction::Pq { intensity_target };
         Self::new(first_channel, tf)
     }
 
+    #[cfg(test)]
     pub fn hlg(first_channel: usize, intensity_target: f32, luminance_rgb: [f32; 3]) -> Self {
         let tf = TransferFunction::Hlg {
             intensity_target,
@@ -117,6 +115,10 @@ impl RenderPipelineInPlaceStage for FromLinearStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -150,6 +152,12 @@ impl TransferFunction {
         matches!(self, Self::Gamma(g) if (*g - 1.0).abs() < f32::EPSILON)
     }
 
+    /// Returns true if this transfer function encodes luminances beyond those of SDR displays,
+    /// which is the case of PQ and HLG.
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, Self::Pq { .. } | Self::Hlg { .. })
+    }
+
     /// Create a TransferFunction from a JxlTransferFunction for use in FromLinearStage.
     /// For PQ/HLG, requires intensity_target and luminances from tone mapping info.
     /// Note: JxlTransferFunction::Gamma stores the encoding exponent (e.g., 1/2.2 for gamma 2.2).
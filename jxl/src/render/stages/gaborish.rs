@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::render::{Channels, ChannelsMut, RenderPipelineInOutStage};
 use jxl_simd::{F32SimdVec, simd_function};
 
@@ -97,6 +98,10 @@ impl RenderPipelineInOutStage for GaborishStage {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Gaborish)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -109,6 +114,213 @@ impl RenderPipelineInOutStage for GaborishStage {
     }
 }
 
+/// Weights of the center and of each neighbor in a normalized `[w, 1 - 2w, w]` kernel whose
+/// product with itself is the closest, in the least-squares sense, to the 3x3 Gaborish kernel
+/// with `weight1` for direct neighbors and `weight2` for diagonal ones.
+fn separable_weights(weight1: f32, weight2: f32) -> (f32, f32) {
+    // In f64, since the error is flat around its minimum.
+    let (weight1, weight2) = (weight1 as f64, weight2 as f64);
+    let weight_total = 1.0 + 4.0 * weight1 + 4.0 * weight2;
+    let (target0, target1, target2) = (
+        1.0 / weight_total,
+        weight1 / weight_total,
+        weight2 / weight_total,
+    );
+    let squared_error = |w: f64| {
+        let center = 1.0 - 2.0 * w;
+        (center * center - target0).powi(2)
+            + 4.0 * (center * w - target1).powi(2)
+            + 4.0 * (w * w - target2).powi(2)
+    };
+    // The error is unimodal over the kernels that blur less than a box filter.
+    let (mut low, mut high) = (0.0, 1.0 / 3.0);
+    for _ in 0..60 {
+        let third = (high - low) / 3.0;
+        if squared_error(low + third) < squared_error(high - third) {
+            high -= third;
+        } else {
+            low += third;
+        }
+    }
+    let weight = (low + high) / 2.0;
+    ((1.0 - 2.0 * weight) as f32, weight as f32)
+}
+
+/// Horizontal half of the separable approximation of [`GaborishStage`], followed by
+/// [`VerticalGaborishStage`].
+#[derive(Debug)]
+pub struct HorizontalGaborishStage {
+    channel: usize,
+    weight0: f32,
+    weight1: f32,
+}
+
+impl HorizontalGaborishStage {
+    pub fn new(channel: usize, weight1: f32, weight2: f32) -> Self {
+        let (weight0, weight1) = separable_weights(weight1, weight2);
+        Self {
+            channel,
+            weight0,
+            weight1,
+        }
+    }
+}
+
+impl std::fmt::Display for HorizontalGaborishStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "separable Gaborish filter for channel {}, horizontally",
+            self.channel
+        )
+    }
+}
+
+simd_function!(
+    hgaborish_process_dispatch,
+    d: D,
+    fn hgaborish_process(weight0: f32, weight1: f32, xsize: usize, input: &[f32], output: &mut [f32]) {
+        let w0 = D::F32Vec::splat(d, weight0);
+        let w1 = D::F32Vec::splat(d, weight1);
+        let len = D::F32Vec::LEN;
+        for (window, out) in input
+            .windows(len + 2)
+            .step_by(len)
+            .zip(output.chunks_exact_mut(len))
+            .take(xsize.div_ceil(len))
+        {
+            let left = D::F32Vec::load(d, window);
+            let center = D::F32Vec::load(d, &window[1..]);
+            let right = D::F32Vec::load(d, &window[2..]);
+            w1.mul_add(left + right, center * w0).store(out);
+        }
+    }
+);
+
+impl RenderPipelineInOutStage for HorizontalGaborishStage {
+    type InputT = f32;
+    type OutputT = f32;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (1, 0);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c == self.channel
+    }
+
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Gaborish)
+    }
+
+    fn process_row_chunk(
+        &self,
+        _position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<f32>,
+        output_rows: &mut ChannelsMut<f32>,
+        _state: Option<&mut dyn std::any::Any>,
+    ) {
+        hgaborish_process_dispatch(
+            self.weight0,
+            self.weight1,
+            xsize,
+            input_rows[0][0],
+            output_rows[0][0],
+        );
+    }
+}
+
+/// Vertical half of the separable approximation of [`GaborishStage`].
+#[derive(Debug)]
+pub struct VerticalGaborishStage {
+    channel: usize,
+    weight0: f32,
+    weight1: f32,
+}
+
+impl VerticalGaborishStage {
+    pub fn new(channel: usize, weight1: f32, weight2: f32) -> Self {
+        let (weight0, weight1) = separable_weights(weight1, weight2);
+        Self {
+            channel,
+            weight0,
+            weight1,
+        }
+    }
+}
+
+impl std::fmt::Display for VerticalGaborishStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "separable Gaborish filter for channel {}, vertically",
+            self.channel
+        )
+    }
+}
+
+simd_function!(
+    vgaborish_process_dispatch,
+    d: D,
+    fn vgaborish_process(
+        weight0: f32,
+        weight1: f32,
+        xsize: usize,
+        input_rows: &[&[f32]],
+        output: &mut [f32],
+    ) {
+        let w0 = D::F32Vec::splat(d, weight0);
+        let w1 = D::F32Vec::splat(d, weight1);
+        let [row_top, row_center, row_bottom] = input_rows else {
+            unreachable!();
+        };
+        let len = D::F32Vec::LEN;
+        for (((top, center), bottom), out) in row_top
+            .chunks_exact(len)
+            .zip(row_center.chunks_exact(len))
+            .zip(row_bottom.chunks_exact(len))
+            .zip(output.chunks_exact_mut(len))
+            .take(xsize.div_ceil(len))
+        {
+            let top = D::F32Vec::load(d, top);
+            let center = D::F32Vec::load(d, center);
+            let bottom = D::F32Vec::load(d, bottom);
+            w1.mul_add(top + bottom, center * w0).store(out);
+        }
+    }
+);
+
+impl RenderPipelineInOutStage for VerticalGaborishStage {
+    type InputT = f32;
+    type OutputT = f32;
+    const SHIFT: (u8, u8) = (0, 0);
+    const BORDER: (u8, u8) = (0, 1);
+
+    fn uses_channel(&self, c: usize) -> bool {
+        c == self.channel
+    }
+
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Gaborish)
+    }
+
+    fn process_row_chunk(
+        &self,
+        _position: (usize, usize),
+        xsize: usize,
+        input_rows: &Channels<f32>,
+        output_rows: &mut ChannelsMut<f32>,
+        _state: Option<&mut dyn std::any::Any>,
+    ) {
+        vgaborish_process_dispatch(
+            self.weight0,
+            self.weight1,
+            xsize,
+            &input_rows[0],
+            output_rows[0][0],
+        );
+    }
+}
+
 #[cfg(test)]
 mod test {
     use test_log::test;
@@ -142,4 +354,33 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn separable_consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || HorizontalGaborishStage::new(0, 0.115169525, 0.061248592),
+            (500, 500),
+            1,
+        )?;
+        crate::render::test::test_stage_consistency(
+            || VerticalGaborishStage::new(0, 0.115169525, 0.061248592),
+            (500, 500),
+            1,
+        )
+    }
+
+    #[test]
+    fn separable_weights_fit() {
+        // Separable kernels are recovered exactly.
+        let (w0, w1) = separable_weights(0.25, 0.0625);
+        assert!((w0 - 2.0 / 3.0).abs() < 1e-6 && (w1 - 1.0 / 6.0).abs() < 1e-6);
+        let (w0, w1) = separable_weights(0.0, 0.0);
+        assert!((w0 - 1.0).abs() < 1e-6 && w1.abs() < 1e-6);
+
+        // The default kernel is approximated with less blur along the axes, which the diagonal
+        // weights of the 3x3 kernel do not add to.
+        let (w0, w1) = separable_weights(0.115169525, 0.061248592);
+        assert!((w0 + 2.0 * w1 - 1.0).abs() < 1e-6);
+        assert!(w1 > 0.1 && w1 < 0.13, "{w1}");
+    }
 }
@@ -11,19 +11,18 @@ mod epf;
 mod extend;
 mod from_linear;
 mod gaborish;
+mod nearest_neighbor;
 mod noise;
 mod patches;
 mod premultiply_alpha;
 mod splines;
 mod spot;
 mod to_linear;
+mod tone_mapping;
 pub mod upsample;
 mod xyb;
 mod ycbcr;
 
-#[cfg(test)]
-mod nearest_neighbor;
-
 pub use blending::*;
 pub use chroma_upsample::*;
 pub use cms::*;
@@ -32,12 +31,14 @@ pub use epf::*;
 pub use extend::*;
 pub use from_linear::*;
 pub use gaborish::*;
+pub use nearest_neighbor::*;
 pub use noise::*;
 pub use patches::*;
 pub use premultiply_alpha::*;
 pub use splines::*;
 pub use spot::*;
-pub use to_linear::{ToLinearStage, TransferFunction as ToLinearTransferFunction};
+pub use to_linear::ToLinearStage;
+pub use tone_mapping::*;
 pub use upsample::*;
 pub use xyb::*;
 pub use ycbcr::*;
@@ -3,7 +3,10 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::render::{Channels, ChannelsMut, RenderPipelineInOutStage};
+
+/// Upsamples a channel 2x in both directions by repeating each sample.
 pub struct NearestNeighbourUpsample {
     channel: usize,
 }
@@ -34,6 +37,10 @@ impl RenderPipelineInOutStage for NearestNeighbourUpsample {
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Upsample)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
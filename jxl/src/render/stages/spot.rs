@@ -9,8 +9,13 @@ use crate::render::RenderPipelineInPlaceStage;
 pub struct SpotColorStage {
     /// Spot color channel index
     spot_c: usize,
-    /// Spot color in linear RGBA
+    /// Spot color, in the color space of the color channels, and its solidity
     spot_color: [f32; 4],
+    /// Whether the color channels of the input are gray copies of the first one, which might not
+    /// be kept in sync by earlier stages. The stage then makes the first three channels RGB.
+    gray: bool,
+    /// Channel index of the alpha channel if the color channels are premultiplied by it
+    associated_alpha_c: Option<usize>,
 }
 
 impl std::fmt::Display for SpotColorStage {
@@ -20,11 +25,19 @@ impl std::fmt::Display for SpotColorStage {
 }
 
 impl SpotColorStage {
-    #[allow(unused, reason = "remove once we actually use this")]
-    pub fn new(spot_c_offset: usize, spot_color: [f32; 4]) -> Self {
+    pub fn new(
+        spot_c_offset: usize,
+        spot_color: [f32; 4],
+        gray: bool,
+        associated_alpha_c: Option<usize>,
+    ) -> Self {
+        let spot_c = 3 + spot_c_offset;
+        assert!(associated_alpha_c.is_none_or(|c| c >= 3 && c != spot_c));
         Self {
-            spot_c: 3 + spot_c_offset,
+            spot_c,
             spot_color,
+            gray,
+            associated_alpha_c,
         }
     }
 }
@@ -33,10 +46,11 @@ impl RenderPipelineInPlaceStage for SpotColorStage {
     type Type = f32;
 
     fn uses_channel(&self, c: usize) -> bool {
-        c < 3 || c == self.spot_c
+        c < 3 || c == self.spot_c || self.associated_alpha_c == Some(c)
     }
 
-    // `row` should only contain color channels and the spot channel.
+    // `row` should only contain color channels, the spot channel and the associated alpha
+    // channel, if any.
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -44,12 +58,17 @@ impl RenderPipelineInPlaceStage for SpotColorStage {
         row: &mut [&mut [f32]],
         _state: Option<&mut dyn std::any::Any>,
     ) {
-        let [row_r, row_g, row_b, row_s] = row else {
-            panic!(
-                "incorrect number of channels; expected 4, found {}",
+        let (row_s, row_a, row_rgb) = match (row, self.associated_alpha_c) {
+            ([r, g, b, s], None) => (s, None, [r, g, b]),
+            ([r, g, b, s, a], Some(c)) if c > self.spot_c => (s, Some(a), [r, g, b]),
+            ([r, g, b, a, s], Some(_)) => (s, Some(a), [r, g, b]),
+            (row, _) => panic!(
+                "incorrect number of channels; expected {}, found {}",
+                4 + self.associated_alpha_c.is_some() as usize,
                 row.len()
-            );
+            ),
         };
+        let [row_r, row_g, row_b] = row_rgb;
 
         let scale = self.spot_color[3];
         assert!(
@@ -57,12 +76,20 @@ impl RenderPipelineInPlaceStage for SpotColorStage {
                 && xsize <= row_g.len()
                 && xsize <= row_b.len()
                 && xsize <= row_s.len()
+                && row_a.as_ref().is_none_or(|row_a| xsize <= row_a.len())
         );
         for idx in 0..xsize {
             let mix = scale * row_s[idx];
-            row_r[idx] = mix * self.spot_color[0] + (1.0 - mix) * row_r[idx];
-            row_g[idx] = mix * self.spot_color[1] + (1.0 - mix) * row_g[idx];
-            row_b[idx] = mix * self.spot_color[2] + (1.0 - mix) * row_b[idx];
+            // Premultiplied samples are composited with a premultiplied spot color.
+            let alpha = row_a.as_ref().map_or(1.0, |row_a| row_a[idx]);
+            let (base_g, base_b) = if self.gray {
+                (row_r[idx], row_r[idx])
+            } else {
+                (row_g[idx], row_b[idx])
+            };
+            row_r[idx] = mix * alpha * self.spot_color[0] + (1.0 - mix) * row_r[idx];
+            row_g[idx] = mix * alpha * self.spot_color[1] + (1.0 - mix) * base_g;
+            row_b[idx] = mix * alpha * self.spot_color[2] + (1.0 - mix) * base_b;
         }
     }
 }
@@ -80,7 +107,7 @@ mod test {
     #[test]
     fn consistency() -> Result<()> {
         crate::render::test::test_stage_consistency(
-            || SpotColorStage::new(0, [0.0; 4]),
+            || SpotColorStage::new(0, [0.0; 4], false, None),
             (500, 500),
             4,
         )
@@ -97,7 +124,7 @@ mod test {
         input_b.row_mut(0).copy_from_slice(&[0.0, 0.0, 1.0]);
         input_s.row_mut(0).copy_from_slice(&[1.0, 1.0, 1.0]);
 
-        let stage = SpotColorStage::new(0, [0.5; 4]);
+        let stage = SpotColorStage::new(0, [0.5; 4], false, None);
         let output = make_and_run_simple_pipeline(
             stage,
             &[input_r, input_g, input_b, input_s],
@@ -110,6 +137,33 @@ mod test {
         assert_all_almost_abs_eq(output[1].row(0), &[0.25, 0.75, 0.25], 1e-6);
         assert_all_almost_abs_eq(output[2].row(0), &[0.25, 0.25, 0.75], 1e-6);
 
+        Ok(())
+    }
+    #[test]
+    fn gray_with_associated_alpha() -> Result<()> {
+        let mut input_r = Image::new((2, 1))?;
+        let mut input_s = Image::new((2, 1))?;
+        let mut input_a = Image::new((2, 1))?;
+        input_r.row_mut(0).copy_from_slice(&[0.2, 0.2]);
+        input_s.row_mut(0).copy_from_slice(&[1.0, 0.0]);
+        input_a.row_mut(0).copy_from_slice(&[0.5, 0.5]);
+        // The other color channels are ignored.
+        let input_g = Image::new((2, 1))?;
+        let input_b = Image::new((2, 1))?;
+
+        let stage = SpotColorStage::new(1, [1.0, 0.0, 0.5, 0.5], true, Some(3));
+        let output = make_and_run_simple_pipeline(
+            stage,
+            &[input_r, input_g, input_b, input_a, input_s],
+            (2, 1),
+            0,
+            256,
+        )?;
+
+        assert_all_almost_abs_eq(output[0].row(0), &[0.35, 0.2], 1e-6);
+        assert_all_almost_abs_eq(output[1].row(0), &[0.1, 0.2], 1e-6);
+        assert_all_almost_abs_eq(output[2].row(0), &[0.225, 0.2], 1e-6);
+
         Ok(())
     }
 }
@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::color::tf;
 use crate::headers::color_encoding::CustomTransferFunction;
 use crate::render::RenderPipelineInPlaceStage;
@@ -121,6 +122,10 @@ impl RenderPipelineInPlaceStage for ToLinearStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -0,0 +1,163 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::api::{JxlDecodeStage, Rec2408ToneMapper, gamut_map};
+use crate::headers::image_metadata::ToneMapping;
+use crate::render::RenderPipelineInPlaceStage;
+
+/// Luminance of the white of SDR displays, in nits.
+pub const SDR_INTENSITY_TARGET: f32 = 255.0;
+
+/// How much of the saturation of out of gamut colors is preserved, at the expense of their
+/// luminance.
+const PRESERVE_SATURATION: f32 = 0.3;
+
+pub(crate) fn register_tone_mapping(capabilities: &mut crate::Capabilities) {
+    capabilities.tone_mapping = true;
+}
+
+/// Map display-referred linear color samples, where 1.0 corresponds to the intensity target of
+/// the image, to the luminance range of SDR displays, where 1.0 corresponds to
+/// [`SDR_INTENSITY_TARGET`] nits.
+#[derive(Clone)]
+pub struct ToneMappingStage {
+    first_channel: usize,
+    tone_mapper: Rec2408ToneMapper,
+    luminances: [f32; 3],
+}
+
+impl ToneMappingStage {
+    /// Returns the stage that maps the luminances of an image with the given tone mapping
+    /// metadata to the SDR range, or `None` if they already fit in it.
+    pub fn to_sdr(
+        first_channel: usize,
+        tone_mapping: &ToneMapping,
+        luminances: [f32; 3],
+    ) -> Option<Self> {
+        if tone_mapping.intensity_target <= SDR_INTENSITY_TARGET {
+            return None;
+        }
+        let tone_mapper = Rec2408ToneMapper::new(
+            (tone_mapping.min_nits, tone_mapping.intensity_target),
+            (0.0, SDR_INTENSITY_TARGET),
+            luminances,
+        );
+        Some(Self {
+            first_channel,
+            tone_mapper,
+            luminances,
+        })
+    }
+}
+
+impl std::fmt::Display for ToneMappingStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let channel = self.first_channel;
+        write!(
+            f,
+            "Tone map to SDR for channel [{},{},{}]",
+            channel,
+            channel + 1,
+            channel + 2
+        )
+    }
+}
+
+impl RenderPipelineInPlaceStage for ToneMappingStage {
+    type Type = f32;
+
+    fn uses_channel(&self, c: usize) -> bool {
+        (self.first_channel..self.first_channel + 3).contains(&c)
+    }
+
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
+    fn process_row_chunk(
+        &self,
+        _position: (usize, usize),
+        xsize: usize,
+        row: &mut [&mut [f32]],
+        _state: Option<&mut dyn std::any::Any>,
+    ) {
+        let [row_r, row_g, row_b] = row else {
+            panic!(
+                "incorrect number of channels; expected 3, found {}",
+                row.len()
+            );
+        };
+        for ((r, g), b) in row_r[..xsize]
+            .iter_mut()
+            .zip(&mut row_g[..xsize])
+            .zip(&mut row_b[..xsize])
+        {
+            let mut rgb = [*r, *g, *b];
+            self.tone_mapper.tone_map(&mut rgb);
+            gamut_map(&mut rgb, &self.luminances, PRESERVE_SATURATION);
+            [*r, *g, *b] = rgb;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_log::test;
+
+    use super::*;
+    use crate::error::Result;
+    use crate::headers::encodings::Empty;
+
+    const LUMINANCE_BT2020: [f32; 3] = [0.2627, 0.678, 0.0593];
+
+    fn tone_mapping(intensity_target: f32) -> ToneMapping {
+        let mut tone_mapping = ToneMapping::default(&Empty {});
+        tone_mapping.intensity_target = intensity_target;
+        tone_mapping
+    }
+
+    #[test]
+    fn consistency() -> Result<()> {
+        crate::render::test::test_stage_consistency(
+            || ToneMappingStage::to_sdr(0, &tone_mapping(4000.0), LUMINANCE_BT2020).unwrap(),
+            (500, 500),
+            3,
+        )
+    }
+
+    #[test]
+    fn sdr_images_are_not_tone_mapped() {
+        assert!(ToneMappingStage::to_sdr(0, &tone_mapping(255.0), LUMINANCE_BT2020).is_none());
+    }
+
+    #[test]
+    fn highlights_are_compressed() {
+        let stage = ToneMappingStage::to_sdr(0, &tone_mapping(4000.0), LUMINANCE_BT2020).unwrap();
+        // Gray at 4000, 1000 and 100 nits, then a saturated red at the peak luminance.
+        let mut r = [1.0, 0.25, 0.025, 1.0];
+        let mut g = [1.0, 0.25, 0.025, 0.0];
+        let mut b = [1.0, 0.25, 0.025, 0.0];
+        stage.process_row_chunk((0, 0), 4, &mut [&mut r, &mut g, &mut b], None);
+        // The peak is mapped to SDR white, and darker grays become relatively brighter.
+        assert!((r[0] - 1.0).abs() < 0.01, "{}", r[0]);
+        assert!(r[1] > 0.8 && r[1] < r[0], "{}", r[1]);
+        assert!(
+            r[2] > 0.025 * 4000.0 / 255.0 * 0.8 && r[2] < r[1],
+            "{}",
+            r[2]
+        );
+        for c in [&r, &g, &b] {
+            assert_eq!(c[0], r[0]);
+            assert!(c[..3].iter().all(|v| (0.0..=1.0).contains(v)));
+        }
+        assert!(
+            r[3] <= 1.0 && g[3] >= 0.0 && r[3] > g[3],
+            "{} {} {}",
+            r[3],
+            g[3],
+            b[3]
+        );
+    }
+}
@@ -9,6 +9,7 @@
 use std::any::Any;
 
 use crate::{
+    api::JxlDecodeStage,
     headers::CustomTransformData,
     render::{Channels, ChannelsMut, RenderPipelineInOutStage},
 };
@@ -403,6 +404,10 @@ impl<const N: usize, const SHIFT: u8> RenderPipelineInOutStage for Upsample<N, S
         c == self.channel
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::Upsample)
+    }
+
     fn init_local_state(&self, _thread_index: usize) -> crate::error::Result<Option<Box<dyn Any>>> {
         Ok(Some(Box::new(UpsampleState::new()) as Box<dyn Any>))
     }
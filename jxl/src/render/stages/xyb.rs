@@ -4,8 +4,8 @@
 // license that can be found in the LICENSE file.
 
 use crate::api::{
-    JxlColorEncoding, JxlPrimaries, JxlTransferFunction, JxlWhitePoint, adapt_to_xyz_d50,
-    primaries_to_xyz, primaries_to_xyz_d50,
+    JxlColorEncoding, JxlDecodeStage, JxlPrimaries, JxlTransferFunction, JxlWhitePoint,
+    adapt_to_xyz_d50, primaries_to_xyz, primaries_to_xyz_d50,
 };
 use crate::error::Result;
 use crate::headers::{FileHeader, OpsinInverseMatrix};
@@ -61,13 +61,18 @@ impl OutputColorInfo {
         ]
     }
 
-    pub fn from_header(header: &FileHeader) -> Result<Self> {
-        let srgb_output = OutputColorInfo {
+    /// Output to sRGB, regardless of the color encoding of the image.
+    pub fn srgb(header: &FileHeader) -> Self {
+        OutputColorInfo {
             luminances: SRGB_LUMINANCES,
             intensity_target: header.image_metadata.tone_mapping.intensity_target,
             opsin: header.transform_data.opsin_inverse_matrix.clone(),
             tf: from_linear::TransferFunction::Srgb,
-        };
+        }
+    }
+
+    pub fn from_header(header: &FileHeader) -> Result<Self> {
+        let srgb_output = Self::srgb(header);
         if header.image_metadata.color_encoding.want_icc {
             return Ok(srgb_output);
         }
@@ -232,6 +237,10 @@ impl RenderPipelineInPlaceStage for XybStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
@@ -3,6 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::api::JxlDecodeStage;
 use crate::render::RenderPipelineInPlaceStage;
 use jxl_simd::{F32SimdVec, simd_function};
 
@@ -86,6 +87,10 @@ impl RenderPipelineInPlaceStage for YcbcrToRgbStage {
         (self.first_channel..self.first_channel + 3).contains(&c)
     }
 
+    fn timing_stage(&self) -> Option<JxlDecodeStage> {
+        Some(JxlDecodeStage::ColorConvert)
+    }
+
     fn process_row_chunk(
         &self,
         _position: (usize, usize),
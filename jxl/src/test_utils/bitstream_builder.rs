@@ -0,0 +1,306 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::headers::encodings::{U32, U32Coder};
+use crate::util::{FloorLog2, f16};
+
+// Entropy coded symbols are written with a prefix code that assigns 6 bits to each token, and a
+// hybrid uint configuration that stores values below 16 directly in the token, and larger values
+// as the position of their most significant bit followed by the remaining bits.
+const TOKEN_BITS: usize = 6;
+const SPLIT_EXPONENT: u32 = 4;
+
+/// Writes bits in the order in which [`crate::bit_reader::BitReader`] reads them.
+#[derive(Debug, Default, Clone)]
+pub struct BitstreamBuilder {
+    bytes: Vec<u8>,
+    num_bits: usize,
+    /// LZ77 `min_symbol` and `min_length` of the last histograms written, if they enable LZ77.
+    lz77: Option<(u32, u32)>,
+}
+
+impl BitstreamBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bits written so far.
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Writes the `n` lowest bits of `value`, which are read back by `BitReader::read(n)`.
+    pub fn write(&mut self, n: usize, value: u64) -> &mut Self {
+        assert!(n <= 64);
+        assert!(
+            n == 64 || value >> n == 0,
+            "{value} does not fit in {n} bits"
+        );
+        for i in 0..n {
+            if self.num_bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (self.num_bits % 8);
+            self.num_bits += 1;
+        }
+        self
+    }
+
+    pub fn write_bool(&mut self, value: bool) -> &mut Self {
+        self.write(1, value as u64)
+    }
+
+    /// Writes `value` with the first distribution of `coder` that can represent it.
+    pub fn write_u32(&mut self, coder: &U32Coder, value: u32) -> &mut Self {
+        let fits = |u: &U32| match *u {
+            U32::Bits(n) => n == 32 || value >> n == 0,
+            U32::BitsOffset { n, off } => value >= off && (n == 32 || (value - off) >> n == 0),
+            U32::Val(val) => val == value,
+        };
+        // The bits written for `value` with distribution `u`.
+        let bits = |u: &U32| match *u {
+            U32::Bits(n) => (n, value as u64),
+            U32::BitsOffset { n, off } => (n, (value - off) as u64),
+            U32::Val(_) => (0, 0),
+        };
+        let (n, bits) = match coder {
+            U32Coder::Direct(u) => {
+                assert!(fits(u), "{value} cannot be coded");
+                bits(u)
+            }
+            U32Coder::Select(u0, u1, u2, u3) => {
+                let (selector, u) = [u0, u1, u2, u3]
+                    .into_iter()
+                    .enumerate()
+                    .find(|(_, u)| fits(u))
+                    .unwrap_or_else(|| panic!("{value} cannot be coded"));
+                self.write(2, selector as u64);
+                bits(u)
+            }
+        };
+        self.write(n, bits)
+    }
+
+    /// Writes a signed `value` packed like [`crate::entropy_coding::decode::unpack_signed`]
+    /// expects.
+    pub fn write_i32(&mut self, coder: &U32Coder, value: i32) -> &mut Self {
+        self.write_u32(coder, pack_signed(value))
+    }
+
+    /// Writes an enum value with the coder used for enums without an explicit one.
+    pub fn write_enum(&mut self, value: u32) -> &mut Self {
+        const ENUM_CODER: U32Coder = U32Coder::Select(
+            U32::Val(0),
+            U32::Val(1),
+            U32::BitsOffset { n: 4, off: 2 },
+            U32::BitsOffset { n: 6, off: 18 },
+        );
+        self.write_u32(&ENUM_CODER, value)
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> &mut Self {
+        match value {
+            0 => self.write(2, 0),
+            1..=16 => self.write(2, 1).write(4, value - 1),
+            17..=272 => self.write(2, 2).write(8, value - 17),
+            _ => {
+                self.write(2, 3).write(12, value & 0xfff);
+                let mut rest = value >> 12;
+                let mut shift = 12;
+                while rest != 0 {
+                    self.write_bool(true);
+                    if shift == 60 {
+                        return self.write(4, rest);
+                    }
+                    self.write(8, rest & 0xff);
+                    rest >>= 8;
+                    shift += 8;
+                }
+                self.write_bool(false)
+            }
+        }
+    }
+
+    pub fn write_f16(&mut self, value: f32) -> &mut Self {
+        self.write(16, f16::from_f32(value).to_bits() as u64)
+    }
+
+    /// Writes a string, which is not required to be valid UTF-8.
+    pub fn write_string(&mut self, value: impl AsRef<[u8]>) -> &mut Self {
+        const LENGTH_CODER: U32Coder = U32Coder::Select(
+            U32::Val(0),
+            U32::Bits(4),
+            U32::BitsOffset { n: 5, off: 16 },
+            U32::BitsOffset { n: 10, off: 48 },
+        );
+        self.write_u32(&LENGTH_CODER, value.as_ref().len() as u32);
+        for byte in value.as_ref() {
+            self.write(8, *byte as u64);
+        }
+        self
+    }
+
+    /// Writes an `extensions` field, where each element of `extensions` is the index of an
+    /// extension and its payload.
+    pub fn write_extensions(&mut self, extensions: &[(u32, Vec<u8>)]) -> &mut Self {
+        let selector = extensions.iter().fold(0u64, |selector, (id, _)| {
+            assert_eq!(selector & (1 << id), 0, "duplicate extension {id}");
+            selector | 1 << id
+        });
+        self.write_u64(selector);
+        let mut sorted: Vec<_> = extensions.iter().collect();
+        sorted.sort_by_key(|(id, _)| *id);
+        for (_, payload) in sorted.iter() {
+            self.write_u64(payload.len() as u64 * 8);
+        }
+        for (_, payload) in sorted.iter() {
+            for byte in payload.iter() {
+                self.write(8, *byte as u64);
+            }
+        }
+        self
+    }
+
+    pub fn zero_pad_to_byte(&mut self) -> &mut Self {
+        let padding = (8 - self.num_bits % 8) % 8;
+        self.write(padding, 0)
+    }
+
+    /// Appends the bits written to `other`.
+    pub fn append(&mut self, other: &BitstreamBuilder) -> &mut Self {
+        if self.num_bits.is_multiple_of(8) {
+            self.bytes.extend_from_slice(&other.bytes);
+            self.num_bits += other.num_bits;
+            return self;
+        }
+        for i in 0..other.num_bits {
+            self.write(1, ((other.bytes[i / 8] >> (i % 8)) & 1) as u64);
+        }
+        self
+    }
+
+    /// Writes histograms for `num_contexts` contexts, which code the symbols written by
+    /// [`Self::write_symbol`]. Symbols of all contexts are coded in the same way, so they can be
+    /// written without knowing their context.
+    pub fn write_histograms(&mut self, num_contexts: usize) -> &mut Self {
+        // No LZ77.
+        self.write_bool(false);
+        self.lz77 = None;
+        self.write_clustered_codes(num_contexts)
+    }
+
+    /// Like [`Self::write_histograms`], but with LZ77 enabled, so that copies can be written
+    /// with [`Self::write_lz77_copy`]. Symbols must be smaller than `2^(min_symbol - 12)`, so
+    /// that their tokens are smaller than `min_symbol`.
+    pub fn write_lz77_histograms(
+        &mut self,
+        num_contexts: usize,
+        min_symbol: u32,
+        min_length: u32,
+    ) -> &mut Self {
+        const MIN_SYMBOL_CODER: U32Coder = U32Coder::Select(
+            U32::Val(224),
+            U32::Val(512),
+            U32::Val(4096),
+            U32::BitsOffset { n: 15, off: 8 },
+        );
+        const MIN_LENGTH_CODER: U32Coder = U32Coder::Select(
+            U32::Val(3),
+            U32::Val(4),
+            U32::BitsOffset { n: 2, off: 5 },
+            U32::BitsOffset { n: 8, off: 9 },
+        );
+        assert!(min_symbol < 1 << TOKEN_BITS);
+        self.write_bool(true)
+            .write_u32(&MIN_SYMBOL_CODER, min_symbol)
+            .write_u32(&MIN_LENGTH_CODER, min_length);
+        // Copy lengths use the same hybrid uint configuration as symbols.
+        self.write(4, SPLIT_EXPONENT as u64).write(3, 0).write(3, 0);
+        self.lz77 = Some((min_symbol, min_length));
+        // The distances have their own context, which also uses histogram 0.
+        self.write_clustered_codes(num_contexts + 1)
+    }
+
+    fn write_clustered_codes(&mut self, num_contexts: usize) -> &mut Self {
+        if num_contexts > 1 {
+            // Simple context map, with all contexts mapped to histogram 0.
+            self.write_bool(true).write(2, 0);
+        }
+        // Prefix codes, with the hybrid uint configuration described above.
+        self.write_bool(true);
+        self.write(4, SPLIT_EXPONENT as u64).write(3, 0).write(3, 0);
+        // The alphabet size minus one, as a varint.
+        let max_symbol = (1u64 << TOKEN_BITS) - 1;
+        let nbits = max_symbol.floor_log2();
+        self.write_bool(true)
+            .write(4, nbits)
+            .write(nbits as usize, max_symbol - (1 << nbits));
+        // A complex prefix code, whose code length code only has a code for `TOKEN_BITS`, so that
+        // reading code lengths assigns it to every symbol without consuming any bit.
+        const CODE_LENGTH_CODE_ORDER: [usize; 18] =
+            [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+        self.write(2, 0);
+        for code_length in CODE_LENGTH_CODE_ORDER {
+            if code_length == TOKEN_BITS {
+                // Code length code length 1.
+                self.write(4, 0b0111);
+            } else {
+                // Code length code length 0.
+                self.write(2, 0);
+            }
+        }
+        self
+    }
+
+    /// Writes `value` with histograms written by [`Self::write_histograms`].
+    pub fn write_symbol(&mut self, value: u32) -> &mut Self {
+        self.write_uint(0, value)
+    }
+
+    /// Writes a copy of `length` symbols, starting `distance_symbol + 1` symbols back or at the
+    /// special distance `distance_symbol` for image contexts, with histograms written by
+    /// [`Self::write_lz77_histograms`].
+    /// `length` can exceed `u32::MAX`, as long as `length - min_length` does not.
+    pub fn write_lz77_copy(&mut self, length: u64, distance_symbol: u32) -> &mut Self {
+        let (min_symbol, min_length) = self.lz77.expect("LZ77 is not enabled");
+        let coded_length = u32::try_from(length - min_length as u64).unwrap();
+        self.write_uint(min_symbol, coded_length)
+            .write_symbol(distance_symbol)
+    }
+
+    /// Writes `value` with a token offset by `first_token`.
+    fn write_uint(&mut self, first_token: u32, value: u32) -> &mut Self {
+        if value < 1 << SPLIT_EXPONENT {
+            return self.write_token(first_token + value);
+        }
+        let nbits = value.floor_log2();
+        self.write_token(first_token + (1 << SPLIT_EXPONENT) + nbits - SPLIT_EXPONENT)
+            .write(nbits as usize, (value - (1 << nbits)) as u64)
+    }
+
+    pub fn write_signed_symbol(&mut self, value: i32) -> &mut Self {
+        self.write_symbol(pack_signed(value))
+    }
+
+    fn write_token(&mut self, token: u32) -> &mut Self {
+        assert!(token < 1 << TOKEN_BITS, "token {token} is too large");
+        // Canonical codes of equal length are assigned in symbol order, and their most
+        // significant bit is read first.
+        let code = token.reverse_bits() >> (32 - TOKEN_BITS);
+        self.write(TOKEN_BITS, code as u64)
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+fn pack_signed(value: i32) -> u32 {
+    if value >= 0 {
+        2 * value as u32
+    } else {
+        (-2 * value as i64 - 1) as u32
+    }
+}
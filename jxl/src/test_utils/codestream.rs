@@ -0,0 +1,807 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::collections::VecDeque;
+
+use super::BitstreamBuilder;
+use crate::{
+    BLOCK_DIM,
+    bit_reader::BitReader,
+    frame::modular::Predictor,
+    headers::{
+        Animation, FileHeader, JxlHeader, Orientation,
+        encodings::{U32, U32Coder, UnconditionalCoder},
+        frame_header::{BlendingMode, FrameHeader, FrameHeaderNonserialized},
+        toc::TOC_ENTRY_CODER,
+    },
+    util::Xorshift128Plus,
+};
+
+const SIZE_CODER: U32Coder = U32Coder::Select(
+    U32::BitsOffset { n: 9, off: 1 },
+    U32::BitsOffset { n: 13, off: 1 },
+    U32::BitsOffset { n: 18, off: 1 },
+    U32::BitsOffset { n: 30, off: 1 },
+);
+const CROP_CODER: U32Coder = U32Coder::Select(
+    U32::Bits(8),
+    U32::BitsOffset { n: 11, off: 256 },
+    U32::BitsOffset { n: 14, off: 2304 },
+    U32::BitsOffset { n: 30, off: 18688 },
+);
+
+const NUM_EXTRA_CHANNELS_CODER: U32Coder = U32Coder::Select(
+    U32::Val(0),
+    U32::Val(1),
+    U32::BitsOffset { n: 4, off: 2 },
+    U32::BitsOffset { n: 12, off: 1 },
+);
+const BLEND_MODE_CODER: U32Coder = U32Coder::Select(
+    U32::Val(0),
+    U32::Val(1),
+    U32::Val(2),
+    U32::BitsOffset { n: 2, off: 3 },
+);
+/// A meta-adaptive tree.
+#[derive(Debug, Clone)]
+pub enum MaTree {
+    Split {
+        property: u8,
+        value: i32,
+        /// Subtree for samples where the property is greater than `value`.
+        greater: Box<MaTree>,
+        other: Box<MaTree>,
+    },
+    Leaf {
+        predictor: Predictor,
+        offset: i32,
+        multiplier: u32,
+    },
+}
+
+impl MaTree {
+    pub fn leaf(predictor: Predictor, offset: i32) -> Self {
+        Self::Leaf {
+            predictor,
+            offset,
+            multiplier: 1,
+        }
+    }
+
+    pub fn split(property: u8, value: i32, greater: MaTree, other: MaTree) -> Self {
+        Self::Split {
+            property,
+            value,
+            greater: Box::new(greater),
+            other: Box::new(other),
+        }
+    }
+
+    /// Generates a tree of at most the given depth, with pseudo-random splits on properties
+    /// that do not depend on previous channels, and pseudo-random leaves.
+    pub fn random(rng: &mut Rng, depth: usize) -> Self {
+        fn generate(rng: &mut Rng, depth: usize, ranges: &mut [(i32, i32); 16]) -> MaTree {
+            let property = rng.below(16) as usize;
+            let (low, high) = ranges[property];
+            if depth == 0 || rng.below(4) == 0 || low == high {
+                return MaTree::Leaf {
+                    predictor: Predictor::try_from(rng.below(14)).unwrap(),
+                    offset: rng.below(9) as i32 - 4,
+                    multiplier: 1 + rng.below(2),
+                };
+            }
+            // Splits on values in [low, high), which keeps both subtrees reachable.
+            let value = low + rng.below((high - low).min(64) as u32) as i32;
+            ranges[property] = (value + 1, high);
+            let greater = generate(rng, depth - 1, ranges);
+            ranges[property] = (low, value);
+            let other = generate(rng, depth - 1, ranges);
+            ranges[property] = (low, high);
+            MaTree::split(property as u8, value, greater, other)
+        }
+        let mut ranges = [(-64, 64); 16];
+        // Channel and stream ids, and coordinates, are never negative.
+        for range in ranges[0..4].iter_mut() {
+            range.0 = 0;
+        }
+        generate(rng, depth, &mut ranges)
+    }
+
+    fn num_nodes(&self) -> usize {
+        match self {
+            Self::Split { greater, other, .. } => 1 + greater.num_nodes() + other.num_nodes(),
+            Self::Leaf { .. } => 1,
+        }
+    }
+
+    /// Writes the tree, followed by the histograms of the residuals.
+    fn write(&self, builder: &mut BitstreamBuilder) {
+        builder.write_histograms(6);
+        // Nodes are decoded in breadth-first order.
+        let mut queue = VecDeque::from([self]);
+        while let Some(node) = queue.pop_front() {
+            match node {
+                Self::Split {
+                    property,
+                    value,
+                    greater,
+                    other,
+                } => {
+                    builder
+                        .write_symbol(*property as u32 + 1)
+                        .write_signed_symbol(*value);
+                    queue.push_back(greater);
+                    queue.push_back(other);
+                }
+                Self::Leaf {
+                    predictor,
+                    offset,
+                    multiplier,
+                } => {
+                    assert_ne!(*multiplier, 0);
+                    let multiplier_log = multiplier.trailing_zeros();
+                    builder
+                        .write_symbol(0)
+                        .write_symbol(*predictor as u32)
+                        .write_signed_symbol(*offset)
+                        .write_symbol(multiplier_log)
+                        .write_symbol((multiplier >> multiplier_log) - 1);
+                }
+            }
+        }
+        builder.write_histograms(self.num_nodes().div_ceil(2));
+    }
+}
+
+/// Deterministic pseudo-random numbers.
+pub struct Rng {
+    generator: Xorshift128Plus,
+    buffer: [u64; Xorshift128Plus::N],
+    position: usize,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            generator: Xorshift128Plus::new_with_seed(seed),
+            buffer: [0; Xorshift128Plus::N],
+            position: Xorshift128Plus::N,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        if self.position == self.buffer.len() {
+            self.generator.fill(&mut self.buffer);
+            self.position = 0;
+        }
+        self.position += 1;
+        self.buffer[self.position - 1]
+    }
+
+    /// Returns a number in `[0, bound)`.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        (self.next_u64() % bound as u64) as u32
+    }
+}
+
+/// Position and size of a cropped frame.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameCrop {
+    pub x0: i32,
+    pub y0: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A regular or SkipProgressive modular frame of an 8-bit image, whose channels are all coded
+/// with the same tree and without transforms. Extra channels are blended like the color channels.
+#[derive(Debug, Clone)]
+pub struct FrameSpec {
+    /// Whether the frame is a SkipProgressive frame, which is only displayed once complete,
+    /// instead of a regular frame.
+    pub skip_progressive: bool,
+    pub tree: MaTree,
+    /// If set, residuals are pseudo-random values in `[-4, 4]` generated from this seed.
+    /// Otherwise, all residuals are zero, so that samples are determined by the tree.
+    pub residual_seed: Option<u64>,
+    pub crop: Option<FrameCrop>,
+    pub blending_mode: BlendingMode,
+    pub blend_source: u32,
+    pub save_as_reference: u32,
+    /// Whether the frame is saved as a reference before the color transform, which is only
+    /// possible for full frames that use [`BlendingMode::Replace`].
+    pub save_before_ct: bool,
+    /// Whether samples are in YCbCr, which is only possible if the image is not XYB encoded.
+    pub ycbcr: bool,
+    /// Duration in ticks, only written for animations.
+    pub duration: u32,
+    /// Whether the frame is marked as the last one, which by default only the last frame of the
+    /// codestream is.
+    pub is_last: Option<bool>,
+    pub name: String,
+    pub group_size_shift: u32,
+    /// Order in which sections are stored: section `i` is stored at position
+    /// `toc_permutation[i]`.
+    pub toc_permutation: Option<Vec<u32>>,
+    /// If set, noise is synthesized with this lookup table of 10-bit strengths.
+    pub noise: Option<[u32; 8]>,
+    /// Frame header flags set in addition to the ones of the features used, such as reserved
+    /// ones.
+    pub extra_flags: u64,
+    pub extensions: Vec<(u32, Vec<u8>)>,
+}
+
+impl Default for FrameSpec {
+    fn default() -> Self {
+        Self {
+            skip_progressive: false,
+            tree: MaTree::leaf(Predictor::Zero, 0),
+            residual_seed: None,
+            crop: None,
+            blending_mode: BlendingMode::Replace,
+            blend_source: 0,
+            save_as_reference: 0,
+            save_before_ct: false,
+            ycbcr: false,
+            duration: 0,
+            is_last: None,
+            name: String::new(),
+            group_size_shift: 1,
+            toc_permutation: None,
+            noise: None,
+            extra_flags: 0,
+            extensions: vec![],
+        }
+    }
+}
+
+impl FrameSpec {
+    fn size(&self, image: &CodestreamSpec) -> (usize, usize) {
+        match self.crop {
+            Some(crop) => (crop.width as usize, crop.height as usize),
+            None => (image.width as usize, image.height as usize),
+        }
+    }
+
+    fn group_dim(&self) -> usize {
+        128 << self.group_size_shift
+    }
+
+    fn num_groups(&self, image: &CodestreamSpec) -> (usize, usize) {
+        let (width, height) = self.size(image);
+        (
+            width.div_ceil(self.group_dim()),
+            height.div_ceil(self.group_dim()),
+        )
+    }
+
+    fn write(&self, builder: &mut BitstreamBuilder, image: &CodestreamSpec, is_last: bool) {
+        let sections = self.sections(image);
+        self.write_header(builder, image, is_last);
+        builder.write_bool(self.toc_permutation.is_some());
+        if let Some(permutation) = &self.toc_permutation {
+            assert_eq!(permutation.len(), sections.len());
+            builder.write_histograms(8);
+            builder.write_symbol(permutation.len() as u32);
+            // Lehmer code: the position of each element among the ones not yet used.
+            let mut unused: Vec<u32> = (0..permutation.len() as u32).collect();
+            for value in permutation {
+                let index = unused.iter().position(|x| x == value).unwrap();
+                builder.write_symbol(index as u32);
+                unused.remove(index);
+            }
+        }
+        builder.zero_pad_to_byte();
+        let mut stored: Vec<_> = sections.iter().collect();
+        if let Some(permutation) = &self.toc_permutation {
+            for (section, position) in sections.iter().zip(permutation) {
+                stored[*position as usize] = section;
+            }
+        }
+        for section in stored.iter() {
+            builder.write_u32(&TOC_ENTRY_CODER, (section.num_bits() / 8) as u32);
+        }
+        builder.zero_pad_to_byte();
+        for section in stored {
+            builder.append(section);
+        }
+    }
+
+    fn write_header(&self, builder: &mut BitstreamBuilder, image: &CodestreamSpec, is_last: bool) {
+        // Not all_default, regular or SkipProgressive frame, modular.
+        let frame_type = if self.skip_progressive { 3 } else { 0 };
+        builder.write_bool(false).write(2, frame_type).write(1, 1);
+        // No flags, except for noise and the extra ones.
+        builder.write_u64(self.noise.is_some() as u64 | self.extra_flags);
+        if image.xyb_encoded {
+            assert!(!self.ycbcr);
+        } else {
+            builder.write_bool(self.ycbcr);
+        }
+        if self.ycbcr {
+            // No chroma subsampling.
+            builder.write(2, 0).write(2, 0).write(2, 0);
+        }
+        // No upsampling of color and extra channels.
+        for _ in 0..=image.extra_channels.len() {
+            builder.write_u32(
+                &U32Coder::Select(U32::Val(1), U32::Val(2), U32::Val(4), U32::Val(8)),
+                1,
+            );
+        }
+        builder.write(2, self.group_size_shift as u64);
+        // One pass.
+        builder.write(2, 0);
+        builder.write_bool(self.crop.is_some());
+        let mut full_frame = true;
+        if let Some(crop) = self.crop {
+            builder
+                .write_i32(&CROP_CODER, crop.x0)
+                .write_i32(&CROP_CODER, crop.y0)
+                .write_u32(&CROP_CODER, crop.width)
+                .write_u32(&CROP_CODER, crop.height);
+            full_frame = crop.x0 <= 0
+                && crop.y0 <= 0
+                && crop.width as i64 + crop.x0 as i64 >= image.width as i64
+                && crop.height as i64 + crop.y0 as i64 >= image.height as i64;
+        }
+        for _ in 0..=image.extra_channels.len() {
+            builder.write_u32(&BLEND_MODE_CODER, self.blending_mode as u32);
+            if !image.extra_channels.is_empty() {
+                // Blending uses the first extra channel as alpha, without clamping.
+                if matches!(
+                    self.blending_mode,
+                    BlendingMode::Blend | BlendingMode::AlphaWeightedAdd
+                ) {
+                    builder.write(2, 0);
+                }
+                if matches!(
+                    self.blending_mode,
+                    BlendingMode::Blend | BlendingMode::AlphaWeightedAdd | BlendingMode::Mul
+                ) {
+                    builder.write_bool(false);
+                }
+            }
+            if !(full_frame && self.blending_mode == BlendingMode::Replace) {
+                builder.write_u32(
+                    &U32Coder::Select(U32::Val(0), U32::Val(1), U32::Val(2), U32::Val(3)),
+                    self.blend_source,
+                );
+            }
+        }
+        if let Some(animation) = &image.animation {
+            builder.write_u32(
+                &U32Coder::Select(U32::Val(0), U32::Val(1), U32::Bits(8), U32::Bits(32)),
+                self.duration,
+            );
+            if animation.have_timecodes {
+                builder.write(32, 0);
+            }
+        } else {
+            assert_eq!(self.duration, 0);
+        }
+        builder.write_bool(is_last);
+        let mut save_before_ct_coded = false;
+        if !is_last {
+            builder.write(2, self.save_as_reference as u64);
+            let can_be_referenced = self.duration == 0 || self.save_as_reference != 0;
+            if can_be_referenced && self.blending_mode == BlendingMode::Replace && full_frame {
+                builder.write_bool(self.save_before_ct);
+                save_before_ct_coded = true;
+            }
+        }
+        assert!(save_before_ct_coded || !self.save_before_ct);
+        builder.write_string(&self.name);
+        // Restoration filter: no Gabor-like filter and no EPF.
+        builder
+            .write_bool(false)
+            .write_bool(false)
+            .write(2, 0)
+            .write_u64(0);
+        builder.write_extensions(&self.extensions);
+    }
+
+    /// Returns the sections of the frame, in TOC order.
+    fn sections(&self, image: &CodestreamSpec) -> Vec<BitstreamBuilder> {
+        let (width, height) = self.size(image);
+        let group_dim = self.group_dim();
+        let mut lf_global = BitstreamBuilder::new();
+        for strength in self.noise.iter().flatten() {
+            lf_global.write(10, *strength as u64);
+        }
+        // Default LF quantization factors.
+        lf_global.write_bool(true);
+        // Global tree.
+        lf_global.write_bool(true);
+        self.tree.write(&mut lf_global);
+        // Global modular header: use the global tree, default weighted predictor parameters, no
+        // transforms.
+        write_group_header(&mut lf_global);
+
+        let mut residuals = self.residual_seed.map(Rng::new);
+        let mut write_group = |builder: &mut BitstreamBuilder, size: (usize, usize)| {
+            for _ in 0..size.0 * size.1 * image.num_channels() {
+                let residual = residuals.as_mut().map_or(0, |rng| rng.below(9) as i32 - 4);
+                builder.write_signed_symbol(residual);
+            }
+        };
+
+        let (groups_x, groups_y) = self.num_groups(image);
+        if groups_x * groups_y == 1 {
+            // All channels are decoded with the global data.
+            write_group(&mut lf_global, (width, height));
+            lf_global.zero_pad_to_byte();
+            return vec![lf_global];
+        }
+        lf_global.zero_pad_to_byte();
+        let lf_group_dim = group_dim * BLOCK_DIM;
+        let num_lf_groups = width.div_ceil(lf_group_dim) * height.div_ceil(lf_group_dim);
+        // LF groups and HF global data are empty.
+        let mut sections = vec![lf_global];
+        sections.extend((0..num_lf_groups + 1).map(|_| BitstreamBuilder::new()));
+        for gy in 0..groups_y {
+            for gx in 0..groups_x {
+                let mut group = BitstreamBuilder::new();
+                write_group_header(&mut group);
+                let group_width = group_dim.min(width - gx * group_dim);
+                let group_height = group_dim.min(height - gy * group_dim);
+                write_group(&mut group, (group_width, group_height));
+                group.zero_pad_to_byte();
+                sections.push(group);
+            }
+        }
+        sections
+    }
+}
+
+fn write_group_header(builder: &mut BitstreamBuilder) {
+    builder.write_bool(true).write_bool(true).write(2, 0);
+}
+
+/// An 8-bit extra channel of a [`CodestreamSpec`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExtraChannelSpec {
+    Alpha {
+        associated: bool,
+    },
+    /// A spot color, as linear RGB and solidity.
+    SpotColor([f32; 4]),
+    /// A channel of a type without fields of its own, such as depth or thermal, given as its
+    /// value in the codestream.
+    Other(u32),
+}
+
+impl ExtraChannelSpec {
+    fn write(&self, builder: &mut BitstreamBuilder) {
+        if *self == (Self::Alpha { associated: false }) {
+            builder.write_bool(true);
+            return;
+        }
+        // Not all_default, followed by the type, 8-bit integer samples, no dim_shift and no
+        // name.
+        builder.write_bool(false);
+        match self {
+            Self::Alpha { associated } => {
+                builder
+                    .write_enum(0)
+                    .write_bool(false)
+                    .write(2, 0)
+                    .write(2, 0)
+                    .write_string("")
+                    .write_bool(*associated);
+            }
+            Self::SpotColor(color) => {
+                builder
+                    .write_enum(2)
+                    .write_bool(false)
+                    .write(2, 0)
+                    .write(2, 0)
+                    .write_string("");
+                for value in color {
+                    builder.write_f16(*value);
+                }
+            }
+            Self::Other(ec_type) => {
+                // Alpha, spot colors and CFA channels have fields of their own.
+                assert!(![0, 2, 5].contains(ec_type));
+                builder
+                    .write_enum(*ec_type)
+                    .write_bool(false)
+                    .write(2, 0)
+                    .write(2, 0)
+                    .write_string("");
+            }
+        }
+    }
+}
+
+/// A codestream of an 8-bit image made of modular frames, which are optionally XYB encoded.
+#[derive(Debug, Clone)]
+pub struct CodestreamSpec {
+    pub width: u32,
+    pub height: u32,
+    pub animation: Option<Animation>,
+    pub orientation: Orientation,
+    pub xyb_encoded: bool,
+    /// Whether the image is in the gray sRGB color space instead of sRGB.
+    pub grayscale: bool,
+    /// If set, the image is in the BT.2100 PQ color space instead of sRGB, and has this
+    /// intensity target.
+    pub pq_intensity_target: Option<f32>,
+    /// If set, color samples are floats with these bits per sample and exponent bits, and
+    /// modular samples hold their bits.
+    pub float_samples: Option<(u32, u32)>,
+    pub extra_channels: Vec<ExtraChannelSpec>,
+    /// Extensions of the image metadata.
+    pub extensions: Vec<(u32, Vec<u8>)>,
+    pub frames: Vec<FrameSpec>,
+}
+
+impl CodestreamSpec {
+    pub fn new(width: u32, height: u32, frames: Vec<FrameSpec>) -> Self {
+        Self {
+            width,
+            height,
+            animation: None,
+            orientation: Orientation::Identity,
+            xyb_encoded: false,
+            grayscale: false,
+            pq_intensity_target: None,
+            float_samples: None,
+            extra_channels: vec![],
+            extensions: vec![],
+            frames,
+        }
+    }
+
+    /// The number of modular channels of frames: color channels, of which XYB encoded images
+    /// always have three, followed by extra channels.
+    fn num_channels(&self) -> usize {
+        let color_channels = if self.grayscale && !self.xyb_encoded {
+            1
+        } else {
+            3
+        };
+        color_channels + self.extra_channels.len()
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut builder = BitstreamBuilder::new();
+        self.write_file_header(&mut builder);
+        for (i, frame) in self.frames.iter().enumerate() {
+            builder.zero_pad_to_byte();
+            let is_last = frame.is_last.unwrap_or(i + 1 == self.frames.len());
+            frame.write(&mut builder, self, is_last);
+        }
+        builder.finish()
+    }
+
+    pub fn write_file_header(&self, builder: &mut BitstreamBuilder) {
+        builder.write(8, 0xff).write(8, 0x0a);
+        // Size, never using the small encoding or a fixed aspect ratio.
+        builder
+            .write_bool(false)
+            .write_u32(&SIZE_CODER, self.height)
+            .write(3, 0)
+            .write_u32(&SIZE_CODER, self.width);
+        // Image metadata, with extra fields only for oriented, animated and HDR images.
+        let extra_fields = self.orientation != Orientation::Identity
+            || self.animation.is_some()
+            || self.pq_intensity_target.is_some();
+        builder.write_bool(false).write_bool(extra_fields);
+        if extra_fields {
+            // Orientation, no intrinsic size, no preview.
+            builder
+                .write(3, self.orientation as u64 - 1)
+                .write_bool(false)
+                .write_bool(false);
+            builder.write_bool(self.animation.is_some());
+        }
+        if let Some(animation) = &self.animation {
+            builder
+                .write_u32(
+                    &U32Coder::Select(
+                        U32::Val(100),
+                        U32::Val(1000),
+                        U32::BitsOffset { n: 10, off: 1 },
+                        U32::BitsOffset { n: 30, off: 1 },
+                    ),
+                    animation.tps_numerator,
+                )
+                .write_u32(
+                    &U32Coder::Select(
+                        U32::Val(1),
+                        U32::Val(1001),
+                        U32::BitsOffset { n: 8, off: 1 },
+                        U32::BitsOffset { n: 10, off: 1 },
+                    ),
+                    animation.tps_denominator,
+                )
+                .write_u32(
+                    &U32Coder::Select(U32::Val(0), U32::Bits(3), U32::Bits(16), U32::Bits(32)),
+                    animation.num_loops,
+                )
+                .write_bool(animation.have_timecodes);
+        }
+        match self.float_samples {
+            // 8-bit integer samples, modular_16bit_sufficient.
+            None => {
+                builder.write_bool(false).write(2, 0).write_bool(true);
+            }
+            Some((bits_per_sample, exponent_bits)) => {
+                builder
+                    .write_bool(true)
+                    .write_u32(
+                        &U32Coder::Select(
+                            U32::Val(32),
+                            U32::Val(16),
+                            U32::Val(24),
+                            U32::BitsOffset { n: 6, off: 1 },
+                        ),
+                        bits_per_sample,
+                    )
+                    .write(4, exponent_bits as u64 - 1)
+                    .write_bool(false);
+            }
+        }
+        builder.write_u32(&NUM_EXTRA_CHANNELS_CODER, self.extra_channels.len() as u32);
+        for extra_channel in &self.extra_channels {
+            extra_channel.write(builder);
+        }
+        builder.write_bool(self.xyb_encoded);
+        match self.pq_intensity_target {
+            // sRGB, and default tone mapping.
+            None if !self.grayscale => {
+                builder.write_bool(true);
+                if extra_fields {
+                    builder.write_bool(true);
+                }
+            }
+            // Gray, D65 white point, sRGB transfer function and relative rendering intent, and
+            // default tone mapping.
+            None => {
+                builder
+                    .write_bool(false)
+                    .write_bool(false)
+                    .write_enum(1)
+                    .write_enum(1)
+                    .write_bool(false)
+                    .write_enum(13)
+                    .write_enum(1);
+                if extra_fields {
+                    builder.write_bool(true);
+                }
+            }
+            Some(intensity_target) => {
+                assert!(!self.grayscale);
+                // RGB, D65 white point, BT.2100 primaries, PQ transfer function and relative
+                // rendering intent.
+                builder
+                    .write_bool(false)
+                    .write_bool(false)
+                    .write_enum(0)
+                    .write_enum(1)
+                    .write_enum(9)
+                    .write_bool(false)
+                    .write_enum(16)
+                    .write_enum(1);
+                // Tone mapping with no minimum luminance.
+                builder
+                    .write_bool(false)
+                    .write_f16(intensity_target)
+                    .write_f16(0.0)
+                    .write_bool(false)
+                    .write_f16(0.0);
+            }
+        }
+        builder.write_extensions(&self.extensions);
+        // Default transform data.
+        builder.write_bool(true);
+    }
+}
+
+/// How a frame header whose fields all have their default values is coded. Such a header
+/// describes a single-pass regular VarDCT frame that covers the whole image, is the last one, and
+/// uses the default restoration filters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultFrameHeader {
+    /// Only `all_default` is coded.
+    AllDefault,
+    /// All fields are coded, except those of the restoration filter, which uses its own
+    /// `all_default`.
+    DefaultRestorationFilter,
+    /// All fields, including those of the restoration filter, are coded.
+    Explicit,
+}
+
+impl DefaultFrameHeader {
+    pub const ALL: [Self; 3] = [
+        Self::AllDefault,
+        Self::DefaultRestorationFilter,
+        Self::Explicit,
+    ];
+
+    pub fn write(self, builder: &mut BitstreamBuilder, nonserialized: &FrameHeaderNonserialized) {
+        builder.write_bool(self == Self::AllDefault);
+        if self == Self::AllDefault {
+            return;
+        }
+        // Regular frame, VarDCT, no flags.
+        builder.write(2, 0).write(1, 0).write_u64(0);
+        if !nonserialized.xyb_encoded {
+            // No YCbCr.
+            builder.write_bool(false);
+        }
+        // No upsampling of color and extra channels.
+        for _ in 0..=nonserialized.num_extra_channels {
+            builder.write(2, 0);
+        }
+        if nonserialized.xyb_encoded {
+            // Default quantization matrix scales.
+            builder.write(3, 3).write(3, 2);
+        }
+        // One pass, no crop.
+        builder.write(2, 0).write_bool(false);
+        // Color and extra channels replace the canvas, so no blending source is coded.
+        for _ in 0..=nonserialized.num_extra_channels {
+            builder.write(2, 0);
+        }
+        if nonserialized.have_animation {
+            // No duration.
+            builder.write(2, 0);
+        }
+        if nonserialized.have_timecode {
+            builder.write(32, 0);
+        }
+        // The last frame, which cannot be saved as a reference, and has no name.
+        builder.write_bool(true).write_string("");
+        if self == Self::DefaultRestorationFilter {
+            builder.write_bool(true);
+        } else {
+            // Gabor-like filter with default weights, 2 EPF iterations with default sharpness,
+            // weights and sigmas, and no extensions.
+            builder
+                .write_bool(false)
+                .write_bool(true)
+                .write_bool(false)
+                .write(2, 2)
+                .write_bool(false)
+                .write_bool(false)
+                .write_bool(false)
+                .write_u64(0);
+        }
+        // No extensions.
+        builder.write_u64(0);
+    }
+}
+
+/// Replaces the header of the first frame of `codestream` with a default one coded as `header`,
+/// and keeps the rest of the frame. The image must not have an ICC profile or a preview, so that
+/// the frame directly follows the image header, and the TOC of the frame must not be permuted.
+pub fn with_default_frame_header(codestream: &[u8], header: DefaultFrameHeader) -> Vec<u8> {
+    let mut br = BitReader::new(codestream);
+    let file_header = FileHeader::read(&mut br).unwrap();
+    assert!(!file_header.image_metadata.color_encoding.want_icc);
+    assert!(file_header.image_metadata.preview.is_none());
+    let nonserialized = file_header.frame_header_nonserialized();
+    br.jump_to_byte_boundary().unwrap();
+    let header_start = br.total_bits_read();
+    FrameHeader::read_unconditional(&(), &mut br, &nonserialized).unwrap();
+    // The TOC is not permuted, so its entries and the sections start at the next byte boundary.
+    assert_eq!(br.read(1).unwrap(), 0, "permuted TOC");
+    br.jump_to_byte_boundary().unwrap();
+    let toc_entries_start = br.total_bits_read() / 8;
+
+    let mut builder = BitstreamBuilder::new();
+    for byte in &codestream[..header_start / 8] {
+        builder.write(8, *byte as u64);
+    }
+    header.write(&mut builder, &nonserialized);
+    builder.write_bool(false).zero_pad_to_byte();
+    for byte in &codestream[toc_entries_start..] {
+        builder.write(8, *byte as u64);
+    }
+    builder.finish()
+}
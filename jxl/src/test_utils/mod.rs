@@ -0,0 +1,14 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Utilities to write synthetic codestreams at the bitstream level, for tests that need inputs
+//! which are impractical to produce with an encoder.
+
+mod bitstream_builder;
+mod codestream;
+pub mod scenarios;
+
+pub use bitstream_builder::BitstreamBuilder;
+pub use codestream::*;
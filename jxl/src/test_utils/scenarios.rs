@@ -0,0 +1,1605 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Constructors of synthetic codestreams for specific decoding scenarios.
+
+use super::{CodestreamSpec, ExtraChannelSpec, FrameCrop, FrameSpec, MaTree, Rng};
+use crate::{
+    frame::modular::Predictor,
+    headers::{Animation, frame_header::BlendingMode},
+};
+
+/// Tree that gives each color channel a constant value.
+pub fn constant_color_tree(color: [i32; 3]) -> MaTree {
+    constant_channels_tree(&color)
+}
+
+/// Tree that gives channel `c` the constant value `samples[c]`, and later channels the last one.
+pub fn constant_channels_tree(samples: &[i32]) -> MaTree {
+    let (last, rest) = samples.split_last().unwrap();
+    if rest.is_empty() {
+        return MaTree::leaf(Predictor::Zero, *last);
+    }
+    MaTree::split(
+        0,
+        rest.len() as i32 - 1,
+        MaTree::leaf(Predictor::Zero, *last),
+        constant_channels_tree(rest),
+    )
+}
+
+/// A single-frame image whose samples are all predicted with `tree`.
+pub fn modular_image(width: u32, height: u32, tree: MaTree) -> CodestreamSpec {
+    CodestreamSpec::new(
+        width,
+        height,
+        vec![FrameSpec {
+            tree,
+            ..Default::default()
+        }],
+    )
+}
+
+/// A `background` colored image, on which a frame of color `foreground` with the given crop is
+/// blended with `mode`.
+pub fn blended_crop(
+    width: u32,
+    height: u32,
+    background: [i32; 3],
+    foreground: [i32; 3],
+    crop: FrameCrop,
+    mode: BlendingMode,
+) -> CodestreamSpec {
+    CodestreamSpec::new(
+        width,
+        height,
+        vec![
+            FrameSpec {
+                tree: constant_color_tree(background),
+                ..Default::default()
+            },
+            FrameSpec {
+                tree: constant_color_tree(foreground),
+                crop: Some(crop),
+                blending_mode: mode,
+                ..Default::default()
+            },
+        ],
+    )
+}
+
+/// A 256x128 image with a frame of 224x128 pseudo-random samples with noise, in 128x128 groups,
+/// blended at horizontal offset `x0`, so that only part of it (or none of it) lands on the image.
+pub fn offset_noisy_frame(x0: i32) -> CodestreamSpec {
+    CodestreamSpec::new(
+        256,
+        128,
+        vec![
+            FrameSpec {
+                tree: constant_color_tree([50, 60, 70]),
+                ..Default::default()
+            },
+            FrameSpec {
+                tree: MaTree::leaf(Predictor::Zero, 100),
+                residual_seed: Some(7),
+                crop: Some(FrameCrop {
+                    x0,
+                    y0: 0,
+                    width: 224,
+                    height: 128,
+                }),
+                group_size_shift: 0,
+                noise: Some([200, 300, 400, 500, 600, 700, 800, 900]),
+                ..Default::default()
+            },
+        ],
+    )
+}
+
+/// A frame saved as reference 1, optionally before the color transform, to which a second
+/// frame is added. Samples of the first frame are in YCbCr if `ycbcr` is set.
+pub fn blend_with_saved_frame(
+    xyb_encoded: bool,
+    ycbcr: bool,
+    save_before_ct: bool,
+) -> CodestreamSpec {
+    CodestreamSpec {
+        xyb_encoded,
+        ..CodestreamSpec::new(
+            16,
+            16,
+            vec![
+                FrameSpec {
+                    tree: constant_color_tree([20, 60, 40]),
+                    save_as_reference: 1,
+                    save_before_ct,
+                    ycbcr,
+                    ..Default::default()
+                },
+                FrameSpec {
+                    tree: constant_color_tree([10, 5, 30]),
+                    blending_mode: BlendingMode::Add,
+                    blend_source: 1,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+}
+
+/// A 16x16 image whose first frames, of colors `saved`, are all saved as reference 1 in turn, so
+/// that each one overwrites the previous one, and whose last frame adds `foreground` to the
+/// contents of slot 1 in the 4x4 crop at (2, 3).
+pub fn overwritten_reference(saved: &[[i32; 3]], foreground: [i32; 3]) -> CodestreamSpec {
+    let mut frames: Vec<_> = saved
+        .iter()
+        .map(|color| FrameSpec {
+            tree: constant_color_tree(*color),
+            save_as_reference: 1,
+            ..Default::default()
+        })
+        .collect();
+    frames.push(FrameSpec {
+        tree: constant_color_tree(foreground),
+        crop: Some(FrameCrop {
+            x0: 2,
+            y0: 3,
+            width: 4,
+            height: 4,
+        }),
+        blending_mode: BlendingMode::Add,
+        blend_source: 1,
+        ..Default::default()
+    });
+    CodestreamSpec::new(16, 16, frames)
+}
+
+/// A 16x16 image with a `background` colored frame saved as reference 1, then a frame that adds
+/// `first` to slot 1 in the 8x8 crop at (0, 0) and saves the result back to slot 1, and a last
+/// frame that adds `second` to slot 1 in the 8x8 crop at (4, 4).
+pub fn self_referential_save(
+    background: [i32; 3],
+    first: [i32; 3],
+    second: [i32; 3],
+) -> CodestreamSpec {
+    let crop = |x0, y0| {
+        Some(FrameCrop {
+            x0,
+            y0,
+            width: 8,
+            height: 8,
+        })
+    };
+    CodestreamSpec::new(
+        16,
+        16,
+        vec![
+            FrameSpec {
+                tree: constant_color_tree(background),
+                save_as_reference: 1,
+                ..Default::default()
+            },
+            FrameSpec {
+                tree: constant_color_tree(first),
+                crop: crop(0, 0),
+                blending_mode: BlendingMode::Add,
+                blend_source: 1,
+                save_as_reference: 1,
+                ..Default::default()
+            },
+            FrameSpec {
+                tree: constant_color_tree(second),
+                crop: crop(4, 4),
+                blending_mode: BlendingMode::Add,
+                blend_source: 1,
+                ..Default::default()
+            },
+        ],
+    )
+}
+
+/// An image with 128x128 groups and pseudo-random residuals, whose sections are stored in the
+/// order given by `permutation`, if any. Images of 300x200 pixels have 9 sections.
+pub fn permuted_sections(width: u32, height: u32, permutation: Option<Vec<u32>>) -> CodestreamSpec {
+    CodestreamSpec::new(
+        width,
+        height,
+        vec![FrameSpec {
+            tree: MaTree::leaf(Predictor::Gradient, 0),
+            residual_seed: Some(1),
+            group_size_shift: 0,
+            toc_permutation: permutation,
+            ..Default::default()
+        }],
+    )
+}
+
+/// An image in which every group is a ramp that starts at 1 in its top-left corner, with groups
+/// of `128 << group_size_shift` pixels.
+pub fn group_ramps(width: u32, height: u32, group_size_shift: u32) -> CodestreamSpec {
+    CodestreamSpec::new(
+        width,
+        height,
+        vec![FrameSpec {
+            tree: MaTree::leaf(Predictor::West, 1),
+            group_size_shift,
+            ..Default::default()
+        }],
+    )
+}
+
+/// An image with extension payloads in both the image metadata and the frame header.
+pub fn with_extensions(
+    metadata_extensions: Vec<(u32, Vec<u8>)>,
+    frame_extensions: Vec<(u32, Vec<u8>)>,
+) -> CodestreamSpec {
+    let mut spec = modular_image(16, 16, MaTree::leaf(Predictor::West, 1));
+    spec.extensions = metadata_extensions;
+    spec.frames[0].extensions = frame_extensions;
+    spec
+}
+
+/// An animation with one frame of the given name and duration in ticks for each element of
+/// `frames`, at 10 ticks per second.
+pub fn named_animation(frames: &[(&str, u32)]) -> CodestreamSpec {
+    let frames = frames
+        .iter()
+        .enumerate()
+        .map(|(i, (name, duration))| FrameSpec {
+            tree: constant_color_tree([i as i32 * 10, 0, 0]),
+            duration: *duration,
+            name: name.to_string(),
+            ..Default::default()
+        })
+        .collect();
+    CodestreamSpec {
+        animation: Some(Animation {
+            tps_numerator: 10,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: false,
+        }),
+        ..CodestreamSpec::new(8, 8, frames)
+    }
+}
+
+/// A 1x1 animation of `num_frames` frames, each named with its index padded with zeros to
+/// `name_len` bytes.
+pub fn long_named_frames(num_frames: usize, name_len: usize) -> CodestreamSpec {
+    let frames = (0..num_frames)
+        .map(|i| FrameSpec {
+            duration: 1,
+            name: format!("{i:0>name_len$}"),
+            ..Default::default()
+        })
+        .collect();
+    CodestreamSpec {
+        animation: Some(Animation {
+            tps_numerator: 10,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: false,
+        }),
+        ..CodestreamSpec::new(1, 1, frames)
+    }
+}
+
+/// An animation of `num_frames` frames of 3x2 groups with noise, which are all keyframes.
+pub fn noisy_animation(num_frames: usize) -> CodestreamSpec {
+    let frames = (0..num_frames)
+        .map(|i| FrameSpec {
+            tree: constant_color_tree([i as i32 * 40 + 60, 100, 140]),
+            group_size_shift: 0,
+            duration: 1,
+            noise: Some([200, 300, 400, 500, 600, 700, 800, 900]),
+            ..Default::default()
+        })
+        .collect();
+    CodestreamSpec {
+        animation: Some(Animation {
+            tps_numerator: 10,
+            tps_denominator: 1,
+            num_loops: 0,
+            have_timecodes: false,
+        }),
+        ..CodestreamSpec::new(300, 200, frames)
+    }
+}
+
+/// A still image with a frame of red value 10, marked as last, followed by one of red value 20.
+pub fn frame_after_last_frame() -> CodestreamSpec {
+    let frames = [10, 20].map(|red| FrameSpec {
+        tree: constant_color_tree([red, 0, 0]),
+        is_last: Some(true),
+        ..Default::default()
+    });
+    CodestreamSpec::new(8, 8, frames.into())
+}
+
+/// An 8x8 image with extra channels, whose channels have the constant modular samples `samples`.
+pub fn constant_image(extra_channels: Vec<ExtraChannelSpec>, samples: &[i32]) -> CodestreamSpec {
+    CodestreamSpec {
+        extra_channels,
+        ..modular_image(8, 8, constant_channels_tree(samples))
+    }
+}
+
+/// A 32x24 image with a non-premultiplied alpha channel and `num_extra_channels - 1` more
+/// extra channels of various types, which are blended with alpha. Its first frame gives channel
+/// `c` the constant modular sample `background[c]`, and its second frame is a crop with the
+/// samples `foreground`.
+pub fn many_extra_channels(
+    num_extra_channels: usize,
+    background: &[i32],
+    foreground: &[i32],
+    crop: FrameCrop,
+) -> CodestreamSpec {
+    // Depth, thermal, unknown and optional channels.
+    let types = [1, 6, 15, 16];
+    let extra_channels = std::iter::once(ExtraChannelSpec::Alpha { associated: false })
+        .chain((1..num_extra_channels).map(|i| ExtraChannelSpec::Other(types[i % types.len()])))
+        .collect();
+    CodestreamSpec {
+        extra_channels,
+        ..CodestreamSpec::new(
+            32,
+            24,
+            vec![
+                FrameSpec {
+                    tree: constant_channels_tree(background),
+                    ..Default::default()
+                },
+                FrameSpec {
+                    tree: constant_channels_tree(foreground),
+                    crop: Some(crop),
+                    blending_mode: BlendingMode::Blend,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+}
+
+/// An 8x8 image with a depth channel followed by an alpha channel, whose second frame is blended
+/// with the depth channel as alpha, since blending always uses the first extra channel. The first
+/// frame gives channel `c` the constant modular sample `background[c]`, and the second frame
+/// `foreground[c]`.
+pub fn depth_as_blending_alpha(background: &[i32], foreground: &[i32]) -> CodestreamSpec {
+    CodestreamSpec {
+        extra_channels: vec![
+            ExtraChannelSpec::Other(1),
+            ExtraChannelSpec::Alpha { associated: false },
+        ],
+        ..CodestreamSpec::new(
+            8,
+            8,
+            vec![
+                FrameSpec {
+                    tree: constant_channels_tree(background),
+                    ..Default::default()
+                },
+                FrameSpec {
+                    tree: constant_channels_tree(foreground),
+                    blending_mode: BlendingMode::Blend,
+                    ..Default::default()
+                },
+            ],
+        )
+    }
+}
+
+/// An XYB encoded gray image in the BT.2100 PQ color space with the given intensity target, whose
+/// luminance is given by the modular sample of its Y channel.
+pub fn hdr_gray_image(intensity_target: f32, luma: i32) -> CodestreamSpec {
+    CodestreamSpec {
+        xyb_encoded: true,
+        pq_intensity_target: Some(intensity_target),
+        ..modular_image(8, 8, constant_color_tree([luma, 0, -luma / 2]))
+    }
+}
+
+/// A 512x256 image of 128x128 groups, whose first frame has pseudo-random samples and is a
+/// SkipProgressive frame if `skip_progressive` is set. If `foreground` is set, a second, regular
+/// frame of that color replaces the 64x64 crop at (32, 32) of the first one.
+pub fn skip_progressive_frame(
+    skip_progressive: bool,
+    foreground: Option<[i32; 3]>,
+) -> CodestreamSpec {
+    let mut frames = vec![FrameSpec {
+        skip_progressive,
+        tree: constant_color_tree([100, 150, 200]),
+        residual_seed: Some(7),
+        group_size_shift: 0,
+        ..Default::default()
+    }];
+    if let Some(foreground) = foreground {
+        frames.push(FrameSpec {
+            tree: constant_color_tree(foreground),
+            crop: Some(FrameCrop {
+                x0: 32,
+                y0: 32,
+                width: 64,
+                height: 64,
+            }),
+            ..Default::default()
+        });
+    }
+    CodestreamSpec::new(512, 256, frames)
+}
+
+/// An image with float samples of the given layout, whose color channels hold the given bits.
+pub fn float_image(bits_per_sample: u32, exponent_bits: u32, samples: [i32; 3]) -> CodestreamSpec {
+    CodestreamSpec {
+        float_samples: Some((bits_per_sample, exponent_bits)),
+        ..modular_image(8, 8, constant_color_tree(samples))
+    }
+}
+
+/// An 8x8 image of a single color whose frame header also sets `extra_flags`.
+pub fn flagged_frame(extra_flags: u64) -> CodestreamSpec {
+    let mut spec = modular_image(8, 8, constant_color_tree([30, 20, 10]));
+    spec.frames[0].extra_flags = extra_flags;
+    spec
+}
+
+/// A pseudo-random image of up to 300x300 pixels, with one or more groups, a random tree and
+/// random residuals.
+pub fn random_modular_image(seed: u64) -> CodestreamSpec {
+    let mut rng = Rng::new(seed);
+    let width = 1 + rng.below(300);
+    let height = 1 + rng.below(300);
+    let tree = MaTree::random(&mut rng, 4);
+    CodestreamSpec::new(
+        width,
+        height,
+        vec![FrameSpec {
+            tree,
+            residual_seed: Some(rng.next_u64()),
+            group_size_shift: rng.below(4),
+            ..Default::default()
+        }],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{
+            FrameFlags, JxlCms, JxlCmsTransformer, JxlColorEncoding, JxlColorProfile, JxlColorType,
+            JxlDecoder, JxlDecoderOptions, JxlExtraChannelType, JxlFrameHeader, JxlLevel,
+            JxlOutputBuffer, JxlPixelFormat, JxlPrimaries, JxlTransferFunction, JxlWhitePoint,
+            ProcessingResult, RenderingChoice, RenderingIntent, states,
+            tests::{decode, decode_with_input_ends},
+        },
+        bit_reader::BitReader,
+        entropy_coding::decode::{Histograms, SymbolReader},
+        error::Error,
+        headers::{
+            Orientation,
+            encodings::{Empty, U32, U32Coder, UnconditionalCoder},
+            frame_header::Flags,
+        },
+        image::{Image, Rect},
+        test_utils::BitstreamBuilder,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn decode_frames(data: &[u8]) -> Vec<Vec<Image<f32>>> {
+        decode(data, usize::MAX, false, false, None).unwrap().1
+    }
+
+    fn assert_pixels(frame: &[Image<f32>], expected: impl Fn(usize, usize, usize) -> i32) {
+        // Color samples are interleaved.
+        assert_eq!(frame.len(), 1);
+        let (width, height) = frame[0].size();
+        for y in 0..height {
+            for x in 0..width {
+                let (c, x) = (x % 3, x / 3);
+                let value = frame[0].row(y)[3 * x + c] * 255.0;
+                let expected = expected(c, x, y) as f32;
+                assert!(
+                    (value - expected).abs() < 1e-3,
+                    "channel {c} at ({x}, {y}): {value} != {expected}"
+                );
+            }
+        }
+    }
+
+    fn assert_same_frames(a: &[Vec<Image<f32>>], b: &[Vec<Image<f32>>]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b) {
+            assert_eq!(a.len(), b.len());
+            for (a, b) in a.iter().zip(b) {
+                crate::util::test::check_equal_images(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn builder_round_trip() {
+        let coder = U32Coder::Select(
+            U32::Val(0),
+            U32::Bits(4),
+            U32::BitsOffset { n: 5, off: 16 },
+            U32::BitsOffset { n: 10, off: 48 },
+        );
+        let values = [0, 1, 15, 16, 47, 48, 1071];
+        let u64_values = [0, 1, 16, 17, 272, 273, 1 << 20, u64::MAX];
+        let mut builder = BitstreamBuilder::new();
+        builder.write(3, 5).write_bool(true);
+        for value in values {
+            builder.write_u32(&coder, value);
+        }
+        for value in u64_values {
+            builder.write_u64(value);
+        }
+        builder.write_f16(0.5).write_string("name");
+        let data = builder.finish();
+
+        let mut br = BitReader::new(&data);
+        assert_eq!(br.read(3).unwrap(), 5);
+        assert_eq!(br.read(1).unwrap(), 1);
+        for value in values {
+            assert_eq!(
+                u32::read_unconditional(&coder, &mut br, &Empty {}).unwrap(),
+                value
+            );
+        }
+        for value in u64_values {
+            assert_eq!(
+                u64::read_unconditional(&(), &mut br, &Empty {}).unwrap(),
+                value
+            );
+        }
+        assert_eq!(
+            f32::read_unconditional(&(), &mut br, &Empty {}).unwrap(),
+            0.5
+        );
+        let name = String::read_unconditional(
+            &(),
+            &mut br,
+            &crate::headers::encodings::StringNonserialized { permissive: false },
+        );
+        assert_eq!(name.unwrap(), "name");
+    }
+
+    #[test]
+    fn symbols_round_trip() {
+        let values = [0, 1, 15, 16, 17, 1000, u32::MAX];
+        let mut builder = BitstreamBuilder::new();
+        builder.write_histograms(3);
+        for value in values {
+            builder.write_symbol(value);
+        }
+        builder
+            .write_signed_symbol(-5)
+            .write_signed_symbol(i32::MAX);
+        let data = builder.finish();
+
+        let mut br = BitReader::new(&data);
+        let histograms = Histograms::decode(3, &mut br, true).unwrap();
+        let mut reader = SymbolReader::new(&histograms, &mut br, None).unwrap();
+        for (i, value) in values.into_iter().enumerate() {
+            assert_eq!(reader.read_unsigned(&histograms, &mut br, i % 3), value);
+        }
+        assert_eq!(reader.read_signed(&histograms, &mut br, 0), -5);
+        assert_eq!(reader.read_signed(&histograms, &mut br, 2), i32::MAX);
+        reader.check_final_state(&histograms, &mut br).unwrap();
+    }
+
+    #[test]
+    fn modular_tree_leaves() {
+        // Right half is gray, left half has a different value for each channel.
+        let tree = MaTree::split(
+            3,
+            9,
+            MaTree::leaf(Predictor::Zero, 128),
+            constant_color_tree([10, 20, 30]),
+        );
+        let frames = decode_frames(&modular_image(20, 12, tree).build());
+        assert_eq!(frames.len(), 1);
+        assert_pixels(
+            &frames[0],
+            |c, x, _| if x > 9 { 128 } else { 10 * (c as i32 + 1) },
+        );
+    }
+
+    #[test]
+    fn modular_predictors() {
+        let frames =
+            decode_frames(&modular_image(24, 16, MaTree::leaf(Predictor::West, 1)).build());
+        // The first column is predicted from the row above.
+        assert_pixels(&frames[0], |_, x, y| (x + y + 1) as i32);
+
+        let frames =
+            decode_frames(&modular_image(24, 16, MaTree::leaf(Predictor::Gradient, 2)).build());
+        assert_pixels(&frames[0], |_, x, y| 2 * (x + y + 1) as i32);
+    }
+
+    #[test]
+    fn cropped_frame_blending() {
+        let crop = FrameCrop {
+            x0: -3,
+            y0: 5,
+            width: 10,
+            height: 20,
+        };
+        let inside = |x: usize, y: usize| x < 7 && y >= 5;
+        let spec = blended_crop(16, 16, [50, 60, 70], [1, 2, 3], crop, BlendingMode::Replace);
+        let frames = decode_frames(&spec.build());
+        assert_eq!(frames.len(), 1);
+        assert_pixels(&frames[0], |c, x, y| {
+            if inside(x, y) {
+                c as i32 + 1
+            } else {
+                50 + 10 * c as i32
+            }
+        });
+
+        let spec = blended_crop(16, 16, [50, 60, 70], [1, 2, 3], crop, BlendingMode::Add);
+        let frames = decode_frames(&spec.build());
+        assert_pixels(&frames[0], |c, x, y| {
+            50 + 10 * c as i32 + if inside(x, y) { c as i32 + 1 } else { 0 }
+        });
+    }
+
+    /// Decodes `data` to RGBA f32 samples, and returns how many groups were not rendered for
+    /// being outside of the image.
+    fn count_offcanvas_groups(data: &[u8]) -> Result<Option<usize>, Error> {
+        let mut input = data;
+        let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input)?
+        else {
+            panic!("image header is not complete");
+        };
+        decoder.set_pixel_format(JxlPixelFormat::rgba_f32(0));
+        let (width, height) = decoder.basic_info().size;
+        let mut image = Image::<f32>::new((4 * width, height))?;
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? else {
+            panic!("frame header is not complete");
+        };
+        let rect = Rect {
+            origin: (0, 0),
+            size: image.size(),
+        };
+        let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+            image.get_rect_mut(rect).into_raw(),
+        )];
+        let ProcessingResult::Complete { result: decoder } =
+            decoder.process(&mut input, &mut buffers)?
+        else {
+            panic!("frame is not complete");
+        };
+        Ok(decoder.offcanvas_groups())
+    }
+
+    #[test]
+    fn offcanvas_groups_are_not_rendered() -> Result<(), Error> {
+        // The frame has two groups, at x in [0, 128) and [128, 224), which are outside of the
+        // image when the frame is moved far enough to the left or right.
+        for (x0, skipped_groups) in [(0, 0), (-192, 1), (-96, 0), (160, 1), (-224, 2), (256, 2)] {
+            let data = offset_noisy_frame(x0).build();
+            let frames = decode_frames(&data);
+            // The simple pipeline renders the whole frame.
+            let simple_frames = decode(&data, usize::MAX, true, false, None)?.1;
+            assert_same_frames(&frames, &simple_frames);
+            let incremental_frames = decode(&data, 997, false, true, None)?.1;
+            assert_same_frames(&frames, &incremental_frames);
+            assert_eq!(
+                count_offcanvas_groups(&data)?,
+                Some(skipped_groups),
+                "x0 = {x0}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn blending_with_frame_saved_before_color_transform() {
+        for (xyb_encoded, ycbcr) in [(false, false), (false, true), (true, false)] {
+            let expected =
+                decode_frames(&blend_with_saved_frame(xyb_encoded, ycbcr, false).build());
+            let data = blend_with_saved_frame(xyb_encoded, ycbcr, true).build();
+            let result = decode(&data, usize::MAX, false, false, None);
+            assert!(
+                matches!(result, Err(Error::BlendingBeforeColorTransform(1))),
+                "{:?}",
+                result.err()
+            );
+            // In permissive mode, the saved frame is converted before blending, which gives the
+            // same result as saving it after the color transform.
+            let options = JxlDecoderOptions {
+                permissive: true,
+                ..Default::default()
+            };
+            let (_, frames) =
+                decode_with_input_ends(&data, |_| usize::MAX, options, false, false, None).unwrap();
+            assert_same_frames(&frames, &expected);
+        }
+    }
+
+    #[test]
+    fn later_saves_overwrite_reference_slots() {
+        let inside = |x: usize, y: usize| (2..6).contains(&x) && (3..7).contains(&y);
+        let spec = overwritten_reference(&[[90, 90, 90], [20, 40, 60]], [1, 2, 3]);
+        let frames = decode_frames(&spec.build());
+        assert_eq!(frames.len(), 1);
+        assert_pixels(&frames[0], |c, x, y| {
+            20 * (c as i32 + 1) + if inside(x, y) { c as i32 + 1 } else { 0 }
+        });
+        // Only the most recent save counts, however many frames were saved before it.
+        let spec = overwritten_reference(&[[90, 90, 90], [5, 5, 5], [20, 40, 60]], [1, 2, 3]);
+        assert_same_frames(&decode_frames(&spec.build()), &frames);
+    }
+
+    #[test]
+    fn frame_saved_to_its_blending_source() {
+        let spec = self_referential_save([20, 40, 60], [1, 2, 3], [10, 10, 10]);
+        let frames = decode_frames(&spec.build());
+        assert_eq!(frames.len(), 1);
+        // The last frame sees the second one blended on the first one, which in turn was blended
+        // on the first frame alone, not on itself.
+        assert_pixels(&frames[0], |c, x, y| {
+            let first = if x < 8 && y < 8 { c as i32 + 1 } else { 0 };
+            let second = if (4..12).contains(&x) && (4..12).contains(&y) {
+                10
+            } else {
+                0
+            };
+            20 * (c as i32 + 1) + first + second
+        });
+    }
+
+    #[test]
+    fn permuted_toc() {
+        let unpermuted = decode_frames(&permuted_sections(300, 200, None).build());
+        for permutation in [
+            vec![8, 7, 6, 5, 4, 3, 2, 1, 0],
+            vec![0, 1, 2, 8, 3, 7, 4, 6, 5],
+        ] {
+            let spec = permuted_sections(300, 200, Some(permutation.clone()));
+            let data = spec.build();
+            let (_, _, toc) = crate::util::test::read_headers_and_toc(&data).unwrap();
+            assert!(toc.permuted);
+            assert_eq!(*toc.permutation, permutation);
+            assert_same_frames(&decode_frames(&data), &unpermuted);
+            // Sections that are stored out of order are also decoded incrementally.
+            assert_same_frames(
+                &decode(&data, 17, false, true, None).unwrap().1,
+                &unpermuted,
+            );
+        }
+    }
+
+    #[test]
+    fn group_sizes() {
+        use crate::api::{GroupLayout, JxlDecoder, ProcessingResult, states};
+        for (width, height, shift, layout) in [
+            (300, 200, 0, (128, (3, 2), (1, 1))),
+            (1100, 20, 3, (1024, (2, 1), (1, 1))),
+            (2100, 10, 0, (128, (17, 1), (3, 1))),
+        ] {
+            let data = group_ramps(width, height, shift).build();
+            let group_dim = layout.0;
+            let frames = decode_frames(&data);
+            assert_pixels(&frames[0], |_, x, y| {
+                (x % group_dim + y % group_dim + 1) as i32
+            });
+
+            let mut input = &data[..];
+            let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+            let ProcessingResult::Complete { result: decoder } =
+                decoder.process(&mut input).unwrap()
+            else {
+                panic!("image header is not complete");
+            };
+            let ProcessingResult::Complete { result: frame } = decoder.process(&mut input).unwrap()
+            else {
+                panic!("frame header is not complete");
+            };
+            let header = frame.frame_header();
+            assert_eq!(header.group_dim(), group_dim);
+            assert_eq!(
+                header.groups,
+                GroupLayout {
+                    group_dim,
+                    lf_group_dim: 8 * group_dim,
+                    num_groups: layout.1,
+                    num_lf_groups: layout.2,
+                    num_passes: 1,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn extensions_are_skipped() {
+        let expected = decode_frames(&with_extensions(vec![], vec![]).build());
+        let spec = with_extensions(
+            vec![(0, b"metadata".to_vec()), (63, vec![0xff; 40])],
+            vec![(5, vec![1, 2, 3])],
+        );
+        assert_same_frames(&decode_frames(&spec.build()), &expected);
+    }
+
+    #[test]
+    fn animation_frames() {
+        let spec = named_animation(&[("first", 1), ("", 0), ("third frame", 25)]);
+        let headers = Rc::new(RefCell::new(vec![]));
+        let callback_headers = headers.clone();
+        let (_, frames) = decode(
+            &spec.build(),
+            usize::MAX,
+            false,
+            false,
+            Some(Box::new(move |frame, _| {
+                let header = frame.header();
+                callback_headers
+                    .borrow_mut()
+                    .push((header.name.clone(), header.duration));
+                Ok(())
+            })),
+        )
+        .unwrap();
+        assert_eq!(
+            *headers.borrow(),
+            [
+                ("first".to_string(), 1),
+                (String::new(), 0),
+                ("third frame".to_string(), 25)
+            ]
+        );
+        // Frames with no duration are blended with the next one.
+        assert_eq!(frames.len(), 2);
+        assert_pixels(&frames[0], |_, _, _| 0);
+        assert_pixels(&frames[1], |c, _, _| if c == 0 { 20 } else { 0 });
+    }
+
+    /// Returns the number of visible frames of `data`, skipping their pixels. The end of the
+    /// input is signaled to the decoder if it still expects frames.
+    fn count_frames(data: &[u8], permissive: bool) -> Result<usize, Error> {
+        let mut input = data;
+        let options = JxlDecoderOptions {
+            permissive,
+            ..Default::default()
+        };
+        let decoder = JxlDecoder::<states::Initialized>::new(options);
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input)?
+        else {
+            panic!("image header is not complete");
+        };
+        let mut frames = 0;
+        while decoder.has_more_frames() {
+            decoder = match decoder.process(&mut input)? {
+                ProcessingResult::Complete { result } => {
+                    frames += 1;
+                    let ProcessingResult::Complete { result } = result.skip_frame(&mut input)?
+                    else {
+                        panic!("frame is not complete");
+                    };
+                    result
+                }
+                ProcessingResult::NeedsMoreInput { mut fallback, .. } => {
+                    fallback.end_input()?;
+                    fallback
+                }
+            };
+        }
+        Ok(frames)
+    }
+
+    /// Wraps `codestream` in a container with a single `jxlc` box.
+    fn in_container(codestream: &[u8]) -> Vec<u8> {
+        let mut container = vec![
+            0, 0, 0, 12, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a, 0, 0, 0, 20,
+        ];
+        container.extend(b"ftypjxl \0\0\0\0jxl ");
+        container.extend((codestream.len() as u32 + 8).to_be_bytes());
+        container.extend(b"jxlc");
+        container.extend(codestream);
+        container
+    }
+
+    #[test]
+    fn frames_after_last_frame() -> Result<(), Error> {
+        // Bare codestreams may be followed by unrelated data, so the frame is in a container box
+        // whose length is known.
+        let data = in_container(&frame_after_last_frame().build());
+        let result = count_frames(&data, false);
+        assert!(
+            matches!(result, Err(Error::DataAfterLastFrame)),
+            "{result:?}"
+        );
+        // In permissive mode, the rest of the codestream is ignored.
+        assert_eq!(count_frames(&data, true)?, 1);
+        let options = JxlDecoderOptions {
+            permissive: true,
+            ..Default::default()
+        };
+        let (_, frames) =
+            decode_with_input_ends(&data, |_| usize::MAX, options, false, false, None)?;
+        assert_eq!(frames.len(), 1);
+        assert_pixels(&frames[0], |c, _, _| if c == 0 { 10 } else { 0 });
+        Ok(())
+    }
+
+    #[test]
+    fn animation_without_last_duration() -> Result<(), Error> {
+        let data = named_animation(&[("", 1), ("", 0)]).build();
+        let result = count_frames(&data, false);
+        assert!(
+            matches!(result, Err(Error::LastFrameWithoutDuration)),
+            "{result:?}"
+        );
+        assert_eq!(count_frames(&data, true)?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_last_frame() -> Result<(), Error> {
+        let mut spec = named_animation(&[("", 1), ("", 1)]);
+        spec.frames[1].is_last = Some(false);
+        let data = spec.build();
+        let result = count_frames(&data, false);
+        assert!(matches!(result, Err(Error::MissingLastFrame)), "{result:?}");
+        // In permissive mode, the frame before the end of the input is the last one.
+        assert_eq!(count_frames(&data, true)?, 2);
+        Ok(())
+    }
+
+    /// Returns the header of the first frame of `data`.
+    fn first_frame_header(data: &[u8], permissive: bool) -> Result<JxlFrameHeader, Error> {
+        let mut input = data;
+        let options = JxlDecoderOptions {
+            permissive,
+            ..Default::default()
+        };
+        let decoder = JxlDecoder::<states::Initialized>::new(options);
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? else {
+            panic!("image header is not complete");
+        };
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? else {
+            panic!("frame header is not complete");
+        };
+        Ok(decoder.frame_header())
+    }
+
+    #[test]
+    fn reserved_frame_flags() -> Result<(), Error> {
+        const RESERVED: u64 = 0x104;
+        let data = flagged_frame(RESERVED).build();
+        let result = first_frame_header(&data, false);
+        assert!(
+            matches!(result, Err(Error::ReservedFrameFlags(RESERVED))),
+            "{result:?}"
+        );
+
+        // In permissive mode, the reserved flags are reported but have no effect.
+        let header = first_frame_header(&data, true)?;
+        assert_eq!(header.raw_flags, RESERVED);
+        assert_eq!(header.flags, FrameFlags::default());
+        let options = JxlDecoderOptions {
+            permissive: true,
+            ..Default::default()
+        };
+        let (_, frames) =
+            decode_with_input_ends(&data, |_| usize::MAX, options, false, false, None)?;
+        let expected = decode_frames(&flagged_frame(0).build());
+        assert_same_frames(&frames, &expected);
+        assert_pixels(&frames[0], |c, _, _| [30, 20, 10][c]);
+
+        // Known flags are reported in both forms.
+        let data = flagged_frame(Flags::SKIP_ADAPTIVE_LF_SMOOTHING).build();
+        let header = first_frame_header(&data, false)?;
+        assert_eq!(header.raw_flags, Flags::SKIP_ADAPTIVE_LF_SMOOTHING);
+        assert!(header.flags.skip_adaptive_lf_smoothing);
+        Ok(())
+    }
+
+    /// Decodes the single frame of `data` to RGBA f32 samples, in `output_profile` if given.
+    /// Extra channels other than the main alpha channel are not output.
+    fn decode_rgba(
+        data: &[u8],
+        options: JxlDecoderOptions,
+        output_profile: Option<JxlColorProfile>,
+    ) -> Result<Image<f32>, Error> {
+        let mut input = data;
+        let decoder = JxlDecoder::<states::Initialized>::new(options);
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input)?
+        else {
+            panic!("image header is not complete");
+        };
+        decoder.set_pixel_format(JxlPixelFormat {
+            extra_channel_format: vec![None; decoder.basic_info().extra_channels.len()],
+            ..JxlPixelFormat::rgba_f32(0)
+        });
+        if let Some(profile) = output_profile {
+            decoder.set_output_color_profile(profile)?;
+        }
+        let (width, height) = decoder.basic_info().size;
+        let mut image = Image::new((4 * width, height))?;
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? else {
+            panic!("frame header is not complete");
+        };
+        let rect = Rect {
+            origin: (0, 0),
+            size: image.size(),
+        };
+        let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+            image.get_rect_mut(rect).into_raw(),
+        )];
+        let ProcessingResult::Complete { .. } = decoder.process(&mut input, &mut buffers)? else {
+            panic!("frame is not complete");
+        };
+        Ok(image)
+    }
+
+    #[test]
+    fn skip_progressive_frames_are_displayed_like_regular_ones() {
+        for foreground in [None, Some([0, 255, 0])] {
+            let skip = decode_frames(&skip_progressive_frame(true, foreground).build());
+            let regular = decode_frames(&skip_progressive_frame(false, foreground).build());
+            // The first frame is only displayed on its own if it is the last one.
+            assert_eq!(skip.len(), 1);
+            assert_same_frames(&skip, &regular);
+        }
+    }
+
+    /// Decodes the frames of `data[..end]` to RGBA f32 samples in a buffer of NaNs, and flushes
+    /// the frame that the input ends in.
+    fn flushed_preview(data: &[u8], end: usize) -> Result<Image<f32>, Error> {
+        let mut input = &data[..end];
+        let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input)?
+        else {
+            panic!("image header is not complete");
+        };
+        decoder.set_pixel_format(JxlPixelFormat::rgba_f32(0));
+        let (width, height) = decoder.basic_info().size;
+        let mut image = Image::new_with_value((4 * width, height), f32::NAN)?;
+        loop {
+            let rect = Rect {
+                origin: (0, 0),
+                size: image.size(),
+            };
+            let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+                image.get_rect_mut(rect).into_raw(),
+            )];
+            let ProcessingResult::Complete { result: frame } = decoder.process(&mut input)? else {
+                panic!("frame header is not complete");
+            };
+            match frame.process(&mut input, &mut buffers)? {
+                ProcessingResult::Complete { result } => decoder = result,
+                ProcessingResult::NeedsMoreInput { mut fallback, .. } => {
+                    fallback.flush_pixels(&mut buffers)?;
+                    return Ok(image);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skip_progressive_frames_are_not_flushed() -> Result<(), Error> {
+        let samples = |image: &Image<f32>| -> Vec<f32> {
+            (0..image.size().1)
+                .flat_map(|y| image.row(y).to_vec())
+                .collect()
+        };
+        // Flushing a truncated regular frame draws the groups that are available.
+        let data = skip_progressive_frame(false, None).build();
+        let preview = samples(&flushed_preview(&data, data.len() * 3 / 4)?);
+        assert!(preview.iter().any(|s| !s.is_nan()));
+        // A truncated SkipProgressive frame is not drawn at all.
+        let data = skip_progressive_frame(true, None).build();
+        let preview = samples(&flushed_preview(&data, data.len() * 3 / 4)?);
+        assert!(preview.iter().all(|s| s.is_nan()));
+
+        // Once complete, it is part of the preview of the next frame.
+        let data = skip_progressive_frame(true, Some([0, 255, 0])).build();
+        let complete = &decode_frames(&data)[0][0];
+        let preview = flushed_preview(&data, data.len() - 1)?;
+        for y in 0..256 {
+            for x in 0..512 {
+                if (32..96).contains(&x) && (32..96).contains(&y) {
+                    continue;
+                }
+                let pixel = &preview.row(y)[4 * x..][..4];
+                assert_eq!(&pixel[..3], &complete.row(y)[3 * x..][..3], "({x}, {y})");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn float_samples() -> Result<(), Error> {
+        for (bits_per_sample, exponent_bits, samples, expected) in [
+            // Half floats.
+            (16, 5, [0x3C00, 0xC100, 0x0001], [1.0, -2.5, 2f32.powi(-24)]),
+            // 24 bits, of which 7 exponent bits, with a bias of 63.
+            (
+                24,
+                7,
+                [0x3F_8000, 0xBD_0000, 0x7E_0000],
+                [1.5, -0.25, 2f32.powi(63)],
+            ),
+        ] {
+            let data = float_image(bits_per_sample, exponent_bits, samples).build();
+            let image = decode_rgba(&data, JxlDecoderOptions::default(), None)?;
+            for y in 0..image.size().1 {
+                for pixel in image.row(y).chunks_exact(4) {
+                    assert_eq!(pixel, [expected[0], expected[1], expected[2], 1.0]);
+                }
+            }
+        }
+        // Mantissas of 24 bits do not fit in f32 samples.
+        let data = float_image(32, 7, [0; 3]).build();
+        let err = decode_rgba(&data, JxlDecoderOptions::default(), None).unwrap_err();
+        assert!(matches!(err, Error::InvalidFloatBitDepth(32, 7)), "{err:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn sdr_and_hdr_rendering() -> Result<(), Error> {
+        // Gray at about 900 nits, in an image whose intensity target is 4000 nits.
+        let data = hdr_gray_image(4000.0, 700).build();
+        let options = |rendering| JxlDecoderOptions {
+            rendering_intent_override: rendering,
+            ..Default::default()
+        };
+        let linear = JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+            white_point: JxlWhitePoint::D65,
+            primaries: JxlPrimaries::BT2100,
+            transfer_function: JxlTransferFunction::Linear,
+            rendering_intent: RenderingIntent::Relative,
+        });
+        let peak = |rendering, output_profile: Option<&JxlColorProfile>| -> Result<f32, Error> {
+            let image = decode_rgba(&data, options(rendering), output_profile.cloned())?;
+            let color_samples = (0..image.size().1)
+                .flat_map(|y| image.row(y).chunks_exact(4))
+                .flat_map(|pixel| &pixel[..3]);
+            Ok(color_samples.copied().fold(0.0, f32::max))
+        };
+
+        // For HDR displays, 1.0 is the intensity target of the image.
+        let hdr = peak(Some(RenderingChoice::Hdr), Some(&linear))?;
+        assert!((hdr - 900.0 / 4000.0).abs() < 0.01, "{hdr}");
+        assert_eq!(peak(None, Some(&linear))?, hdr);
+        // For SDR displays, highlights are compressed so that the gray is close to SDR white.
+        let sdr = peak(Some(RenderingChoice::Sdr), Some(&linear))?;
+        assert!(sdr > 0.9 && sdr <= 1.0, "{sdr}");
+
+        // PQ outputs are always rendered for HDR displays.
+        let pq_hdr = decode_rgba(&data, options(Some(RenderingChoice::Hdr)), None)?;
+        let pq_sdr = decode_rgba(&data, options(Some(RenderingChoice::Sdr)), None)?;
+        crate::util::test::check_equal_images(&pq_hdr, &pq_sdr);
+        Ok(())
+    }
+
+    #[test]
+    fn random_images_decode_consistently() -> Result<(), Error> {
+        for seed in 0..12 {
+            let data = random_modular_image(seed).build();
+            let frames = decode(&data, usize::MAX, false, false, None)?.1;
+            let simple_frames = decode(&data, usize::MAX, true, false, None)?.1;
+            assert_same_frames(&frames, &simple_frames);
+            let incremental_frames = decode(&data, 1 + seed as usize * 37, false, true, None)?.1;
+            assert_same_frames(&frames, &incremental_frames);
+        }
+        Ok(())
+    }
+
+    /// Decodes the single frame of `data` to RGBA u8 samples, and returns them with whether the
+    /// orientation of the image was applied.
+    fn decode_rgba8(data: &[u8], adjust_orientation: bool) -> Result<(Image<u8>, bool), Error> {
+        let options = JxlDecoderOptions {
+            adjust_orientation,
+            ..Default::default()
+        };
+        let mut input = data;
+        let decoder = JxlDecoder::<states::Initialized>::new(options);
+        let ProcessingResult::Complete {
+            result: mut decoder,
+        } = decoder.process(&mut input)?
+        else {
+            panic!("image header is not complete");
+        };
+        decoder.set_pixel_format(JxlPixelFormat::rgba8(0));
+        let orientation_applied = decoder.orientation_applied();
+        let (width, height) = decoder.basic_info().size;
+        let mut image = Image::new((4 * width, height))?;
+        let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)? else {
+            panic!("frame header is not complete");
+        };
+        let rect = Rect {
+            origin: (0, 0),
+            size: image.size(),
+        };
+        let mut buffers = [JxlOutputBuffer::from_image_rect_mut(
+            image.get_rect_mut(rect).into_raw(),
+        )];
+        let ProcessingResult::Complete { .. } = decoder.process(&mut input, &mut buffers)? else {
+            panic!("frame is not complete");
+        };
+        Ok((image, orientation_applied))
+    }
+
+    #[test]
+    fn lossless_orientation_is_a_permutation() -> Result<(), Error> {
+        for seed in [0, 5, 9] {
+            let source_spec = random_modular_image(seed);
+            let (source, _) = decode_rgba8(&source_spec.build(), true)?;
+            let size = (source.size().0 / 4, source.size().1);
+            for orientation in 1..=8 {
+                let orientation: Orientation =
+                    num_traits::FromPrimitive::from_u32(orientation).unwrap();
+                let data = CodestreamSpec {
+                    orientation,
+                    ..source_spec.clone()
+                }
+                .build();
+
+                // The samples of the image as stored are moved to their display position,
+                // unchanged.
+                let (oriented, orientation_applied) = decode_rgba8(&data, true)?;
+                assert!(orientation_applied);
+                let oriented_size = orientation.map_size(size);
+                let mut expected = Image::new((4 * oriented_size.0, oriented_size.1))?;
+                for y in 0..size.1 {
+                    for x in 0..size.0 {
+                        let (dx, dy) = orientation.display_pixel((x, y), size);
+                        expected.row_mut(dy)[4 * dx..][..4]
+                            .copy_from_slice(&source.row(y)[4 * x..][..4]);
+                    }
+                }
+                crate::util::test::check_equal_images(&oriented, &expected);
+
+                let (stored, orientation_applied) = decode_rgba8(&data, false)?;
+                assert!(!orientation_applied);
+                crate::util::test::check_equal_images(&stored, &source);
+            }
+        }
+        Ok(())
+    }
+
+    /// Converts linear sRGB to Display P3, with the sRGB transfer function.
+    struct DisplayP3Cms;
+
+    struct DisplayP3Transformer;
+
+    fn display_p3() -> JxlColorProfile {
+        JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
+            white_point: JxlWhitePoint::D65,
+            primaries: JxlPrimaries::P3,
+            transfer_function: JxlTransferFunction::SRGB,
+            rendering_intent: RenderingIntent::Relative,
+        })
+    }
+
+    fn linear_srgb_to_display_p3(rgb: [f32; 3]) -> [f32; 3] {
+        const MATRIX: [[f32; 3]; 3] = [
+            [0.822462, 0.177538, 0.0],
+            [0.033194, 0.966806, 0.0],
+            [0.017083, 0.072397, 0.910520],
+        ];
+        MATRIX.map(|row| {
+            let linear: f32 = row.iter().zip(rgb).map(|(m, x)| m * x).sum();
+            if linear <= 0.0031308 {
+                12.92 * linear
+            } else {
+                1.055 * linear.powf(1.0 / 2.4) - 0.055
+            }
+        })
+    }
+
+    impl JxlCms for DisplayP3Cms {
+        fn initialize_transforms(
+            &self,
+            n: usize,
+            _max_pixels_per_transform: usize,
+            input: JxlColorProfile,
+            output: JxlColorProfile,
+            _intensity_target: f32,
+        ) -> Result<(usize, Vec<Box<dyn JxlCmsTransformer + Send>>), Error> {
+            assert!(
+                input == JxlColorProfile::Simple(JxlColorEncoding::linear_srgb(false)),
+                "{input}"
+            );
+            assert!(output == display_p3(), "{output}");
+            let transformers = (0..n)
+                .map(|_| Box::new(DisplayP3Transformer) as Box<dyn JxlCmsTransformer + Send>)
+                .collect();
+            Ok((3, transformers))
+        }
+    }
+
+    impl JxlCmsTransformer for DisplayP3Transformer {
+        fn do_transform(&mut self, input: &[f32], output: &mut [f32]) -> Result<(), Error> {
+            output.copy_from_slice(input);
+            self.do_transform_inplace(output)
+        }
+
+        fn do_transform_inplace(&mut self, inout: &mut [f32]) -> Result<(), Error> {
+            for pixel in inout.chunks_exact_mut(3) {
+                let converted = linear_srgb_to_display_p3(pixel.try_into().unwrap());
+                pixel.copy_from_slice(&converted);
+            }
+            Ok(())
+        }
+    }
+
+    /// Checks that all pixels of an RGBA `image` have the color `expected`.
+    fn assert_rgb(image: &Image<f32>, expected: [f32; 3]) {
+        for y in 0..image.size().1 {
+            for pixel in image.row(y).chunks_exact(4) {
+                for (value, expected) in pixel[..3].iter().zip(expected) {
+                    assert!(
+                        (value - expected).abs() < 2e-3,
+                        "{:?} != {expected:?}",
+                        &pixel[..3]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spot_colors_are_composited_in_output_space() -> Result<(), Error> {
+        // A saturated red spot color at half solidity over mid-gray. Like the samples of XYB
+        // encoded images, it is in linear sRGB.
+        let data = CodestreamSpec {
+            xyb_encoded: true,
+            ..constant_image(
+                vec![ExtraChannelSpec::SpotColor([1.0, 0.0, 0.0, 0.5])],
+                &[250, 0, -125, 255],
+            )
+        }
+        .build();
+        let options = |render_spot_colors, p3: bool| JxlDecoderOptions {
+            render_spot_colors,
+            cms: p3.then(|| Box::new(DisplayP3Cms) as Box<dyn JxlCms>),
+            ..Default::default()
+        };
+        for p3 in [false, true] {
+            let output_profile = p3.then(display_p3);
+            let background = decode_rgba(&data, options(false, p3), output_profile.clone())?;
+            let gray = background.row(0)[0];
+            assert!((0.3..0.7).contains(&gray), "{gray}");
+            assert_rgb(&background, [gray; 3]);
+
+            let spot = if p3 {
+                linear_srgb_to_display_p3([1.0, 0.0, 0.0])
+            } else {
+                [1.0, 0.0, 0.0]
+            };
+            let image = decode_rgba(&data, options(true, p3), output_profile)?;
+            assert_rgb(&image, spot.map(|spot| 0.5 * spot + 0.5 * gray));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn spot_colors_respect_alpha_premultiplication() -> Result<(), Error> {
+        for associated in [false, true] {
+            let data = constant_image(
+                vec![
+                    ExtraChannelSpec::Alpha { associated },
+                    ExtraChannelSpec::SpotColor([0.0, 0.0, 1.0, 0.5]),
+                ],
+                &[51, 51, 51, 102, 255],
+            )
+            .build();
+            let image = decode_rgba(&data, JxlDecoderOptions::default(), None)?;
+            assert!((image.row(0)[3] - 0.4).abs() < 1e-6);
+            // Premultiplied samples stay premultiplied, so the spot color is premultiplied too.
+            let blue = if associated { 0.4 } else { 1.0 };
+            assert_rgb(&image, [0.1, 0.1, 0.5 * blue + 0.1]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn spot_colors_of_gray_images() -> Result<(), Error> {
+        let data = CodestreamSpec {
+            grayscale: true,
+            ..constant_image(
+                vec![ExtraChannelSpec::SpotColor([0.0, 0.0, 1.0, 0.5])],
+                &[102, 255],
+            )
+        }
+        .build();
+        let options = |render_spot_colors| JxlDecoderOptions {
+            render_spot_colors,
+            ..Default::default()
+        };
+        let header = |options| -> Result<_, Error> {
+            let mut input = &data[..];
+            let decoder = JxlDecoder::<states::Initialized>::new(options);
+            let ProcessingResult::Complete { result: decoder } = decoder.process(&mut input)?
+            else {
+                panic!("image header is not complete");
+            };
+            Ok((decoder, input))
+        };
+
+        // Rendering spot colors promotes gray images to RGB.
+        let color_type = |options| -> Result<_, Error> {
+            Ok(header(options)?.0.current_pixel_format().color_type)
+        };
+        assert_eq!(color_type(options(false))?, JxlColorType::Grayscale);
+        assert_eq!(color_type(options(true))?, JxlColorType::Rgb);
+        let image = decode_rgba(&data, options(false), None)?;
+        assert_rgb(&image, [0.4; 3]);
+        let image = decode_rgba(&data, options(true), None)?;
+        assert_rgb(&image, [0.2, 0.2, 0.7]);
+
+        // Grayscale output of rendered spot colors is rejected.
+        let (mut decoder, mut input) = header(options(true))?;
+        decoder.set_pixel_format(JxlPixelFormat {
+            color_type: JxlColorType::Grayscale,
+            ..decoder.current_pixel_format().clone()
+        });
+        let result = decoder.process(&mut input);
+        assert!(
+            matches!(result, Err(Error::GrayscaleSpotColors)),
+            "{:?}",
+            result.err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn many_extra_channels_are_blended_per_channel() -> Result<(), Error> {
+        const NUM_EC: usize = 16;
+        // Channel 3 is alpha, the first extra channel.
+        let background: Vec<i32> = (0..3 + NUM_EC)
+            .map(|c| if c == 3 { 153 } else { 10 + 5 * c as i32 })
+            .collect();
+        let foreground: Vec<i32> = (0..3 + NUM_EC)
+            .map(|c| if c == 3 { 102 } else { 200 - 7 * c as i32 })
+            .collect();
+        // Partly outside of the 32x24 image.
+        let crop = FrameCrop {
+            x0: 20,
+            y0: -5,
+            width: 20,
+            height: 17,
+        };
+        let data = many_extra_channels(NUM_EC, &background, &foreground, crop).build();
+        let (_, frames) = decode_with_input_ends(
+            &data,
+            |_| usize::MAX,
+            JxlDecoderOptions::default(),
+            false,
+            false,
+            None,
+        )?;
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert_eq!(frame.len(), 1 + NUM_EC);
+        let samples_per_pixel = frame[0].size().0 / 32;
+
+        let (ba, fa) = (153.0 / 255.0, 102.0 / 255.0);
+        let alpha = fa + ba * (1.0 - fa);
+        let expected = |c: usize, inside: bool| -> f32 {
+            let (b, f) = (background[c] as f32 / 255.0, foreground[c] as f32 / 255.0);
+            match (inside, c) {
+                (false, _) => b,
+                (true, 3) => alpha,
+                (true, _) => (f * fa + b * ba * (1.0 - fa)) / alpha,
+            }
+        };
+        for y in 0..24 {
+            for x in 0..32 {
+                let inside = x >= 20 && y < 12;
+                for c in 0..3 + NUM_EC {
+                    let value = if c < 3 {
+                        frame[0].row(y)[samples_per_pixel * x + c]
+                    } else {
+                        frame[c - 2].row(y)[x]
+                    };
+                    let expected = expected(c, inside);
+                    assert!(
+                        (value - expected).abs() < 1e-5,
+                        "channel {c} at ({x}, {y}): {value} != {expected}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn blending_with_depth_as_alpha() -> Result<(), Error> {
+        let background = [51, 51, 51, 255, 255];
+        // Depth samples in range, above 1 and below 0.
+        for (depth, fa) in [(102, 0.4), (510, 1.0), (-255, 0.0)] {
+            let data = depth_as_blending_alpha(&background, &[255, 255, 255, depth, 51]).build();
+            let decode_with = |permissive| {
+                decode_with_input_ends(
+                    &data,
+                    |_| usize::MAX,
+                    JxlDecoderOptions {
+                        permissive,
+                        ..Default::default()
+                    },
+                    false,
+                    false,
+                    None,
+                )
+            };
+            let result = decode_with(false);
+            assert!(
+                matches!(
+                    result,
+                    Err(Error::NonAlphaBlendingChannel(
+                        0,
+                        JxlExtraChannelType::Depth
+                    ))
+                ),
+                "{:?}",
+                result.err()
+            );
+
+            // The depth channel is clamped to [0, 1] and used as unassociated alpha, including
+            // to blend itself and the alpha channel.
+            let (_, frames) = decode_with(true)?;
+            assert_eq!(frames.len(), 1);
+            let frame = &frames[0];
+            assert_eq!(frame.len(), 3);
+            let samples_per_pixel = frame[0].size().0 / 8;
+            let expected = [fa + 0.2 * (1.0 - fa), 1.0, 0.2 * fa + (1.0 - fa)];
+            for y in 0..8 {
+                for x in 0..8 {
+                    let values = [
+                        frame[0].row(y)[samples_per_pixel * x],
+                        frame[1].row(y)[x],
+                        frame[2].row(y)[x],
+                    ];
+                    for (value, expected) in values.iter().zip(expected) {
+                        assert!(
+                            (value - expected).abs() < 1e-5,
+                            "depth {depth} at ({x}, {y}): {values:?} != {expected:?}"
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn level_limits_extra_channels() -> Result<(), Error> {
+        let data = constant_image(vec![ExtraChannelSpec::Other(1); 5], &[10]).build();
+        let decode_at = |level| {
+            decode_with_input_ends(
+                &data,
+                |_| usize::MAX,
+                JxlDecoderOptions {
+                    enforce_level: level,
+                    ..Default::default()
+                },
+                false,
+                false,
+                None,
+            )
+        };
+        assert!(matches!(
+            decode_at(Some(JxlLevel::Level5)),
+            Err(Error::TooManyExtraChannels(5, 4))
+        ));
+        assert_eq!(decode_at(Some(JxlLevel::Level10))?.1[0].len(), 6);
+        assert_eq!(decode_at(None)?.1[0].len(), 6);
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Clocks used to time decoding.
+//!
+//! The decoder reads the time through a [`Clock`] rather than [`std::time::Instant`], which
+//! panics on targets without a system clock such as `wasm32-unknown-unknown`. Embedders on such
+//! targets can provide their own clock (for example one backed by `performance.now()` in a
+//! browser) with [`set_clock`]; otherwise, all times read there are zero.
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// A monotonic source of time.
+pub trait Clock: Sync {
+    /// Time elapsed since an arbitrary point in the past, which must not change while the
+    /// program runs. Successive calls must not return decreasing values.
+    fn now(&self) -> Duration;
+}
+
+/// Clock backed by [`std::time::Instant`]. This is the default on targets that have one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdClock;
+
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<std::time::Instant> = OnceLock::new();
+        ORIGIN.get_or_init(std::time::Instant::now).elapsed()
+    }
+}
+
+/// Clock that never advances. This is the default on targets without a system clock, where all
+/// measured durations are zero.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoClock;
+
+impl Clock for NoClock {
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+static DEFAULT_CLOCK: StdClock = StdClock;
+
+// `Instant::now` panics on targets without a clock.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+static DEFAULT_CLOCK: NoClock = NoClock;
+
+static CLOCK: OnceLock<&'static dyn Clock> = OnceLock::new();
+
+/// Sets the clock used by all decoders of this process. This can only be done once, before the
+/// clock is first read; returns the rejected clock if that is too late.
+pub fn set_clock(clock: &'static dyn Clock) -> Result<(), &'static dyn Clock> {
+    CLOCK.set(clock)
+}
+
+/// The clock set with [`set_clock`], or the default clock of the target.
+pub fn clock() -> &'static dyn Clock {
+    *CLOCK.get_or_init(|| &DEFAULT_CLOCK)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Clock that only advances when told to.
+    pub(crate) struct MockClock {
+        nanos: AtomicU64,
+    }
+
+    impl MockClock {
+        pub(crate) const fn new() -> MockClock {
+            MockClock {
+                nanos: AtomicU64::new(0),
+            }
+        }
+
+        pub(crate) fn advance(&self, duration: Duration) {
+            self.nanos
+                .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration {
+            Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn default_clocks() {
+        assert_eq!(NoClock.now(), Duration::ZERO);
+        let start = StdClock.now();
+        assert!(StdClock.now() >= start);
+        let mock = MockClock::new();
+        mock.advance(Duration::from_micros(3));
+        assert_eq!(mock.now(), Duration::from_micros(3));
+    }
+}
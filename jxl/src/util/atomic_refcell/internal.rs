@@ -149,20 +149,10 @@ impl<'a, T: ?Sized> AtomicRef<'a, T> {
         // Safety note: `f(&*orig)` is derived from `orig.ptr` (via `Deref` impl), therefore `ptr`
         // is derived from the same `AtomicRefCell` that `orig.token` is obtained from.
         AtomicRef {
-            ptr: NonNull::from_ref(f(&*orig)),
+            ptr: NonNull::from(f(&*orig)),
             token: orig.token,
         }
     }
-
-    #[expect(clippy::should_implement_trait)]
-    #[inline]
-    pub fn clone(orig: &Self) -> Self {
-        // Safety note: The invariants hold trivially, from the invariants of `orig`.
-        Self {
-            ptr: orig.ptr,
-            token: orig.token.clone(),
-        }
-    }
 }
 
 impl<T: ?Sized> Deref for AtomicRef<'_, T> {
@@ -215,7 +205,7 @@ impl<'a, T: ?Sized> AtomicRefMut<'a, T> {
         // Safety note: `f(&mut *orig)` is derived from `orig.ptr` (via `DerefMut` impl), therefore
         // `ptr` is derived from the same `AtomicRefCell` that `orig.token` is obtained from.
         AtomicRefMut {
-            ptr: NonNull::from_mut(f(&mut *orig)),
+            ptr: NonNull::from(f(&mut *orig)),
             token: orig.token,
             _phantom: PhantomData,
         }
@@ -42,11 +42,6 @@ impl<T: ?Sized> AtomicRefCell<T> {
     pub fn borrow_mut(&self) -> AtomicRefMut<'_, T> {
         AtomicRefMut::new(self).unwrap()
     }
-
-    #[inline]
-    pub fn try_borrow_mut(&self) -> Option<AtomicRefMut<'_, T>> {
-        AtomicRefMut::new(self)
-    }
 }
 
 // SAFETY: Accesses to the inner data are synchronized by the atomic reference counter.
@@ -41,6 +41,7 @@ pub fn fast_cos(x: f32) -> f32 {
     }
 }
 
+#[cfg(test)]
 #[inline]
 pub fn fast_erff(x: f32) -> f32 {
     // Formula from
@@ -0,0 +1,58 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! File offsets and sizes that may exceed 4GB.
+//!
+//! Offsets in the file or the codestream, box sizes and sums of TOC entries are `u64` throughout,
+//! since files larger than 4GB are valid on every platform. Only what is held in memory at once is
+//! a `usize`: a single section, whose TOC entry is a `u32`, or the bytes of a frame that is being
+//! buffered. The assertion below is what makes converting the former free on every target;
+//! everything else goes through [`usize_from_file_offset`], which fails with
+//! [`Error::FileOffsetTooLarge`] where it does not fit, or [`saturating_usize`] for estimates.
+
+use crate::error::{Error, Result};
+
+// TOC entries and box header fields are `u32`, and are used as buffer sizes without checks.
+const _: () = assert!(usize::BITS >= 32, "usize must be able to hold a u32");
+
+/// Converts an offset or size that must be addressed in memory, failing on platforms where
+/// `usize` is too narrow for it.
+#[inline]
+pub fn usize_from_file_offset(offset: u64) -> Result<usize> {
+    usize::try_from(offset).map_err(|_| Error::FileOffsetTooLarge(offset))
+}
+
+/// Converts a number of bytes that is only used as an upper bound or estimate, such as the bytes
+/// available in a box or a size hint, clamping it to `usize::MAX`.
+#[inline]
+pub fn saturating_usize(bytes: u64) -> usize {
+    usize::try_from(bytes).unwrap_or(usize::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            usize_from_file_offset(u32::MAX as u64).unwrap(),
+            u32::MAX as usize
+        );
+        assert_eq!(saturating_usize(u32::MAX as u64), u32::MAX as usize);
+        let large = (1u64 << 32) + 5;
+        if usize::BITS >= 64 {
+            assert_eq!(usize_from_file_offset(large).unwrap() as u64, large);
+            assert_eq!(saturating_usize(large) as u64, large);
+        } else {
+            assert!(matches!(
+                usize_from_file_offset(large),
+                Err(Error::FileOffsetTooLarge(offset)) if offset == large
+            ));
+            assert_eq!(saturating_usize(large), usize::MAX);
+        }
+        assert_eq!(saturating_usize(u64::MAX), usize::MAX);
+    }
+}
@@ -8,14 +8,6 @@ use crate::error::Error;
 pub type Matrix3x3<T> = [[T; 3]; 3];
 pub type Vector3<T> = [T; 3];
 
-pub fn matmul3_vec(m: [f32; 9], v: [f32; 3]) -> [f32; 3] {
-    [
-        v[0] * m[0] + v[1] * m[1] + v[2] * m[2],
-        v[0] * m[3] + v[1] * m[4] + v[2] * m[5],
-        v[0] * m[6] + v[1] * m[7] + v[2] * m[8],
-    ]
-}
-
 pub fn mul_3x3_vector(matrix: &Matrix3x3<f64>, vector: &Vector3<f64>) -> Vector3<f64> {
     std::array::from_fn(|i| {
         matrix[i]
@@ -9,32 +9,38 @@ pub mod test;
 mod atomic_refcell;
 mod bits;
 mod cacheline;
-mod concat_slice;
 mod fast_math;
+mod file_offset;
 mod float16;
 mod linalg;
 mod log2;
 mod mirror;
 pub mod ndarray;
+mod paranoid;
 mod rational_poly;
 mod shift_right_ceil;
 mod smallvec;
+mod timing;
 pub mod tracing_wrappers;
 mod vec_helpers;
+mod work_budget;
 mod xorshift128plus;
 
 pub use atomic_refcell::*;
 pub use bits::*;
 pub use cacheline::*;
-pub use concat_slice::*;
 pub use fast_math::*;
+pub use file_offset::*;
 pub use float16::f16;
 pub use linalg::*;
 pub use log2::*;
 pub use mirror::*;
 pub(crate) use ndarray::*;
+pub(crate) use paranoid::*;
 pub use rational_poly::*;
 pub use shift_right_ceil::*;
 pub use smallvec::*;
+pub(crate) use timing::*;
 pub use vec_helpers::*;
+pub(crate) use work_budget::*;
 pub use xorshift128plus::*;
@@ -0,0 +1,43 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::error::{Error, Result};
+
+use super::tracing_wrappers::*;
+
+/// Whether the `paranoid-checks` feature is enabled. Code that only gathers data for paranoid
+/// checks should be guarded by this, so that it is optimized out otherwise.
+pub(crate) const PARANOID_CHECKS: bool = cfg!(feature = "paranoid-checks");
+
+pub(crate) fn register_paranoid_checks(capabilities: &mut crate::Capabilities) {
+    capabilities.paranoid_checks = PARANOID_CHECKS;
+}
+
+/// Validates an invariant that is too expensive to check in regular builds: if paranoid checks
+/// are enabled and `holds` returns false, returns [`Error::Internal`] describing `invariant`.
+#[inline(always)]
+pub(crate) fn paranoid_check(holds: impl FnOnce() -> bool, invariant: &'static str) -> Result<()> {
+    if PARANOID_CHECKS && !holds() {
+        warn!(invariant, "paranoid check failed");
+        return Err(Error::Internal(invariant));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn violations_are_internal_errors() {
+        assert!(paranoid_check(|| true, "holds").is_ok());
+        let result = paranoid_check(|| false, "violated");
+        if PARANOID_CHECKS {
+            assert!(matches!(result, Err(Error::Internal("violated"))));
+        } else {
+            assert!(result.is_ok());
+        }
+    }
+}
@@ -130,9 +130,10 @@ impl<T, const N: usize> SmallVec<T, N> {
         };
 
         // We now know `iter`'s elements fit on the stack.
-        while *len < N
-            && let Some(e) = iter.next()
-        {
+        while *len < N {
+            let Some(e) = iter.next() else {
+                break;
+            };
             data[*len].write(e);
             // Safety note: we just wrote a new element in the first non-initialized slot of
             // the array.
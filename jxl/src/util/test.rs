@@ -5,7 +5,7 @@
 
 use std::{
     fmt::Debug,
-    io::{BufRead, BufReader, Cursor, Read, Write},
+    io::{BufRead, BufReader, Cursor, Read},
     num::{ParseFloatError, ParseIntError},
 };
 
@@ -90,18 +90,6 @@ pub fn assert_almost_eq<T: AsPrimitive<f64> + Debug + Copy>(
     }
 }
 
-pub fn assert_almost_rel_eq<T: AsPrimitive<f64> + Debug + Copy>(
-    left: T,
-    right: T,
-    max_rel_error: T,
-) {
-    if rel_error_gt(left, right, max_rel_error) {
-        panic!(
-            "assertion failed: `(left ≈ right)`\n  left: `{left:?}`,\n right: `{right:?}`,\n max_rel_error: `{max_rel_error:?}`"
-        );
-    }
-}
-
 pub fn assert_almost_abs_eq<T: AsPrimitive<f64> + Debug + Copy>(
     left: T,
     right: T,
@@ -114,21 +102,6 @@ pub fn assert_almost_abs_eq<T: AsPrimitive<f64> + Debug + Copy>(
     }
 }
 
-pub fn assert_almost_abs_eq_coords<T: AsPrimitive<f64> + Debug + Copy>(
-    left: T,
-    right: T,
-    max_abs_error: T,
-    pos: (usize, usize),
-    c: usize,
-) {
-    if abs_error_gt(left, right, max_abs_error) {
-        panic!(
-            "assertion failed @{pos:?}, c {c}: `(left ≈ right)`\n  left: `{left:?}`,\n right: `{right:?}`,\n abs error: `{:?}`\n max_abs_error: `{max_abs_error:?}`",
-            (left.as_() - right.as_()).abs()
-        );
-    }
-}
-
 fn assert_same_len<T: AsPrimitive<f64> + Debug + Copy>(left: &[T], right: &[T]) {
     if left.as_ref().len() != right.as_ref().len() {
         panic!(
@@ -139,51 +112,6 @@ fn assert_same_len<T: AsPrimitive<f64> + Debug + Copy>(left: &[T], right: &[T])
     }
 }
 
-pub fn assert_all_almost_eq<T: AsPrimitive<f64> + Debug + Copy, V: AsRef<[T]> + Debug>(
-    left: V,
-    right: V,
-    max_abs_error: T,
-    max_rel_error: T,
-) {
-    assert_same_len(left.as_ref(), right.as_ref());
-    for (idx, (left_val, right_val)) in left
-        .as_ref()
-        .iter()
-        .copied()
-        .zip(right.as_ref().iter().copied())
-        .enumerate()
-    {
-        if abs_error_gt(left_val, right_val, max_abs_error)
-            || rel_error_gt(left_val, right_val, max_rel_error)
-        {
-            panic!(
-                "assertion failed: `(left ≈ right)`\n left: `{left:?}`,\n right: `{right:?}`,\n max_abs_error: `{max_abs_error:?}`,\n max_rel_error: `{max_rel_error:?}`,\n left[{idx}]: `{left_val:?}`,\n right[{idx}]: `{right_val:?}`",
-            );
-        }
-    }
-}
-
-pub fn assert_all_almost_rel_eq<T: AsPrimitive<f64> + Debug + Copy, V: AsRef<[T]> + Debug>(
-    left: V,
-    right: V,
-    max_rel_error: T,
-) {
-    assert_same_len(left.as_ref(), right.as_ref());
-    for (idx, (left_val, right_val)) in left
-        .as_ref()
-        .iter()
-        .copied()
-        .zip(right.as_ref().iter().copied())
-        .enumerate()
-    {
-        if rel_error_gt(left_val, right_val, max_rel_error) {
-            panic!(
-                "assertion failed: `(left ≈ right)`\n left: `{left:?}`,\n right: `{right:?}`,\n max_rel_error: `{max_rel_error:?}`,\n left[{idx}]: `{left_val:?}`,\n right[{idx}]: `{right_val:?}`",
-            );
-        }
-    }
-}
-
 pub fn assert_all_almost_abs_eq<T: AsPrimitive<f64> + Debug + Copy, V: AsRef<[T]> + Debug>(
     left: V,
     right: V,
@@ -273,36 +201,6 @@ pub fn read_headers_and_toc(image: &[u8]) -> Result<(FileHeader, FrameHeader, To
     Ok((file_header, frame_header, toc))
 }
 
-pub fn write_pfm(image: Vec<Image<f32>>, mut buf: impl Write) -> Result<(), Error> {
-    if image.len() == 1 {
-        buf.write_all(b"Pf\n")?;
-    } else if image.len() == 3 {
-        buf.write_all(b"PF\n")?;
-    } else {
-        return Err(Error::InvalidPFM(format!(
-            "invalid number of channels: {}",
-            image.len()
-        )));
-    }
-    let size = image[0].size();
-    for c in image.iter().skip(1) {
-        assert_eq!(size, c.size());
-    }
-    buf.write_fmt(format_args!("{} {}\n", size.0, size.1))?;
-    buf.write_all(b"1.0\n")?;
-    let mut b: [u8; 4];
-    for row in 0..size.1 {
-        for col in 0..size.0 {
-            for c in image.iter() {
-                b = c.row(size.1 - row - 1)[col].to_be_bytes();
-                buf.write_all(&b)?;
-            }
-        }
-    }
-    buf.flush()?;
-    Ok(())
-}
-
 pub fn read_pfm(b: &[u8]) -> Result<Vec<Image<f32>>, Error> {
     let mut bf = BufReader::new(Cursor::new(b));
     let mut line = String::new();
@@ -0,0 +1,171 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::cell::Cell;
+use std::time::Duration;
+
+use crate::api::{JxlDecodeStage, JxlDecodeTimings};
+use crate::time::{Clock, clock};
+
+/// Whether the `timing-stats` feature is enabled. When it is not, the clock is never read and
+/// all the code below is optimized out.
+pub(crate) const TIMING_STATS: bool = cfg!(feature = "timing-stats");
+
+pub(crate) fn register_decode_timings(capabilities: &mut crate::Capabilities) {
+    capabilities.decode_timings = TIMING_STATS;
+}
+
+thread_local! {
+    static THREAD_NANOS: [Cell<u64>; JxlDecodeStage::ALL.len()] =
+        const { [const { Cell::new(0) }; JxlDecodeStage::ALL.len()] };
+}
+
+/// Reading the clock for every row would slow decoding down noticeably, so [`StageClock`]s only
+/// time one row (or row of blocks) out of `SAMPLE_PERIOD`, and scale up the measured time.
+const SAMPLE_PERIOD: usize = 16;
+
+#[inline(always)]
+fn record(stage: JxlDecodeStage, start: Duration, end: Duration, scale: usize) {
+    if !TIMING_STATS {
+        return;
+    }
+    let nanos = end.saturating_sub(start).as_nanos() as u64 * scale as u64;
+    THREAD_NANOS.with(|counters| {
+        let counter = &counters[stage as usize];
+        counter.set(counter.get() + nanos);
+    });
+}
+
+/// Returns the time recorded on the current thread since the last call, and resets the
+/// counters. Threads that decode on behalf of a decoder must hand these over to it.
+pub(crate) fn take_thread_timings() -> JxlDecodeTimings {
+    let mut timings = JxlDecodeTimings::default();
+    if TIMING_STATS {
+        THREAD_NANOS.with(|counters| {
+            for (stage, counter) in JxlDecodeStage::ALL.into_iter().zip(counters) {
+                timings.add(stage, counter.take());
+            }
+        });
+    }
+    timings
+}
+
+/// Adds the time between its creation and its drop to a stage, if any.
+pub(crate) struct StageTimer {
+    started: Option<(JxlDecodeStage, &'static dyn Clock, Duration)>,
+}
+
+impl StageTimer {
+    #[inline(always)]
+    pub(crate) fn new(stage: impl Into<Option<JxlDecodeStage>>) -> StageTimer {
+        Self::with_clock(stage, clock)
+    }
+
+    #[inline(always)]
+    fn with_clock(
+        stage: impl Into<Option<JxlDecodeStage>>,
+        clock: impl FnOnce() -> &'static dyn Clock,
+    ) -> StageTimer {
+        StageTimer {
+            started: stage.into().filter(|_| TIMING_STATS).map(|stage| {
+                let clock = clock();
+                (stage, clock, clock.now())
+            }),
+        }
+    }
+}
+
+impl Drop for StageTimer {
+    #[inline(always)]
+    fn drop(&mut self) {
+        if let Some((stage, clock, start)) = self.started {
+            record(stage, start, clock.now(), 1);
+        }
+    }
+}
+
+/// Attributes consecutive intervals of a sampled row to stages, reading the clock once per
+/// interval. Used where stages alternate too quickly for a [`StageTimer`] to be cheap enough.
+pub(crate) struct StageClock {
+    last: Option<(&'static dyn Clock, Duration)>,
+}
+
+impl StageClock {
+    /// Starts timing `row`, if it is one of the sampled rows.
+    #[inline(always)]
+    pub(crate) fn sample_row(row: usize) -> StageClock {
+        Self::sample_row_with_clock(row, clock)
+    }
+
+    #[inline(always)]
+    fn sample_row_with_clock(row: usize, clock: impl FnOnce() -> &'static dyn Clock) -> StageClock {
+        StageClock {
+            last: (TIMING_STATS && row.is_multiple_of(SAMPLE_PERIOD)).then(|| {
+                let clock = clock();
+                (clock, clock.now())
+            }),
+        }
+    }
+
+    /// Attributes the time since the previous lap (or since the start) to `stage`, if any.
+    #[inline(always)]
+    pub(crate) fn lap(&mut self, stage: impl Into<Option<JxlDecodeStage>>) {
+        if let Some((clock, last)) = &mut self.last {
+            let now = clock.now();
+            if let Some(stage) = stage.into() {
+                record(stage, *last, now, SAMPLE_PERIOD);
+            }
+            *last = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time::test::MockClock;
+
+    static CLOCK: MockClock = MockClock::new();
+
+    fn mock() -> &'static dyn Clock {
+        &CLOCK
+    }
+
+    #[test]
+    fn timers_record_on_current_thread() {
+        let ms = Duration::from_millis;
+        take_thread_timings();
+        {
+            let _timer = StageTimer::with_clock(JxlDecodeStage::Gaborish, mock);
+            let _untimed = StageTimer::with_clock(None, mock);
+            CLOCK.advance(ms(2));
+        }
+        let mut clock = StageClock::sample_row_with_clock(SAMPLE_PERIOD, mock);
+        CLOCK.advance(ms(1));
+        clock.lap(JxlDecodeStage::Epf);
+        CLOCK.advance(ms(3));
+        clock.lap(None);
+        CLOCK.advance(ms(5));
+        clock.lap(JxlDecodeStage::Epf);
+        let mut unsampled = StageClock::sample_row_with_clock(1, mock);
+        CLOCK.advance(ms(7));
+        unsampled.lap(JxlDecodeStage::Upsample);
+        let timings = take_thread_timings();
+        if TIMING_STATS {
+            assert_eq!(timings.stage(JxlDecodeStage::Gaborish), ms(2));
+            // Sampled rows count for all the rows that were not timed, and time spent outside of
+            // any stage is not counted.
+            assert_eq!(
+                timings.stage(JxlDecodeStage::Epf),
+                ms(6) * SAMPLE_PERIOD as u32
+            );
+            assert_eq!(timings.stage(JxlDecodeStage::Upsample), Duration::ZERO);
+        } else {
+            assert_eq!(timings, JxlDecodeTimings::default());
+        }
+        // Counters are reset once taken.
+        assert_eq!(take_thread_timings(), JxlDecodeTimings::default());
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::cell::Cell;
+
+use crate::error::{Error, Result};
+
+thread_local! {
+    static THREAD_TOKENS: Cell<u64> = const { Cell::new(0) };
+    static THREAD_TOKEN_LIMIT: Cell<u64> = const { Cell::new(u64::MAX) };
+}
+
+/// Accounts for `num_tokens` entropy-coded tokens that were just decoded on the current thread,
+/// failing if they exceed the thread's budget.
+///
+/// Callers charge the tokens of whole rows rather than single tokens, so that the accounting
+/// does not slow down the entropy decoders. Decoding thus stops at most a row past the budget.
+#[inline]
+pub(crate) fn charge_tokens(num_tokens: usize) -> Result<()> {
+    let total = THREAD_TOKENS.with(|tokens| {
+        let total = tokens.get().saturating_add(num_tokens as u64);
+        tokens.set(total);
+        total
+    });
+    let limit = THREAD_TOKEN_LIMIT.with(Cell::get);
+    if total > limit {
+        return Err(Error::WorkLimitExceeded(total, limit));
+    }
+    Ok(())
+}
+
+/// Makes the current thread account for the tokens of a decoder that has already decoded
+/// `tokens_used` of its `limit`, until [`take_thread_tokens`] is called.
+pub(crate) fn set_thread_token_budget(tokens_used: u64, limit: Option<u64>) {
+    THREAD_TOKENS.with(|tokens| tokens.set(tokens_used));
+    THREAD_TOKEN_LIMIT.with(|l| l.set(limit.unwrap_or(u64::MAX)));
+}
+
+/// Returns the number of tokens the current thread accounted for, including the ones it started
+/// with, and lifts its limit. Threads that decode on behalf of a decoder must hand these over
+/// to it.
+pub(crate) fn take_thread_tokens() -> u64 {
+    THREAD_TOKEN_LIMIT.with(|l| l.set(u64::MAX));
+    THREAD_TOKENS.with(|tokens| tokens.take())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn budget_is_per_thread() {
+        set_thread_token_budget(20, Some(100));
+        charge_tokens(40).unwrap();
+        std::thread::spawn(|| charge_tokens(1000).unwrap())
+            .join()
+            .unwrap();
+        charge_tokens(40).unwrap();
+        assert!(matches!(
+            charge_tokens(1),
+            Err(Error::WorkLimitExceeded(101, 100))
+        ));
+        assert_eq!(take_thread_tokens(), 101);
+        charge_tokens(usize::MAX).unwrap();
+        assert_eq!(take_thread_tokens(), usize::MAX as u64);
+    }
+}
@@ -0,0 +1,466 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Checks the items exported by the crate against `api_surface.txt`, so that changes to the public
+//! API are deliberate. After an intended change, regenerate the list with
+//! `UPDATE_API_SURFACE=1 cargo test -p jxl --test api_surface`.
+//!
+//! The items are found by reading the sources: public items and modules reachable from `lib.rs`,
+//! and the items re-exported by `pub use`. Methods, fields and items generated by macros are not
+//! listed.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Modules that are internal to the decoder, none of which can appear in exported paths.
+const INTERNAL_MODULES: [&str; 9] = [
+    "bit_reader",
+    "color",
+    "entropy_coding",
+    "features",
+    "frame",
+    "headers",
+    "icc",
+    "render",
+    "util",
+];
+
+/// A module of the crate, public or not.
+#[derive(Default)]
+struct Module {
+    /// Kinds of the items declared `pub`, by name, with their `cfg` conditions if any.
+    items: BTreeMap<String, (String, Option<String>)>,
+    submodules: BTreeMap<String, (bool, Module)>,
+    /// Paths re-exported by `pub use`, as (name in this module, path of the item or of the
+    /// module for glob imports).
+    reexports: Vec<(Option<String>, Vec<String>)>,
+}
+
+/// Replaces comments, string and character literals with spaces, so that the braces and
+/// semicolons left delimit the items.
+fn strip_comments_and_literals(src: &str) -> String {
+    let chars: Vec<char> = src.chars().collect();
+    let mut out = String::with_capacity(src.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        if rest.starts_with(&['/', '/']) {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if rest.starts_with(&['/', '*']) {
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i..].starts_with(&['/', '*']) {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i..].starts_with(&['*', '/']) {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+            out.push(' ');
+        } else if rest[0] == '"' || (rest[0] == 'r' && matches!(rest.get(1), Some('"' | '#'))) {
+            let raw = rest[0] == 'r';
+            let mut hashes = 0;
+            i += 1;
+            if raw {
+                while chars[i] == '#' {
+                    hashes += 1;
+                    i += 1;
+                }
+                i += 1;
+            }
+            loop {
+                if !raw && chars[i] == '\\' {
+                    i += 2;
+                } else if chars[i] == '"' && chars[i + 1..].iter().take(hashes).all(|&c| c == '#') {
+                    i += 1 + hashes;
+                    break;
+                } else {
+                    i += 1;
+                }
+            }
+            out.push_str("\"\"");
+        } else if rest[0] == '\'' && (rest.get(1) == Some(&'\\') || rest.get(2) == Some(&'\'')) {
+            i += if rest[1] == '\\' {
+                2 + rest[2..].iter().position(|&c| c == '\'').unwrap() + 1
+            } else {
+                3
+            };
+            out.push_str("' '");
+        } else {
+            out.push(rest[0]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Splits the contents of a module into items, each with the text of its attributes and the
+/// text of the item itself.
+fn split_items(src: &str) -> Vec<(String, String)> {
+    let mut items = vec![];
+    let mut attrs = String::new();
+    let mut item = String::new();
+    let mut depth = 0;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if depth == 0 && item.trim().is_empty() && c == '#' {
+            // An attribute, outer or inner.
+            let mut attr = String::from(c);
+            let mut attr_depth = 0;
+            for c in chars.by_ref() {
+                attr.push(c);
+                match c {
+                    '[' => attr_depth += 1,
+                    ']' => {
+                        attr_depth -= 1;
+                        if attr_depth == 0 {
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if !attr.starts_with("#!") {
+                attrs.push_str(&attr);
+            }
+            continue;
+        }
+        item.push(c);
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    items.push((std::mem::take(&mut attrs), std::mem::take(&mut item)));
+                }
+            }
+            ';' if depth == 0 => {
+                items.push((std::mem::take(&mut attrs), std::mem::take(&mut item)));
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+/// Expands a use tree such as `a::{b, c::{d as e, *}}` into (path, name) pairs, where the name
+/// is `None` for glob imports.
+fn expand_use_tree(prefix: &[String], tree: &str, out: &mut Vec<(Vec<String>, Option<String>)>) {
+    let tree = tree.trim();
+    if tree.is_empty() {
+        return;
+    }
+    if let Some(brace) = tree.find('{') {
+        let mut path = prefix.to_vec();
+        path.extend(
+            tree[..brace]
+                .split("::")
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+        );
+        let inner = &tree[brace + 1..tree.rfind('}').unwrap()];
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, c) in inner.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    expand_use_tree(&path, &inner[start..i], out);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        expand_use_tree(&path, &inner[start..], out);
+        return;
+    }
+    let (tree, alias) = match tree.split_once(" as ") {
+        Some((tree, alias)) => (tree.trim(), Some(alias.trim().to_string())),
+        None => (tree, None),
+    };
+    let mut path = prefix.to_vec();
+    path.extend(tree.split("::").map(|s| s.trim().to_string()));
+    if path.last().unwrap() == "*" {
+        path.pop();
+        out.push((path, None));
+    } else if path.last().unwrap() == "self" {
+        path.pop();
+        let name = alias.unwrap_or_else(|| path.last().unwrap().clone());
+        out.push((path, Some(name)));
+    } else {
+        let name = alias.unwrap_or_else(|| path.last().unwrap().clone());
+        out.push((path, Some(name)));
+    }
+}
+
+/// Returns the file of the module `name` declared in the module whose submodules live in `dir`.
+fn module_file(dir: &Path, name: &str) -> PathBuf {
+    let file = dir.join(format!("{name}.rs"));
+    if file.exists() {
+        file
+    } else {
+        dir.join(name).join("mod.rs")
+    }
+}
+
+/// Parses the module with the given contents, whose submodules live in `dir`. `path` is the
+/// absolute path of the module, used to resolve `self` and `super` in re-exports.
+fn parse_module(src: &str, dir: &Path, path: &[String]) -> Module {
+    let mut module = Module::default();
+    for (attrs, item) in split_items(src) {
+        if attrs.contains("cfg(test)") {
+            continue;
+        }
+        let cfg = attrs
+            .split("#[")
+            .map(|attr| attr.trim().trim_end_matches(']'))
+            .find(|attr| attr.starts_with("cfg(") || attr.starts_with("cfg_attr"))
+            .filter(|attr| attr.starts_with("cfg("))
+            .map(String::from);
+        let item = item.trim();
+        let is_pub = item.starts_with("pub ");
+        let words: Vec<&str> = item
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|w| !w.is_empty())
+            .collect();
+        let words = if words.first() == Some(&"pub") {
+            if item.starts_with("pub(") {
+                // `pub(crate)`, `pub(super)` and `pub(in path)`: not exported.
+                let close = item.find(')').unwrap();
+                let rest = item[close + 1..].trim_start();
+                let is_mod = rest.starts_with("mod ");
+                if is_mod {
+                    let name = rest[4..]
+                        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .next()
+                        .unwrap();
+                    let sub = parse_submodule(rest, name, dir, path);
+                    module.submodules.insert(name.to_string(), (false, sub));
+                }
+                continue;
+            }
+            &words[1..]
+        } else {
+            &words[..]
+        };
+        let Some(pos) = words.iter().position(|w| {
+            matches!(
+                *w,
+                "fn" | "struct"
+                    | "enum"
+                    | "trait"
+                    | "type"
+                    | "const"
+                    | "static"
+                    | "union"
+                    | "mod"
+                    | "use"
+                    | "impl"
+                    | "macro_rules"
+            )
+        }) else {
+            continue;
+        };
+        let mut kind = words[pos];
+        // `const fn` and `const unsafe fn` declare functions, not constants.
+        if kind == "const" && matches!(words.get(pos + 1), Some(&"fn") | Some(&"unsafe")) {
+            kind = "fn";
+        }
+        let name_index = words[pos..].iter().position(|w| *w == kind).unwrap() + pos + 1;
+        match kind {
+            "impl" | "macro_rules" => {}
+            "mod" => {
+                let name = words[name_index];
+                let sub = parse_submodule(item, name, dir, path);
+                module.submodules.insert(name.to_string(), (is_pub, sub));
+            }
+            "use" if is_pub => {
+                let tree = item["pub use".len()..].trim_end_matches(';');
+                let mut uses = vec![];
+                expand_use_tree(&[], tree, &mut uses);
+                for (use_path, name) in uses {
+                    module.reexports.push((name, use_path));
+                }
+            }
+            _ if is_pub => {
+                let name = words[name_index].to_string();
+                module.items.insert(name, (kind.to_string(), cfg));
+            }
+            _ => {}
+        }
+    }
+    module
+}
+
+fn parse_submodule(item: &str, name: &str, dir: &Path, path: &[String]) -> Module {
+    let mut sub_path = path.to_vec();
+    sub_path.push(name.to_string());
+    let sub_dir = dir.join(name);
+    if let Some(brace) = item.find('{') {
+        let body = &item[brace + 1..item.rfind('}').unwrap()];
+        parse_module(body, &sub_dir, &sub_path)
+    } else {
+        let file = module_file(dir, name);
+        let src = strip_comments_and_literals(&std::fs::read_to_string(&file).unwrap());
+        let dir = if file.ends_with("mod.rs") {
+            file.parent().unwrap().to_path_buf()
+        } else {
+            sub_dir
+        };
+        parse_module(&src, &dir, &sub_path)
+    }
+}
+
+struct Crate {
+    root: Module,
+}
+
+impl Crate {
+    fn module(&self, path: &[String]) -> Option<&Module> {
+        let mut module = &self.root;
+        for name in path {
+            module = &module.submodules.get(name)?.1;
+        }
+        Some(module)
+    }
+
+    /// Turns the path of a `pub use` of the module at `at` into an absolute path, or returns
+    /// `None` for paths into other crates.
+    fn absolute(&self, at: &[String], path: &[String]) -> Option<Vec<String>> {
+        let mut base = at.to_vec();
+        let mut rest = path;
+        match path[0].as_str() {
+            "crate" => {
+                base.clear();
+                rest = &path[1..];
+            }
+            "self" => rest = &path[1..],
+            "super" => {
+                while rest.first().map(String::as_str) == Some("super") {
+                    base.pop();
+                    rest = &rest[1..];
+                }
+            }
+            first if self.module(at)?.submodules.contains_key(first) => {}
+            _ => return None,
+        }
+        base.extend_from_slice(rest);
+        Some(base)
+    }
+
+    /// Returns the items visible with `pub` visibility in the module at `path`, as kind, cfg
+    /// condition and, for modules, their path.
+    fn pub_items(
+        &self,
+        path: &[String],
+    ) -> BTreeMap<String, (String, Option<String>, Vec<String>)> {
+        let module = self.module(path).unwrap();
+        let mut items = BTreeMap::new();
+        for (name, (kind, cfg)) in &module.items {
+            items.insert(name.clone(), (kind.clone(), cfg.clone(), vec![]));
+        }
+        for (name, (is_pub, _)) in &module.submodules {
+            if *is_pub {
+                let mut sub_path = path.to_vec();
+                sub_path.push(name.clone());
+                items.insert(name.clone(), ("mod".to_string(), None, sub_path));
+            }
+        }
+        for (name, use_path) in &module.reexports {
+            let Some(target) = self.absolute(path, use_path) else {
+                let name = name.clone().unwrap_or_else(|| "*".to_string());
+                let extern_path = use_path.join("::");
+                items.insert(name, (format!("extern {extern_path}"), None, vec![]));
+                continue;
+            };
+            match name {
+                None => items.extend(self.pub_items(&target)),
+                Some(name) => {
+                    let (item_name, parent) = target.split_last().unwrap();
+                    let item = if self.module(&target).is_some() {
+                        ("mod".to_string(), None, target.clone())
+                    } else {
+                        self.pub_items(parent)
+                            .remove(item_name)
+                            .unwrap_or_else(|| panic!("cannot resolve {}", target.join("::")))
+                    };
+                    items.insert(name.clone(), item);
+                }
+            }
+        }
+        items
+    }
+
+    /// Lists the exported items under `path`, one per line.
+    fn surface(&self, path: &[String], prefix: &str, out: &mut Vec<String>) {
+        for (name, (kind, cfg, module_path)) in self.pub_items(path) {
+            let item_path = format!("{prefix}::{name}");
+            let cfg = cfg.map(|cfg| format!(" #[{cfg}]")).unwrap_or_default();
+            out.push(format!("{kind} {item_path}{cfg}"));
+            if kind == "mod" {
+                self.surface(&module_path, &item_path, out);
+            }
+        }
+    }
+}
+
+fn exported_items() -> Vec<String> {
+    let src_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src");
+    let lib = std::fs::read_to_string(src_dir.join("lib.rs")).unwrap();
+    let krate = Crate {
+        root: parse_module(&strip_comments_and_literals(&lib), &src_dir, &[]),
+    };
+    let mut items = vec![];
+    krate.surface(&[], "jxl", &mut items);
+    items
+}
+
+#[test]
+fn api_surface_matches_snapshot() {
+    let snapshot_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/api_surface.txt");
+    let actual = exported_items().join("\n") + "\n";
+    if std::env::var_os("UPDATE_API_SURFACE").is_some() {
+        std::fs::write(&snapshot_path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&snapshot_path).unwrap();
+    let added: Vec<_> = actual
+        .lines()
+        .filter(|l| !expected.lines().any(|e| e == *l))
+        .collect();
+    let removed: Vec<_> = expected
+        .lines()
+        .filter(|l| !actual.lines().any(|a| a == *l))
+        .collect();
+    assert!(
+        added.is_empty() && removed.is_empty(),
+        "the public API changed\nadded: {added:#?}\nremoved: {removed:#?}\n\
+         rerun with UPDATE_API_SURFACE=1 if this is intended"
+    );
+}
+
+#[test]
+fn internal_modules_are_not_exported() {
+    for item in exported_items() {
+        let path = item.split(' ').nth(1).unwrap();
+        for module in INTERNAL_MODULES {
+            assert!(
+                !path.starts_with(&format!("jxl::{module}::")),
+                "{item} exposes an internal module"
+            );
+        }
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Checks of the minimum supported Rust version (MSRV) policy, see CONTRIBUTING.md.
+//!
+//! CI also builds and runs these tests with the MSRV toolchain, so that decoding an image through
+//! the public API is known to compile without newer language features and standard library APIs.
+
+use std::path::Path;
+
+use jxl::prelude::*;
+
+/// Crates that are used as libraries, which must all support the MSRV of `jxl`.
+const LIBRARY_CRATES: [&str; 5] = ["jxl", "jxl_cms", "jxl_macros", "jxl_simd", "jxl_transforms"];
+
+/// Returns the `rust-version` declared in the manifest of `crate_name`.
+fn rust_version(crate_name: &str) -> (u32, u32) {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let manifest = std::fs::read_to_string(workspace.join(crate_name).join("Cargo.toml")).unwrap();
+    let version = manifest
+        .lines()
+        .find_map(|line| line.strip_prefix("rust-version = "))
+        .unwrap_or_else(|| panic!("{crate_name} does not declare a rust-version"));
+    let mut parts = version.trim_matches('"').split('.');
+    let mut next = || parts.next().unwrap().parse().unwrap();
+    (next(), next())
+}
+
+#[test]
+fn manifests_declare_msrv() {
+    let msrv = rust_version("jxl");
+    for crate_name in LIBRARY_CRATES {
+        assert_eq!(rust_version(crate_name), msrv, "{crate_name}");
+    }
+    // The command line tool may require a newer compiler for its own dependencies.
+    assert!(rust_version("jxl_cli") >= msrv);
+}
+
+/// Unwraps the result of a decoding step that has all of its input.
+fn complete<T, U>(result: ProcessingResult<T, U>) -> T {
+    match result {
+        ProcessingResult::Complete { result } => result,
+        ProcessingResult::NeedsMoreInput { .. } => panic!("the whole file was provided"),
+    }
+}
+
+#[test]
+fn decode_with_public_api() -> Result<()> {
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/test/3x3_srgb_lossless.jxl");
+    let data = std::fs::read(file).unwrap();
+    let mut input = &data[..];
+
+    let decoder = JxlDecoder::<states::Initialized>::new(JxlDecoderOptions::default());
+    let mut decoder = complete(decoder.process(&mut input)?);
+    let (width, height) = decoder.basic_info().size;
+    decoder.set_pixel_format(JxlPixelFormat::rgba8(0));
+
+    let mut pixels = vec![0u8; width * height * 4];
+    let mut buffers = [JxlOutputBuffer::new(&mut pixels, height, width * 4)];
+    let decoder = complete(decoder.process(&mut input)?);
+    let decoder = complete(decoder.process(&mut input, &mut buffers)?);
+    assert!(!decoder.has_more_frames());
+    // The image is opaque.
+    assert!(pixels.chunks_exact(4).all(|pixel| pixel[3] == 255));
+    Ok(())
+}
@@ -4,7 +4,7 @@
 // license that can be found in the LICENSE file.
 
 use criterion::{BenchmarkId, Criterion, SamplingMode, criterion_group, criterion_main};
-use jxl::api::JxlDecoderOptions;
+use jxl::api::{Endianness, JxlDecoderOptions};
 use jxl_cli::dec::{OutputDataType, decode_frames, decode_header};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -68,6 +68,9 @@ fn decode_benches(c: &mut Criterion) {
                         false,
                         None,
                         false,
+                        None,
+                        None,
+                        Endianness::native(),
                     )
                     .unwrap();
                 })
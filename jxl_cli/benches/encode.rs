@@ -0,0 +1,95 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use criterion::{BenchmarkId, Criterion, SamplingMode, criterion_group, criterion_main};
+use jxl::api::{Endianness, JxlDecoderOptions};
+use jxl_cli::dec::{DecodeOutput, OutputDataType, decode_frames};
+use jxl_cli::enc::{png, pnm, sink::BufferedSink};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Compares writing outputs through a `BufWriter` and through the `BufferedSink` of the CLI.
+fn encode_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    group.sampling_mode(SamplingMode::Flat);
+
+    let paths: Vec<PathBuf> = std::env::var("JXL_FILES").map_or_else(
+        |_| {
+            let root_test_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .parent()
+                .unwrap()
+                .join("jxl")
+                .join("resources")
+                .join("test");
+            ["has_permutation.jxl", "zoltan_tasi_unsplash.jxl"]
+                .iter()
+                .map(|name| root_test_dir.join(name))
+                .collect()
+        },
+        |csv| csv.split(',').map(PathBuf::from).collect(),
+    );
+    let output = std::env::temp_dir().join(format!("jxl_cli_bench_{}", std::process::id()));
+
+    for path in paths {
+        let mut input = fs::read(&path).unwrap();
+        let (image, _) = decode_frames(
+            &mut input.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            None,
+            &[OutputDataType::U8],
+            false,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap();
+        input.clear();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let (width, height) = image.size;
+        group.throughput(criterion::Throughput::Elements((width * height) as u64));
+
+        type Encode = fn(&DecodeOutput, &mut dyn Write);
+        let encoders: [(&str, Encode); 2] = [
+            ("ppm", |image, mut writer| {
+                pnm::to_ppm(image, &mut writer).unwrap()
+            }),
+            ("png", |image, mut writer| {
+                png::to_png(image, &mut writer, None).unwrap()
+            }),
+        ];
+        for (format, encode) in encoders {
+            let id = format!("{format}/{name}");
+            group.bench_with_input(BenchmarkId::new("bufwriter", &id), &image, |b, image| {
+                b.iter(|| {
+                    let mut writer = BufWriter::new(File::create(&output).unwrap());
+                    encode(image, &mut writer);
+                    writer.flush().unwrap();
+                })
+            });
+            group.bench_with_input(BenchmarkId::new("sink", &id), &image, |b, image| {
+                b.iter(|| {
+                    let mut writer = BufferedSink::new(File::create(&output).unwrap());
+                    encode(image, &mut writer);
+                    writer.finish().unwrap();
+                })
+            });
+        }
+    }
+    let _ = fs::remove_file(output);
+
+    group.finish();
+}
+
+criterion_group!(
+    name = encode;
+    config = Criterion::default().sample_size(20);
+    targets = encode_benches
+);
+criterion_main!(encode);
@@ -0,0 +1,76 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use criterion::{BenchmarkId, Criterion, SamplingMode, criterion_group, criterion_main};
+use jxl::api::{Endianness, JxlDecoderOptions, decode_thumbnail};
+use jxl_cli::dec::{OutputDataType, decode_frames};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_DIM: usize = 256;
+
+/// Compares thumbnail decoding with a full decode of the same file.
+fn thumbnail_benches(c: &mut Criterion) {
+    let mut group = c.benchmark_group("thumbnail");
+    group.sampling_mode(SamplingMode::Flat);
+
+    let paths: Vec<PathBuf> = std::env::var("JXL_FILES").map_or_else(
+        |_| {
+            let root_test_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .parent()
+                .unwrap()
+                .join("jxl")
+                .join("resources")
+                .join("test");
+            // Large VarDCT photos.
+            [
+                "progressive_ac.jxl",
+                "has_permutation.jxl",
+                "zoltan_tasi_unsplash.jxl",
+            ]
+            .iter()
+            .map(|name| root_test_dir.join(name))
+            .collect()
+        },
+        |csv| csv.split(',').map(PathBuf::from).collect(),
+    );
+
+    for path in paths {
+        let bytes = fs::read(&path).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        group.bench_with_input(BenchmarkId::new("full", &name), &bytes, |b, bytes| {
+            b.iter(|| {
+                let mut input = bytes.as_slice();
+                decode_frames(
+                    &mut input,
+                    JxlDecoderOptions::default(),
+                    None,
+                    None,
+                    &[OutputDataType::U8],
+                    true,
+                    false,
+                    None,
+                    false,
+                    None,
+                    None,
+                    Endianness::native(),
+                )
+                .unwrap();
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("thumbnail", &name), &bytes, |b, bytes| {
+            b.iter(|| decode_thumbnail(bytes, MAX_DIM).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    name = thumbnail;
+    config = Criterion::default().sample_size(20);
+    targets = thumbnail_benches
+);
+criterion_main!(thumbnail);
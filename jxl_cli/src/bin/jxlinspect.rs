@@ -6,14 +6,15 @@
 use clap::{Arg, Command};
 use color_eyre::eyre::{Result, eyre};
 use jxl::api::{
-    JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlDecoder, JxlDecoderOptions, JxlOutputBuffer,
-    ProcessingResult,
+    FrameTiming, JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlDecoder, JxlDecoderOptions,
+    JxlExtraChannelType, JxlOutputBuffer, ProcessingResult,
 };
-use jxl::headers::extra_channels::ExtraChannel;
 use jxl::image::{Image, Rect};
+use jxl_cli::report::ExitStatus;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::process::ExitCode;
 
 fn parse_jxl(path: &Path) -> Result<()> {
     let file = File::open(path)?;
@@ -40,7 +41,7 @@ fn parse_jxl(path: &Path) -> Result<()> {
     let alpha_info = if info
         .extra_channels
         .iter()
-        .any(|c| c.ec_type == ExtraChannel::Alpha)
+        .any(|c| c.ec_type == JxlExtraChannelType::Alpha)
     {
         "+Alpha"
     } else {
@@ -117,7 +118,7 @@ fn parse_jxl(path: &Path) -> Result<()> {
         let pixel_format = decoder_with_image_info.current_pixel_format().clone();
         let num_channels: usize = pixel_format.color_type.samples_per_pixel();
         let mut num_frames = 0;
-        let mut total_seconds = 0.0;
+        let mut total_ticks = 0;
 
         loop {
             let decoder_with_frame_info = match decoder_with_image_info.process(&mut reader)? {
@@ -127,9 +128,13 @@ fn parse_jxl(path: &Path) -> Result<()> {
                 }
             };
 
-            let duration = decoder_with_frame_info.frame_header().duration.unwrap();
-            total_seconds += duration;
-            println!("Frame {}, duration {}ms", num_frames, duration);
+            let timing = decoder_with_frame_info.frame_header().timing.unwrap();
+            total_ticks += timing.ticks as u64;
+            println!(
+                "Frame {}, duration {}ms",
+                num_frames,
+                timing.as_duration().as_secs_f64() * 1000.0
+            );
 
             let mut outputs = vec![Image::<f32>::new((
                 info.size.0 * num_channels,
@@ -166,10 +171,13 @@ fn parse_jxl(path: &Path) -> Result<()> {
             }
         }
 
+        // Sum the durations in ticks and convert at the end to avoid accumulating rounding errors.
+        let total_seconds = FrameTiming::new(0, &animation)
+            .ticks_as_duration(total_ticks)
+            .as_secs_f64();
         print!(
             "Animation length: {} frames in {} seconds",
-            num_frames,
-            total_seconds / 1000.0
+            num_frames, total_seconds
         );
         if animation.have_timecodes {
             print!(" with (potentially) individual timecodes");
@@ -187,7 +195,7 @@ fn parse_jxl(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn main() {
+fn main() -> ExitCode {
     #[cfg(feature = "tracing-subscriber")]
     {
         use tracing_subscriber::{EnvFilter, fmt, prelude::*};
@@ -211,8 +219,10 @@ fn main() {
 
     let res = parse_jxl(filename);
     if let Err(err) = res {
-        println!("Error parsing JXL codestream: {err}");
+        eprintln!("Error parsing JXL codestream: {err}");
+        return ExitStatus::DecodeError.into();
     }
+    ExitStatus::Success.into()
 }
 
 #[cfg(test)]
@@ -0,0 +1,202 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Persistent cache of converted outputs, so that re-running a conversion over unchanged inputs
+//! does not decode them again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+/// Extension of cache entries; entries are named after their key.
+const ENTRY_EXTENSION: &str = "entry";
+
+/// Cache key of an input file together with the options that affect its output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheKey(String);
+
+impl CacheKey {
+    /// Hashes `input` together with `fingerprint`, a description of every option that influences
+    /// the produced output.
+    pub fn new(input: &[u8], fingerprint: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher
+            .update(&(fingerprint.len() as u64).to_le_bytes())
+            .update(fingerprint.as_bytes())
+            .update(input);
+        Self(hasher.finalize().to_hex().to_string())
+    }
+}
+
+pub struct DecodeCache {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+}
+
+impl DecodeCache {
+    /// Opens (and creates if needed) the cache in `dir`. If `max_bytes` is set, the least recently
+    /// used entries are evicted when the cache grows larger than that.
+    pub fn new(dir: &Path, max_bytes: Option<u64>) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create cache directory {dir:?}"))?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            max_bytes,
+        })
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(&key.0).with_extension(ENTRY_EXTENSION)
+    }
+
+    /// Copies the cached output for `key` to `output`. Returns false if there is no such entry.
+    pub fn restore(&self, key: &CacheKey, output: &Path) -> Result<bool> {
+        let entry = self.entry_path(key);
+        if !entry.exists() {
+            return Ok(false);
+        }
+        fs::copy(&entry, output)
+            .wrap_err_with(|| format!("Failed to copy cache entry {entry:?} to {output:?}"))?;
+        // Mark the entry as recently used.
+        fs::File::options()
+            .append(true)
+            .open(&entry)?
+            .set_modified(SystemTime::now())?;
+        Ok(true)
+    }
+
+    /// Stores `output` as the entry for `key`, then evicts old entries if the cache is too large.
+    pub fn insert(&self, key: &CacheKey, output: &Path) -> Result<()> {
+        let entry = self.entry_path(key);
+        // Copy to a temporary file first, so that concurrent runs never see partial entries.
+        let tmp = entry.with_extension(format!("tmp{}", std::process::id()));
+        fs::copy(output, &tmp)
+            .wrap_err_with(|| format!("Failed to copy {output:?} to cache entry {tmp:?}"))?;
+        fs::rename(&tmp, &entry)?;
+        self.evict(&entry)
+    }
+
+    /// Checks that the cached entry for `key`, if any, matches the freshly produced `output`.
+    pub fn verify(&self, key: &CacheKey, output: &Path) -> Result<()> {
+        let entry = self.entry_path(key);
+        if !entry.exists() {
+            return self.insert(key, output);
+        }
+        if fs::read(&entry)? != fs::read(output)? {
+            bail!("Cache entry {entry:?} does not match the decoded output {output:?}");
+        }
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache fits in `max_bytes`, keeping `keep`.
+    fn evict(&self, keep: &Path) -> Result<()> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(());
+        };
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|e| e == ENTRY_EXTENSION) {
+                let metadata = fs::metadata(&path)?;
+                entries.push((metadata.modified()?, metadata.len(), path));
+            }
+        }
+        let mut total: u64 = entries.iter().map(|e| e.1).sum();
+        entries.sort();
+        for (_, len, path) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            if path != keep {
+                fs::remove_file(&path)?;
+                total -= len;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A directory that is removed with its contents when dropped.
+    pub(crate) struct TempDir(pub PathBuf);
+
+    impl TempDir {
+        pub fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("jxl_cli_{name}_{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn key_depends_on_input_and_fingerprint() {
+        let key = CacheKey::new(b"input", "png");
+        assert_eq!(key, CacheKey::new(b"input", "png"));
+        assert_ne!(key, CacheKey::new(b"input", "ppm"));
+        assert_ne!(key, CacheKey::new(b"inpu", "tpng"));
+        assert_eq!(key.0.len(), 64);
+    }
+
+    #[test]
+    fn restores_and_verifies_entries() {
+        let tmp = TempDir::new("cache_entries");
+        let cache = DecodeCache::new(&tmp.0.join("cache"), None).unwrap();
+        let key = CacheKey::new(b"input", "png");
+        let output = tmp.0.join("output");
+        assert!(!cache.restore(&key, &output).unwrap());
+        fs::write(&output, b"decoded").unwrap();
+        cache.insert(&key, &output).unwrap();
+
+        let restored = tmp.0.join("restored");
+        assert!(cache.restore(&key, &restored).unwrap());
+        assert_eq!(fs::read(&restored).unwrap(), b"decoded");
+        // Verification accepts matching entries...
+        cache.verify(&key, &restored).unwrap();
+        // ...but rejects modified ones.
+        fs::write(cache.entry_path(&key), b"corrupt").unwrap();
+        assert!(cache.verify(&key, &output).is_err());
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let tmp = TempDir::new("cache_evict");
+        let cache = DecodeCache::new(&tmp.0.join("cache"), Some(250)).unwrap();
+        let keys: Vec<_> = (0..3u8).map(|i| CacheKey::new(&[i], "")).collect();
+        let output = tmp.0.join("output");
+        let base = SystemTime::now() - Duration::from_secs(100);
+        for (i, key) in keys.iter().enumerate() {
+            fs::write(&output, [i as u8; 100]).unwrap();
+            cache.insert(key, &output).unwrap();
+            // Make modification times deterministic regardless of the file system resolution.
+            fs::File::options()
+                .append(true)
+                .open(cache.entry_path(key))
+                .unwrap()
+                .set_modified(base + Duration::from_secs(i as u64))
+                .unwrap();
+            if i == 1 {
+                // Using the first entry makes the second one the least recently used.
+                assert!(cache.restore(&keys[0], &output).unwrap());
+            }
+        }
+        let exists: Vec<_> = keys.iter().map(|k| cache.entry_path(k).exists()).collect();
+        assert_eq!(exists, [true, false, true]);
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! BLAKE3 checksums of the codestream of a file and of each of its frame headers and sections, to
+//! store alongside the file and locate corrupted bytes later.
+//!
+//! Checksum files have a line per checksum, with the hexadecimal digest followed by two spaces and
+//! the name of the checksummed part, as in `sha256sum` output.
+
+use std::fmt::Write;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use jxl::api::{FileMap, Section, SectionHasher, map_file};
+
+/// Size of BLAKE3 digests, in bytes.
+const DIGEST_LEN: usize = 32;
+
+struct Blake3(blake3::Hasher);
+
+impl SectionHasher for Blake3 {
+    type Digest = [u8; DIGEST_LEN];
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finalize(self) -> Self::Digest {
+        self.0.finalize().into()
+    }
+}
+
+/// Returns the name of `section` as printed by `map`, `--checksum-out` and
+/// `--verify-checksums`.
+pub fn section_name(section: Option<Section>) -> String {
+    match section {
+        None => "all".to_string(),
+        Some(Section::LfGlobal) => "LfGlobal".to_string(),
+        Some(Section::Lf { group }) => format!("Lf {group}"),
+        Some(Section::HfGlobal) => "HfGlobal".to_string(),
+        Some(Section::Hf { group, pass }) => format!("Hf {group} pass {pass}"),
+    }
+}
+
+/// Checksum of a part of a codestream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    /// Name of the part, such as `codestream`, `frame 0 header` or `frame 0 Hf 3 pass 0`.
+    pub name: String,
+    /// Codestream offset of the part.
+    pub offset: u64,
+    pub len: u64,
+    pub digest: [u8; DIGEST_LEN],
+}
+
+/// Computes the checksums of the codestream of `bytes`, of its image header, and of each frame
+/// header and section.
+pub fn compute(bytes: &[u8]) -> Result<(FileMap, Vec<Checksum>)> {
+    let map = map_file(bytes)?;
+    let digests = map.section_digests(bytes, || Blake3(blake3::Hasher::new()));
+    let first_frame = map
+        .frames
+        .first()
+        .map_or(map.codestream_len, |f| f.header_bits_offset / 8);
+    let mut checksums = vec![
+        Checksum {
+            name: "codestream".to_string(),
+            offset: 0,
+            len: map.codestream_len,
+            digest: digests.codestream,
+        },
+        Checksum {
+            name: "image header".to_string(),
+            offset: 0,
+            len: first_frame,
+            digest: digests.image_header,
+        },
+    ];
+    let mut index = 0;
+    for (frame, frame_digests) in map.frames.iter().zip(digests.frames) {
+        let frame_name = if frame.is_preview {
+            "preview frame".to_string()
+        } else {
+            index += 1;
+            format!("frame {}", index - 1)
+        };
+        let header_offset = frame.header_bits_offset / 8;
+        checksums.push(Checksum {
+            name: format!("{frame_name} header"),
+            offset: header_offset,
+            len: frame.sections[0].offset - header_offset,
+            digest: frame_digests.header,
+        });
+        for (section, digest) in frame.sections.iter().zip(frame_digests.sections) {
+            checksums.push(Checksum {
+                name: format!("{frame_name} {}", section_name(section.section)),
+                offset: section.offset,
+                len: section.len,
+                digest,
+            });
+        }
+    }
+    Ok((map, checksums))
+}
+
+/// Formats `checksums` as the contents of a checksum file.
+pub fn format(checksums: &[Checksum]) -> String {
+    let mut text = String::new();
+    for checksum in checksums {
+        for b in checksum.digest {
+            write!(text, "{b:02x}").unwrap();
+        }
+        writeln!(text, "  {}", checksum.name).unwrap();
+    }
+    text
+}
+
+/// Parses the contents of a checksum file into (name, digest) pairs.
+pub fn parse(text: &str) -> Result<Vec<(String, [u8; DIGEST_LEN])>> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let invalid = || eyre!("Invalid checksum on line {}: {line:?}", i + 1);
+            let (hex, name) = line.split_once("  ").ok_or_else(invalid)?;
+            if hex.len() != 2 * DIGEST_LEN || !hex.is_ascii() {
+                return Err(invalid());
+            }
+            let mut digest = [0; DIGEST_LEN];
+            for (b, pair) in digest.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+                let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+                *b = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+            }
+            Ok((name.to_string(), digest))
+        })
+        .collect()
+}
+
+/// Difference between stored checksums and those of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The part has a different checksum.
+    Changed(Checksum),
+    /// The part is missing from the file, whose structure changed.
+    Missing(String),
+    /// The part was not checksummed, because the structure of the file changed.
+    Unexpected(String),
+}
+
+/// Compares the `expected` checksums, as parsed from a checksum file, with the `actual` ones of a
+/// file, and returns the differences in file order.
+pub fn compare(expected: &[(String, [u8; DIGEST_LEN])], actual: &[Checksum]) -> Vec<Mismatch> {
+    let mut mismatches = vec![];
+    for checksum in actual {
+        match expected.iter().find(|(name, _)| *name == checksum.name) {
+            Some((_, digest)) if *digest == checksum.digest => {}
+            Some(_) => mismatches.push(Mismatch::Changed(checksum.clone())),
+            None => mismatches.push(Mismatch::Unexpected(checksum.name.clone())),
+        }
+    }
+    for (name, _) in expected {
+        if !actual.iter().any(|c| c.name == *name) {
+            mismatches.push(Mismatch::Missing(name.clone()));
+        }
+    }
+    mismatches
+}
+
+/// Checks `bytes` against the contents of a checksum file, printing each difference to stdout,
+/// and fails if there is any.
+pub fn verify(bytes: &[u8], checksum_file: &str) -> Result<()> {
+    let expected = parse(checksum_file)?;
+    let (map, actual) = compute(bytes)?;
+    let mismatches = compare(&expected, &actual);
+    for mismatch in &mismatches {
+        match mismatch {
+            Mismatch::Changed(checksum) => {
+                let file_offset = map
+                    .file_offset(checksum.offset)
+                    .map_or("-".to_string(), |o| o.to_string());
+                println!(
+                    "{}: checksum mismatch (codestream offset {}, file offset {file_offset}, \
+                     {} bytes)",
+                    checksum.name, checksum.offset, checksum.len
+                );
+            }
+            Mismatch::Missing(name) => println!("{name}: missing from the file"),
+            Mismatch::Unexpected(name) => println!("{name}: not in the checksum file"),
+        }
+    }
+    if !mismatches.is_empty() {
+        bail!(
+            "{} of {} checksums do not match",
+            mismatches.len(),
+            expected.len()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file(name: &str) -> Vec<u8> {
+        let root = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        std::fs::read(root.parent().unwrap().join("jxl/resources/test").join(name)).unwrap()
+    }
+
+    #[test]
+    fn format_and_parse() {
+        let (_, checksums) = compute(&test_file("has_permutation_with_container.jxl")).unwrap();
+        assert_eq!(checksums[0].name, "codestream");
+        assert!(checksums.iter().any(|c| c.name == "frame 0 Hf 0 pass 0"));
+        let parsed = parse(&format(&checksums)).unwrap();
+        let names: Vec<_> = checksums.iter().map(|c| c.name.clone()).collect();
+        assert_eq!(
+            parsed.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>(),
+            names
+        );
+        assert!(compare(&parsed, &checksums).is_empty());
+
+        assert!(parse("00  codestream").is_err());
+        assert!(parse(&format!("{}  codestream", "zz".repeat(DIGEST_LEN))).is_err());
+        assert!(parse(&"00".repeat(DIGEST_LEN)).is_err());
+        assert!(parse("\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn structure_changes() {
+        let (_, checksums) = compute(&test_file("basic.jxl")).unwrap();
+        let mut expected = parse(&format(&checksums)).unwrap();
+        expected[1].1[0] ^= 1;
+        expected[2].0 = "frame 7 header".to_string();
+        assert_eq!(
+            compare(&expected, &checksums),
+            [
+                Mismatch::Changed(checksums[1].clone()),
+                Mismatch::Unexpected(checksums[2].name.clone()),
+                Mismatch::Missing("frame 7 header".to_string()),
+            ]
+        );
+    }
+}
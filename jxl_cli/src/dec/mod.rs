@@ -4,7 +4,8 @@
 // license that can be found in the LICENSE file.
 
 use std::{
-    io::BufReader,
+    fmt,
+    io::{BufReader, Read, Seek, SeekFrom},
     str::FromStr,
     time::{Duration, Instant},
 };
@@ -12,19 +13,76 @@ use std::{
 use color_eyre::eyre::{Result, eyre};
 use jxl::{
     api::{
-        Endianness, JxlAnimation, JxlBitDepth, JxlBitstreamInput, JxlColorProfile, JxlColorType,
-        JxlDataFormat, JxlDecoder, JxlDecoderOptions, JxlOutputBuffer, JxlPixelFormat,
-        ProcessingResult, states::WithImageInfo,
+        BufferRequirement, CompressionSummary, ConcealedGroup, Endianness, EntropyCodeInfo,
+        FIND_STREAM_LOOKAHEAD, FrameTiming, GroupLayout, JxlAnimation, JxlBitDepth,
+        JxlBitstreamInput, JxlColorProfile, JxlColorType, JxlDataFormat, JxlDecodeTimings,
+        JxlDecoder, JxlDecoderOptions, JxlExtraChannelType, JxlFrameDiff, JxlOutputBuffer,
+        JxlPixelFormat, JxlTransferFunction, ModularChannelCheck, ModularStats, OutputLayout,
+        PassesInfo, PreferredOutput, ProcessingResult, RenderingChoice, Section, find_stream,
+        states::WithImageInfo,
     },
-    headers::extra_channels::ExtraChannel,
     image::{OwnedRawImage, Rect},
 };
 
+use crate::report::Reporter;
+
+pub mod png;
+pub mod pnm;
+pub mod pool;
+mod rows;
+
+pub use pool::{BufferPool, DEFAULT_POOL_DEPTH, FrameLease};
+pub use rows::{FrameRows, RowOrder};
+
 pub struct ImageFrame {
     pub partial_renders: Vec<Vec<OwnedRawImage>>,
     pub channels: Vec<OwnedRawImage>,
-    pub duration: f64,
+    /// Duration of the frame, for animations.
+    pub timing: Option<FrameTiming>,
     pub color_type: JxlColorType,
+    pub name: String,
+    /// Progressive passes of the frame.
+    pub passes: PassesInfo,
+    /// Number of passes that were fully decoded. This is only smaller than the number of passes
+    /// for frames truncated with `allow_partial_files`.
+    pub completed_passes: usize,
+    /// Groups in which the frame is coded, for JPEG XL frames whose header was decoded.
+    pub groups: Option<GroupLayout>,
+    /// Difference with the previous frame, if `JxlDecoderOptions::compute_frame_diffs` was set.
+    pub diff: Option<JxlFrameDiff>,
+    /// Statistics about the modular streams of the frame, only collected with the
+    /// `timing-stats` feature.
+    pub modular_stats: Option<ModularStats>,
+    /// Checks of the modular streams of the frame, only done with the `verify` feature and
+    /// `JxlDecoderOptions::verify_modular`.
+    pub modular_checks: Option<Vec<ModularChannelCheck>>,
+    /// Entropy codes read since the previous frame, only collected with the `debug-tools`
+    /// feature and `JxlDecoderOptions::dump_entropy_codes`.
+    pub entropy_codes: Option<Vec<EntropyCodeInfo>>,
+}
+
+impl ImageFrame {
+    /// Frame names longer than this many bytes are shortened by [`Self::listed_name`].
+    pub const MAX_LISTED_NAME_LEN: usize = 100;
+
+    /// The quoted name of the frame, shortened to its first [`Self::MAX_LISTED_NAME_LEN`] bytes
+    /// and followed by its length if it is longer.
+    pub fn listed_name(&self) -> String {
+        listed_name(&self.name)
+    }
+}
+
+/// Quotes a name read from a file, such as the name of a frame or of an extra channel, and
+/// shortens it like [`ImageFrame::listed_name`].
+pub fn listed_name(name: &str) -> String {
+    if name.len() <= ImageFrame::MAX_LISTED_NAME_LEN {
+        return format!("{name:?}");
+    }
+    let end = (0..=ImageFrame::MAX_LISTED_NAME_LEN)
+        .rev()
+        .find(|&i| name.is_char_boundary(i))
+        .unwrap();
+    format!("{:?}... ({} bytes)", &name[..end], name.len())
 }
 
 pub struct DecodeOutput {
@@ -35,6 +93,51 @@ pub struct DecodeOutput {
     pub output_profile: JxlColorProfile,
     pub embedded_profile: JxlColorProfile,
     pub jxl_animation: Option<JxlAnimation>,
+    /// Time spent in each decoding stage, only measured with the `timing-stats` feature.
+    pub timings: JxlDecodeTimings,
+    /// Byte order of the 16 and 32-bit samples in the frames. Only the npy writer supports a
+    /// byte order other than the native one.
+    pub endianness: Endianness,
+    /// Whether the frames are stored row by row or column by column, in which case their
+    /// buffers have one row per column of the image. Only the npy writer supports column-major
+    /// frames.
+    pub layout: OutputLayout,
+}
+
+impl DecodeOutput {
+    /// A copy of the properties of the output, with the given frames.
+    pub fn with_frames(&self, frames: Vec<ImageFrame>) -> Self {
+        Self {
+            size: self.size,
+            frames,
+            data_type: self.data_type,
+            original_bit_depth: self.original_bit_depth.clone(),
+            output_profile: self.output_profile.clone(),
+            embedded_profile: self.embedded_profile.clone(),
+            jxl_animation: self.jxl_animation.clone(),
+            timings: self.timings.clone(),
+            endianness: self.endianness,
+            layout: self.layout,
+        }
+    }
+}
+
+/// Wall-clock time spent in the parts of a decoding by [`decode_frames_leased`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DecodeTime {
+    /// Reading the container and the image header, and configuring the decoder.
+    pub headers: Duration,
+    /// Decoding each frame, including its conversion to the output color space and data type,
+    /// which the decoder does while rendering.
+    pub frames: Vec<Duration>,
+    /// Handing the decoded frames over to the caller, which keeps or drops them.
+    pub convert: Duration,
+}
+
+impl DecodeTime {
+    pub fn total(&self) -> Duration {
+        self.headers + self.frames.iter().sum::<Duration>() + self.convert
+    }
 }
 
 pub fn decode_header<In: JxlBitstreamInput>(
@@ -44,11 +147,126 @@ pub fn decode_header<In: JxlBitstreamInput>(
     let initialized_decoder = JxlDecoder::<jxl::api::states::Initialized>::new(decoder_options);
 
     match initialized_decoder.process(input)? {
-        ProcessingResult::Complete { result } => Ok(result),
+        ProcessingResult::Complete { result } => {
+            if let Some(mismatch) = result.color_profile_mismatch() {
+                Reporter::get().warn(format_args!("{mismatch}"));
+            }
+            if let Some(mismatch) = result.size_mismatch() {
+                Reporter::get().warn(format_args!("{mismatch}"));
+            }
+            Ok(result)
+        }
         ProcessingResult::NeedsMoreInput { .. } => Err(eyre!("Source file truncated")),
     }
 }
 
+/// Walks the headers of all remaining frames without decoding them, and returns the resulting
+/// compression summary. `decoder` should have been created with `scan_frames_only` enabled.
+pub fn scan_compression_summary<In: JxlBitstreamInput>(
+    input: &mut In,
+    mut decoder: JxlDecoder<WithImageInfo>,
+) -> Result<CompressionSummary> {
+    while decoder.has_more_frames() {
+        let frame = match decoder.process(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+        decoder = match frame.skip_frame(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+    }
+    decoder
+        .compression_summary()
+        .ok_or_else(|| eyre!("Could not parse all frame headers"))
+}
+
+/// Walks the frame headers of `input` without decoding the frames, and returns the number of
+/// frames that [`decode_frames_leased`] hands over with the same options when its frames are
+/// selected by `selectors`, or all frames without selectors.
+pub fn count_frames<In: JxlBitstreamInput>(
+    input: &mut In,
+    mut decoder_options: JxlDecoderOptions,
+    selectors: &[FrameSelector],
+) -> Result<usize> {
+    decoder_options.scan_frames_only = true;
+    let mut decoder = decode_header(input, decoder_options)?;
+    let mut selection = FrameSelection::new(selectors);
+    let mut num_frames = 0;
+    while decoder.has_more_frames() {
+        let frame = match decoder.process(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+        let action = if selectors.is_empty() {
+            FrameAction::Decode
+        } else {
+            selection.next_action(&frame.frame_header().name)
+        };
+        match action {
+            FrameAction::Decode => num_frames += 1,
+            FrameAction::Skip => {}
+            FrameAction::Stop => break,
+        }
+        decoder = match frame.skip_frame(input)? {
+            ProcessingResult::Complete { result } => result,
+            ProcessingResult::NeedsMoreInput { .. } => return Err(eyre!("Source file truncated")),
+        };
+    }
+    Ok(num_frames)
+}
+
+/// Size of the blocks in which [`decode_embedded`] scans its input.
+const SCAN_BLOCK_SIZE: usize = 1 << 20;
+
+/// Scans `input` for embedded JPEG XL streams, and calls `decode` with the input positioned at
+/// each candidate offset in turn until it succeeds. Returns the offset of the decoded stream
+/// together with the result of `decode`.
+///
+/// The input is scanned block by block, so streams found early do not require reading all of
+/// it.
+pub fn decode_embedded<In: Read + Seek, T>(
+    input: &mut In,
+    mut decode: impl FnMut(&mut In) -> Result<T>,
+) -> Result<(u64, T)> {
+    let mut block_start = 0;
+    let mut block = vec![];
+    let mut last_error = None;
+    loop {
+        input.seek(SeekFrom::Start(block_start))?;
+        block.clear();
+        // Blocks overlap, so that the headers following a signature can always be validated.
+        let read_size = SCAN_BLOCK_SIZE + FIND_STREAM_LOOKAHEAD;
+        input
+            .by_ref()
+            .take(read_size as u64)
+            .read_to_end(&mut block)?;
+        let is_last_block = block.len() < read_size;
+        for offset in find_stream(&block) {
+            if offset >= SCAN_BLOCK_SIZE && !is_last_block {
+                // Will be found again in the next block.
+                break;
+            }
+            let offset = block_start + offset as u64;
+            input.seek(SeekFrom::Start(offset))?;
+            match decode(input) {
+                Ok(result) => return Ok((offset, result)),
+                Err(err) => last_error = Some((offset, err)),
+            }
+        }
+        if is_last_block {
+            break;
+        }
+        block_start += SCAN_BLOCK_SIZE as u64;
+    }
+    Err(match last_error {
+        Some((offset, err)) => err.wrap_err(format!(
+            "No decodable JPEG XL stream found, last attempt at offset {offset} failed"
+        )),
+        None => eyre!("No JPEG XL stream found"),
+    })
+}
+
 /// Output data type for decoding.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputDataType {
@@ -72,6 +290,278 @@ impl FromStr for OutputDataType {
     }
 }
 
+/// Byte order of the 16 and 32-bit samples of the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputEndianness {
+    #[default]
+    Native,
+    Little,
+    Big,
+}
+
+impl OutputEndianness {
+    pub fn to_endianness(self) -> Endianness {
+        match self {
+            Self::Native => Endianness::native(),
+            Self::Little => Endianness::LittleEndian,
+            Self::Big => Endianness::BigEndian,
+        }
+    }
+}
+
+impl FromStr for OutputEndianness {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "native" => Ok(Self::Native),
+            "little" => Ok(Self::Little),
+            "big" => Ok(Self::Big),
+            _ => Err(format!(
+                "Unknown endianness {s}, expected native, little or big"
+            )),
+        }
+    }
+}
+
+/// Selects decoded frames, by their zero-based index among the visible frames or by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FrameSelector {
+    Index(usize),
+    /// The frames from `start` up to, but not including, `end`, or up to the last frame if there
+    /// is no end.
+    Range {
+        start: usize,
+        end: Option<usize>,
+    },
+    Name(String),
+}
+
+impl FrameSelector {
+    /// Number of frames the selector selects at least, if the image has them.
+    pub fn min_frames(&self) -> usize {
+        match self {
+            Self::Range {
+                start,
+                end: Some(end),
+            } => end - start,
+            _ => 1,
+        }
+    }
+
+    /// Index of the last frame the selector selects, if it is known before decoding.
+    fn last_index(&self) -> Option<usize> {
+        match self {
+            Self::Index(i) => Some(*i),
+            Self::Range { end, .. } => end.map(|end| end - 1),
+            Self::Name(_) => None,
+        }
+    }
+}
+
+impl FromStr for FrameSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(name) = s.strip_prefix("name=") {
+            return Ok(Self::Name(name.to_string()));
+        }
+        let invalid = || {
+            format!(
+                "Invalid frame {s}, expected an index, a range A..B, A..=B or A.., or name=<frame name>"
+            )
+        };
+        let Some((start, end)) = s.split_once("..") else {
+            return s.parse().map(Self::Index).map_err(|_| invalid());
+        };
+        let start = match start {
+            "" => 0,
+            start => start.parse().map_err(|_| invalid())?,
+        };
+        let end = match end.strip_prefix('=') {
+            Some(last) => Some(last.parse::<usize>().map_err(|_| invalid())? + 1),
+            None if end.is_empty() => None,
+            None => Some(end.parse().map_err(|_| invalid())?),
+        };
+        if end.is_some_and(|end| end <= start) {
+            return Err(format!("Empty frame range {s}"));
+        }
+        Ok(Self::Range { start, end })
+    }
+}
+
+impl fmt::Display for FrameSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Index(i) => write!(f, "frame {i}"),
+            Self::Range { start, end: None } => write!(f, "frames {start}.."),
+            Self::Range {
+                start,
+                end: Some(end),
+            } => write!(f, "frames {start}..{end}"),
+            Self::Name(name) => write!(f, "frame named {name:?}"),
+        }
+    }
+}
+
+/// Size of the decoded output, if it differs from the size of the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputSize {
+    /// Resizes the image to exactly `width x height`, ignoring its aspect ratio.
+    Exact(usize, usize),
+    /// Scales the image, preserving its aspect ratio, so that its longer side has this length.
+    LongEdge(usize),
+    /// Divides both sides of the image by this factor, rounding up, and decodes only the data
+    /// needed for that resolution where the frames allow it.
+    Downsample(u32),
+}
+
+impl OutputSize {
+    /// Returns the output size for an image of `size`.
+    pub fn for_image(self, size: (usize, usize)) -> (usize, usize) {
+        match self {
+            Self::Exact(width, height) => (width, height),
+            Self::LongEdge(edge) => {
+                let long = size.0.max(size.1);
+                let scale = |side: usize| {
+                    ((side as f64 * edge as f64 / long as f64).round() as usize).max(1)
+                };
+                (scale(size.0), scale(size.1))
+            }
+            Self::Downsample(factor) => (
+                size.0.div_ceil(factor as usize),
+                size.1.div_ceil(factor as usize),
+            ),
+        }
+    }
+}
+
+impl FromStr for OutputSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parsed = s
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+        match parsed {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Self::Exact(width, height)),
+            _ => Err(format!("Invalid size {s}, expected WIDTHxHEIGHT")),
+        }
+    }
+}
+
+/// What to do with a frame, decided from its header before its pixels are decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameAction {
+    Decode,
+    /// Skips the frame without rendering it. Its data is still decoded if later frames refer to
+    /// it.
+    Skip,
+    /// Stops decoding before the frame, since none of the remaining frames are needed.
+    Stop,
+}
+
+/// Selection of frames by [`FrameSelector`]s, made one frame at a time as they are decoded.
+pub struct FrameSelection<'a> {
+    selectors: &'a [FrameSelector],
+    /// Whether each selector matched a frame so far, or all its frames for bounded ranges.
+    matched: Vec<bool>,
+    index: usize,
+}
+
+impl<'a> FrameSelection<'a> {
+    pub fn new(selectors: &'a [FrameSelector]) -> Self {
+        Self {
+            selectors,
+            matched: vec![false; selectors.len()],
+            index: 0,
+        }
+    }
+
+    /// Returns whether the frame named `name`, which follows the frames previously passed to
+    /// this function, is matched by any of the selectors.
+    pub fn select(&mut self, name: &str) -> bool {
+        let mut selected = false;
+        for (selector, matched) in self.selectors.iter().zip(self.matched.iter_mut()) {
+            let index = self.index;
+            let (is_selected, is_matched) = match selector {
+                FrameSelector::Index(i) => (*i == index, *i == index),
+                FrameSelector::Range { start, end } => (
+                    index >= *start && end.is_none_or(|end| index < end),
+                    index + 1 == end.unwrap_or(*start + 1),
+                ),
+                FrameSelector::Name(selected_name) => {
+                    (selected_name == name, selected_name == name)
+                }
+            };
+            *matched |= is_matched;
+            selected |= is_selected;
+        }
+        self.index += 1;
+        selected
+    }
+
+    /// Like [`Self::select`], but also tells when no later frame can be selected.
+    pub fn next_action(&mut self, name: &str) -> FrameAction {
+        let complete = self
+            .selectors
+            .iter()
+            .zip(&self.matched)
+            .all(|(selector, matched)| *matched && selector.last_index().is_some());
+        if complete {
+            FrameAction::Stop
+        } else if self.select(name) {
+            FrameAction::Decode
+        } else {
+            FrameAction::Skip
+        }
+    }
+
+    /// Fails if a selector did not match all its frames. Unless decoding stopped after
+    /// [`FrameAction::Stop`], all frames of the image were passed.
+    pub fn finish(self) -> Result<()> {
+        let Some((selector, _)) = self.selectors.iter().zip(self.matched).find(|x| !x.1) else {
+            return Ok(());
+        };
+        let num_frames = self.index;
+        match selector {
+            FrameSelector::Name(_) => Err(eyre!("No frame matches {selector}")),
+            FrameSelector::Index(i) => Err(eyre!(
+                "Frame {i} is out of range, the image has {num_frames} frame(s)"
+            )),
+            FrameSelector::Range { .. } => Err(eyre!(
+                "The {selector} are out of range, the image has {num_frames} frame(s)"
+            )),
+        }
+    }
+}
+
+/// Keeps the frames matched by any of `selectors`, in their original order. Fails if a selector
+/// does not match all its frames.
+pub fn select_frames(
+    frames: Vec<ImageFrame>,
+    selectors: &[FrameSelector],
+) -> Result<Vec<ImageFrame>> {
+    let mut selection = FrameSelection::new(selectors);
+    let frames = frames
+        .into_iter()
+        .filter(|frame| selection.select(&frame.name))
+        .collect();
+    selection.finish()?;
+    Ok(frames)
+}
+
+impl From<PreferredOutput> for OutputDataType {
+    fn from(preferred: PreferredOutput) -> Self {
+        match preferred {
+            PreferredOutput::U8 => Self::U8,
+            PreferredOutput::U16 => Self::U16,
+            PreferredOutput::F16 => Self::F16,
+            PreferredOutput::F32 => Self::F32,
+        }
+    }
+}
+
 impl OutputDataType {
     pub const ALL: &'static [OutputDataType] = &[
         OutputDataType::U8,
@@ -80,23 +570,25 @@ impl OutputDataType {
         OutputDataType::F32,
     ];
 
-    /// Get the JxlDataFormat for this type.
-    pub fn to_data_format(self) -> JxlDataFormat {
+    /// Get the JxlDataFormat for this type, with multi-byte samples in the given byte order.
+    pub fn to_data_format(self, endianness: Endianness) -> JxlDataFormat {
         match self {
             Self::U8 => JxlDataFormat::U8 { bit_depth: 8 },
             Self::U16 => JxlDataFormat::U16 {
-                endianness: Endianness::native(),
+                endianness,
                 bit_depth: 16,
             },
-            Self::F16 => JxlDataFormat::F16 {
-                endianness: Endianness::native(),
-            },
-            Self::F32 => JxlDataFormat::f32(),
+            Self::F16 => JxlDataFormat::F16 { endianness },
+            Self::F32 => JxlDataFormat::F32 { endianness },
         }
     }
 
     pub fn bits_per_sample(&self) -> usize {
-        self.to_data_format().bytes_per_sample() * 8
+        self.to_data_format(Endianness::native()).bytes_per_sample() * 8
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::F16 | Self::F32)
     }
 }
 
@@ -128,59 +620,95 @@ where
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-pub fn decode_frames<In: JxlBitstreamInputExt>(
-    input: &mut In,
-    decoder_options: JxlDecoderOptions,
+fn byte_sizes(requirements: &[BufferRequirement]) -> Vec<(usize, usize)> {
+    requirements
+        .iter()
+        .map(BufferRequirement::byte_size)
+        .collect()
+}
+
+/// Describes how many of the groups of a frame coded in `layout` were concealed, such as
+/// "concealed 3 of 480 groups".
+fn concealment_summary(concealed: &[ConcealedGroup], layout: &GroupLayout) -> String {
+    let (lf_groups, groups): (Vec<&ConcealedGroup>, Vec<_>) = concealed
+        .iter()
+        .partition(|group| matches!(group.section, Section::Lf { .. }));
+    let mut summary = format!(
+        "concealed {} of {} groups",
+        groups.len(),
+        layout.num_groups.0 * layout.num_groups.1
+    );
+    if !lf_groups.is_empty() {
+        summary += &format!(
+            ", with interpolated DC in {} of {} LF groups",
+            lf_groups.len(),
+            layout.num_lf_groups.0 * layout.num_lf_groups.1
+        );
+    }
+    summary
+}
+
+/// Chooses the accepted output type that best fits samples of `bit_depth`, or of
+/// `requested_bit_depth` bits if set.
+fn default_output_type(
+    bit_depth: &JxlBitDepth,
+    requested_bit_depth: Option<usize>,
+    accepted_output_types: &[OutputDataType],
+) -> OutputDataType {
+    let preferred = match requested_bit_depth {
+        // The override always describes integer samples, even for float inputs.
+        Some(bits) => JxlBitDepth::Int {
+            bits_per_sample: bits as u32,
+        }
+        .preferred_output(),
+        None => bit_depth.preferred_output(),
+    };
+    let preferred = OutputDataType::from(preferred);
+    if accepted_output_types.contains(&preferred) {
+        return preferred;
+    }
+    // Keep float samples float, and integer samples integer, when the format allows it.
+    let wide_enough = |x: &&OutputDataType| x.bits_per_sample() >= preferred.bits_per_sample();
+    *accepted_output_types
+        .iter()
+        .filter(wide_enough)
+        .find(|x| x.is_float() == preferred.is_float())
+        .or_else(|| accepted_output_types.iter().find(wide_enough))
+        .unwrap_or(accepted_output_types.last().unwrap())
+}
+
+/// Chooses the data type of the output and the pixel format that the decoder should produce.
+fn output_pixel_format(
+    decoder: &JxlDecoder<WithImageInfo>,
     requested_bit_depth: Option<usize>,
     requested_output_type: Option<OutputDataType>,
     accepted_output_types: &[OutputDataType],
     interleave_alpha: bool,
-    linear_output: bool,
-    render_interval: Option<usize>,
-    allow_partial_files: bool,
-) -> Result<(DecodeOutput, Duration)> {
-    let start = Instant::now();
-
-    let mut decoder_with_image_info = decode_header(input, decoder_options)?;
-
-    // Get info and clone what we need before mutating the decoder
-    let info = decoder_with_image_info.basic_info().clone();
-    let embedded_profile = decoder_with_image_info.embedded_color_profile().clone();
-
-    let output_type = if let Some(ot) = requested_output_type
-        && accepted_output_types.contains(&ot)
-    {
-        ot
-    } else {
-        if requested_output_type.is_some() {
-            eprintln!("Warning: requested output type is not compatible with output format");
-        }
-        let bit_depth = requested_bit_depth.unwrap_or(info.bit_depth.bits_per_sample() as usize);
-        *accepted_output_types
-            .iter()
-            .find(|x| x.bits_per_sample() >= bit_depth)
-            .unwrap_or(accepted_output_types.last().unwrap())
+    endianness: Endianness,
+) -> (OutputDataType, JxlPixelFormat) {
+    let info = decoder.basic_info();
+    let output_type = match requested_output_type {
+        Some(ot) if accepted_output_types.contains(&ot) => ot,
+        _ => default_output_type(&info.bit_depth, requested_bit_depth, accepted_output_types),
     };
 
     let main_alpha_channel = info
         .extra_channels
         .iter()
         .enumerate()
-        .find(|x| x.1.ec_type == ExtraChannel::Alpha)
+        .find(|x| x.1.ec_type == JxlExtraChannelType::Alpha)
         .map(|x| x.0);
 
     let interleave_alpha = interleave_alpha && main_alpha_channel.is_some();
 
-    // Set the pixel format to the requested data type
-    let current_format = decoder_with_image_info.current_pixel_format().clone();
-    let new_format = JxlPixelFormat {
+    let current_format = decoder.current_pixel_format();
+    let format = JxlPixelFormat {
         color_type: if interleave_alpha {
             current_format.color_type.add_alpha()
         } else {
             current_format.color_type
         },
-        color_data_format: Some(output_type.to_data_format()),
+        color_data_format: Some(output_type.to_data_format(endianness)),
         extra_channel_format: current_format
             .extra_channel_format
             .iter()
@@ -189,11 +717,200 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
                 if interleave_alpha && Some(c) == main_alpha_channel {
                     None
                 } else {
-                    f.as_ref().map(|_| output_type.to_data_format())
+                    f.as_ref().map(|_| output_type.to_data_format(endianness))
                 }
             })
             .collect(),
     };
+    (output_type, format)
+}
+
+/// Samples of the output of a decoding, as far as they are known from the image header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputShape {
+    pub size: (usize, usize),
+    pub num_frames: usize,
+    /// Samples per pixel of the color channels, including the alpha channel if it is
+    /// interleaved with them.
+    pub color_samples: usize,
+    /// Number of extra channels that are output separately.
+    pub extra_channels: usize,
+    pub data_type: OutputDataType,
+    pub layout: OutputLayout,
+}
+
+impl OutputShape {
+    /// Returns the shape of the frames that [`decode_frames`] produces with the same arguments,
+    /// for a single frame of the size of the image. The decoder must not have been configured
+    /// yet.
+    pub fn of_image(
+        decoder: &JxlDecoder<WithImageInfo>,
+        requested_bit_depth: Option<usize>,
+        requested_output_type: Option<OutputDataType>,
+        accepted_output_types: &[OutputDataType],
+        interleave_alpha: bool,
+        output_size: Option<OutputSize>,
+        layout: OutputLayout,
+    ) -> Self {
+        let (data_type, format) = output_pixel_format(
+            decoder,
+            requested_bit_depth,
+            requested_output_type,
+            accepted_output_types,
+            interleave_alpha,
+            Endianness::native(),
+        );
+        let size = decoder.basic_info().size;
+        OutputShape {
+            size: output_size.map_or(size, |s| s.for_image(size)),
+            num_frames: 1,
+            color_samples: format.color_type.samples_per_pixel(),
+            extra_channels: format.extra_channel_format.iter().flatten().count(),
+            data_type,
+            layout,
+        }
+    }
+
+    /// Returns the shape of decoded frames.
+    pub fn of_output(output: &DecodeOutput) -> Self {
+        let frame = &output.frames[0];
+        OutputShape {
+            size: output.size,
+            num_frames: output.frames.len(),
+            color_samples: frame.color_type.samples_per_pixel(),
+            extra_channels: frame.channels.len() - 1,
+            data_type: output.data_type,
+            layout: output.layout,
+        }
+    }
+
+    /// Size of the samples of all frames, in bytes.
+    pub fn sample_bytes(&self) -> u64 {
+        let samples = (self.color_samples + self.extra_channels) as u64
+            * self.size.0 as u64
+            * self.size.1 as u64
+            * self.num_frames as u64;
+        samples * (self.data_type.bits_per_sample() / 8) as u64
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn decode_frames<In: JxlBitstreamInputExt>(
+    input: &mut In,
+    decoder_options: JxlDecoderOptions,
+    requested_bit_depth: Option<usize>,
+    requested_output_type: Option<OutputDataType>,
+    accepted_output_types: &[OutputDataType],
+    interleave_alpha: bool,
+    linear_output: bool,
+    render_interval: Option<usize>,
+    allow_partial_files: bool,
+    max_tokens_per_pixel: Option<u64>,
+    output_size: Option<OutputSize>,
+    endianness: Endianness,
+) -> Result<(DecodeOutput, DecodeTime)> {
+    let mut frames = vec![];
+    // Every frame is kept, so there is nothing to reuse.
+    let (mut output, time) = decode_frames_leased(
+        input,
+        decoder_options,
+        requested_bit_depth,
+        requested_output_type,
+        accepted_output_types,
+        interleave_alpha,
+        linear_output,
+        render_interval,
+        allow_partial_files,
+        max_tokens_per_pixel,
+        output_size,
+        endianness,
+        &BufferPool::new(0),
+        |_| FrameAction::Decode,
+        |_, frame| {
+            frames.push(frame.into_owned());
+            Ok(())
+        },
+    )?;
+    output.frames = frames;
+    Ok((output, time))
+}
+
+/// Same as [`decode_frames`], but passes each frame to `on_frame` as soon as it is decoded, in
+/// buffers taken from `pool`, instead of returning it in the output. `on_frame` also gets the
+/// output the frame belongs to, without any frames. Consumers that drop the frames they are done
+/// with let the following frames reuse their buffers.
+///
+/// Before a frame is decoded, `frame_action` gets its name and decides whether to decode it, to
+/// skip it, or to stop decoding. Frames of truncated files whose header is incomplete get an
+/// empty name.
+#[allow(clippy::too_many_arguments)]
+pub fn decode_frames_leased<In: JxlBitstreamInputExt>(
+    input: &mut In,
+    mut decoder_options: JxlDecoderOptions,
+    requested_bit_depth: Option<usize>,
+    requested_output_type: Option<OutputDataType>,
+    accepted_output_types: &[OutputDataType],
+    interleave_alpha: bool,
+    linear_output: bool,
+    render_interval: Option<usize>,
+    allow_partial_files: bool,
+    max_tokens_per_pixel: Option<u64>,
+    output_size: Option<OutputSize>,
+    endianness: Endianness,
+    pool: &BufferPool,
+    mut frame_action: impl FnMut(&str) -> FrameAction,
+    mut on_frame: impl FnMut(&DecodeOutput, FrameLease) -> Result<()>,
+) -> Result<(DecodeOutput, DecodeTime)> {
+    let start = Instant::now();
+    let mut time = DecodeTime::default();
+    let mut convert = Duration::ZERO;
+    let mut on_frame = |image: &DecodeOutput, frame| {
+        let start = Instant::now();
+        let result = on_frame(image, frame);
+        convert += start.elapsed();
+        result
+    };
+    // Partial renders only write the decoded parts of the frame, which must not show pixels of
+    // the frame that used the buffers before.
+    let clear_buffers = render_interval.is_some() || allow_partial_files;
+
+    let rendering = decoder_options.rendering_intent_override;
+    let layout = decoder_options.output_layout;
+    let crop = decoder_options.crop;
+    if let Some(OutputSize::Downsample(factor)) = output_size {
+        decoder_options.downsample = factor;
+    }
+    let downsample = decoder_options.downsample;
+    let mut decoder_with_image_info = decode_header(input, decoder_options)?;
+
+    // Get info and clone what we need before mutating the decoder
+    let info = decoder_with_image_info.basic_info().clone();
+    if let Some(tokens_per_pixel) = max_tokens_per_pixel {
+        // VarDCT codes whole 8x8 blocks, which matters for tiny images.
+        let pixels =
+            info.size.0.next_multiple_of(8) as u64 * info.size.1.next_multiple_of(8) as u64;
+        decoder_with_image_info.set_max_total_tokens(Some(pixels.saturating_mul(tokens_per_pixel)));
+    }
+    let image_size = crop.map_or(info.size, |c| c.size);
+    let size = output_size.map_or(image_size, |s| s.for_image(image_size));
+    if output_size.is_some() {
+        decoder_with_image_info.set_resize_to(Some(size));
+    }
+    let embedded_profile = decoder_with_image_info.embedded_color_profile().clone();
+
+    if requested_output_type.is_some_and(|ot| !accepted_output_types.contains(&ot)) {
+        Reporter::get().warn(format_args!(
+            "requested output type is not compatible with output format"
+        ));
+    }
+    let (output_type, new_format) = output_pixel_format(
+        &decoder_with_image_info,
+        requested_bit_depth,
+        requested_output_type,
+        accepted_output_types,
+        interleave_alpha,
+        endianness,
+    );
     decoder_with_image_info.set_pixel_format(new_format);
 
     // If linear output is requested, modify the output profile
@@ -203,38 +920,50 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
         decoder_with_image_info
             .set_output_color_profile(JxlColorProfile::Simple(enc.with_linear_tf()))?;
     }
+    // HDR transfer functions always render for HDR displays, so SDR rendering needs another one.
+    let profile = decoder_with_image_info.output_color_profile().clone();
+    if rendering == Some(RenderingChoice::Sdr)
+        && matches!(
+            profile.transfer_function(),
+            Some(JxlTransferFunction::PQ | JxlTransferFunction::HLG)
+        )
+        && let JxlColorProfile::Simple(enc) = profile
+    {
+        decoder_with_image_info.set_output_color_profile(JxlColorProfile::Simple(
+            enc.with_transfer_function(JxlTransferFunction::SRGB),
+        ))?;
+    }
     let output_profile = decoder_with_image_info.output_color_profile().clone();
 
-    let mut image_data = DecodeOutput {
-        size: info.size,
+    // Frames are handed over with the output they belong to, whose timings are only filled in
+    // once all of them are decoded.
+    #[cfg(feature = "timing-stats")]
+    let mut timings = JxlDecodeTimings::default();
+    let image_data = DecodeOutput {
+        size,
         frames: Vec::new(),
         data_type: output_type,
-        original_bit_depth: info.bit_depth.clone(),
+        original_bit_depth: info.bit_depth,
         output_profile,
         embedded_profile,
-        jxl_animation: info.animation.clone(),
+        jxl_animation: info.animation,
+        timings: JxlDecodeTimings::default(),
+        endianness,
+        layout,
     };
 
-    let extra_channels = info.extra_channels.len() - if interleave_alpha { 1 } else { 0 };
-    let pixel_format = decoder_with_image_info.current_pixel_format().clone();
-    let color_type = pixel_format.color_type;
-    let samples_per_pixel = pixel_format.color_type.samples_per_pixel();
+    let color_type = decoder_with_image_info.current_pixel_format().color_type;
+    // Simplifications of the speed profile that were reported, to report each combination once.
+    let mut reported_simplifications = vec![];
+    // Downsampling factors used instead of the requested one, to report each of them once.
+    let mut reported_downsampling = vec![];
+    time.headers = start.elapsed();
 
     'frame: loop {
-        let image_size = info.size;
-        let byte_size = (
-            image_size.0 * output_type.bits_per_sample() / 8,
-            image_size.1,
-        );
-
-        let mut outputs = vec![OwnedRawImage::new((
-            byte_size.0 * samples_per_pixel,
-            byte_size.1,
-        ))?];
-
-        for _ in 0..extra_channels {
-            outputs.push(OwnedRawImage::new(byte_size)?);
-        }
+        let frame_start = Instant::now();
+        // Buffers are only taken once the frame is known to be decoded, or to render it before
+        // its header is complete.
+        let mut outputs = None;
 
         let mut partial_renders = vec![];
 
@@ -246,7 +975,14 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
                     break 'partial result;
                 }
                 ProcessingResult::NeedsMoreInput { mut fallback, .. } => {
-                    let mut output_bufs: Vec<JxlOutputBuffer<'_>> = outputs
+                    if outputs.is_none() {
+                        outputs = Some(pool.take(
+                            &byte_sizes(&fallback.output_buffer_requirements()),
+                            clear_buffers,
+                        )?);
+                    }
+                    let buffers = outputs.as_mut().unwrap();
+                    let mut output_bufs: Vec<JxlOutputBuffer<'_>> = buffers
                         .iter_mut()
                         .map(|x| {
                             let rect = Rect {
@@ -262,7 +998,7 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
                     if render_interval.is_some() && input.available_bytes()? > 0 {
                         fallback.flush_pixels(&mut output_bufs)?;
                         partial_renders.push(
-                            outputs
+                            buffers
                                 .iter()
                                 .map(|x| x.try_clone())
                                 .collect::<Result<_, _>>()?,
@@ -270,13 +1006,31 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
                         decoder_with_image_info = fallback;
                         continue 'partial;
                     } else if allow_partial_files {
+                        if frame_action("") != FrameAction::Decode {
+                            break 'frame;
+                        }
                         fallback.flush_pixels(&mut output_bufs)?;
-                        image_data.frames.push(ImageFrame {
-                            partial_renders,
-                            duration: 0.0,
-                            channels: outputs,
-                            color_type,
-                        });
+                        time.frames.push(frame_start.elapsed());
+                        on_frame(
+                            &image_data,
+                            FrameLease::new(
+                                ImageFrame {
+                                    partial_renders,
+                                    timing: None,
+                                    channels: outputs.unwrap(),
+                                    color_type,
+                                    name: String::new(),
+                                    passes: PassesInfo::default(),
+                                    completed_passes: 0,
+                                    groups: None,
+                                    diff: None,
+                                    modular_stats: None,
+                                    modular_checks: None,
+                                    entropy_codes: None,
+                                },
+                                pool,
+                            ),
+                        )?;
                         break 'frame;
                     }
                     return Err(eyre!("Source file truncated"));
@@ -285,6 +1039,78 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
         };
 
         let frame_header = decoder_with_frame_info.frame_header();
+        let action = frame_action(&frame_header.name);
+        if action != FrameAction::Decode
+            && let Some(outputs) = outputs.take()
+        {
+            pool.give_back(outputs);
+        }
+        match action {
+            FrameAction::Decode => {}
+            FrameAction::Skip => {
+                decoder_with_image_info = loop {
+                    match input.with_capped_size(render_interval, |inp| {
+                        decoder_with_frame_info.skip_frame(inp)
+                    })? {
+                        ProcessingResult::Complete { result } => break result,
+                        ProcessingResult::NeedsMoreInput { fallback, .. } => {
+                            if render_interval.is_some() && input.available_bytes()? > 0 {
+                                decoder_with_frame_info = fallback;
+                            } else if allow_partial_files {
+                                break 'frame;
+                            } else {
+                                return Err(eyre!("Source file truncated"));
+                            }
+                        }
+                    }
+                };
+                time.frames.push(frame_start.elapsed());
+                if !decoder_with_image_info.has_more_frames() {
+                    break;
+                }
+                continue;
+            }
+            FrameAction::Stop => break,
+        }
+        let simplifications = frame_header.simplifications;
+        if !simplifications.is_empty() && !reported_simplifications.contains(&simplifications) {
+            Reporter::get().warn(format_args!(
+                "rendering with simplifications for speed: {simplifications}"
+            ));
+            reported_simplifications.push(simplifications);
+        }
+        if frame_header.downsample != downsample
+            && !reported_downsampling.contains(&frame_header.downsample)
+        {
+            let fallback = match frame_header.downsample {
+                1 => "at full resolution".to_string(),
+                factor => format!("downsampled by {factor}"),
+            };
+            Reporter::get().warn(format_args!(
+                "frames cannot be decoded downsampled by {downsample}, decoding them {fallback} \
+                 instead"
+            ));
+            reported_downsampling.push(frame_header.downsample);
+        }
+
+        // The frame might not cover the whole image (i.e. preview frames).
+        let requirements = decoder_with_frame_info.output_buffer_requirements();
+        let mut outputs = match outputs {
+            Some(outputs)
+                if outputs
+                    .iter()
+                    .zip(requirements.iter())
+                    .all(|(o, r)| o.byte_size() == r.byte_size()) =>
+            {
+                outputs
+            }
+            outputs => {
+                if let Some(outputs) = outputs {
+                    pool.give_back(outputs);
+                }
+                pool.take(&byte_sizes(&requirements), clear_buffers)?
+            }
+        };
 
         decoder_with_image_info = 'partial: loop {
             let mut output_bufs: Vec<JxlOutputBuffer<'_>> = outputs
@@ -319,12 +1145,27 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
                         continue 'partial;
                     } else if allow_partial_files {
                         fallback.flush_pixels(&mut output_bufs)?;
-                        image_data.frames.push(ImageFrame {
-                            partial_renders,
-                            duration: frame_header.duration.unwrap_or(0.0),
-                            channels: outputs,
-                            color_type,
-                        });
+                        time.frames.push(frame_start.elapsed());
+                        on_frame(
+                            &image_data,
+                            FrameLease::new(
+                                ImageFrame {
+                                    partial_renders,
+                                    timing: frame_header.timing,
+                                    channels: outputs,
+                                    color_type,
+                                    name: frame_header.name,
+                                    completed_passes: fallback.num_completed_passes(),
+                                    groups: Some(frame_header.groups),
+                                    passes: frame_header.passes,
+                                    diff: None,
+                                    modular_stats: None,
+                                    modular_checks: None,
+                                    entropy_codes: None,
+                                },
+                                pool,
+                            ),
+                        )?;
                         break 'frame;
                     }
                     return Err(eyre!("Source file truncated"));
@@ -332,17 +1173,488 @@ pub fn decode_frames<In: JxlBitstreamInputExt>(
             };
         };
 
-        image_data.frames.push(ImageFrame {
-            partial_renders,
-            duration: frame_header.duration.unwrap_or(0.0),
-            channels: outputs,
-            color_type,
-        });
+        if let Some(concealed) = decoder_with_image_info.concealed_groups()
+            && !concealed.is_empty()
+        {
+            Reporter::get().warn(format_args!(
+                "{}",
+                concealment_summary(concealed, &frame_header.groups)
+            ));
+        }
+        #[cfg(feature = "timing-stats")]
+        let modular_stats = decoder_with_image_info.modular_stats().cloned();
+        #[cfg(not(feature = "timing-stats"))]
+        let modular_stats = None;
+        #[cfg(feature = "verify")]
+        let modular_checks = decoder_with_image_info.modular_checks().map(<[_]>::to_vec);
+        #[cfg(not(feature = "verify"))]
+        let modular_checks = None;
+        #[cfg(feature = "debug-tools")]
+        let entropy_codes = decoder_with_image_info.entropy_codes().map(<[_]>::to_vec);
+        #[cfg(not(feature = "debug-tools"))]
+        let entropy_codes = None;
+        time.frames.push(frame_start.elapsed());
+        on_frame(
+            &image_data,
+            FrameLease::new(
+                ImageFrame {
+                    partial_renders,
+                    timing: frame_header.timing,
+                    channels: outputs,
+                    color_type,
+                    name: frame_header.name,
+                    completed_passes: frame_header.passes.num_passes(),
+                    groups: Some(frame_header.groups),
+                    passes: frame_header.passes,
+                    diff: decoder_with_image_info.frame_diff(),
+                    modular_stats,
+                    modular_checks,
+                    entropy_codes,
+                },
+                pool,
+            ),
+        )?;
+        #[cfg(feature = "timing-stats")]
+        {
+            timings = decoder_with_image_info.decode_timings().clone();
+        }
 
         if !decoder_with_image_info.has_more_frames() {
             break;
         }
     }
 
-    Ok((image_data, start.elapsed()))
+    #[cfg(feature = "timing-stats")]
+    let image_data = DecodeOutput {
+        timings,
+        ..image_data
+    };
+    time.convert = convert;
+    Ok((image_data, time))
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::{DecodeOutput, ImageFrame, OutputDataType};
+    use jxl::{
+        api::{
+            Endianness, JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlColorType, OutputLayout,
+            PassesInfo,
+        },
+        image::OwnedRawImage,
+    };
+
+    /// Creates a single-frame image with a deterministic pattern covering the full sample range.
+    pub fn make_test_image(
+        color_type: JxlColorType,
+        data_type: OutputDataType,
+        size: (usize, usize),
+    ) -> DecodeOutput {
+        let bytes_per_sample = data_type.bits_per_sample() / 8;
+        let samples_per_row = size.0 * color_type.samples_per_pixel();
+        let mut image = OwnedRawImage::new((samples_per_row * bytes_per_sample, size.1)).unwrap();
+        for y in 0..size.1 {
+            let row = image.row_mut(y);
+            for x in 0..samples_per_row {
+                let v = (x * 7919 + y * 104729) as u32;
+                match data_type {
+                    OutputDataType::U8 => row[x] = v as u8,
+                    OutputDataType::U16 => {
+                        row[x * 2..][..2].copy_from_slice(&((v * 257) as u16).to_ne_bytes())
+                    }
+                    _ => unimplemented!("only integer test images are supported"),
+                }
+            }
+        }
+        let profile = JxlColorProfile::Simple(JxlColorEncoding::srgb(color_type.is_grayscale()));
+        DecodeOutput {
+            size,
+            frames: vec![ImageFrame {
+                partial_renders: vec![],
+                channels: vec![image],
+                timing: None,
+                color_type,
+                name: String::new(),
+                passes: PassesInfo::default(),
+                completed_passes: 1,
+                groups: None,
+                diff: None,
+                modular_stats: None,
+                modular_checks: None,
+                entropy_codes: None,
+            }],
+            data_type,
+            original_bit_depth: JxlBitDepth::Int {
+                bits_per_sample: data_type.bits_per_sample() as u32,
+            },
+            output_profile: profile.clone(),
+            embedded_profile: profile,
+            jxl_animation: None,
+            timings: Default::default(),
+            endianness: Endianness::native(),
+            layout: OutputLayout::RowMajor,
+        }
+    }
+
+    /// Reads sample `i` of row `y`, for `bytes`-byte native endian integer samples.
+    pub fn sample(image: &OwnedRawImage, y: usize, i: usize, bytes: usize) -> u32 {
+        let row = image.row(y);
+        if bytes == 1 {
+            row[i] as u32
+        } else {
+            u16::from_ne_bytes([row[i * 2], row[i * 2 + 1]]) as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn garbage(len: usize) -> Vec<u8> {
+        // Never produces bytes that are part of signatures.
+        (0..len).map(|i| (i * 7 % 200 + 20) as u8).collect()
+    }
+
+    fn decode(input: &mut impl Read) -> Result<DecodeOutput> {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        let (output, _) = decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            None,
+            &[OutputDataType::U8],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )?;
+        Ok(output)
+    }
+
+    #[test]
+    fn long_frame_names_are_shortened_in_lists() {
+        use super::test_utils::make_test_image;
+        let mut frame = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (1, 1))
+            .frames
+            .pop()
+            .unwrap();
+        frame.name = "a".repeat(ImageFrame::MAX_LISTED_NAME_LEN);
+        assert_eq!(frame.listed_name(), format!("{:?}", frame.name));
+        // Names are only cut at character boundaries.
+        frame.name = format!("{}é{}", "a".repeat(99), "b".repeat(1000));
+        assert_eq!(
+            frame.listed_name(),
+            format!("\"{}\"... (1101 bytes)", "a".repeat(99))
+        );
+    }
+
+    #[test]
+    fn default_output_types() {
+        use OutputDataType::*;
+        let int = |bits_per_sample| JxlBitDepth::Int { bits_per_sample };
+        let half = JxlBitDepth::Float {
+            bits_per_sample: 16,
+            exponent_bits_per_sample: 5,
+        };
+        let single = JxlBitDepth::Float {
+            bits_per_sample: 32,
+            exponent_bits_per_sample: 8,
+        };
+        let png = &[U8, U16];
+        let exr = &[F16, F32];
+        let tiff = &[U8, U16, F32];
+        assert_eq!(default_output_type(&int(1), None, png), U8);
+        assert_eq!(default_output_type(&int(10), None, png), U16);
+        assert_eq!(default_output_type(&int(10), None, exr), F16);
+        assert_eq!(default_output_type(&int(20), None, exr), F32);
+        assert_eq!(default_output_type(&half, None, exr), F16);
+        assert_eq!(default_output_type(&half, None, png), U16);
+        assert_eq!(default_output_type(&single, None, exr), F32);
+        assert_eq!(default_output_type(&single, None, &[F32]), F32);
+        assert_eq!(default_output_type(&single, Some(8), png), U8);
+        assert_eq!(default_output_type(&half, Some(8), exr), F16);
+        assert_eq!(default_output_type(&int(8), Some(16), png), U16);
+        assert_eq!(default_output_type(&half, None, tiff), F32);
+        assert_eq!(default_output_type(&int(12), None, tiff), U16);
+        // Half floats cannot hold the 11-bit mantissas of 16-bit floats with 4 exponent bits.
+        let wide_mantissa = JxlBitDepth::Float {
+            bits_per_sample: 16,
+            exponent_bits_per_sample: 4,
+        };
+        assert_eq!(default_output_type(&wide_mantissa, None, exr), F32);
+    }
+
+    #[test]
+    fn decode_stream_at_offset() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let stream = std::fs::read(root.join("3x3_srgb_lossless.jxl")).unwrap();
+        let decoy = std::fs::read(root.join("basic.jxl")).unwrap();
+        let mut data = garbage(200);
+        // The start of another stream, which has valid headers but cannot be decoded.
+        data.extend(&decoy[..20]);
+        data.extend(garbage(1337 - data.len()));
+        data.extend(&stream);
+        data.extend(garbage(100));
+
+        let mut attempts = vec![];
+        let (offset, output) = decode_embedded(&mut Cursor::new(&data), |input| {
+            attempts.push(input.stream_position()?);
+            decode(input)
+        })
+        .unwrap();
+        assert_eq!(offset, 1337);
+        assert_eq!(attempts, [200, 1337]);
+        let expected = decode(&mut stream.as_slice()).unwrap();
+        assert_eq!(output.size, expected.size);
+        assert_eq!(
+            output.frames[0].channels[0].row(1),
+            expected.frames[0].channels[0].row(1)
+        );
+
+        let Err(err) = decode_embedded(&mut Cursor::new(&data[..1337]), decode) else {
+            panic!("truncated stream decoded");
+        };
+        assert!(err.to_string().contains("offset 200"), "{err}");
+        let Err(err) = decode_embedded(&mut Cursor::new(garbage(100)), decode) else {
+            panic!("garbage decoded");
+        };
+        assert_eq!(err.to_string(), "No JPEG XL stream found");
+    }
+
+    #[test]
+    fn token_limit_scales_with_image_size() {
+        let testdata =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/tests/testdata");
+        let data = std::fs::read(testdata.join("token_bomb.jxl")).unwrap();
+        let decode = |max_tokens_per_pixel| {
+            decode_frames(
+                &mut data.as_slice(),
+                JxlDecoderOptions::default(),
+                None,
+                None,
+                &[OutputDataType::U8],
+                true,
+                false,
+                None,
+                false,
+                max_tokens_per_pixel,
+                None,
+                Endianness::native(),
+            )
+        };
+        // The image is 8x8, so 1024 tokens per pixel do not even cover one channel of the first
+        // frame.
+        let Err(err) = decode(Some(1024)) else {
+            panic!("decompression bomb decoded");
+        };
+        assert!(format!("{err:?}").contains("limit of 65536"), "{err:?}");
+    }
+
+    #[test]
+    fn output_size_for_image() {
+        assert_eq!(OutputSize::Exact(3, 5).for_image((100, 10)), (3, 5));
+        assert_eq!(OutputSize::LongEdge(50).for_image((100, 10)), (50, 5));
+        assert_eq!(OutputSize::LongEdge(50).for_image((11, 100)), (6, 50));
+        assert_eq!(OutputSize::LongEdge(5).for_image((1000, 1)), (5, 1));
+        assert_eq!(OutputSize::Downsample(8).for_image((100, 10)), (13, 2));
+        assert_eq!("12x34".parse(), Ok(OutputSize::Exact(12, 34)));
+        assert!("12x0".parse::<OutputSize>().is_err());
+        assert!("12".parse::<OutputSize>().is_err());
+    }
+
+    #[test]
+    fn resized_alpha_edges_have_no_fringes() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        // Hard-edged triangles over a transparent background.
+        let data = std::fs::read(root.join("conformance_test_images/alpha_triangles.jxl")).unwrap();
+        for interleave_alpha in [true, false] {
+            let (output, _) = decode_frames(
+                &mut data.as_slice(),
+                JxlDecoderOptions::default(),
+                None,
+                None,
+                &[OutputDataType::U16],
+                interleave_alpha,
+                false,
+                None,
+                false,
+                None,
+                Some(OutputSize::LongEdge(256)),
+                Endianness::native(),
+            )
+            .unwrap();
+            assert_eq!(output.size, (256, 256));
+            let channels = &output.frames[0].channels;
+            let pixel = |x: usize, y: usize| {
+                let sample = |image, i| test_utils::sample(image, y, i, 2) as f32 / 65535.0;
+                if interleave_alpha {
+                    [0, 1, 2, 3].map(|c| sample(&channels[0], x * 4 + c))
+                } else {
+                    let [r, g, b] = [0, 1, 2].map(|c| sample(&channels[0], x * 3 + c));
+                    [r, g, b, sample(&channels[1], x)]
+                }
+            };
+            let mut edge_pixels = 0;
+            for y in 0..256 {
+                for x in 0..256 {
+                    let p = pixel(x, y);
+                    if !(0.1..0.9).contains(&p[3]) {
+                        continue;
+                    }
+                    // The colors of partially transparent pixels must come from the opaque side
+                    // of the edge, not from the invisible colors behind it.
+                    let opaque: Vec<_> = (y.saturating_sub(2)..(y + 3).min(256))
+                        .flat_map(|ny| {
+                            (x.saturating_sub(2)..(x + 3).min(256)).map(move |nx| (nx, ny))
+                        })
+                        .map(|(nx, ny)| pixel(nx, ny))
+                        .filter(|n| n[3] > 0.99)
+                        .collect();
+                    if opaque.is_empty() {
+                        continue;
+                    }
+                    edge_pixels += 1;
+                    for c in 0..3 {
+                        let distance = opaque
+                            .iter()
+                            .map(|n| (n[c] - p[c]).abs())
+                            .fold(f32::INFINITY, f32::min);
+                        assert!(distance < 0.1, "pixel ({x}, {y}): {p:?}");
+                    }
+                }
+            }
+            assert!(edge_pixels > 1000, "{edge_pixels}");
+        }
+    }
+
+    #[test]
+    fn leased_animation_frames_reuse_buffers() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let path = root.join("conformance_test_images/animation_spline.jxl");
+        if !path.exists() {
+            return;
+        }
+        let data = std::fs::read(path).unwrap();
+        let pool = BufferPool::new(DEFAULT_POOL_DEPTH);
+        let mut num_frames = 0;
+        let mut num_buffers = 0;
+        decode_frames_leased(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            None,
+            &[OutputDataType::U8],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+            &pool,
+            |_| FrameAction::Decode,
+            |_, frame| {
+                num_frames += 1;
+                num_buffers = frame.channels.len();
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(num_frames > 2);
+        // Every frame is dropped before the next one is decoded.
+        assert_eq!(pool.allocations(), num_buffers);
+    }
+
+    #[test]
+    fn select_frames_by_name() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join("named_frame_test.jxl")).unwrap();
+        let output = decode(&mut data.as_slice()).unwrap();
+        assert_eq!(output.frames[0].name, "TestFrameName");
+
+        let frames = ["first", "thumbnail", "last"]
+            .map(|name| {
+                let mut frame =
+                    test_utils::make_test_image(JxlColorType::Rgb, OutputDataType::U8, (2, 2))
+                        .frames
+                        .pop()
+                        .unwrap();
+                frame.name = name.to_string();
+                frame
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        let selectors = ["name=thumbnail", "0"].map(|s| s.parse::<FrameSelector>().unwrap());
+        let selected = select_frames(frames, &selectors).unwrap();
+        let names: Vec<_> = selected.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["first", "thumbnail"]);
+
+        let selected = select_frames(output.frames, &["name=missing".parse().unwrap()]);
+        assert!(selected.is_err());
+        assert!("thumbnail".parse::<FrameSelector>().is_err());
+    }
+
+    #[test]
+    fn parse_frame_ranges() {
+        let range = |start, end| FrameSelector::Range { start, end };
+        for (s, expected) in [
+            ("5..20", range(5, Some(20))),
+            ("5..=20", range(5, Some(21))),
+            ("5..", range(5, None)),
+            ("..3", range(0, Some(3))),
+            ("7", FrameSelector::Index(7)),
+        ] {
+            assert_eq!(s.parse::<FrameSelector>(), Ok(expected));
+        }
+        for s in ["5..5", "5..=4", "a..3", "1...3", "..=", "-1..3"] {
+            assert!(s.parse::<FrameSelector>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn frame_selection_stops_after_last_selected_frame() {
+        let selectors = ["2..4", "1"].map(|s| s.parse::<FrameSelector>().unwrap());
+        let mut selection = FrameSelection::new(&selectors);
+        let actions: Vec<_> = (0..6).map(|_| selection.next_action("")).collect();
+        use FrameAction::*;
+        assert_eq!(actions, [Skip, Decode, Decode, Decode, Stop, Stop]);
+        assert!(selection.finish().is_ok());
+
+        // Open ranges and names can select any later frame.
+        for s in ["3..", "name=x"] {
+            let selectors = [s.parse::<FrameSelector>().unwrap()];
+            let mut selection = FrameSelection::new(&selectors);
+            let actions: Vec<_> = (0..5).map(|_| selection.next_action("x")).collect();
+            assert!(!actions.contains(&Stop), "{s}");
+        }
+    }
+
+    #[test]
+    fn frames_out_of_range() {
+        for (s, error) in [
+            ("5", "Frame 5 is out of range, the image has 3 frame(s)"),
+            (
+                "2..5",
+                "The frames 2..5 are out of range, the image has 3 frame(s)",
+            ),
+            (
+                "3..",
+                "The frames 3.. are out of range, the image has 3 frame(s)",
+            ),
+            ("name=x", "No frame matches frame named \"x\""),
+        ] {
+            let selectors = [s.parse::<FrameSelector>().unwrap()];
+            let mut selection = FrameSelection::new(&selectors);
+            for _ in 0..3 {
+                selection.next_action("");
+            }
+            assert_eq!(selection.finish().unwrap_err().to_string(), error);
+        }
+    }
 }
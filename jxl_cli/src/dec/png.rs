@@ -0,0 +1,203 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::{Cursor, Read};
+
+use color_eyre::eyre::{Result, bail, ensure};
+use jxl::{
+    api::{
+        Endianness, FrameTiming, JxlAnimation, JxlBitDepth, JxlColorEncoding, JxlColorProfile,
+        JxlColorType, OutputLayout, PassesInfo,
+    },
+    image::OwnedRawImage,
+};
+
+use crate::dec::{DecodeOutput, ImageFrame, OutputDataType};
+
+fn jxl_color_type(color_type: png::ColorType) -> Result<JxlColorType> {
+    Ok(match color_type {
+        png::ColorType::Grayscale => JxlColorType::Grayscale,
+        png::ColorType::GrayscaleAlpha => JxlColorType::GrayscaleAlpha,
+        png::ColorType::Rgb => JxlColorType::Rgb,
+        png::ColorType::Rgba => JxlColorType::Rgba,
+        png::ColorType::Indexed => bail!("Unexpected indexed PNG output"),
+    })
+}
+
+/// Reads a (possibly animated) PNG file into a [`DecodeOutput`].
+///
+/// Palette and low bit depth images are expanded to 8 bits; 16-bit images are returned as
+/// [`OutputDataType::U16`] in native endianness. Grayscale, RGB and their variants with alpha are
+/// stored interleaved in the first channel of each frame.
+pub fn from_png<R: Read>(reader: &mut R) -> Result<DecodeOutput> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+
+    let (width, height) = reader.info().size();
+    let (width, height) = (width as usize, height as usize);
+    let (png_color_type, bit_depth) = reader.output_color_type();
+    let color_type = jxl_color_type(png_color_type)?;
+    let data_type = match bit_depth {
+        png::BitDepth::Eight => OutputDataType::U8,
+        png::BitDepth::Sixteen => OutputDataType::U16,
+        _ => bail!("Unexpected PNG output bit depth {bit_depth:?}"),
+    };
+    let original_bits = reader.info().bit_depth as u32;
+    let embedded_profile = match &reader.info().icc_profile {
+        Some(icc) => JxlColorProfile::Icc(icc.to_vec()),
+        None => JxlColorProfile::Simple(JxlColorEncoding::srgb(color_type.is_grayscale())),
+    };
+    let jxl_animation = reader
+        .info()
+        .animation_control
+        .map(|actl| JxlAnimation::new(1000, 1, actl.num_plays));
+    let num_frames = reader
+        .info()
+        .animation_control
+        .map_or(1, |actl| actl.num_frames as usize);
+
+    let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let mut frames = vec![];
+    for _ in 0..num_frames {
+        let output_info = reader.next_frame(&mut buf)?;
+        ensure!(
+            (output_info.width as usize, output_info.height as usize) == (width, height),
+            "APNG frames that do not cover the whole image are not supported"
+        );
+        // The delay is a number of seconds, so it maps to ticks at a rate of `den` per second.
+        let timing = match reader.info().frame_control {
+            Some(fctl) if jxl_animation.is_some() => Some(FrameTiming {
+                ticks: fctl.delay_num as u32,
+                tps_num: if fctl.delay_den == 0 {
+                    100
+                } else {
+                    fctl.delay_den as u32
+                },
+                tps_den: 1,
+            }),
+            _ => None,
+        };
+        let mut image = OwnedRawImage::new((output_info.line_size, height))?;
+        for y in 0..height {
+            let src = &buf[y * output_info.line_size..][..output_info.line_size];
+            let dst = image.row_mut(y);
+            if data_type == OutputDataType::U8 {
+                dst.copy_from_slice(src);
+            } else {
+                for (d, s) in dst.chunks_exact_mut(2).zip(src.chunks_exact(2)) {
+                    d.copy_from_slice(&u16::from_be_bytes([s[0], s[1]]).to_ne_bytes());
+                }
+            }
+        }
+        frames.push(ImageFrame {
+            partial_renders: vec![],
+            channels: vec![image],
+            timing,
+            color_type,
+            name: String::new(),
+            passes: PassesInfo::default(),
+            completed_passes: 1,
+            groups: None,
+            diff: None,
+            modular_stats: None,
+            modular_checks: None,
+            entropy_codes: None,
+        });
+    }
+
+    Ok(DecodeOutput {
+        size: (width, height),
+        frames,
+        data_type,
+        original_bit_depth: JxlBitDepth::Int {
+            bits_per_sample: original_bits,
+        },
+        output_profile: embedded_profile.clone(),
+        embedded_profile,
+        jxl_animation,
+        timings: Default::default(),
+        endianness: Endianness::native(),
+        layout: OutputLayout::RowMajor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::make_test_image;
+    use crate::enc::png::to_png;
+
+    #[test]
+    fn roundtrip() {
+        for color_type in [
+            JxlColorType::Grayscale,
+            JxlColorType::GrayscaleAlpha,
+            JxlColorType::Rgb,
+            JxlColorType::Rgba,
+        ] {
+            for data_type in [OutputDataType::U8, OutputDataType::U16] {
+                let image = make_test_image(color_type, data_type, (17, 5));
+                let mut encoded = vec![];
+                to_png(&image, &mut encoded, None).unwrap();
+                let decoded = from_png(&mut encoded.as_slice()).unwrap();
+                assert_eq!(decoded.size, image.size);
+                assert_eq!(decoded.data_type, data_type);
+                assert_eq!(decoded.frames.len(), 1);
+                assert_eq!(decoded.frames[0].color_type, color_type);
+                for y in 0..image.size.1 {
+                    assert_eq!(
+                        decoded.frames[0].channels[0].row(y),
+                        image.frames[0].channels[0].row(y),
+                        "{color_type:?} {data_type:?} row {y}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip_animation() {
+        let mut image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (4, 3));
+        let mut second = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (4, 3));
+        second.frames[0].channels[0].row_mut(1)[2] ^= 0xff;
+        second.frames[0].timing = Some(FrameTiming {
+            ticks: 250,
+            tps_num: 1000,
+            tps_den: 1,
+        });
+        image.frames[0].timing = Some(FrameTiming {
+            ticks: 1001,
+            tps_num: 30000,
+            tps_den: 1,
+        });
+        image.frames.push(second.frames.pop().unwrap());
+        image.jxl_animation = Some(JxlAnimation::new(1000, 1, 3));
+        let mut encoded = vec![];
+        to_png(&image, &mut encoded, None).unwrap();
+        let decoded = from_png(&mut encoded.as_slice()).unwrap();
+        assert_eq!(decoded.frames.len(), 2);
+        assert_eq!(decoded.jxl_animation.as_ref().unwrap().num_loops, 3);
+        for (d, o) in decoded.frames.iter().zip(image.frames.iter()) {
+            let duration = |frame: &ImageFrame| frame.timing.unwrap().as_duration();
+            assert_eq!(duration(d), duration(o));
+            for y in 0..image.size.1 {
+                assert_eq!(d.channels[0].row(y), o.channels[0].row(y));
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_inputs() {
+        let image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (8, 8));
+        let mut encoded = vec![];
+        to_png(&image, &mut encoded, None).unwrap();
+        assert!(from_png(&mut &encoded[..encoded.len() / 2]).is_err());
+        assert!(from_png(&mut &b"\x89PNX\r\n\x1a\n"[..]).is_err());
+        assert!(from_png(&mut &b""[..]).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::Read;
+
+use color_eyre::eyre::{Result, bail, ensure, eyre};
+use jxl::{
+    api::{
+        Endianness, JxlBitDepth, JxlColorEncoding, JxlColorProfile, JxlColorType, OutputLayout,
+        PassesInfo,
+    },
+    image::OwnedRawImage,
+};
+
+use crate::dec::{DecodeOutput, ImageFrame, OutputDataType};
+
+struct PnmHeader {
+    color_type: JxlColorType,
+    plain: bool,
+    width: usize,
+    height: usize,
+    maxval: u32,
+}
+
+/// Minimal tokenizer for the whitespace/comment-separated fields of PNM files.
+struct Tokenizer<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&c) = self.data.get(self.pos) {
+            if c == b'#' {
+                while self.pos < self.data.len() && self.data[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else if c.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn next_u32(&mut self, what: &str) -> Result<u32> {
+        self.skip_whitespace_and_comments();
+        let start = self.pos;
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            if self.pos == self.data.len() {
+                bail!("Truncated PNM file while reading {what}");
+            }
+            bail!("Invalid character while reading PNM {what}");
+        }
+        std::str::from_utf8(&self.data[start..self.pos])?
+            .parse()
+            .map_err(|_| eyre!("PNM {what} is too large"))
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+fn parse_header(tokenizer: &mut Tokenizer) -> Result<PnmHeader> {
+    let (color_type, plain) = match tokenizer.data.get(..2) {
+        Some(b"P2") => (JxlColorType::Grayscale, true),
+        Some(b"P3") => (JxlColorType::Rgb, true),
+        Some(b"P5") => (JxlColorType::Grayscale, false),
+        Some(b"P6") => (JxlColorType::Rgb, false),
+        _ => bail!("Invalid PNM magic, expected one of P2, P3, P5 or P6"),
+    };
+    tokenizer.pos = 2;
+    let width = tokenizer.next_u32("width")? as usize;
+    let height = tokenizer.next_u32("height")? as usize;
+    let maxval = tokenizer.next_u32("maxval")?;
+    ensure!(width > 0 && height > 0, "Invalid PNM size {width}x{height}");
+    ensure!(
+        (1..=65535).contains(&maxval),
+        "Invalid PNM maxval {maxval}, should be in 1..=65535"
+    );
+    if !plain {
+        // Exactly one whitespace character separates the header from the raster.
+        match tokenizer.remaining().first() {
+            Some(c) if c.is_ascii_whitespace() => tokenizer.pos += 1,
+            Some(_) => bail!("Missing whitespace after PNM header"),
+            None => bail!("Truncated PNM file: missing raster data"),
+        }
+    }
+    Ok(PnmHeader {
+        color_type,
+        plain,
+        width,
+        height,
+        maxval,
+    })
+}
+
+/// Reads a PGM or PPM file (both raw and plain variants, with any maxval) into a
+/// [`DecodeOutput`]. Samples are rescaled to the full range of the output data type, which is
+/// [`OutputDataType::U8`] for maxval <= 255 and [`OutputDataType::U16`] otherwise.
+pub fn from_pnm<R: Read>(reader: &mut R) -> Result<DecodeOutput> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut tokenizer = Tokenizer {
+        data: &data,
+        pos: 0,
+    };
+    let header = parse_header(&mut tokenizer)?;
+    let samples_per_row = header.width * header.color_type.samples_per_pixel();
+    let data_type = if header.maxval <= 255 {
+        OutputDataType::U8
+    } else {
+        OutputDataType::U16
+    };
+    let bytes_per_sample = data_type.bits_per_sample() / 8;
+    let out_max = (1u64 << data_type.bits_per_sample()) - 1;
+    let maxval = header.maxval as u64;
+    let mut image = OwnedRawImage::new((samples_per_row * bytes_per_sample, header.height))?;
+
+    let raw_bytes_per_sample = if header.maxval <= 255 { 1 } else { 2 };
+    let raw = tokenizer.remaining();
+    if !header.plain {
+        let expected = samples_per_row * header.height * raw_bytes_per_sample;
+        ensure!(
+            raw.len() >= expected,
+            "Truncated PNM raster: expected {expected} bytes, found {}",
+            raw.len()
+        );
+    }
+
+    for y in 0..header.height {
+        let row = image.row_mut(y);
+        for x in 0..samples_per_row {
+            let sample = if header.plain {
+                tokenizer.next_u32("sample")?
+            } else {
+                let pos = (y * samples_per_row + x) * raw_bytes_per_sample;
+                if raw_bytes_per_sample == 1 {
+                    raw[pos] as u32
+                } else {
+                    u16::from_be_bytes([raw[pos], raw[pos + 1]]) as u32
+                }
+            };
+            ensure!(
+                sample <= header.maxval,
+                "PNM sample {sample} exceeds maxval {}",
+                header.maxval
+            );
+            let scaled = (sample as u64 * out_max + maxval / 2) / maxval;
+            if data_type == OutputDataType::U8 {
+                row[x] = scaled as u8;
+            } else {
+                row[x * 2..][..2].copy_from_slice(&(scaled as u16).to_ne_bytes());
+            }
+        }
+    }
+
+    let profile = JxlColorProfile::Simple(JxlColorEncoding::srgb(
+        header.color_type == JxlColorType::Grayscale,
+    ));
+    Ok(DecodeOutput {
+        size: (header.width, header.height),
+        frames: vec![ImageFrame {
+            partial_renders: vec![],
+            channels: vec![image],
+            timing: None,
+            color_type: header.color_type,
+            name: String::new(),
+            passes: PassesInfo::default(),
+            completed_passes: 1,
+            groups: None,
+            diff: None,
+            modular_stats: None,
+            modular_checks: None,
+            entropy_codes: None,
+        }],
+        data_type,
+        original_bit_depth: JxlBitDepth::Int {
+            bits_per_sample: 32 - header.maxval.leading_zeros(),
+        },
+        output_profile: profile.clone(),
+        embedded_profile: profile,
+        jxl_animation: None,
+        timings: Default::default(),
+        endianness: Endianness::native(),
+        layout: OutputLayout::RowMajor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::{make_test_image, sample};
+    use crate::enc::pnm::{to_pgm, to_ppm};
+
+    #[test]
+    fn roundtrip_8bit() {
+        for color_type in [JxlColorType::Grayscale, JxlColorType::Rgb] {
+            let image = make_test_image(color_type, OutputDataType::U8, (13, 7));
+            let mut encoded = vec![];
+            if color_type == JxlColorType::Grayscale {
+                to_pgm(&image, &mut encoded).unwrap();
+            } else {
+                to_ppm(&image, &mut encoded).unwrap();
+            }
+            let decoded = from_pnm(&mut encoded.as_slice()).unwrap();
+            assert_eq!(decoded.size, image.size);
+            assert_eq!(decoded.data_type, OutputDataType::U8);
+            assert_eq!(decoded.frames[0].color_type, color_type);
+            for y in 0..image.size.1 {
+                assert_eq!(
+                    decoded.frames[0].channels[0].row(y),
+                    image.frames[0].channels[0].row(y)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn raw_16bit() {
+        let mut data = b"P6\n2 1\n65535\n".to_vec();
+        for v in [0u16, 1, 2, 65535, 32768, 1000] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        let decoded = from_pnm(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.data_type, OutputDataType::U16);
+        assert_eq!(
+            decoded.original_bit_depth,
+            JxlBitDepth::Int {
+                bits_per_sample: 16
+            }
+        );
+        let chan = &decoded.frames[0].channels[0];
+        let values: Vec<_> = (0..6).map(|i| sample(chan, 0, i, 2)).collect();
+        assert_eq!(values, [0, 1, 2, 65535, 32768, 1000]);
+    }
+
+    #[test]
+    fn plain_with_comments_and_maxval_scaling() {
+        let data = b"P2\n# a comment\n3 2 # trailing\n15\n0 15 5\n10 1\n 14\n";
+        let decoded = from_pnm(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.size, (3, 2));
+        assert_eq!(decoded.data_type, OutputDataType::U8);
+        let chan = &decoded.frames[0].channels[0];
+        assert_eq!(chan.row(0), [0, 255, 85]);
+        assert_eq!(chan.row(1), [170, 17, 238]);
+
+        let data = b"P3 1 1 1000 1000 0 500";
+        let decoded = from_pnm(&mut data.as_slice()).unwrap();
+        assert_eq!(decoded.data_type, OutputDataType::U16);
+        let chan = &decoded.frames[0].channels[0];
+        assert_eq!(sample(chan, 0, 0, 2), 65535);
+        assert_eq!(sample(chan, 0, 1, 2), 0);
+        assert_eq!(sample(chan, 0, 2, 2), 32768);
+    }
+
+    #[test]
+    fn malformed_inputs() {
+        let inputs: [&[u8]; 7] = [
+            b"P7\n1 1\n255\n\0",
+            b"P5\n1 1\n0\n\0",
+            b"P5\n2 2\n255\n\0\0\0",
+            b"P5\n0 2\n255\n",
+            b"P2\n2 1\n255\n0",
+            b"P2\n1 1\n10\n11",
+            b"P6\n1 1",
+        ];
+        for input in inputs {
+            assert!(
+                from_pnm(&mut &input[..]).is_err(),
+                "{:?} should fail to decode",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+}
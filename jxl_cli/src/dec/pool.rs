@@ -0,0 +1,206 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Reuse of output buffers across the frames of an animation.
+//!
+//! Frames decoded by [`super::decode_frames_leased`] are handed out as [`FrameLease`]s, whose
+//! buffers go back to a [`BufferPool`] when the lease is dropped, so that consumers that are done
+//! with a frame before the next one is decoded do not allocate (and page fault) a new set of
+//! buffers for every frame.
+
+use std::{cell::RefCell, ops::Deref, rc::Rc};
+
+use color_eyre::eyre::Result;
+use jxl::image::OwnedRawImage;
+
+use super::ImageFrame;
+
+/// Default number of frames whose buffers a pool keeps, which lets a consumer hold on to one frame
+/// while the next one is decoded.
+pub const DEFAULT_POOL_DEPTH: usize = 2;
+
+#[derive(Default)]
+struct PoolState {
+    /// Buffers of returned frames, one set per frame.
+    free: Vec<Vec<OwnedRawImage>>,
+    depth: usize,
+    allocations: usize,
+}
+
+/// Output buffers of returned frames, ready to be reused for the next frames.
+///
+/// Clones of a pool share its buffers.
+#[derive(Clone, Default)]
+pub struct BufferPool(Rc<RefCell<PoolState>>);
+
+impl BufferPool {
+    /// Creates a pool that keeps the buffers of up to `depth` returned frames. Pools of depth 0
+    /// allocate new buffers for every frame.
+    pub fn new(depth: usize) -> Self {
+        Self(Rc::new(RefCell::new(PoolState {
+            depth,
+            ..Default::default()
+        })))
+    }
+
+    /// Number of buffers that the pool had to allocate so far.
+    pub fn allocations(&self) -> usize {
+        self.0.borrow().allocations
+    }
+
+    /// Returns buffers of the given sizes in bytes, reusing those of a returned frame if they have
+    /// the same sizes. Reused buffers still hold the pixels of that frame, unless `clear` is set.
+    pub(super) fn take(
+        &self,
+        byte_sizes: &[(usize, usize)],
+        clear: bool,
+    ) -> Result<Vec<OwnedRawImage>> {
+        let mut state = self.0.borrow_mut();
+        let fits = |buffers: &Vec<OwnedRawImage>| {
+            buffers.len() == byte_sizes.len()
+                && buffers
+                    .iter()
+                    .zip(byte_sizes)
+                    .all(|(b, size)| b.byte_size() == *size)
+        };
+        if let Some(index) = state.free.iter().position(fits) {
+            let mut buffers = state.free.swap_remove(index);
+            if clear {
+                for buffer in &mut buffers {
+                    for y in 0..buffer.byte_size().1 {
+                        buffer.row_mut(y).fill(0);
+                    }
+                }
+            }
+            return Ok(buffers);
+        }
+        state.allocations += byte_sizes.len();
+        Ok(byte_sizes
+            .iter()
+            .map(|size| OwnedRawImage::new(*size))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Returns the buffers of a frame to the pool, which drops them if it is full.
+    pub(super) fn give_back(&self, buffers: Vec<OwnedRawImage>) {
+        let mut state = self.0.borrow_mut();
+        if state.free.len() < state.depth {
+            state.free.push(buffers);
+        }
+    }
+}
+
+/// A decoded frame whose buffers return to the [`BufferPool`] they came from when it is dropped.
+pub struct FrameLease {
+    // Only `None` once the frame was moved out by `into_owned` or `drop`.
+    frame: Option<ImageFrame>,
+    pool: BufferPool,
+}
+
+impl FrameLease {
+    pub(super) fn new(frame: ImageFrame, pool: &BufferPool) -> Self {
+        Self {
+            frame: Some(frame),
+            pool: pool.clone(),
+        }
+    }
+
+    /// Takes the frame out of the lease, to keep it after the following frames are decoded. Its
+    /// buffers do not return to the pool, which allocates new ones when it runs out.
+    pub fn into_owned(mut self) -> ImageFrame {
+        self.frame.take().unwrap()
+    }
+}
+
+impl Deref for FrameLease {
+    type Target = ImageFrame;
+
+    fn deref(&self) -> &ImageFrame {
+        self.frame.as_ref().unwrap()
+    }
+}
+
+impl Drop for FrameLease {
+    fn drop(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            self.pool.give_back(frame.channels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jxl::api::JxlColorType;
+
+    use super::*;
+    use crate::dec::{OutputDataType, test_utils::make_test_image};
+
+    /// Byte sizes of the buffers of an 8-bit RGB image with a separate alpha channel.
+    fn rgb_and_alpha(width: usize, height: usize) -> Vec<(usize, usize)> {
+        vec![(width * 3, height), (width, height)]
+    }
+
+    fn lease(pool: &BufferPool, byte_sizes: &[(usize, usize)]) -> FrameLease {
+        let mut frame = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (1, 1))
+            .frames
+            .pop()
+            .unwrap();
+        frame.channels = pool.take(byte_sizes, false).unwrap();
+        FrameLease::new(frame, pool)
+    }
+
+    #[test]
+    fn animation_reuses_buffers() {
+        let pool = BufferPool::new(DEFAULT_POOL_DEPTH);
+        let byte_sizes = rgb_and_alpha(64, 48);
+        // A pipelined consumer holds on to the previous frame while the next one is decoded.
+        let mut previous = None;
+        for frame in 0..20 {
+            let lease = lease(&pool, &byte_sizes);
+            assert_eq!(lease.channels[0].byte_size(), (64 * 3, 48));
+            drop(previous.replace(lease));
+            // Two sets of buffers are needed before the first lease returns.
+            let expected = byte_sizes.len() * (frame + 1).min(2);
+            assert_eq!(pool.allocations(), expected, "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn owned_frames_and_size_changes() {
+        let pool = BufferPool::new(1);
+        let small = rgb_and_alpha(8, 8);
+        let kept = lease(&pool, &small).into_owned();
+        assert_eq!(kept.channels.len(), 2);
+        drop(lease(&pool, &small));
+        assert_eq!(pool.allocations(), 4);
+        drop(lease(&pool, &small));
+        assert_eq!(pool.allocations(), 4);
+
+        // Frames of another size, such as preview frames, do not reuse the buffers.
+        drop(lease(&pool, &rgb_and_alpha(4, 4)));
+        assert_eq!(pool.allocations(), 6);
+
+        let pool = BufferPool::new(0);
+        for _ in 0..3 {
+            drop(lease(&pool, &small));
+        }
+        assert_eq!(pool.allocations(), 6);
+    }
+
+    #[test]
+    fn cleared_buffers() {
+        let pool = BufferPool::new(1);
+        let byte_sizes = [(8, 2)];
+        let mut buffers = pool.take(&byte_sizes, false).unwrap();
+        buffers[0].row_mut(1).fill(7);
+        pool.give_back(buffers);
+        let buffers = pool.take(&byte_sizes, false).unwrap();
+        assert_eq!(buffers[0].row(1), [7; 8]);
+        pool.give_back(buffers);
+        let buffers = pool.take(&byte_sizes, true).unwrap();
+        assert_eq!(buffers[0].row(1), [0; 8]);
+        assert_eq!(pool.allocations(), 1);
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::borrow::Cow;
+
+use jxl::api::OutputLayout;
+
+use super::{DecodeOutput, ImageFrame};
+
+/// Order of the samples in the rows of [`FrameRows`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RowOrder {
+    /// Rows of whole pixels, each with its color samples (including alpha) followed by its extra
+    /// channels.
+    PixelMajor,
+    /// Rows of single channels: all rows of the first color sample, then of the next ones, and
+    /// then of the extra channels.
+    ChannelMajor,
+}
+
+/// Iterator over the rows of a frame as bytes, with the data type and byte order of the decoded
+/// samples. Rows of column-major frames are columns of the image.
+///
+/// Rows that are stored as they are in the buffers of the frame, such as the rows of frames
+/// without extra channels in pixel-major order, are borrowed; the others are assembled.
+pub struct FrameRows<'a> {
+    frame: &'a ImageFrame,
+    order: RowOrder,
+    /// Number of samples per row of each channel, and number of rows.
+    size: (usize, usize),
+    bytes_per_sample: usize,
+    color_samples: usize,
+    /// Index of the next row, counting the rows of all channels in channel-major order.
+    next: usize,
+}
+
+impl<'a> FrameRows<'a> {
+    /// The rows of `frame`, a frame of `image`.
+    pub fn new(image: &DecodeOutput, frame: &'a ImageFrame, order: RowOrder) -> Self {
+        Self {
+            frame,
+            order,
+            size: match image.layout {
+                OutputLayout::RowMajor => image.size,
+                OutputLayout::ColumnMajor => (image.size.1, image.size.0),
+            },
+            bytes_per_sample: image.data_type.bits_per_sample() / 8,
+            color_samples: frame.color_type.samples_per_pixel(),
+            next: 0,
+        }
+    }
+
+    /// Number of samples of each pixel.
+    pub fn num_channels(&self) -> usize {
+        self.color_samples + self.frame.channels.len() - 1
+    }
+
+    fn num_rows(&self) -> usize {
+        match self.order {
+            RowOrder::PixelMajor => self.size.1,
+            RowOrder::ChannelMajor => self.size.1 * self.num_channels(),
+        }
+    }
+
+    fn pixel_row(&self, y: usize) -> Cow<'a, [u8]> {
+        let (width, bytes) = (self.size.0, self.bytes_per_sample);
+        let color = &self.frame.channels[0].row(y)[..width * self.color_samples * bytes];
+        if self.frame.channels.len() == 1 {
+            return Cow::Borrowed(color);
+        }
+        let mut row = Vec::with_capacity(width * self.num_channels() * bytes);
+        for (x, pixel) in color.chunks_exact(self.color_samples * bytes).enumerate() {
+            row.extend_from_slice(pixel);
+            for channel in &self.frame.channels[1..] {
+                row.extend_from_slice(&channel.row(y)[x * bytes..][..bytes]);
+            }
+        }
+        Cow::Owned(row)
+    }
+
+    fn channel_row(&self, c: usize, y: usize) -> Cow<'a, [u8]> {
+        let (width, bytes) = (self.size.0, self.bytes_per_sample);
+        if c >= self.color_samples {
+            let channel = &self.frame.channels[c - self.color_samples + 1];
+            return Cow::Borrowed(&channel.row(y)[..width * bytes]);
+        }
+        let color = &self.frame.channels[0].row(y)[..width * self.color_samples * bytes];
+        if self.color_samples == 1 {
+            return Cow::Borrowed(color);
+        }
+        Cow::Owned(
+            color
+                .chunks_exact(self.color_samples * bytes)
+                .flat_map(|pixel| &pixel[c * bytes..][..bytes])
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl<'a> Iterator for FrameRows<'a> {
+    type Item = Cow<'a, [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.num_rows() {
+            return None;
+        }
+        let row = match self.order {
+            RowOrder::PixelMajor => self.pixel_row(self.next),
+            RowOrder::ChannelMajor => {
+                self.channel_row(self.next / self.size.1, self.next % self.size.1)
+            }
+        };
+        self.next += 1;
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.num_rows() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for FrameRows<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::OutputDataType;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::JxlColorType;
+    use jxl::image::OwnedRawImage;
+
+    /// A 3x2 RGB frame of u8 samples with two extra channels, whose sample `c` of pixel (x, y)
+    /// is `c * 100 + y * 10 + x`.
+    fn test_image() -> DecodeOutput {
+        let size = (3, 2);
+        let mut image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, size);
+        let frame = &mut image.frames[0];
+        frame.channels.clear();
+        for (c0, samples) in [(0, 3), (3, 1), (4, 1)] {
+            let mut channel = OwnedRawImage::new((size.0 * samples, size.1)).unwrap();
+            for y in 0..size.1 {
+                for (i, sample) in channel.row_mut(y).iter_mut().enumerate() {
+                    let (x, c) = (i / samples, c0 + i % samples);
+                    *sample = (c * 100 + y * 10 + x) as u8;
+                }
+            }
+            frame.channels.push(channel);
+        }
+        image
+    }
+
+    #[test]
+    fn pixel_and_channel_major_rows() {
+        let image = test_image();
+        let sample = |c: usize, x: usize, y: usize| (c * 100 + y * 10 + x) as u8;
+        let rows = FrameRows::new(&image, &image.frames[0], RowOrder::PixelMajor);
+        assert_eq!(rows.num_channels(), 5);
+        assert_eq!(rows.len(), 2);
+        for (y, row) in rows.enumerate() {
+            let expected: Vec<u8> = (0..3)
+                .flat_map(|x| (0..5).map(move |c| sample(c, x, y)))
+                .collect();
+            assert_eq!(*row, expected);
+        }
+
+        let rows = FrameRows::new(&image, &image.frames[0], RowOrder::ChannelMajor);
+        assert_eq!(rows.len(), 10);
+        for (i, row) in rows.enumerate() {
+            let (c, y) = (i / 2, i % 2);
+            let expected: Vec<u8> = (0..3).map(|x| sample(c, x, y)).collect();
+            assert_eq!(*row, expected, "channel {c}, row {y}");
+        }
+    }
+
+    #[test]
+    fn rows_without_extra_channels_are_borrowed() {
+        let mut image = test_image();
+        image.frames[0].channels.truncate(1);
+        for row in FrameRows::new(&image, &image.frames[0], RowOrder::PixelMajor) {
+            assert!(matches!(row, Cow::Borrowed(_)));
+        }
+    }
+}
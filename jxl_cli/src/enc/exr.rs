@@ -3,7 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use std::io::{Seek, Write};
+use std::io::{Cursor, Seek, Write};
 
 use color_eyre::eyre::{Result, eyre};
 use jxl::api::{JxlColorEncoding, JxlColorProfile, JxlTransferFunction};
@@ -11,7 +11,10 @@ use jxl::api::{JxlColorEncoding, JxlColorProfile, JxlTransferFunction};
 use exr::meta::attribute::Chromaticities;
 use exr::prelude::*;
 
+use super::file::OutputStream;
+use super::sink::BufferedSink;
 use crate::dec::{DecodeOutput, OutputDataType};
+use crate::report::Reporter;
 
 pub fn to_exr<Writer: Write + Seek>(image_data: &DecodeOutput, writer: &mut Writer) -> Result<()> {
     let tuple_to_vec2 = |(x, y)| Vec2(x, y);
@@ -52,10 +55,12 @@ pub fn to_exr<Writer: Write + Seek>(image_data: &DecodeOutput, writer: &mut Writ
     };
 
     if image_data.frames.len() > 1 {
-        eprintln!("Warning: More than one frame found, saving just the first one.");
+        Reporter::get().warn(format_args!(
+            "More than one frame found, saving just the first one."
+        ));
     }
     if image_data.frames[0].channels.len() > 1 {
-        eprintln!("Warning: Ignoring extra channels.");
+        Reporter::get().warn(format_args!("Ignoring extra channels."));
     }
 
     let (width, height) = image_data.size;
@@ -121,3 +126,17 @@ pub fn to_exr<Writer: Write + Seek>(image_data: &DecodeOutput, writer: &mut Writ
     image.write().to_buffered(writer)?;
     Ok(())
 }
+
+/// Like [`to_exr`], for streams that may not be able to seek, such as stdout.
+pub fn to_exr_stream(
+    image_data: &DecodeOutput,
+    writer: &mut BufferedSink<OutputStream>,
+) -> Result<()> {
+    if writer.get_ref().is_seekable() {
+        return to_exr(image_data, writer);
+    }
+    // The encoder seeks back to fill in the offset tables, so the file is encoded in memory first.
+    let mut exr = Cursor::new(vec![]);
+    to_exr(image_data, &mut exr)?;
+    Ok(writer.write_all(exr.get_ref())?)
+}
@@ -0,0 +1,370 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, StdoutLock, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::Result;
+
+use super::sink::BufferedSink;
+
+/// Output path that stands for stdout.
+pub const STDOUT_PATH: &str = "-";
+
+/// What output files are written through. Only implemented by [`OutputStream`] outside of tests.
+pub trait OutputHandle: Write + Seek {
+    fn set_len(&self, len: u64) -> io::Result<()>;
+}
+
+/// A file, or stdout when the output path is [`STDOUT_PATH`].
+///
+/// Stdout cannot seek, other than to where it already is, and cannot be resized, other than to
+/// the bytes already written.
+pub enum OutputStream {
+    File(File),
+    Stdout {
+        handle: StdoutLock<'static>,
+        position: u64,
+    },
+}
+
+impl OutputStream {
+    /// Whether the stream can seek to any position, which some encoders need.
+    pub fn is_seekable(&self) -> bool {
+        matches!(self, Self::File(_))
+    }
+}
+
+fn stdout_unsupported(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("cannot {what} standard output"),
+    )
+}
+
+impl Write for OutputStream {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(data),
+            Self::Stdout { handle, position } => {
+                let n = handle.write(data)?;
+                *position += n as u64;
+                Ok(n)
+            }
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.write_vectored(bufs),
+            Self::Stdout { handle, position } => {
+                let n = handle.write_vectored(bufs)?;
+                *position += n as u64;
+                Ok(n)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Stdout { handle, .. } => handle.flush(),
+        }
+    }
+}
+
+impl Seek for OutputStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Stdout { position, .. } => match pos {
+                SeekFrom::Start(p) if p == *position => Ok(p),
+                SeekFrom::Current(0) => Ok(*position),
+                _ => Err(stdout_unsupported("seek in")),
+            },
+        }
+    }
+}
+
+impl OutputHandle for OutputStream {
+    fn set_len(&self, len: u64) -> io::Result<()> {
+        match self {
+            Self::File(file) => file.set_len(len),
+            Self::Stdout { position, .. } if len == *position => Ok(()),
+            Self::Stdout { .. } => Err(stdout_unsupported("resize")),
+        }
+    }
+}
+
+/// An output file that is opened before decoding, so that outputs that cannot be written are
+/// reported before spending time on the decoding, and then written through the same handle.
+///
+/// Files that are not completely written are removed, so that a failed decode never leaves
+/// behind an output that looks valid. Files that already existed are only removed once they were
+/// modified.
+///
+/// The path [`STDOUT_PATH`] writes to stdout instead of a file.
+pub struct OutputFile<H: OutputHandle = OutputStream> {
+    path: PathBuf,
+    handle: Option<H>,
+    existed: bool,
+    modified: bool,
+    complete: bool,
+}
+
+impl OutputFile {
+    /// Opens `path` for writing, creating it if needed. Existing files are only truncated when
+    /// they are written or preallocated.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if is_stdout(path) {
+            let stdout = OutputStream::Stdout {
+                handle: io::stdout().lock(),
+                position: 0,
+            };
+            return Ok(Self::with_handle(path, stdout, true));
+        }
+        let existed = path.exists();
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(Self::with_handle(path, OutputStream::File(file), existed))
+    }
+}
+
+/// Whether `path` stands for stdout.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new(STDOUT_PATH)
+}
+
+impl<H: OutputHandle> OutputFile<H> {
+    fn with_handle(path: &Path, handle: H, existed: bool) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            handle: Some(handle),
+            existed,
+            modified: false,
+            complete: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_stdout(&self) -> bool {
+        is_stdout(&self.path)
+    }
+
+    /// Sets the length of the file to `len` bytes, so that outputs that exceed a quota or a size
+    /// limit fail before decoding. File systems that support sparse files do not allocate the
+    /// bytes, so a full disk can still only be detected while writing. The file is truncated
+    /// again when it is written. Does nothing for stdout.
+    pub fn preallocate(&mut self, len: u64) -> io::Result<()> {
+        if self.is_stdout() {
+            return Ok(());
+        }
+        self.modified = true;
+        let handle = self.handle.as_ref().unwrap();
+        handle.set_len(0)?;
+        handle.set_len(len)
+    }
+
+    /// Replaces the contents of the file with the bytes written by `encode`. Returns the time
+    /// spent writing to the file, which does not include the time `encode` spends producing the
+    /// bytes.
+    pub fn write(
+        self,
+        encode: impl FnOnce(&mut BufferedSink<H>) -> Result<()>,
+    ) -> Result<Duration> {
+        let mut writer = self.start()?;
+        encode(writer.sink())?;
+        writer.finish()
+    }
+
+    /// Truncates the file and returns a writer to replace its contents with, for outputs that
+    /// are written a bit at a time. The file is removed if the writer is dropped before
+    /// [`OutputWriter::finish`].
+    pub fn start(mut self) -> Result<OutputWriter<H>> {
+        self.modified = true;
+        let start = Instant::now();
+        let mut handle = self.handle.take().unwrap();
+        handle.set_len(0)?;
+        handle.rewind()?;
+        Ok(OutputWriter {
+            sink: BufferedSink::new(handle),
+            file: self,
+            truncate_time: start.elapsed(),
+        })
+    }
+}
+
+/// The contents of an [`OutputFile`] being written, from [`OutputFile::start`].
+pub struct OutputWriter<H: OutputHandle = OutputStream> {
+    // Dropped before the file, which may remove it.
+    sink: BufferedSink<H>,
+    file: OutputFile<H>,
+    truncate_time: Duration,
+}
+
+impl<H: OutputHandle> OutputWriter<H> {
+    pub fn sink(&mut self) -> &mut BufferedSink<H> {
+        &mut self.sink
+    }
+
+    /// Writes the buffered bytes, and returns the time spent writing to the file so far.
+    pub fn finish(mut self) -> Result<Duration> {
+        let flush_start = Instant::now();
+        let write_time = self.sink.write_time();
+        self.sink.flush()?;
+        self.file.complete = true;
+        Ok(self.truncate_time + write_time + flush_start.elapsed())
+    }
+}
+
+impl<H: OutputHandle> Drop for OutputFile<H> {
+    fn drop(&mut self) {
+        // The handle is closed first, as open files cannot be removed on some systems.
+        drop(self.handle.take());
+        if !self.complete && (!self.existed || self.modified) && !self.is_stdout() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::tests::TempDir;
+    use io::SeekFrom;
+
+    /// A file on a file system that only has room for `capacity` bytes, which fails like a full
+    /// disk when writing or preallocating more.
+    struct Quota {
+        file: File,
+        capacity: u64,
+    }
+
+    impl Write for Quota {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            let position = self.file.stream_position()?;
+            let n = data
+                .len()
+                .min(self.capacity.saturating_sub(position) as usize);
+            if n == 0 && !data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.file.write(&data[..n])
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl Seek for Quota {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.file.seek(pos)
+        }
+    }
+
+    impl OutputHandle for Quota {
+        fn set_len(&self, len: u64) -> io::Result<()> {
+            if len > self.capacity {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.file.set_len(len)
+        }
+    }
+
+    fn with_quota(path: &Path, capacity: u64) -> OutputFile<Quota> {
+        let file = File::create(path).unwrap();
+        OutputFile::with_handle(path, Quota { file, capacity }, false)
+    }
+
+    #[test]
+    fn unwritten_outputs_are_removed() {
+        let dir = TempDir::new("unwritten_output");
+        let path = dir.0.join("out.ppm");
+        drop(OutputFile::create(&path).unwrap());
+        assert!(!path.exists());
+
+        // Existing files are kept, unless they were modified.
+        std::fs::write(&path, b"old").unwrap();
+        drop(OutputFile::create(&path).unwrap());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old");
+        OutputFile::create(&path).unwrap().preallocate(10).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn writes_replace_and_truncate() {
+        let dir = TempDir::new("replaced_output");
+        let path = dir.0.join("out.ppm");
+        std::fs::write(&path, b"a longer existing file").unwrap();
+        let mut output = OutputFile::create(&path).unwrap();
+        output.preallocate(100).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 100);
+        output.write(|writer| Ok(writer.write_all(b"P6")?)).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"P6");
+    }
+
+    #[test]
+    fn missing_directories_fail_early() {
+        let dir = TempDir::new("missing_output_dir");
+        assert!(OutputFile::create(&dir.0.join("missing/out.ppm")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_only_directories_fail_early() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new("read_only_output");
+        let set_mode =
+            |mode| std::fs::set_permissions(&dir.0, std::fs::Permissions::from_mode(mode)).unwrap();
+        set_mode(0o555);
+        // Privileged users can write to read-only directories.
+        let writable = File::create(dir.0.join("probe")).is_ok();
+        let result = OutputFile::create(&dir.0.join("out.ppm"));
+        set_mode(0o755);
+        if writable {
+            eprintln!("read-only directories are writable, skipping");
+        } else {
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn full_disks_remove_partial_files() {
+        let dir = TempDir::new("full_output");
+        let path = dir.0.join("out.npy");
+        let err = with_quota(&path, 1000)
+            .write(|writer| {
+                for _ in 0..10 {
+                    writer.write_all(&[0; 1 << 10])?;
+                }
+                Ok(())
+            })
+            .unwrap_err();
+        let err = err.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert!(!path.exists());
+
+        // Preallocation fails before anything is written.
+        let mut output = with_quota(&path, 1000);
+        let err = output.preallocate(1 << 20).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        output.preallocate(1000).unwrap();
+        output
+            .write(|writer| Ok(writer.write_all(&[1; 1000])?))
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [1; 1000]);
+    }
+}
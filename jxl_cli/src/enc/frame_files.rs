@@ -0,0 +1,101 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Names of the files of outputs that are written one file per frame.
+
+use std::path::{Path, PathBuf};
+
+/// Names the file of each frame of an output written one file per frame, by its zero-based index.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameFiles {
+    dir: PathBuf,
+    prefix: String,
+    suffix: String,
+    /// Minimum number of digits of the index, padded with leading zeros.
+    width: usize,
+}
+
+impl FrameFiles {
+    /// The files named by `path` if its file name contains `%d`, or `%0Nd` for indices padded
+    /// to N digits, which is replaced by the index of each frame.
+    pub fn from_pattern(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let mut search_start = 0;
+        while let Some(offset) = name[search_start..].find('%') {
+            let start = search_start + offset;
+            let spec = &name[start + 1..];
+            let digits = spec.bytes().take_while(u8::is_ascii_digit).count();
+            if spec[digits..].starts_with('d') && (digits == 0 || spec.starts_with('0')) {
+                return Some(Self {
+                    dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+                    prefix: name[..start].to_string(),
+                    suffix: spec[digits + 1..].to_string(),
+                    width: spec[..digits].parse().unwrap_or(0),
+                });
+            }
+            search_start = start + 1;
+        }
+        None
+    }
+
+    /// The files named after `path` with `-NNN` before its extension, such as `out-000.ppm`,
+    /// `out-001.ppm` and so on for `out.ppm`.
+    pub fn numbered(path: &Path) -> Self {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let suffix = match path.extension() {
+            Some(extension) => format!(".{}", extension.to_string_lossy()),
+            None => String::new(),
+        };
+        Self {
+            dir: path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            prefix: format!("{stem}-"),
+            suffix,
+            width: 3,
+        }
+    }
+
+    /// The file of the frame with the given zero-based index.
+    pub fn path(&self, index: usize) -> PathBuf {
+        let Self {
+            prefix,
+            suffix,
+            width,
+            ..
+        } = self;
+        self.dir.join(format!("{prefix}{index:0width$}{suffix}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns() {
+        let path = |pattern: &str, index| {
+            FrameFiles::from_pattern(Path::new(pattern)).map(|files| files.path(index))
+        };
+        assert_eq!(path("out_%04d.png", 7), Some(PathBuf::from("out_0007.png")));
+        assert_eq!(path("dir/%d.ppm", 12), Some(PathBuf::from("dir/12.ppm")));
+        assert_eq!(path("a%b_%03d", 1), Some(PathBuf::from("a%b_001")));
+        assert_eq!(
+            path("out_%02d.png", 123),
+            Some(PathBuf::from("out_123.png"))
+        );
+        // Only the file name is a pattern, and only zero padding is supported.
+        assert_eq!(path("%d/out.png", 0), None);
+        assert_eq!(path("out_%4d.png", 0), None);
+        assert_eq!(path("out.png", 0), None);
+    }
+
+    #[test]
+    fn numbered() {
+        let files = FrameFiles::numbered(Path::new("dir/out.ppm"));
+        assert_eq!(files.path(0), PathBuf::from("dir/out-000.ppm"));
+        assert_eq!(files.path(1234), PathBuf::from("dir/out-1234.ppm"));
+        let files = FrameFiles::numbered(Path::new("out"));
+        assert_eq!(files.path(5), PathBuf::from("out-005"));
+    }
+}
@@ -0,0 +1,600 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use color_eyre::eyre::{Result, bail, ensure};
+use jxl::api::FrameTiming;
+
+use crate::dec::{DecodeOutput, ImageFrame, OutputDataType, OutputShape};
+use crate::report::Reporter;
+
+/// Number of colors of a GIF palette.
+const MAX_COLORS: usize = 256;
+
+/// Number of pixels that are sampled to build the palette, at most.
+const MAX_PALETTE_SAMPLES: usize = 1 << 22;
+
+/// Largest code of the LZW compression of GIF files.
+const MAX_LZW_CODE: u16 = 4095;
+
+/// Pixels with an alpha below this are transparent, and all others are opaque.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Returns the color of a pixel, or `None` if it is transparent.
+fn pixel(samples: &[u8], is_grayscale: bool, has_alpha: bool) -> Option<[u8; 3]> {
+    let num_colors = if is_grayscale { 1 } else { 3 };
+    if has_alpha && samples[num_colors] < ALPHA_THRESHOLD {
+        return None;
+    }
+    Some(if is_grayscale {
+        [samples[0]; 3]
+    } else {
+        [samples[0], samples[1], samples[2]]
+    })
+}
+
+/// Calls `f` with the color of every `step`-th pixel of `frame`, in raster order.
+fn for_each_pixel(
+    frame: &ImageFrame,
+    size: (usize, usize),
+    step: usize,
+    mut f: impl FnMut(Option<[u8; 3]>),
+) {
+    let color_type = frame.color_type;
+    let samples_per_pixel = color_type.samples_per_pixel();
+    let mut skip = 0;
+    for y in 0..size.1 {
+        let row = &frame.channels[0].row(y)[..size.0 * samples_per_pixel];
+        for samples in row.chunks_exact(samples_per_pixel) {
+            if skip == 0 {
+                f(pixel(
+                    samples,
+                    color_type.is_grayscale(),
+                    color_type.has_alpha(),
+                ));
+                skip = step;
+            }
+            skip -= 1;
+        }
+    }
+}
+
+/// Picks at most `max_colors` colors that represent the pixels of `histogram` with median cut:
+/// the set of colors is split in two at its median along the channel with the largest range,
+/// starting with the set with the largest range, until there are enough sets. Each set is then
+/// represented by its average color. Images with few enough colors keep them exactly.
+fn median_cut(histogram: HashMap<[u8; 3], u64>, max_colors: usize) -> Vec<[u8; 3]> {
+    let mut colors: Vec<_> = histogram.into_iter().collect();
+    if colors.len() <= max_colors {
+        colors.sort_unstable();
+        return colors.into_iter().map(|(color, _)| color).collect();
+    }
+    // Channel with the largest range of a set of colors, and that range.
+    let widest_channel = |colors: &[([u8; 3], u64)]| {
+        (0..3)
+            .map(|c| {
+                let (min, max) = colors.iter().fold((u8::MAX, 0), |(min, max), (color, _)| {
+                    (min.min(color[c]), max.max(color[c]))
+                });
+                (max - min, c)
+            })
+            .max()
+            .unwrap()
+    };
+    let mut boxes = vec![colors];
+    while boxes.len() < max_colors {
+        let Some((index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(i, colors)| (widest_channel(colors), i))
+            .max()
+            .map(|((_, channel), i)| (i, channel))
+        else {
+            break;
+        };
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut count = 0;
+        let median = colors
+            .iter()
+            .position(|(_, n)| {
+                count += n;
+                2 * count >= total
+            })
+            .unwrap();
+        // Both halves must keep at least one color.
+        let split = (median + 1).min(colors.len() - 1);
+        let upper = colors.split_off(split);
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|(_, count)| count).sum();
+            std::array::from_fn(|c| {
+                let sum: u64 = colors
+                    .iter()
+                    .map(|(color, count)| color[c] as u64 * count)
+                    .sum();
+                ((sum + total / 2) / total) as u8
+            })
+        })
+        .collect()
+}
+
+/// Maps colors to the index of the closest color of a palette.
+struct Quantizer {
+    palette: Vec<[u8; 3]>,
+    cache: HashMap<[u8; 3], u8>,
+}
+
+impl Quantizer {
+    fn index(&mut self, color: [u8; 3]) -> u8 {
+        let palette = &self.palette;
+        *self.cache.entry(color).or_insert_with(|| {
+            let distance = |p: &[u8; 3]| {
+                (0..3)
+                    .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+                    .sum::<i32>()
+            };
+            (0..palette.len())
+                .min_by_key(|&i| distance(&palette[i]))
+                .unwrap() as u8
+        })
+    }
+}
+
+/// Packs LZW codes into bytes, least significant bit first.
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u32,
+    num_bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, code_size: u32) {
+        self.bits |= (code as u32) << self.num_bits;
+        self.num_bits += code_size;
+        while self.num_bits >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.num_bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.num_bits > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Compresses palette indices with the variable code size LZW compression of GIF files, with 8-bit
+/// literals.
+fn lzw_compress(indices: &[u8]) -> Vec<u8> {
+    const CLEAR: u16 = 256;
+    const END: u16 = 257;
+    let mut writer = BitWriter {
+        bytes: Vec::with_capacity(indices.len() / 2),
+        bits: 0,
+        num_bits: 0,
+    };
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END + 1;
+    let mut code_size = 9;
+    writer.write(CLEAR, code_size);
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(END, code_size);
+        return writer.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, code_size);
+        codes.insert((prefix, index), next_code);
+        prefix = index as u16;
+        // Decoders only see a code after reading the next one, so they widen the codes one code
+        // later than the table needs: the code that fills a width still fits in it.
+        if next_code == 1 << code_size {
+            code_size += 1;
+        }
+        next_code += 1;
+        if next_code > MAX_LZW_CODE {
+            writer.write(CLEAR, code_size);
+            codes.clear();
+            next_code = END + 1;
+            code_size = 9;
+        }
+    }
+    writer.write(prefix, code_size);
+    writer.write(END, code_size);
+    writer.finish()
+}
+
+/// Writes `data` as a sequence of sub-blocks of at most 255 bytes, and the terminating empty
+/// sub-block.
+fn write_sub_blocks<Writer: Write>(data: &[u8], writer: &mut Writer) -> Result<()> {
+    for block in data.chunks(255) {
+        writer.write_all(&[block.len() as u8])?;
+        writer.write_all(block)?;
+    }
+    writer.write_all(&[0])?;
+    Ok(())
+}
+
+/// Returns the GIF delays, in hundredths of a second, of frames with the given durations.
+/// Rounding errors do not accumulate: each frame ends at the multiple of a hundredth of a
+/// second closest to the end of the original frame.
+fn gif_delays<'a>(timings: impl Iterator<Item = Option<&'a FrameTiming>>) -> Vec<u16> {
+    let mut ticks = 0u128;
+    let mut end = 0u128;
+    timings
+        .map(|timing| {
+            let Some(timing) = timing.filter(|timing| timing.tps_num > 0) else {
+                return 0;
+            };
+            ticks += timing.ticks as u128;
+            let tps_num = timing.tps_num as u128;
+            let start = end;
+            end = (ticks * timing.tps_den as u128 * 100 + tps_num / 2) / tps_num;
+            (end - start).min(u16::MAX as u128) as u16
+        })
+        .collect()
+}
+
+/// Writes the frames as a GIF, animated if there is more than one, with a palette of at most
+/// 256 colors shared by all frames. Pixels are either opaque or fully transparent, depending on
+/// their alpha, and frame durations are rounded to hundredths of a second.
+pub fn to_gif<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    ensure!(
+        img.data_type == OutputDataType::U8,
+        "GIF only stores 8-bit samples, not {:?}",
+        img.data_type
+    );
+    let (width, height) = img.size;
+    if width > u16::MAX as usize || height > u16::MAX as usize {
+        bail!("GIF images are at most 65535 pixels wide and high, not {width}x{height}");
+    }
+    if img.frames[0].channels.len() > 1 {
+        Reporter::get().warn(format_args!("Ignoring non-alpha extra channels."));
+    }
+
+    let num_pixels = width * height * img.frames.len();
+    let step = num_pixels.div_ceil(MAX_PALETTE_SAMPLES);
+    let mut histogram = HashMap::new();
+    let mut has_transparency = false;
+    for frame in &img.frames {
+        for_each_pixel(frame, img.size, step, |color| match color {
+            Some(color) => *histogram.entry(color).or_insert(0) += 1,
+            None => has_transparency = true,
+        });
+    }
+    // Fully transparent images still need a color.
+    if histogram.is_empty() {
+        histogram.insert([0; 3], 1);
+    }
+    let palette = median_cut(histogram, MAX_COLORS - has_transparency as usize);
+    let transparent_index = has_transparency.then_some(palette.len() as u8);
+    let table_bits = (palette.len() + has_transparency as usize)
+        .next_power_of_two()
+        .trailing_zeros()
+        .max(1);
+
+    writer.write_all(b"GIF89a")?;
+    writer.write_all(&(width as u16).to_le_bytes())?;
+    writer.write_all(&(height as u16).to_le_bytes())?;
+    // Global color table, 8 bits per channel, and background color and aspect ratio 0.
+    writer.write_all(&[0xf0 | (table_bits - 1) as u8, 0, 0])?;
+    let mut table = palette.concat();
+    table.resize(3 << table_bits, 0);
+    writer.write_all(&table)?;
+
+    let animated = img.frames.len() > 1;
+    if animated {
+        // The loop count is the number of repetitions after the first run, 0 being forever.
+        let loops = img.jxl_animation.as_ref().map_or(0, |anim| anim.num_loops);
+        if loops != 1 {
+            writer.write_all(b"\x21\xff\x0bNETSCAPE2.0\x03\x01")?;
+            writer
+                .write_all(&(loops.saturating_sub(1).min(u16::MAX as u32) as u16).to_le_bytes())?;
+            writer.write_all(&[0])?;
+        }
+    }
+
+    let delays = gif_delays(img.frames.iter().map(|frame| frame.timing.as_ref()));
+    let mut quantizer = Quantizer {
+        palette,
+        cache: HashMap::new(),
+    };
+    let mut indices = Vec::with_capacity(width * height);
+    for (frame, delay) in img.frames.iter().zip(delays) {
+        if animated || has_transparency {
+            // Frames replace each other entirely, so transparent pixels must not show the
+            // previous frame.
+            let disposal = if has_transparency { 2 } else { 1 };
+            writer.write_all(&[0x21, 0xf9, 4, disposal << 2 | has_transparency as u8])?;
+            writer.write_all(&delay.to_le_bytes())?;
+            writer.write_all(&[transparent_index.unwrap_or(0), 0])?;
+        }
+        writer.write_all(&[0x2c, 0, 0, 0, 0])?;
+        writer.write_all(&(width as u16).to_le_bytes())?;
+        writer.write_all(&(height as u16).to_le_bytes())?;
+        writer.write_all(&[0])?;
+
+        indices.clear();
+        for_each_pixel(frame, img.size, 1, |color| {
+            indices.push(match color {
+                Some(color) => quantizer.index(color),
+                None => transparent_index.unwrap(),
+            })
+        });
+        writer.write_all(&[8])?;
+        write_sub_blocks(&lzw_compress(&indices), writer)?;
+    }
+    writer.write_all(&[0x3b])?;
+    Ok(())
+}
+
+/// Rough size of the GIF written for frames of the given shape, assuming that compression makes
+/// up for the headers.
+pub fn estimate_size(shape: &OutputShape) -> u64 {
+    shape.size.0 as u64 * shape.size.1 as u64 * shape.num_frames as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::{JxlAnimation, JxlColorType};
+
+    /// Decompresses the LZW data of a GIF image with 8-bit literals.
+    fn lzw_decompress(data: &[u8]) -> Vec<u8> {
+        let mut table: Vec<Vec<u8>> = vec![];
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            *table = (0..=255).map(|i| vec![i as u8]).collect();
+            table.extend([vec![], vec![]]);
+        };
+        reset(&mut table);
+        let (mut bits, mut num_bits, mut code_size) = (0u32, 0, 9);
+        let mut prev: Option<Vec<u8>> = None;
+        let mut output = vec![];
+        let mut bytes = data.iter();
+        loop {
+            while num_bits < code_size {
+                bits |= (*bytes.next().unwrap() as u32) << num_bits;
+                num_bits += 8;
+            }
+            let code = (bits & ((1 << code_size) - 1)) as usize;
+            bits >>= code_size;
+            num_bits -= code_size;
+            match code {
+                256 => {
+                    reset(&mut table);
+                    code_size = 9;
+                    prev = None;
+                    continue;
+                }
+                257 => return output,
+                _ => {}
+            }
+            let entry = if code < table.len() {
+                table[code].clone()
+            } else {
+                let prev = prev.as_ref().unwrap();
+                [prev.as_slice(), &prev[..1]].concat()
+            };
+            if let Some(prev) = prev {
+                if table.len() < 4096 {
+                    table.push([prev.as_slice(), &entry[..1]].concat());
+                }
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            output.extend_from_slice(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    struct ParsedGif {
+        size: (u16, u16),
+        palette: Vec<[u8; 3]>,
+        loops: Option<u16>,
+        /// Delay and transparent index of each frame, and the palette indices of its pixels.
+        frames: Vec<(u16, Option<u8>, Vec<u8>)>,
+    }
+
+    fn parse_gif(gif: &[u8]) -> ParsedGif {
+        assert_eq!(&gif[..6], b"GIF89a");
+        let u16_at = |pos: usize| u16::from_le_bytes([gif[pos], gif[pos + 1]]);
+        let size = (u16_at(6), u16_at(8));
+        assert_eq!(gif[10] & 0x80, 0x80);
+        let table_len = 3 << ((gif[10] & 7) + 1);
+        let palette = gif[13..13 + table_len]
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        let mut pos = 13 + table_len;
+        let read_sub_blocks = |pos: &mut usize| {
+            let mut data = vec![];
+            loop {
+                let len = gif[*pos] as usize;
+                *pos += 1;
+                if len == 0 {
+                    return data;
+                }
+                data.extend_from_slice(&gif[*pos..*pos + len]);
+                *pos += len;
+            }
+        };
+        let mut parsed = ParsedGif {
+            size,
+            palette,
+            loops: None,
+            frames: vec![],
+        };
+        let mut control = (0, None);
+        loop {
+            match gif[pos] {
+                0x21 => {
+                    let label = gif[pos + 1];
+                    pos += 2;
+                    let data = read_sub_blocks(&mut pos);
+                    match label {
+                        0xf9 => {
+                            let transparent = (data[0] & 1 == 1).then_some(data[3]);
+                            control = (u16::from_le_bytes([data[1], data[2]]), transparent);
+                        }
+                        0xff => {
+                            assert_eq!(&data[..11], b"NETSCAPE2.0");
+                            parsed.loops = Some(u16::from_le_bytes([data[12], data[13]]));
+                        }
+                        _ => panic!("unexpected extension {label:#x}"),
+                    }
+                }
+                0x2c => {
+                    assert_eq!((u16_at(pos + 5), u16_at(pos + 7)), size);
+                    assert_eq!(gif[pos + 9], 0, "no local color table or interlacing");
+                    assert_eq!(gif[pos + 10], 8);
+                    pos += 11;
+                    let pixels = lzw_decompress(&read_sub_blocks(&mut pos));
+                    parsed.frames.push((control.0, control.1, pixels));
+                    control = (0, None);
+                }
+                0x3b => {
+                    assert_eq!(pos + 1, gif.len());
+                    return parsed;
+                }
+                byte => panic!("unexpected block {byte:#x} at {pos}"),
+            }
+        }
+    }
+
+    /// An RGBA animation whose frames have a few colors each, and a transparent pixel in the
+    /// second frame.
+    fn make_animation(size: (usize, usize), ticks: &[u32]) -> DecodeOutput {
+        let animation = JxlAnimation::new(100, 1, 0);
+        let mut image = make_test_image(JxlColorType::Rgba, OutputDataType::U8, size);
+        image.frames.clear();
+        for (i, &ticks) in ticks.iter().enumerate() {
+            let mut frame = make_test_image(JxlColorType::Rgba, OutputDataType::U8, size)
+                .frames
+                .remove(0);
+            for y in 0..size.1 {
+                for (x, pixel) in frame.channels[0].row_mut(y).chunks_exact_mut(4).enumerate() {
+                    let color = [(x * 60) as u8, (y * 40) as u8, (i * 100) as u8];
+                    pixel[..3].copy_from_slice(&color);
+                    pixel[3] = if i == 1 && (x, y) == (1, 1) { 0 } else { 255 };
+                }
+            }
+            frame.timing = Some(FrameTiming::new(ticks, &animation));
+            image.frames.push(frame);
+        }
+        image.jxl_animation = Some(animation);
+        image
+    }
+
+    #[test]
+    fn animation_has_a_descriptor_per_frame() {
+        let size = (4, 3);
+        let image = make_animation(size, &[10, 25, 3]);
+        let mut gif = vec![];
+        to_gif(&image, &mut gif).unwrap();
+        let parsed = parse_gif(&gif);
+        assert_eq!(parsed.size, (4, 3));
+        assert_eq!(parsed.loops, Some(0));
+        assert_eq!(parsed.frames.len(), 3);
+        // At 100 ticks per second, a tick is a hundredth of a second.
+        let delays: Vec<_> = parsed.frames.iter().map(|f| f.0).collect();
+        assert_eq!(delays, [10, 25, 3]);
+        // The image has few enough colors to keep them all.
+        let transparent = parsed.frames[0].1.unwrap();
+        for (frame, (_, transparent_index, pixels)) in image.frames.iter().zip(&parsed.frames) {
+            assert_eq!(*transparent_index, Some(transparent));
+            assert_eq!(pixels.len(), size.0 * size.1);
+            let mut expected = vec![];
+            for_each_pixel(frame, size, 1, |color| expected.push(color));
+            for (&index, color) in pixels.iter().zip(expected) {
+                match color {
+                    Some(color) => assert_eq!(parsed.palette[index as usize], color),
+                    None => assert_eq!(index, transparent),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn delays_are_rounded_without_drift() {
+        let animation = JxlAnimation::new(30000, 1001, 0);
+        let timings: Vec<_> = (0..1000)
+            .map(|i| FrameTiming::new(1 + i % 3, &animation))
+            .collect();
+        let delays = gif_delays(timings.iter().map(Some));
+        assert_eq!(&delays[..3], [3, 7, 10]);
+        let ticks: u32 = timings.iter().map(|t| t.ticks).sum();
+        let total: u32 = delays.iter().map(|&d| d as u32).sum();
+        let expected = ticks as f64 * 1001.0 / 300.0;
+        assert!(
+            (total as f64 - expected).abs() <= 0.5,
+            "{total} vs {expected}"
+        );
+        assert_eq!(gif_delays([None].into_iter()), [0]);
+    }
+
+    #[test]
+    fn colors_are_quantized_to_a_palette() {
+        // Far more colors than fit in a palette.
+        let size = (300, 200);
+        let image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, size);
+        let mut gif = vec![];
+        to_gif(&image, &mut gif).unwrap();
+        let parsed = parse_gif(&gif);
+        assert_eq!(parsed.palette.len(), MAX_COLORS);
+        assert_eq!(parsed.loops, None);
+        let [(_, None, pixels)] = &parsed.frames[..] else {
+            panic!("expected a single opaque frame");
+        };
+        let mut expected = vec![];
+        for_each_pixel(&image.frames[0], size, 1, |color| {
+            expected.push(color.unwrap())
+        });
+        let error: f64 = pixels
+            .iter()
+            .zip(expected)
+            .map(|(&index, color)| {
+                let quantized = parsed.palette[index as usize];
+                (0..3)
+                    .map(|c| (quantized[c] as f64 - color[c] as f64).powi(2))
+                    .sum::<f64>()
+            })
+            .sum();
+        let rms = (error / (3 * pixels.len()) as f64).sqrt();
+        assert!(rms < 24.0, "rms error {rms}");
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for indices in [
+            vec![],
+            vec![7],
+            vec![0; 100000],
+            (0..200000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+                .collect(),
+            (0..100000u32).map(|i| (i / 100 % 7) as u8).collect(),
+        ] {
+            assert_eq!(lzw_decompress(&lzw_compress(&indices)), indices);
+        }
+    }
+}
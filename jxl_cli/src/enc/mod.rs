@@ -3,102 +3,549 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use std::{fs::File, io::BufWriter, path::PathBuf};
+use std::{path::Path, str::FromStr, time::Duration};
 
-use color_eyre::eyre::{Result, eyre};
+use color_eyre::eyre::{Result, WrapErr, bail, eyre};
 
-use crate::dec::{DecodeOutput, OutputDataType};
+use crate::dec::{DecodeOutput, OutputDataType, OutputShape};
+use crate::report::Reporter;
+use file::{OutputFile, OutputStream};
+use frame_files::FrameFiles;
+use numpy::NumpyWriter;
+use raw::RawWriter;
+use sink::BufferedSink;
+use writer::{AllFrames, FirstFrame, FrameOutput, FrameWriter};
+use y4m::{ChromaSubsampling, Y4mWriter};
 
 #[cfg(feature = "exr")]
 pub mod exr;
+pub mod file;
+pub mod frame_files;
+pub mod gif;
 pub mod numpy;
+pub mod pam;
+pub mod pfm;
 pub mod png;
 pub mod pnm;
+pub mod raw;
+pub mod sink;
+pub mod tiff;
+pub mod writer;
+pub mod y4m;
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum OutputFormat {
     Ppm,
     Pgm,
     Npy,
+    Pfm,
     Png,
+    Pam,
+    Tiff,
+    Gif,
+    Y4m,
+    Y4m420,
+    Raw,
+    Planes,
     #[cfg(feature = "exr")]
     Exr,
 }
 
+/// Writes a decoded image to a file.
+pub type EncodeFn = fn(&DecodeOutput, &mut BufferedSink<OutputStream>) -> Result<()>;
+
+/// How a format takes the frames of an image while it is decoded.
+#[derive(Clone, Copy)]
+enum Frames {
+    /// Only the first frame is written, as soon as it is decoded.
+    First,
+    /// The file depends on all the frames, which are only written once the last one is decoded.
+    All,
+    /// The frames are written as they are decoded by the writer returned for files that can be
+    /// seeked or not, if any. Files without a writer get all the frames at once.
+    Streamed(fn(seekable: bool) -> Option<Box<dyn FrameWriter>>),
+    /// Like [`Frames::Streamed`], for files whose start depends on the number of frames. Files
+    /// that cannot be seeked get the number of frames counted before decoding them, and all the
+    /// frames at once if they cannot be counted.
+    Counted(fn(num_frames: Option<usize>) -> Box<dyn FrameWriter>),
+}
+
+struct FormatEntry {
+    format: OutputFormat,
+    name: &'static str,
+    /// Lowercase file extensions, the first one being the preferred one.
+    extensions: &'static [&'static str],
+    encode: EncodeFn,
+    frames: Frames,
+    /// Size of the file written for frames of the given shape, exact for uncompressed formats.
+    estimate_size: fn(&OutputShape) -> u64,
+}
+
+/// All supported output formats. New formats only need to be registered here.
+const FORMATS: &[FormatEntry] = &[
+    FormatEntry {
+        format: OutputFormat::Ppm,
+        name: "ppm",
+        extensions: &["ppm"],
+        encode: |image, writer| pnm::to_ppm(image, writer),
+        frames: Frames::First,
+        estimate_size: |shape| pnm::file_size(shape, "P6"),
+    },
+    FormatEntry {
+        format: OutputFormat::Pgm,
+        name: "pgm",
+        extensions: &["pgm"],
+        encode: |image, writer| pnm::to_pgm(image, writer),
+        frames: Frames::First,
+        estimate_size: |shape| pnm::file_size(shape, "P5"),
+    },
+    FormatEntry {
+        format: OutputFormat::Npy,
+        name: "npy",
+        extensions: &["npy"],
+        encode: |image, writer| Ok(numpy::to_numpy(image, writer)?),
+        frames: Frames::Counted(|num_frames| Box::new(NumpyWriter::new(num_frames))),
+        estimate_size: numpy::file_size,
+    },
+    FormatEntry {
+        format: OutputFormat::Pfm,
+        name: "pfm",
+        extensions: &["pfm"],
+        encode: |image, writer| pfm::to_pfm(image, writer),
+        frames: Frames::First,
+        estimate_size: pfm::file_size,
+    },
+    FormatEntry {
+        format: OutputFormat::Png,
+        name: "png",
+        extensions: &["png", "apng"],
+        encode: |image, writer| png::to_png(image, writer, None),
+        frames: Frames::All,
+        // Compression usually more than makes up for the headers and the filter type bytes.
+        estimate_size: |shape| shape.sample_bytes(),
+    },
+    FormatEntry {
+        format: OutputFormat::Pam,
+        name: "pam",
+        extensions: &["pam"],
+        encode: |image, writer| pam::to_pam(image, writer),
+        frames: Frames::First,
+        estimate_size: pam::file_size,
+    },
+    FormatEntry {
+        format: OutputFormat::Tiff,
+        name: "tiff",
+        extensions: &["tif", "tiff"],
+        encode: |image, writer| tiff::to_tiff(image, writer),
+        frames: Frames::All,
+        estimate_size: tiff::file_size,
+    },
+    FormatEntry {
+        format: OutputFormat::Gif,
+        name: "gif",
+        extensions: &["gif"],
+        encode: |image, writer| gif::to_gif(image, writer),
+        frames: Frames::All,
+        estimate_size: gif::estimate_size,
+    },
+    FormatEntry {
+        format: OutputFormat::Y4m,
+        name: "y4m",
+        extensions: &["y4m"],
+        encode: |image, writer| y4m::to_y4m(image, writer, ChromaSubsampling::Yuv444),
+        frames: Frames::Streamed(|_| Some(Box::new(Y4mWriter::new(ChromaSubsampling::Yuv444)))),
+        estimate_size: |shape| y4m::estimate_size(shape, ChromaSubsampling::Yuv444),
+    },
+    // Only selected with --output-format, as it shares its extension with 4:4:4 streams.
+    FormatEntry {
+        format: OutputFormat::Y4m420,
+        name: "y4m420",
+        extensions: &[],
+        encode: |image, writer| y4m::to_y4m(image, writer, ChromaSubsampling::Yuv420),
+        frames: Frames::Streamed(|_| Some(Box::new(Y4mWriter::new(ChromaSubsampling::Yuv420)))),
+        estimate_size: |shape| y4m::estimate_size(shape, ChromaSubsampling::Yuv420),
+    },
+    FormatEntry {
+        format: OutputFormat::Raw,
+        name: "raw",
+        extensions: &["raw"],
+        encode: |image, writer| raw::to_raw(image, writer, false),
+        frames: Frames::Streamed(|seekable| seekable.then(|| Box::new(RawWriter::new(false)) as _)),
+        estimate_size: |shape| raw::file_size(shape, false),
+    },
+    FormatEntry {
+        format: OutputFormat::Planes,
+        name: "planes",
+        extensions: &["planes"],
+        encode: |image, writer| raw::to_raw(image, writer, true),
+        frames: Frames::Streamed(|seekable| seekable.then(|| Box::new(RawWriter::new(true)) as _)),
+        estimate_size: |shape| raw::file_size(shape, true),
+    },
+    #[cfg(feature = "exr")]
+    FormatEntry {
+        format: OutputFormat::Exr,
+        name: "exr",
+        extensions: &["exr"],
+        encode: |image, writer| exr::to_exr_stream(image, writer),
+        frames: Frames::First,
+        estimate_size: |shape| shape.sample_bytes(),
+    },
+];
+
+/// Returns the names of all supported output formats, separated by commas.
+pub fn supported_formats() -> String {
+    let names: Vec<_> = FORMATS.iter().map(|f| f.name).collect();
+    names.join(", ")
+}
+
+impl FromStr for OutputFormat {
+    type Err = color_eyre::Report;
+
+    fn from_str(name: &str) -> Result<Self> {
+        FORMATS
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+            .map(|f| f.format)
+            .ok_or_else(|| {
+                eyre!(
+                    "Unknown output format {name:?}, supported formats: {}",
+                    supported_formats()
+                )
+            })
+    }
+}
+
 impl OutputFormat {
-    pub fn from_output_filename(filename: &str) -> Result<Self> {
-        #[cfg(feature = "exr")]
-        if filename.ends_with(".exr") {
-            return Ok(OutputFormat::Exr);
-        }
-        if filename.ends_with(".ppm") {
-            return Ok(OutputFormat::Ppm);
-        }
-        if filename.ends_with(".pgm") {
-            return Ok(OutputFormat::Pgm);
-        }
-        if filename.ends_with(".npy") {
-            return Ok(OutputFormat::Npy);
-        }
-        if filename.ends_with(".png") || filename.ends_with(".apng") {
-            return Ok(OutputFormat::Png);
+    fn entry(&self) -> &'static FormatEntry {
+        FORMATS.iter().find(|f| f.format == *self).unwrap()
+    }
+
+    /// Infers the output format from the extension of `path`, ignoring case.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let Some(extension) = path.extension() else {
+            bail!(
+                "Cannot infer the output format of {path:?} without an extension, use \
+                 --output-format ({})",
+                supported_formats()
+            );
+        };
+        let extension = extension.to_string_lossy();
+        FORMATS
+            .iter()
+            .find(|f| {
+                f.extensions
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(&extension))
+            })
+            .map(|f| f.format)
+            .ok_or_else(|| {
+                eyre!(
+                    "Unsupported output extension {extension:?} of {path:?}, supported formats: {}",
+                    supported_formats()
+                )
+            })
+    }
+
+    /// Returns `explicit` if set, and the format inferred from `path` otherwise.
+    pub fn for_output(path: &Path, explicit: Option<Self>) -> Result<Self> {
+        if explicit.is_none() && file::is_stdout(path) {
+            bail!(
+                "Writing to standard output needs --format ({})",
+                supported_formats()
+            );
         }
-        Err(eyre!("Output format not supported for {:?}", filename))
+        explicit.map_or_else(|| Self::from_path(path), Ok)
     }
 
     pub fn supported_output_data_types(&self) -> &'static [OutputDataType] {
         match self {
-            Self::Ppm | Self::Pgm => &[OutputDataType::U8],
-            Self::Npy => &[OutputDataType::F32],
-            Self::Png => &[OutputDataType::U8, OutputDataType::U16],
+            Self::Npy => &[OutputDataType::F32, OutputDataType::U16],
+            Self::Pfm | Self::Raw | Self::Planes => &[OutputDataType::F32],
+            Self::Ppm | Self::Pgm | Self::Png | Self::Pam | Self::Y4m | Self::Y4m420 => {
+                &[OutputDataType::U8, OutputDataType::U16]
+            }
+            Self::Tiff => &[OutputDataType::U8, OutputDataType::U16, OutputDataType::F32],
+            Self::Gif => &[OutputDataType::U8],
             #[cfg(feature = "exr")]
             Self::Exr => &[OutputDataType::F16, OutputDataType::F32],
         }
     }
 
+    /// The data type to use when none is requested, if it does not depend on the bit depth of the
+    /// image. npy files store f32 samples unless another type is requested, and pfm and raw files
+    /// only store f32 samples.
+    pub fn default_output_data_type(&self) -> Option<OutputDataType> {
+        match self {
+            Self::Npy | Self::Pfm | Self::Raw | Self::Planes => Some(OutputDataType::F32),
+            _ => None,
+        }
+    }
+
+    /// Whether the format can store 16 and 32-bit samples in either byte order.
+    pub fn supports_endianness(&self) -> bool {
+        *self == Self::Npy
+    }
+
+    /// Whether the format can store pixels column by column.
+    pub fn supports_column_major(&self) -> bool {
+        *self == Self::Npy
+    }
+
     pub fn should_fold_alpha(&self) -> bool {
         match self {
-            Self::Ppm | Self::Pgm | Self::Npy => false,
-            Self::Png => true,
+            Self::Ppm | Self::Pgm | Self::Npy | Self::Pfm | Self::Y4m | Self::Y4m420 => false,
+            Self::Png | Self::Pam | Self::Tiff | Self::Gif | Self::Raw | Self::Planes => true,
             #[cfg(feature = "exr")]
             Self::Exr => true,
         }
     }
 
-    pub fn save_image(&self, image_data: &DecodeOutput, output_filename: &PathBuf) -> Result<()> {
+    /// Whether files of this format only hold one frame, so that animations are written one file
+    /// per frame.
+    pub fn holds_single_frame(&self) -> bool {
+        matches!(self.entry().frames, Frames::First)
+    }
+
+    /// Estimated size of the file written for frames of the given shape.
+    pub fn estimate_size(&self, shape: &OutputShape) -> u64 {
+        (self.entry().estimate_size)(shape)
+    }
+
+    /// Writes `image_data` to `output`, and the partial renders of its frame, if any, to files
+    /// next to it. Returns the time spent writing to the files, as opposed to encoding.
+    pub fn save_image(&self, image_data: &DecodeOutput, output: OutputFile) -> Result<Duration> {
+        let mut write_time = Duration::ZERO;
+        let output_filename = output.path().to_path_buf();
         let has_partial_renders = image_data
             .frames
             .iter()
             .any(|x| !x.partial_renders.is_empty());
         if has_partial_renders {
-            if image_data.frames.len() != 1 {
-                eprintln!("Warning: Ignoring partial renders in animations.");
+            if output.is_stdout() {
+                Reporter::get().warn(format_args!(
+                    "Ignoring partial renders when writing to standard output."
+                ));
+            } else if image_data.frames.len() != 1 {
+                Reporter::get().warn(format_args!("Ignoring partial renders in animations."));
             } else if *self != Self::Png {
-                eprintln!("Warning: Ignoring partial renders with non-PNG output.");
+                Reporter::get().warn(format_args!(
+                    "Ignoring partial renders with non-PNG output."
+                ));
             } else {
                 let num_partials = image_data.frames[0].partial_renders.len();
                 for i in 0..=num_partials {
                     let dir = output_filename.parent().unwrap();
                     let stem = output_filename.file_stem().unwrap().to_string_lossy();
                     let fname = dir.join(format!("{stem}.partial{i:05}.png"));
-                    let mut writer = BufWriter::new(File::create(fname)?);
-                    png::to_png(
-                        image_data,
-                        &mut writer,
-                        if i < num_partials { Some(i) } else { None },
-                    )?
+                    write_time += OutputFile::create(&fname)?.write(|writer| {
+                        png::to_png(
+                            image_data,
+                            writer,
+                            if i < num_partials { Some(i) } else { None },
+                        )
+                    })?;
                 }
             }
         }
-        let mut writer = BufWriter::new(File::create(output_filename)?);
-        match self {
-            Self::Ppm => pnm::to_ppm(image_data, &mut writer)?,
-            Self::Pgm => pnm::to_pgm(image_data, &mut writer)?,
-            Self::Npy => numpy::to_numpy(image_data, &mut writer)?,
-            Self::Png => png::to_png(image_data, &mut writer, None)?,
-            #[cfg(feature = "exr")]
-            Self::Exr => exr::to_exr(image_data, &mut writer)?,
+        Ok(write_time + output.write(|writer| (self.entry().encode)(image_data, writer))?)
+    }
+
+    /// Writes each frame of `image_data` to its own file, named by `files`, like [`Self::save_image`]
+    /// writes an image of that frame alone. The frames are handed back to `image_data` afterwards.
+    pub fn save_frame_files(
+        &self,
+        image_data: &mut DecodeOutput,
+        files: &FrameFiles,
+    ) -> Result<Duration> {
+        let mut write_time = Duration::ZERO;
+        let frames = std::mem::take(&mut image_data.frames);
+        for (i, frame) in frames.into_iter().enumerate() {
+            let mut frame_data = image_data.with_frames(vec![frame]);
+            let path = files.path(i);
+            let result = OutputFile::create(&path)
+                .map_err(Into::into)
+                .and_then(|output| self.save_image(&frame_data, output));
+            image_data.frames.append(&mut frame_data.frames);
+            write_time += result.wrap_err_with(|| format!("Failed to write {path:?}"))?;
+        }
+        Ok(write_time)
+    }
+
+    /// Starts writing `output` frame by frame while the image is decoded, which only keeps the
+    /// frames that the format needs at once. Partial renders are not written. `count_frames` is
+    /// only called for formats that need the number of frames before the first one.
+    pub fn start_output(
+        &self,
+        output: OutputFile,
+        count_frames: impl FnOnce() -> Result<usize>,
+    ) -> Result<FrameOutput> {
+        let entry = self.entry();
+        let writer: Box<dyn FrameWriter> = match entry.frames {
+            Frames::First => Box::new(FirstFrame::new(entry.encode)),
+            Frames::All => Box::new(AllFrames::new(entry.encode)),
+            Frames::Streamed(writer) => writer(!output.is_stdout())
+                .unwrap_or_else(|| Box::new(AllFrames::new(entry.encode))),
+            Frames::Counted(writer) if !output.is_stdout() => writer(None),
+            Frames::Counted(writer) => match count_frames() {
+                Ok(num_frames) => writer(Some(num_frames)),
+                Err(_) => Box::new(AllFrames::new(entry.encode)),
+            },
         };
-        Ok(())
+        FrameOutput::new(writer, output)
+    }
+
+    /// Starts writing each frame to its own file, named by `files`, as soon as it is decoded.
+    pub fn start_frame_files(&self, files: FrameFiles) -> FrameOutput {
+        FrameOutput::frame_files(files, self.entry().encode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn format_from_path() {
+        let format = |p: &str| OutputFormat::from_path(Path::new(p)).ok();
+        assert_eq!(format("out.png"), Some(OutputFormat::Png));
+        assert_eq!(format("OUT.PNG"), Some(OutputFormat::Png));
+        assert_eq!(format("dir.ppm/out.Apng"), Some(OutputFormat::Png));
+        assert_eq!(format("out.pgm"), Some(OutputFormat::Pgm));
+        assert_eq!(format("out.NPY"), Some(OutputFormat::Npy));
+        assert_eq!(format("out.ppm"), Some(OutputFormat::Ppm));
+        assert_eq!(format("out.pfm"), Some(OutputFormat::Pfm));
+        assert_eq!(format("out.pam"), Some(OutputFormat::Pam));
+        assert_eq!(format("out.tif"), Some(OutputFormat::Tiff));
+        assert_eq!(format("OUT.TIFF"), Some(OutputFormat::Tiff));
+        assert_eq!(format("out.gif"), Some(OutputFormat::Gif));
+        assert_eq!(format("out.y4m"), Some(OutputFormat::Y4m));
+        assert_eq!(format("out.raw"), Some(OutputFormat::Raw));
+        assert_eq!(format("out.planes"), Some(OutputFormat::Planes));
+        #[cfg(feature = "exr")]
+        assert_eq!(format("out.exr"), Some(OutputFormat::Exr));
+        // Only the last extension counts.
+        assert_eq!(format("out.png.tmp"), None);
+        assert_eq!(format("out.tmp.png"), Some(OutputFormat::Png));
+        assert_eq!(format("png"), None);
+        assert_eq!(format("out."), None);
+    }
+
+    #[test]
+    fn unknown_formats_list_supported_ones() {
+        let err = OutputFormat::from_path(Path::new("out.bmp")).unwrap_err();
+        assert!(err.to_string().contains("ppm, pgm, npy, pfm, png"), "{err}");
+        let err = OutputFormat::from_path(Path::new("out")).unwrap_err();
+        assert!(err.to_string().contains("--output-format"), "{err}");
+        let err = "bmp".parse::<OutputFormat>().unwrap_err();
+        assert!(err.to_string().contains("ppm, pgm, npy, pfm, png"), "{err}");
+    }
+
+    #[test]
+    fn explicit_format_wins() {
+        assert_eq!("PNG".parse::<OutputFormat>().unwrap(), OutputFormat::Png);
+        let path = Path::new("out.ppm");
+        assert_eq!(
+            OutputFormat::for_output(path, Some(OutputFormat::Npy)).unwrap(),
+            OutputFormat::Npy
+        );
+        assert_eq!(
+            OutputFormat::for_output(path, None).unwrap(),
+            OutputFormat::Ppm
+        );
+        assert_eq!(
+            OutputFormat::for_output(Path::new("-"), Some(OutputFormat::Png)).unwrap(),
+            OutputFormat::Png
+        );
+        assert!(OutputFormat::for_output(Path::new("-"), None).is_err());
+    }
+
+    #[test]
+    fn failed_writes_remove_the_file() {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("jxl_cli_failed_{}.ppm", std::process::id()));
+        let err = OutputFile::create(&path)
+            .unwrap()
+            .write(|writer| {
+                writer.write_all(&[0; 1 << 20])?;
+                bail!("encoding failed")
+            })
+            .unwrap_err();
+        assert_eq!(err.to_string(), "encoding failed");
+        assert!(!path.exists());
+        OutputFile::create(&path)
+            .unwrap()
+            .write(|writer| Ok(writer.write_all(b"P6")?))
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"P6");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn uncompressed_sizes_are_exact() {
+        use crate::dec::{decode_frames, decode_header};
+        use jxl::api::{Endianness, JxlDecoderOptions};
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let dir = crate::cache::tests::TempDir::new("estimated_sizes");
+        for (file, format) in [
+            ("basic.jxl", OutputFormat::Ppm),
+            ("basic.jxl", OutputFormat::Npy),
+            ("grayscale_patches_modular.jxl", OutputFormat::Pgm),
+            ("3x3a_srgb_lossy.jxl", OutputFormat::Npy),
+            ("basic.jxl", OutputFormat::Pfm),
+            ("grayscale_patches_modular.jxl", OutputFormat::Pfm),
+            ("3x3a_srgb_lossy.jxl", OutputFormat::Pam),
+            ("gray_alpha_lossless.jxl", OutputFormat::Pam),
+            ("3x3a_srgb_lossy.jxl", OutputFormat::Raw),
+            ("3x3a_srgb_lossy.jxl", OutputFormat::Planes),
+        ] {
+            let data = std::fs::read(root.join(file)).unwrap();
+            let output = decode_frames(
+                &mut data.as_slice(),
+                JxlDecoderOptions::default(),
+                None,
+                format.default_output_data_type(),
+                format.supported_output_data_types(),
+                format.should_fold_alpha(),
+                false,
+                None,
+                false,
+                None,
+                None,
+                Endianness::native(),
+            )
+            .unwrap()
+            .0;
+            let decoder =
+                decode_header(&mut data.as_slice(), JxlDecoderOptions::default()).unwrap();
+            let shape = OutputShape::of_image(
+                &decoder,
+                None,
+                format.default_output_data_type(),
+                format.supported_output_data_types(),
+                format.should_fold_alpha(),
+                None,
+                output.layout,
+            );
+            assert_eq!(shape, OutputShape::of_output(&output), "{file}");
+            let path = dir.0.join(format!("out.{}", format.entry().name));
+            format
+                .save_image(&output, OutputFile::create(&path).unwrap())
+                .unwrap();
+            let len = std::fs::metadata(&path).unwrap().len();
+            assert_eq!(format.estimate_size(&shape), len, "{file}");
+        }
+    }
+
+    #[test]
+    fn registry_is_consistent() {
+        for entry in FORMATS {
+            assert_eq!(entry.name.parse::<OutputFormat>().unwrap(), entry.format);
+            assert_eq!(entry.format.entry().name, entry.name);
+            for extension in entry.extensions {
+                let path = PathBuf::from(format!("out.{extension}"));
+                assert_eq!(OutputFormat::from_path(&path).unwrap(), entry.format);
+            }
+        }
     }
 }
@@ -3,15 +3,39 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::dec::DecodeOutput;
+use super::writer::{FrameWriter, Sink};
+use crate::dec::{
+    DecodeOutput, FrameLease, FrameRows, ImageFrame, OutputDataType, OutputShape, RowOrder,
+};
+use color_eyre::eyre::{self, bail};
+use jxl::api::{Endianness, OutputLayout};
 use jxl::error::Result;
-use std::io::Write;
+use std::io::{Seek, SeekFrom, Write};
 
+/// The numpy dtype of samples of `data_type` in the given byte order.
+fn numpy_descr(data_type: OutputDataType, endianness: Endianness) -> String {
+    let byte_order = match endianness {
+        Endianness::LittleEndian => '<',
+        Endianness::BigEndian => '>',
+    };
+    let kind = match data_type {
+        OutputDataType::U8 => "u1",
+        OutputDataType::U16 => "u2",
+        OutputDataType::F16 => "f2",
+        OutputDataType::F32 => "f4",
+    };
+    format!("{byte_order}{kind}")
+}
+
+/// Writes the header of the file. The header leaves room for any number of frames, so that
+/// [`NumpyWriter`] can write it again with the final number of frames.
 fn numpy_header<Writer: Write>(
+    descr: &str,
     xsize: usize,
     ysize: usize,
     num_channels: usize,
     num_frames: usize,
+    layout: OutputLayout,
     writer: &mut Writer,
 ) -> Result<()> {
     // The magic string and version for .npy files (Version 1.0)
@@ -19,17 +43,27 @@ fn numpy_header<Writer: Write>(
 
     // Construct the header dictionary string.
     // Note the trailing comma in the tuple and the space before the closing brace, and the newline.
-    //
-    // The dtype '<f4' signifies little-endian 32-bit float.
-    let mut header_dict_str = format!(
-        "{{'descr': '<f4', 'fortran_order': False, 'shape': \
-	 ({num_frames}, {ysize}, {xsize}, {num_channels}), }}"
-    );
+    // In Fortran order the first axis varies fastest, so column-major samples have the reverse
+    // shape.
+    let header_dict = |num_frames: usize| {
+        let (fortran_order, shape) = match layout {
+            OutputLayout::RowMajor => ("False", [num_frames, ysize, xsize, num_channels]),
+            OutputLayout::ColumnMajor => ("True", [num_channels, ysize, xsize, num_frames]),
+        };
+        let [s0, s1, s2, s3] = shape;
+        format!(
+            "{{'descr': '{descr}', 'fortran_order': {fortran_order}, 'shape': \
+	 ({s0}, {s1}, {s2}, {s3}), }}"
+        )
+    };
+    let mut header_dict_str = header_dict(num_frames);
+    let reserved_len = header_dict(usize::MAX).len();
     // https://github.com/numpy/numpy/blob/main/doc/neps/nep-0001-npy-format.rst:
     // "terminated by a newline ('n') and padded with spaces ('x20') to make the total length of the magic string + 4 + HEADER_LEN be evenly divisible by 16 for alignment purposes"
     // The 4 is a 2 since the major and minor versions are included in the magic string. The extra 1 at the end is for the newline.
+    let padded_len = (magic_string.len() + 2 + reserved_len + 1).next_multiple_of(16);
     header_dict_str.push_str(
-        (0..(16 - ((magic_string.len() + 2 + header_dict_str.len() + 1) % 16) % 16))
+        (0..padded_len - (magic_string.len() + 2 + header_dict_str.len() + 1))
             .map(|_| " ")
             .collect::<String>()
             .as_str(),
@@ -52,55 +86,131 @@ fn numpy_header<Writer: Write>(
     Ok(())
 }
 
-fn numpy_bytes<Writer: Write>(image_data: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+fn num_channels(frame: &ImageFrame) -> usize {
+    frame.channels.len() - 1 + frame.color_type.samples_per_pixel()
+}
+
+fn write_header<Writer: Write>(
+    image_data: &DecodeOutput,
+    num_channels: usize,
+    num_frames: usize,
+    writer: &mut Writer,
+) -> Result<()> {
     let (width, height) = image_data.size;
+    numpy_header(
+        &numpy_descr(image_data.data_type, image_data.endianness),
+        width,
+        height,
+        num_channels,
+        num_frames,
+        image_data.layout,
+        writer,
+    )
+}
 
-    for frame in &image_data.frames {
-        // Special-case the common case of having a single channel buffer in little endian floats.
-        if frame.channels.len() == 1 && cfg!(target_endian = "little") {
-            for y in 0..height {
-                writer.write_all(frame.channels[0].row(y))?;
-            }
-            continue;
-        }
-        let ch0 = frame.color_type.samples_per_pixel();
-        for y in 0..height {
-            for x in 0..width {
-                for c in 0..ch0 {
-                    writer.write_all(
-                        &f32::from_ne_bytes(
-                            frame.channels[0].row(y)[(x * ch0 + c) * 4..][..4]
-                                .try_into()
-                                .unwrap(),
-                        )
-                        .to_le_bytes(),
-                    )?;
-                }
-                for channel in frame.channels.iter().skip(1) {
-                    writer.write_all(
-                        &f32::from_ne_bytes(channel.row(y)[x * 4..][..4].try_into().unwrap())
-                            .to_le_bytes(),
-                    )?;
-                }
-            }
-        }
+fn numpy_bytes<Writer: Write>(
+    image_data: &DecodeOutput,
+    frame: &ImageFrame,
+    writer: &mut Writer,
+) -> Result<()> {
+    // Samples are already in the byte order of the file, so they are copied as they are.
+    for row in FrameRows::new(image_data, frame, RowOrder::PixelMajor) {
+        writer.write_all(&row)?;
     }
+    // Frames are handed over one at a time, so that readers of the file see whole frames.
+    writer.flush()?;
     Ok(())
 }
 
 /// Converts image_data to a Vec<u8> in .npy format.
-/// The data will be represented as little-endian 32-bit floats ('<f4').
-/// The shape of the NumPy array will be (num_frames, height, width, num_channels).
+/// The data is stored with the data type and byte order of the decoded samples, usually
+/// little-endian 32-bit floats ('<f4').
+/// The shape of the NumPy array will be (num_frames, height, width, num_channels), or
+/// (num_channels, height, width, num_frames) in Fortran order for column-major frames, which
+/// holds the same samples with the first and last axes swapped.
 ///
 pub fn to_numpy<Writer: Write>(image_data: &DecodeOutput, writer: &mut Writer) -> Result<()> {
-    let size = image_data.size;
-    let (width, height) = size;
-    let num_frames = image_data.frames.len();
-    let num_channels = image_data.frames[0].channels.len() - 1
-        + image_data.frames[0].color_type.samples_per_pixel();
+    let num_channels = num_channels(&image_data.frames[0]);
+    write_header(image_data, num_channels, image_data.frames.len(), writer)?;
+    for frame in &image_data.frames {
+        numpy_bytes(image_data, frame, writer)?;
+    }
+    Ok(())
+}
 
-    numpy_header(width, height, num_channels, num_frames, writer)?;
-    numpy_bytes(image_data, writer)?;
+/// Writes the same file as [`to_numpy`] frame by frame. When the number of frames is known in
+/// advance, the final header is written before the first frame, which also works for files that
+/// cannot be seeked. Otherwise the header is written for no frames before the first one, and
+/// written again for all of them after the last one, with the same length.
+pub struct NumpyWriter {
+    /// Number of frames of the file, if known before the first one.
+    expected_frames: Option<usize>,
+    num_channels: usize,
+    num_frames: usize,
+}
 
-    Ok(())
+impl NumpyWriter {
+    pub fn new(expected_frames: Option<usize>) -> Self {
+        Self {
+            expected_frames,
+            num_channels: 0,
+            num_frames: 0,
+        }
+    }
+}
+
+impl FrameWriter for NumpyWriter {
+    fn push_frame(
+        &mut self,
+        image: &DecodeOutput,
+        frame: FrameLease,
+        sink: &mut Sink,
+    ) -> eyre::Result<()> {
+        if self.num_frames == 0 {
+            self.num_channels = num_channels(&frame);
+            write_header(
+                image,
+                self.num_channels,
+                self.expected_frames.unwrap_or(0),
+                sink,
+            )?;
+        }
+        numpy_bytes(image, &frame, sink)?;
+        self.num_frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, image: &DecodeOutput, sink: &mut Sink) -> eyre::Result<()> {
+        match self.expected_frames {
+            Some(expected) if expected != self.num_frames => bail!(
+                "Expected {expected} frame(s) for the header, but {} were decoded",
+                self.num_frames
+            ),
+            Some(_) => {}
+            None => {
+                let end = sink.stream_position()?;
+                sink.rewind()?;
+                write_header(image, self.num_channels, self.num_frames, sink)?;
+                sink.seek(SeekFrom::Start(end))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Size of the file [`to_numpy`] writes for frames of the given shape.
+pub fn file_size(shape: &OutputShape) -> u64 {
+    let mut header = vec![];
+    // The byte order does not change the length of the header.
+    numpy_header(
+        &numpy_descr(shape.data_type, Endianness::native()),
+        shape.size.0,
+        shape.size.1,
+        shape.color_samples + shape.extra_channels,
+        shape.num_frames,
+        shape.layout,
+        &mut header,
+    )
+    .expect("writing to a Vec cannot fail");
+    header.len() as u64 + shape.sample_bytes()
 }
@@ -0,0 +1,167 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::{Result, bail};
+use jxl::api::JxlColorType;
+use std::io::Write;
+
+use crate::dec::{DecodeOutput, OutputDataType, OutputShape};
+use crate::enc::pnm::write_samples;
+use crate::report::Reporter;
+
+fn pam_header(size: (usize, usize), depth: usize, maxval: u32, tuple_type: &str) -> String {
+    format!(
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {depth}\nMAXVAL {maxval}\nTUPLTYPE {tuple_type}\nENDHDR\n",
+        size.0, size.1
+    )
+}
+
+fn tuple_type(samples_per_pixel: usize) -> &'static str {
+    match samples_per_pixel {
+        1 => "GRAYSCALE",
+        2 => "GRAYSCALE_ALPHA",
+        3 => "RGB",
+        _ => "RGB_ALPHA",
+    }
+}
+
+fn maxval(data_type: OutputDataType) -> Result<u32> {
+    match data_type {
+        OutputDataType::U8 => Ok(255),
+        OutputDataType::U16 => Ok(65535),
+        data_type => bail!("PAM only stores 8 and 16-bit samples, not {data_type:?}"),
+    }
+}
+
+/// Writes the first frame as a Portable Arbitrary Map, with its alpha channel, if any, as the
+/// last sample of each pixel. 16-bit samples are stored big-endian.
+pub fn to_pam<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    let maxval = maxval(img.data_type)?;
+    let frame = &img.frames[0];
+    let samples_per_pixel = match frame.color_type {
+        JxlColorType::Grayscale => 1,
+        JxlColorType::GrayscaleAlpha => 2,
+        JxlColorType::Rgb => 3,
+        JxlColorType::Rgba => 4,
+        color_type => bail!("PAM does not support {color_type:?} samples"),
+    };
+    if img.frames.len() > 1 {
+        Reporter::get().warn(format_args!(
+            "More than one frame found, saving just the first one."
+        ));
+    }
+    if frame.channels.len() > 1 {
+        Reporter::get().warn(format_args!("Ignoring extra channels."));
+    }
+    let header = pam_header(
+        img.size,
+        samples_per_pixel,
+        maxval,
+        tuple_type(samples_per_pixel),
+    );
+    writer.write_all(header.as_bytes())?;
+    write_samples(img, samples_per_pixel, writer)
+}
+
+/// Size of the file [`to_pam`] writes for frames of the given shape.
+pub fn file_size(shape: &OutputShape) -> u64 {
+    let maxval = maxval(shape.data_type).unwrap_or(255);
+    let header = pam_header(
+        shape.size,
+        shape.color_samples,
+        maxval,
+        tuple_type(shape.color_samples),
+    );
+    let samples = shape.size.0 as u64 * shape.size.1 as u64 * shape.color_samples as u64;
+    header.len() as u64 + samples * if maxval > 255 { 2 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::decode_frames;
+    use jxl::api::{Endianness, JxlDecoderOptions};
+    use std::path::PathBuf;
+
+    fn decode(file: &str, data_type: OutputDataType) -> DecodeOutput {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join(file)).unwrap();
+        decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            Some(data_type),
+            &[OutputDataType::U8, OutputDataType::U16],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap()
+        .0
+    }
+
+    /// Checks the header of `pam` and returns its samples.
+    fn samples<'a>(pam: &'a [u8], image: &DecodeOutput, header: &str) -> &'a [u8] {
+        let (width, height) = image.size;
+        let header = format!("P7\nWIDTH {width}\nHEIGHT {height}\n{header}\nENDHDR\n");
+        assert!(
+            pam.starts_with(header.as_bytes()),
+            "{}",
+            String::from_utf8_lossy(pam)
+        );
+        &pam[header.len()..]
+    }
+
+    #[test]
+    fn rgba_8_bit() {
+        let image = decode("3x3a_srgb_lossy.jxl", OutputDataType::U8);
+        assert_eq!(image.frames[0].color_type, JxlColorType::Rgba);
+        let mut pam = vec![];
+        to_pam(&image, &mut pam).unwrap();
+        let samples = samples(&pam, &image, "DEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA");
+        let (width, height) = image.size;
+        assert_eq!(samples.len(), width * height * 4);
+        for y in 0..height {
+            let row = &image.frames[0].channels[0].row(y)[..width * 4];
+            assert_eq!(&samples[y * width * 4..][..width * 4], row);
+        }
+    }
+
+    #[test]
+    fn gray_alpha_16_bit() {
+        let image = decode("gray_alpha_lossless.jxl", OutputDataType::U16);
+        assert_eq!(image.frames[0].color_type, JxlColorType::GrayscaleAlpha);
+        let mut pam = vec![];
+        to_pam(&image, &mut pam).unwrap();
+        let samples = samples(
+            &pam,
+            &image,
+            "DEPTH 2\nMAXVAL 65535\nTUPLTYPE GRAYSCALE_ALPHA",
+        );
+        let (width, height) = image.size;
+        assert_eq!(samples.len(), width * height * 4);
+        for y in 0..height {
+            let row = image.frames[0].channels[0].row(y);
+            for i in 0..width * 2 {
+                let expected = u16::from_ne_bytes([row[2 * i], row[2 * i + 1]]);
+                let offset = (y * width * 2 + i) * 2;
+                let value = u16::from_be_bytes([samples[offset], samples[offset + 1]]);
+                assert_eq!(value, expected, "sample {i} of row {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn float_samples_are_rejected() {
+        let mut image = decode("basic.jxl", OutputDataType::U8);
+        image.data_type = OutputDataType::F32;
+        let err = to_pam(&image, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("8 and 16-bit"), "{err}");
+    }
+}
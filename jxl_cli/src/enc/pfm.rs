@@ -0,0 +1,136 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::{Result, bail, ensure};
+use jxl::api::{Endianness, JxlColorType};
+use std::io::Write;
+
+use crate::dec::{DecodeOutput, OutputDataType, OutputShape};
+use crate::report::Reporter;
+
+fn pfm_header(magic: &str, size: (usize, usize)) -> String {
+    // A negative scale means little-endian samples.
+    format!("{magic}\n{} {}\n-1.0\n", size.0, size.1)
+}
+
+/// Writes the color samples of the first frame as a Portable FloatMap: `Pf` for grayscale and `PF`
+/// for RGB frames, with little-endian 32-bit float samples and rows stored from bottom to top.
+/// Frames with extra channels, including alpha, are rejected.
+pub fn to_pfm<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    assert_eq!(img.data_type, OutputDataType::F32);
+    let frame = &img.frames[0];
+    let magic = match frame.color_type {
+        JxlColorType::Grayscale => "Pf",
+        JxlColorType::Rgb => "PF",
+        color_type => bail!("PFM cannot store extra channels, but the image is {color_type:?}"),
+    };
+    ensure!(
+        frame.channels.len() == 1,
+        "PFM cannot store extra channels, but the image has {}",
+        frame.channels.len() - 1
+    );
+    if img.frames.len() > 1 {
+        Reporter::get().warn(format_args!(
+            "More than one frame found, saving just the first one."
+        ));
+    }
+    writer.write_all(pfm_header(magic, img.size).as_bytes())?;
+    let row_len = img.size.0 * frame.color_type.samples_per_pixel() * 4;
+    let mut row_bytes = Vec::with_capacity(row_len);
+    for y in (0..img.size.1).rev() {
+        let row = &frame.channels[0].row(y)[..row_len];
+        row_bytes.clear();
+        if img.endianness == Endianness::LittleEndian {
+            row_bytes.extend_from_slice(row);
+        } else {
+            for sample in row.chunks_exact(4) {
+                row_bytes.extend_from_slice(&[sample[3], sample[2], sample[1], sample[0]]);
+            }
+        }
+        writer.write_all(&row_bytes)?;
+    }
+    Ok(())
+}
+
+/// Size of the file [`to_pfm`] writes for frames of the given shape.
+pub fn file_size(shape: &OutputShape) -> u64 {
+    let header = pfm_header("PF", shape.size);
+    let samples = shape.size.0 as u64 * shape.size.1 as u64 * shape.color_samples as u64;
+    header.len() as u64 + samples * 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::decode_frames;
+    use jxl::api::JxlDecoderOptions;
+    use std::path::PathBuf;
+
+    fn decode(file: &str) -> DecodeOutput {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join(file)).unwrap();
+        decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            Some(OutputDataType::F32),
+            &[OutputDataType::F32],
+            false,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn round_trip() {
+        for (file, magic, samples_per_pixel) in [
+            ("basic.jxl", "PF", 3),
+            ("grayscale_patches_modular.jxl", "Pf", 1),
+        ] {
+            let image = decode(file);
+            let mut pfm = vec![];
+            to_pfm(&image, &mut pfm).unwrap();
+
+            let (width, height) = image.size;
+            let header = format!("{magic}\n{width} {height}\n-1.0\n");
+            assert!(pfm.starts_with(header.as_bytes()), "{file}");
+            let samples = &pfm[header.len()..];
+            assert_eq!(
+                samples.len(),
+                width * height * samples_per_pixel * 4,
+                "{file}"
+            );
+            // Rows are stored from the bottom of the image.
+            for (x, y) in [
+                (0, 0),
+                (width - 1, 0),
+                (width / 2, height / 3),
+                (0, height - 1),
+            ] {
+                let row = image.frames[0].channels[0].row(y);
+                for c in 0..samples_per_pixel {
+                    let i = x * samples_per_pixel + c;
+                    let expected = f32::from_ne_bytes(row[4 * i..][..4].try_into().unwrap());
+                    let offset = ((height - 1 - y) * width * samples_per_pixel + i) * 4;
+                    let value = f32::from_le_bytes(samples[offset..][..4].try_into().unwrap());
+                    assert_eq!(value, expected, "{file} at ({x}, {y})");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extra_channels_are_rejected() {
+        let image = decode("3x3a_srgb_lossy.jxl");
+        let err = to_pfm(&image, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("extra channels"), "{err}");
+    }
+}
@@ -4,43 +4,70 @@
 // license that can be found in the LICENSE file.
 
 use jxl::api::{
-    JxlColorEncoding, JxlColorProfile, JxlPrimaries, JxlTransferFunction, JxlWhitePoint,
+    FrameTiming, JxlColorEncoding, JxlColorProfile, JxlPrimaries, JxlTransferFunction,
+    JxlWhitePoint,
 };
 
 use crate::dec::{DecodeOutput, OutputDataType};
+use crate::report::Reporter;
 use color_eyre::eyre::{Result, eyre};
-use jxl::headers::color_encoding::RenderingIntent;
+use jxl::api::RenderingIntent;
 
 use std::borrow::Cow;
 use std::io::Write;
 
-fn gcd(a: u64, b: u64) -> u64 {
-    if b == 0 { a } else { gcd(b, a % b) }
-}
-
-fn calculate_apng_delay(duration_ms: f64) -> Result<(u16, u16)> {
-    if duration_ms < 0.0 {
-        return Err(eyre!("Negative frame duration: {}", duration_ms));
+/// Returns the fraction with a numerator and denominator of at most `max` that is closest to
+/// `num / den`, which must have a nonzero denominator.
+fn best_fraction(num: u64, den: u64, max: u64) -> (u64, u64) {
+    if num >= max * den {
+        return (max, 1);
     }
-    if duration_ms == 0.0 {
-        return Ok((0, 1));
+    // Error of `n / d` relative to `num / den`, scaled by `den`, as a fraction over `d`.
+    let error = |n: u64, d: u64| (num as u128 * d as u128).abs_diff(n as u128 * den as u128);
+    let is_closer = |(n1, d1): (u64, u64), (n2, d2): (u64, u64)| {
+        error(n1, d1) * (d2 as u128) < error(n2, d2) * (d1 as u128)
+    };
+    // Walk the convergents of the continued fraction expansion of `num / den` until one
+    // exceeds `max`, and then pick the best of the last convergent and the largest
+    // semiconvergent that does not exceed it.
+    let (mut p, mut q) = (num, den);
+    let (mut n0, mut d0, mut n1, mut d1) = (0, 1, 1, 0);
+    loop {
+        let a = p / q;
+        let (n, d) = (a * n1 + n0, a * d1 + d0);
+        if n > max || d > max {
+            let bound = |limit: u64, step: u64| limit.checked_div(step).unwrap_or(u64::MAX);
+            let t = bound(max - n0, n1).min(bound(max - d0, d1));
+            let semiconvergent = (t * n1 + n0, t * d1 + d0);
+            return if t > 0 && is_closer(semiconvergent, (n1, d1)) {
+                semiconvergent
+            } else {
+                (n1, d1)
+            };
+        }
+        (n0, d0, n1, d1) = (n1, d1, n, d);
+        (p, q) = (q, p - a * q);
+        if q == 0 {
+            return (n1, d1);
+        }
     }
+}
 
-    let mut num = duration_ms.round() as u64;
-    let mut den = 1000u64;
-
-    let common = gcd(num, den);
-    num /= common;
-    den /= common;
-
-    if num > u16::MAX as u64 || den > u16::MAX as u64 {
-        Err(eyre!(
-            "APNG frame delay overflow after GCD: {}/{}",
-            num,
-            den
-        ))
+/// Returns the APNG delay, in seconds, closest to the duration of a frame.
+fn apng_delay(timing: Option<&FrameTiming>) -> (u16, u16) {
+    let Some(timing) = timing.filter(|timing| timing.ticks > 0 && timing.tps_num > 0) else {
+        return (0, 1);
+    };
+    let (num, den) = best_fraction(
+        timing.ticks as u64 * timing.tps_den as u64,
+        timing.tps_num as u64,
+        u16::MAX as u64,
+    );
+    // Delays that round to zero would make the frame last as short as possible instead.
+    if num == 0 {
+        (1, u16::MAX)
     } else {
-        Ok((num as u16, den as u16))
+        (num as u16, den as u16)
     }
 }
 
@@ -70,13 +97,7 @@ fn make_cicp(encoding: &JxlColorEncoding) -> Option<png::CodingIndependentCodePo
 
     Some(png::CodingIndependentCodePoints {
         color_primaries: match white_point {
-            JxlWhitePoint::DCI => {
-                if *primaries == JxlPrimaries::P3 {
-                    11
-                } else {
-                    return None;
-                }
-            }
+            JxlWhitePoint::DCI if *primaries == JxlPrimaries::P3 => 11,
             JxlWhitePoint::D65 => match primaries {
                 JxlPrimaries::SRGB => 1,
                 JxlPrimaries::BT2100 => 9,
@@ -99,13 +120,25 @@ fn make_cicp(encoding: &JxlColorEncoding) -> Option<png::CodingIndependentCodePo
     })
 }
 
+/// Returns `row` with samples in the big endian layout used by PNG, using `buffer` for the
+/// conversion of 16-bit samples if needed.
+fn png_row<'a>(row: &'a [u8], eight_bits: bool, buffer: &'a mut [u8]) -> &'a [u8] {
+    if eight_bits || cfg!(target_endian = "big") {
+        return &row[..buffer.len()];
+    }
+    for (dst, src) in buffer.chunks_exact_mut(2).zip(row.chunks_exact(2)) {
+        dst.copy_from_slice(&u16::from_ne_bytes([src[0], src[1]]).to_be_bytes());
+    }
+    buffer
+}
+
 pub fn to_png<Writer: Write>(
     image_data: &DecodeOutput,
     buf: &mut Writer,
     partial_render: Option<usize>,
 ) -> Result<()> {
     if image_data.frames[0].channels.len() > 1 {
-        eprintln!("Warning: Ignoring non-alpha extra channels.");
+        Reporter::get().warn(format_args!("Ignoring non-alpha extra channels."));
     }
 
     let (width, height) = image_data.size;
@@ -173,51 +206,103 @@ pub fn to_png<Writer: Write>(
     }
     let mut writer = encoder.write_header()?;
 
-    if eight_bits {
-        for frame in &image_data.frames {
-            if animated {
-                let (delay_num, delay_den) = calculate_apng_delay(frame.duration)?;
-                writer.set_frame_delay(delay_num, delay_den)?;
+    let row_size = width * num_channels * if eight_bits { 1 } else { 2 };
+    let mut buffer: Vec<u8> = vec![0; row_size];
+    for frame in &image_data.frames {
+        let chan = if let Some(p) = partial_render {
+            &frame.partial_renders[p][0]
+        } else {
+            &frame.channels[0]
+        };
+        if animated {
+            let (delay_num, delay_den) = apng_delay(frame.timing.as_ref());
+            writer.set_frame_delay(delay_num, delay_den)?;
+            // Stream writers borrowed from the same writer do not produce valid `fdAT` chunks,
+            // so animation frames are written in one go.
+            let mut data = Vec::with_capacity(row_size * height);
+            for y in 0..height {
+                data.extend_from_slice(png_row(chan.row(y), eight_bits, &mut buffer));
             }
+            writer.write_image_data(&data)?;
+        } else {
             let mut ww = writer.stream_writer()?;
-            let chan = if let Some(p) = partial_render {
-                &frame.partial_renders[p][0]
-            } else {
-                &frame.channels[0]
-            };
             for y in 0..height {
-                ww.write_all(chan.row(y))?;
+                ww.write_all(png_row(chan.row(y), eight_bits, &mut buffer))?;
             }
+            // Dropping the writers would write the last chunks but swallow their errors.
+            ww.finish()?;
         }
-    } else {
-        // 16-bit
-        let mut buffer: Vec<u8> = vec![0; 2 * width * num_channels];
-        for frame in &image_data.frames {
-            if animated {
-                let (delay_num, delay_den) = calculate_apng_delay(frame.duration)?;
-                writer.set_frame_delay(delay_num, delay_den)?;
-            }
-            let mut ww = writer.stream_writer()?;
-            let chan = if let Some(p) = partial_render {
-                &frame.partial_renders[p][0]
-            } else {
-                &frame.channels[0]
-            };
-            for y in 0..height {
-                let row = chan.row(y);
-                if cfg!(target_endian = "big") {
-                    ww.write_all(row)?;
-                } else {
-                    for x in 0..width * num_channels {
-                        buffer[x * 2..][..2].copy_from_slice(
-                            &u16::from_ne_bytes(row[x * 2..][..2].try_into().unwrap())
-                                .to_be_bytes(),
-                        );
-                    }
-                    ww.write_all(&buffer)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn best_fraction_is_closest() {
+        let max = 20;
+        for (num, den) in [
+            (1, 3),
+            (355, 113),
+            (1000, 3),
+            (1, 1000),
+            (31, 77),
+            (7, 7),
+            (0, 5),
+        ] {
+            let (n, d) = best_fraction(num, den, max);
+            let error = |n: u64, d: u64| (num as f64 / den as f64 - n as f64 / d as f64).abs();
+            for d2 in 1..=max {
+                for n2 in 0..=max {
+                    assert!(
+                        error(n, d) <= error(n2, d2),
+                        "{num}/{den}: {n}/{d} vs {n2}/{d2}"
+                    );
                 }
             }
         }
     }
-    Ok(())
+
+    #[test]
+    fn apng_delay_ntsc() {
+        // Durations that cycle through 1, 2 and 3 ticks at 30000/1001 ticks per second.
+        let mut ticks = 0;
+        let mut seconds = 0.0;
+        for i in 0..10000 {
+            let timing = FrameTiming {
+                ticks: 1 + i % 3,
+                tps_num: 30000,
+                tps_den: 1001,
+            };
+            let (num, den) = apng_delay(Some(&timing));
+            ticks += timing.ticks as u64;
+            seconds += num as f64 / den as f64;
+        }
+        let drift = seconds * 30000.0 / 1001.0 - ticks as f64;
+        assert!(drift.abs() < 1.0, "drift of {drift} ticks");
+    }
+
+    #[test]
+    fn apng_delay_rounding() {
+        let timing = |ticks, tps_num, tps_den| {
+            apng_delay(Some(&FrameTiming {
+                ticks,
+                tps_num,
+                tps_den,
+            }))
+        };
+        assert_eq!(apng_delay(None), (0, 1));
+        assert_eq!(timing(0, 1000, 1), (0, 1));
+        assert_eq!(timing(100, 1000, 1), (1, 10));
+        assert_eq!(timing(1001, 30000, 1), (1001, 30000));
+        // 1 / 65537 seconds is too short to be represented.
+        assert_eq!(timing(1, 65537, 1), (1, 65535));
+        assert_eq!(timing(u32::MAX, 1, 1), (65535, 1));
+        // 333667 / 10000000 seconds.
+        let (num, den) = timing(333667, 10000000, 1);
+        assert!((num as f64 / den as f64 - 0.0333667).abs() < 1e-9);
+    }
 }
@@ -3,46 +3,198 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use color_eyre::eyre::{Result, ensure};
+use color_eyre::eyre::{Result, bail, ensure};
 use jxl::api::JxlColorType;
 use std::io::Write;
 
-use crate::dec::{DecodeOutput, OutputDataType};
+use crate::dec::{DecodeOutput, OutputDataType, OutputShape};
+use crate::report::Reporter;
 
-pub fn to_pgm<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
-    assert_eq!(img.data_type, OutputDataType::U8);
-    ensure!(
-        img.frames[0].color_type == JxlColorType::Grayscale,
-        "Writing to PPM only supports Grayscale"
-    );
-    if img.frames.len() > 1 {
-        eprintln!("Warning: More than one frame found, saving just the first one.");
-    }
-    if img.frames[0].channels.len() > 1 {
-        eprintln!("Warning: Ignoring extra channels.");
+fn maxval(data_type: OutputDataType) -> u32 {
+    if data_type == OutputDataType::U16 {
+        65535
+    } else {
+        255
     }
-    write!(writer, "P5\n{} {}\n255\n", img.size.0, img.size.1)?;
+}
+
+fn pnm_header(magic: &str, size: (usize, usize), maxval: u32) -> String {
+    format!("{magic}\n{} {}\n{maxval}\n", size.0, size.1)
+}
+
+/// Writes the interleaved samples of the first channel of the first frame, row by row, with
+/// 16-bit samples in the big-endian order that PNM and PAM files use.
+pub(crate) fn write_samples<Writer: Write>(
+    img: &DecodeOutput,
+    samples_per_pixel: usize,
+    writer: &mut Writer,
+) -> Result<()> {
+    let bytes_per_sample = img.data_type.bits_per_sample() / 8;
+    let row_len = img.size.0 * samples_per_pixel * bytes_per_sample;
+    let mut row_bytes = Vec::with_capacity(row_len);
     for y in 0..img.size.1 {
-        writer.write_all(img.frames[0].channels[0].row(y))?;
+        let row = &img.frames[0].channels[0].row(y)[..row_len];
+        if bytes_per_sample == 1 || cfg!(target_endian = "big") {
+            writer.write_all(row)?;
+            continue;
+        }
+        row_bytes.clear();
+        for sample in row.chunks_exact(2) {
+            row_bytes.extend_from_slice(&[sample[1], sample[0]]);
+        }
+        writer.write_all(&row_bytes)?;
     }
     Ok(())
 }
 
-pub fn to_ppm<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
-    assert_eq!(img.data_type, OutputDataType::U8);
+fn write_pnm<Writer: Write>(
+    img: &DecodeOutput,
+    writer: &mut Writer,
+    magic: &str,
+    color_type: JxlColorType,
+) -> Result<()> {
     ensure!(
-        img.frames[0].color_type == JxlColorType::Rgb,
-        "Writing to PPM only supports RGB"
+        img.frames[0].color_type == color_type,
+        "Writing to {} only supports {color_type:?}",
+        if magic == "P5" { "PGM" } else { "PPM" }
     );
     if img.frames.len() > 1 {
-        eprintln!("Warning: More than one frame found, saving just the first one.");
+        Reporter::get().warn(format_args!(
+            "More than one frame found, saving just the first one."
+        ));
     }
     if img.frames[0].channels.len() > 1 {
-        eprintln!("Warning: Ignoring extra channels.");
+        Reporter::get().warn(format_args!("Ignoring extra channels."));
     }
-    write!(writer, "P6\n{} {}\n255\n", img.size.0, img.size.1)?;
-    for y in 0..img.size.1 {
-        writer.write_all(img.frames[0].channels[0].row(y))?;
+    let header = pnm_header(magic, img.size, maxval(img.data_type));
+    writer.write_all(header.as_bytes())?;
+    write_samples(img, color_type.samples_per_pixel(), writer)
+}
+
+/// Writes the first frame as a PGM with 8 or 16-bit samples, depending on the output data type.
+pub fn to_pgm<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    match img.data_type {
+        OutputDataType::U8 => write_pnm(img, writer, "P5", JxlColorType::Grayscale),
+        OutputDataType::U16 => to_pgm_16bit(img, writer),
+        data_type => bail!("PGM only stores 8 and 16-bit samples, not {data_type:?}"),
+    }
+}
+
+/// Writes the first frame as a PGM with a maxval of 65535 and big-endian samples.
+pub fn to_pgm_16bit<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    assert_eq!(img.data_type, OutputDataType::U16);
+    write_pnm(img, writer, "P5", JxlColorType::Grayscale)
+}
+
+/// Writes the first frame as a PPM with 8 or 16-bit samples, depending on the output data type.
+pub fn to_ppm<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    match img.data_type {
+        OutputDataType::U8 => write_pnm(img, writer, "P6", JxlColorType::Rgb),
+        OutputDataType::U16 => to_ppm_16bit(img, writer),
+        data_type => bail!("PPM only stores 8 and 16-bit samples, not {data_type:?}"),
+    }
+}
+
+/// Writes the first frame as a PPM with a maxval of 65535 and big-endian samples.
+pub fn to_ppm_16bit<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    assert_eq!(img.data_type, OutputDataType::U16);
+    write_pnm(img, writer, "P6", JxlColorType::Rgb)
+}
+
+/// Size of the file with the given magic number that [`to_pgm`] or [`to_ppm`] write for frames of
+/// the given shape. Only the color samples of the first frame are written.
+pub fn file_size(shape: &OutputShape, magic: &str) -> u64 {
+    let header = pnm_header(magic, shape.size, maxval(shape.data_type));
+    let samples = shape.size.0 as u64 * shape.size.1 as u64 * shape.color_samples as u64;
+    header.len() as u64 + samples * (shape.data_type.bits_per_sample() / 8) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::decode_frames;
+    use jxl::api::{Endianness, JxlDecoderOptions};
+    use std::path::PathBuf;
+
+    fn decode(file: &str, data_type: OutputDataType) -> DecodeOutput {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join(file)).unwrap();
+        decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            Some(data_type),
+            &[data_type],
+            false,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap()
+        .0
+    }
+
+    /// Checks that the 16-bit samples of `pnm` are the float samples of the same image, scaled
+    /// to 65535 and rounded.
+    fn check_16_bit(pnm: &[u8], float: &DecodeOutput, header: &str) {
+        assert!(pnm.starts_with(header.as_bytes()));
+        let samples = &pnm[header.len()..];
+        let (width, height) = float.size;
+        let samples_per_row = width * float.frames[0].color_type.samples_per_pixel();
+        assert_eq!(samples.len(), samples_per_row * height * 2);
+        for y in 0..height {
+            let row = float.frames[0].channels[0].row(y);
+            for i in 0..samples_per_row {
+                let f = f32::from_ne_bytes(row[4 * i..][..4].try_into().unwrap());
+                let expected = (f.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                let offset = (y * samples_per_row + i) * 2;
+                let value = u16::from_be_bytes([samples[offset], samples[offset + 1]]);
+                assert_eq!(value, expected, "sample {i} of row {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn gradient_16_bit_pgm() {
+        let image = decode("pq_gradient.jxl", OutputDataType::U16);
+        let mut pgm = vec![];
+        to_pgm(&image, &mut pgm).unwrap();
+        let float = decode("pq_gradient.jxl", OutputDataType::F32);
+        check_16_bit(&pgm, &float, "P5\n1088 64\n65535\n");
+        // The gradient covers most of the 16-bit range, not just 256 levels of it.
+        let header_len = "P5\n1088 64\n65535\n".len();
+        let first_row: Vec<_> = pgm[header_len..][..1088 * 2]
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        assert!(first_row.iter().any(|v| v % 257 != 0));
+        assert!(first_row.is_sorted() || first_row.iter().rev().is_sorted());
+    }
+
+    #[test]
+    fn rgb_16_bit_ppm() {
+        let image = decode("basic.jxl", OutputDataType::U16);
+        let mut ppm = vec![];
+        to_ppm_16bit(&image, &mut ppm).unwrap();
+        let float = decode("basic.jxl", OutputDataType::F32);
+        let (width, height) = image.size;
+        check_16_bit(&ppm, &float, &format!("P6\n{width} {height}\n65535\n"));
+    }
+
+    #[test]
+    fn data_type_selects_the_sample_size() {
+        for (data_type, maxval) in [(OutputDataType::U8, 255), (OutputDataType::U16, 65535)] {
+            let image = decode("basic.jxl", data_type);
+            let mut ppm = vec![];
+            to_ppm(&image, &mut ppm).unwrap();
+            let (width, height) = image.size;
+            let header = format!("P6\n{width} {height}\n{maxval}\n");
+            assert!(ppm.starts_with(header.as_bytes()));
+            let shape = OutputShape::of_output(&image);
+            assert_eq!(ppm.len() as u64, file_size(&shape, "P6"));
+        }
     }
-    Ok(())
 }
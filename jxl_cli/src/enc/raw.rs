@@ -0,0 +1,315 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::Result;
+use jxl::api::{Endianness, JxlColorType};
+use std::io::{Seek, SeekFrom, Write};
+
+use super::writer::{FrameWriter, Sink};
+use crate::dec::{DecodeOutput, FrameLease, ImageFrame, OutputDataType, OutputShape};
+use crate::report::Reporter;
+
+pub const MAGIC: &[u8; 8] = b"JXLPLANE";
+
+/// Size of the [`RawHeader`] at the start of the file.
+pub const HEADER_SIZE: usize = 24;
+
+/// Header of a raw planar dump: the magic followed by the dimensions as little-endian u32s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawHeader {
+    pub width: u32,
+    pub height: u32,
+    pub num_channels: u32,
+    pub num_frames: u32,
+}
+
+impl RawHeader {
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(MAGIC);
+        for (i, value) in [self.width, self.height, self.num_channels, self.num_frames]
+            .into_iter()
+            .enumerate()
+        {
+            bytes[8 + 4 * i..][..4].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+fn num_channels(color_samples: usize, extra_channels: usize, include_extra: bool) -> usize {
+    color_samples + if include_extra { extra_channels } else { 0 }
+}
+
+/// Writes the samples of all frames as planes of little-endian f32s, after a [`RawHeader`]:
+/// the color channels (including alpha) of the first frame one after the other, then, if
+/// `include_extra` is set, its extra channels, and so on for every frame. The samples are written
+/// as they were decoded, without any conversion or clamping.
+pub fn to_raw<Writer: Write>(
+    img: &DecodeOutput,
+    writer: &mut Writer,
+    include_extra: bool,
+) -> Result<()> {
+    let mut raw = RawWriter::new(include_extra);
+    raw.write_header(img, &img.frames[0], img.frames.len(), writer)?;
+    for frame in &img.frames {
+        raw.write_frame(img, frame, writer)?;
+    }
+    raw.report_layout();
+    Ok(())
+}
+
+/// Writes the same file as [`to_raw`] frame by frame, to a file that can be seeked: the number of
+/// frames in the header is only written after the last one.
+pub struct RawWriter {
+    include_extra: bool,
+    header: Option<RawHeader>,
+    color_type: Option<JxlColorType>,
+    extra_channels: usize,
+    row_bytes: Vec<u8>,
+}
+
+impl RawWriter {
+    pub fn new(include_extra: bool) -> Self {
+        Self {
+            include_extra,
+            header: None,
+            color_type: None,
+            extra_channels: 0,
+            row_bytes: vec![],
+        }
+    }
+
+    fn write_header<Writer: Write>(
+        &mut self,
+        img: &DecodeOutput,
+        frame: &ImageFrame,
+        num_frames: usize,
+        writer: &mut Writer,
+    ) -> Result<()> {
+        assert_eq!(img.data_type, OutputDataType::F32);
+        let (width, height) = img.size;
+        let color_samples = frame.color_type.samples_per_pixel();
+        self.color_type = Some(frame.color_type);
+        self.extra_channels = frame.channels.len() - 1;
+        if self.extra_channels > 0 && !self.include_extra {
+            Reporter::get().warn(format_args!(
+                "Ignoring {} extra channels, use the planes format to include them.",
+                self.extra_channels
+            ));
+        }
+        let channels = num_channels(color_samples, self.extra_channels, self.include_extra);
+        let header = RawHeader {
+            width: width as u32,
+            height: height as u32,
+            num_channels: channels as u32,
+            num_frames: num_frames as u32,
+        };
+        writer.write_all(&header.to_bytes())?;
+        self.header = Some(header);
+        Ok(())
+    }
+
+    fn report_layout(&self) {
+        let header = self.header.unwrap();
+        Reporter::get().note(format_args!(
+            "Raw layout: {HEADER_SIZE}-byte header, then {} frames of {} planes \
+             ({:?} color{}) of {}x{} little-endian f32 samples, row by row",
+            header.num_frames,
+            header.num_channels,
+            self.color_type.unwrap(),
+            if self.include_extra {
+                format!(" and {} extra channels", self.extra_channels)
+            } else {
+                String::new()
+            },
+            header.width,
+            header.height,
+        ));
+    }
+
+    fn write_frame<Writer: Write>(
+        &mut self,
+        img: &DecodeOutput,
+        frame: &ImageFrame,
+        writer: &mut Writer,
+    ) -> Result<()> {
+        let (width, height) = img.size;
+        let color_samples = frame.color_type.samples_per_pixel();
+        let to_le = |sample: &[u8]| {
+            let sample: [u8; 4] = sample.try_into().unwrap();
+            match img.endianness {
+                Endianness::LittleEndian => sample,
+                Endianness::BigEndian => f32::from_be_bytes(sample).to_le_bytes(),
+            }
+        };
+        let row_bytes = &mut self.row_bytes;
+        for c in 0..color_samples {
+            for y in 0..height {
+                let row = frame.channels[0].row(y);
+                row_bytes.clear();
+                for x in 0..width {
+                    let i = (x * color_samples + c) * 4;
+                    row_bytes.extend_from_slice(&to_le(&row[i..i + 4]));
+                }
+                writer.write_all(row_bytes)?;
+            }
+        }
+        if self.include_extra {
+            for channel in &frame.channels[1..] {
+                for y in 0..height {
+                    row_bytes.clear();
+                    for sample in channel.row(y)[..width * 4].chunks_exact(4) {
+                        row_bytes.extend_from_slice(&to_le(sample));
+                    }
+                    writer.write_all(row_bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FrameWriter for RawWriter {
+    fn push_frame(
+        &mut self,
+        image: &DecodeOutput,
+        frame: FrameLease,
+        sink: &mut Sink,
+    ) -> Result<()> {
+        if self.header.is_none() {
+            self.write_header(image, &frame, 0, sink)?;
+        }
+        self.write_frame(image, &frame, sink)?;
+        self.header.as_mut().unwrap().num_frames += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self, _image: &DecodeOutput, sink: &mut Sink) -> Result<()> {
+        let end = sink.stream_position()?;
+        sink.rewind()?;
+        sink.write_all(&self.header.unwrap().to_bytes())?;
+        sink.seek(SeekFrom::Start(end))?;
+        self.report_layout();
+        Ok(())
+    }
+}
+
+/// Size of the file [`to_raw`] writes for frames of the given shape.
+pub fn file_size(shape: &OutputShape, include_extra: bool) -> u64 {
+    let channels = num_channels(shape.color_samples, shape.extra_channels, include_extra);
+    let plane = shape.size.0 as u64 * shape.size.1 as u64 * 4;
+    HEADER_SIZE as u64 + shape.num_frames as u64 * channels as u64 * plane
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::ImageFrame;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::JxlColorType;
+    use jxl::image::OwnedRawImage;
+
+    /// Value of sample `c` of pixel (x, y) in frame `f`, negative and above 1 to check that
+    /// samples are not clamped.
+    fn sample(f: usize, c: usize, x: usize, y: usize) -> f32 {
+        (f * 1000 + c * 100 + y * 10 + x) as f32 * 0.5 - 2.0
+    }
+
+    fn f32_plane(
+        size: (usize, usize),
+        samples: usize,
+        value: impl Fn(usize, usize, usize) -> f32,
+    ) -> OwnedRawImage {
+        let mut image = OwnedRawImage::new((size.0 * samples * 4, size.1)).unwrap();
+        for y in 0..size.1 {
+            let row = image.row_mut(y);
+            for x in 0..size.0 {
+                for c in 0..samples {
+                    row[(x * samples + c) * 4..][..4]
+                        .copy_from_slice(&value(c, x, y).to_ne_bytes());
+                }
+            }
+        }
+        image
+    }
+
+    /// Two frames of 3x2 RGB pixels with two extra channels, as f32 samples.
+    fn test_image() -> DecodeOutput {
+        let size = (3, 2);
+        let mut image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, size);
+        image.data_type = OutputDataType::F32;
+        image.endianness = Endianness::native();
+        image.frames.clear();
+        for f in 0..2 {
+            let mut frame: ImageFrame =
+                make_test_image(JxlColorType::Rgb, OutputDataType::U8, size)
+                    .frames
+                    .remove(0);
+            frame.channels = vec![f32_plane(size, 3, |c, x, y| sample(f, c, x, y))];
+            for e in 0..2 {
+                frame
+                    .channels
+                    .push(f32_plane(size, 1, |_, x, y| sample(f, 3 + e, x, y)));
+            }
+            image.frames.push(frame);
+        }
+        image
+    }
+
+    fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|s| f32::from_le_bytes(s.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn byte_layout() {
+        let image = test_image();
+        for (include_extra, channels) in [(false, 3), (true, 5)] {
+            let mut raw = vec![];
+            to_raw(&image, &mut raw, include_extra).unwrap();
+            assert_eq!(raw.len(), HEADER_SIZE + 2 * channels * 3 * 2 * 4);
+
+            assert_eq!(&raw[..8], MAGIC);
+            let header: Vec<u32> = raw[8..HEADER_SIZE]
+                .chunks_exact(4)
+                .map(|v| u32::from_le_bytes(v.try_into().unwrap()))
+                .collect();
+            assert_eq!(header, [3, 2, channels as u32, 2]);
+
+            let mut expected = vec![];
+            for f in 0..2 {
+                for c in 0..channels {
+                    for y in 0..2 {
+                        for x in 0..3 {
+                            expected.push(sample(f, c, x, y));
+                        }
+                    }
+                }
+            }
+            assert_eq!(read_f32s(&raw[HEADER_SIZE..]), expected);
+        }
+    }
+
+    #[test]
+    fn file_size_is_exact() {
+        let image = test_image();
+        let shape = OutputShape {
+            size: image.size,
+            num_frames: 2,
+            color_samples: 3,
+            extra_channels: 2,
+            data_type: OutputDataType::F32,
+            layout: image.layout,
+        };
+        for include_extra in [false, true] {
+            let mut raw = vec![];
+            to_raw(&image, &mut raw, include_extra).unwrap();
+            assert_eq!(raw.len() as u64, file_size(&shape, include_extra));
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::{
+    fmt::Display,
+    io::{self, IoSlice, Seek, SeekFrom, Write},
+    time::{Duration, Instant},
+};
+
+/// Default buffer size of [`BufferedSink`]. Encoders write row by row, so with the 8KiB of a
+/// `BufWriter` most rows of large images turn into at least one system call.
+pub const SINK_BUFFER_SIZE: usize = 256 << 10;
+
+/// Error of a [`BufferedSink`], with the number of bytes that reached the inner writer before it.
+#[derive(Debug)]
+pub struct SinkError {
+    pub bytes_written: u64,
+    pub source: io::Error,
+}
+
+impl Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} after writing {} bytes",
+            self.source, self.bytes_written
+        )
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Buffered writer shared by all encoders.
+///
+/// Unlike `BufWriter`, writes that do not fit in the buffer are passed to the inner writer
+/// together with the buffered bytes, in a single vectored write, and errors are [`SinkError`]s
+/// that say how much of the output was written. Buffered bytes are discarded when the sink is
+/// dropped: [`BufferedSink::finish`] must be called to write them, so that errors cannot be
+/// missed.
+pub struct BufferedSink<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    bytes_written: u64,
+    write_time: Duration,
+}
+
+impl<W: Write> BufferedSink<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_capacity(SINK_BUFFER_SIZE, inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> Self {
+        Self {
+            inner,
+            buf: Vec::with_capacity(capacity),
+            bytes_written: 0,
+            write_time: Duration::ZERO,
+        }
+    }
+
+    /// Number of bytes that reached the inner writer so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Time spent in the inner writer so far, as opposed to producing the bytes.
+    pub fn write_time(&self) -> Duration {
+        self.write_time
+    }
+
+    /// Runs `f` on the inner writer, and counts the time it takes as write time.
+    fn timed<T>(&mut self, f: impl FnOnce(&mut W) -> T) -> T {
+        let start = Instant::now();
+        let result = f(&mut self.inner);
+        self.write_time += start.elapsed();
+        result
+    }
+
+    /// Writes the buffered bytes, flushes the inner writer and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.inner)
+    }
+
+    fn error(&self, source: io::Error) -> io::Error {
+        io::Error::new(
+            source.kind(),
+            SinkError {
+                bytes_written: self.bytes_written,
+                source,
+            },
+        )
+    }
+
+    /// Writes the start of `bufs` to the inner writer, retrying on interruptions.
+    fn write_inner(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        loop {
+            match self.timed(|inner| inner.write_vectored(bufs)) {
+                Ok(0) => return Err(self.error(io::ErrorKind::WriteZero.into())),
+                Ok(n) => {
+                    self.bytes_written += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(self.error(e)),
+            }
+        }
+    }
+
+    /// Writes all the buffered bytes, followed by as much of `data` as the inner writer accepts
+    /// in the same call. Returns the number of bytes of `data` that were written.
+    fn write_buffered_and(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        let result = loop {
+            if start == self.buf.len() {
+                break Ok(0);
+            }
+            let buf = std::mem::take(&mut self.buf);
+            let n = self.write_inner(&[IoSlice::new(&buf[start..]), IoSlice::new(data)]);
+            self.buf = buf;
+            match n {
+                Ok(n) if start + n > self.buf.len() => break Ok(start + n - self.buf.len()),
+                Ok(n) => start += n,
+                Err(e) => break Err(e),
+            }
+        };
+        self.buf.drain(..start.min(self.buf.len()));
+        if result.is_ok() {
+            self.buf.clear();
+        }
+        result
+    }
+}
+
+impl<W: Write> Write for BufferedSink<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() <= self.buf.capacity() {
+            self.buf.extend_from_slice(data);
+            return Ok(data.len());
+        }
+        let written = self.write_buffered_and(data)?;
+        let rest = &data[written..];
+        if rest.is_empty() {
+            Ok(data.len())
+        } else if rest.len() < self.buf.capacity() {
+            self.buf.extend_from_slice(rest);
+            Ok(data.len())
+        } else if written > 0 {
+            Ok(written)
+        } else {
+            self.write_inner(&[IoSlice::new(rest)])
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffered_and(&[])?;
+        self.timed(|inner| inner.flush()).map_err(|e| self.error(e))
+    }
+}
+
+impl<W: Write + Seek> Seek for BufferedSink<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.write_buffered_and(&[])?;
+        self.timed(|inner| inner.seek(pos))
+            .map_err(|e| self.error(e))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Accepts at most `max_write` bytes per call, and fails once `capacity` bytes were written.
+    pub(crate) struct FailingWriter {
+        pub data: Vec<u8>,
+        pub capacity: usize,
+        pub max_write: usize,
+        pub num_writes: usize,
+    }
+
+    impl FailingWriter {
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                data: vec![],
+                capacity,
+                max_write: usize::MAX,
+                num_writes: 0,
+            }
+        }
+    }
+
+    impl Write for FailingWriter {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.num_writes += 1;
+            let n = data
+                .len()
+                .min(self.max_write)
+                .min(self.capacity - self.data.len());
+            if n == 0 && !data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.data.extend_from_slice(&data[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn writes_everything_in_order() {
+        let data = pattern(10000);
+        for max_write in [1, 7, 100, usize::MAX] {
+            let mut writer = FailingWriter::new(usize::MAX);
+            writer.max_write = max_write;
+            let mut sink = BufferedSink::with_capacity(64, writer);
+            // Small writes, writes larger than the buffer and writes that just overflow it.
+            for chunk in data.chunks(3).take(100) {
+                sink.write_all(chunk).unwrap();
+            }
+            sink.write_all(&data[300..1000]).unwrap();
+            sink.write_all(&data[1000..1064]).unwrap();
+            for chunk in data[1064..].chunks(63) {
+                sink.write_all(chunk).unwrap();
+            }
+            assert_eq!(sink.finish().unwrap().data, data, "{max_write}");
+        }
+    }
+
+    #[test]
+    fn coalesces_small_writes() {
+        let mut sink = BufferedSink::new(FailingWriter::new(usize::MAX));
+        for row in pattern(1 << 20).chunks(1000) {
+            sink.write_all(row).unwrap();
+        }
+        let writer = sink.finish().unwrap();
+        assert_eq!(writer.data.len(), 1 << 20);
+        assert!(writer.num_writes <= 5, "{}", writer.num_writes);
+    }
+
+    #[test]
+    fn errors_report_bytes_written() {
+        let mut sink = BufferedSink::with_capacity(64, FailingWriter::new(100));
+        let err = pattern(1000)
+            .chunks(10)
+            .try_for_each(|chunk| sink.write_all(chunk))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        let sink_err = err.get_ref().unwrap().downcast_ref::<SinkError>().unwrap();
+        assert_eq!(sink_err.bytes_written, 100);
+        assert_eq!(sink.bytes_written(), 100);
+        assert!(
+            err.to_string().ends_with("after writing 100 bytes"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn dropping_discards_buffered_bytes() {
+        let mut writer = FailingWriter::new(usize::MAX);
+        let mut sink = BufferedSink::new(&mut writer);
+        sink.write_all(b"unfinished").unwrap();
+        drop(sink);
+        assert!(writer.data.is_empty());
+    }
+}
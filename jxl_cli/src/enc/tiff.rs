@@ -0,0 +1,530 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::{Result, bail};
+use jxl::api::{Endianness, JxlColorType};
+use std::io::Write;
+
+use crate::dec::{DecodeOutput, OutputDataType, OutputShape};
+use crate::report::Reporter;
+
+/// Strips hold up to this many bytes, and at least one row.
+const STRIP_BYTES: usize = 1 << 16;
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PAGE_NUMBER: u16 = 297;
+const EXTRA_SAMPLES: u16 = 338;
+const SAMPLE_FORMAT: u16 = 339;
+const ICC_PROFILE: u16 = 34675;
+
+enum Value<'a> {
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    Undefined(&'a [u8]),
+}
+
+impl Value<'_> {
+    fn field_type(&self) -> u16 {
+        match self {
+            Value::Short(_) => 3,
+            Value::Long(_) => 4,
+            Value::Undefined(_) => 7,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            Value::Short(v) => v.len(),
+            Value::Long(v) => v.len(),
+            Value::Undefined(v) => v.len(),
+        }
+    }
+
+    fn to_bytes(&self, endianness: Endianness) -> Vec<u8> {
+        let big = endianness == Endianness::BigEndian;
+        match self {
+            Value::Short(v) => v
+                .iter()
+                .flat_map(|x| {
+                    if big {
+                        x.to_be_bytes()
+                    } else {
+                        x.to_le_bytes()
+                    }
+                })
+                .collect(),
+            Value::Long(v) => v
+                .iter()
+                .flat_map(|x| {
+                    if big {
+                        x.to_be_bytes()
+                    } else {
+                        x.to_le_bytes()
+                    }
+                })
+                .collect(),
+            Value::Undefined(v) => v.to_vec(),
+        }
+    }
+
+    /// Size of the value when it does not fit in its entry, padded to a word boundary.
+    fn external_len(&self) -> usize {
+        let len = match self {
+            Value::Short(v) => v.len() * 2,
+            Value::Long(v) => v.len() * 4,
+            Value::Undefined(v) => v.len(),
+        };
+        if len > 4 { len.next_multiple_of(2) } else { 0 }
+    }
+}
+
+/// Size of an IFD with the given entries, followed by the values that do not fit in them.
+fn ifd_len(entries: &[(u16, Value)]) -> usize {
+    let values: usize = entries.iter().map(|(_, v)| v.external_len()).sum();
+    2 + entries.len() * 12 + 4 + values
+}
+
+/// Serializes an IFD that starts at `offset` in the file, followed by the values that do not fit
+/// in its entries. `entries` must be sorted by tag.
+fn write_ifd(
+    entries: &[(u16, Value)],
+    offset: u32,
+    next_ifd: u32,
+    endianness: Endianness,
+) -> Vec<u8> {
+    let big = endianness == Endianness::BigEndian;
+    let u16_bytes = |x: u16| {
+        if big {
+            x.to_be_bytes()
+        } else {
+            x.to_le_bytes()
+        }
+    };
+    let u32_bytes = |x: u32| {
+        if big {
+            x.to_be_bytes()
+        } else {
+            x.to_le_bytes()
+        }
+    };
+    let mut ifd = Vec::with_capacity(ifd_len(entries));
+    let mut values = vec![];
+    let mut value_offset = offset + (2 + entries.len() * 12 + 4) as u32;
+    ifd.extend_from_slice(&u16_bytes(entries.len() as u16));
+    for (tag, value) in entries {
+        ifd.extend_from_slice(&u16_bytes(*tag));
+        ifd.extend_from_slice(&u16_bytes(value.field_type()));
+        ifd.extend_from_slice(&u32_bytes(value.count() as u32));
+        let mut bytes = value.to_bytes(endianness);
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            ifd.extend_from_slice(&bytes);
+        } else {
+            ifd.extend_from_slice(&u32_bytes(value_offset));
+            bytes.resize(value.external_len(), 0);
+            value_offset += bytes.len() as u32;
+            values.extend_from_slice(&bytes);
+        }
+    }
+    ifd.extend_from_slice(&u32_bytes(next_ifd));
+    ifd.extend_from_slice(&values);
+    ifd
+}
+
+/// Bits per sample and the value of the SampleFormat tag for the data type.
+fn sample_format(data_type: OutputDataType) -> Result<(u16, u16)> {
+    match data_type {
+        OutputDataType::U8 => Ok((8, 1)),
+        OutputDataType::U16 => Ok((16, 1)),
+        OutputDataType::F32 => Ok((32, 3)),
+        data_type => {
+            bail!("TIFF output stores 8, 16-bit and 32-bit float samples, not {data_type:?}")
+        }
+    }
+}
+
+/// Entries of the IFD of one page, with the pixels of its strips starting at `data_offset`.
+fn ifd_entries(
+    size: (usize, usize),
+    color_type: JxlColorType,
+    data_type: OutputDataType,
+    page: (usize, usize),
+    icc: &[u8],
+    data_offset: u32,
+) -> Result<Vec<(u16, Value<'_>)>> {
+    let (bits, format) = sample_format(data_type)?;
+    let samples_per_pixel = color_type.samples_per_pixel();
+    let row_len = size.0 * samples_per_pixel * bits as usize / 8;
+    let rows_per_strip = (STRIP_BYTES / row_len.max(1)).clamp(1, size.1.max(1));
+    let num_strips = size.1.div_ceil(rows_per_strip);
+    let strip_rows = |i: usize| rows_per_strip.min(size.1 - i * rows_per_strip);
+    let mut entries = vec![
+        (IMAGE_WIDTH, Value::Long(vec![size.0 as u32])),
+        (IMAGE_LENGTH, Value::Long(vec![size.1 as u32])),
+        (BITS_PER_SAMPLE, Value::Short(vec![bits; samples_per_pixel])),
+        // No compression.
+        (COMPRESSION, Value::Short(vec![1])),
+        (
+            PHOTOMETRIC_INTERPRETATION,
+            // BlackIsZero or RGB.
+            Value::Short(vec![if color_type.is_grayscale() { 1 } else { 2 }]),
+        ),
+        (
+            STRIP_OFFSETS,
+            Value::Long(
+                (0..num_strips)
+                    .map(|i| data_offset + (i * rows_per_strip * row_len) as u32)
+                    .collect(),
+            ),
+        ),
+        (
+            SAMPLES_PER_PIXEL,
+            Value::Short(vec![samples_per_pixel as u16]),
+        ),
+        (ROWS_PER_STRIP, Value::Long(vec![rows_per_strip as u32])),
+        (
+            STRIP_BYTE_COUNTS,
+            Value::Long(
+                (0..num_strips)
+                    .map(|i| (strip_rows(i) * row_len) as u32)
+                    .collect(),
+            ),
+        ),
+        // Samples of a pixel are interleaved.
+        (PLANAR_CONFIGURATION, Value::Short(vec![1])),
+    ];
+    if page.1 > 1 {
+        entries.push((
+            PAGE_NUMBER,
+            Value::Short(vec![page.0 as u16, page.1 as u16]),
+        ));
+    }
+    if color_type.has_alpha() {
+        // Unassociated alpha, the decoder does not premultiply the color samples.
+        entries.push((EXTRA_SAMPLES, Value::Short(vec![2])));
+    }
+    entries.push((SAMPLE_FORMAT, Value::Short(vec![format; samples_per_pixel])));
+    if !icc.is_empty() {
+        entries.push((ICC_PROFILE, Value::Undefined(icc)));
+    }
+    Ok(entries)
+}
+
+/// Writes a baseline TIFF with uncompressed strips of interleaved samples, the alpha channel, if
+/// any, being an unassociated extra sample, and the output color profile as an ICC profile.
+/// Every frame is written as its own page, that is, its own IFD. Samples are stored in the byte
+/// order of the output, which is also the byte order of the file.
+pub fn to_tiff<Writer: Write>(img: &DecodeOutput, writer: &mut Writer) -> Result<()> {
+    let frame = &img.frames[0];
+    let color_type = frame.color_type;
+    if !matches!(
+        color_type,
+        JxlColorType::Grayscale
+            | JxlColorType::GrayscaleAlpha
+            | JxlColorType::Rgb
+            | JxlColorType::Rgba
+    ) {
+        bail!("TIFF does not support {color_type:?} samples");
+    }
+    if frame.channels.len() > 1 {
+        Reporter::get().warn(format_args!("Ignoring extra channels."));
+    }
+    let icc = img.output_profile.as_icc();
+    let endianness = img.endianness;
+    let (bits, _) = sample_format(img.data_type)?;
+    let row_len = img.size.0 * color_type.samples_per_pixel() * bits as usize / 8;
+    let num_pages = img.frames.len();
+    let page_len = |entries: &[(u16, Value)]| {
+        let pixels = row_len * img.size.1;
+        (ifd_len(entries) + pixels.next_multiple_of(2)) as u64
+    };
+    // Pages only differ in the values of their entries, not in their size.
+    let layout = ifd_entries(img.size, color_type, img.data_type, (0, num_pages), &icc, 0)?;
+    let file_len = 8 + page_len(&layout) * num_pages as u64;
+    if file_len > u32::MAX as u64 {
+        bail!("TIFF files are limited to 4 GiB, but the image needs {file_len} bytes");
+    }
+
+    let mut offset = 8u32;
+    let header: &[u8] = match endianness {
+        Endianness::LittleEndian => b"II\x2a\x00",
+        Endianness::BigEndian => b"MM\x00\x2a",
+    };
+    writer.write_all(header)?;
+    writer.write_all(&match endianness {
+        Endianness::LittleEndian => offset.to_le_bytes(),
+        Endianness::BigEndian => offset.to_be_bytes(),
+    })?;
+    for (page, frame) in img.frames.iter().enumerate() {
+        let entries = ifd_entries(
+            img.size,
+            color_type,
+            img.data_type,
+            (page, num_pages),
+            &icc,
+            offset + ifd_len(&layout) as u32,
+        )?;
+        let next_offset = offset + page_len(&entries) as u32;
+        let next_ifd = if page + 1 < num_pages { next_offset } else { 0 };
+        writer.write_all(&write_ifd(&entries, offset, next_ifd, endianness))?;
+        for y in 0..img.size.1 {
+            writer.write_all(&frame.channels[0].row(y)[..row_len])?;
+        }
+        if (row_len * img.size.1) % 2 == 1 {
+            writer.write_all(&[0])?;
+        }
+        offset = next_offset;
+    }
+    Ok(())
+}
+
+/// Size of the file [`to_tiff`] writes for frames of the given shape, not counting the ICC
+/// profile and its entry in each IFD.
+pub fn file_size(shape: &OutputShape) -> u64 {
+    let color_type = match shape.color_samples {
+        1 => JxlColorType::Grayscale,
+        2 => JxlColorType::GrayscaleAlpha,
+        3 => JxlColorType::Rgb,
+        _ => JxlColorType::Rgba,
+    };
+    let Ok(entries) = ifd_entries(
+        shape.size,
+        color_type,
+        shape.data_type,
+        (0, shape.num_frames),
+        &[],
+        0,
+    ) else {
+        return shape.sample_bytes();
+    };
+    let pixels = shape.size.0 as u64
+        * shape.size.1 as u64
+        * shape.color_samples as u64
+        * (shape.data_type.bits_per_sample() / 8) as u64;
+    8 + (ifd_len(&entries) as u64 + pixels.next_multiple_of(2)) * shape.num_frames as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::decode_frames;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::JxlDecoderOptions;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    /// The entries of an IFD, as their type, count and the position of their value field.
+    type Ifd = BTreeMap<u16, (u16, u32, usize)>;
+
+    fn u16_at(tiff: &[u8], offset: usize) -> u16 {
+        let bytes = [tiff[offset], tiff[offset + 1]];
+        if tiff.starts_with(b"II") {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32_at(tiff: &[u8], offset: usize) -> u32 {
+        let bytes = tiff[offset..][..4].try_into().unwrap();
+        if tiff.starts_with(b"II") {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    /// Parses the chain of IFDs of `tiff`.
+    fn ifds(tiff: &[u8]) -> Vec<Ifd> {
+        assert!(tiff.starts_with(b"II\x2a\x00") || tiff.starts_with(b"MM\x00\x2a"));
+        let mut ifds = vec![];
+        let mut offset = u32_at(tiff, 4) as usize;
+        while offset != 0 {
+            assert_eq!(offset % 2, 0);
+            let count = u16_at(tiff, offset) as usize;
+            let mut ifd = Ifd::new();
+            for i in 0..count {
+                let entry = offset + 2 + i * 12;
+                let field = (u16_at(tiff, entry + 2), u32_at(tiff, entry + 4), entry + 8);
+                assert!(ifd.insert(u16_at(tiff, entry), field).is_none());
+            }
+            assert!(ifd.keys().is_sorted());
+            ifds.push(ifd);
+            offset = u32_at(tiff, offset + 2 + count * 12) as usize;
+        }
+        ifds
+    }
+
+    /// The raw bytes of the value of an entry.
+    fn bytes<'a>(tiff: &'a [u8], ifd: &Ifd, tag: u16) -> &'a [u8] {
+        let (field_type, count, position) = ifd[&tag];
+        let len = count as usize * [0, 1, 1, 2, 4, 8, 1, 1][field_type as usize];
+        let start = if len <= 4 {
+            position
+        } else {
+            u32_at(tiff, position) as usize
+        };
+        &tiff[start..][..len]
+    }
+
+    /// The values of a SHORT or LONG entry.
+    fn values(tiff: &[u8], ifd: &Ifd, tag: u16) -> Vec<u32> {
+        let (field_type, count, _) = ifd[&tag];
+        let start = bytes(tiff, ifd, tag).as_ptr() as usize - tiff.as_ptr() as usize;
+        (0..count as usize)
+            .map(|i| match field_type {
+                3 => u16_at(tiff, start + 2 * i) as u32,
+                4 => u32_at(tiff, start + 4 * i),
+                _ => panic!("tag {tag} has type {field_type}"),
+            })
+            .collect()
+    }
+
+    /// The samples of all strips of a page.
+    fn pixels(tiff: &[u8], ifd: &Ifd) -> Vec<u8> {
+        let offsets = values(tiff, ifd, STRIP_OFFSETS);
+        let counts = values(tiff, ifd, STRIP_BYTE_COUNTS);
+        offsets
+            .iter()
+            .zip(&counts)
+            .flat_map(|(&o, &c)| tiff[o as usize..][..c as usize].to_vec())
+            .collect()
+    }
+
+    /// Bytes that the ICC profile adds to a page, which [`file_size`] does not know about.
+    fn icc_len(ifd: &Ifd) -> u64 {
+        12 + (ifd[&ICC_PROFILE].1 as u64).next_multiple_of(2)
+    }
+
+    /// The samples of a frame, without row padding.
+    fn frame_samples(image: &DecodeOutput, frame: usize) -> Vec<u8> {
+        let frame = &image.frames[frame];
+        let row_len =
+            image.size.0 * frame.color_type.samples_per_pixel() * image.data_type.bits_per_sample()
+                / 8;
+        (0..image.size.1)
+            .flat_map(|y| frame.channels[0].row(y)[..row_len].to_vec())
+            .collect()
+    }
+
+    fn decode(file: &str, data_type: OutputDataType) -> DecodeOutput {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join(file)).unwrap();
+        decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            Some(data_type),
+            &[OutputDataType::U8, OutputDataType::U16, OutputDataType::F32],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn float_rgb_with_icc() {
+        let image = decode("lossy_with_icc.jxl", OutputDataType::F32);
+        let mut tiff = vec![];
+        to_tiff(&image, &mut tiff).unwrap();
+        let ifds = ifds(&tiff);
+        assert_eq!(ifds.len(), 1);
+        let ifd = &ifds[0];
+        let (width, height) = image.size;
+        assert_eq!(values(&tiff, ifd, IMAGE_WIDTH), [width as u32]);
+        assert_eq!(values(&tiff, ifd, IMAGE_LENGTH), [height as u32]);
+        assert_eq!(values(&tiff, ifd, BITS_PER_SAMPLE), [32; 3]);
+        assert_eq!(values(&tiff, ifd, SAMPLE_FORMAT), [3; 3]);
+        assert_eq!(values(&tiff, ifd, PHOTOMETRIC_INTERPRETATION), [2]);
+        assert!(!ifd.contains_key(&EXTRA_SAMPLES));
+        assert!(!ifd.contains_key(&PAGE_NUMBER));
+        assert_eq!(
+            bytes(&tiff, ifd, ICC_PROFILE),
+            image.output_profile.as_icc().as_slice()
+        );
+        assert_eq!(pixels(&tiff, ifd), frame_samples(&image, 0));
+        assert_eq!(
+            tiff.len() as u64,
+            file_size(&OutputShape::of_output(&image)) + icc_len(ifd)
+        );
+    }
+
+    #[test]
+    fn gray_alpha_16_bit() {
+        let image = decode("gray_alpha_lossless.jxl", OutputDataType::U16);
+        assert_eq!(image.frames[0].color_type, JxlColorType::GrayscaleAlpha);
+        let mut tiff = vec![];
+        to_tiff(&image, &mut tiff).unwrap();
+        let ifd = &ifds(&tiff)[0];
+        assert_eq!(values(&tiff, ifd, BITS_PER_SAMPLE), [16; 2]);
+        assert_eq!(values(&tiff, ifd, SAMPLE_FORMAT), [1; 2]);
+        assert_eq!(values(&tiff, ifd, PHOTOMETRIC_INTERPRETATION), [1]);
+        assert_eq!(values(&tiff, ifd, EXTRA_SAMPLES), [2]);
+        assert_eq!(pixels(&tiff, ifd), frame_samples(&image, 0));
+    }
+
+    #[test]
+    fn every_frame_is_a_page() {
+        // An odd number of bytes per frame needs padding to keep the next IFD word-aligned.
+        let mut image = make_test_image(JxlColorType::Rgba, OutputDataType::U8, (3, 5));
+        for page in 1..3 {
+            let mut next = make_test_image(JxlColorType::Rgba, OutputDataType::U8, (3, 5));
+            next.frames[0].channels[0].row_mut(4)[page] ^= 0xff;
+            image.frames.push(next.frames.pop().unwrap());
+        }
+        let mut tiff = vec![];
+        to_tiff(&image, &mut tiff).unwrap();
+        let ifds = ifds(&tiff);
+        assert_eq!(ifds.len(), 3);
+        for (page, ifd) in ifds.iter().enumerate() {
+            assert_eq!(values(&tiff, ifd, PAGE_NUMBER), [page as u32, 3]);
+            assert_eq!(values(&tiff, ifd, BITS_PER_SAMPLE), [8; 4]);
+            assert_eq!(pixels(&tiff, ifd), frame_samples(&image, page));
+        }
+        assert_ne!(pixels(&tiff, &ifds[1]), pixels(&tiff, &ifds[2]));
+        assert_eq!(
+            tiff.len() as u64,
+            file_size(&OutputShape::of_output(&image)) + 3 * icc_len(&ifds[0])
+        );
+    }
+
+    #[test]
+    fn rows_are_split_into_strips() {
+        let image = make_test_image(JxlColorType::Rgb, OutputDataType::U16, (200, 100));
+        let mut tiff = vec![];
+        to_tiff(&image, &mut tiff).unwrap();
+        let ifd = &ifds(&tiff)[0];
+        let rows_per_strip = STRIP_BYTES / 1200;
+        assert_eq!(values(&tiff, ifd, ROWS_PER_STRIP), [rows_per_strip as u32]);
+        assert_eq!(
+            values(&tiff, ifd, STRIP_BYTE_COUNTS),
+            [rows_per_strip * 1200, (100 - rows_per_strip) * 1200].map(|c| c as u32)
+        );
+        assert_eq!(pixels(&tiff, ifd), frame_samples(&image, 0));
+    }
+
+    #[test]
+    fn half_float_samples_are_rejected() {
+        let mut image = decode("basic.jxl", OutputDataType::U8);
+        image.data_type = OutputDataType::F16;
+        let err = to_tiff(&image, &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("32-bit float"), "{err}");
+    }
+}
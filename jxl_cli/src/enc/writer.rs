@@ -0,0 +1,225 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Writing of outputs frame by frame, while the image is decoded.
+//!
+//! Formats whose files can be written one frame at a time implement [`FrameWriter`] directly, so
+//! that the frames of an animation can be dropped as soon as they are written. The others are
+//! written by [`FirstFrame`], which only needs the first frame, or [`AllFrames`], which keeps the
+//! frames until the last one is decoded. Outputs written one file per frame skip the writers and
+//! write each frame with the encoder of the format.
+
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use super::EncodeFn;
+use super::file::{OutputFile, OutputStream, OutputWriter};
+use super::frame_files::FrameFiles;
+use super::sink::BufferedSink;
+use crate::dec::{DecodeOutput, FrameLease, ImageFrame};
+use crate::report::Reporter;
+
+pub type Sink = BufferedSink<OutputStream>;
+
+/// Writes the frames of an image to a file as they are decoded.
+///
+/// Every call gets the output the frames belong to, without any frames, for the size, data type
+/// and color profiles of the image.
+pub trait FrameWriter {
+    /// Called before the first frame.
+    fn begin(&mut self, _image: &DecodeOutput, _sink: &mut Sink) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes the next frame, or keeps it if the file needs the following frames first.
+    fn push_frame(
+        &mut self,
+        image: &DecodeOutput,
+        frame: FrameLease,
+        sink: &mut Sink,
+    ) -> Result<()>;
+
+    /// Completes the file after the last frame.
+    fn finish(&mut self, image: &DecodeOutput, sink: &mut Sink) -> Result<()>;
+}
+
+/// Writes the first frame as soon as it is decoded, and ignores the others.
+pub struct FirstFrame {
+    encode: EncodeFn,
+    num_frames: usize,
+}
+
+impl FirstFrame {
+    pub fn new(encode: EncodeFn) -> Self {
+        Self {
+            encode,
+            num_frames: 0,
+        }
+    }
+}
+
+impl FrameWriter for FirstFrame {
+    fn push_frame(
+        &mut self,
+        image: &DecodeOutput,
+        frame: FrameLease,
+        sink: &mut Sink,
+    ) -> Result<()> {
+        self.num_frames += 1;
+        if self.num_frames == 1 {
+            (self.encode)(&image.with_frames(vec![frame.into_owned()]), sink)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _image: &DecodeOutput, _sink: &mut Sink) -> Result<()> {
+        if self.num_frames > 1 {
+            Reporter::get().warn(format_args!(
+                "More than one frame found, saving just the first one."
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps all the frames, and writes them once the last one is decoded, for files whose start
+/// depends on all the frames.
+pub struct AllFrames {
+    encode: EncodeFn,
+    frames: Vec<ImageFrame>,
+}
+
+impl AllFrames {
+    pub fn new(encode: EncodeFn) -> Self {
+        Self {
+            encode,
+            frames: vec![],
+        }
+    }
+}
+
+impl FrameWriter for AllFrames {
+    fn push_frame(
+        &mut self,
+        _image: &DecodeOutput,
+        frame: FrameLease,
+        _sink: &mut Sink,
+    ) -> Result<()> {
+        self.frames.push(frame.into_owned());
+        Ok(())
+    }
+
+    fn finish(&mut self, image: &DecodeOutput, sink: &mut Sink) -> Result<()> {
+        (self.encode)(&image.with_frames(std::mem::take(&mut self.frames)), sink)
+    }
+}
+
+/// An output written while the image is decoded, from [`super::OutputFormat::start_output`] or
+/// [`super::OutputFormat::start_frame_files`].
+pub struct FrameOutput {
+    target: Target,
+    num_frames: usize,
+    /// Time spent in the writer, including the time spent writing to the file.
+    time: Duration,
+}
+
+enum Target {
+    /// A single file, written by a [`FrameWriter`].
+    File {
+        writer: Box<dyn FrameWriter>,
+        output: OutputWriter,
+    },
+    /// One file per frame, each written as soon as its frame is decoded.
+    FrameFiles {
+        files: FrameFiles,
+        encode: EncodeFn,
+        write_time: Duration,
+    },
+}
+
+impl FrameOutput {
+    pub(super) fn new(writer: Box<dyn FrameWriter>, output: OutputFile) -> Result<Self> {
+        Ok(Self::with_target(Target::File {
+            writer,
+            output: output.start()?,
+        }))
+    }
+
+    pub(super) fn frame_files(files: FrameFiles, encode: EncodeFn) -> Self {
+        Self::with_target(Target::FrameFiles {
+            files,
+            encode,
+            write_time: Duration::ZERO,
+        })
+    }
+
+    fn with_target(target: Target) -> Self {
+        Self {
+            target,
+            num_frames: 0,
+            time: Duration::ZERO,
+        }
+    }
+
+    /// Number of frames passed to [`Self::push_frame`] so far.
+    pub fn num_frames(&self) -> usize {
+        self.num_frames
+    }
+
+    /// Time spent encoding and writing the frames passed so far.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn push_frame(&mut self, image: &DecodeOutput, frame: FrameLease) -> Result<()> {
+        let start = Instant::now();
+        let result = match &mut self.target {
+            Target::File { writer, output } => {
+                if self.num_frames == 0 {
+                    writer.begin(image, output.sink())?;
+                }
+                writer.push_frame(image, frame, output.sink())
+            }
+            Target::FrameFiles {
+                files,
+                encode,
+                write_time,
+            } => {
+                let path = files.path(self.num_frames);
+                let image = image.with_frames(vec![frame.into_owned()]);
+                OutputFile::create(&path)
+                    .map_err(Into::into)
+                    .and_then(|output| output.write(|sink| encode(&image, sink)))
+                    .map(|time| *write_time += time)
+                    .wrap_err_with(|| format!("Failed to write {path:?}"))
+            }
+        };
+        self.num_frames += 1;
+        self.time += start.elapsed();
+        result
+    }
+
+    /// Completes the output once all frames were passed. Returns the time spent encoding and the
+    /// time spent writing to the files, since the first frame.
+    pub fn finish(self, image: &DecodeOutput) -> Result<(Duration, Duration)> {
+        let start = Instant::now();
+        if self.num_frames == 0 {
+            bail!("No frames to write");
+        }
+        let write_time = match self.target {
+            Target::File {
+                mut writer,
+                mut output,
+            } => {
+                writer.finish(image, output.sink())?;
+                output.finish()?
+            }
+            Target::FrameFiles { write_time, .. } => write_time,
+        };
+        let time = self.time + start.elapsed();
+        Ok((time.saturating_sub(write_time), write_time))
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::{Result, bail};
+use std::io::Write;
+
+use super::writer::{FrameWriter, Sink};
+use crate::dec::{DecodeOutput, FrameLease, ImageFrame, OutputDataType, OutputShape};
+use crate::report::Reporter;
+use jxl::api::JxlAnimation;
+
+/// Luma weights of red and blue in BT.709.
+const KR: f32 = 0.2126;
+const KB: f32 = 0.0722;
+
+/// Resolution of the chroma planes of a YUV4MPEG2 stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Chroma planes of the size of the image.
+    Yuv444,
+    /// Chroma planes of half the width and height of the image, with samples centered between
+    /// the luma samples they cover.
+    Yuv420,
+}
+
+impl ChromaSubsampling {
+    fn chroma_size(self, size: (usize, usize)) -> (usize, usize) {
+        match self {
+            Self::Yuv444 => size,
+            Self::Yuv420 => (size.0.div_ceil(2), size.1.div_ceil(2)),
+        }
+    }
+
+    fn colorspace(self, data_type: OutputDataType) -> &'static str {
+        let ten_bits = data_type == OutputDataType::U16;
+        match (self, ten_bits) {
+            (Self::Yuv444, false) => "444",
+            (Self::Yuv444, true) => "444p10",
+            (Self::Yuv420, false) => "420jpeg",
+            (Self::Yuv420, true) => "420p10",
+        }
+    }
+}
+
+fn bytes_per_sample(data_type: OutputDataType) -> Result<usize> {
+    match data_type {
+        OutputDataType::U8 => Ok(1),
+        // 16-bit samples are stored with 10 significant bits.
+        OutputDataType::U16 => Ok(2),
+        data_type => bail!("Y4M only stores 8 and 10-bit samples, not {data_type:?}"),
+    }
+}
+
+fn greatest_common_divisor(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Returns the frame rate of the stream, as a fraction, for frames of `ticks` ticks.
+fn frame_rate(animation: &JxlAnimation, ticks: u32) -> Result<(u64, u64)> {
+    let num = animation.tps_numerator as u64;
+    let den = animation.tps_denominator as u64 * ticks.max(1) as u64;
+    if num == 0 || den == 0 {
+        bail!("Invalid animation tick rate {num}/{den}");
+    }
+    let gcd = greatest_common_divisor(num, den);
+    Ok((num / gcd, den / gcd))
+}
+
+/// Converts the color samples of `frame` to BT.709 limited range Y'CbCr planes, with 8-bit samples
+/// for 8-bit frames and 10-bit samples otherwise.
+fn to_yuv(
+    frame: &ImageFrame,
+    size: (usize, usize),
+    data_type: OutputDataType,
+    subsampling: ChromaSubsampling,
+) -> [Vec<u16>; 3] {
+    let is_grayscale = frame.color_type.is_grayscale();
+    let samples_per_pixel = frame.color_type.samples_per_pixel();
+    let (max_sample, scale) = if data_type == OutputDataType::U8 {
+        (255.0, 1.0)
+    } else {
+        (65535.0, 4.0)
+    };
+    let mut luma = Vec::with_capacity(size.0 * size.1);
+    let mut cb = Vec::with_capacity(size.0 * size.1);
+    let mut cr = Vec::with_capacity(size.0 * size.1);
+    let quantize =
+        |value: f32, offset: f32, range: f32| (scale * (offset + range * value)).round() as u16;
+    for y in 0..size.1 {
+        let row = frame.channels[0].row(y);
+        for x in 0..size.0 {
+            let sample = |c: usize| {
+                let i = x * samples_per_pixel + if is_grayscale { 0 } else { c };
+                let value = if data_type == OutputDataType::U8 {
+                    row[i] as f32
+                } else {
+                    u16::from_ne_bytes([row[2 * i], row[2 * i + 1]]) as f32
+                };
+                value / max_sample
+            };
+            let (r, g, b) = (sample(0), sample(1), sample(2));
+            let l = KR * r + (1.0 - KR - KB) * g + KB * b;
+            luma.push(quantize(l, 16.0, 219.0));
+            cb.push(quantize((b - l) / (2.0 * (1.0 - KB)), 128.0, 224.0));
+            cr.push(quantize((r - l) / (2.0 * (1.0 - KR)), 128.0, 224.0));
+        }
+    }
+    if subsampling == ChromaSubsampling::Yuv420 {
+        cb = downsample(&cb, size);
+        cr = downsample(&cr, size);
+    }
+    [luma, cb, cr]
+}
+
+/// Averages the 2x2 blocks of `plane`, repeating the last row and column of planes of odd sizes.
+fn downsample(plane: &[u16], size: (usize, usize)) -> Vec<u16> {
+    let (width, height) = ChromaSubsampling::Yuv420.chroma_size(size);
+    let mut downsampled = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let rows = [2 * y, (2 * y + 1).min(size.1 - 1)];
+        for x in 0..width {
+            let columns = [2 * x, (2 * x + 1).min(size.0 - 1)];
+            let sum: u32 = rows
+                .iter()
+                .flat_map(|y| columns.iter().map(move |x| plane[y * size.0 + x] as u32))
+                .sum();
+            downsampled.push(((sum + 2) / 4) as u16);
+        }
+    }
+    downsampled
+}
+
+/// Writes the frames of an animation as a YUV4MPEG2 stream of BT.709 limited range Y'CbCr
+/// samples, which are 8-bit for 8-bit output and 10-bit (stored as little-endian 16-bit samples)
+/// otherwise. The color samples are converted as they are, whatever their color space.
+///
+/// The stream has a constant frame rate, so frames last as long as the first one.
+pub struct Y4mWriter {
+    subsampling: ChromaSubsampling,
+    /// Duration of the first frame, once the header is written.
+    ticks: Option<u32>,
+    warned_about_timing: bool,
+    bytes: Vec<u8>,
+}
+
+impl Y4mWriter {
+    pub fn new(subsampling: ChromaSubsampling) -> Self {
+        Self {
+            subsampling,
+            ticks: None,
+            warned_about_timing: false,
+            bytes: vec![],
+        }
+    }
+
+    fn check(&self, img: &DecodeOutput) -> Result<()> {
+        bytes_per_sample(img.data_type)?;
+        if img.jxl_animation.is_none() {
+            bail!("Y4M output needs an animation, use another format for still images");
+        }
+        Ok(())
+    }
+
+    fn write_frame<Writer: Write>(
+        &mut self,
+        img: &DecodeOutput,
+        frame: &ImageFrame,
+        writer: &mut Writer,
+    ) -> Result<()> {
+        let ticks = frame.timing.as_ref().map_or(0, |timing| timing.ticks);
+        match self.ticks {
+            None => {
+                let (rate_num, rate_den) = frame_rate(img.jxl_animation.as_ref().unwrap(), ticks)?;
+                if frame.channels.len() > 1 || frame.color_type.has_alpha() {
+                    Reporter::get().warn(format_args!("Ignoring alpha and extra channels."));
+                }
+                let (width, height) = img.size;
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{width} H{height} F{rate_num}:{rate_den} Ip A1:1 C{} \
+                     XCOLORRANGE=LIMITED",
+                    self.subsampling.colorspace(img.data_type)
+                )?;
+                self.ticks = Some(ticks);
+            }
+            Some(first_ticks) if first_ticks != ticks && !self.warned_about_timing => {
+                Reporter::get().warn(format_args!(
+                    "Frames last different times, but Y4M streams have a constant frame rate."
+                ));
+                self.warned_about_timing = true;
+            }
+            Some(_) => {}
+        }
+        writer.write_all(b"FRAME\n")?;
+        for plane in to_yuv(frame, img.size, img.data_type, self.subsampling) {
+            self.bytes.clear();
+            if bytes_per_sample(img.data_type)? == 1 {
+                self.bytes.extend(plane.iter().map(|&sample| sample as u8));
+            } else {
+                self.bytes
+                    .extend(plane.iter().flat_map(|sample| sample.to_le_bytes()));
+            }
+            writer.write_all(&self.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameWriter for Y4mWriter {
+    fn begin(&mut self, image: &DecodeOutput, _sink: &mut Sink) -> Result<()> {
+        self.check(image)
+    }
+
+    fn push_frame(
+        &mut self,
+        image: &DecodeOutput,
+        frame: FrameLease,
+        sink: &mut Sink,
+    ) -> Result<()> {
+        self.write_frame(image, &frame, sink)
+    }
+
+    fn finish(&mut self, _image: &DecodeOutput, _sink: &mut Sink) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes all the frames of `img` with a [`Y4mWriter`].
+pub fn to_y4m<Writer: Write>(
+    img: &DecodeOutput,
+    writer: &mut Writer,
+    subsampling: ChromaSubsampling,
+) -> Result<()> {
+    let mut y4m = Y4mWriter::new(subsampling);
+    y4m.check(img)?;
+    for frame in &img.frames {
+        y4m.write_frame(img, frame, writer)?;
+    }
+    Ok(())
+}
+
+/// Size of the stream that [`to_y4m`] writes for frames of the given shape, assuming a frame
+/// rate of up to 10 digits.
+pub fn estimate_size(shape: &OutputShape, subsampling: ChromaSubsampling) -> u64 {
+    let bytes_per_sample = bytes_per_sample(shape.data_type).unwrap_or(2) as u64;
+    let (width, height) = shape.size;
+    let (chroma_width, chroma_height) = subsampling.chroma_size(shape.size);
+    let samples = width * height + 2 * chroma_width * chroma_height;
+    let header = format!(
+        "YUV4MPEG2 W{width} H{height} F1000000000:1 Ip A1:1 C{} XCOLORRANGE=LIMITED\n",
+        subsampling.colorspace(shape.data_type)
+    );
+    header.len() as u64
+        + shape.num_frames as u64 * ("FRAME\n".len() as u64 + samples as u64 * bytes_per_sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::{FrameTiming, JxlAnimation, JxlColorType};
+
+    /// An animation whose frames each have a single color, and last the given numbers of ticks
+    /// at 100 ticks per second.
+    fn make_animation(
+        size: (usize, usize),
+        data_type: OutputDataType,
+        colors: &[[u16; 3]],
+        ticks: &[u32],
+    ) -> DecodeOutput {
+        let animation = JxlAnimation::new(100, 1, 0);
+        let mut image = make_test_image(JxlColorType::Rgb, data_type, size);
+        image.frames.clear();
+        for (color, &ticks) in colors.iter().zip(ticks) {
+            let mut frame = make_test_image(JxlColorType::Rgb, data_type, size)
+                .frames
+                .remove(0);
+            for y in 0..size.1 {
+                let row = frame.channels[0].row_mut(y);
+                for x in 0..3 * size.0 {
+                    match data_type {
+                        OutputDataType::U8 => row[x] = color[x % 3] as u8,
+                        _ => row[2 * x..][..2].copy_from_slice(&color[x % 3].to_ne_bytes()),
+                    }
+                }
+            }
+            frame.timing = Some(FrameTiming::new(ticks, &animation));
+            image.frames.push(frame);
+        }
+        image.jxl_animation = Some(animation);
+        image
+    }
+
+    /// Splits a stream into its header and its frames.
+    fn parse_y4m(stream: &[u8], frame_len: usize) -> (String, Vec<&[u8]>) {
+        let header_len = stream.iter().position(|&b| b == b'\n').unwrap() + 1;
+        let header = String::from_utf8(stream[..header_len].to_vec()).unwrap();
+        let frames = stream[header_len..]
+            .chunks(6 + frame_len)
+            .map(|frame| {
+                assert_eq!(&frame[..6], b"FRAME\n");
+                assert_eq!(frame.len(), 6 + frame_len);
+                &frame[6..]
+            })
+            .collect();
+        (header, frames)
+    }
+
+    #[test]
+    fn every_frame_is_a_frame_block() {
+        let size = (3, 2);
+        let colors = [[255, 255, 255], [0, 0, 0], [255, 0, 0]];
+        let image = make_animation(size, OutputDataType::U8, &colors, &[4, 4, 4]);
+        let mut y4m = vec![];
+        to_y4m(&image, &mut y4m, ChromaSubsampling::Yuv444).unwrap();
+        let (header, frames) = parse_y4m(&y4m, 3 * 6);
+        // Frames of 4 ticks at 100 ticks per second make 25 frames per second.
+        assert_eq!(
+            header,
+            "YUV4MPEG2 W3 H2 F25:1 Ip A1:1 C444 XCOLORRANGE=LIMITED\n"
+        );
+        assert_eq!(frames.len(), 3);
+        // White, black and red in BT.709 limited range Y'CbCr.
+        for (frame, yuv) in frames
+            .iter()
+            .zip([[235, 128, 128], [16, 128, 128], [63, 102, 240]])
+        {
+            let expected: Vec<u8> = yuv.iter().flat_map(|&v| [v; 6]).collect();
+            assert_eq!(*frame, &expected[..]);
+        }
+        let shape = OutputShape::of_output(&image);
+        assert!(estimate_size(&shape, ChromaSubsampling::Yuv444) >= y4m.len() as u64);
+    }
+
+    #[test]
+    fn ten_bit_420() {
+        let size = (3, 3);
+        let image = make_animation(size, OutputDataType::U16, &[[65535, 0, 0]], &[1]);
+        let mut y4m = vec![];
+        to_y4m(&image, &mut y4m, ChromaSubsampling::Yuv420).unwrap();
+        let (header, frames) = parse_y4m(&y4m, 2 * (9 + 2 * 4));
+        assert_eq!(
+            header,
+            "YUV4MPEG2 W3 H3 F100:1 Ip A1:1 C420p10 XCOLORRANGE=LIMITED\n"
+        );
+        let samples: Vec<u16> = frames[0]
+            .chunks_exact(2)
+            .map(|s| u16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        // Red, with 10-bit samples.
+        let expected: Vec<u16> = [(250, 9), (409, 4), (960, 4)]
+            .iter()
+            .flat_map(|&(v, n)| std::iter::repeat_n(v, n))
+            .collect();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn chroma_is_averaged() {
+        let plane = [0, 4, 8, 2, 6, 10, 100, 100, 100];
+        assert_eq!(downsample(&plane, (3, 3)), [3, 9, 100, 100]);
+    }
+
+    #[test]
+    fn still_images_are_rejected() {
+        let image = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (4, 4));
+        let err = to_y4m(&image, &mut vec![], ChromaSubsampling::Yuv444).unwrap_err();
+        assert!(err.to_string().contains("needs an animation"), "{err}");
+    }
+}
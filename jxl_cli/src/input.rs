@@ -0,0 +1,267 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use color_eyre::eyre::{Result, WrapErr, eyre};
+use std::fs::File;
+use std::io::{self, Cursor, IsTerminal, Read, Seek, SeekFrom};
+use std::ops::Deref;
+use std::path::Path;
+
+use crate::report::ExitStatusContext;
+
+/// Input path that stands for stdin.
+pub const STDIN_PATH: &str = "-";
+
+/// Whether `path` stands for stdin.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new(STDIN_PATH)
+}
+
+/// An input file, or the bytes piped to stdin for the path [`STDIN_PATH`]. Stdin is read to the
+/// end when it is opened, so that it can be read again, like files.
+pub enum InputFile {
+    File(File),
+    Stdin(Cursor<Vec<u8>>),
+}
+
+impl InputFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        if !is_stdin(path) {
+            return Ok(Self::File(File::open(path)?));
+        }
+        let mut stdin = io::stdin().lock();
+        if stdin.is_terminal() {
+            return Err(eyre!(
+                "Standard input is a terminal, pipe a file into it instead"
+            ))
+            .usage_context("No input");
+        }
+        let mut bytes = vec![];
+        stdin.read_to_end(&mut bytes)?;
+        Ok(Self::Stdin(Cursor::new(bytes)))
+    }
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file) => file.read(buf),
+            Self::Stdin(bytes) => bytes.read(buf),
+        }
+    }
+}
+
+impl Seek for InputFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file) => file.seek(pos),
+            Self::Stdin(bytes) => bytes.seek(pos),
+        }
+    }
+}
+
+/// Reads all of the file at `path`, or of stdin for [`STDIN_PATH`].
+pub fn read_input(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    let context = || format!("Failed to read {path:?}");
+    let mut bytes = vec![];
+    match InputFile::open(path).wrap_err_with(context)? {
+        InputFile::File(mut file) => {
+            file.read_to_end(&mut bytes).wrap_err_with(context)?;
+        }
+        InputFile::Stdin(stdin) => bytes = stdin.into_inner(),
+    }
+    Ok(bytes)
+}
+
+/// Contents of an input file, either mapped into memory or read into a buffer.
+pub enum InputBytes {
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+    Read(Vec<u8>),
+}
+
+impl InputBytes {
+    /// Maps `file` into memory. Returns `None` if mapping is not supported, either because the
+    /// `mmap` feature is disabled or because the file cannot be mapped, as for stdin.
+    pub fn map(file: &InputFile) -> Option<Self> {
+        let InputFile::File(file) = file else {
+            return None;
+        };
+        #[cfg(feature = "mmap")]
+        {
+            // Empty files cannot be mapped on all platforms.
+            if file.metadata().ok()?.len() == 0 {
+                return None;
+            }
+            // SAFETY: the mapping is read-only, and the CLI does not modify its input. Other
+            // processes truncating or modifying the file while it is decoded are not guarded
+            // against, like in other tools that map their inputs.
+            unsafe { memmap2::Mmap::map(file) }.ok().map(Self::Mapped)
+        }
+        #[cfg(not(feature = "mmap"))]
+        {
+            let _ = file;
+            None
+        }
+    }
+
+    /// Maps `file` into memory if `mmap` is set and mapping is supported, and reads the rest of
+    /// it otherwise.
+    pub fn new(file: &mut InputFile, mmap: bool) -> Result<Self> {
+        if mmap && let Some(bytes) = Self::map(file) {
+            return Ok(bytes);
+        }
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        Ok(Self::Read(bytes))
+    }
+
+    /// Reads one byte of every page, so that the pages of mapped files are loaded from disk
+    /// before decoding, instead of while decoding.
+    pub fn touch_pages(&self) {
+        const PAGE_SIZE: usize = 4096;
+        let sum = self
+            .iter()
+            .step_by(PAGE_SIZE)
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        std::hint::black_box(sum);
+    }
+}
+
+impl Deref for InputBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(feature = "mmap")]
+            Self::Mapped(map) => map,
+            Self::Read(bytes) => bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("jxl_cli_{name}_{}.jxl", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn test_file(name: &str) -> Vec<u8> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        std::fs::read(root.join(name)).unwrap()
+    }
+
+    #[test]
+    fn mapped_and_read_inputs_match() {
+        let data = test_file("basic.jxl");
+        let temp = TempFile::new("input_match", &data);
+        let mut file = InputFile::open(&temp.0).unwrap();
+        let mapped = InputBytes::new(&mut file, true).unwrap();
+        assert_eq!(
+            matches!(mapped, InputBytes::Read(_)),
+            cfg!(not(feature = "mmap"))
+        );
+        mapped.touch_pages();
+        assert_eq!(&mapped[..], data);
+
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let read = InputBytes::new(&mut file, false).unwrap();
+        assert!(matches!(read, InputBytes::Read(_)));
+        assert_eq!(&read[..], data);
+
+        // Empty files are read instead.
+        let empty = TempFile::new("input_empty", &[]);
+        let mut file = InputFile::open(&empty.0).unwrap();
+        assert!(InputBytes::map(&file).is_none());
+        assert!(InputBytes::new(&mut file, true).unwrap().is_empty());
+
+        // Piped inputs are read again from the start after seeking, and never mapped.
+        let mut stdin = InputFile::Stdin(Cursor::new(data.clone()));
+        assert!(InputBytes::map(&stdin).is_none());
+        let mut start = [0; 2];
+        stdin.read_exact(&mut start).unwrap();
+        stdin.seek(SeekFrom::Start(0)).unwrap();
+        let read = InputBytes::new(&mut stdin, true).unwrap();
+        assert!(matches!(read, InputBytes::Read(_)));
+        assert_eq!(&read[..], data);
+    }
+
+    #[cfg(all(feature = "mmap", target_pointer_width = "64"))]
+    #[test]
+    fn decode_large_sparse_file() {
+        use crate::dec::{OutputDataType, decode_frames};
+        use jxl::api::Endianness;
+        use jxl::api::JxlDecoderOptions;
+        use std::io::Write;
+
+        // A codestream followed by more than 4 GiB of zeros, which are never read. The file is
+        // sparse, so it takes neither disk space nor memory.
+        const SIZE: u64 = (4 << 30) + 1;
+        let data = test_file("basic.jxl");
+        let temp = TempFile::new("input_sparse", &[]);
+        let mut file = File::options().write(true).open(&temp.0).unwrap();
+        file.write_all(&data).unwrap();
+        file.set_len(SIZE).unwrap();
+        drop(file);
+
+        let input = InputBytes::map(&InputFile::open(&temp.0).unwrap()).unwrap();
+        assert_eq!(input.len() as u64, SIZE);
+        let (output, _) = decode_frames(
+            &mut &input[..],
+            JxlDecoderOptions::default(),
+            None,
+            None,
+            &[OutputDataType::U8],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap();
+        let (expected, _) = decode_frames(
+            &mut data.as_slice(),
+            JxlDecoderOptions::default(),
+            None,
+            None,
+            &[OutputDataType::U8],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap();
+        assert_eq!(output.size, expected.size);
+        assert_eq!(output.frames.len(), expected.frames.len());
+        for y in 0..output.size.1 {
+            assert_eq!(
+                output.frames[0].channels[0].row(y),
+                expected.frames[0].channels[0].row(y)
+            );
+        }
+    }
+}
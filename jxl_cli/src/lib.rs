@@ -3,13 +3,23 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+pub mod cache;
+pub mod checksum;
 pub mod dec;
 pub mod enc;
+pub mod input;
+pub mod metrics;
+pub mod phases;
+pub mod progressive_sim;
+pub mod remux;
+pub mod report;
+pub mod speedtest;
+pub mod term;
 
 #[cfg(test)]
 mod tests {
     use crate::dec::{DecodeOutput, OutputDataType, decode_frames};
-    use jxl::api::JxlDecoderOptions;
+    use jxl::api::{Endianness, JxlDecoderOptions};
     use std::path::PathBuf;
 
     fn get_test_file(name: &str) -> PathBuf {
@@ -77,6 +87,9 @@ mod tests {
             false,
             None,
             false,
+            None,
+            None,
+            Endianness::native(),
         )
         .unwrap()
         .0
@@ -188,6 +201,9 @@ mod tests {
                 false,
                 None,
                 false,
+                None,
+                None,
+                Endianness::native(),
             )
             .unwrap();
         }
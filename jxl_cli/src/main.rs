@@ -3,17 +3,35 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use clap::Parser;
+use clap::{ArgGroup, Parser, Subcommand};
 use color_eyre::eyre::{Result, WrapErr, eyre};
-use jxl::api::JxlDecoderOptions;
+use jxl::api::{
+    Endianness, FileMap, JxlDecoderOptions, JxlExtraChannel, OutputLayout, RenderingChoice,
+    ResampleFilter, SpeedProfile,
+};
+use jxl::image::Rect;
+use jxl::simd::{Dispatch, FORCE_SCALAR_ENV};
+use jxl_cli::cache::{CacheKey, DecodeCache};
+use jxl_cli::checksum::{self, section_name};
 use jxl_cli::dec;
 use jxl_cli::dec::OutputDataType;
 use jxl_cli::enc::OutputFormat;
+use jxl_cli::enc::file::{self, OutputFile};
+use jxl_cli::enc::frame_files::FrameFiles;
+use jxl_cli::enc::writer::FrameOutput;
+use jxl_cli::input::{self, InputBytes, InputFile};
+use jxl_cli::phases::PhaseTimes;
+use jxl_cli::progressive_sim::{self, ByteBudget};
+use jxl_cli::report::{ExitStatus, ExitStatusContext, Reporter};
+use jxl_cli::speedtest::{Speedtest, SpeedtestOptionsJson};
+use jxl_cli::term;
 use jxl_cms::lcms2::Lcms2Cms;
+use serde::Serialize;
 use std::fs;
-use std::io::{BufReader, Read, Seek};
-use std::path::PathBuf;
-use std::time::Duration;
+use std::io::{BufReader, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 const VERSION_STRING: &str = concat!(
     env!("VERGEN_GIT_DESCRIBE"),
@@ -22,17 +40,81 @@ const VERSION_STRING: &str = concat!(
     ")"
 );
 
+/// Like `print!`, for requested data, which goes to stderr when the image is written to stdout.
+macro_rules! data {
+    ($($arg:tt)*) => {
+        Reporter::get().data(format_args!($($arg)*))
+    };
+}
+
+/// Like `println!`, see [`data!`].
+macro_rules! dataln {
+    () => {
+        data!("\n")
+    };
+    ($($arg:tt)*) => {
+        data!("{}\n", format_args!($($arg)*))
+    };
+}
+
+/// Options that do not need an output file.
+const OUTPUT_OPTIONAL_WITH: &[&str] = &[
+    "version",
+    "speedtest",
+    "info",
+    "list_frames",
+    "preview_terminal",
+    "checksum_out",
+    "verify_checksums",
+    #[cfg(feature = "verify")]
+    "verify",
+    #[cfg(feature = "debug-tools")]
+    "dump_entropy",
+];
+
+/// Decodes JPEG XL images.
+///
+/// Only the requested data is printed to stdout, and diagnostics are printed to stderr. The exit
+/// code is 0 on success, 1 if the input could not be read or decoded, 2 for invalid command lines,
+/// 3 if the output could not be written, and 4 if only some inputs of a batch were processed.
 #[derive(Parser)]
-#[command(version = VERSION_STRING)]
+#[command(
+    version = VERSION_STRING,
+    disable_version_flag = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    group(ArgGroup::new("json_data").args(["info", "time", "speedtest"]).multiple(true))
+)]
 struct Opt {
-    /// Input JXL file
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input JXL file, or - to read it from stdin
+    #[clap(required_unless_present = "version")]
+    input: Option<PathBuf>,
 
-    /// Output image file, should end in .ppm, .pgm, .png, .apng or .npy
-    /// (optional with --speedtest or --info)
-    #[clap(required_unless_present_any = ["speedtest", "info"])]
+    /// Output image file, should end in .ppm, .pgm, .pam, .png, .apng, .tif, .tiff, .gif, .y4m,
+    /// .npy, .pfm, .raw, .planes or .exr unless --output-format is given, or - to write the image
+    /// to stdout in the format given by --output-format (optional with --speedtest, --info,
+    /// --list-frames, --preview-terminal, --checksum-out, --verify-checksums, --verify or
+    /// --dump-entropy). A %d or %0Nd in the file name, such as out_%04d.png, writes each frame to
+    /// its own file, numbered from 0. Animations written to formats that only hold one frame
+    /// (.ppm, .pgm, .pam, .pfm and .exr) are also written one file per frame, numbered from 0 with
+    /// -000, -001 and so on before the extension
+    #[clap(required_unless_present_any = OUTPUT_OPTIONAL_WITH)]
     output: Option<PathBuf>,
 
+    /// Output format (ppm, pgm, npy, pfm, png, pam, tiff, gif, y4m, raw, planes, exr), overriding
+    /// the extension of the output file; y4m420 writes Y4M streams with 4:2:0 chroma subsampling,
+    /// raw writes the color and alpha samples as planes of f32s and planes adds the extra channels
+    #[clap(long, visible_alias = "format", requires = "output")]
+    output_format: Option<OutputFormat>,
+
+    /// Reserve the estimated size of the output before decoding, to fail early if it does not
+    /// fit; the size is exact for ppm, pgm, pam, npy, pfm, raw and planes outputs of a single frame
+    #[clap(long, action, requires = "output", conflicts_with = "scan")]
+    preallocate: bool,
+
     /// Print measured decoding speed.
     #[clap(long, short, action)]
     speedtest: bool,
@@ -45,6 +127,11 @@ struct Opt {
     #[clap(long, default_value_t = 1, requires = "speedtest")]
     warmup_reps: usize,
 
+    /// Write the measured decoding speed to this file as JSON, with the duration of every
+    /// repetition, the decoder version and the options in effect (only valid with --speedtest)
+    #[clap(long, value_name = "FILE", requires = "speedtest")]
+    speedtest_json: Option<PathBuf>,
+
     ///  If specified, writes the ICC profile of the decoded image
     #[clap(long)]
     icc_out: Option<PathBuf>,
@@ -53,7 +140,8 @@ struct Opt {
     #[clap(long)]
     original_icc_out: Option<PathBuf>,
 
-    /// If specified, takes precedence over the bit depth in the input metadata
+    /// If specified, takes precedence over the bit depth in the input metadata when choosing the
+    /// output data type, and is interpreted as an integer bit depth even for float inputs
     #[clap(long)]
     override_bitdepth: Option<usize>,
 
@@ -61,10 +149,83 @@ struct Opt {
     #[clap(long, action)]
     preview: bool,
 
+    /// Only output the given frames, selected by zero-based index, by a range of indices
+    /// (`5..20`, `5..=19` or `5..`), or by name with `name=<frame name>`. Can be repeated. Other
+    /// frames are skipped without rendering them, and decoding stops after the last selected
+    /// frame
+    #[clap(long, conflicts_with = "preview")]
+    frames: Vec<dec::FrameSelector>,
+
+    /// Show the first frame in the terminal, using the kitty graphics protocol or sixel if
+    /// supported, and colored half blocks otherwise
+    #[clap(long, action)]
+    preview_terminal: bool,
+
     /// Print image information without decoding
     #[clap(long, short, action)]
     info: bool,
 
+    /// Print the output of --info, --time or --speedtest as JSON; with both --speedtest and
+    /// --time, the phases are fields of the speedtest object
+    #[clap(long, action, requires = "json_data")]
+    json: bool,
+
+    /// Print the wall-clock time spent reading the input, reading the headers, decoding each
+    /// frame, handing frames over, encoding the output and writing it. With --speedtest, the
+    /// decoding phases are summed over all decodes, including warmups
+    #[clap(long, action, conflicts_with_all = ["info", "checksum_out", "verify_checksums", "cache_dir"])]
+    time: bool,
+
+    /// Print the name and duration of every decoded frame as it is decoded; names longer than 100
+    /// bytes are shortened to their first 100 bytes
+    #[clap(long, action)]
+    list_frames: bool,
+
+    /// Write the BLAKE3 checksums of the codestream and of each frame header and section to this
+    /// file, without decoding, so that corrupted bytes can later be located with
+    /// --verify-checksums
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["output", "info", "verify_checksums"])]
+    checksum_out: Option<PathBuf>,
+
+    /// Compare the checksums of the input with those written to this file by --checksum-out,
+    /// without decoding, print the frame headers and sections that changed, and fail if any did
+    #[clap(long, value_name = "FILE", conflicts_with_all = ["output", "info"])]
+    verify_checksums: Option<PathBuf>,
+
+    /// Print more diagnostics to stderr. With --list-frames, also print the region that changed
+    /// since the previous frame and the progressive passes. With --info, also print the issues
+    /// of the embedded ICC profile if built with the debug-tools feature
+    #[clap(long, short, action)]
+    verbose: bool,
+
+    /// Print the version. With --verbose, also print the capabilities of the decoder, as
+    /// reported by `jxl::capabilities`, and the supported output formats
+    #[clap(long, short = 'V', action)]
+    version: bool,
+
+    /// Do not print warnings and other diagnostics to stderr. Errors are still printed
+    #[clap(long, short, action, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print the time spent in each decoding stage, and the predictors and transforms used by
+    /// the modular streams of each frame
+    #[cfg(feature = "timing-stats")]
+    #[clap(long, action)]
+    print_stats: bool,
+
+    /// Re-check the modular streams of every frame by re-applying their transforms and
+    /// predictors to the decoded channels, print the result for each channel, and fail if any
+    /// channel does not match the residuals it was decoded from
+    #[cfg(feature = "verify")]
+    #[clap(long, action)]
+    verify: bool,
+
+    /// Write the entropy codes of every frame, with the distributions of their clusters, their
+    /// context maps and their LZ77 parameters, to the given JSON file
+    #[cfg(feature = "debug-tools")]
+    #[clap(long, value_name = "FILE")]
+    dump_entropy: Option<PathBuf>,
+
     /// Use high precision mode for decoding
     #[clap(long)]
     high_precision: bool,
@@ -75,6 +236,21 @@ struct Opt {
     #[clap(long)]
     data_type: Option<OutputDataType>,
 
+    /// Byte order of 16 and 32-bit samples (native, little, big). Only npy output can use a
+    /// byte order other than the native one
+    #[clap(long, default_value = "native")]
+    output_endianness: dec::OutputEndianness,
+
+    /// Store the pixels column by column, in Fortran order, for consumers such as MATLAB. Only
+    /// npy output can be column-major
+    #[clap(long)]
+    column_major: bool,
+
+    /// Map the input file into memory instead of reading it, which avoids holding a copy of
+    /// large files in memory. Falls back to reading the file if it cannot be mapped
+    #[clap(long)]
+    mmap: bool,
+
     /// Allow partial files (flush pixels on EOF)
     #[clap(long)]
     allow_partial_files: bool,
@@ -82,16 +258,740 @@ struct Opt {
     /// Force a partial render every `render_interval` bytes.
     #[clap(long)]
     render_interval: Option<usize>,
+
+    /// Number of frames whose output buffers are kept for the following frames to reuse, when
+    /// frames are not all kept until the end of decoding (with --speedtest, --preview or
+    /// --frames, or when they are written as they are decoded)
+    #[clap(long, default_value_t = dec::DEFAULT_POOL_DEPTH)]
+    frame_pool_depth: usize,
+
+    /// Abort decoding after this many entropy-coded tokens per image pixel (rounding the image
+    /// up to whole 8x8 blocks), to bound the time spent on malicious files. Normal images need
+    /// fewer than 20. Use 0 for no limit
+    #[clap(long, default_value_t = 1024)]
+    max_tokens_per_pixel: u64,
+
+    /// Resize the output to WIDTHxHEIGHT pixels, ignoring the aspect ratio of the image
+    #[clap(long, conflicts_with = "preview")]
+    resize: Option<dec::OutputSize>,
+
+    /// Resize the output, preserving its aspect ratio, so that its longer side has this many
+    /// pixels
+    #[clap(long, conflicts_with_all = ["preview", "resize"])]
+    resize_long_edge: Option<usize>,
+
+    /// Decode at a lower resolution, dividing both sides of the image by 1, 2, 4 or 8. Only the
+    /// progressive passes needed for that resolution are decoded, or only the LF image for 8.
+    /// Frames that do not support the factor are decoded at the closest higher resolution that
+    /// they support and resized
+    #[clap(long, conflicts_with_all = ["preview", "resize", "resize_long_edge"], value_parser = parse_downsample)]
+    downsample: Option<u32>,
+
+    /// Decode only the region of WIDTHxHEIGHT pixels whose top left corner is at (X, Y) in the
+    /// displayed image, given as X,Y,WIDTH,HEIGHT. Regions that do not lie within the image are
+    /// rejected. With --resize or --resize-long-edge, the region is resized
+    #[clap(long, conflicts_with = "preview", value_parser = parse_crop)]
+    crop: Option<Rect>,
+
+    /// Filter used for resizing (catmull-rom, lanczos3)
+    #[clap(long, default_value = "catmull-rom", value_parser = parse_resize_filter)]
+    resize_filter: ResampleFilter,
+
+    /// Use the embedded ICC profile even if it disagrees with the color space signaled in the
+    /// image header, which takes precedence by default
+    #[clap(long)]
+    prefer_icc_profile: bool,
+
+    /// Fail on files whose Exif metadata declares an image size that disagrees with the
+    /// codestream, whose size is used with a warning by default
+    #[clap(long)]
+    reject_size_mismatch: bool,
+
+    /// Conceal groups whose sections are corrupt instead of failing: groups with corrupt AC are
+    /// rendered from their DC, and corrupt DC is interpolated from the groups around it
+    #[clap(long)]
+    skip_corrupt_sections: bool,
+
+    /// Render HDR images for an SDR or an HDR display (sdr, hdr). SDR rendering tone maps the
+    /// image to the luminance range of SDR displays, and outputs sRGB instead of PQ or HLG.
+    /// Default: hdr
+    #[clap(long, value_parser = parse_rendering)]
+    rendering: Option<RenderingChoice>,
+
+    /// Trade fidelity for decoding speed (faithful, fast). Fast skips some restoration filter
+    /// passes and noise synthesis, approximates Gaborish, and upsamples extra channels with the
+    /// nearest neighbor; the applied simplifications are reported as warnings
+    #[clap(long, default_value = "faithful", value_parser = parse_speed_profile)]
+    speed_profile: SpeedProfile,
+
+    /// Search the input for a JPEG XL stream embedded at any offset, for example inside another
+    /// file or a memory dump, and decode the first one that can be decoded
+    #[clap(long, conflicts_with_all = ["speedtest", "info", "preview", "render_interval"])]
+    scan: bool,
+
+    /// Cache outputs in this directory, keyed by the input contents and the decoding options,
+    /// and reuse them instead of decoding unchanged inputs again
+    #[clap(long, requires = "output", conflicts_with_all = ["speedtest", "icc_out", "original_icc_out", "preview_terminal"])]
+    cache_dir: Option<PathBuf>,
+
+    /// Maximum size of the cache in bytes; least recently used entries are evicted first
+    #[clap(long, requires = "cache_dir")]
+    cache_max_bytes: Option<u64>,
+
+    /// Decode even if the output is cached, and fail if the cached output does not match
+    #[clap(long, requires = "cache_dir")]
+    cache_verify: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Copy a JXL file into a container with added metadata boxes, without decoding it
+    Remux {
+        /// Exif data to add, in TIFF format, optionally preceded by "Exif\0\0"
+        #[clap(long)]
+        add_exif: Option<PathBuf>,
+
+        /// XMP packet to add
+        #[clap(long)]
+        add_xmp: Option<PathBuf>,
+
+        /// Compress the added metadata boxes with Brotli
+        #[clap(long, action)]
+        compress: bool,
+
+        /// Input JXL file
+        input: PathBuf,
+
+        /// Output JXL file
+        output: PathBuf,
+    },
+    /// Decode growing prefixes of a file, as if it was being downloaded, write the partial
+    /// renders to step_N.png and print how close each one is to the final image
+    ProgressiveSim {
+        /// Prefixes to decode, as percentages of the file size or numbers of bytes
+        #[clap(long, value_delimiter = ',', default_value = "5%,10%,25%,50%,100%")]
+        budget_steps: Vec<ByteBudget>,
+
+        /// Input JXL file
+        input: PathBuf,
+
+        /// Directory to write the partial renders to
+        output_dir: PathBuf,
+    },
+    /// Print the location of every box, frame and section of a file, without decoding it
+    Map {
+        /// Print the map as JSON
+        #[clap(long, action)]
+        json: bool,
+
+        /// Input JXL file
+        input: PathBuf,
+    },
+}
+
+fn read(path: &PathBuf) -> Result<Vec<u8>> {
+    input::read_input(path)
+}
+
+fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Remux {
+            add_exif,
+            add_xmp,
+            compress,
+            input,
+            output,
+        } => remux(
+            add_exif.as_ref(),
+            add_xmp.as_ref(),
+            *compress,
+            input,
+            output,
+        ),
+        Command::ProgressiveSim {
+            budget_steps,
+            input,
+            output_dir,
+        } => progressive_sim(budget_steps, input, output_dir),
+        Command::Map { json, input } => map(input, *json),
+    }
+}
+
+fn remux(
+    add_exif: Option<&PathBuf>,
+    add_xmp: Option<&PathBuf>,
+    compress: bool,
+    input: &PathBuf,
+    output: &PathBuf,
+) -> Result<()> {
+    let metadata = jxl_cli::remux::Metadata {
+        exif: add_exif.map(read).transpose()?,
+        xmp: add_xmp.map(read).transpose()?,
+        compress,
+    };
+    let mut out = vec![];
+    jxl_cli::remux::remux(&read(input)?, &metadata, &mut out)?;
+    fs::write(output, out).output_context(|| format!("Failed to write {:?}", output))
+}
+
+fn progressive_sim(budgets: &[ByteBudget], input: &PathBuf, output_dir: &PathBuf) -> Result<()> {
+    let data = read(input)?;
+    let steps = progressive_sim::simulate(&data, budgets, || {
+        let mut options = JxlDecoderOptions::default();
+        options.cms = Some(Box::new(Lcms2Cms));
+        options
+    })?;
+    fs::create_dir_all(output_dir)
+        .output_context(|| format!("Failed to create {:?}", output_dir))?;
+    dataln!("step  bytes      frames  passes  PSNR (dB)");
+    for (i, step) in steps.iter().enumerate() {
+        if let Some(output) = &step.output {
+            let path = output_dir.join(format!("step_{}.png", i + 1));
+            OutputFile::create(&path)
+                .map_err(Into::into)
+                .and_then(|file| OutputFormat::Png.save_image(output, file))
+                .output_context(|| format!("Failed to write {path:?}"))?;
+        }
+        let passes = step
+            .passes
+            .map_or("-".to_string(), |(done, total)| format!("{done}/{total}"));
+        let psnr = step
+            .psnr
+            .map_or("-".to_string(), |psnr| format!("{psnr:.2}"));
+        dataln!(
+            "{:<5} {:<10} {:<7} {:<7} {psnr}",
+            i + 1,
+            step.bytes,
+            step.complete_frames,
+            passes
+        );
+    }
+    Ok(())
+}
+
+fn map(input: &PathBuf, json: bool) -> Result<()> {
+    let map = jxl::api::map_file(&read(input)?)?;
+    if json {
+        return Reporter::get().json(&MapJson::new(&map));
+    }
+    let file_offset = |offset| {
+        map.file_offset(offset)
+            .map_or("-".to_string(), |o| o.to_string())
+    };
+    if !map.boxes.is_empty() {
+        dataln!("box   offset      payload     size");
+        for b in &map.boxes {
+            let size = b.payload_len.map_or("-".to_string(), |len| len.to_string());
+            dataln!(
+                "{:<5} {:<11} {:<11} {size}",
+                String::from_utf8_lossy(&b.kind.0),
+                b.header_offset,
+                b.payload_offset
+            );
+        }
+        dataln!();
+    }
+    dataln!("frame   section            offset      file offset size");
+    dataln!(
+        "-       image header       {:<11} {:<11} {}",
+        0,
+        file_offset(0),
+        map.frames[0].header_bits_offset / 8
+    );
+    let mut index = 0;
+    for frame in &map.frames {
+        let name = if frame.is_preview {
+            "preview".to_string()
+        } else {
+            index += 1;
+            (index - 1).to_string()
+        };
+        let header_offset = frame.header_bits_offset / 8;
+        let rows = std::iter::once((
+            "header".to_string(),
+            header_offset,
+            frame.sections[0].offset - header_offset,
+        ))
+        .chain(
+            frame
+                .sections
+                .iter()
+                .map(|s| (section_name(s.section), s.offset, s.len)),
+        );
+        for (section, offset, size) in rows {
+            dataln!(
+                "{name:<7} {section:<18} {offset:<11} {:<11} {size}",
+                file_offset(offset)
+            );
+        }
+    }
+    Ok(())
+}
+
+fn simd_description() -> String {
+    let tier = Dispatch::active_tier();
+    if Dispatch::forced_scalar() {
+        format!("{tier} (forced by {FORCE_SCALAR_ENV})")
+    } else {
+        tier.to_string()
+    }
+}
+
+/// Prints the version, and with `verbose` what this build can decode and write.
+fn print_version(verbose: bool) {
+    dataln!("jxl_cli {VERSION_STRING}");
+    if !verbose {
+        return;
+    }
+    let capabilities = jxl::capabilities();
+    for (name, supported) in capabilities.flags() {
+        dataln!("{name:>20}: {}", if supported { "yes" } else { "no" });
+    }
+    dataln!("{:>20}: {:#x}", "capability bits", capabilities.bits());
+    dataln!("{:>20}: {}", "max threads", capabilities.max_threads);
+    let tiers: Vec<_> = capabilities.simd_tiers.iter().map(|t| t.name()).collect();
+    dataln!("{:>20}: {}", "SIMD tiers", tiers.join(", "));
+    dataln!("{:>20}: {}", "SIMD", simd_description());
+    dataln!(
+        "{:>20}: {}",
+        "output formats",
+        jxl_cli::enc::supported_formats()
+    );
+}
+
+/// Describes all the options that influence the output, for use in cache keys.
+fn cache_fingerprint(opt: &Opt, output_format: Option<OutputFormat>) -> String {
+    format!(
+        "{VERSION_STRING};{output_format:?};{:?};{};{:?};{};{:?};{};{:?};{};{:?};{:?};{:?};{:?};{:?};{};{:?};{};{};{}",
+        opt.override_bitdepth,
+        opt.preview,
+        opt.frames,
+        opt.high_precision,
+        opt.data_type,
+        opt.allow_partial_files,
+        opt.render_interval,
+        opt.scan,
+        output_size(opt),
+        opt.crop,
+        opt.resize_filter,
+        opt.output_endianness,
+        opt.rendering,
+        opt.column_major,
+        opt.speed_profile,
+        opt.reject_size_mismatch,
+        opt.skip_corrupt_sections,
+        opt.prefer_icc_profile,
+    )
+}
+
+fn parse_resize_filter(s: &str) -> Result<ResampleFilter, String> {
+    match s.to_lowercase().as_str() {
+        "catmull-rom" => Ok(ResampleFilter::CatmullRom),
+        "lanczos3" => Ok(ResampleFilter::Lanczos3),
+        _ => Err(format!("Unknown resize filter {s}")),
+    }
+}
+
+fn parse_crop(s: &str) -> Result<Rect, String> {
+    let values: Option<Vec<usize>> = s.split(',').map(|v| v.trim().parse().ok()).collect();
+    match values.as_deref() {
+        Some(&[x, y, width, height]) if width > 0 && height > 0 => Ok(Rect {
+            origin: (x, y),
+            size: (width, height),
+        }),
+        _ => Err(format!("Invalid crop {s}, expected X,Y,WIDTH,HEIGHT")),
+    }
+}
+
+fn parse_downsample(s: &str) -> Result<u32, String> {
+    match s.parse() {
+        Ok(factor @ (1 | 2 | 4 | 8)) => Ok(factor),
+        _ => Err(format!(
+            "Invalid downsampling factor {s}, expected 1, 2, 4 or 8"
+        )),
+    }
+}
+
+fn parse_rendering(s: &str) -> Result<RenderingChoice, String> {
+    match s.to_lowercase().as_str() {
+        "sdr" => Ok(RenderingChoice::Sdr),
+        "hdr" => Ok(RenderingChoice::Hdr),
+        _ => Err(format!("Unknown rendering {s}")),
+    }
+}
+
+fn parse_speed_profile(s: &str) -> Result<SpeedProfile, String> {
+    match s.to_lowercase().as_str() {
+        "faithful" => Ok(SpeedProfile::Faithful),
+        "fast" => Ok(SpeedProfile::Fast),
+        _ => Err(format!("Unknown speed profile {s}")),
+    }
+}
+
+fn output_size(opt: &Opt) -> Option<dec::OutputSize> {
+    opt.resize
+        .or(opt.resize_long_edge.map(dec::OutputSize::LongEdge))
+        .or(opt
+            .downsample
+            .filter(|&factor| factor > 1)
+            .map(dec::OutputSize::Downsample))
+}
+
+#[cfg(feature = "timing-stats")]
+fn print_modular_stats(frame: usize, stats: &jxl::api::ModularStats) {
+    dataln!(
+        "Frame {frame}: {} modular channels, {} MA tree nodes",
+        stats.channels,
+        stats.tree_nodes
+    );
+    let total: u64 = stats.predictor_pixel_counts.iter().sum();
+    for (predictor, count) in stats.predictor_counts().filter(|(_, count)| *count > 0) {
+        dataln!(
+            "{:>26}: {count:12} samples ({:5.1}%)",
+            format!("{predictor:?}"),
+            100.0 * count as f64 / total as f64
+        );
+    }
+    // Groups usually share their transforms, so print each distinct chain once.
+    let mut chains: Vec<(String, usize)> = vec![];
+    for stream in stats.transforms.chunk_by(|a, b| a.stream == b.stream) {
+        let chain = stream
+            .iter()
+            .map(|desc| desc.to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        match chains.iter_mut().find(|(c, _)| *c == chain) {
+            Some((_, count)) => *count += 1,
+            None => chains.push((chain, 1)),
+        }
+    }
+    for (chain, num_streams) in chains {
+        let plural = if num_streams == 1 { "" } else { "s" };
+        dataln!(
+            "{:>26}: {chain} ({num_streams} stream{plural})",
+            "transforms"
+        );
+    }
+}
+
+#[cfg(feature = "verify")]
+fn print_modular_checks(frames: &[dec::ImageFrame]) -> Result<()> {
+    use jxl::api::ModularCheckOutcome;
+    let mut num_mismatches = 0;
+    for (i, frame) in frames.iter().enumerate() {
+        for check in frame.modular_checks.iter().flatten() {
+            dataln!(
+                "Frame {i}: stream {}, channel {} ({}x{}): {}",
+                check.stream,
+                check.channel,
+                check.size.0,
+                check.size.1,
+                check.outcome
+            );
+            if matches!(check.outcome, ModularCheckOutcome::Mismatch { .. }) {
+                num_mismatches += 1;
+            }
+        }
+    }
+    if num_mismatches > 0 {
+        return Err(eyre!(
+            "{num_mismatches} modular channel(s) do not match their residuals"
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `f`, and adds the time it takes to `phase`.
+fn timed<T>(phase: &mut Duration, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    *phase += start.elapsed();
+    result
+}
+
+/// Prints the name and duration of frame `index`, and with `verbose` what changed since the
+/// previous frame and how the frame is coded.
+fn print_frame_line(index: usize, frame: &dec::ImageFrame, verbose: bool) {
+    data!(
+        "Frame {index}: name {}, duration {} ms",
+        frame.listed_name(),
+        frame
+            .timing
+            .map_or(0.0, |timing| timing.as_duration().as_secs_f64() * 1000.0)
+    );
+    if verbose {
+        match frame
+            .diff
+            .map(|diff| (diff.changed_rect, diff.changed_pixels))
+        {
+            Some((Some(rect), pixels)) => data!(
+                ", changed {}x{}+{}+{} ({pixels} pixels)",
+                rect.size.0,
+                rect.size.1,
+                rect.origin.0,
+                rect.origin.1
+            ),
+            Some((None, _)) => data!(", unchanged"),
+            None => {}
+        }
+        data!(", passes: {}", frame.passes);
+        if let Some(groups) = frame.groups {
+            data!(
+                ", groups: {}x{} of {2}x{2}",
+                groups.num_groups.0,
+                groups.num_groups.1,
+                groups.group_dim
+            );
+        }
+    }
+    dataln!();
 }
 
-fn save_icc(icc_bytes: &[u8], icc_filename: Option<&PathBuf>) -> Result<()> {
-    icc_filename.map_or(Ok(()), |path| {
-        std::fs::write(path, icc_bytes)
-            .wrap_err_with(|| format!("Failed to write ICC profile to {:?}", path))
+/// Writes `icc_bytes` to `icc_file`, if any, and returns the time spent writing.
+fn save_icc(icc_bytes: &[u8], icc_file: Option<OutputFile>) -> Result<Duration> {
+    icc_file.map_or(Ok(Duration::ZERO), |file| {
+        let path = file.path().to_path_buf();
+        file.write(|writer| Ok(writer.write_all(icc_bytes)?))
+            .output_context(|| format!("Failed to write ICC profile to {:?}", path))
     })
 }
 
-fn main() -> Result<()> {
+/// Opens an output before decoding, so that outputs that cannot be written are reported early.
+fn open_output(path: &Path) -> Result<OutputFile> {
+    OutputFile::create(path).output_context(|| format!("Failed to write {path:?}"))
+}
+
+/// The output of --info --json.
+#[derive(Serialize)]
+struct InfoJson {
+    width: usize,
+    height: usize,
+    bits_per_sample: u32,
+    exponent_bits_per_sample: u32,
+    orientation: String,
+    color_profile: String,
+    output_color_profile: String,
+    preview_size: Option<(usize, usize)>,
+    animation: Option<AnimationJson>,
+    extra_channels: usize,
+    extra_channel_info: Vec<ExtraChannelJson>,
+    compression: String,
+}
+
+#[derive(Serialize)]
+struct AnimationJson {
+    num_loops: u32,
+    tps_numerator: u32,
+    tps_denominator: u32,
+    have_timecodes: bool,
+}
+
+#[derive(Serialize)]
+struct ExtraChannelJson {
+    kind: String,
+    name: String,
+    bits_per_sample: u32,
+    exponent_bits_per_sample: u32,
+    dim_shift: u32,
+    alpha_associated: bool,
+}
+
+impl ExtraChannelJson {
+    fn new(channel: &JxlExtraChannel) -> Self {
+        Self {
+            kind: format!("{:?}", channel.ec_type),
+            name: channel.name.clone(),
+            bits_per_sample: channel.bit_depth.bits_per_sample(),
+            exponent_bits_per_sample: channel.bit_depth.exponent_bits(),
+            dim_shift: channel.dim_shift,
+            alpha_associated: channel.alpha_associated,
+        }
+    }
+}
+
+/// Describes an extra channel on one line of --info.
+fn describe_extra_channel(channel: &JxlExtraChannel) -> String {
+    let mut description = format!("{:?}", channel.ec_type);
+    if !channel.name.is_empty() {
+        description += &format!(" {}", dec::listed_name(&channel.name));
+    }
+    description += &format!(", {:?}", channel.bit_depth);
+    if channel.dim_shift > 0 {
+        description += &format!(", downsampled {}x", 1 << channel.dim_shift);
+    }
+    if channel.alpha_associated {
+        description += ", premultiplied";
+    }
+    description
+}
+
+/// The output of map --json.
+#[derive(Serialize)]
+struct MapJson {
+    boxes: Vec<BoxJson>,
+    codestream_len: u64,
+    frames: Vec<FrameJson>,
+}
+
+#[derive(Serialize)]
+struct BoxJson {
+    kind: String,
+    header_offset: u64,
+    payload_offset: u64,
+    payload_len: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct FrameJson {
+    header_bits_offset: u64,
+    is_preview: bool,
+    sections: Vec<SectionJson>,
+}
+
+#[derive(Serialize)]
+struct SectionJson {
+    section: String,
+    offset: u64,
+    len: u64,
+    file_offset: Option<u64>,
+}
+
+impl MapJson {
+    fn new(map: &FileMap) -> Self {
+        MapJson {
+            boxes: map
+                .boxes
+                .iter()
+                .map(|b| BoxJson {
+                    kind: String::from_utf8_lossy(&b.kind.0).into_owned(),
+                    header_offset: b.header_offset,
+                    payload_offset: b.payload_offset,
+                    payload_len: b.payload_len,
+                })
+                .collect(),
+            codestream_len: map.codestream_len,
+            frames: map
+                .frames
+                .iter()
+                .map(|f| FrameJson {
+                    header_bits_offset: f.header_bits_offset,
+                    is_preview: f.is_preview,
+                    sections: f
+                        .sections
+                        .iter()
+                        .map(|s| SectionJson {
+                            section: section_name(s.section),
+                            offset: s.offset,
+                            len: s.len,
+                            file_offset: map.file_offset(s.offset),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The output of --dump-entropy.
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+struct EntropyDumpJson {
+    codes: Vec<EntropyCodeJson>,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+struct EntropyCodeJson {
+    /// `None` for the ICC profile and the preview frame.
+    frame: Option<usize>,
+    /// `None` outside of sections, as for the ICC profile and TOC permutations.
+    section: Option<String>,
+    purpose: &'static str,
+    context_map: Vec<u8>,
+    lz77: Option<Lz77Json>,
+    clusters: Vec<EntropyClusterJson>,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+struct Lz77Json {
+    min_symbol: u32,
+    min_length: u32,
+    length_uint: HybridUintJson,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+struct HybridUintJson {
+    split_exponent: u32,
+    msb_in_token: u32,
+    lsb_in_token: u32,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+struct EntropyClusterJson {
+    uint: HybridUintJson,
+    distribution: DistributionJson,
+}
+
+#[cfg(feature = "debug-tools")]
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum DistributionJson {
+    Ans { counts: Vec<u16> },
+    Prefix { code_lengths: Vec<u8> },
+    PrefixSingle { symbol: u32 },
+}
+
+#[cfg(feature = "debug-tools")]
+impl HybridUintJson {
+    fn new(uint: &jxl::api::HybridUintInfo) -> Self {
+        HybridUintJson {
+            split_exponent: uint.split_exponent,
+            msb_in_token: uint.msb_in_token,
+            lsb_in_token: uint.lsb_in_token,
+        }
+    }
+}
+
+#[cfg(feature = "debug-tools")]
+impl EntropyCodeJson {
+    fn new(code: &jxl::api::EntropyCodeInfo) -> Self {
+        use jxl::api::SymbolDistribution;
+        EntropyCodeJson {
+            frame: code.frame,
+            section: code.section.map(|s| section_name(Some(s))),
+            purpose: code.purpose.name(),
+            context_map: code.context_map.clone(),
+            lz77: code.lz77.as_ref().map(|lz77| Lz77Json {
+                min_symbol: lz77.min_symbol,
+                min_length: lz77.min_length,
+                length_uint: HybridUintJson::new(&lz77.length_uint),
+            }),
+            clusters: code
+                .clusters
+                .iter()
+                .map(|cluster| EntropyClusterJson {
+                    uint: HybridUintJson::new(&cluster.uint),
+                    distribution: match &cluster.distribution {
+                        SymbolDistribution::Ans { counts } => DistributionJson::Ans {
+                            counts: counts.clone(),
+                        },
+                        SymbolDistribution::Prefix { code_lengths } => DistributionJson::Prefix {
+                            code_lengths: code_lengths.clone(),
+                        },
+                        SymbolDistribution::PrefixSingle { symbol } => {
+                            DistributionJson::PrefixSingle { symbol: *symbol }
+                        }
+                    },
+                })
+                .collect(),
+        }
+    }
+}
+
+fn main() -> ExitCode {
     #[cfg(feature = "tracing-subscriber")]
     {
         use tracing_subscriber::{EnvFilter, fmt, prelude::*};
@@ -102,21 +1002,128 @@ fn main() -> Result<()> {
     }
 
     let opt = Opt::parse();
-    let mut file = fs::File::open(opt.input.clone())
-        .wrap_err_with(|| format!("Failed to read source image from {:?}", opt.input))?;
+    let reporter = Reporter {
+        quiet: opt.quiet,
+        verbose: opt.verbose,
+        json: opt.json,
+        image_to_stdout: opt.output.as_deref().is_some_and(file::is_stdout),
+    };
+    reporter.install();
+    match run(&opt) {
+        Ok(()) => ExitStatus::Success.into(),
+        Err(err) => {
+            reporter.error(&err);
+            ExitStatus::of(&err).into()
+        }
+    }
+}
+
+fn run(opt: &Opt) -> Result<()> {
+    let reporter = Reporter::get();
+    if let Some(command) = &opt.command {
+        return run_command(command);
+    }
+    if opt.version {
+        print_version(opt.verbose);
+        return Ok(());
+    }
+    let run_start = Instant::now();
+    let mut times = PhaseTimes::default();
+    let input = opt.input.as_ref().unwrap();
+    reporter.detail(format_args!("SIMD: {}", simd_description()));
+    if let Some(path) = &opt.checksum_out {
+        let (_, checksums) = checksum::compute(&read(input)?)?;
+        return fs::write(path, checksum::format(&checksums))
+            .output_context(|| format!("Failed to write {path:?}"));
+    }
+    if let Some(path) = &opt.verify_checksums {
+        let expected = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read checksums from {path:?}"))?;
+        return checksum::verify(&read(input)?, &expected);
+    }
+    let mut file = timed(&mut times.read_input, || InputFile::open(input))
+        .wrap_err_with(|| format!("Failed to read source image from {:?}", input))?;
 
     let output_format = opt
         .output
         .as_ref()
-        .map(|f| OutputFormat::from_output_filename(&f.to_string_lossy()))
-        .transpose()?;
+        .map(|f| OutputFormat::for_output(f, opt.output_format))
+        .transpose()
+        .usage_context("Invalid output")?;
+
+    if reporter.image_to_stdout {
+        if opt.cache_dir.is_some() {
+            return Err(eyre!("The cache only stores output files"))
+                .usage_context("Invalid --cache-dir");
+        }
+        if opt.preview_terminal {
+            return Err(eyre!("Standard output already carries the output image"))
+                .usage_context("Invalid --preview-terminal");
+        }
+    }
+
+    let endianness = opt.output_endianness.to_endianness();
+    if endianness != Endianness::native() && !output_format.is_some_and(|f| f.supports_endianness())
+    {
+        return Err(eyre!(
+            "Only npy output can be written in a non-native byte order"
+        ))
+        .usage_context("Invalid --output-endianness");
+    }
+    if opt.column_major && !output_format.is_some_and(|f| f.supports_column_major()) {
+        return Err(eyre!(
+            "Only npy output can be written in column-major order"
+        ))
+        .usage_context("Invalid --column-major");
+    }
+    let output_layout = if opt.column_major {
+        OutputLayout::ColumnMajor
+    } else {
+        OutputLayout::RowMajor
+    };
 
     let high_precision = opt.high_precision;
+    // Spot colors are kept as separate channels in npy outputs.
+    let render_spot_colors = !matches!(output_format, Some(OutputFormat::Npy));
+    let compute_frame_diffs = opt.verbose && opt.list_frames;
+    // Frames are listed as they are decoded, except when scanning for a stream, where they are
+    // only listed once a stream was decoded completely.
+    let stream_frame_list = opt.list_frames && !opt.scan;
+    let resize_filter = opt.resize_filter;
+    let prefer_icc_profile = opt.prefer_icc_profile;
+    let reject_size_mismatch = opt.reject_size_mismatch;
+    let skip_corrupt_sections = opt.skip_corrupt_sections;
+    let rendering = opt.rendering;
+    let speed_profile = opt.speed_profile;
+    #[cfg(feature = "timing-stats")]
+    let print_stats = opt.print_stats;
+    #[cfg(not(feature = "timing-stats"))]
+    let print_stats = false;
+    #[cfg(feature = "verify")]
+    let verify = opt.verify;
+    #[cfg(not(feature = "verify"))]
+    let verify = false;
+    #[cfg(feature = "debug-tools")]
+    let dump_entropy = opt.dump_entropy.is_some();
+    #[cfg(not(feature = "debug-tools"))]
+    let dump_entropy = false;
+    let crop = opt.crop;
     let options = |skip_preview: bool| {
         let mut options = JxlDecoderOptions::default();
-        options.render_spot_colors = !matches!(output_format, Some(OutputFormat::Npy));
+        options.render_spot_colors = render_spot_colors;
         options.skip_preview = skip_preview;
         options.high_precision = high_precision;
+        options.compute_frame_diffs = compute_frame_diffs;
+        options.resize_filter = resize_filter;
+        options.crop = crop;
+        options.prefer_icc_profile = prefer_icc_profile;
+        options.reject_size_mismatch = reject_size_mismatch;
+        options.skip_corrupt_sections = skip_corrupt_sections;
+        options.rendering_intent_override = rendering;
+        options.output_layout = output_layout;
+        options.speed_profile = speed_profile;
+        options.verify_modular = verify;
+        options.dump_entropy_codes = dump_entropy;
         options.cms = Some(Box::new(Lcms2Cms));
         options
     };
@@ -124,23 +1131,87 @@ fn main() -> Result<()> {
     // Handle --info flag: print image info and exit
     if opt.info {
         let mut reader = BufReader::new(&mut file);
-        let decoder = dec::decode_header(&mut reader, options(true))?;
-        let info = decoder.basic_info();
-        println!("Image size: {}x{}", info.size.0, info.size.1);
-        println!("Bit depth: {:?}", info.bit_depth);
-        println!("Orientation: {:?}", info.orientation);
+        let mut info_options = options(true);
+        info_options.scan_frames_only = true;
+        let decoder = dec::decode_header(&mut reader, info_options)?;
+        let info = decoder.basic_info().clone();
+        if reporter.json {
+            let color_profile = decoder.embedded_color_profile().describe();
+            let output_color_profile = decoder.output_color_profile().describe();
+            let summary = dec::scan_compression_summary(&mut reader, decoder)?;
+            return reporter.json(&InfoJson {
+                width: info.size.0,
+                height: info.size.1,
+                bits_per_sample: info.bit_depth.bits_per_sample(),
+                exponent_bits_per_sample: info.bit_depth.exponent_bits(),
+                orientation: format!("{:?}", info.orientation),
+                color_profile,
+                output_color_profile,
+                preview_size: info.preview_size,
+                animation: info.animation.as_ref().map(|anim| AnimationJson {
+                    num_loops: anim.num_loops,
+                    tps_numerator: anim.tps_numerator,
+                    tps_denominator: anim.tps_denominator,
+                    have_timecodes: anim.have_timecodes,
+                }),
+                extra_channels: info.extra_channels.len(),
+                extra_channel_info: info
+                    .extra_channels
+                    .iter()
+                    .map(ExtraChannelJson::new)
+                    .collect(),
+                compression: summary.to_string(),
+            });
+        }
+        dataln!("Image size: {}x{}", info.size.0, info.size.1);
+        dataln!("Bit depth: {:?}", info.bit_depth);
+        dataln!("Orientation: {:?}", info.orientation);
+        dataln!(
+            "Color profile: {}",
+            decoder.embedded_color_profile().describe()
+        );
+        dataln!(
+            "Output color profile: {}",
+            decoder.output_color_profile().describe()
+        );
+        #[cfg(feature = "debug-tools")]
+        if let (true, jxl::api::JxlColorProfile::Icc(icc)) =
+            (opt.verbose, decoder.embedded_color_profile())
+        {
+            let issues = jxl::api::validate_icc(icc);
+            if issues.is_empty() {
+                dataln!("ICC profile issues: none");
+            }
+            for issue in issues {
+                dataln!("ICC profile issue: {issue}");
+            }
+        }
         if let Some(preview_size) = info.preview_size {
-            println!("Preview size: {}x{}", preview_size.0, preview_size.1);
+            dataln!("Preview size: {}x{}", preview_size.0, preview_size.1);
         } else {
-            println!("Preview: none");
+            dataln!("Preview: none");
         }
         if let Some(anim) = &info.animation {
-            println!(
-                "Animation: {} loops, {}/{} tps",
-                anim.num_loops, anim.tps_numerator, anim.tps_denominator
+            dataln!(
+                "Animation: {} loops, {}/{} tps{}",
+                anim.num_loops,
+                anim.tps_numerator,
+                anim.tps_denominator,
+                if anim.have_timecodes {
+                    ", with timecodes"
+                } else {
+                    ""
+                }
             );
+        } else {
+            dataln!("Animation: none");
         }
-        println!("Extra channels: {}", info.extra_channels.len());
+        dataln!("Extra channels: {}", info.extra_channels.len());
+        for (i, channel) in info.extra_channels.iter().enumerate() {
+            dataln!("Extra channel {i}: {}", describe_extra_channel(channel));
+        }
+        let summary = dec::scan_compression_summary(&mut reader, decoder)?;
+        dataln!("Compression: {summary}");
         return Ok(());
     }
 
@@ -150,27 +1221,199 @@ fn main() -> Result<()> {
         let decoder = dec::decode_header(&mut reader, options(true))?;
         let info = decoder.basic_info();
         if info.preview_size.is_none() {
-            return Err(eyre!("This file does not contain a preview frame"));
+            return Err(eyre!("This file does not contain a preview frame"))
+                .usage_context("Cannot extract the preview");
         }
         // Seek back to start for actual decoding
         file.seek(std::io::SeekFrom::Start(0))?;
     }
 
-    let mut duration_sum = Duration::new(0, 0);
+    if let Some(crop) = opt.crop {
+        let decoder = dec::decode_header(&mut BufReader::new(&mut file), options(true))?;
+        let (width, height) = decoder.basic_info().size;
+        let fits = |origin: usize, size: usize, image: usize| {
+            origin.checked_add(size).is_some_and(|end| end <= image)
+        };
+        if !fits(crop.origin.0, crop.size.0, width) || !fits(crop.origin.1, crop.size.1, height) {
+            return Err(eyre!(
+                "The region of {}x{} pixels at ({}, {}) does not lie within the image of \
+                 {width}x{height} pixels",
+                crop.size.0,
+                crop.size.1,
+                crop.origin.0,
+                crop.origin.1
+            ))
+            .usage_context("Invalid --crop");
+        }
+        file.seek(std::io::SeekFrom::Start(0))?;
+    }
+
+    // Animations are written one file per frame if the output names the files of the frames, or
+    // if the format only holds one frame and the image has more than one. A preview is a single
+    // image, and images whose frames cannot be counted keep a single output.
+    let frame_files = match (opt.output.as_deref(), output_format) {
+        (Some(path), Some(format)) if !reporter.image_to_stdout => {
+            if let Some(files) = FrameFiles::from_pattern(path) {
+                Some(files)
+            } else if format.holds_single_frame() && !opt.preview {
+                let num_frames =
+                    dec::count_frames(&mut BufReader::new(&mut file), options(true), &opt.frames);
+                file.seek(std::io::SeekFrom::Start(0))?;
+                num_frames
+                    .is_ok_and(|n| n > 1)
+                    .then(|| FrameFiles::numbered(path))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    if frame_files.is_some() && opt.cache_dir.is_some() {
+        return Err(eyre!("The cache only stores outputs of a single file"))
+            .usage_context("Invalid --cache-dir");
+    }
+
+    let cache = match &opt.cache_dir {
+        Some(dir) => {
+            let input_bytes = InputBytes::new(&mut file, opt.mmap)?;
+            file.seek(std::io::SeekFrom::Start(0))?;
+            let key = CacheKey::new(&input_bytes, &cache_fingerprint(opt, output_format));
+            Some((DecodeCache::new(dir, opt.cache_max_bytes)?, key))
+        }
+        None => None,
+    };
+    if let Some((cache, key)) = &cache
+        && !opt.cache_verify
+        && !verify
+        && cache
+            .restore(key, opt.output.as_ref().unwrap())
+            .output_context(|| "Failed to restore the output from the cache")?
+    {
+        reporter.detail(format_args!(
+            "Restored {:?} from the cache",
+            opt.output.as_ref().unwrap()
+        ));
+        return Ok(());
+    }
+
+    let image_file = opt
+        .output
+        .as_deref()
+        .filter(|_| output_format.is_some() && frame_files.is_none());
+    let (mut image_file, icc_file, original_icc_file) = timed(&mut times.write, || {
+        Ok::<_, color_eyre::Report>((
+            image_file.map(open_output).transpose()?,
+            opt.icc_out.as_deref().map(open_output).transpose()?,
+            opt.original_icc_out
+                .as_deref()
+                .map(open_output)
+                .transpose()?,
+        ))
+    })?;
+    #[cfg(feature = "debug-tools")]
+    let dump_entropy_file = opt.dump_entropy.as_deref().map(open_output).transpose()?;
+    let speedtest_json_file = opt.speedtest_json.as_deref().map(open_output).transpose()?;
+
+    if opt.preallocate
+        && let (Some(format), Some(image_file)) = (output_format, &mut image_file)
+    {
+        let mut reader = BufReader::new(&mut file);
+        let decoder = dec::decode_header(&mut reader, options(true))?;
+        let mut shape = dec::OutputShape::of_image(
+            &decoder,
+            opt.override_bitdepth,
+            opt.data_type.or(format.default_output_data_type()),
+            format.supported_output_data_types(),
+            format.should_fold_alpha(),
+            output_size(opt),
+            output_layout,
+        );
+        if let Some(preview_size) = decoder.basic_info().preview_size.filter(|_| opt.preview) {
+            shape.size = preview_size;
+        }
+        if let Some(crop) = opt.crop {
+            shape.size = output_size(opt).map_or(crop.size, |s| s.for_image(crop.size));
+        }
+        // Without a selection, the number of frames is not known before decoding.
+        shape.num_frames = opt
+            .frames
+            .iter()
+            .map(dec::FrameSelector::min_frames)
+            .sum::<usize>()
+            .max(1);
+        let len = format.estimate_size(&shape);
+        reporter.detail(format_args!("Preallocating {len} bytes for the output"));
+        image_file
+            .preallocate(len)
+            .output_context(|| format!("Failed to preallocate {len} bytes for the output"))?;
+        file.seek(std::io::SeekFrom::Start(0))?;
+    }
+
+    let mut speedtest = Speedtest::default();
     // When extracting preview, don't skip it; otherwise skip preview by default
     let skip_preview = !opt.preview;
 
+    let pool = dec::BufferPool::new(opt.frame_pool_depth);
+
+    // Frames are written as they are decoded, unless they are still needed after the decoding.
+    // Streams found by --scan are only known once they were decoded completely, and the decodes
+    // of --speedtest would also time the writing.
+    let needs_frames = opt.speedtest
+        || opt.scan
+        || opt.preview
+        || opt.preview_terminal
+        || opt.render_interval.is_some()
+        || (opt.list_frames && !stream_frame_list)
+        || print_stats
+        || verify
+        || dump_entropy;
+    let mut frame_output = match output_format.zip(image_file.take_if(|_| !needs_frames)) {
+        Some((format, image_file)) => {
+            let path = image_file.path().to_path_buf();
+            // Outputs that need the number of frames up front get it from a walk over the frame
+            // headers, after which the input is read again from the start.
+            let count_frames = || {
+                let num_frames = dec::count_frames(
+                    &mut BufReader::new(&mut file),
+                    options(skip_preview),
+                    &opt.frames,
+                );
+                file.seek(std::io::SeekFrom::Start(0))?;
+                num_frames
+            };
+            Some(
+                format
+                    .start_output(image_file, count_frames)
+                    .output_context(|| format!("Failed to write {path:?}"))?,
+            )
+        }
+        None if !needs_frames => output_format
+            .zip(frame_files.clone())
+            .map(|(format, files)| format.start_frame_files(files)),
+        None => None,
+    };
+
+    #[cfg(feature = "exr")]
+    let linear_output = matches!(output_format, Some(OutputFormat::Exr));
+    #[cfg(not(feature = "exr"))]
+    let linear_output = false;
+
+    // Frames that are not kept, because they are not selected or because the output of the
+    // decoding is not used, return their buffers to the pool as soon as they are decoded.
     macro_rules! run_decoder {
-        ($input: expr) => {{
-            #[cfg(feature = "exr")]
-            let linear_output = matches!(output_format, Some(OutputFormat::Exr));
-            #[cfg(not(feature = "exr"))]
-            let linear_output = false;
-            let (mut output, duration) = dec::decode_frames(
+        ($input: expr) => {
+            run_decoder!($input, true)
+        };
+        ($input: expr, $keep_frames: expr) => {{
+            let mut frames = vec![];
+            let mut num_listed = 0;
+            let mut selection = dec::FrameSelection::new(&opt.frames);
+            let (mut output, time) = dec::decode_frames_leased(
                 $input,
                 options(skip_preview),
                 opt.override_bitdepth,
-                opt.data_type,
+                opt.data_type
+                    .or(output_format.and_then(|x| x.default_output_data_type())),
                 output_format
                     .map(|x| x.supported_output_data_types())
                     .unwrap_or(OutputDataType::ALL),
@@ -178,40 +1421,97 @@ fn main() -> Result<()> {
                 linear_output,
                 opt.render_interval,
                 opt.allow_partial_files,
+                (opt.max_tokens_per_pixel != 0).then_some(opt.max_tokens_per_pixel),
+                output_size(opt),
+                endianness,
+                &pool,
+                // Frames that are not selected are skipped without rendering them.
+                |name| {
+                    if opt.frames.is_empty() {
+                        dec::FrameAction::Decode
+                    } else {
+                        selection.next_action(name)
+                    }
+                },
+                |image, frame| {
+                    let selected = !opt.preview || frames.is_empty();
+                    if $keep_frames && selected && stream_frame_list {
+                        print_frame_line(num_listed, &frame, opt.verbose);
+                        num_listed += 1;
+                    }
+                    if $keep_frames && selected {
+                        match &mut frame_output {
+                            Some(frame_output) => {
+                                frame_output.push_frame(image, frame).output_context(|| {
+                                    format!("Failed to write {:?}", opt.output.as_ref().unwrap())
+                                })?
+                            }
+                            None => frames.push(frame.into_owned()),
+                        }
+                    }
+                    Ok(())
+                },
             )?;
-            if opt.preview {
-                output.frames.truncate(1);
-                let ctype = output.frames[0].color_type;
-                let bsize = output.frames[0].channels[0].byte_size();
+            if !opt.frames.is_empty() {
+                selection
+                    .finish()
+                    .usage_context("Invalid frame selection")?;
+            }
+            output.frames = frames;
+            times.add_decode(&time);
+            if opt.preview
+                && let Some(frame) = output.frames.first()
+            {
+                let ctype = frame.color_type;
+                let bsize = frame.channels[0].byte_size();
                 let bytes_per_pixel =
                     ctype.samples_per_pixel() * output.data_type.bits_per_sample() / 8;
-                output.size = (bsize.0 / bytes_per_pixel, bsize.1);
+                output.size = match output.layout {
+                    OutputLayout::RowMajor => (bsize.0 / bytes_per_pixel, bsize.1),
+                    OutputLayout::ColumnMajor => (bsize.1, bsize.0 / bytes_per_pixel),
+                };
             }
-            (output, duration)
+            (output, time)
         }};
     }
 
     // For benchmarking, always read into memory to avoid I/O variability
-    let output = if opt.speedtest {
-        let mut input_bytes = Vec::<u8>::new();
-        file.read_to_end(&mut input_bytes)?;
+    let mut output = if opt.speedtest {
+        let input_bytes = timed(&mut times.read_input, || {
+            let input_bytes = InputBytes::new(&mut file, opt.mmap)?;
+            // Mapped files are only loaded as they are accessed, which should not be timed.
+            input_bytes.touch_pages();
+            Ok::<_, color_eyre::Report>(input_bytes)
+        })?;
 
         for _ in 0..opt.warmup_reps {
-            run_decoder!(&mut input_bytes.as_slice());
+            run_decoder!(&mut &input_bytes[..], false);
         }
 
         let mut last_output = None;
 
-        for _ in 0..opt.num_reps {
-            let (output, duration) = run_decoder!(&mut input_bytes.as_slice());
-            duration_sum += duration;
+        for rep in 0..opt.num_reps {
+            // Only the frames of the last decoding are written.
+            let (output, time) = run_decoder!(&mut &input_bytes[..], rep + 1 == opt.num_reps);
+            speedtest.reps.push(time.total());
             last_output = Some(output);
         }
         last_output.unwrap()
+    } else if opt.scan {
+        let (offset, (output, _)) = dec::decode_embedded(&mut file, |file| {
+            Ok(run_decoder!(&mut BufReader::new(file)))
+        })?;
+        reporter.note(format_args!("Decoded JPEG XL stream at offset {offset}"));
+        output
     } else if opt.render_interval.is_some() {
-        let mut input_bytes = Vec::<u8>::new();
-        file.read_to_end(&mut input_bytes)?;
-        run_decoder!(&mut input_bytes.as_slice()).0
+        let input_bytes = timed(&mut times.read_input, || {
+            InputBytes::new(&mut file, opt.mmap)
+        })?;
+        run_decoder!(&mut &input_bytes[..]).0
+    } else if opt.mmap
+        && let Some(input_bytes) = timed(&mut times.read_input, || InputBytes::map(&file))
+    {
+        run_decoder!(&mut &input_bytes[..]).0
     } else {
         // For single decode without speedtest, stream from file
         run_decoder!(&mut BufReader::new(file)).0
@@ -221,12 +1521,25 @@ fn main() -> Result<()> {
     let output_icc = output.output_profile.as_icc().to_vec();
     let embedded_icc = output.embedded_profile.as_icc().to_vec();
     let image_size = output.size;
+    reporter.detail(format_args!(
+        "Decoded {} frame(s) of {}x{} pixels as {:?}",
+        frame_output
+            .as_ref()
+            .map_or(output.frames.len(), FrameOutput::num_frames),
+        image_size.0,
+        image_size.1,
+        output.data_type
+    ));
+    reporter.detail(format_args!(
+        "Allocated {} output buffer(s)",
+        pool.allocations()
+    ));
 
-    if opt.speedtest {
+    if opt.speedtest && !reporter.json {
         let num_pixels = image_size.0 * image_size.1;
-        let duration_seconds = duration_sum.as_secs_f64();
+        let duration_seconds = speedtest.total().as_secs_f64();
         let avg_seconds = duration_seconds / opt.num_reps as f64;
-        println!(
+        dataln!(
             "Decoded {} pixels in {:.3} seconds: {:.3} MP/s",
             opt.num_reps * num_pixels,
             duration_seconds,
@@ -234,12 +1547,133 @@ fn main() -> Result<()> {
         );
     }
 
-    if let Some(output_format) = output_format {
-        output_format.save_image(&output, opt.output.as_ref().unwrap())?;
+    #[cfg(feature = "timing-stats")]
+    if opt.print_stats {
+        let total = output.timings.total();
+        for (stage, time) in output.timings.iter() {
+            dataln!(
+                "{:>20}: {:9.3} ms ({:5.1}%)",
+                stage.name(),
+                time.as_secs_f64() * 1e3,
+                100.0 * time.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE)
+            );
+        }
+        dataln!("{:>20}: {:9.3} ms", "total", total.as_secs_f64() * 1e3);
+        dataln!("{:>20}: {}", "SIMD", simd_description());
+        for (i, frame) in output.frames.iter().enumerate() {
+            if let Some(stats) = &frame.modular_stats {
+                print_modular_stats(i, stats);
+            }
+        }
+    }
+
+    #[cfg(feature = "verify")]
+    if opt.verify {
+        print_modular_checks(&output.frames)?;
     }
 
-    save_icc(&output_icc, opt.icc_out.as_ref())?;
-    save_icc(&embedded_icc, opt.original_icc_out.as_ref())?;
+    #[cfg(feature = "debug-tools")]
+    if let Some(dump_file) = dump_entropy_file {
+        let codes: Vec<_> = output
+            .frames
+            .iter()
+            .flat_map(|frame| frame.entropy_codes.iter().flatten())
+            .map(EntropyCodeJson::new)
+            .collect();
+        let json = serde_json::to_string_pretty(&EntropyDumpJson { codes })?;
+        let path = dump_file.path().to_path_buf();
+        dump_file
+            .write(|writer| Ok(writer.write_all(json.as_bytes())?))
+            .output_context(|| format!("Failed to write entropy codes to {path:?}"))?;
+    }
 
+    if opt.list_frames && !stream_frame_list {
+        for (i, frame) in output.frames.iter().enumerate() {
+            print_frame_line(i, frame, opt.verbose);
+        }
+    }
+
+    if let Some(frame_output) = frame_output {
+        // The decoding counted the time spent writing the frames as handing them over.
+        times.convert = times.convert.saturating_sub(frame_output.time());
+        let (encode_time, write_time) = frame_output
+            .finish(&output)
+            .output_context(|| format!("Failed to write {:?}", opt.output.as_ref().unwrap()))?;
+        times.encode += encode_time;
+        times.write += write_time;
+    }
+
+    if let (Some(output_format), Some(image_file)) = (output_format, image_file) {
+        let path = image_file.path().to_path_buf();
+        let start = Instant::now();
+        let write_time = output_format
+            .save_image(&output, image_file)
+            .output_context(|| format!("Failed to write {path:?}"))?;
+        times.encode += start.elapsed().saturating_sub(write_time);
+        times.write += write_time;
+    } else if let (Some(output_format), Some(files)) = (output_format, &frame_files)
+        && needs_frames
+    {
+        let start = Instant::now();
+        let write_time = output_format
+            .save_frame_files(&mut output, files)
+            .output_context(|| format!("Failed to write {:?}", opt.output.as_ref().unwrap()))?;
+        times.encode += start.elapsed().saturating_sub(write_time);
+        times.write += write_time;
+    }
+
+    if let Some((cache, key)) = &cache {
+        if opt.cache_verify {
+            cache.verify(key, opt.output.as_ref().unwrap())?;
+        } else {
+            cache
+                .insert(key, opt.output.as_ref().unwrap())
+                .output_context(|| "Failed to add the output to the cache")?;
+        }
+    }
+
+    if opt.preview_terminal {
+        let protocol = term::Protocol::from_env();
+        term::write_preview(
+            &output,
+            protocol,
+            term::terminal_size(),
+            &mut std::io::stdout().lock(),
+        )
+        .output_context(|| "Failed to write the preview")?;
+    }
+
+    times.write += save_icc(&output_icc, icc_file)?;
+    times.write += save_icc(&embedded_icc, original_icc_file)?;
+
+    times.total = run_start.elapsed();
+    if opt.time && !reporter.json {
+        data!("{}", times.table());
+    }
+    if opt.speedtest && (reporter.json || speedtest_json_file.is_some()) {
+        let json = speedtest.to_json(
+            opt.input.as_ref().unwrap().display().to_string(),
+            VERSION_STRING,
+            image_size.0 * image_size.1,
+            SpeedtestOptionsJson {
+                linear_output,
+                render_spot_colors,
+                high_precision,
+                data_type: format!("{:?}", output.data_type),
+            },
+            opt.time.then(|| times.to_json()),
+        );
+        if let Some(file) = speedtest_json_file {
+            let path = file.path().to_path_buf();
+            let text = serde_json::to_string_pretty(&json)?;
+            file.write(|writer| Ok(writer.write_all(text.as_bytes())?))
+                .output_context(|| format!("Failed to write the speed measurement to {path:?}"))?;
+        }
+        if reporter.json {
+            reporter.json(&json)?;
+        }
+    } else if opt.time && reporter.json {
+        reporter.json(&times.to_json())?;
+    }
     Ok(())
 }
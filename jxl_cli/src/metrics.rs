@@ -0,0 +1,93 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Image comparison metrics, shared by `progressive-sim` and by tests that compare decoded
+//! images.
+
+use color_eyre::eyre::{Result, bail};
+use jxl::image::OwnedRawImage;
+
+use crate::dec::OutputDataType;
+
+/// Computes the PSNR of `image` against `reference`, in dB, over the samples of all channels.
+/// Both images must have the same layout and integer samples of `data_type`. Returns infinity if
+/// the images are identical.
+pub fn psnr(
+    image: &[OwnedRawImage],
+    reference: &[OwnedRawImage],
+    data_type: OutputDataType,
+) -> Result<f64> {
+    let bytes = match data_type {
+        OutputDataType::U8 => 1,
+        OutputDataType::U16 => 2,
+        _ => bail!("PSNR is only computed for integer samples, not {data_type:?}"),
+    };
+    if image.len() != reference.len()
+        || image
+            .iter()
+            .zip(reference)
+            .any(|(a, b)| a.byte_size() != b.byte_size())
+    {
+        bail!("Cannot compare images of different sizes");
+    }
+    let sample = |row: &[u8], i: usize| {
+        if bytes == 1 {
+            row[i] as f64
+        } else {
+            u16::from_ne_bytes([row[i * 2], row[i * 2 + 1]]) as f64
+        }
+    };
+    let mut sum_squared_error = 0.0;
+    let mut num_samples = 0;
+    for (a, b) in image.iter().zip(reference) {
+        let (width, height) = a.byte_size();
+        for y in 0..height {
+            let (row_a, row_b) = (a.row(y), b.row(y));
+            for i in 0..width / bytes {
+                let diff = sample(row_a, i) - sample(row_b, i);
+                sum_squared_error += diff * diff;
+            }
+        }
+        num_samples += width / bytes * height;
+    }
+    if sum_squared_error == 0.0 {
+        return Ok(f64::INFINITY);
+    }
+    let max = ((1u32 << (8 * bytes)) - 1) as f64;
+    let mse = sum_squared_error / num_samples as f64;
+    Ok(10.0 * (max * max / mse).log10())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::make_test_image;
+    use jxl::api::JxlColorType;
+
+    #[test]
+    fn psnr_of_known_error() {
+        let reference = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (4, 3)).frames;
+        let mut image: Vec<_> = reference[0]
+            .channels
+            .iter()
+            .map(|c| c.try_clone().unwrap())
+            .collect();
+        let reference = &reference[0].channels;
+        assert_eq!(
+            psnr(&image, reference, OutputDataType::U8).unwrap(),
+            f64::INFINITY
+        );
+        // Offset every sample by 1 (without overflowing), for an MSE of 1.
+        let (width, height) = image[0].byte_size();
+        for y in 0..height {
+            for v in image[0].row_mut(y)[..width].iter_mut() {
+                *v = if *v == 255 { 254 } else { *v + 1 };
+            }
+        }
+        let expected = 20.0 * 255f64.log10();
+        let actual = psnr(&image, reference, OutputDataType::U8).unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{actual} vs {expected}");
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Wall-clock durations of the phases of a run of `jxl_cli`, printed with `--time`.
+//!
+//! Unlike the decoding stages measured with the `timing-stats` feature, the phases are measured
+//! by the command line tool itself, so they are available in every build.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::dec::DecodeTime;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PhaseTimes {
+    /// Opening the input and reading it into memory, if it is not streamed while decoding.
+    pub read_input: Duration,
+    /// Reading the container and the image header, summed over repeated decodes.
+    pub headers: Duration,
+    /// Decoding each frame, summed over repeated decodes.
+    pub frames: Vec<Duration>,
+    /// Handing the decoded frames over, summed over repeated decodes.
+    pub convert: Duration,
+    /// Encoding the output image.
+    pub encode: Duration,
+    /// Opening the output files and writing to them.
+    pub write: Duration,
+    /// The whole run, including the time between phases.
+    pub total: Duration,
+}
+
+impl PhaseTimes {
+    /// Adds the time of one decoding, frame by frame.
+    pub fn add_decode(&mut self, time: &DecodeTime) {
+        self.headers += time.headers;
+        if self.frames.len() < time.frames.len() {
+            self.frames.resize(time.frames.len(), Duration::ZERO);
+        }
+        for (sum, frame) in self.frames.iter_mut().zip(&time.frames) {
+            *sum += *frame;
+        }
+        self.convert += time.convert;
+    }
+
+    /// Names and durations of the phases, in the order in which they happen.
+    pub fn phases(&self) -> Vec<(String, Duration)> {
+        let mut phases = vec![
+            ("read input".to_string(), self.read_input),
+            ("container+headers".to_string(), self.headers),
+        ];
+        for (i, frame) in self.frames.iter().enumerate() {
+            phases.push((format!("frame {i}"), *frame));
+        }
+        phases.extend([
+            ("convert".to_string(), self.convert),
+            ("encode output".to_string(), self.encode),
+            ("write output".to_string(), self.write),
+        ]);
+        phases
+    }
+
+    /// Formats the phases and the total as a table with one `name: time ms (share%)` line each.
+    pub fn table(&self) -> String {
+        let total_ms = self.total.as_secs_f64() * 1e3;
+        let mut table = String::new();
+        for (name, time) in self.phases() {
+            let ms = time.as_secs_f64() * 1e3;
+            let share = 100.0 * ms / total_ms.max(f64::MIN_POSITIVE);
+            writeln!(table, "{name:>20}: {ms:9.3} ms ({share:5.1}%)").unwrap();
+        }
+        writeln!(table, "{:>20}: {total_ms:9.3} ms", "total").unwrap();
+        table
+    }
+
+    pub fn to_json(&self) -> PhaseTimesJson {
+        let ms = |time: Duration| time.as_secs_f64() * 1e3;
+        PhaseTimesJson {
+            read_input_ms: ms(self.read_input),
+            headers_ms: ms(self.headers),
+            frames_ms: self.frames.iter().copied().map(ms).collect(),
+            convert_ms: ms(self.convert),
+            encode_ms: ms(self.encode),
+            write_ms: ms(self.write),
+            total_ms: ms(self.total),
+        }
+    }
+}
+
+/// The output of `--time --json`, in milliseconds.
+#[derive(Serialize)]
+pub struct PhaseTimesJson {
+    pub read_input_ms: f64,
+    pub headers_ms: f64,
+    pub frames_ms: Vec<f64>,
+    pub convert_ms: f64,
+    pub encode_ms: f64,
+    pub write_ms: f64,
+    pub total_ms: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_decodes_add_up_per_frame() {
+        let ms = Duration::from_millis;
+        let mut times = PhaseTimes::default();
+        times.add_decode(&DecodeTime {
+            headers: ms(1),
+            frames: vec![ms(10)],
+            convert: ms(2),
+        });
+        times.add_decode(&DecodeTime {
+            headers: ms(1),
+            frames: vec![ms(20), ms(5)],
+            convert: ms(3),
+        });
+        assert_eq!(times.headers, ms(2));
+        assert_eq!(times.frames, [ms(30), ms(5)]);
+        assert_eq!(times.convert, ms(5));
+        times.total = ms(50);
+        let table = times.table();
+        let lines: Vec<_> = table.lines().map(str::trim_start).collect();
+        assert!(lines.contains(&"frame 1:     5.000 ms ( 10.0%)"), "{table}");
+        assert_eq!(lines.last(), Some(&"total:    50.000 ms"), "{table}");
+    }
+}
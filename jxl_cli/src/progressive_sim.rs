@@ -0,0 +1,162 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Simulates the progressive display of a partially downloaded file, by decoding successive
+//! prefixes of it and comparing each partial render with the final image.
+
+use std::str::FromStr;
+
+use color_eyre::eyre::Result;
+use jxl::api::{Endianness, JxlDecoderOptions};
+
+use crate::dec::{self, DecodeOutput, OutputDataType};
+use crate::metrics::psnr;
+
+/// A prefix of the file to decode, either as a percentage of its size or as a number of bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteBudget {
+    Percent(f64),
+    Bytes(usize),
+}
+
+impl ByteBudget {
+    pub fn bytes(self, file_size: usize) -> usize {
+        match self {
+            Self::Percent(p) => ((file_size as f64 * p / 100.0).round() as usize).min(file_size),
+            Self::Bytes(b) => b.min(file_size),
+        }
+    }
+}
+
+impl FromStr for ByteBudget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(percent) = s.strip_suffix('%') {
+            match percent.parse() {
+                Ok(p) if (0.0..=100.0).contains(&p) => Ok(Self::Percent(p)),
+                _ => Err(format!("Invalid percentage {s}, expected 0% to 100%")),
+            }
+        } else {
+            s.parse().map(Self::Bytes).map_err(|_| {
+                format!("Invalid byte budget {s}, expected a number of bytes or a percentage")
+            })
+        }
+    }
+}
+
+/// The state of the decoder after one prefix of the file.
+pub struct SimStep {
+    pub bytes: usize,
+    /// The partial render, or `None` if the prefix does not contain the image header.
+    pub output: Option<DecodeOutput>,
+    /// Number of frames that were fully decoded.
+    pub complete_frames: usize,
+    /// Completed passes and total passes of the last frame, if any.
+    pub passes: Option<(usize, usize)>,
+    /// PSNR of the first frame against the final image, in dB.
+    pub psnr: Option<f64>,
+}
+
+/// Decodes the prefixes of `data` given by `budgets` to 16-bit samples, flushing the pixels that
+/// are available at the end of each prefix. `options` creates the options of each decoder.
+pub fn simulate(
+    data: &[u8],
+    budgets: &[ByteBudget],
+    options: impl Fn() -> JxlDecoderOptions,
+) -> Result<Vec<SimStep>> {
+    let decode = |prefix: &[u8], allow_partial_files| {
+        let (output, _) = dec::decode_frames(
+            &mut &prefix[..],
+            options(),
+            Some(16),
+            Some(OutputDataType::U16),
+            &[OutputDataType::U16],
+            false,
+            false,
+            None,
+            allow_partial_files,
+            None,
+            None,
+            Endianness::native(),
+        )?;
+        Ok::<_, color_eyre::Report>(output)
+    };
+    let final_output = decode(data, false)?;
+    let reference = &final_output.frames[0].channels;
+
+    budgets
+        .iter()
+        .map(|budget| {
+            let prefix = &data[..budget.bytes(data.len())];
+            if dec::decode_header(&mut &prefix[..], options()).is_err() {
+                return Ok(SimStep {
+                    bytes: prefix.len(),
+                    output: None,
+                    complete_frames: 0,
+                    passes: None,
+                    psnr: None,
+                });
+            }
+            let output = decode(prefix, true)?;
+            let complete_frames = output
+                .frames
+                .iter()
+                .filter(|f| f.completed_passes == f.passes.num_passes())
+                .count();
+            let passes = output
+                .frames
+                .last()
+                .map(|f| (f.completed_passes, f.passes.num_passes()));
+            let psnr = psnr(&output.frames[0].channels, reference, output.data_type)?;
+            Ok(SimStep {
+                bytes: prefix.len(),
+                output: Some(output),
+                complete_frames,
+                passes,
+                psnr: Some(psnr),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_budgets() {
+        assert_eq!(
+            "5%".parse::<ByteBudget>().ok(),
+            Some(ByteBudget::Percent(5.0))
+        );
+        assert_eq!(
+            "1000".parse::<ByteBudget>().ok(),
+            Some(ByteBudget::Bytes(1000))
+        );
+        assert!("150%".parse::<ByteBudget>().is_err());
+        assert!("x".parse::<ByteBudget>().is_err());
+        assert_eq!(ByteBudget::Percent(50.0).bytes(101), 51);
+        assert_eq!(ByteBudget::Bytes(1000).bytes(10), 10);
+    }
+
+    #[test]
+    fn psnr_increases_with_budget() {
+        let root = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../jxl/resources/test");
+        let data = std::fs::read(root.join("green_queen_vardct_e3.jxl")).unwrap();
+        let budgets: Vec<_> = [5.0, 10.0, 25.0, 50.0, 75.0, 100.0]
+            .into_iter()
+            .map(ByteBudget::Percent)
+            .collect();
+        let steps = simulate(&data, &budgets, JxlDecoderOptions::default).unwrap();
+        let psnrs: Vec<f64> = steps
+            .iter()
+            .map(|s| s.psnr.unwrap_or(f64::NEG_INFINITY))
+            .collect();
+        assert!(psnrs.windows(2).all(|w| w[0] <= w[1]), "{psnrs:?}");
+        assert_eq!(*psnrs.last().unwrap(), f64::INFINITY);
+        assert_eq!(steps.last().unwrap().passes, Some((1, 1)));
+    }
+}
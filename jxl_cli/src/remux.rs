@@ -0,0 +1,175 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::Write;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use jxl::container::{
+    box_header::{ContainerBoxHeader, ContainerBoxType, HeaderParseResult},
+    writer::ContainerWriter,
+};
+
+const CODESTREAM_SIGNATURE: [u8; 2] = [0xff, 0x0a];
+
+/// Metadata to add to a file with [`remux`].
+#[derive(Debug, Default)]
+pub struct Metadata {
+    /// Exif data in TIFF format, optionally preceded by the `Exif\0\0` marker of JPEG files.
+    pub exif: Option<Vec<u8>>,
+    /// XMP packet.
+    pub xmp: Option<Vec<u8>>,
+    /// Compress the metadata boxes with Brotli.
+    pub compress: bool,
+}
+
+/// Writes `input`, which is either a bare codestream or a container, to `out` as a container with
+/// the given metadata added before all the other boxes. The codestream is copied unchanged.
+pub fn remux(input: &[u8], metadata: &Metadata, out: impl Write) -> Result<()> {
+    let mut writer = ContainerWriter::new(out);
+    writer.write_signature()?;
+    if input.starts_with(&CODESTREAM_SIGNATURE) {
+        write_metadata(&mut writer, metadata)?;
+        writer.write_codestream(input)?;
+        writer.finish()?;
+        return Ok(());
+    }
+    let mut boxes = split_boxes(input)?.into_iter().peekable();
+    if boxes.next().map(|(ty, _)| ty) != Some(ContainerBoxType::JXL) {
+        bail!("Input is not a JPEG XL file");
+    }
+    if boxes.next().map(|(ty, _)| ty) != Some(ContainerBoxType::FILE_TYPE) {
+        bail!("Missing file type box");
+    }
+    if let Some(&(ContainerBoxType::JXL_LEVEL, payload)) = boxes.peek() {
+        writer.write_level(*payload.first().ok_or_else(|| eyre!("Empty level box"))?)?;
+        boxes.next();
+    }
+    write_metadata(&mut writer, metadata)?;
+    for (ty, payload) in boxes {
+        match ty {
+            ContainerBoxType::CODESTREAM => writer.write_codestream(payload)?,
+            ContainerBoxType::PARTIAL_CODESTREAM => {
+                let (index, part) = payload
+                    .split_first_chunk::<4>()
+                    .ok_or_else(|| eyre!("Truncated jxlp box"))?;
+                writer.write_codestream_part(part, u32::from_be_bytes(*index) & 0x80000000 != 0)?
+            }
+            _ => writer.write_box(ty, payload)?,
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+fn write_metadata(writer: &mut ContainerWriter<impl Write>, metadata: &Metadata) -> Result<()> {
+    if let Some(exif) = &metadata.exif {
+        // The Exif box starts with the offset of the TIFF header, which directly follows it.
+        let tiff = exif.strip_prefix(b"Exif\0\0").unwrap_or(exif);
+        let payload = [&[0; 4][..], tiff].concat();
+        writer.write_metadata_box(ContainerBoxType::EXIF, &payload, metadata.compress)?;
+    }
+    if let Some(xmp) = &metadata.xmp {
+        writer.write_metadata_box(ContainerBoxType::XML, xmp, metadata.compress)?;
+    }
+    Ok(())
+}
+
+/// Splits a container into its boxes, as (type, payload) pairs.
+fn split_boxes(mut input: &[u8]) -> Result<Vec<(ContainerBoxType, &[u8])>> {
+    let mut boxes = vec![];
+    while !input.is_empty() {
+        let HeaderParseResult::Done {
+            header,
+            header_size,
+        } = ContainerBoxHeader::parse(input)?
+        else {
+            bail!("Truncated box header");
+        };
+        input = &input[header_size..];
+        let size = match header.box_size() {
+            Some(size) => usize::try_from(size)
+                .ok()
+                .filter(|size| *size <= input.len())
+                .ok_or_else(|| eyre!("Truncated box"))?,
+            None => input.len(),
+        };
+        let (payload, rest) = input.split_at(size);
+        boxes.push((header.box_type(), payload));
+        input = rest;
+    }
+    Ok(boxes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::{OutputDataType, decode_frames};
+    use jxl::api::{Endianness, JxlDecoderOptions};
+    use std::path::PathBuf;
+
+    fn read_test_file(name: &str) -> Vec<u8> {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        std::fs::read(root.parent().unwrap().join("jxl/resources/test").join(name)).unwrap()
+    }
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let (output, _) = decode_frames(
+            &mut &input[..],
+            JxlDecoderOptions::default(),
+            None,
+            Some(OutputDataType::U8),
+            &[OutputDataType::U8],
+            true,
+            false,
+            None,
+            false,
+            None,
+            None,
+            Endianness::native(),
+        )
+        .unwrap();
+        output.frames[0].channels[0].row(0).to_vec()
+    }
+
+    #[test]
+    fn remux_adds_metadata() {
+        let metadata = Metadata {
+            exif: Some(b"Exif\0\0MM\0*\0\0\0\x08\0\0".to_vec()),
+            xmp: Some(b"<x:xmpmeta xmlns:x='adobe:ns:meta/'/>".to_vec()),
+            compress: false,
+        };
+        for name in ["basic.jxl", "has_permutation_with_container.jxl"] {
+            let input = read_test_file(name);
+            let mut once = vec![];
+            remux(&input, &metadata, &mut once).unwrap();
+            let boxes = split_boxes(&once).unwrap();
+            let types: Vec<_> = boxes.iter().map(|(ty, _)| ty.0).collect();
+            assert_eq!(types[..4], [*b"JXL ", *b"ftyp", *b"Exif", *b"xml "]);
+            assert_eq!(boxes[2].1, b"\0\0\0\0MM\0*\0\0\0\x08\0\0");
+            assert_eq!(decode(&once), decode(&input));
+
+            // Remuxing a container keeps its boxes.
+            let mut twice = vec![];
+            remux(&once, &Metadata::default(), &mut twice).unwrap();
+            assert_eq!(once, twice);
+        }
+    }
+
+    #[test]
+    fn remux_compressed() {
+        let metadata = Metadata {
+            xmp: Some(b"<x:xmpmeta xmlns:x='adobe:ns:meta/'/>".repeat(10)),
+            compress: true,
+            ..Default::default()
+        };
+        let input = read_test_file("basic.jxl");
+        let mut out = vec![];
+        remux(&input, &metadata, &mut out).unwrap();
+        let boxes = split_boxes(&out).unwrap();
+        assert_eq!(boxes[2].0, ContainerBoxType::BROTLI_COMPRESSED);
+        assert_eq!(&boxes[2].1[..4], b"xml ");
+        assert_eq!(decode(&out), decode(&input));
+    }
+}
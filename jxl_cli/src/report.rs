@@ -0,0 +1,179 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Output conventions of `jxl_cli`, which scripts can rely on.
+//!
+//! - Only the data that was asked for (image information, speed measurements, statistics, frame
+//!   lists and terminal previews) is written to stdout. Errors, warnings and other diagnostics
+//!   are written to stderr.
+//! - When the output image is written to stdout (with `-` as the output path), the data that was
+//!   asked for is written to stderr instead, so that it does not corrupt the image.
+//! - Numbers are formatted independently of the locale, with `.` as the decimal separator.
+//! - The exit code is one of the [`ExitStatus`] values. Invalid command lines are rejected with
+//!   [`ExitStatus::UsageError`] too.
+
+use std::{
+    fmt::{Arguments, Display},
+    process::ExitCode,
+    sync::OnceLock,
+};
+
+use color_eyre::eyre::{Report, Result, WrapErr};
+use serde::Serialize;
+
+/// Exit codes of `jxl_cli`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ExitStatus {
+    Success = 0,
+    /// The input could not be read or decoded.
+    DecodeError = 1,
+    /// The command line is invalid, or does not apply to the input.
+    UsageError = 2,
+    /// The output could not be written.
+    OutputError = 3,
+    /// Some, but not all, inputs of a batch were processed. Reserved for batch mode.
+    PartialSuccess = 4,
+}
+
+impl ExitStatus {
+    /// Classifies an error by the context it was wrapped in: errors that were not wrapped with
+    /// [`ExitStatusContext`] come from reading or decoding the input.
+    pub fn of(err: &Report) -> ExitStatus {
+        if err.downcast_ref::<UsageError>().is_some() {
+            ExitStatus::UsageError
+        } else if err.downcast_ref::<OutputError>().is_some() {
+            ExitStatus::OutputError
+        } else {
+            ExitStatus::DecodeError
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> ExitCode {
+        ExitCode::from(status as u8)
+    }
+}
+
+#[derive(Debug)]
+struct UsageError(String);
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug)]
+struct OutputError(String);
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Wraps errors with a message that also selects their [`ExitStatus`].
+pub trait ExitStatusContext<T, E>: WrapErr<T, E> + Sized {
+    fn usage_context(self, msg: impl Display) -> Result<T> {
+        self.wrap_err(UsageError(msg.to_string()))
+    }
+
+    fn output_context<D: Display>(self, msg: impl FnOnce() -> D) -> Result<T> {
+        self.wrap_err_with(|| OutputError(msg().to_string()))
+    }
+}
+
+impl<T, E, R: WrapErr<T, E>> ExitStatusContext<T, E> for R {}
+
+/// Verbosity and format of the output, set once from the command line.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reporter {
+    /// Do not print warnings and notes. Errors are always printed.
+    pub quiet: bool,
+    /// Print additional diagnostics.
+    pub verbose: bool,
+    /// Print requested data as JSON instead of text, where supported.
+    pub json: bool,
+    /// The output image is written to stdout, so requested data goes to stderr.
+    pub image_to_stdout: bool,
+}
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+impl Reporter {
+    /// Makes this the reporter returned by [`Reporter::get`]. Only the first call has an effect.
+    pub fn install(self) {
+        let _ = REPORTER.set(self);
+    }
+
+    /// The installed reporter, or the default one if none was installed (e.g. in tests).
+    pub fn get() -> Reporter {
+        REPORTER.get().copied().unwrap_or_default()
+    }
+
+    pub fn warn(&self, args: Arguments) {
+        if !self.quiet {
+            eprintln!("Warning: {args}");
+        }
+    }
+
+    /// Prints a diagnostic that is not a problem, such as where an embedded stream was found.
+    pub fn note(&self, args: Arguments) {
+        if !self.quiet {
+            eprintln!("{args}");
+        }
+    }
+
+    /// Prints a diagnostic only shown with `--verbose`.
+    pub fn detail(&self, args: Arguments) {
+        if self.verbose && !self.quiet {
+            eprintln!("{args}");
+        }
+    }
+
+    pub fn error(&self, err: &Report) {
+        eprintln!("Error: {err:?}");
+    }
+
+    /// Prints requested data, like `print!`.
+    pub fn data(&self, args: Arguments) {
+        if self.image_to_stdout {
+            eprint!("{args}");
+        } else {
+            print!("{args}");
+        }
+    }
+
+    /// Prints requested data as a single JSON document.
+    pub fn json(&self, value: &impl Serialize) -> Result<()> {
+        self.data(format_args!("{}\n", serde_json::to_string_pretty(value)?));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::eyre;
+
+    #[test]
+    fn exit_status_of_wrapped_errors() {
+        let decode: Result<()> = Err(eyre!("Source file truncated"));
+        let decode = decode.wrap_err("Failed to decode").unwrap_err();
+        assert_eq!(ExitStatus::of(&decode), ExitStatus::DecodeError);
+
+        let usage = Err::<(), _>(eyre!("Unknown output format")).usage_context("Invalid output");
+        // Context added later does not hide the classification.
+        let usage = usage.wrap_err("While parsing options").unwrap_err();
+        assert_eq!(ExitStatus::of(&usage), ExitStatus::UsageError);
+
+        let io = Err::<(), _>(std::io::Error::other("disk full"));
+        let output = io.output_context(|| "Failed to write out.png").unwrap_err();
+        assert_eq!(ExitStatus::of(&output), ExitStatus::OutputError);
+        assert_eq!(output.to_string(), "Failed to write out.png");
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Durations of the measured decodes of `--speedtest`, and their JSON form for benchmark scripts.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::phases::PhaseTimesJson;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Speedtest {
+    /// Duration of each measured decode, excluding warmups, in the order of the decodes.
+    pub reps: Vec<Duration>,
+}
+
+impl Speedtest {
+    pub fn total(&self) -> Duration {
+        self.reps.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.total() / self.reps.len().max(1) as u32
+    }
+
+    /// The middle duration, or the mean of the two middle durations for an even number of reps.
+    pub fn median(&self) -> Duration {
+        let mut reps = self.reps.clone();
+        reps.sort();
+        match reps.len() {
+            0 => Duration::ZERO,
+            n if n % 2 == 1 => reps[n / 2],
+            n => (reps[n / 2 - 1] + reps[n / 2]) / 2,
+        }
+    }
+
+    pub fn min(&self) -> Duration {
+        self.reps.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.reps.iter().copied().max().unwrap_or_default()
+    }
+
+    /// The JSON form of the measurements of decoding `input`, with `num_pixels` pixels, by
+    /// `decoder_version` using `options`.
+    pub fn to_json(
+        &self,
+        input: String,
+        decoder_version: &str,
+        num_pixels: usize,
+        options: SpeedtestOptionsJson,
+        phases: Option<PhaseTimesJson>,
+    ) -> SpeedtestJson {
+        let ns = |time: Duration| time.as_nanos() as u64;
+        SpeedtestJson {
+            input,
+            decoder_version: decoder_version.to_string(),
+            num_pixels,
+            reps_ns: self.reps.iter().copied().map(ns).collect(),
+            mean_ns: ns(self.mean()),
+            median_ns: ns(self.median()),
+            min_ns: ns(self.min()),
+            max_ns: ns(self.max()),
+            pixels_per_second: num_pixels as f64 / self.mean().as_secs_f64().max(f64::MIN_POSITIVE),
+            options,
+            phases,
+        }
+    }
+}
+
+/// The output of `--speedtest --json` and `--speedtest-json`, with durations in nanoseconds.
+#[derive(Serialize)]
+pub struct SpeedtestJson {
+    pub input: String,
+    pub decoder_version: String,
+    /// Pixels of one decode.
+    pub num_pixels: usize,
+    pub reps_ns: Vec<u64>,
+    pub mean_ns: u64,
+    pub median_ns: u64,
+    pub min_ns: u64,
+    pub max_ns: u64,
+    /// Pixels decoded per second, at the mean duration.
+    pub pixels_per_second: f64,
+    pub options: SpeedtestOptionsJson,
+    /// The phases of `--time`, if it was given, as fields of the same object.
+    #[serde(flatten)]
+    pub phases: Option<PhaseTimesJson>,
+}
+
+/// Decoder options that change the work done by each decode, so that runs can be compared.
+#[derive(Serialize)]
+pub struct SpeedtestOptionsJson {
+    pub linear_output: bool,
+    pub render_spot_colors: bool,
+    pub high_precision: bool,
+    pub data_type: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistics_of_reps() {
+        let ms = Duration::from_millis;
+        let speedtest = Speedtest {
+            reps: vec![ms(30), ms(10), ms(40), ms(20)],
+        };
+        assert_eq!(speedtest.mean(), ms(25));
+        assert_eq!(speedtest.median(), ms(25));
+        assert_eq!(speedtest.min(), ms(10));
+        assert_eq!(speedtest.max(), ms(40));
+        let options = SpeedtestOptionsJson {
+            linear_output: false,
+            render_spot_colors: true,
+            high_precision: false,
+            data_type: "U8".to_string(),
+        };
+        let json = speedtest.to_json("a.jxl".to_string(), "0.3.0", 1000, options, None);
+        assert_eq!(
+            json.reps_ns,
+            [30_000_000, 10_000_000, 40_000_000, 20_000_000]
+        );
+        assert_eq!(json.pixels_per_second, 40_000.0);
+        let value = serde_json::to_value(&json).unwrap();
+        assert!(value.get("total_ms").is_none());
+        assert_eq!(value["options"]["render_spot_colors"], true);
+
+        let odd = Speedtest {
+            reps: vec![ms(3), ms(1), ms(2)],
+        };
+        assert_eq!(odd.median(), ms(2));
+    }
+}
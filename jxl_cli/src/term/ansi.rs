@@ -0,0 +1,76 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::{Result, Write};
+
+use super::Rgba8Image;
+
+/// Composites a pixel on a black background.
+fn opaque(pixel: [u8; 4]) -> [u8; 3] {
+    std::array::from_fn(|c| ((pixel[c] as u32 * pixel[3] as u32 + 127) / 255) as u8)
+}
+
+/// Writes `image` with 24-bit ANSI colors, drawing two rows of pixels per line of text with
+/// upper half block characters.
+pub fn write_half_blocks(image: &Rgba8Image, out: &mut impl Write) -> Result<()> {
+    for y in (0..image.height).step_by(2) {
+        for x in 0..image.width {
+            let [r, g, b] = opaque(image.pixels[y * image.width + x]);
+            write!(out, "\x1b[38;2;{r};{g};{b}m")?;
+            if y + 1 < image.height {
+                let [r, g, b] = opaque(image.pixels[(y + 1) * image.width + x]);
+                write!(out, "\x1b[48;2;{r};{g};{b}m▀")?;
+            } else {
+                // Odd number of rows: keep the terminal background for the bottom half.
+                write!(out, "\x1b[49m▀")?;
+            }
+        }
+        writeln!(out, "\x1b[0m")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::test_utils::gradient;
+
+    #[test]
+    fn two_rows_per_line() {
+        let image = Rgba8Image {
+            width: 2,
+            height: 3,
+            pixels: vec![
+                [255, 0, 0, 255],
+                [0, 255, 0, 255],
+                [0, 0, 255, 255],
+                [255, 255, 255, 0],
+                [10, 20, 30, 255],
+                [255, 255, 255, 128],
+            ],
+        };
+        let mut out = vec![];
+        write_half_blocks(&image, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\
+             \x1b[38;2;0;255;0m\x1b[48;2;0;0;0m▀\x1b[0m\n\
+             \x1b[38;2;10;20;30m\x1b[49m▀\
+             \x1b[38;2;128;128;128m\x1b[49m▀\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn line_count() {
+        let mut out = vec![];
+        write_half_blocks(&gradient(7, 9), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 5);
+        for line in text.lines() {
+            assert_eq!(line.matches('▀').count(), 7);
+            assert!(line.ends_with("\x1b[0m"));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::{Result, Write};
+
+use super::Rgba8Image;
+
+/// Maximum size of the base64 payload of a single escape sequence, as mandated by the protocol.
+const MAX_CHUNK_SIZE: usize = 4096;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let v = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(v >> (18 - 6 * i)) as usize & 63]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+    out
+}
+
+/// Writes `image` using the kitty graphics protocol, as raw RGBA data split over multiple
+/// escape sequences.
+pub fn write_kitty(image: &Rgba8Image, out: &mut impl Write) -> Result<()> {
+    let data: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+    let payload = base64(&data);
+    let mut chunks = payload.chunks(MAX_CHUNK_SIZE).peekable();
+    let mut first = true;
+    while let Some(chunk) = chunks.next() {
+        let more = chunks.peek().is_some() as u8;
+        if first {
+            write!(
+                out,
+                "\x1b_Ga=T,f=32,q=2,s={},v={},m={more};",
+                image.width, image.height
+            )?;
+            first = false;
+        } else {
+            write!(out, "\x1b_Gm={more};")?;
+        }
+        out.write_all(chunk)?;
+        out.write_all(b"\x1b\\")?;
+    }
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::test_utils::gradient;
+
+    #[test]
+    fn base64_vectors() {
+        assert_eq!(base64(b""), b"");
+        assert_eq!(base64(b"f"), b"Zg==");
+        assert_eq!(base64(b"fo"), b"Zm8=");
+        assert_eq!(base64(b"foo"), b"Zm9v");
+        assert_eq!(base64(b"foobar"), b"Zm9vYmFy");
+        assert_eq!(base64(&[0xff, 0xfe, 0xfd]), b"//79");
+    }
+
+    #[test]
+    fn single_chunk() {
+        let image = gradient(2, 1);
+        let mut out = vec![];
+        write_kitty(&image, &mut out).unwrap();
+        let data: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+        let mut expected = b"\x1b_Ga=T,f=32,q=2,s=2,v=1,m=0;".to_vec();
+        expected.extend(base64(&data));
+        expected.extend(b"\x1b\\\n");
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn chunked_payload() {
+        let image = gradient(40, 30);
+        let mut out = vec![];
+        write_kitty(&image, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let sequences: Vec<_> = text
+            .trim_end()
+            .split("\x1b\\")
+            .filter(|s| !s.is_empty())
+            .collect();
+        // 40 * 30 * 4 bytes -> 6400 base64 characters.
+        assert_eq!(sequences.len(), 2);
+        assert!(sequences[0].starts_with("\x1b_Ga=T,f=32,q=2,s=40,v=30,m=1;"));
+        assert!(sequences[1].starts_with("\x1b_Gm=0;"));
+        let payload: String = sequences
+            .iter()
+            .map(|s| s.split_once(';').unwrap().1)
+            .inspect(|p| assert!(p.len() <= MAX_CHUNK_SIZE))
+            .collect();
+        assert_eq!(payload.len(), 6400);
+        let data: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+        assert_eq!(payload.as_bytes(), base64(&data));
+    }
+}
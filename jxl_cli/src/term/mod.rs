@@ -0,0 +1,326 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Preview of decoded images directly in the terminal.
+
+use std::io::Write;
+
+use color_eyre::eyre::{Result, ensure};
+use jxl::api::{JxlColorType, ResampleFilter, resample_with_alpha};
+use jxl::image::Image;
+
+use crate::dec::{DecodeOutput, OutputDataType};
+
+mod ansi;
+mod kitty;
+mod sixel;
+
+pub use ansi::write_half_blocks;
+pub use kitty::write_kitty;
+pub use sixel::write_sixel;
+
+/// Approximate size of a terminal cell in pixels, used for the pixel based protocols.
+const CELL_SIZE: (usize, usize) = (8, 16);
+
+/// Graphics protocol used to draw the preview.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    /// 24-bit ANSI colors with upper half block characters, two pixels per cell.
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// Picks the best supported protocol from the values of `$TERM`, `$TERM_PROGRAM` and whether
+    /// `$KITTY_WINDOW_ID` is set.
+    pub fn detect(term: Option<&str>, term_program: Option<&str>, kitty_window: bool) -> Self {
+        let term = term.unwrap_or_default();
+        let term_program = term_program.unwrap_or_default();
+        if kitty_window
+            || term.contains("kitty")
+            || term.contains("ghostty")
+            || matches!(term_program, "WezTerm" | "ghostty")
+        {
+            Protocol::Kitty
+        } else if term.contains("sixel")
+            || ["mlterm", "foot", "yaft", "contour"]
+                .iter()
+                .any(|t| term.starts_with(t))
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let var = |name| std::env::var(name).ok();
+        Self::detect(
+            var("TERM").as_deref(),
+            var("TERM_PROGRAM").as_deref(),
+            var("KITTY_WINDOW_ID").is_some(),
+        )
+    }
+}
+
+/// An 8-bit RGBA image with interleaved samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rgba8Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Rgba8Image {
+    /// Converts the first frame of `output` to 8-bit RGBA, ignoring the color profile.
+    pub fn from_first_frame(output: &DecodeOutput) -> Result<Self> {
+        ensure!(!output.frames.is_empty(), "No frames to preview");
+        let frame = &output.frames[0];
+        let (width, height) = output.size;
+        let spp = frame.color_type.samples_per_pixel();
+        let bytes = output.data_type.bits_per_sample() / 8;
+        let chan = &frame.channels[0];
+        ensure!(
+            chan.byte_size() == (width * spp * bytes, height),
+            "Unexpected preview buffer size"
+        );
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let row = chan.row(y);
+            for x in 0..width {
+                let s = |i: usize| to_u8(&row[(x * spp + i) * bytes..][..bytes], output.data_type);
+                pixels.push(match frame.color_type {
+                    JxlColorType::Grayscale => [s(0), s(0), s(0), 255],
+                    JxlColorType::GrayscaleAlpha => [s(0), s(0), s(0), s(1)],
+                    JxlColorType::Rgb => [s(0), s(1), s(2), 255],
+                    JxlColorType::Rgba => [s(0), s(1), s(2), s(3)],
+                    JxlColorType::Bgr => [s(2), s(1), s(0), 255],
+                    JxlColorType::Bgra => [s(2), s(1), s(0), s(3)],
+                });
+            }
+        }
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Downsamples the image with the resampler of the decoder so that it fits in `max_size`,
+    /// preserving the aspect ratio. Images that already fit are returned unchanged.
+    pub fn fit_into(&self, max_size: (usize, usize)) -> Result<Self> {
+        let scale = (max_size.0 as f64 / self.width as f64)
+            .min(max_size.1 as f64 / self.height as f64)
+            .min(1.0);
+        if scale == 1.0 {
+            return Ok(self.clone());
+        }
+        let width = ((self.width as f64 * scale).round() as usize).max(1);
+        let height = ((self.height as f64 * scale).round() as usize).max(1);
+        let mut image = Image::<f32>::new((self.width * 4, self.height))?;
+        for (y, pixels) in self.pixels.chunks_exact(self.width).enumerate() {
+            for (value, sample) in image.row_mut(y).iter_mut().zip(pixels.as_flattened()) {
+                *value = *sample as f32 / 255.0;
+            }
+        }
+        // Colors are weighted by alpha so that transparent pixels do not bleed in.
+        let resampled =
+            resample_with_alpha(&image, 4, 3, (width, height), ResampleFilter::CatmullRom)?;
+        let pixels = (0..height)
+            .flat_map(|y| resampled.row(y).chunks_exact(4))
+            .map(|pixel| std::array::from_fn(|c| (pixel[c].clamp(0.0, 1.0) * 255.0 + 0.5) as u8))
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+fn to_u8(sample: &[u8], data_type: OutputDataType) -> u8 {
+    let from_f32 = |v: f32| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+    match data_type {
+        OutputDataType::U8 => sample[0],
+        OutputDataType::U16 => (u16::from_ne_bytes([sample[0], sample[1]]) >> 8) as u8,
+        OutputDataType::F16 => {
+            from_f32(half::f16::from_bits(u16::from_ne_bytes([sample[0], sample[1]])).to_f32())
+        }
+        OutputDataType::F32 => from_f32(f32::from_ne_bytes(sample.try_into().unwrap())),
+    }
+}
+
+/// Writes the first frame of `output` to `out` using `protocol`, downsampled to fit in a
+/// terminal of `cells` (columns, lines).
+pub fn write_preview(
+    output: &DecodeOutput,
+    protocol: Protocol,
+    cells: (usize, usize),
+    out: &mut impl Write,
+) -> Result<()> {
+    let image = Rgba8Image::from_first_frame(output)?;
+    // Leave a line for the shell prompt.
+    let (columns, lines) = (cells.0.max(1), cells.1.saturating_sub(1).max(1));
+    match protocol {
+        Protocol::Kitty => write_kitty(
+            &image.fit_into((columns * CELL_SIZE.0, lines * CELL_SIZE.1))?,
+            out,
+        )?,
+        Protocol::Sixel => write_sixel(
+            &image.fit_into((columns * CELL_SIZE.0, lines * CELL_SIZE.1))?,
+            out,
+        )?,
+        Protocol::HalfBlocks => write_half_blocks(&image.fit_into((columns, lines * 2))?, out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Returns the size in cells (columns, lines) of the terminal on stdout, or from `$COLUMNS` and
+/// `$LINES` if stdout is not a terminal, defaulting to 80x24.
+pub fn terminal_size() -> (usize, usize) {
+    tty_size().unwrap_or_else(|| {
+        let var = |name, default| {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .filter(|&v| v > 0)
+                .unwrap_or(default)
+        };
+        (var("COLUMNS", 80), var("LINES", 24))
+    })
+}
+
+#[cfg(unix)]
+fn tty_size() -> Option<(usize, usize)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer, which points to one.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0 && size.ws_row > 0)
+        .then_some((size.ws_col as usize, size.ws_row as usize))
+}
+
+#[cfg(not(unix))]
+fn tty_size() -> Option<(usize, usize)> {
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::Rgba8Image;
+
+    /// A small image with a different color in each pixel.
+    pub fn gradient(width: usize, height: usize) -> Rgba8Image {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                [(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255]
+            })
+            .collect();
+        Rgba8Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dec::test_utils::make_test_image;
+
+    #[test]
+    fn detect_protocol() {
+        assert_eq!(
+            Protocol::detect(Some("xterm-kitty"), None, false),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            Protocol::detect(Some("xterm-256color"), Some("WezTerm"), false),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            Protocol::detect(Some("screen"), None, true),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            Protocol::detect(Some("foot-extra"), None, false),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            Protocol::detect(Some("xterm-sixel"), None, false),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            Protocol::detect(Some("xterm-256color"), None, false),
+            Protocol::HalfBlocks
+        );
+        assert_eq!(Protocol::detect(None, None, false), Protocol::HalfBlocks);
+    }
+
+    #[test]
+    fn convert_first_frame() {
+        for data_type in [OutputDataType::U8, OutputDataType::U16] {
+            let output = make_test_image(JxlColorType::GrayscaleAlpha, data_type, (3, 2));
+            let image = Rgba8Image::from_first_frame(&output).unwrap();
+            assert_eq!((image.width, image.height), (3, 2));
+            for p in &image.pixels {
+                assert_eq!(p[0], p[1]);
+                assert_eq!(p[1], p[2]);
+            }
+        }
+    }
+
+    #[test]
+    fn fit_into_preserves_aspect_ratio_and_alpha() {
+        let image = test_utils::gradient(40, 10);
+        let small = image.fit_into((10, 10)).unwrap();
+        assert_eq!((small.width, small.height), (10, 3));
+        // Never upsamples.
+        assert_eq!(image.fit_into((100, 100)).unwrap(), image);
+
+        let image = Rgba8Image {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                [0, 0, 0, 255],
+                [200, 100, 50, 255],
+                [0, 0, 0, 0],
+                [0, 0, 0, 0],
+            ],
+        };
+        let small = image.fit_into((1, 1)).unwrap();
+        assert_eq!(small.pixels, [[100, 50, 25, 128]]);
+
+        // Fully transparent pixels do not darken the average.
+        let image = Rgba8Image {
+            width: 2,
+            height: 1,
+            pixels: vec![[200, 100, 50, 255], [0, 0, 0, 0]],
+        };
+        assert_eq!(
+            image.fit_into((1, 1)).unwrap().pixels,
+            [[200, 100, 50, 128]]
+        );
+    }
+
+    #[test]
+    fn preview_fits_terminal() {
+        let output = make_test_image(JxlColorType::Rgb, OutputDataType::U8, (300, 100));
+        let mut out = vec![];
+        write_preview(&output, Protocol::HalfBlocks, (30, 11), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        // 30x10 pixels, two pixel rows per line.
+        assert_eq!(text.lines().count(), 5);
+        assert!(text.lines().all(|l| l.matches('▀').count() == 30));
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::io::{Result, Write};
+
+use super::Rgba8Image;
+
+/// Number of levels of the red, green and blue components of the fixed palette.
+const LEVELS: [usize; 3] = [6, 7, 6];
+
+/// Pixels with alpha below this threshold are left transparent.
+const ALPHA_THRESHOLD: u8 = 128;
+
+/// Maps a pixel to an index of the fixed 6x7x6 palette, or `None` if it is transparent.
+fn quantize(pixel: [u8; 4]) -> Option<usize> {
+    if pixel[3] < ALPHA_THRESHOLD {
+        return None;
+    }
+    let level = |c: usize| (pixel[c] as usize * (LEVELS[c] - 1) + 127) / 255;
+    Some((level(0) * LEVELS[1] + level(1)) * LEVELS[2] + level(2))
+}
+
+/// Returns the color of a palette entry, in percent as used by sixel color definitions.
+fn palette_color(index: usize) -> [usize; 3] {
+    let levels = [
+        index / (LEVELS[1] * LEVELS[2]),
+        index / LEVELS[2] % LEVELS[1],
+        index % LEVELS[2],
+    ];
+    std::array::from_fn(|c| (levels[c] * 100 + (LEVELS[c] - 1) / 2) / (LEVELS[c] - 1))
+}
+
+fn write_run(out: &mut impl Write, sixel: u8, count: usize) -> Result<()> {
+    let c = (63 + sixel) as char;
+    match count {
+        0 => Ok(()),
+        1..=3 => write!(out, "{}", c.to_string().repeat(count)),
+        _ => write!(out, "!{count}{c}"),
+    }
+}
+
+/// Writes `image` as a sixel sequence, quantized to a fixed palette.
+pub fn write_sixel(image: &Rgba8Image, out: &mut impl Write) -> Result<()> {
+    let indices: Vec<_> = image.pixels.iter().map(|p| quantize(*p)).collect();
+    let mut used = vec![false; LEVELS.iter().product()];
+    for i in indices.iter().flatten() {
+        used[*i] = true;
+    }
+
+    // Transparent background (P2 = 1), with square pixels.
+    write!(out, "\x1bP0;1;0q\"1;1;{};{}", image.width, image.height)?;
+    for (i, _) in used.iter().enumerate().filter(|(_, u)| **u) {
+        let [r, g, b] = palette_color(i);
+        write!(out, "#{i};2;{r};{g};{b}")?;
+    }
+
+    let num_bands = image.height.div_ceil(6);
+    for band in 0..num_bands {
+        let rows = band * 6..(band * 6 + 6).min(image.height);
+        let mut band_colors: Vec<_> = rows
+            .clone()
+            .flat_map(|y| indices[y * image.width..][..image.width].iter().flatten())
+            .copied()
+            .collect();
+        band_colors.sort_unstable();
+        band_colors.dedup();
+        for (n, color) in band_colors.into_iter().enumerate() {
+            if n > 0 {
+                // Go back to the start of the band for the next color.
+                write!(out, "$")?;
+            }
+            write!(out, "#{color}")?;
+            let (mut run_sixel, mut run_len) = (0, 0);
+            for x in 0..image.width {
+                let sixel = rows
+                    .clone()
+                    .filter(|y| indices[y * image.width + x] == Some(color))
+                    .fold(0, |acc, y| acc | 1 << (y - rows.start));
+                if sixel != run_sixel {
+                    write_run(out, run_sixel, run_len)?;
+                    (run_sixel, run_len) = (sixel, 0);
+                }
+                run_len += 1;
+            }
+            // Trailing empty columns do not need to be written.
+            if run_sixel != 0 {
+                write_run(out, run_sixel, run_len)?;
+            }
+        }
+        if band + 1 < num_bands {
+            write!(out, "-")?;
+        }
+    }
+    writeln!(out, "\x1b\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::test_utils::gradient;
+
+    /// Minimal sixel parser returning the palette index of each pixel.
+    fn parse_sixel(data: &str, width: usize, height: usize) -> Vec<Option<usize>> {
+        let data = data.strip_prefix("\x1bP0;1;0q").unwrap();
+        let data = data.strip_suffix("\x1b\\\n").unwrap();
+        let header = format!("\"1;1;{width};{height}");
+        let mut data = data.strip_prefix(&header).unwrap().as_bytes();
+        let mut pixels = vec![None; width * height];
+        let (mut x, mut band, mut color) = (0, 0, 0);
+        let number = |data: &mut &[u8]| {
+            let len = data.iter().take_while(|c| c.is_ascii_digit()).count();
+            let n = std::str::from_utf8(&data[..len]).unwrap().parse().unwrap();
+            *data = &data[len..];
+            n
+        };
+        while let Some((&c, rest)) = data.split_first() {
+            data = rest;
+            match c {
+                b'#' => {
+                    color = number(&mut data);
+                    if data.first() == Some(&b';') {
+                        // Color definition, skip.
+                        while data.first().is_some_and(|c| *c != b'#') {
+                            data = &data[1..];
+                        }
+                    }
+                }
+                b'$' => x = 0,
+                b'-' => (x, band) = (0, band + 1),
+                b'!' | b'?'..=b'~' => {
+                    let count = if c == b'!' { number(&mut data) } else { 1 };
+                    let sixel = if c == b'!' {
+                        let s = data[0];
+                        data = &data[1..];
+                        s
+                    } else {
+                        c
+                    } - 63;
+                    for _ in 0..count {
+                        for bit in 0..6 {
+                            if sixel & (1 << bit) != 0 {
+                                pixels[(band * 6 + bit) * width + x] = Some(color);
+                            }
+                        }
+                        x += 1;
+                    }
+                }
+                _ => panic!("unexpected character {c}"),
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(quantize([0, 0, 0, 255]), Some(0));
+        assert_eq!(quantize([255, 255, 255, 255]), Some(251));
+        assert_eq!(quantize([255, 255, 255, 0]), None);
+        assert_eq!(palette_color(0), [0, 0, 0]);
+        assert_eq!(palette_color(251), [100, 100, 100]);
+        assert_eq!(
+            palette_color(quantize([255, 0, 0, 255]).unwrap()),
+            [100, 0, 0]
+        );
+        for i in 0..252 {
+            let [r, g, b] = palette_color(i);
+            let rgb = [r, g, b].map(|c| (c * 255 / 100) as u8);
+            assert_eq!(quantize([rgb[0], rgb[1], rgb[2], 255]), Some(i));
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let mut image = gradient(23, 13);
+        image.pixels[5][3] = 0;
+        let mut out = vec![];
+        write_sixel(&image, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with("\x1bP0;1;0q\"1;1;23;13#"));
+        assert!(text.ends_with("\x1b\\\n"));
+        // Three bands of six rows.
+        assert_eq!(text.matches('-').count(), 2);
+        let expected: Vec<_> = image.pixels.iter().map(|p| quantize(*p)).collect();
+        assert_eq!(parse_sixel(&text, 23, 13), expected);
+    }
+
+    #[test]
+    fn run_length_encoding() {
+        let image = Rgba8Image {
+            width: 10,
+            height: 1,
+            pixels: vec![[255, 255, 255, 255]; 10],
+        };
+        let mut out = vec![];
+        write_sixel(&image, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "\x1bP0;1;0q\"1;1;10;1#251;2;100;100;100#251!10@\x1b\\\n"
+        );
+    }
+}
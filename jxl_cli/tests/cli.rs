@@ -0,0 +1,1243 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Checks the output and exit code contract documented in `jxl_cli::report`.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn test_file(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../jxl/resources/test")
+        .join(name)
+}
+
+fn run(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_jxl_cli"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn info_goes_to_stdout() {
+    let input = test_file("basic.jxl");
+    let output = run(&[input.as_os_str(), "--info".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Image size: "), "{stdout}");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn info_as_json() {
+    let input = test_file("basic.jxl");
+    let output = run(&[input.as_os_str(), "--info".as_ref(), "--json".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(info["width"].as_u64().unwrap() > 0);
+    assert!(info["compression"].is_string());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn info_describes_channels_and_animation() {
+    let info = |name: &str| {
+        let output = run(&[test_file(name).as_os_str(), "--info".as_ref()]);
+        assert_eq!(output.status.code(), Some(0));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let spot = info("conformance_test_images/spot.jxl");
+    for line in [
+        "Image size: 600x400",
+        "Bit depth: Int { bits_per_sample: 16 }",
+        "Color profile: RGB, ICC profile of 940 bytes",
+        "Output color profile: RGB, ICC profile of 940 bytes",
+        "Animation: none",
+        "Extra channels: 3",
+        "Extra channel 0: Alpha, Int { bits_per_sample: 16 }",
+        "Extra channel 2: SpotColor, Int { bits_per_sample: 16 }",
+    ] {
+        assert!(spot.lines().any(|l| l == line), "{line:?} in {spot}");
+    }
+    let animation = info("conformance_test_images/animation_spline.jxl");
+    for line in [
+        "Image size: 320x320",
+        "Animation: 0 loops, 100/1 tps",
+        "Extra channels: 0",
+    ] {
+        assert!(
+            animation.lines().any(|l| l == line),
+            "{line:?} in {animation}"
+        );
+    }
+
+    let output = run(&[
+        test_file("conformance_test_images/spot.jxl").as_os_str(),
+        "--info".as_ref(),
+        "--json".as_ref(),
+    ]);
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let channels = info["extra_channel_info"].as_array().unwrap();
+    let kinds: Vec<_> = channels
+        .iter()
+        .map(|c| c["kind"].as_str().unwrap())
+        .collect();
+    assert_eq!(kinds, ["Alpha", "SpotColor", "SpotColor"]);
+    assert_eq!(channels[1]["bits_per_sample"], 16);
+    assert_eq!(info["output_color_profile"], info["color_profile"]);
+}
+
+#[test]
+fn time_breakdown() {
+    let input = test_file("basic.jxl");
+    let path = std::env::temp_dir().join(format!("jxl_cli_time_{}.png", std::process::id()));
+    let output = run(&[input.as_os_str(), path.as_os_str(), "--time".as_ref()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let line =
+        regex::Regex::new(r"^ *([a-z0-9+ ]+): +(\d+\.\d{3}) ms(?: \( *\d+\.\d%\))?$").unwrap();
+    let phases: Vec<(String, f64)> = stdout
+        .lines()
+        .map(|l| {
+            let captures = line.captures(l).unwrap_or_else(|| panic!("{l:?}"));
+            (captures[1].to_string(), captures[2].parse().unwrap())
+        })
+        .collect();
+    let names: Vec<_> = phases.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "read input",
+            "container+headers",
+            "frame 0",
+            "convert",
+            "encode output",
+            "write output",
+            "total"
+        ]
+    );
+    let (total, phases) = phases.split_last().unwrap();
+    let sum: f64 = phases.iter().map(|(_, ms)| ms).sum();
+    // The phases cover all the work, and are rounded to microseconds.
+    assert!(sum <= total.1 + 0.01, "{stdout}");
+    assert!(sum >= 0.8 * total.1, "{stdout}");
+}
+
+#[test]
+fn time_breakdown_as_json() {
+    let input = test_file("basic.jxl");
+    let output = run(&[
+        input.as_os_str(),
+        "--speedtest".as_ref(),
+        "--num-reps=2".as_ref(),
+        "--time".as_ref(),
+        "--json".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    // The phases are fields of the speed measurement.
+    let json = stdout.as_str();
+    let times: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(times["reps_ns"].as_array().unwrap().len(), 2);
+    assert_eq!(times["frames_ms"].as_array().unwrap().len(), 1);
+    let phases = [
+        "read_input_ms",
+        "headers_ms",
+        "convert_ms",
+        "encode_ms",
+        "write_ms",
+    ];
+    let sum = phases
+        .iter()
+        .map(|phase| times[phase].as_f64().unwrap())
+        .sum::<f64>()
+        + times["frames_ms"][0].as_f64().unwrap();
+    assert!(sum <= times["total_ms"].as_f64().unwrap(), "{json}");
+    assert_eq!(times["encode_ms"].as_f64(), Some(0.0));
+}
+
+#[test]
+fn speedtest_as_json() {
+    let input = test_file("basic.jxl");
+    let output = run(&[
+        input.as_os_str(),
+        "--speedtest".as_ref(),
+        "--num-reps=3".as_ref(),
+        "--json".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let speedtest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(speedtest["input"].as_str(), input.to_str());
+    assert!(speedtest["decoder_version"].is_string());
+    let reps: Vec<u64> = speedtest["reps_ns"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rep| rep.as_u64().unwrap())
+        .collect();
+    assert_eq!(reps.len(), 3);
+    assert_eq!(speedtest["min_ns"].as_u64(), reps.iter().min().copied());
+    assert_eq!(speedtest["max_ns"].as_u64(), reps.iter().max().copied());
+    assert!(speedtest["pixels_per_second"].as_f64().unwrap() > 0.0);
+    assert_eq!(speedtest["options"]["render_spot_colors"], true);
+    assert!(speedtest.get("total_ms").is_none());
+
+    // The file is written alongside the unchanged human-readable measurement.
+    let path = std::env::temp_dir().join(format!("jxl_cli_speedtest_{}.json", std::process::id()));
+    let output = run(&[
+        input.as_os_str(),
+        "--speedtest".as_ref(),
+        "--speedtest-json".as_ref(),
+        path.as_os_str(),
+    ]);
+    let written = std::fs::read(&path);
+    std::fs::remove_file(&path).ok();
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Decoded "), "{stdout}");
+    assert!(stdout.contains(" MP/s"), "{stdout}");
+    let speedtest: serde_json::Value = serde_json::from_slice(&written.unwrap()).unwrap();
+    assert_eq!(speedtest["reps_ns"].as_array().unwrap().len(), 1);
+    assert!(speedtest["num_pixels"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn decode_error_exit_code() {
+    let invalid = std::env::temp_dir().join(format!("jxl_cli_invalid_{}.jxl", std::process::id()));
+    std::fs::write(&invalid, b"not a JPEG XL file").unwrap();
+    let output = run(&[invalid.as_os_str(), "--info".as_ref()]);
+    std::fs::remove_file(&invalid).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: "));
+}
+
+#[test]
+fn usage_error_exit_code() {
+    let input = test_file("basic.jxl");
+    let output = run(&[input.as_os_str(), "--no-such-flag".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = run(&[input.as_os_str(), "out.unknown".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn dash_writes_the_image_to_stdout() {
+    let input = test_file("3x3_srgb_lossless.jxl");
+    let output = run(&[
+        input.as_os_str(),
+        "-".as_ref(),
+        "--format".as_ref(),
+        "ppm".as_ref(),
+        "--speedtest".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    // The speed measurement goes to stderr, so that only the image is piped.
+    let header = b"P6\n3 3\n255\n";
+    assert!(output.stdout.starts_with(header));
+    assert_eq!(output.stdout.len(), header.len() + 3 * 3 * 3);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("MP/s"));
+    assert!(!std::path::Path::new("-").exists());
+
+    // EXR files, which are encoded by seeking, are written too.
+    let output = run(&[input.as_os_str(), "-".as_ref(), "--format=exr".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.starts_with(&[0x76, 0x2f, 0x31, 0x01]));
+
+    // There is no extension to infer the format from.
+    let output = run(&[input.as_os_str(), "-".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--format"));
+}
+
+/// Runs the CLI with `input` piped to its stdin.
+fn run_with_stdin(args: &[&std::ffi::OsStr], input: &[u8]) -> Output {
+    use std::io::Write;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jxl_cli"))
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn dash_reads_the_input_from_stdin() {
+    let input = test_file("3x3_srgb_lossless.jxl");
+    let data = std::fs::read(&input).unwrap();
+    let expected = run(&[input.as_os_str(), "-".as_ref(), "--format=ppm".as_ref()]);
+    assert_eq!(expected.status.code(), Some(0));
+
+    let output = run_with_stdin(
+        &["-".as_ref(), "-".as_ref(), "--format=ppm".as_ref()],
+        &data,
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, expected.stdout);
+
+    // The input is only read once for all repetitions.
+    let output = run_with_stdin(
+        &[
+            "-".as_ref(),
+            "--speedtest".as_ref(),
+            "--num-reps=3".as_ref(),
+        ],
+        &data,
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Decoded 27 pixels"), "{stdout}");
+
+    let output = run_with_stdin(&["-".as_ref(), "--info".as_ref()], &data);
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .starts_with("Image size: 3x3")
+    );
+
+    // Nothing piped is an empty input.
+    let output = run_with_stdin(&["-".as_ref(), "--info".as_ref()], &[]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn output_error_exit_code() {
+    let input = test_file("3x3_srgb_lossless.jxl");
+    let output_path = test_file("no_such_dir/out.png");
+    let output = run(&[input.as_os_str(), output_path.as_os_str()]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn outputs_are_checked_before_decoding() {
+    let invalid = std::env::temp_dir().join(format!("jxl_cli_early_{}.jxl", std::process::id()));
+    std::fs::write(&invalid, b"not a JPEG XL file").unwrap();
+    // The output is opened first, so its error wins over the decoding error.
+    let output_path = test_file("no_such_dir/out.png");
+    let output = run(&[invalid.as_os_str(), output_path.as_os_str()]);
+    assert_eq!(output.status.code(), Some(3));
+
+    // Failed decodes leave no outputs behind.
+    let output_path = invalid.with_extension("png");
+    let icc_path = invalid.with_extension("icc");
+    let output = run(&[
+        invalid.as_os_str(),
+        output_path.as_os_str(),
+        "--icc-out".as_ref(),
+        icc_path.as_os_str(),
+    ]);
+    std::fs::remove_file(&invalid).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output_path.exists());
+    assert!(!icc_path.exists());
+}
+
+#[test]
+fn preallocated_sizes() {
+    let input = test_file("3x3_srgb_lossless.jxl");
+    for (extension, exact) in [("npy", true), ("ppm", true), ("png", false)] {
+        let path = std::env::temp_dir().join(format!(
+            "jxl_cli_preallocated_{}.{extension}",
+            std::process::id()
+        ));
+        let decode = |preallocate: bool| {
+            let mut args = vec![input.as_os_str(), path.as_os_str(), "--verbose".as_ref()];
+            if preallocate {
+                args.push("--preallocate".as_ref());
+            }
+            let output = run(&args);
+            assert_eq!(output.status.code(), Some(0));
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            let data = std::fs::read(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            (stderr, data)
+        };
+        let (stderr, data) = decode(true);
+        assert_eq!(data, decode(false).1);
+        let line = stderr
+            .lines()
+            .find(|line| line.starts_with("Preallocating "))
+            .unwrap();
+        let expected = format!("Preallocating {} bytes for the output", data.len());
+        assert_eq!(line == expected, exact, "{line}");
+    }
+}
+
+#[test]
+fn npy_output_endianness() {
+    let input = test_file("3x3_srgb_lossless.jxl");
+    let decode = |endianness: &str| {
+        let path =
+            std::env::temp_dir().join(format!("jxl_cli_{endianness}_{}.npy", std::process::id()));
+        let output = run(&[
+            input.as_os_str(),
+            path.as_os_str(),
+            "--data-type=u16".as_ref(),
+            "--output-endianness".as_ref(),
+            endianness.as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    };
+    let little = decode("little");
+    let big = decode("big");
+    let header_len = 10 + u16::from_le_bytes([little[8], little[9]]) as usize;
+    let header = String::from_utf8_lossy(&big[..header_len]);
+    assert!(header.contains("'descr': '>u2'"), "{header}");
+    assert!(String::from_utf8_lossy(&little[..header_len]).contains("'descr': '<u2'"));
+    assert_eq!(little.len(), big.len());
+    for (l, b) in little[header_len..]
+        .chunks_exact(2)
+        .zip(big[header_len..].chunks_exact(2))
+    {
+        assert_eq!(l, [b[1], b[0]]);
+    }
+
+    let output = run(&[
+        input.as_os_str(),
+        test_file("out.png").as_os_str(),
+        "--output-endianness=big".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn npy_column_major() {
+    let input = test_file("orientation1_identity.jxl");
+    let decode = |column_major: bool| {
+        let path = std::env::temp_dir().join(format!(
+            "jxl_cli_column_major_{column_major}_{}.npy",
+            std::process::id()
+        ));
+        let mut args = vec![input.as_os_str(), path.as_os_str()];
+        if column_major {
+            args.push("--column-major".as_ref());
+        }
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(0));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header_len = 10 + u16::from_le_bytes([data[8], data[9]]) as usize;
+        let header = String::from_utf8(data[10..header_len].to_vec()).unwrap();
+        let shape: Vec<usize> = header
+            .split_once("'shape': (")
+            .unwrap()
+            .1
+            .split_once(')')
+            .unwrap()
+            .0
+            .split(", ")
+            .map(|dim| dim.parse().unwrap())
+            .collect();
+        (header, shape, data[header_len..].to_vec())
+    };
+    let (row_header, row_shape, row_major) = decode(false);
+    let (column_header, column_shape, column_major) = decode(true);
+    assert!(
+        row_header.contains("'fortran_order': False"),
+        "{row_header}"
+    );
+    assert!(
+        column_header.contains("'fortran_order': True"),
+        "{column_header}"
+    );
+    let [frames, height, width, channels] = row_shape[..] else {
+        panic!("{row_header}");
+    };
+    assert_eq!(column_shape, [channels, height, width, frames]);
+    assert_ne!(width, height);
+    assert_eq!(row_major.len(), column_major.len());
+    let pixel_bytes = channels * 4;
+    for y in 0..height {
+        for x in 0..width {
+            assert_eq!(
+                row_major[(y * width + x) * pixel_bytes..][..pixel_bytes],
+                column_major[(x * height + y) * pixel_bytes..][..pixel_bytes],
+                "({x}, {y})"
+            );
+        }
+    }
+
+    let output = run(&[
+        input.as_os_str(),
+        test_file("out.png").as_os_str(),
+        "--column-major".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn map_as_json() {
+    let input = test_file("has_permutation_with_container.jxl");
+    let output = run(&["map".as_ref(), "--json".as_ref(), input.as_os_str()]);
+    assert_eq!(output.status.code(), Some(0));
+    let map: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(map["boxes"][0]["kind"], "JXL ");
+    let sections = map["frames"][0]["sections"].as_array().unwrap();
+    let last = sections.last().unwrap();
+    assert_eq!(
+        last["offset"].as_u64().unwrap() + last["len"].as_u64().unwrap(),
+        map["codestream_len"].as_u64().unwrap()
+    );
+}
+
+#[test]
+fn verify_checksums_locates_corrupted_section() {
+    let input = test_file("has_permutation_with_container.jxl");
+    let dir = std::env::temp_dir();
+    let checksums = dir.join(format!("jxl_cli_checksums_{}.txt", std::process::id()));
+    let corrupted = dir.join(format!("jxl_cli_corrupted_{}.jxl", std::process::id()));
+    let output = run(&[
+        input.as_os_str(),
+        "--checksum-out".as_ref(),
+        checksums.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = run(&[
+        input.as_os_str(),
+        "--verify-checksums".as_ref(),
+        checksums.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    // Flip a bit in the middle of an AC group.
+    let mut bytes = std::fs::read(&input).unwrap();
+    let map = jxl::api::map_file(&bytes).unwrap();
+    let section = map.frames[0]
+        .sections
+        .iter()
+        .find(|s| {
+            matches!(s.section, Some(jxl::api::Section::Hf { group, pass })
+                if group.index() == 9 && pass.index() == 0)
+        })
+        .unwrap();
+    let file_offset = map.file_offset(section.offset + section.len / 2).unwrap();
+    bytes[file_offset as usize] ^= 0x10;
+    std::fs::write(&corrupted, &bytes).unwrap();
+    let output = run(&[
+        corrupted.as_os_str(),
+        "--verify-checksums".as_ref(),
+        checksums.as_os_str(),
+    ]);
+    std::fs::remove_file(&checksums).unwrap();
+    std::fs::remove_file(&corrupted).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let changed: Vec<_> = stdout
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(changed, ["codestream", "frame 0 Hf 9 pass 0"], "{stdout}");
+    assert!(stdout.contains(&format!(
+        "file offset {}",
+        map.file_offset(section.offset).unwrap()
+    )));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("2 of "));
+}
+
+#[test]
+fn forced_scalar_matches_simd() {
+    let input = test_file("basic.jxl");
+    let decode = |force_scalar: bool| {
+        let path = std::env::temp_dir().join(format!(
+            "jxl_cli_scalar_{force_scalar}_{}.ppm",
+            std::process::id()
+        ));
+        let mut command = Command::new(env!("CARGO_BIN_EXE_jxl_cli"));
+        command.args([input.as_os_str(), path.as_os_str(), "--verbose".as_ref()]);
+        if force_scalar {
+            command.env("JXL_RS_FORCE_SCALAR", "1");
+        } else {
+            command.env_remove("JXL_RS_FORCE_SCALAR");
+        }
+        let output = command.output().unwrap();
+        assert_eq!(output.status.code(), Some(0));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (String::from_utf8(output.stderr).unwrap(), data)
+    };
+    let (scalar_log, scalar) = decode(true);
+    assert!(
+        scalar_log.contains("SIMD: scalar (forced by JXL_RS_FORCE_SCALAR)"),
+        "{scalar_log}"
+    );
+    let (simd_log, simd) = decode(false);
+    assert!(simd_log.contains("SIMD: "), "{simd_log}");
+    assert_eq!(scalar, simd);
+}
+
+#[test]
+fn frame_selection_reuses_buffers() {
+    let input = test_file("conformance_test_images/animation_spline.jxl");
+    // A single PPM image on stdout holds the first selected frame.
+    let decode = |pool_depth: &str| {
+        let output = run(&[
+            input.as_os_str(),
+            "-".as_ref(),
+            "--format=ppm".as_ref(),
+            "--frames=2..".as_ref(),
+            "--frame-pool-depth".as_ref(),
+            pool_depth.as_ref(),
+            "--verbose".as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        (allocated_buffers(&output), output.stdout)
+    };
+    let (unpooled_allocations, unpooled) = decode("0");
+    let (allocations, pooled) = decode("2");
+    // Frames after the selected one reuse buffers that still hold the pixels of earlier frames.
+    assert_eq!(pooled, unpooled);
+    assert!(allocations < unpooled_allocations);
+}
+
+/// Number of output buffers allocated by a decoding with `--verbose`.
+fn allocated_buffers(output: &Output) -> usize {
+    let log = String::from_utf8_lossy(&output.stderr);
+    log.lines()
+        .find_map(|line| line.strip_prefix("Allocated "))
+        .and_then(|line| line.split(' ').next()?.parse().ok())
+        .unwrap_or_else(|| panic!("{log}"))
+}
+
+/// An animation of `num_frames` frames, made of the headers and the first frame of an animation
+/// whose frames are all coded on their own, followed by copies of its second frame and its last
+/// frame.
+fn generated_animation(num_frames: usize) -> Vec<u8> {
+    let data = std::fs::read(test_file("conformance_test_images/animation_spline.jxl")).unwrap();
+    let map = jxl::api::map_file(&data).unwrap();
+    assert!(map.boxes.is_empty());
+    let starts: Vec<usize> = map
+        .frames
+        .iter()
+        .map(|frame| (frame.header_bits_offset / 8) as usize)
+        .chain([data.len()])
+        .collect();
+    let frame = |i: usize| &data[starts[i]..starts[i + 1]];
+    let mut animation = data[..starts[2]].to_vec();
+    for _ in 2..num_frames - 1 {
+        animation.extend_from_slice(frame(1));
+    }
+    animation.extend_from_slice(frame(map.frames.len() - 1));
+    animation
+}
+
+#[test]
+fn animations_are_written_frame_by_frame() {
+    let input = std::env::temp_dir().join(format!("jxl_cli_animation_{}.jxl", std::process::id()));
+    std::fs::write(&input, generated_animation(50)).unwrap();
+    let path =
+        std::env::temp_dir().join(format!("jxl_cli_frame_by_frame_{}.npy", std::process::id()));
+    let output = run(&[input.as_os_str(), path.as_os_str(), "--verbose".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    let streamed = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let header_len = 10 + u16::from_le_bytes([streamed[8], streamed[9]]) as usize;
+    let header = String::from_utf8_lossy(&streamed[..header_len]);
+    assert!(header.contains("'shape': (50, 320, 320, 3)"), "{header}");
+    // Each RGB frame has a single buffer, which returns to the pool once it is written, so the
+    // output never holds more than two frames.
+    let allocations = allocated_buffers(&output);
+    assert!(allocations <= 2, "{allocations}");
+
+    // The header cannot be completed on stdout, so the frames are counted before decoding them.
+    let output = run(&[
+        input.as_os_str(),
+        "-".as_ref(),
+        "--format=npy".as_ref(),
+        "--verbose".as_ref(),
+    ]);
+    std::fs::remove_file(&input).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    let allocations = allocated_buffers(&output);
+    assert!(allocations <= 2, "{allocations}");
+    assert!(output.stdout == streamed);
+}
+
+#[test]
+fn animations_are_written_one_file_per_frame() {
+    let input = std::env::temp_dir().join(format!("jxl_cli_frames_{}.jxl", std::process::id()));
+    std::fs::write(&input, generated_animation(3)).unwrap();
+    let dir = std::env::temp_dir().join(format!("jxl_cli_frame_files_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let decode = |output: &str, extra_args: &[&str]| {
+        let path = dir.join(output);
+        let mut args = vec![input.as_os_str(), path.as_os_str()];
+        args.extend(extra_args.iter().map(std::ffi::OsStr::new));
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(0), "{output:?}");
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        let files: Vec<_> = names
+            .iter()
+            .map(|name| std::fs::read(dir.join(name)).unwrap())
+            .collect();
+        for name in &names {
+            std::fs::remove_file(dir.join(name)).unwrap();
+        }
+        (names, files)
+    };
+
+    // Formats that only hold one frame get a file for each, numbered before the extension.
+    let (names, numbered) = decode("out.ppm", &[]);
+    assert_eq!(names, ["out-000.ppm", "out-001.ppm", "out-002.ppm"]);
+    let first = run(&[input.as_os_str(), "-".as_ref(), "--format=ppm".as_ref()]);
+    assert!(first.stdout == numbered[0]);
+    assert!(numbered[0] != numbered[2]);
+    // Outputs that keep the frames write the same files.
+    let (names, kept) = decode("out.ppm", &["--render-interval=1000"]);
+    assert_eq!(names.len(), 3);
+    assert!(kept == numbered);
+
+    // Patterns number the files of any format.
+    let (names, _) = decode("frame_%02d.png", &[]);
+    assert_eq!(names, ["frame_00.png", "frame_01.png", "frame_02.png"]);
+
+    // Single frames are written to the named file, as are animations to formats with frames.
+    let (names, _) = decode("out.ppm", &["--frames=1"]);
+    assert_eq!(names, ["out.ppm"]);
+    let (names, _) = decode("out.png", &[]);
+    assert_eq!(names, ["out.png"]);
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+/// Converts `input` to `output` with `--cache-dir cache_dir` and `args`, returning whether the
+/// input was decoded rather than restored from the cache.
+fn decoded_with_cache(
+    input: &std::path::Path,
+    output: &std::path::Path,
+    cache_dir: &std::path::Path,
+    args: &[&str],
+) -> bool {
+    let mut all_args = vec![
+        input.as_os_str(),
+        output.as_os_str(),
+        "--cache-dir".as_ref(),
+        cache_dir.as_os_str(),
+        "--verbose".as_ref(),
+    ];
+    all_args.extend(args.iter().map(std::ffi::OsStr::new));
+    let result = run(&all_args);
+    assert_eq!(result.status.code(), Some(0), "{result:?}");
+    let log = String::from_utf8_lossy(&result.stderr);
+    let decoded = log.lines().any(|line| line.starts_with("Decoded "));
+    let restored = log.lines().any(|line| line.starts_with("Restored "));
+    assert_ne!(decoded, restored, "{log}");
+    decoded
+}
+
+#[test]
+fn second_batch_run_does_not_decode() {
+    let dir = std::env::temp_dir().join(format!("jxl_cli_cache_batch_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cache_dir = dir.join("cache");
+    let inputs = [test_file("basic.jxl"), test_file("3x3_srgb_lossless.jxl")];
+    let mut first_outputs = vec![];
+    for run in 0..2 {
+        for (i, input) in inputs.iter().enumerate() {
+            let output = dir.join(format!("{run}_{i}.png"));
+            assert_eq!(
+                decoded_with_cache(input, &output, &cache_dir, &[]),
+                run == 0
+            );
+            let data = std::fs::read(&output).unwrap();
+            if run == 0 {
+                first_outputs.push(data);
+            } else {
+                assert!(data == first_outputs[i]);
+            }
+        }
+    }
+    // Verification decodes again.
+    let output = dir.join("verify.png");
+    assert!(decoded_with_cache(
+        &inputs[0],
+        &output,
+        &cache_dir,
+        &["--cache-verify"]
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn prefer_icc_profile_misses_cache() {
+    let dir = std::env::temp_dir().join(format!("jxl_cli_cache_icc_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let cache_dir = dir.join("cache");
+    let input = test_file("basic.jxl");
+    let output = dir.join("output.png");
+    assert!(decoded_with_cache(&input, &output, &cache_dir, &[]));
+    assert!(!decoded_with_cache(&input, &output, &cache_dir, &[]));
+    let flag = ["--prefer-icc-profile"];
+    assert!(decoded_with_cache(&input, &output, &cache_dir, &flag));
+    assert!(!decoded_with_cache(&input, &output, &cache_dir, &flag));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn npy_rows_with_extra_channels_are_streamed_to_stdout() {
+    let input = test_file("extra_channels.jxl");
+    let path = std::env::temp_dir().join(format!("jxl_cli_extra_{}.npy", std::process::id()));
+    let output = run(&[input.as_os_str(), path.as_os_str()]);
+    assert_eq!(output.status.code(), Some(0));
+    let buffered = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let output = run(&[input.as_os_str(), "-".as_ref(), "--format=npy".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout == buffered);
+}
+
+/// Splits a .npy file into the shape of its array and its samples.
+fn npy_shape_and_data(data: &[u8]) -> (Vec<usize>, &[u8]) {
+    let header_len = 10 + u16::from_le_bytes([data[8], data[9]]) as usize;
+    let header = std::str::from_utf8(&data[10..header_len]).unwrap();
+    let (_, shape) = header.split_once("'shape': (").unwrap();
+    let shape = shape.split_once(')').unwrap().0;
+    let shape = shape.split(", ").map(|dim| dim.parse().unwrap()).collect();
+    (shape, &data[header_len..])
+}
+
+#[test]
+fn crop_decodes_a_region_of_the_image() {
+    for (file, crop) in [
+        ("extra_channels.jxl", [2, 1, 5, 6]),
+        ("orientation6_rotate_90_cw.jxl", [100, 30, 150, 41]),
+    ] {
+        let input = test_file(file);
+        let decode = |crop: Option<String>| {
+            let mut args = vec![input.as_os_str(), "-".as_ref(), "--format=npy".as_ref()];
+            if let Some(crop) = &crop {
+                args.push("--crop".as_ref());
+                args.push(crop.as_ref());
+            }
+            let output = run(&args);
+            assert_eq!(output.status.code(), Some(0), "{file}");
+            output.stdout
+        };
+        let full = decode(None);
+        let cropped = decode(Some(crop.map(|v| v.to_string()).join(",")));
+        let (full_shape, full) = npy_shape_and_data(&full);
+        let (cropped_shape, cropped) = npy_shape_and_data(&cropped);
+        let [x, y, width, height] = crop;
+        let [frames, _, full_width, channels] = full_shape[..] else {
+            panic!("{full_shape:?}");
+        };
+        assert_eq!(cropped_shape, [frames, height, width, channels], "{file}");
+        // Samples are 32-bit floats.
+        let pixel_bytes = channels * 4;
+        for row in 0..height {
+            assert!(
+                cropped[row * width * pixel_bytes..][..width * pixel_bytes]
+                    == full[((y + row) * full_width + x) * pixel_bytes..][..width * pixel_bytes],
+                "{file}, row {row}"
+            );
+        }
+    }
+}
+
+#[test]
+fn crop_past_the_image_is_rejected() {
+    let input = test_file("extra_channels.jxl");
+    for crop in ["4,0,5,8", "0,0,8,9", "0,0,0,8", "1,2,3"] {
+        let output = run(&[
+            input.as_os_str(),
+            "-".as_ref(),
+            "--format=npy".as_ref(),
+            "--crop".as_ref(),
+            crop.as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(2), "{crop}");
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("crop"), "{stderr}");
+    }
+}
+
+#[test]
+fn downsample_divides_the_output_size() {
+    // progressive_ac.jxl is a 2268x1512 VarDCT image, which is decoded from its LF image only.
+    // orientation6_rotate_90_cw.jxl is a modular image, displayed with a size of 256x100,
+    // which cannot be decoded downsampled.
+    for (file, factor, shape, fallback) in [
+        ("progressive_ac.jxl", "8", [1, 189, 284, 3], false),
+        ("orientation6_rotate_90_cw.jxl", "2", [1, 50, 128, 3], true),
+    ] {
+        let output = run(&[
+            test_file(file).as_os_str(),
+            "-".as_ref(),
+            "--format=npy".as_ref(),
+            "--downsample".as_ref(),
+            factor.as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(0), "{file}");
+        assert_eq!(npy_shape_and_data(&output.stdout).0, shape, "{file}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(stderr.contains("at full resolution"), fallback, "{stderr}");
+    }
+}
+
+#[test]
+fn unsupported_downsample_factor_is_rejected() {
+    let output = run(&[
+        test_file("extra_channels.jxl").as_os_str(),
+        "-".as_ref(),
+        "--format=npy".as_ref(),
+        "--downsample".as_ref(),
+        "3".as_ref(),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("downsampling factor"), "{stderr}");
+}
+
+#[test]
+fn verbose_version_lists_capabilities() {
+    let output = run(&["--version".as_ref()]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("jxl_cli "), "{stdout}");
+    assert!(!stdout.contains("progressive_flush"), "{stdout}");
+
+    let output = run(&["-V".as_ref(), "--verbose".as_ref()]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("progressive_flush"), "{stdout}");
+    assert!(stdout.contains("output formats"), "{stdout}");
+}
+
+#[test]
+fn frame_ranges_skip_other_frames() {
+    let input = std::env::temp_dir().join(format!("jxl_cli_ranges_{}.jxl", std::process::id()));
+    std::fs::write(&input, generated_animation(8)).unwrap();
+    let decode = |frames: Option<&str>| {
+        let mut args = vec![
+            input.as_os_str(),
+            "-".as_ref(),
+            "--format=raw".as_ref(),
+            "--verbose".as_ref(),
+        ];
+        if let Some(frames) = frames {
+            args.push("--frames".as_ref());
+            args.push(frames.as_ref());
+        }
+        run(&args)
+    };
+    let all = decode(None);
+    let range = decode(Some("3..6"));
+    let out_of_range = decode(Some("8"));
+    std::fs::remove_file(&input).unwrap();
+
+    assert_eq!(all.status.code(), Some(0));
+    assert_eq!(range.status.code(), Some(0));
+    // Skipped frames are not rendered, so only the selected ones need buffers.
+    assert_eq!(allocated_buffers(&range), 3);
+    // Raw files are a 24-byte header followed by the frames.
+    let frame_size = (all.stdout.len() - 24) / 8;
+    assert_eq!(range.stdout.len(), 24 + 3 * frame_size);
+    assert!(range.stdout[24..] == all.stdout[24 + 3 * frame_size..24 + 6 * frame_size]);
+
+    assert_eq!(out_of_range.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&out_of_range.stderr);
+    assert!(
+        stderr.contains("Frame 8 is out of range, the image has 8 frame(s)"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn icc_profile_disagreeing_with_color_space() {
+    // Flipping a bit of the color space makes the grayscale image with a grayscale ICC profile
+    // signal a color image.
+    let mut bytes = std::fs::read(test_file("with_icc.jxl")).unwrap();
+    bytes[6] ^= 1 << 6;
+    let input = std::env::temp_dir().join(format!("jxl_cli_mismatch_{}.jxl", std::process::id()));
+    std::fs::write(&input, bytes).unwrap();
+    let info = |prefer_icc: bool| {
+        let mut args = vec![input.as_os_str(), "--info".as_ref()];
+        if prefer_icc {
+            args.push("--prefer-icc-profile".as_ref());
+        }
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(0));
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+    let (enumerated_out, enumerated_err) = info(false);
+    let (icc_out, icc_err) = info(true);
+    std::fs::remove_file(&input).unwrap();
+
+    assert!(
+        enumerated_out.contains("Color profile: RGB, D65, sRGB primaries, sRGB transfer"),
+        "{enumerated_out}"
+    );
+    assert!(
+        enumerated_err.contains("Warning: ICC profile disagrees with the enumerated color space"),
+        "{enumerated_err}"
+    );
+    assert!(
+        icc_out.contains("Color profile: Grayscale, ICC profile"),
+        "{icc_out}"
+    );
+    assert!(
+        icc_err.contains("using Grayscale, ICC profile"),
+        "{icc_err}"
+    );
+}
+
+#[test]
+fn exif_size_disagreeing_with_codestream() {
+    // Big-endian TIFF data whose first IFD declares a width of 3 and a height of 5.
+    let mut exif = b"MM\0*\0\0\0\x08\0\x02".to_vec();
+    exif.extend(b"\x01\0\0\x03\0\0\0\x01\0\x03\0\0");
+    exif.extend(b"\x01\x01\0\x03\0\0\0\x01\0\x05\0\0");
+    exif.extend(b"\0\0\0\0");
+    let temp = |name: &str| {
+        std::env::temp_dir().join(format!("jxl_cli_size_{name}_{}", std::process::id()))
+    };
+    let (exif_path, input) = (temp("exif"), temp("file.jxl"));
+    std::fs::write(&exif_path, exif).unwrap();
+    let output = run(&[
+        "remux".as_ref(),
+        "--add-exif".as_ref(),
+        exif_path.as_os_str(),
+        test_file("3x3_srgb_lossless.jxl").as_os_str(),
+        input.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let info = run(&[input.as_os_str(), "--info".as_ref()]);
+    let rejected = run(&[
+        input.as_os_str(),
+        "--info".as_ref(),
+        "--reject-size-mismatch".as_ref(),
+    ]);
+    std::fs::remove_file(&exif_path).unwrap();
+    std::fs::remove_file(&input).unwrap();
+
+    assert_eq!(info.status.code(), Some(0));
+    let stdout = String::from_utf8(info.stdout).unwrap();
+    assert!(stdout.contains("Image size: 3x3"), "{stdout}");
+    let stderr = String::from_utf8(info.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: Exif metadata declares an image size of 3x5"),
+        "{stderr}"
+    );
+    assert_eq!(rejected.status.code(), Some(1));
+    let stderr = String::from_utf8(rejected.stderr).unwrap();
+    assert!(
+        stderr.contains("Container declares an image size of 3x5"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn sdr_and_hdr_rendering() {
+    let input = test_file("pq_gradient.jxl");
+    let decode = |rendering: Option<&str>| {
+        let name = rendering.unwrap_or("default");
+        let path = std::env::temp_dir().join(format!("jxl_cli_{name}_{}.pgm", std::process::id()));
+        let mut args = vec![input.as_os_str(), path.as_os_str()];
+        if let Some(rendering) = rendering {
+            args.push("--rendering".as_ref());
+            args.push(rendering.as_ref());
+        }
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(0));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    };
+    let hdr = decode(Some("hdr"));
+    assert_eq!(decode(None), hdr);
+    assert_ne!(decode(Some("SDR")), hdr);
+
+    let output = run(&[input.as_os_str(), "--rendering=dim".as_ref()]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn fast_speed_profile_is_reported() {
+    let input = test_file("conformance_test_images/animation_icos4d.jxl");
+    let decode = |profile: &str| {
+        let path =
+            std::env::temp_dir().join(format!("jxl_cli_{profile}_{}.png", std::process::id()));
+        let output = run(&[
+            input.as_os_str(),
+            path.as_os_str(),
+            "--speed-profile".as_ref(),
+            profile.as_ref(),
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (String::from_utf8(output.stderr).unwrap(), data)
+    };
+    let (log, faithful) = decode("faithful");
+    assert!(log.is_empty(), "{log}");
+    let (log, fast) = decode("fast");
+    assert_ne!(fast, faithful);
+    // Reported once, although every frame of the animation is simplified.
+    assert_eq!(
+        log,
+        "Warning: rendering with simplifications for speed: skipped extra EPF passes, \
+         separable Gaborish\n"
+    );
+}
+
+#[cfg(feature = "verify")]
+#[test]
+fn verify_reports_each_modular_channel() {
+    let input = test_file("grayscale_patches_modular.jxl");
+    let output = run(&[input.as_os_str(), "--verify".as_ref()]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Frame 0: stream "), "{stdout}");
+    assert!(
+        stdout
+            .lines()
+            .all(|line| line.ends_with(": passed") || line.ends_with(": skipped")),
+        "{stdout}"
+    );
+}
+
+#[cfg(feature = "debug-tools")]
+#[test]
+fn info_verbose_validates_icc() {
+    let validate = |name: &str| {
+        let input = test_file(name);
+        let output = run(&[input.as_os_str(), "--info".as_ref(), "--verbose".as_ref()]);
+        assert_eq!(output.status.code(), Some(0));
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(validate("lossy_with_icc.jxl").contains("ICC profile issues: none\n"));
+    // libjxl writes the white point of grayscale profiles as is.
+    assert!(
+        validate("with_icc.jxl")
+            .contains("ICC profile issue: media white point of a display profile is not D50\n")
+    );
+    // Images without an ICC profile have nothing to validate.
+    assert!(!validate("basic.jxl").contains("ICC profile"));
+}
+
+#[cfg(feature = "debug-tools")]
+#[test]
+fn dump_entropy_schema() {
+    let input = test_file("with_icc.jxl");
+    let path = std::env::temp_dir().join(format!("jxl_cli_entropy_{}.json", std::process::id()));
+    let output = run(&[
+        input.as_os_str(),
+        "--dump-entropy".as_ref(),
+        path.as_os_str(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let dump: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let codes = dump["codes"].as_array().unwrap();
+    assert_eq!(codes[0]["purpose"], "icc_profile");
+    assert!(codes[0]["frame"].is_null() && codes[0]["section"].is_null());
+    for code in codes {
+        assert!(code["purpose"].is_string(), "{code}");
+        assert!(code["lz77"].is_null() || code["lz77"]["length_uint"].is_object());
+        let context_map = code["context_map"].as_array().unwrap();
+        let clusters = code["clusters"].as_array().unwrap();
+        assert!(
+            context_map
+                .iter()
+                .all(|c| (c.as_u64().unwrap() as usize) < clusters.len())
+        );
+        for cluster in clusters {
+            assert!(cluster["uint"]["split_exponent"].is_u64(), "{cluster}");
+            let distribution = &cluster["distribution"];
+            match distribution["kind"].as_str().unwrap() {
+                "ans" => {
+                    let counts = distribution["counts"].as_array().unwrap();
+                    let sum: u64 = counts.iter().map(|c| c.as_u64().unwrap()).sum();
+                    assert_eq!(sum, 4096);
+                }
+                "prefix" => assert!(distribution["code_lengths"].is_array()),
+                "prefix_single" => assert!(distribution["symbol"].is_u64()),
+                kind => panic!("unexpected distribution kind {kind}"),
+            }
+        }
+    }
+    assert!(
+        codes
+            .iter()
+            .any(|code| code["frame"] == 0 && code["section"] == "LfGlobal")
+    );
+}
+
+#[test]
+fn frame_list() {
+    let input = test_file("named_frame_test.jxl");
+    for scan in [false, true] {
+        let mut args = vec![input.as_os_str(), "--list-frames".as_ref()];
+        if scan {
+            args.push("--scan".as_ref());
+        }
+        let output = run(&args);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "Frame 0: name \"TestFrameName\", duration 0 ms\n"
+        );
+    }
+}
+
+#[test]
+fn corrupt_groups_are_concealed() {
+    let dir = std::env::temp_dir();
+    let corrupted = dir.join(format!("jxl_cli_concealed_{}.jxl", std::process::id()));
+    let output_path = dir.join(format!("jxl_cli_concealed_{}.png", std::process::id()));
+    let mut bytes = std::fs::read(test_file("green_queen_vardct_e3.jxl")).unwrap();
+    let map = jxl::api::map_file(&bytes).unwrap();
+    for section in &map.frames.last().unwrap().sections {
+        if matches!(section.section, Some(jxl::api::Section::Hf { group, .. })
+            if group.index() == 0 || group.index() == 5)
+        {
+            for offset in section.offset..section.offset + section.len {
+                bytes[map.file_offset(offset).unwrap() as usize] ^= 0xff;
+            }
+        }
+    }
+    std::fs::write(&corrupted, &bytes).unwrap();
+
+    let output = run(&[corrupted.as_os_str(), output_path.as_os_str()]);
+    assert_eq!(output.status.code(), Some(1));
+    let output = run(&[
+        corrupted.as_os_str(),
+        output_path.as_os_str(),
+        "--skip-corrupt-sections".as_ref(),
+    ]);
+    std::fs::remove_file(&corrupted).unwrap();
+    let written = output_path.exists();
+    std::fs::remove_file(&output_path).ok();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(written);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("concealed 2 of 6 groups"), "{stderr}");
+}
@@ -0,0 +1,138 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Differential testing against libjxl: decodes each test image with djxl and with `jxl_cli` to
+//! npy files, and checks that their samples differ by no more than the tolerances of the image
+//! in `tolerances.toml`.
+//!
+//! The comparison only runs when `JXL_RS_DIFF_DJXL` is set to the path of djxl:
+//!
+//! ```sh
+//! JXL_RS_DIFF_DJXL=/path/to/djxl cargo test --release -p jxl_cli --test djxl_diff -- --nocapture
+//! ```
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod metrics;
+mod npy;
+mod tolerances;
+
+/// Environment variable with the path of the djxl binary to compare with.
+const DJXL_ENV: &str = "JXL_RS_DIFF_DJXL";
+
+fn test_file(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("../jxl/resources/test")
+        .join(name)
+}
+
+/// Names of the test images, relative to `jxl/resources/test`.
+fn corpus() -> Vec<String> {
+    let mut names = vec![];
+    for dir in ["", "conformance_test_images/"] {
+        for entry in std::fs::read_dir(test_file(dir)).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|e| e == "jxl") {
+                let file_name = path.file_name().unwrap().to_string_lossy();
+                names.push(format!("{dir}{file_name}"));
+            }
+        }
+    }
+    names.sort();
+    names
+}
+
+fn load_tolerances() -> tolerances::Tolerances {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/djxl_diff/tolerances.toml");
+    tolerances::parse(&std::fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// Runs `decoder` to decode `input` to `output`, and reads the result.
+fn decode(decoder: &OsStr, input: &Path, output: &Path) -> Result<npy::Npy, String> {
+    let result = Command::new(decoder)
+        .args([input.as_os_str(), output.as_os_str()])
+        .output()
+        .map_err(|e| format!("cannot run {}: {e}", decoder.to_string_lossy()))?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let message = stderr.lines().next().unwrap_or_default();
+        return Err(format!("{} failed: {message}", decoder.to_string_lossy()));
+    }
+    let bytes = std::fs::read(output).map_err(|e| e.to_string())?;
+    std::fs::remove_file(output).map_err(|e| e.to_string())?;
+    npy::read(&bytes)
+}
+
+#[test]
+fn matches_djxl() {
+    let Some(djxl) = std::env::var_os(DJXL_ENV) else {
+        eprintln!("{DJXL_ENV} is not set, skipping the comparison with djxl");
+        return;
+    };
+    let tolerances = load_tolerances();
+    let dir = std::env::temp_dir().join(format!("jxl_rs_diff_djxl_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut table = format!(
+        "{:<56} {:<9} {:>10} {:>10}  result\n",
+        "file", "class", "peak", "rmse"
+    );
+    let mut failures = 0;
+    for name in corpus() {
+        let tolerance = tolerances.for_file(&name);
+        let input = test_file(&name);
+        let difference = match &tolerance.skip {
+            Some(reason) => Err(format!("skipped: {reason}")),
+            None => decode(&djxl, &input, &dir.join("djxl.npy")).and_then(|reference| {
+                let jxl_cli = OsStr::new(env!("CARGO_BIN_EXE_jxl_cli"));
+                let image = decode(jxl_cli, &input, &dir.join("jxl_cli.npy"))?;
+                metrics::compare(&image, &reference)
+            }),
+        };
+        let (peak_error, rmse, result) = match difference {
+            Ok(difference) if tolerance.allows(&difference) => (
+                format!("{:.3e}", difference.peak_error),
+                format!("{:.3e}", difference.rmse),
+                "ok".to_string(),
+            ),
+            Ok(difference) => {
+                failures += 1;
+                (
+                    format!("{:.3e}", difference.peak_error),
+                    format!("{:.3e}", difference.rmse),
+                    format!(
+                        "FAILED, limits {:.3e} and {:.3e}",
+                        tolerance.peak_error, tolerance.rmse
+                    ),
+                )
+            }
+            Err(message) if tolerance.skip.is_some() => ("-".into(), "-".into(), message),
+            Err(message) => {
+                failures += 1;
+                ("-".into(), "-".into(), format!("FAILED, {message}"))
+            }
+        };
+        table.push_str(&format!(
+            "{name:<56} {:<9} {peak_error:>10} {rmse:>10}  {result}\n",
+            tolerance.class
+        ));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    println!("{table}");
+    assert_eq!(failures, 0, "outputs differ from djxl:\n{table}");
+}
+
+#[test]
+fn tolerances_list_test_images() {
+    let corpus = corpus();
+    for name in load_tolerances().files() {
+        assert!(
+            corpus.iter().any(|n| n == name),
+            "{name} is not a test image"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Differences between decoded images, with the metrics of the libjxl conformance tests.
+
+use super::npy::Npy;
+
+/// Difference between two decoded images with the same shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difference {
+    /// Largest absolute difference of any sample.
+    pub peak_error: f32,
+    /// Root mean square of the differences of all samples.
+    pub rmse: f32,
+}
+
+pub fn compare(image: &Npy, reference: &Npy) -> Result<Difference, String> {
+    if image.shape != reference.shape {
+        return Err(format!(
+            "shape {:?} differs from the reference {:?}",
+            image.shape, reference.shape
+        ));
+    }
+    let mut peak_error = 0.0f32;
+    let mut squared_error = 0.0f64;
+    for (a, b) in image.data.iter().zip(&reference.data) {
+        let error = (a - b).abs();
+        // NaN samples count as infinitely different, unless both are NaN.
+        let error = if error.is_nan() && !(a.is_nan() && b.is_nan()) {
+            f32::INFINITY
+        } else if error.is_nan() {
+            0.0
+        } else {
+            error
+        };
+        peak_error = peak_error.max(error);
+        squared_error += error as f64 * error as f64;
+    }
+    let rmse = (squared_error / image.data.len().max(1) as f64).sqrt() as f32;
+    Ok(Difference { peak_error, rmse })
+}
+
+#[test]
+fn differences() {
+    let npy = |data: &[f32]| Npy {
+        shape: vec![1, 1, data.len(), 1],
+        data: data.to_vec(),
+    };
+    let reference = npy(&[0.0, 0.5, 1.0, 0.25]);
+    let same = compare(&reference, &reference).unwrap();
+    assert_eq!(
+        same,
+        Difference {
+            peak_error: 0.0,
+            rmse: 0.0
+        }
+    );
+
+    let difference = compare(&npy(&[0.0, 0.5, 0.8, 0.25]), &reference).unwrap();
+    assert!((difference.peak_error - 0.2).abs() < 1e-6);
+    assert!((difference.rmse - 0.1).abs() < 1e-6);
+
+    let nan = compare(&npy(&[0.0, f32::NAN, 1.0, 0.25]), &reference).unwrap();
+    assert_eq!(nan.peak_error, f32::INFINITY);
+    assert!(compare(&npy(&[0.0]), &reference).is_err());
+}
@@ -0,0 +1,143 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Reader for the `.npy` files written by djxl and `jxl_cli`.
+
+/// Samples of a row-major array, converted to `f32`. Integer samples are scaled to [0, 1], as
+/// `jxl_cli` does for float output.
+#[derive(Debug)]
+pub struct Npy {
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+/// Returns the value of `key` in the header dictionary, up to the next top-level comma.
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str, String> {
+    let start = header
+        .find(&format!("'{key}':"))
+        .ok_or_else(|| format!("no {key} in header {header:?}"))?
+        + key.len()
+        + 3;
+    let value = header[start..].trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')').map(|end| end + 1)
+    } else {
+        value.find([',', '}'])
+    };
+    Ok(value[..end.unwrap_or(value.len())].trim())
+}
+
+pub fn read(bytes: &[u8]) -> Result<Npy, String> {
+    let magic = b"\x93NUMPY";
+    if !bytes.starts_with(magic) || bytes.len() < 10 {
+        return Err("not an npy file".to_string());
+    }
+    // Version 1 has a 2-byte header length, versions 2 and 3 a 4-byte one.
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            12,
+        ),
+        version => return Err(format!("unsupported npy version {version}")),
+    };
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .ok_or("truncated npy header")?;
+    let header = std::str::from_utf8(header).map_err(|e| e.to_string())?;
+
+    if header_value(header, "fortran_order")? != "False" {
+        return Err("Fortran-order arrays are not supported".to_string());
+    }
+    let shape: Vec<usize> = header_value(header, "shape")?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| {
+            dim.parse()
+                .map_err(|_| format!("invalid shape in {header:?}"))
+        })
+        .collect::<Result<_, _>>()?;
+    let descr = header_value(header, "descr")?.trim_matches('\'');
+
+    let samples = &bytes[header_start + header_len..];
+    let num_samples: usize = shape.iter().product();
+    let (byte_order, kind) = descr.split_at(1);
+    let little_endian = match byte_order {
+        "<" | "|" => true,
+        ">" => false,
+        _ => return Err(format!("invalid dtype {descr}")),
+    };
+    let sample_size = match kind {
+        "u1" => 1,
+        "u2" | "f2" => 2,
+        "f4" => 4,
+        _ => return Err(format!("unsupported dtype {descr}")),
+    };
+    if samples.len() != num_samples * sample_size {
+        return Err(format!(
+            "{} bytes of samples for shape {shape:?} and dtype {descr}",
+            samples.len()
+        ));
+    }
+    let data = samples
+        .chunks_exact(sample_size)
+        .map(|sample| {
+            let mut sample: [u8; 4] = std::array::from_fn(|i| *sample.get(i).unwrap_or(&0));
+            if !little_endian {
+                sample[..sample_size].reverse();
+            }
+            match kind {
+                "u1" => sample[0] as f32 / 255.0,
+                "u2" => u16::from_le_bytes([sample[0], sample[1]]) as f32 / 65535.0,
+                "f2" => f16_to_f32(u16::from_le_bytes([sample[0], sample[1]])),
+                _ => f32::from_le_bytes(sample),
+            }
+        })
+        .collect();
+    Ok(Npy { shape, data })
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[test]
+fn read_jxl_cli_output() {
+    let input = super::test_file("3x3_srgb_lossless.jxl");
+    let path = std::env::temp_dir().join(format!("jxl_rs_npy_{}.npy", std::process::id()));
+    let decode = |data_type: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_jxl_cli"))
+            .args([input.as_os_str(), path.as_os_str()])
+            .args(["--data-type", data_type])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        read(&std::fs::read(&path).unwrap()).unwrap()
+    };
+    let reference = decode("f32");
+    assert_eq!(reference.shape, [1, 3, 3, 3]);
+    // The lossless 8-bit samples are represented exactly by all types, up to float rounding.
+    for data_type in ["f16", "u8", "u16"] {
+        let npy = decode(data_type);
+        assert_eq!(npy.shape, reference.shape, "{data_type}");
+        for (a, b) in npy.data.iter().zip(&reference.data) {
+            assert!((a - b).abs() < 1e-3, "{data_type}: {a} != {b}");
+        }
+    }
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(read(b"\x93NUMPY\x01\x00").is_err());
+    assert!(read(b"GIF89a").is_err());
+}
@@ -0,0 +1,177 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Per-file tolerances of the comparison with djxl, read from `tolerances.toml`.
+//!
+//! The file has a table per class of files, with the limits of the class and the `files` that it
+//! applies to, relative to `jxl/resources/test`. The `default` table applies to all other files.
+//! Only the part of TOML that the file uses is supported: tables, comments, and numbers, strings
+//! and arrays of strings as values.
+
+use super::metrics::Difference;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tolerance {
+    /// Name of the table that the tolerance comes from.
+    pub class: String,
+    pub peak_error: f32,
+    pub rmse: f32,
+    /// Reason to not compare the files at all.
+    pub skip: Option<String>,
+}
+
+impl Tolerance {
+    pub fn allows(&self, difference: &Difference) -> bool {
+        difference.peak_error <= self.peak_error && difference.rmse <= self.rmse
+    }
+}
+
+#[derive(Debug)]
+pub struct Tolerances {
+    default: Tolerance,
+    classes: Vec<(Tolerance, Vec<String>)>,
+}
+
+impl Tolerances {
+    pub fn for_file(&self, name: &str) -> &Tolerance {
+        self.classes
+            .iter()
+            .find(|(_, files)| files.iter().any(|f| f == name))
+            .map_or(&self.default, |(tolerance, _)| tolerance)
+    }
+
+    /// Names of the files that the tolerances list.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.classes
+            .iter()
+            .flat_map(|(_, files)| files.iter().map(String::as_str))
+    }
+}
+
+enum Value {
+    Number(f32),
+    String(String),
+    Array(Vec<String>),
+}
+
+fn parse_string(s: &str) -> Option<String> {
+    let s = s.trim().strip_prefix('"')?.strip_suffix('"')?;
+    (!s.contains(['"', '\\'])).then(|| s.to_string())
+}
+
+fn parse_value(s: &str) -> Option<Value> {
+    if let Some(items) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return items
+            .split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(parse_string)
+            .collect::<Option<_>>()
+            .map(Value::Array);
+    }
+    if s.starts_with('"') {
+        return parse_string(s).map(Value::String);
+    }
+    s.parse().ok().map(Value::Number)
+}
+
+pub fn parse(text: &str) -> Result<Tolerances, String> {
+    let mut tables: Vec<(Tolerance, Vec<String>)> = vec![];
+    let mut lines = text.lines().enumerate();
+    while let Some((i, line)) = lines.next() {
+        let error = |message: &str| format!("line {}: {message}: {line:?}", i + 1);
+        // Comments cannot contain quotes, which keeps strings with '#' unambiguous.
+        let mut line = line.split('#').next().unwrap().trim().to_string();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            if tables.iter().any(|(t, _)| t.class == name) {
+                return Err(error("duplicate table"));
+            }
+            let tolerance = Tolerance {
+                class: name.to_string(),
+                ..Default::default()
+            };
+            tables.push((tolerance, vec![]));
+            continue;
+        }
+        let (tolerance, files) = tables
+            .last_mut()
+            .ok_or_else(|| error("key outside a table"))?;
+        // Arrays may span several lines.
+        if line.ends_with('[') || (line.contains('[') && !line.contains(']')) {
+            for (_, next) in lines.by_ref() {
+                line.push_str(next.split('#').next().unwrap().trim());
+                if line.ends_with(']') {
+                    break;
+                }
+            }
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected key = value"))?;
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+        match (key.trim(), value) {
+            ("peak_error", Value::Number(n)) => tolerance.peak_error = n,
+            ("rmse", Value::Number(n)) => tolerance.rmse = n,
+            ("skip", Value::String(reason)) => tolerance.skip = Some(reason),
+            ("files", Value::Array(names)) => *files = names,
+            _ => return Err(error("unknown key or wrong type")),
+        }
+    }
+    let default = tables
+        .iter()
+        .position(|(t, _)| t.class == "default")
+        .ok_or("no default table")?;
+    let (default, _) = tables.remove(default);
+    Ok(Tolerances {
+        default,
+        classes: tables,
+    })
+}
+
+#[test]
+fn parse_tolerances() {
+    let tolerances = parse(
+        r#"
+        # Lossy files.
+        [default]
+        peak_error = 0.01
+        rmse = 1e-3
+
+        [lossless]
+        peak_error = 0
+        rmse = 0
+        files = [
+            "a.jxl",  # Comment.
+            "b.jxl",
+        ]
+
+        [broken]
+        skip = "reason"
+        files = ["c.jxl"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(tolerances.for_file("x.jxl").rmse, 1e-3);
+    assert_eq!(tolerances.for_file("b.jxl").class, "lossless");
+    assert_eq!(tolerances.for_file("b.jxl").peak_error, 0.0);
+    assert_eq!(tolerances.for_file("c.jxl").skip.as_deref(), Some("reason"));
+    assert_eq!(
+        tolerances.files().collect::<Vec<_>>(),
+        ["a.jxl", "b.jxl", "c.jxl"]
+    );
+
+    let difference = Difference {
+        peak_error: 0.005,
+        rmse: 2e-3,
+    };
+    assert!(!tolerances.for_file("x.jxl").allows(&difference));
+
+    assert!(parse("[lossless]\npeak_error = 0").is_err());
+    assert!(parse("rmse = 0\n[default]").is_err());
+    assert!(parse("[default]\nrmse = \"x\"").is_err());
+    assert!(parse("[default]\n[default]").is_err());
+}
@@ -136,8 +136,8 @@ impl JxlCmsTransformer for Lcms2Transformer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use jxl::api::RenderingIntent;
     use jxl::api::{JxlColorEncoding, JxlPrimaries, JxlTransferFunction, JxlWhitePoint};
-    use jxl::headers::color_encoding::RenderingIntent;
 
     fn srgb_profile() -> JxlColorProfile {
         JxlColorProfile::Simple(JxlColorEncoding::RgbColorSpace {
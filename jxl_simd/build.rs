@@ -0,0 +1,42 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Enables the code paths that need a compiler newer than the minimum supported Rust version,
+//! when the compiler building the crate supports them.
+
+#[path = "build/rustc_version.rs"]
+mod rustc_version;
+
+/// AVX-512 intrinsics and target features were stabilized in Rust 1.89.
+const AVX512_MIN_RUSTC: rustc_version::RustcVersion = (1, 89);
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(jxl_simd_avx512)");
+    println!(
+        "cargo::rerun-if-env-changed={}",
+        rustc_version::RUSTC_VERSION_OVERRIDE_ENV
+    );
+
+    if std::env::var_os("CARGO_FEATURE_AVX512").is_none() {
+        return;
+    }
+    match rustc_version::detect() {
+        Some(version) if version >= AVX512_MIN_RUSTC => {
+            println!("cargo::rustc-cfg=jxl_simd_avx512");
+        }
+        version => {
+            // Kernels fall back to the AVX2 tier, which is what the decoder also does on CPUs
+            // without AVX-512.
+            let version = version.map_or("unknown".to_string(), |(major, minor)| {
+                format!("{major}.{minor}")
+            });
+            println!(
+                "cargo::warning=the avx512 feature needs Rust {}.{} or newer (found {version}), \
+                 building without AVX-512 kernels",
+                AVX512_MIN_RUSTC.0, AVX512_MIN_RUSTC.1
+            );
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Detection of the version of the compiler building the crate, shared by the build script and
+//! its tests.
+
+use std::{env, process::Command};
+
+/// Environment variable that overrides the detected compiler version, as `MAJOR.MINOR`, to build
+/// the fallback paths of newer compilers.
+pub const RUSTC_VERSION_OVERRIDE_ENV: &str = "JXL_SIMD_RUSTC_VERSION";
+
+/// A `(major, minor)` compiler version.
+pub type RustcVersion = (u32, u32);
+
+/// Parses the version out of the output of `rustc --version`, such as
+/// `rustc 1.89.0 (29483883e 2025-08-04)` or `rustc 1.90.0-nightly (...)`. Also accepts a bare
+/// version such as `1.89.0` or `1.89`.
+pub fn parse(version: &str) -> Option<RustcVersion> {
+    let version = version.trim();
+    let version = version.strip_prefix("rustc ").unwrap_or(version);
+    let version = version.split([' ', '-']).next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Returns the version of the compiler that cargo builds the crate with, or `None` if it could
+/// not be determined.
+pub fn detect() -> Option<RustcVersion> {
+    if let Some(version) = env::var(RUSTC_VERSION_OVERRIDE_ENV)
+        .ok()
+        .filter(|v| !v.is_empty())
+    {
+        return parse(&version);
+    }
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse(std::str::from_utf8(&output.stdout).ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("rustc 1.89.0 (29483883e 2025-08-04)\n"),
+            Some((1, 89))
+        );
+        assert_eq!(
+            parse("rustc 1.90.0-nightly (abcdef012 2025-07-01)"),
+            Some((1, 90))
+        );
+        assert_eq!(parse("rustc 1.85.1"), Some((1, 85)));
+        assert_eq!(parse("1.88"), Some((1, 88)));
+        assert_eq!(parse(" 2.0.0 "), Some((2, 0)));
+        assert_eq!(parse("rustc"), None);
+        assert_eq!(parse("rustc 1"), None);
+        assert_eq!(parse("rustc one.two"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn test_avx512_follows_compiler_version() {
+        let version = detect().expect("rustc version");
+        assert_eq!(
+            cfg!(jxl_simd_avx512),
+            cfg!(feature = "avx512") && version >= (1, 89)
+        );
+    }
+}
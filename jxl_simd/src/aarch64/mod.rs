@@ -36,7 +36,7 @@ macro_rules! simd_function {
 #[macro_export]
 macro_rules! simd_function_body_neon {
     ($name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty )?; ($($val:expr),* $(,)?)) => {
-        if cfg!(target_feature = "neon") {
+        if cfg!(target_feature = "neon") && $crate::SimdTier::Neon.is_enabled() {
             // SAFETY: we just checked for neon.
             let d = unsafe { $crate::NeonDescriptor::new_unchecked() };
             return $name(d, $($val),*);
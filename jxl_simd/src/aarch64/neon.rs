@@ -12,7 +12,7 @@ use std::{
     },
 };
 
-use crate::U32SimdVec;
+use crate::{SimdTier, U32SimdVec};
 
 use super::super::{F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask, U8SimdVec, U16SimdVec};
 
@@ -52,8 +52,8 @@ impl SimdDescriptor for NeonDescriptor {
     type Descriptor128 = Self;
 
     fn new() -> Option<Self> {
-        if std::arch::is_aarch64_feature_detected!("neon") {
-            // SAFETY: we just checked neon.
+        if SimdTier::Neon.is_enabled() {
+            // SAFETY: the neon tier is only enabled if neon is available.
             Some(unsafe { Self::new_unchecked() })
         } else {
             None
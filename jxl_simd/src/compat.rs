@@ -0,0 +1,89 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Fallbacks for standard library APIs that are newer than the minimum supported Rust version.
+//!
+//! The crates of this workspace support the Rust version declared as `rust-version` in their
+//! manifests. Standard library APIs stabilized after it are either replaced by their older
+//! equivalents (such as `NonNull::from` instead of `NonNull::from_ref`) or, when there is no such
+//! equivalent, implemented here with the same semantics, so that they can be swapped for the
+//! standard ones when the MSRV is raised.
+
+/// Splits `slice` into `N`-element arrays, starting at the beginning of the slice, and a remainder
+/// shorter than `N`. Same as `<[T]>::as_chunks`, stabilized in Rust 1.88.
+///
+/// # Panics
+/// Panics if `N` is 0.
+#[inline(always)]
+pub fn as_chunks<T, const N: usize>(slice: &[T]) -> (&[[T; N]], &[T]) {
+    const { assert!(N != 0, "chunk size must be non-zero") };
+    let len = slice.len() / N;
+    let (chunks, remainder) = slice.split_at(len * N);
+    // SAFETY: `chunks` holds exactly `len * N` elements, and `[T; N]` has the same layout as `N`
+    // consecutive `T`s.
+    let chunks = unsafe { std::slice::from_raw_parts(chunks.as_ptr().cast::<[T; N]>(), len) };
+    (chunks, remainder)
+}
+
+/// Mutable version of [`as_chunks`]. Same as `<[T]>::as_chunks_mut`, stabilized in Rust 1.88.
+///
+/// # Panics
+/// Panics if `N` is 0.
+#[inline(always)]
+pub fn as_chunks_mut<T, const N: usize>(slice: &mut [T]) -> (&mut [[T; N]], &mut [T]) {
+    const { assert!(N != 0, "chunk size must be non-zero") };
+    let len = slice.len() / N;
+    let (chunks, remainder) = slice.split_at_mut(len * N);
+    // SAFETY: `chunks` holds exactly `len * N` elements, and `[T; N]` has the same layout as `N`
+    // consecutive `T`s. The returned slices do not overlap.
+    let chunks =
+        unsafe { std::slice::from_raw_parts_mut(chunks.as_mut_ptr().cast::<[T; N]>(), len) };
+    (chunks, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_chunks() {
+        let data: Vec<u32> = (0..11).collect();
+        let (chunks, rest) = as_chunks::<_, 4>(&data);
+        assert_eq!(chunks, &[[0, 1, 2, 3], [4, 5, 6, 7]]);
+        assert_eq!(rest, &[8, 9, 10]);
+
+        let (chunks, rest) = as_chunks::<_, 11>(&data);
+        assert_eq!(chunks.len(), 1);
+        assert!(rest.is_empty());
+
+        let (chunks, rest) = as_chunks::<u32, 3>(&[]);
+        assert!(chunks.is_empty());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_as_chunks_mut() {
+        let mut data: Vec<u8> = (0..8).collect();
+        let (chunks, rest) = as_chunks_mut::<_, 3>(&mut data);
+        for chunk in chunks.iter_mut() {
+            chunk.reverse();
+        }
+        rest[0] = 100;
+        assert_eq!(data, [2, 1, 0, 5, 4, 3, 100, 7]);
+    }
+
+    #[test]
+    fn test_as_chunks_matches_std() {
+        arbtest::arbtest(|u| {
+            let data: Vec<u16> = u.arbitrary()?;
+            let (chunks, rest) = as_chunks::<_, 5>(&data);
+            assert_eq!(chunks.len() * 5 + rest.len(), data.len());
+            assert!(rest.len() < 5);
+            assert!(data.chunks_exact(5).eq(chunks.iter().map(|c| &c[..])));
+            assert_eq!(rest, data.chunks_exact(5).remainder());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) the JPEG XL Project Authors. All rights reserved.
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use std::sync::OnceLock;
+
+/// Environment variable that makes every kernel use its scalar implementation when set to `1`,
+/// for debugging and for comparing SIMD and scalar results.
+pub const FORCE_SCALAR_ENV: &str = "JXL_RS_FORCE_SCALAR";
+
+/// Instruction sets that SIMD kernels can be dispatched to. On each architecture, a tier
+/// requires all the instruction sets of the previous ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SimdTier {
+    Scalar,
+    Sse42,
+    /// AVX2 with FMA and F16C.
+    Avx2,
+    /// AVX-512 F and BW.
+    Avx512,
+    Neon,
+}
+
+impl SimdTier {
+    pub fn name(self) -> &'static str {
+        match self {
+            SimdTier::Scalar => "scalar",
+            SimdTier::Sse42 => "sse4.2",
+            SimdTier::Avx2 => "avx2",
+            SimdTier::Avx512 => "avx512",
+            SimdTier::Neon => "neon",
+        }
+    }
+
+    /// Returns whether the crate was built with kernels for this tier, and the CPU supports it.
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdTier::Scalar => true,
+            #[cfg(all(target_arch = "x86_64", feature = "sse42"))]
+            SimdTier::Sse42 => is_x86_feature_detected!("sse4.2"),
+            #[cfg(all(target_arch = "x86_64", feature = "avx"))]
+            SimdTier::Avx2 => {
+                is_x86_feature_detected!("avx2")
+                    && is_x86_feature_detected!("fma")
+                    && is_x86_feature_detected!("f16c")
+            }
+            #[cfg(all(target_arch = "x86_64", jxl_simd_avx512))]
+            SimdTier::Avx512 => {
+                is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw")
+            }
+            #[cfg(all(target_arch = "aarch64", feature = "neon"))]
+            SimdTier::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    /// Returns whether kernels may use this tier, that is, whether it is supported and not above
+    /// the [active tier](Dispatch::active_tier).
+    #[inline]
+    pub fn is_enabled(self) -> bool {
+        self <= Dispatch::active_tier() && self.is_supported()
+    }
+}
+
+impl std::fmt::Display for SimdTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The dispatch decision of the process: the most capable tier that all SIMD kernels use,
+/// resolved on first use from the CPU features and [`FORCE_SCALAR_ENV`].
+#[derive(Debug)]
+pub struct Dispatch {
+    tier: SimdTier,
+    forced_scalar: bool,
+}
+
+static DISPATCH: OnceLock<Dispatch> = OnceLock::new();
+
+impl Dispatch {
+    pub fn get() -> &'static Dispatch {
+        DISPATCH.get_or_init(|| {
+            let forced_scalar = std::env::var_os(FORCE_SCALAR_ENV).is_some_and(|v| v == "1");
+            Dispatch {
+                tier: Self::resolve(forced_scalar),
+                forced_scalar,
+            }
+        })
+    }
+
+    #[inline]
+    pub fn active_tier() -> SimdTier {
+        Self::get().tier
+    }
+
+    /// Returns whether [`FORCE_SCALAR_ENV`] disabled the SIMD kernels.
+    pub fn forced_scalar() -> bool {
+        Self::get().forced_scalar
+    }
+
+    fn resolve(force_scalar: bool) -> SimdTier {
+        if force_scalar {
+            return SimdTier::Scalar;
+        }
+        let x86 = [SimdTier::Sse42, SimdTier::Avx2, SimdTier::Avx512]
+            .into_iter()
+            .take_while(|tier| tier.is_supported())
+            .last();
+        x86.or(SimdTier::Neon.is_supported().then_some(SimdTier::Neon))
+            .unwrap_or(SimdTier::Scalar)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        assert_eq!(Dispatch::resolve(true), SimdTier::Scalar);
+        let tier = Dispatch::resolve(false);
+        assert!(tier.is_supported());
+        if !Dispatch::forced_scalar() {
+            assert_eq!(Dispatch::active_tier(), tier);
+        }
+        // Every tier of the architecture up to the resolved one is usable.
+        let x86 = [SimdTier::Sse42, SimdTier::Avx2, SimdTier::Avx512];
+        if x86.contains(&tier) {
+            assert!(x86.iter().filter(|t| **t <= tier).all(|t| t.is_supported()));
+        }
+    }
+}
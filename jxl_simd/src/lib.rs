@@ -20,14 +20,21 @@ mod x86_64;
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
 
+pub mod compat;
+mod dispatch;
 pub mod float16;
 pub mod scalar;
 
+#[cfg(test)]
+#[path = "../build/rustc_version.rs"]
+mod rustc_version;
+
+pub use dispatch::{Dispatch, FORCE_SCALAR_ENV, SimdTier};
 pub use float16::f16;
 
 #[cfg(all(target_arch = "x86_64", feature = "avx"))]
 pub use x86_64::avx::AvxDescriptor;
-#[cfg(all(target_arch = "x86_64", feature = "avx512"))]
+#[cfg(all(target_arch = "x86_64", jxl_simd_avx512))]
 pub use x86_64::avx512::Avx512Descriptor;
 #[cfg(all(target_arch = "x86_64", feature = "sse42"))]
 pub use x86_64::sse42::Sse42Descriptor;
@@ -557,14 +564,14 @@ macro_rules! impl_f32_array_interface {
 
         #[inline(always)]
         fn make_array_slice(slice: &[f32]) -> &[Self::UnderlyingArray] {
-            let (ret, rem) = slice.as_chunks();
+            let (ret, rem) = $crate::compat::as_chunks(slice);
             assert!(rem.is_empty());
             ret
         }
 
         #[inline(always)]
         fn make_array_slice_mut(slice: &mut [f32]) -> &mut [Self::UnderlyingArray] {
-            let (ret, rem) = slice.as_chunks_mut();
+            let (ret, rem) = $crate::compat::as_chunks_mut(slice);
             assert!(rem.is_empty());
             ret
         }
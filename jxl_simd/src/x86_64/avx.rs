@@ -3,7 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{U32SimdVec, impl_f32_array_interface, x86_64::sse42::Sse42Descriptor};
+use crate::{SimdTier, U32SimdVec, impl_f32_array_interface, x86_64::sse42::Sse42Descriptor};
 
 use super::super::{F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask, U8SimdVec, U16SimdVec};
 use std::{
@@ -141,11 +141,8 @@ impl SimdDescriptor for AvxDescriptor {
     }
 
     fn new() -> Option<Self> {
-        if is_x86_feature_detected!("avx2")
-            && is_x86_feature_detected!("fma")
-            && is_x86_feature_detected!("f16c")
-        {
-            // SAFETY: we just checked avx2, fma, and f16c.
+        if SimdTier::Avx2.is_enabled() {
+            // SAFETY: the avx2 tier is only enabled if avx2, fma, and f16c are available.
             Some(unsafe { Self::new_unchecked() })
         } else {
             None
@@ -6,7 +6,7 @@
 use super::super::{
     AvxDescriptor, F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask, U8SimdVec, U16SimdVec,
 };
-use crate::{Sse42Descriptor, U32SimdVec, impl_f32_array_interface};
+use crate::{SimdTier, Sse42Descriptor, U32SimdVec, impl_f32_array_interface};
 use std::{
     arch::x86_64::*,
     mem::MaybeUninit,
@@ -62,8 +62,8 @@ impl SimdDescriptor for Avx512Descriptor {
     }
 
     fn new() -> Option<Self> {
-        if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") {
-            // SAFETY: we just checked avx512f and avx512bw.
+        if SimdTier::Avx512.is_enabled() {
+            // SAFETY: the avx512 tier is only enabled if avx512f and avx512bw are available.
             Some(Self(()))
         } else {
             None
@@ -8,7 +8,9 @@
 
 #[cfg(feature = "avx")]
 pub(super) mod avx;
-#[cfg(feature = "avx512")]
+// Only built with Rust 1.89 or newer, see `build.rs`.
+#[cfg(jxl_simd_avx512)]
+#[clippy::msrv = "1.89"]
 pub(super) mod avx512;
 #[cfg(feature = "sse42")]
 pub(super) mod sse42;
@@ -42,7 +44,7 @@ macro_rules! simd_function {
 #[macro_export]
 macro_rules! simd_function_body_sse42 {
     ($name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty )?; ($($val:expr),* $(,)?)) => {
-        if cfg!(target_feature = "sse4.2") {
+        if cfg!(target_feature = "sse4.2") && $crate::SimdTier::Sse42.is_enabled() {
             // SAFETY: we just checked for sse4.2.
             let d = unsafe { $crate::Sse42Descriptor::new_unchecked() };
             return $name(d, $($val),*);
@@ -62,7 +64,9 @@ macro_rules! simd_function_body_sse42 {
 #[macro_export]
 macro_rules! simd_function_body_avx {
     ($name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty )?; ($($val:expr),* $(,)?)) => {
-        if cfg!(all(target_feature = "avx2", target_feature = "fma", target_feature = "f16c")) {
+        if cfg!(all(target_feature = "avx2", target_feature = "fma", target_feature = "f16c"))
+            && $crate::SimdTier::Avx2.is_enabled()
+        {
             // SAFETY: we just checked for avx2, fma and f16c.
             let d = unsafe { $crate::AvxDescriptor::new_unchecked() };
             return $name(d, $($val),*);
@@ -77,12 +81,12 @@ macro_rules! simd_function_body_avx {
     };
 }
 
-#[cfg(feature = "avx512")]
+#[cfg(jxl_simd_avx512)]
 #[doc(hidden)]
 #[macro_export]
 macro_rules! simd_function_body_avx512 {
     ($name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty )?; ($($val:expr),* $(,)?)) => {
-        if cfg!(target_feature = "avx512f") {
+        if cfg!(target_feature = "avx512f") && $crate::SimdTier::Avx512.is_enabled() {
             // SAFETY: we just checked for avx512f.
             let d = unsafe { $crate::Avx512Descriptor::new_unchecked() };
             return $name(d, $($val),*);
@@ -111,7 +115,7 @@ macro_rules! simd_function_body_avx {
     ($($ignore:tt)*) => {};
 }
 
-#[cfg(not(feature = "avx512"))]
+#[cfg(not(jxl_simd_avx512))]
 #[doc(hidden)]
 #[macro_export]
 macro_rules! simd_function_body_avx512 {
@@ -181,7 +185,7 @@ macro_rules! test_avx {
     };
 }
 
-#[cfg(feature = "avx512")]
+#[cfg(jxl_simd_avx512)]
 #[doc(hidden)]
 #[macro_export]
 macro_rules! test_avx512 {
@@ -217,7 +221,7 @@ macro_rules! test_avx {
     ($name:ident) => {};
 }
 
-#[cfg(not(feature = "avx512"))]
+#[cfg(not(jxl_simd_avx512))]
 #[doc(hidden)]
 #[macro_export]
 macro_rules! test_avx512 {
@@ -3,7 +3,7 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::{U32SimdVec, impl_f32_array_interface};
+use crate::{SimdTier, U32SimdVec, impl_f32_array_interface};
 
 use super::super::{F32SimdVec, I32SimdVec, SimdDescriptor, SimdMask, U8SimdVec, U16SimdVec};
 use std::{
@@ -48,8 +48,8 @@ impl SimdDescriptor for Sse42Descriptor {
     }
 
     fn new() -> Option<Self> {
-        if is_x86_feature_detected!("sse4.2") {
-            // SAFETY: we just checked sse4.2.
+        if SimdTier::Sse42.is_enabled() {
+            // SAFETY: the sse4.2 tier is only enabled if sse4.2 is available.
             Some(unsafe { Self::new_unchecked() })
         } else {
             None
@@ -4,7 +4,9 @@
 // license that can be found in the LICENSE file.
 
 use super::*;
-use jxl_simd::{test_all_instruction_sets, ScalarDescriptor, SimdDescriptor};
+use jxl_simd::{
+    compat::as_chunks_mut, test_all_instruction_sets, ScalarDescriptor, SimdDescriptor,
+};
 use rand::Rng;
 use rand::SeedableRng;
 use rand_chacha::ChaCha12Rng;
@@ -197,7 +199,7 @@ macro_rules! test_reinterpreting_dct1d_eq_slow_n {
             let mut output: Vec<_> = input_matrix_for_ref.iter().map(|x| x[0] as f32).collect();
             let d = ScalarDescriptor {};
 
-            let (output_chunks, remainder) = output.as_chunks_mut::<1>();
+            let (output_chunks, remainder) = as_chunks_mut::<_, 1>(&mut output);
             assert!(remainder.is_empty());
             $do_idct_fun(d, output_chunks, 1);
 
@@ -269,7 +271,7 @@ macro_rules! test_idct1d_eq_slow_n {
             let mut output: Vec<_> = input_matrix_for_ref.iter().map(|x| x[0] as f32).collect();
             let d = ScalarDescriptor {};
 
-            let (output_chunks, remainder) = output.as_chunks_mut::<1>();
+            let (output_chunks, remainder) = as_chunks_mut::<_, 1>(&mut output);
             assert!(remainder.is_empty());
             $do_idct_fun(d, output_chunks, 1);
 